@@ -32,6 +32,7 @@ mod namespaceable_name;
 pub mod query;
 pub mod shellings;
 pub mod types;
+pub mod uri;
 pub mod pretty_print;
 pub mod utils;
 pub mod matcher;
@@ -66,6 +67,11 @@ pub use types::{
     ValueAndSpan,
 };
 
+pub use uri::{
+    Uri,
+    UriParseError,
+};
+
 pub use shellings::{
     Keyword,
     NamespacedShelling,
@@ -45,6 +45,7 @@ use ::{
     BigInt,
     DateTime,
     OrderedFloat,
+    Uri,
     Uuid,
     Utc,
 };
@@ -208,6 +209,7 @@ pub enum NonIntegerConstant {
     Text(ValueRc<String>),
     Instant(DateTime<Utc>),
     Uuid(Uuid),
+    Uri(Uri),
 }
 
 impl<'a> From<&'a str> for NonIntegerConstant {
@@ -251,6 +253,8 @@ impl FromValue<FnArg> for FnArg {
                 Some(FnArg::Constant(NonIntegerConstant::Instant(x))),
             Uuid(x) =>
                 Some(FnArg::Constant(NonIntegerConstant::Uuid(x))),
+            Uri(ref x) =>
+                Some(FnArg::Constant(NonIntegerConstant::Uri(x.clone()))),
             Boolean(x) =>
                 Some(FnArg::Constant(NonIntegerConstant::Boolean(x))),
             Float(x) =>
@@ -425,6 +429,8 @@ impl FromValue<PatternValuePlace> for PatternValuePlace {
                 Some(PatternValuePlace::Constant(x.clone().into())),
             ::SpannedValue::Uuid(ref u) =>
                 Some(PatternValuePlace::Constant(NonIntegerConstant::Uuid(u.clone()))),
+            ::SpannedValue::Uri(ref u) =>
+                Some(PatternValuePlace::Constant(NonIntegerConstant::Uri(u.clone()))),
 
             // These don't appear in queries.
             ::SpannedValue::Nil => None,
@@ -26,6 +26,7 @@ use ordered_float::OrderedFloat;
 use uuid::Uuid;
 
 use shellings;
+use uri::Uri;
 
 /// Value represents one of the allowed values in an EML string.
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
@@ -38,6 +39,7 @@ pub enum Value {
     Float(OrderedFloat<f64>),
     Text(String),
     Uuid(Uuid),
+    Uri(Uri),
     PlainShelling(shellings::PlainShelling),
     NamespacedShelling(shellings::NamespacedShelling),
     Keyword(shellings::Keyword),
@@ -66,6 +68,7 @@ pub enum SpannedValue {
     Float(OrderedFloat<f64>),
     Text(String),
     Uuid(Uuid),
+    Uri(Uri),
     PlainShelling(shellings::PlainShelling),
     NamespacedShelling(shellings::NamespacedShelling),
     Keyword(shellings::Keyword),
@@ -154,6 +157,7 @@ impl From<SpannedValue> for Value {
             SpannedValue::Float(v) => Value::Float(v),
             SpannedValue::Text(v) => Value::Text(v),
             SpannedValue::Uuid(v) => Value::Uuid(v),
+            SpannedValue::Uri(v) => Value::Uri(v),
             SpannedValue::PlainShelling(v) => Value::PlainShelling(v),
             SpannedValue::NamespacedShelling(v) => Value::NamespacedShelling(v),
             SpannedValue::Keyword(v) => Value::Keyword(v),
@@ -306,6 +310,7 @@ macro_rules! def_common_value_methods {
         def_is!(is_float, $t::Float(_));
         def_is!(is_text, $t::Text(_));
         def_is!(is_uuid, $t::Uuid(_));
+        def_is!(is_uri, $t::Uri(_));
         def_is!(is_shelling, $t::PlainShelling(_));
         def_is!(is_isoliton_namespaceable_shelling, $t::NamespacedShelling(_));
         def_is!(is_vector, $t::Vector(_));
@@ -342,6 +347,7 @@ macro_rules! def_common_value_methods {
         def_as_ref!(as_ordered_float, $t::Float, OrderedFloat<f64>);
         def_as_ref!(as_text, $t::Text, String);
         def_as_ref!(as_uuid, $t::Uuid, Uuid);
+        def_as_ref!(as_uri, $t::Uri, Uri);
         def_as_ref!(as_shelling, $t::PlainShelling, shellings::PlainShelling);
         def_as_ref!(as_isoliton_namespaceable_shelling, $t::NamespacedShelling, shellings::NamespacedShelling);
 
@@ -379,6 +385,7 @@ macro_rules! def_common_value_methods {
         def_into!(into_float, $t::Float, f64, |v: OrderedFloat<f64>| v.into_inner());
         def_into!(into_text, $t::Text, String,);
         def_into!(into_uuid, $t::Uuid, Uuid,);
+        def_into!(into_uri, $t::Uri, Uri,);
         def_into!(into_shelling, $t::PlainShelling, shellings::PlainShelling,);
         def_into!(into_isoliton_namespaceable_shelling, $t::NamespacedShelling, shellings::NamespacedShelling,);
 
@@ -443,14 +450,15 @@ macro_rules! def_common_value_methods {
                 $t::Instant(_) => 5,
                 $t::Text(_) => 6,
                 $t::Uuid(_) => 7,
-                $t::PlainShelling(_) => 8,
-                $t::NamespacedShelling(_) => 9,
-                $t::Keyword(ref k) if !k.is_isoliton_namespaceable() => 10,
-                $t::Keyword(_) => 11,
-                $t::Vector(_) => 12,
-                $t::List(_) => 13,
-                $t::Set(_) => 14,
-                $t::Map(_) => 15,
+                $t::Uri(_) => 8,
+                $t::PlainShelling(_) => 9,
+                $t::NamespacedShelling(_) => 10,
+                $t::Keyword(ref k) if !k.is_isoliton_namespaceable() => 11,
+                $t::Keyword(_) => 12,
+                $t::Vector(_) => 13,
+                $t::List(_) => 14,
+                $t::Set(_) => 15,
+                $t::Map(_) => 16,
             }
         }
 
@@ -464,6 +472,7 @@ macro_rules! def_common_value_methods {
                 $t::Float(_) => false,
                 $t::Text(_) => false,
                 $t::Uuid(_) => false,
+                $t::Uri(_) => false,
                 $t::PlainShelling(_) => false,
                 $t::NamespacedShelling(_) => false,
                 $t::Keyword(_) => false,
@@ -501,6 +510,7 @@ macro_rules! def_common_value_ord {
             (&$t::Float(ref a), &$t::Float(ref b)) => b.cmp(a),
             (&$t::Text(ref a), &$t::Text(ref b)) => b.cmp(a),
             (&$t::Uuid(ref a), &$t::Uuid(ref b)) => b.cmp(a),
+            (&$t::Uri(ref a), &$t::Uri(ref b)) => b.cmp(a),
             (&$t::PlainShelling(ref a), &$t::PlainShelling(ref b)) => b.cmp(a),
             (&$t::NamespacedShelling(ref a), &$t::NamespacedShelling(ref b)) => b.cmp(a),
             (&$t::Keyword(ref a), &$t::Keyword(ref b)) => b.cmp(a),
@@ -538,6 +548,7 @@ macro_rules! def_common_value_display {
             }
             $t::BigDecimal(ref v) => write!($f, "{}M", v),
             $t::Uuid(ref v) => write!($f, "#uuid \"{}\"", v),
+            $t::Uri(ref v) => write!($f, "#uri \"{}\"", v),
             $t::Keyword(ref v) => write!($f, ":{}", v),
             $t::Shelling(ref s) => write!($f, "{}", s.as_str()),
             $t::String(ref s) => {
@@ -579,6 +590,7 @@ macro_rules! def_common_value_display {
             // TODO: EML escaping.
             $t::Text(ref v) => write!($f, "\"{}\"", v),
             $t::Uuid(ref u) => write!($f, "#uuid \"{}\"", u.hyphenated().to_string()),
+            $t::Uri(ref u) => write!($f, "#uri \"{}\"", u),
             $t::PlainShelling(ref v) => v.fmt($f),
             $t::NamespacedShelling(ref v) => v.fmt($f),
             $t::Keyword(ref v) => v.fmt($f),
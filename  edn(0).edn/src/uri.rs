@@ -0,0 +1,231 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(feature = "serde_support")]
+use serde::de::{
+    self,
+    Deserialize,
+    Deserializer,
+};
+#[cfg(feature = "serde_support")]
+use serde::ser::{
+    Serialize,
+    Serializer,
+};
+
+/// A URI, as RFC 3986 defines it: a scheme, then a hierarchical part, an optional query and an
+/// optional fragment.  Written in EML as `#uri "https://example.com/a?b#c"`.
+///
+/// Only the syntax is checked: the characters of each part, and that every `%` starts a
+/// percent-encoded octet.  The URI is kept as written; it isn't normalized.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
+pub struct Uri(String);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UriParseError {
+    pub uri: String,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for UriParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid URI {:?}: {}", self.uri, self.reason)
+    }
+}
+
+fn is_unreserved(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'-' || c == b'.' || c == b'_' || c == b'~'
+}
+
+fn is_sub_delim(c: u8) -> bool {
+    b"!$&'()*+,;=".contains(&c)
+}
+
+/// Check that `part` consists of `pchar`s and the `extra` characters, and that every `%` starts a
+/// percent-encoded octet.
+fn check_part(part: &[u8], extra: &[u8]) -> Result<(), &'static str> {
+    let mut i = 0;
+    while i < part.len() {
+        let c = part[i];
+        if c == b'%' {
+            if i + 2 >= part.len() || !part[i + 1].is_ascii_hexdigit() || !part[i + 2].is_ascii_hexdigit() {
+                return Err("malformed percent-encoding");
+            }
+            i += 3;
+            continue;
+        }
+        if !(is_unreserved(c) || is_sub_delim(c) || c == b':' || c == b'@' || extra.contains(&c)) {
+            return Err("invalid character");
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+impl Uri {
+    pub fn parse(s: &str) -> Result<Uri, UriParseError> {
+        Uri::check(s)
+            .map(|()| Uri(s.to_string()))
+            .map_err(|reason| UriParseError { uri: s.to_string(), reason })
+    }
+
+    fn check(s: &str) -> Result<(), &'static str> {
+        let bytes = s.as_bytes();
+        let colon = bytes.iter().position(|&c| c == b':').ok_or("no scheme")?;
+        let (scheme, rest) = (&bytes[..colon], &bytes[colon + 1..]);
+        match scheme.first() {
+            Some(c) if c.is_ascii_alphabetic() => {},
+            _ => return Err("scheme doesn't start with a letter"),
+        }
+        if !scheme.iter().all(|&c| c.is_ascii_alphanumeric() || c == b'+' || c == b'-' || c == b'.') {
+            return Err("invalid character in scheme");
+        }
+
+        let (rest, fragment) = match rest.iter().position(|&c| c == b'#') {
+            Some(hash) => (&rest[..hash], Some(&rest[hash + 1..])),
+            None => (rest, None),
+        };
+        let (hier, query) = match rest.iter().position(|&c| c == b'?') {
+            Some(question) => (&rest[..question], Some(&rest[question + 1..])),
+            None => (rest, None),
+        };
+        if let Some(fragment) = fragment {
+            check_part(fragment, b"/?")?;
+        }
+        if let Some(query) = query {
+            check_part(query, b"/?")?;
+        }
+
+        if hier.starts_with(b"//") {
+            let authority_end = hier[2..].iter().position(|&c| c == b'/').map_or(hier.len(), |slash| slash + 2);
+            let (authority, path) = (&hier[2..authority_end], &hier[authority_end..]);
+            Uri::check_authority(authority)?;
+            check_part(path, b"/")
+        } else {
+            check_part(hier, b"/")
+        }
+    }
+
+    fn check_authority(authority: &[u8]) -> Result<(), &'static str> {
+        let host_port = match authority.iter().rposition(|&c| c == b'@') {
+            Some(at) => {
+                check_part(&authority[..at], b"")?;
+                &authority[at + 1..]
+            },
+            None => authority,
+        };
+        let port_start = if host_port.starts_with(b"[") {
+            // An IP literal.
+            let close = host_port.iter().position(|&c| c == b']').ok_or("unclosed IP literal")?;
+            if !host_port[1..close].iter().all(|&c| c.is_ascii_hexdigit() || c == b':' || c == b'.' || is_unreserved(c) || is_sub_delim(c)) {
+                return Err("invalid IP literal");
+            }
+            match host_port.get(close + 1) {
+                None => return Ok(()),
+                Some(b':') => close + 2,
+                Some(_) => return Err("invalid character after IP literal"),
+            }
+        } else {
+            let host_end = host_port.iter().position(|&c| c == b':').unwrap_or(host_port.len());
+            if host_port[..host_end].iter().any(|&c| c == b'@' || c == b'[' || c == b']') {
+                return Err("invalid character in host");
+            }
+            check_part(&host_port[..host_end], b"").map_err(|_| "invalid character in host")?;
+            if host_end == host_port.len() {
+                return Ok(());
+            }
+            host_end + 1
+        };
+        if host_port[port_start..].iter().all(|c| c.is_ascii_digit()) {
+            Ok(())
+        } else {
+            Err("invalid port")
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn scheme(&self) -> &str {
+        &self.0[..self.0.find(':').expect("a scheme")]
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl FromStr for Uri {
+    type Err = UriParseError;
+
+    fn from_str(s: &str) -> Result<Uri, UriParseError> {
+        Uri::parse(s)
+    }
+}
+
+impl fmt::Display for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(feature = "serde_support")]
+impl Serialize for Uri {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+#[cfg(feature = "serde_support")]
+impl<'de> Deserialize<'de> for Uri {
+    fn deserialize<D>(deserializer: D) -> Result<Uri, D::Error> where D: Deserializer<'de> {
+        let s = String::deserialize(deserializer)?;
+        Uri::parse(&s).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        for uri in &["https://example.com",
+                     "https://user:pw@example.com:8080/a/b%20c?x=1&y=%5B2%5D#frag/ment",
+                     "http://[::1]:80/",
+                     "mailto:someone@example.com",
+                     "urn:isbn:0451450523",
+                     "file:///etc/hosts",
+                     "a+b.c-d:"] {
+            let parsed = Uri::parse(uri).expect("valid");
+            assert_eq!(parsed.as_str(), *uri);
+            assert_eq!(parsed.to_string(), *uri);
+        }
+        assert_eq!(Uri::parse("HTTPS://x").unwrap().scheme(), "HTTPS");
+
+        for uri in &["example.com",
+                     ":no-scheme",
+                     "1http://x",
+                     "ht tp://x",
+                     "http://x/a b",
+                     "http://x/%2",
+                     "http://x/%zz",
+                     "http://x:port",
+                     "http://[::1",
+                     "http://x#a#b",
+                     "http://x/\u{e9}"] {
+            assert!(Uri::parse(uri).is_err(), "{} should be invalid", uri);
+        }
+    }
+}
@@ -274,6 +274,25 @@ fn test_uuid() {
     assert_eq!(value.to_pretty(100).unwrap(), s);
 }
 
+#[test]
+fn test_uri() {
+    assert!(parse::uri("#uri\"https://example.com\"").is_err());   // No whitespace.
+    assert!(parse::uri("\"https://example.com\"").is_err());       // No tag.
+    assert!(parse::uri("#uri \"example.com\"").is_err());          // No scheme.
+    assert!(parse::uri("#uri \"https://example.com/a b\"").is_err());  // Space.
+
+    let expected = edn::Uri::parse("https://example.com/a?b=%20#c").expect("valid URI");
+    let s = "#uri \"https://example.com/a?b=%20#c\"";
+    let actual = parse::uri(s)
+                       .expect("parse success")
+                       .into();
+    let value = self::Value::Uri(expected);
+    assert_eq!(value, actual);
+    assert_eq!(format!("{}", value), s);
+    assert_eq!(value.to_pretty(100).unwrap(), s);
+    assert_eq!(parse::value(s).expect("parse success").without_spans(), value);
+}
+
 #[test]
 fn test_inst() {
     assert!(parse::value("#inst\"2022-01-01T11:00:00.000Z\"").is_err());   // No whitespace.
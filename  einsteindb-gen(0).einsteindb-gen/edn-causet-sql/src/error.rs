@@ -15,6 +15,16 @@ pub enum EvaluateError {
     #[fail(display = "Execution terminated due to exceeding the deadline")]
     DeadlineExceeded,
 
+    #[fail(
+        display = "Memory quota exceeded: {} bytes requested with {} of {} in use",
+        requested, in_use, capacity
+    )]
+    MemoryQuotaExceeded {
+        capacity: usize,
+        in_use: usize,
+        requested: usize,
+    },
+
     #[fail(display = "Invalid {} character string", charset)]
     InvalidCharacterString { charset: String },
 
@@ -33,6 +43,7 @@ impl EvaluateError {
         match self {
             EvaluateError::InvalidCharacterString { .. } => 1300,
             EvaluateError::DeadlineExceeded => 9007,
+            EvaluateError::MemoryQuotaExceeded { .. } => 8001,
             EvaluateError::Custom { code, .. } => *code,
             EvaluateError::Other(_) => 10000,
         }
@@ -65,6 +76,15 @@ impl From<failure::Error> for StorageError {
     }
 }
 
+impl StorageError {
+    /// The first cause of type `T` in the error's cause chain.  An engine error converted with
+    /// `failure::Error::from` stays there, so callers that depend on `fdb_traits` can recover and
+    /// classify it with `fdb_traits::class_of_fail(err.0.as_fail())`.
+    pub fn find_cause<T: Fail>(&self) -> Option<&T> {
+        self.0.iter_chain().find_map(|cause| cause.downcast_ref::<T>())
+    }
+}
+
 /// We want to restrict the type of errors to be either a `StorageError` or `EvaluateError`, thus
 /// `failure::Error` is not used. Instead, we introduce our own error enum.
 #[derive(Fail, Debug)]
@@ -24,6 +24,16 @@ pub struct ExecSummary {
     pub num_iterations: usize,
 }
 
+/// Statistics of the lookups an index executor makes back into the record storage.
+#[derive(Debug, Default, Copy, Clone, Add, AddAssign, PartialEq, Eq)]
+pub struct IndexLookupStats {
+    /// How many index entries were looked up in the record storage.
+    pub lookups: usize,
+
+    /// How many of those lookups found no row, i.e. dangling index entries.
+    pub missing_rows: usize,
+}
+
 /// A trait for all execution summary collectors.
 pub trait ExecSummaryCollector: Send {
     type DurationRecorder;
@@ -122,6 +132,15 @@ pub struct ExecuteStats {
 
     /// For each range given in the request, how many rows are mutant_searchned.
     pub mutant_searchned_rows_per_range: Vec<usize>,
+
+    /// The plans chosen by `plan_mutant_search` for the mutant_searchs of this request.
+    pub scan_plans: Vec<crate::storage::mutant_searchner::ScanPlan>,
+
+    /// The lookups index executors made into the record storage.
+    pub index_lookups: IndexLookupStats,
+
+    /// The most bytes of buffered rows charged to the request's `MemoryQuota` at once.
+    pub peak_memory_usage: usize,
 }
 
 impl ExecuteStats {
@@ -133,6 +152,9 @@ impl ExecuteStats {
         Self {
             summary_per_executor: vec![ExecSummary::default(); executors_len],
             mutant_searchned_rows_per_range: Vec::new(),
+            scan_plans: Vec::new(),
+            index_lookups: IndexLookupStats::default(),
+            peak_memory_usage: 0,
         }
     }
 
@@ -142,5 +164,8 @@ impl ExecuteStats {
             *item = ExecSummary::default();
         }
         self.mutant_searchned_rows_per_range.clear();
+        self.scan_plans.clear();
+        self.index_lookups = IndexLookupStats::default();
+        self.peak_memory_usage = 0;
     }
 }
@@ -5,6 +5,7 @@ pub mod macros;
 
 pub mod error;
 pub mod execute_stats;
+pub mod memory_quota;
 pub mod metrics;
 pub mod storage;
 pub mod util;
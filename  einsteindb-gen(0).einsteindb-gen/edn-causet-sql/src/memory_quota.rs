@@ -0,0 +1,206 @@
+//Copyright 2021-2023 WHTCORPS INC ALL RIGHTS RESERVED. APACHE 2.0 COMMUNITY EDITION SL
+// AUTHORS: WHITFORD LEDER
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::error::EvaluateError;
+
+/// A handle to a budget of bytes for buffered rows. Clones share the budget, so a quota can
+/// bound a single request or, cloned into each of them, a set of requests. `for_request` gives
+/// a handle charging the same budget whose usage and peak only count that request.
+#[derive(Clone, Debug)]
+pub struct MemoryQuota {
+    inner: Arc<MemoryQuotaInner>,
+    // The quota every charge is also made to, if this handle is a request's share of it.
+    parent: Option<Box<MemoryQuota>>,
+}
+
+#[derive(Debug)]
+struct MemoryQuotaInner {
+    capacity: usize,
+    in_use: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl MemoryQuota {
+    pub fn new(capacity: usize) -> Self {
+        MemoryQuota {
+            inner: Arc::new(MemoryQuotaInner {
+                capacity,
+                in_use: AtomicUsize::new(0),
+                peak: AtomicUsize::new(0),
+            }),
+            parent: None,
+        }
+    }
+
+    /// A handle for a single request: it charges this quota, but `in_use` and `peak` only count
+    /// the bytes charged through it.
+    pub fn for_request(&self) -> Self {
+        MemoryQuota {
+            parent: Some(Box::new(self.clone())),
+            ..MemoryQuota::unlimited()
+        }
+    }
+
+    /// A quota that only tracks usage.
+    pub fn unlimited() -> Self {
+        MemoryQuota::new(usize::MAX)
+    }
+
+    pub fn capacity(&self) -> usize {
+        match self.parent {
+            Some(ref parent) => parent.capacity(),
+            None => self.inner.capacity,
+        }
+    }
+
+    pub fn in_use(&self) -> usize {
+        self.inner.in_use.load(Ordering::Relaxed)
+    }
+
+    /// The most bytes in use at once so far.
+    pub fn peak(&self) -> usize {
+        self.inner.peak.load(Ordering::Relaxed)
+    }
+
+    /// Charges `bytes` to the quota, or fails without charging them if that would exceed it.
+    pub fn consume(&self, bytes: usize) -> Result<(), EvaluateError> {
+        if let Some(ref parent) = self.parent {
+            parent.consume(bytes)?;
+        }
+        if let Err(e) = self.consume_own(bytes) {
+            if let Some(ref parent) = self.parent {
+                parent.release(bytes);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn consume_own(&self, bytes: usize) -> Result<(), EvaluateError> {
+        let mut in_use = self.in_use();
+        loop {
+            let new_in_use = match in_use.checked_add(bytes) {
+                Some(n) if n <= self.inner.capacity => n,
+                _ => {
+                    return Err(EvaluateError::MemoryQuotaExceeded {
+                        capacity: self.inner.capacity,
+                        in_use,
+                        requested: bytes,
+                    });
+                }
+            };
+            match self.inner.in_use.compare_exchange_weak(
+                in_use,
+                new_in_use,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    self.inner.peak.fetch_max(new_in_use, Ordering::Relaxed);
+                    return Ok(());
+                }
+                Err(actual) => in_use = actual,
+            }
+        }
+    }
+
+    /// Gives back `bytes` charged by `consume`.
+    pub fn release(&self, bytes: usize) {
+        let mut in_use = self.in_use();
+        loop {
+            let new_in_use = in_use.saturating_sub(bytes);
+            match self.inner.in_use.compare_exchange_weak(
+                in_use,
+                new_in_use,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(actual) => in_use = actual,
+            }
+        }
+        if let Some(ref parent) = self.parent {
+            parent.release(bytes);
+        }
+    }
+}
+
+impl Default for MemoryQuota {
+    fn default() -> Self {
+        MemoryQuota::unlimited()
+    }
+}
+
+#[braneg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_quota() {
+        let quota = MemoryQuota::new(100);
+        quota.consume(60).unwrap();
+        let shared = quota.clone();
+        shared.consume(40).unwrap();
+        assert_eq!(quota.in_use(), 100);
+
+        match quota.consume(1) {
+            Err(EvaluateError::MemoryQuotaExceeded {
+                capacity,
+                in_use,
+                requested,
+            }) => assert_eq!((capacity, in_use, requested), (100, 100, 1)),
+            r => panic!("unexpected {:?}", r),
+        }
+        // A failed charge isn't made.
+        assert_eq!(quota.in_use(), 100);
+
+        quota.release(70);
+        assert_eq!(shared.in_use(), 30);
+        quota.consume(50).unwrap();
+        assert_eq!(quota.in_use(), 80);
+        assert_eq!(quota.peak(), 100);
+
+        // Releasing more than was charged empties the quota.
+        quota.release(1000);
+        assert_eq!(quota.in_use(), 0);
+
+        let unlimited = MemoryQuota::unlimited();
+        unlimited.consume(usize::MAX).unwrap();
+        assert!(unlimited.consume(1).is_err());
+    }
+
+    #[test]
+    fn test_memory_quota_for_request() {
+        let shared = MemoryQuota::new(100);
+        let first = shared.for_request();
+        let second = shared.for_request();
+        assert_eq!(first.capacity(), 100);
+
+        first.consume(60).unwrap();
+        first.release(60);
+        second.consume(30).unwrap();
+        assert_eq!((shared.in_use(), shared.peak()), (30, 60));
+        // Each request only sees its own usage.
+        assert_eq!((first.in_use(), first.peak()), (0, 60));
+        assert_eq!((second.in_use(), second.peak()), (30, 30));
+
+        // The shared budget still bounds them together, and a failed charge isn't made.
+        first.consume(50).unwrap();
+        assert!(second.consume(30).is_err());
+        assert_eq!((second.in_use(), shared.in_use()), (30, 80));
+
+        first.release(50);
+        second.release(30);
+        assert_eq!(shared.in_use(), 0);
+    }
+}
@@ -29,6 +29,7 @@ make_auto_flush_static_metric! {
         stream_aggr,
         top_n,
         limit,
+        count,
     }
 
     pub struct LocalCoprExecutorCount: LocalIntCounter {
@@ -11,10 +11,14 @@
 mod range;
 pub mod ranges_iter;
 pub mod mutant_searchner;
+pub mod overlay;
 pub mod test_fixture;
+pub mod synthetic;
 
 pub use self::range::*;
 
+use self::mutant_searchner::RangeStats;
+
 pub type Result<T> = std::result::Result<T, crate::error::StorageError>;
 
 pub type OwnedHikvPair = (Vec<u8>, Vec<u8>);
@@ -40,6 +44,12 @@ pub trait Storage: Send {
     fn met_uncacheable_data(&self) -> Option<bool>;
 
     fn collect_statistics(&mut self, dest: &mut Self::Statistics);
+
+    /// Approximate statistics of `range`, if the storage keeps any. They are only used to plan
+    /// mutant_searchs.
+    fn range_stats(&self, _range: &IntervalRange) -> Option<RangeStats> {
+        None
+    }
 }
 
 impl<T: Storage + ?Sized> Storage for Box<T> {
@@ -69,4 +79,8 @@ impl<T: Storage + ?Sized> Storage for Box<T> {
     fn collect_statistics(&mut self, dest: &mut Self::Statistics) {
         (**self).collect_statistics(dest);
     }
+
+    fn range_stats(&self, range: &IntervalRange) -> Option<RangeStats> {
+        (**self).range_stats(range)
+    }
 }
@@ -15,6 +15,121 @@ use crate::error::StorageError;
 
 const KEY_BUFFER_CAPACITY: usize = 64;
 
+/// Batch sizes chosen by `plan_mutant_search` are kept in this range.
+pub const MIN_SCAN_BATCH_SIZE: usize = 32;
+pub const MAX_SCAN_BATCH_SIZE: usize = 1024;
+
+/// The approximate memory a single batch produced by a planned mutant_search may take.
+pub const SCAN_BATCH_MEMORY_BUDGET: u64 = 1024 * 1024;
+
+/// Assumed size of a key when the range statistics don't tell.
+const ASSUMED_KEY_SIZE: u64 = 32;
+
+/// Approximate statistics of the ranges to be mutant_searchned, typically obtained from
+/// `RangeGreedoidsExt::get_range_approximate_keys` and `get_range_approximate_size`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RangeStats {
+    pub approximate_keys: u64,
+    pub approximate_size: u64,
+}
+
+impl RangeStats {
+    fn avg_row_size(&self) -> u64 {
+        if self.approximate_keys == 0 {
+            ASSUMED_KEY_SIZE
+        } else {
+            std::cmp::max(self.approximate_size / self.approximate_keys, 1)
+        }
+    }
+}
+
+/// Sums the statistics `storage` keeps of `ranges`, counting a point range as a single key.
+///
+/// Returns `None` if the storage has no statistics of some interval range.
+pub fn ranges_stats<T: Storage>(storage: &T, ranges: &[Range]) -> Option<RangeStats> {
+    let mut total = RangeStats::default();
+    for range in ranges {
+        match range {
+            Range::Point(_) => total.approximate_keys += 1,
+            Range::Interval(r) => {
+                let stats = storage.range_stats(r)?;
+                total.approximate_keys += stats.approximate_keys;
+                total.approximate_size += stats.approximate_size;
+            }
+        }
+    }
+    Some(total)
+}
+
+/// The order in which the caller needs rows within a range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanOrder {
+    /// Any order will do, e.g. for aggregations.
+    Any,
+    Ascending,
+    Descending,
+}
+
+/// What the caller needs from a mutant_search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanRequirements {
+    pub order: ScanOrder,
+    /// Whether any column other than those encoded in the key is needed.
+    pub needs_values: bool,
+    /// Maximum number of rows the caller will consume, if known.
+    pub limit: Option<usize>,
+}
+
+/// The decision made by `plan_mutant_search`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanPlan {
+    pub mutant_search_spacelike_completion_in_range: bool,
+    pub is_key_only: bool,
+    pub batch_size: usize,
+    /// The number of rows the plan expects to mutant_search.
+    pub estimated_rows: u64,
+}
+
+/// Chooses the mutant_search direction, key-only mode and batch size for mutant_searchning ranges
+/// described by `stats`.
+///
+/// Backward iteration is noticeably more expensive than forward iteration in an LSM tree,
+/// so ranges are mutant_searchned backward only if descending order is required. Values are
+/// skipped whenever they aren't needed. The batch size is derived from the average row
+/// size, so that a batch stays within `SCAN_BATCH_MEMORY_BUDGET`, and is never larger than
+/// the expected number of rows.
+pub fn plan_mutant_search(stats: &RangeStats, requirements: &ScanRequirements) -> ScanPlan {
+    let is_key_only = !requirements.needs_values;
+    let row_size = if is_key_only {
+        // Range statistics include values. Assume keys of a reasonable size instead.
+        std::cmp::min(stats.avg_row_size(), ASSUMED_KEY_SIZE)
+    } else {
+        stats.avg_row_size()
+    };
+
+    let estimated_rows = match requirements.limit {
+        Some(limit) => std::cmp::min(stats.approximate_keys, limit as u64),
+        None => stats.approximate_keys,
+    };
+
+    let mut batch_size = (SCAN_BATCH_MEMORY_BUDGET / row_size) as usize;
+    batch_size = std::cmp::min(batch_size, MAX_SCAN_BATCH_SIZE);
+    if let Some(limit) = requirements.limit {
+        batch_size = std::cmp::min(batch_size, limit);
+    }
+    batch_size = std::cmp::max(batch_size, MIN_SCAN_BATCH_SIZE);
+    if estimated_rows > 0 && (estimated_rows as usize) < batch_size {
+        batch_size = estimated_rows as usize;
+    }
+
+    ScanPlan {
+        mutant_search_spacelike_completion_in_range: requirements.order == ScanOrder::Descending,
+        is_key_only,
+        batch_size,
+        estimated_rows,
+    }
+}
+
 /// A mutant_searchner that mutant_searchs over multiple ranges. Each range can be a point range containing only
 /// one row, or an interval range containing multiple rows.
 pub struct RangesScanner<T> {
@@ -26,6 +141,9 @@ pub struct RangesScanner<T> {
 
     mutant_searchned_rows_per_range: Vec<usize>,
 
+    // The plan this mutant_searchner was created from, until collected.
+    uncollected_plan: Option<ScanPlan>,
+
     // The following fields are only used for calculating mutant_searchned range. Scanned range is only
     // useful in streaming mode, where the client need to know the underlying physical data range
     // of each response slice, so that partial retry can be non-overlapping.
@@ -43,6 +161,24 @@ pub struct RangesScannerOptions<T> {
     pub is_mutant_searchned_range_aware: bool, // TODO: This can be const generics
 }
 
+impl<T: Storage> RangesScannerOptions<T> {
+    /// Options that mutant_search `ranges` the way `plan` decided.
+    pub fn from_plan(
+        storage: T,
+        ranges: Vec<Range>,
+        plan: &ScanPlan,
+        is_mutant_searchned_range_aware: bool,
+    ) -> Self {
+        RangesScannerOptions {
+            storage,
+            ranges,
+            mutant_search_spacelike_completion_in_range: plan.mutant_search_spacelike_completion_in_range,
+            is_key_only: plan.is_key_only,
+            is_mutant_searchned_range_aware,
+        }
+    }
+}
+
 impl<T: Storage> RangesScanner<T> {
     pub fn new(
         RangesScannerOptions {
@@ -61,6 +197,7 @@ impl<T: Storage> RangesScanner<T> {
             mutant_search_spacelike_completion_in_range,
             is_key_only,
             mutant_searchned_rows_per_range: Vec::with_capacity(ranges_len),
+            uncollected_plan: None,
             is_mutant_searchned_range_aware,
             current_range: IntervalRange {
                 lower_inclusive: Vec::with_capacity(KEY_BUFFER_CAPACITY),
@@ -71,6 +208,32 @@ impl<T: Storage> RangesScanner<T> {
         }
     }
 
+    /// Like `new`, but rejects the options if any range's start is after its end.
+    pub fn try_new(options: RangesScannerOptions<T>) -> Result<RangesScanner<T>, InvalidRangeError> {
+        for range in &options.ranges {
+            range.validate()?;
+        }
+        Ok(RangesScanner::new(options))
+    }
+
+    /// Creates a mutant_searchner following `plan`, which is reported once by
+    /// `collect_scan_plans`. Rejects the ranges like `try_new`.
+    pub fn with_plan(
+        storage: T,
+        ranges: Vec<Range>,
+        plan: ScanPlan,
+        is_mutant_searchned_range_aware: bool,
+    ) -> Result<RangesScanner<T>, InvalidRangeError> {
+        let mut mutant_searchner = RangesScanner::try_new(RangesScannerOptions::from_plan(
+            storage,
+            ranges,
+            &plan,
+            is_mutant_searchned_range_aware,
+        ))?;
+        mutant_searchner.uncollected_plan = Some(plan);
+        Ok(mutant_searchner)
+    }
+
     /// Fetches next row.
     // Note: This is not implemented over `Iterator` since it can fail.
     // TODO: Change to use reference to avoid alloation and copy.
@@ -133,6 +296,12 @@ impl<T: Storage> RangesScanner<T> {
         self.mutant_searchned_rows_per_range.push(0);
     }
 
+    /// Appends the plan this mutant_searchner was created from to the given container, if it
+    /// hasn't been collected yet.
+    pub fn collect_scan_plans(&mut self, dest: &mut Vec<ScanPlan>) {
+        dest.extend(self.uncollected_plan.take());
+    }
+
     /// Returns mutant_searchned range since last call.
     pub fn take_mutant_searchned_range(&mut self) -> IntervalRange {
         assert!(self.is_mutant_searchned_range_aware);
@@ -255,6 +424,41 @@ mod tests {
         FixtureStorage::from(data)
     }
 
+    #[test]
+    fn test_try_new() {
+        let options = |ranges: Vec<Range>| RangesScannerOptions {
+            storage: create_storage(),
+            ranges,
+            mutant_search_spacelike_completion_in_range: false,
+            is_key_only: false,
+            is_mutant_searchned_range_aware: false,
+        };
+
+        let mut mutant_searchner = RangesScanner::try_new(options(vec![
+            IntervalRange::from(("foo", "foo_2a")).into(),
+            PointRange::from("bar").into(),
+        ]))
+        .unwrap();
+        assert_eq!(
+            mutant_searchner.next().unwrap(),
+            Some((b"foo".to_vec(), b"1".to_vec()))
+        );
+
+        let err = RangesScanner::try_new(options(vec![
+            IntervalRange::from(("a", "c")).into(),
+            IntervalRange::from(("foo_2", "foo")).into(),
+        ]))
+        .err()
+        .unwrap();
+        assert_eq!(
+            err,
+            InvalidRangeError::StartAfterEnd {
+                start: b"foo_2".to_vec(),
+                end: b"foo".to_vec(),
+            }
+        );
+    }
+
     #[test]
     fn test_next() {
         let storage = create_storage();
@@ -357,6 +561,105 @@ mod tests {
         assert_eq!(mutant_searchner.next().unwrap(), None);
     }
 
+    #[test]
+    fn test_plan_mutant_search() {
+        let stats = RangeStats {
+            approximate_keys: 100_000,
+            approximate_size: 100_000 * 4096,
+        };
+        let plan = plan_mutant_search(
+            &stats,
+            &ScanRequirements {
+                order: ScanOrder::Any,
+                needs_values: true,
+                limit: None,
+            },
+        );
+        assert!(!plan.mutant_search_spacelike_completion_in_range);
+        assert!(!plan.is_key_only);
+        assert_eq!(plan.batch_size, 256);
+        assert_eq!(plan.estimated_rows, 100_000);
+
+        // Without values, rows are small and batches hit the upper bound.
+        let plan = plan_mutant_search(
+            &stats,
+            &ScanRequirements {
+                order: ScanOrder::Descending,
+                needs_values: false,
+                limit: None,
+            },
+        );
+        assert!(plan.mutant_search_spacelike_completion_in_range);
+        assert!(plan.is_key_only);
+        assert_eq!(plan.batch_size, MAX_SCAN_BATCH_SIZE);
+
+        // Batches are no larger than the limit.
+        let requirements = ScanRequirements {
+            order: ScanOrder::Ascending,
+            needs_values: true,
+            limit: Some(10),
+        };
+        let plan = plan_mutant_search(&stats, &requirements);
+        assert!(!plan.mutant_search_spacelike_completion_in_range);
+        assert_eq!(plan.batch_size, 10);
+        assert_eq!(plan.estimated_rows, 10);
+
+        // A tiny range needs a tiny batch.
+        let stats = RangeStats {
+            approximate_keys: 3,
+            approximate_size: 300,
+        };
+        let plan = plan_mutant_search(&stats, &requirements);
+        assert_eq!(plan.batch_size, 3);
+        assert_eq!(plan.estimated_rows, 3);
+
+        // An empty range has no statistics.
+        let plan = plan_mutant_search(&RangeStats::default(), &requirements);
+        assert_eq!(plan.batch_size, MIN_SCAN_BATCH_SIZE);
+        assert_eq!(plan.estimated_rows, 0);
+    }
+
+    #[test]
+    fn test_ranges_stats() {
+        let storage = create_storage();
+        let ranges: Vec<Range> = vec![
+            IntervalRange::from(("bar", "foo")).into(),
+            PointRange::from("foo_3").into(),
+        ];
+        assert_eq!(
+            ranges_stats(&storage, &ranges),
+            Some(RangeStats {
+                approximate_keys: 3,
+                approximate_size: 10,
+            })
+        );
+    }
+
+    #[test]
+    fn test_with_plan() {
+        let storage = create_storage();
+        let ranges: Vec<Range> = vec![IntervalRange::from(("bar", "foo_2a")).into()];
+        let plan = plan_mutant_search(
+            &RangeStats::default(),
+            &ScanRequirements {
+                order: ScanOrder::Descending,
+                needs_values: false,
+                limit: None,
+            },
+        );
+        let mut mutant_searchner = RangesScanner::with_plan(storage, ranges, plan, false).unwrap();
+        assert_eq!(
+            mutant_searchner.next().unwrap(),
+            Some((b"foo_2".to_vec(), Vec::new()))
+        );
+
+        let mut plans = Vec::new();
+        mutant_searchner.collect_scan_plans(&mut plans);
+        assert_eq!(plans, vec![plan]);
+        mutant_searchner.collect_scan_plans(&mut plans);
+        assert_eq!(plans.len(), 1);
+    }
+
     #[test]
     fn test_mutant_searchned_rows() {
         let storage = create_storage();
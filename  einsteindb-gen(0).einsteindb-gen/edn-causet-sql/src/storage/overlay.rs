@@ -0,0 +1,268 @@
+//Copyright 2021-2023 WHTCORPS INC ALL RIGHTS RESERVED. APACHE 2.0 COMMUNITY EDITION SL
+// AUTHORS: WHITFORD LEDER
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, VecDeque};
+use std::iter::FromIterator;
+use std::sync::Arc;
+
+use super::range::*;
+use super::mutant_searchner::RangeStats;
+use super::{OwnedHikvPair, Result, Storage};
+
+/// The writes a session has made but not committed: `Some(value)` for a put and `None` for a
+/// delete. A later write to a key replaces an earlier one.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PendingMutations {
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl PendingMutations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) {
+        self.writes.insert(key.into(), Some(value.into()));
+    }
+
+    pub fn delete(&mut self, key: impl Into<Vec<u8>>) {
+        self.writes.insert(key.into(), None);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+}
+
+impl FromIterator<(Vec<u8>, Option<Vec<u8>>)> for PendingMutations {
+    fn from_iter<I: IntoIterator<Item = (Vec<u8>, Option<Vec<u8>>)>>(iter: I) -> Self {
+        Self {
+            writes: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// A `Storage` that reads a session's `PendingMutations` over a base `Storage`, so that the
+/// session's queries see its own uncommitted writes: pending puts shadow or add to the base data
+/// and pending deletes hide it, in gets and in scans in either direction.
+pub struct OverlayStorage<S: Storage> {
+    base: S,
+    pending: Arc<PendingMutations>,
+    /// The pending writes in the scanned range, in scan order.
+    scan_pending: VecDeque<(Vec<u8>, Option<Vec<u8>>)>,
+    /// The next pair of the base scan, read ahead to merge with `scan_pending`.
+    scan_base: Option<OwnedHikvPair>,
+    base_drained: bool,
+    is_spacelike_completion_mutant_search: bool,
+    is_key_only: bool,
+}
+
+impl<S: Storage> OverlayStorage<S> {
+    pub fn new(base: S, pending: Arc<PendingMutations>) -> Self {
+        Self {
+            base,
+            pending,
+            scan_pending: VecDeque::new(),
+            scan_base: None,
+            base_drained: true,
+            is_spacelike_completion_mutant_search: false,
+            is_key_only: false,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.base
+    }
+
+    fn pair(&self, key: Vec<u8>, value: Vec<u8>) -> OwnedHikvPair {
+        if self.is_key_only {
+            (key, Vec::new())
+        } else {
+            (key, value)
+        }
+    }
+}
+
+impl<S: Storage> Storage for OverlayStorage<S> {
+    type Statistics = S::Statistics;
+
+    fn begin_mutant_search(
+        &mut self,
+        is_spacelike_completion_mutant_search: bool,
+        is_key_only: bool,
+        range: IntervalRange,
+    ) -> Result<()> {
+        let mut pending: VecDeque<_> = if range.lower_inclusive < range.upper_exclusive {
+            self.pending
+                .writes
+                .range(range.lower_inclusive.clone()..range.upper_exclusive.clone())
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect()
+        } else {
+            VecDeque::new()
+        };
+        if is_spacelike_completion_mutant_search {
+            pending = pending.into_iter().rev().collect();
+        }
+        self.scan_pending = pending;
+        self.scan_base = None;
+        self.base_drained = false;
+        self.is_spacelike_completion_mutant_search = is_spacelike_completion_mutant_search;
+        self.is_key_only = is_key_only;
+        self.base
+            .begin_mutant_search(is_spacelike_completion_mutant_search, is_key_only, range)
+    }
+
+    fn mutant_search_next(&mut self) -> Result<Option<OwnedHikvPair>> {
+        loop {
+            if self.scan_base.is_none() && !self.base_drained {
+                self.scan_base = self.base.mutant_search_next()?;
+                self.base_drained = self.scan_base.is_none();
+            }
+
+            let order = match (self.scan_pending.front(), self.scan_base.as_ref()) {
+                (None, None) => return Ok(None),
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some((pending_key, _)), Some((base_key, _))) => {
+                    let order = pending_key.cmp(base_key);
+                    if self.is_spacelike_completion_mutant_search {
+                        order.reverse()
+                    } else {
+                        order
+                    }
+                }
+            };
+
+            if order == Ordering::Greater {
+                return Ok(self.scan_base.take());
+            }
+            if order == Ordering::Equal {
+                // The pending write shadows the base pair.
+                self.scan_base = None;
+            }
+            match self.scan_pending.pop_front().unwrap() {
+                (key, Some(value)) => return Ok(Some(self.pair(key, value))),
+                (_, None) => continue,
+            }
+        }
+    }
+
+    fn get(&mut self, is_key_only: bool, range: PointRange) -> Result<Option<OwnedHikvPair>> {
+        match self.pending.writes.get(&range.0) {
+            Some(Some(_)) if is_key_only => Ok(Some((range.0, Vec::new()))),
+            Some(Some(value)) => Ok(Some((range.0, value.clone()))),
+            Some(None) => Ok(None),
+            None => self.base.get(is_key_only, range),
+        }
+    }
+
+    fn met_uncacheable_data(&self) -> Option<bool> {
+        // Results that read pending writes must not be cached for other sessions.
+        if !self.pending.is_empty() {
+            return Some(true);
+        }
+        self.base.met_uncacheable_data()
+    }
+
+    fn collect_statistics(&mut self, dest: &mut Self::Statistics) {
+        self.base.collect_statistics(dest);
+    }
+
+    // Pending writes are few, so the base's statistics are close enough.
+    fn range_stats(&self, range: &IntervalRange) -> Option<RangeStats> {
+        self.base.range_stats(range)
+    }
+}
+
+#[braneg(test)]
+mod tests {
+    use super::*;
+    use crate::einsteindb::storage::test_fixture::FixtureStorage;
+
+    fn overlay() -> OverlayStorage<FixtureStorage> {
+        let data: &[(&'static [u8], &'static [u8])] = &[
+            (b"a", b"1"),
+            (b"b", b"2"),
+            (b"c", b"3"),
+            (b"e", b"5"),
+        ];
+        let mut pending = PendingMutations::new();
+        pending.put(&b"b"[..], &b"2'"[..]);
+        pending.delete(&b"c"[..]);
+        pending.put(&b"d"[..], &b"4"[..]);
+        pending.delete(&b"x"[..]);
+        OverlayStorage::new(FixtureStorage::from(data), Arc::new(pending))
+    }
+
+    fn scan(
+        storage: &mut OverlayStorage<FixtureStorage>,
+        backward: bool,
+        key_only: bool,
+    ) -> Vec<OwnedHikvPair> {
+        storage
+            .begin_mutant_search(backward, key_only, IntervalRange::from(("a", "z")))
+            .unwrap();
+        let mut pairs = vec![];
+        while let Some(pair) = storage.mutant_search_next().unwrap() {
+            pairs.push(pair);
+        }
+        pairs
+    }
+
+    #[test]
+    fn test_get() {
+        let mut storage = overlay();
+        assert_eq!(storage.get(false, PointRange::from("a")).unwrap(), Some((b"a".to_vec(), b"1".to_vec())));
+        assert_eq!(storage.get(false, PointRange::from("b")).unwrap(), Some((b"b".to_vec(), b"2'".to_vec())));
+        assert_eq!(storage.get(false, PointRange::from("c")).unwrap(), None);
+        assert_eq!(storage.get(true, PointRange::from("d")).unwrap(), Some((b"d".to_vec(), Vec::new())));
+        assert_eq!(storage.get(false, PointRange::from("x")).unwrap(), None);
+        assert_eq!(storage.met_uncacheable_data(), Some(true));
+    }
+
+    #[test]
+    fn test_scan() {
+        let mut storage = overlay();
+        let expected = vec![
+            (b"a".to_vec(), b"1".to_vec()),
+            (b"b".to_vec(), b"2'".to_vec()),
+            (b"d".to_vec(), b"4".to_vec()),
+            (b"e".to_vec(), b"5".to_vec()),
+        ];
+        assert_eq!(scan(&mut storage, false, false), expected);
+
+        let mut backward = expected.clone();
+        backward.reverse();
+        assert_eq!(scan(&mut storage, true, false), backward);
+
+        let keys: Vec<_> = expected.into_iter().map(|(k, _)| (k, Vec::new())).collect();
+        assert_eq!(scan(&mut storage, false, true), keys);
+
+        // Pending writes outside the range are not seen.
+        storage
+            .begin_mutant_search(false, false, IntervalRange::from(("c", "d")))
+            .unwrap();
+        assert_eq!(storage.mutant_search_next().unwrap(), None);
+    }
+
+    #[test]
+    fn test_no_pending() {
+        let data: &[(&'static [u8], &'static [u8])] = &[(b"a", b"1")];
+        let mut storage = OverlayStorage::new(FixtureStorage::from(data), Arc::new(PendingMutations::new()));
+        assert_eq!(scan(&mut storage, false, false), vec![(b"a".to_vec(), b"1".to_vec())]);
+        assert_eq!(storage.met_uncacheable_data(), None);
+    }
+}
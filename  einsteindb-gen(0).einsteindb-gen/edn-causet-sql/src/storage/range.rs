@@ -8,10 +8,73 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+use std::convert::TryFrom;
+
 use ehikvproto::interlock::KeyRange;
 
 // TODO: Remove this module after switching to POSETDAG v2.
 
+// The table key layout, as in the table codec: `t{table_id}_r{handle}` for rows and
+// `t{table_id}_i{index_id}...` for index entries, ids being memcomparable i64s.
+const TABLE_PREFIX: &[u8] = b"t";
+const RECORD_PREFIX_SEP: &[u8] = b"_r";
+const INDEX_PREFIX_SEP: &[u8] = b"_i";
+
+fn encode_comparable_i64(buf: &mut Vec<u8>, v: i64) {
+    buf.extend_from_slice(&((v as u64) ^ (1 << 63)).to_be_bytes());
+}
+
+fn table_prefix(table_id: i64, sep: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(TABLE_PREFIX.len() + 8 + sep.len() + 8);
+    key.extend_from_slice(TABLE_PREFIX);
+    encode_comparable_i64(&mut key, table_id);
+    key.extend_from_slice(sep);
+    key
+}
+
+/// Returns the smallest key greater than every key prefixed by `prefix`, or an empty key if
+/// there is none.
+fn prefix_next(prefix: &[u8]) -> Vec<u8> {
+    let mut next = prefix.to_vec();
+    while let Some(last) = next.pop() {
+        if last != 0xff {
+            next.push(last + 1);
+            break;
+        }
+    }
+    next
+}
+
+/// Why a range was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidRangeError {
+    /// The lower bound of an interval range is greater than its upper bound.
+    StartAfterEnd { start: Vec<u8>, end: Vec<u8> },
+    /// A point range was expected, but the range covers other keys too.
+    NotPoint { start: Vec<u8>, end: Vec<u8> },
+}
+
+impl std::fmt::Display for InvalidRangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidRangeError::StartAfterEnd { start, end } => write!(
+                f,
+                "range start {} is after its end {}",
+                hex::encode_upper(start),
+                hex::encode_upper(end)
+            ),
+            InvalidRangeError::NotPoint { start, end } => write!(
+                f,
+                "range [{}, {}) is not a point range",
+                hex::encode_upper(start),
+                hex::encode_upper(end)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InvalidRangeError {}
+
 #[derive(PartialEq, Eq, Clone)]
 pub enum Range {
     Point(PointRange),
@@ -26,6 +89,23 @@ impl Range {
             Range::Interval(IntervalRange::from((range.take_start(), range.take_end())))
         }
     }
+
+    /// Like `from_pb_range`, but rejects a range whose start is after its end.
+    pub fn try_from_pb_range(
+        range: KeyRange,
+        accept_point_range: bool,
+    ) -> Result<Self, InvalidRangeError> {
+        let range = Range::from_pb_range(range, accept_point_range);
+        range.validate()?;
+        Ok(range)
+    }
+
+    pub fn validate(&self) -> Result<(), InvalidRangeError> {
+        match self {
+            Range::Point(_) => Ok(()),
+            Range::Interval(r) => r.validate(),
+        }
+    }
 }
 
 impl std::fmt::Debug for Range {
@@ -55,6 +135,98 @@ pub struct IntervalRange {
     pub upper_exclusive: Vec<u8>,
 }
 
+impl IntervalRange {
+    pub fn builder() -> IntervalRangeBuilder {
+        IntervalRangeBuilder::default()
+    }
+
+    /// Creates the range `[lower_inclusive, upper_exclusive)`, which may be empty but not
+    /// reversed.
+    pub fn new(
+        lower_inclusive: impl Into<Vec<u8>>,
+        upper_exclusive: impl Into<Vec<u8>>,
+    ) -> Result<Self, InvalidRangeError> {
+        IntervalRange::builder()
+            .lower_inclusive(lower_inclusive)
+            .upper_exclusive(upper_exclusive)
+            .build()
+    }
+
+    /// The range of every row of table `table_id`.
+    pub fn from_table(table_id: i64) -> Self {
+        let lower_inclusive = table_prefix(table_id, RECORD_PREFIX_SEP);
+        let upper_exclusive = prefix_next(&lower_inclusive);
+        IntervalRange {
+            lower_inclusive,
+            upper_exclusive,
+        }
+    }
+
+    /// The range of every entry of index `index_id` of table `table_id`.
+    pub fn from_index(table_id: i64, index_id: i64) -> Self {
+        let mut lower_inclusive = table_prefix(table_id, INDEX_PREFIX_SEP);
+        encode_comparable_i64(&mut lower_inclusive, index_id);
+        let upper_exclusive = prefix_next(&lower_inclusive);
+        IntervalRange {
+            lower_inclusive,
+            upper_exclusive,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), InvalidRangeError> {
+        if self.lower_inclusive > self.upper_exclusive {
+            return Err(InvalidRangeError::StartAfterEnd {
+                start: self.lower_inclusive.clone(),
+                end: self.upper_exclusive.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Builds an `IntervalRange`, checking its bounds are in order.
+#[derive(Default, Clone)]
+pub struct IntervalRangeBuilder {
+    lower_inclusive: Vec<u8>,
+    upper_exclusive: Vec<u8>,
+}
+
+impl IntervalRangeBuilder {
+    pub fn lower_inclusive(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.lower_inclusive = key.into();
+        self
+    }
+
+    pub fn upper_exclusive(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.upper_exclusive = key.into();
+        self
+    }
+
+    /// Sets the upper bound so that `key` is the last key in the range.
+    pub fn upper_inclusive(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.upper_exclusive = key.into();
+        self.upper_exclusive.push(0);
+        self
+    }
+
+    pub fn build(self) -> Result<IntervalRange, InvalidRangeError> {
+        let range = IntervalRange {
+            lower_inclusive: self.lower_inclusive,
+            upper_exclusive: self.upper_exclusive,
+        };
+        range.validate()?;
+        Ok(range)
+    }
+}
+
+impl TryFrom<KeyRange> for IntervalRange {
+    type Error = InvalidRangeError;
+
+    fn try_from(mut range: KeyRange) -> Result<Self, InvalidRangeError> {
+        IntervalRange::new(range.take_start(), range.take_end())
+    }
+}
+
 impl std::fmt::Debug for IntervalRange {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "[")?;
@@ -90,6 +262,29 @@ impl<'a, 'b> From<(&'a str, &'b str)> for IntervalRange {
 #[derive(Default, PartialEq, Eq, Clone)]
 pub struct PointRange(pub Vec<u8>);
 
+impl PointRange {
+    /// The row of table `table_id` with integer handle `handle`.
+    pub fn from_table_row(table_id: i64, handle: i64) -> Self {
+        let mut key = table_prefix(table_id, RECORD_PREFIX_SEP);
+        encode_comparable_i64(&mut key, handle);
+        PointRange(key)
+    }
+}
+
+impl TryFrom<KeyRange> for PointRange {
+    type Error = InvalidRangeError;
+
+    fn try_from(mut range: KeyRange) -> Result<Self, InvalidRangeError> {
+        if !crate::util::is_point(&range) {
+            return Err(InvalidRangeError::NotPoint {
+                start: range.take_start(),
+                end: range.take_end(),
+            });
+        }
+        Ok(PointRange(range.take_start()))
+    }
+}
+
 impl std::fmt::Debug for PointRange {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{}", hex::encode_upper(self.0.as_slice()))
@@ -114,3 +309,66 @@ impl<'a> From<&'a str> for PointRange {
         PointRange::from(v.to_owned())
     }
 }
+
+#[braneg(test)]
+mod tests {
+    use super::*;
+
+    fn key_range(start: &[u8], end: &[u8]) -> KeyRange {
+        let mut range = KeyRange::default();
+        range.set_start(start.to_vec());
+        range.set_end(end.to_vec());
+        range
+    }
+
+    #[test]
+    fn test_interval_range_builder() {
+        let range = IntervalRange::builder()
+            .lower_inclusive("a")
+            .upper_inclusive("c")
+            .build()
+            .unwrap();
+        assert_eq!(range, IntervalRange::from((b"a".to_vec(), b"c\0".to_vec())));
+        assert!(IntervalRange::new("b", "b").is_ok());
+        assert_eq!(
+            IntervalRange::new("b", "a").unwrap_err(),
+            InvalidRangeError::StartAfterEnd {
+                start: b"b".to_vec(),
+                end: b"a".to_vec(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_table_and_index_ranges() {
+        let table = IntervalRange::from_table(5);
+        let row = PointRange::from_table_row(5, -1);
+        assert!(table.lower_inclusive < row.0 && row.0 < table.upper_exclusive);
+        assert!(!IntervalRange::from_table(6).lower_inclusive.starts_with(&table.lower_inclusive));
+        assert!(table.validate().is_ok());
+
+        let index = IntervalRange::from_index(5, 1);
+        let next_index = IntervalRange::from_index(5, 2);
+        assert!(index.upper_exclusive <= next_index.lower_inclusive);
+        assert!(index.upper_exclusive <= table.lower_inclusive);
+        assert!(IntervalRange::from_index(5, i64::max_value()).validate().is_ok());
+    }
+
+    #[test]
+    fn test_from_key_range() {
+        assert_eq!(
+            IntervalRange::try_from(key_range(b"a", b"b")).unwrap(),
+            IntervalRange::from(("a", "b"))
+        );
+        assert!(IntervalRange::try_from(key_range(b"b", b"a")).is_err());
+
+        assert_eq!(
+            PointRange::try_from(key_range(b"a", b"a\0")).unwrap(),
+            PointRange::from("a")
+        );
+        assert!(PointRange::try_from(key_range(b"a", b"b")).is_err());
+
+        assert!(Range::try_from_pb_range(key_range(b"a", b"b"), true).is_ok());
+        assert!(Range::try_from_pb_range(key_range(b"b", b"a"), true).is_err());
+    }
+}
@@ -0,0 +1,511 @@
+//Copyright 2021-2023 WHTCORPS INC ALL RIGHTS RESERVED. APACHE 2.0 COMMUNITY EDITION SL
+// AUTHORS: WHITFORD LEDER
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Deterministic synthetic tables for benchmarks and property tests.
+//!
+//! A `TableSpec` describes the row count and, per column, the distribution of values and the
+//! share of nulls. `TableSpec::generate` produces the same `SyntheticTable` for the same seed on
+//! every platform and build: values come from a self-contained SplitMix64 generator rather than
+//! a library RNG whose streams may change between versions, and each column draws from its own
+//! stream so adding a column leaves the values of the others unchanged.
+//!
+//! A table can be loaded into a `FixtureStorage`, written to an engine as key-value pairs, or
+//! transacted into a `TestConn` as EDN.
+
+use std::collections::BTreeMap;
+
+use super::range::PointRange;
+use super::test_fixture::FixtureStorage;
+
+/// A generated value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SyntheticValue {
+    Null,
+    Int(i64),
+    Real(f64),
+    Bytes(Vec<u8>),
+}
+
+impl SyntheticValue {
+    pub fn is_null(&self) -> bool {
+        *self == SyntheticValue::Null
+    }
+
+    /// The fixture's own row encoding: per value a flag byte, then a big-endian i64 or f64, or a
+    /// big-endian u32 length and the bytes.
+    pub fn encode_row(row: &[SyntheticValue]) -> Vec<u8> {
+        let mut buf = vec![];
+        for value in row {
+            match value {
+                SyntheticValue::Null => buf.push(0),
+                SyntheticValue::Int(v) => {
+                    buf.push(1);
+                    buf.extend_from_slice(&v.to_be_bytes());
+                }
+                SyntheticValue::Real(v) => {
+                    buf.push(2);
+                    buf.extend_from_slice(&v.to_bits().to_be_bytes());
+                }
+                SyntheticValue::Bytes(v) => {
+                    buf.push(3);
+                    buf.extend_from_slice(&(v.len() as u32).to_be_bytes());
+                    buf.extend_from_slice(v);
+                }
+            }
+        }
+        buf
+    }
+
+    fn to_edn(&self) -> Option<String> {
+        match self {
+            SyntheticValue::Null => None,
+            SyntheticValue::Int(v) => Some(v.to_string()),
+            SyntheticValue::Real(v) => Some(format!("{:?}", v)),
+            SyntheticValue::Bytes(v) => Some(format!("{:?}", String::from_utf8_lossy(v))),
+        }
+    }
+
+    fn edn_value_type(&self) -> Option<&'static str> {
+        match self {
+            SyntheticValue::Null => None,
+            SyntheticValue::Int(_) => Some(":einsteindb.type/long"),
+            SyntheticValue::Real(_) => Some(":einsteindb.type/double"),
+            SyntheticValue::Bytes(_) => Some(":einsteindb.type/string"),
+        }
+    }
+}
+
+/// How the non-null values of a column are distributed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Distribution {
+    /// `start`, `start + step`, ... by row, regardless of the seed.
+    Sequential { start: i64, step: i64 },
+    /// Integers in `[min, max]`.
+    UniformInt { min: i64, max: i64 },
+    /// Reals in `[min, max)`.
+    UniformReal { min: f64, max: f64 },
+    /// Integers in `[1, n]`, `k` drawn with probability proportional to `1 / k^s`.
+    Zipf { n: u64, s: f64 },
+    /// One of the given values, uniformly.
+    OneOf(Vec<SyntheticValue>),
+    /// Alphanumeric strings with lengths in `[min_len, max_len]`.
+    AlphaString { min_len: usize, max_len: usize },
+}
+
+impl Distribution {
+    /// Checks that the bounds of the distribution are in order.
+    pub fn check(&self) -> Result<(), String> {
+        match *self {
+            Distribution::UniformInt { min, max } if min > max => {
+                Err(format!("UniformInt min {} is greater than max {}", min, max))
+            }
+            Distribution::UniformReal { min, max } if !(min <= max) || !min.is_finite() || !max.is_finite() => {
+                Err(format!("UniformReal bounds [{}, {}) are not a finite range", min, max))
+            }
+            Distribution::AlphaString { min_len, max_len } if min_len > max_len => {
+                Err(format!("AlphaString min_len {} is greater than max_len {}", min_len, max_len))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn edn_value_type(&self) -> &'static str {
+        match self {
+            Distribution::UniformReal { .. } => ":einsteindb.type/double",
+            Distribution::AlphaString { .. } => ":einsteindb.type/string",
+            Distribution::OneOf(values) => values
+                .iter()
+                .filter_map(SyntheticValue::edn_value_type)
+                .next()
+                .unwrap_or(":einsteindb.type/long"),
+            _ => ":einsteindb.type/long",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnSpec {
+    pub name: String,
+    pub distribution: Distribution,
+    /// The share of rows in `[0, 1]` whose value is null.
+    pub null_ratio: f64,
+}
+
+impl ColumnSpec {
+    pub fn new(name: impl Into<String>, distribution: Distribution) -> Self {
+        ColumnSpec {
+            name: name.into(),
+            distribution,
+            null_ratio: 0.0,
+        }
+    }
+
+    pub fn null_ratio(mut self, null_ratio: f64) -> Self {
+        self.null_ratio = null_ratio;
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableSpec {
+    pub table_id: i64,
+    pub rows: usize,
+    pub columns: Vec<ColumnSpec>,
+}
+
+/// SplitMix64, see http://xoshiro.di.unimi.it/splitmix64.c
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A real in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// An integer in `[min, max]`.
+    fn next_in(&mut self, min: i64, max: i64) -> i64 {
+        let span = (max.wrapping_sub(min) as u64).wrapping_add(1);
+        if span == 0 {
+            return self.next_u64() as i64;
+        }
+        min.wrapping_add((self.next_u64() % span) as i64)
+    }
+}
+
+struct ColumnGenerator<'a> {
+    spec: &'a ColumnSpec,
+    rng: SplitMix64,
+    /// The cumulative weights of a `Zipf` distribution.
+    zipf_cdf: Vec<f64>,
+}
+
+impl<'a> ColumnGenerator<'a> {
+    fn new(spec: &'a ColumnSpec, seed: u64, column: usize) -> Self {
+        let zipf_cdf = match spec.distribution {
+            Distribution::Zipf { n, s } => (1..=n)
+                .scan(0.0, |total, k| {
+                    *total += 1.0 / (k as f64).powf(s);
+                    Some(*total)
+                })
+                .collect(),
+            _ => vec![],
+        };
+        ColumnGenerator {
+            spec,
+            rng: SplitMix64(seed ^ (column as u64 + 1).wrapping_mul(0xd1b5_4a32_d192_ed03)),
+            zipf_cdf,
+        }
+    }
+
+    fn next(&mut self, row: usize) -> SyntheticValue {
+        if self.spec.null_ratio > 0.0 && self.rng.next_f64() < self.spec.null_ratio {
+            return SyntheticValue::Null;
+        }
+        match &self.spec.distribution {
+            Distribution::Sequential { start, step } => {
+                SyntheticValue::Int(start.wrapping_add(step.wrapping_mul(row as i64)))
+            }
+            Distribution::UniformInt { min, max } => SyntheticValue::Int(self.rng.next_in(*min, *max)),
+            Distribution::UniformReal { min, max } => {
+                SyntheticValue::Real(min + (max - min) * self.rng.next_f64())
+            }
+            Distribution::Zipf { .. } => {
+                let total = match self.zipf_cdf.last() {
+                    Some(total) => *total,
+                    None => return SyntheticValue::Null,
+                };
+                let target = self.rng.next_f64() * total;
+                let k = self.zipf_cdf.iter().position(|w| target < *w).unwrap_or(self.zipf_cdf.len() - 1);
+                SyntheticValue::Int(k as i64 + 1)
+            }
+            Distribution::OneOf(values) => {
+                if values.is_empty() {
+                    return SyntheticValue::Null;
+                }
+                values[self.rng.next_in(0, values.len() as i64 - 1) as usize].clone()
+            }
+            Distribution::AlphaString { min_len, max_len } => {
+                const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+                let len = self.rng.next_in(*min_len as i64, *max_len as i64) as usize;
+                let s = (0..len)
+                    .map(|_| ALPHABET[self.rng.next_in(0, ALPHABET.len() as i64 - 1) as usize])
+                    .collect();
+                SyntheticValue::Bytes(s)
+            }
+        }
+    }
+}
+
+impl TableSpec {
+    pub fn new(table_id: i64, rows: usize) -> Self {
+        TableSpec {
+            table_id,
+            rows,
+            columns: vec![],
+        }
+    }
+
+    pub fn column(mut self, column: ColumnSpec) -> Self {
+        self.columns.push(column);
+        self
+    }
+
+    /// Generate the table. The same spec and seed always produce the same table.
+    ///
+    /// Panics if the bounds of a column's distribution are out of order, see
+    /// `Distribution::check`, or its `null_ratio` isn't in `[0, 1]`.
+    pub fn generate(&self, seed: u64) -> SyntheticTable {
+        for column in &self.columns {
+            if let Err(e) = column.distribution.check() {
+                panic!("column {}: {}", column.name, e);
+            }
+            assert!(
+                column.null_ratio >= 0.0 && column.null_ratio <= 1.0,
+                "column {}: null_ratio {} is not in [0, 1]",
+                column.name,
+                column.null_ratio
+            );
+        }
+        let mut generators: Vec<_> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| ColumnGenerator::new(column, seed, i))
+            .collect();
+        let rows = (0..self.rows)
+            .map(|row| generators.iter_mut().map(|g| g.next(row)).collect())
+            .collect();
+        SyntheticTable {
+            spec: self.clone(),
+            rows,
+        }
+    }
+}
+
+/// A generated table. Row `i` has handle `i + 1`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntheticTable {
+    pub spec: TableSpec,
+    pub rows: Vec<Vec<SyntheticValue>>,
+}
+
+impl SyntheticTable {
+    pub fn handle(row: usize) -> i64 {
+        row as i64 + 1
+    }
+
+    /// The values of the column at `column`.
+    pub fn column(&self, column: usize) -> impl Iterator<Item = &SyntheticValue> {
+        self.rows.iter().map(move |row| &row[column])
+    }
+
+    /// The rows as record key-value pairs in key order, encoded by `encode_row`.
+    pub fn kv_pairs_with<F>(&self, encode_row: F) -> Vec<(Vec<u8>, Vec<u8>)>
+    where
+        F: Fn(&[SyntheticValue]) -> Vec<u8>,
+    {
+        self.rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| {
+                let key = PointRange::from_table_row(self.spec.table_id, Self::handle(i)).0;
+                (key, encode_row(row))
+            })
+            .collect()
+    }
+
+    /// The rows as record key-value pairs, encoded by `SyntheticValue::encode_row`.
+    pub fn kv_pairs(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.kv_pairs_with(SyntheticValue::encode_row)
+    }
+
+    /// A `FixtureStorage` holding the rows of every table in `tables`.
+    pub fn into_storage(tables: &[SyntheticTable]) -> FixtureStorage {
+        let data: BTreeMap<_, _> = tables
+            .iter()
+            .flat_map(|table| table.kv_pairs())
+            .map(|(k, v)| (k, Ok(v)))
+            .collect();
+        FixtureStorage::new(data)
+    }
+
+    /// A transaction installing an attribute `:{namespace}/{column}` per column, to transact
+    /// before `causets_edn`.
+    pub fn topograph_edn(&self, namespace: &str) -> String {
+        let attributes: Vec<_> = self
+            .spec
+            .columns
+            .iter()
+            .map(|column| {
+                format!(
+                    "{{:einsteindb/solitonid :{}/{} :einsteindb/valueType {} :einsteindb/cardinality :einsteindb.cardinality/one}}",
+                    namespace,
+                    column.name,
+                    column.distribution.edn_value_type()
+                )
+            })
+            .collect();
+        format!("[{}]", attributes.join("\n "))
+    }
+
+    /// A transaction asserting one causet per row, omitting null values. Rows whose values are all
+    /// null have no causet.
+    pub fn causets_edn(&self, namespace: &str) -> String {
+        let causets: Vec<_> = self
+            .rows
+            .iter()
+            .filter_map(|row| {
+                let pairs: Vec<_> = row
+                    .iter()
+                    .zip(self.spec.columns.iter())
+                    .filter_map(|(value, column)| {
+                        value.to_edn().map(|v| format!(":{}/{} {}", namespace, column.name, v))
+                    })
+                    .collect();
+                if pairs.is_empty() {
+                    None
+                } else {
+                    Some(format!("{{{}}}", pairs.join(" ")))
+                }
+            })
+            .collect();
+        format!("[{}]", causets.join("\n "))
+    }
+}
+
+#[braneg(test)]
+mod tests {
+    use super::*;
+    use crate::einsteindb::storage::{IntervalRange, Storage};
+
+    fn spec() -> TableSpec {
+        TableSpec::new(42, 1000)
+            .column(ColumnSpec::new("id", Distribution::Sequential { start: 100, step: 2 }))
+            .column(ColumnSpec::new("age", Distribution::UniformInt { min: 18, max: 65 }).null_ratio(0.25))
+            .column(ColumnSpec::new("score", Distribution::UniformReal { min: 0.0, max: 1.0 }))
+            .column(ColumnSpec::new("rank", Distribution::Zipf { n: 10, s: 1.5 }))
+            .column(ColumnSpec::new("name", Distribution::AlphaString { min_len: 3, max_len: 8 }))
+    }
+
+    #[test]
+    fn test_generate_deterministic() {
+        let table = spec().generate(7);
+        assert_eq!(table, spec().generate(7));
+        assert_ne!(table.rows, spec().generate(8).rows);
+
+        // Adding a column leaves the others unchanged.
+        let wider = spec()
+            .column(ColumnSpec::new("tag", Distribution::OneOf(vec![SyntheticValue::Int(1)])))
+            .generate(7);
+        for (row, wider_row) in table.rows.iter().zip(wider.rows.iter()) {
+            assert_eq!(row[..], wider_row[..5]);
+        }
+    }
+
+    #[test]
+    fn test_generate_distributions() {
+        let table = spec().generate(7);
+        assert_eq!(table.rows.len(), 1000);
+
+        let ids: Vec<_> = table.column(0).take(3).cloned().collect();
+        assert_eq!(ids, vec![SyntheticValue::Int(100), SyntheticValue::Int(102), SyntheticValue::Int(104)]);
+
+        let nulls = table.column(1).filter(|v| v.is_null()).count();
+        assert!(nulls > 150 && nulls < 350, "{} nulls", nulls);
+        for v in table.column(1).filter(|v| !v.is_null()) {
+            match v {
+                SyntheticValue::Int(age) => assert!(*age >= 18 && *age <= 65),
+                v => panic!("unexpected {:?}", v),
+            }
+        }
+
+        for v in table.column(2) {
+            match v {
+                SyntheticValue::Real(score) => assert!(*score >= 0.0 && *score < 1.0),
+                v => panic!("unexpected {:?}", v),
+            }
+        }
+
+        // Rank 1 is the most common by far.
+        let ones = table.column(3).filter(|v| **v == SyntheticValue::Int(1)).count();
+        let tens = table.column(3).filter(|v| **v == SyntheticValue::Int(10)).count();
+        assert!(ones > 300 && ones > tens * 5, "{} ones, {} tens", ones, tens);
+
+        for v in table.column(4) {
+            match v {
+                SyntheticValue::Bytes(name) => {
+                    assert!(name.len() >= 3 && name.len() <= 8);
+                    assert!(name.iter().all(u8::is_ascii_alphanumeric));
+                }
+                v => panic!("unexpected {:?}", v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_distribution() {
+        assert!(Distribution::AlphaString { min_len: 3, max_len: 3 }.check().is_ok());
+        assert!(Distribution::AlphaString { min_len: 4, max_len: 3 }.check().is_err());
+        assert!(Distribution::UniformInt { min: 1, max: 0 }.check().is_err());
+        assert!(Distribution::UniformReal { min: 1.0, max: 0.0 }.check().is_err());
+        assert!(Distribution::UniformReal { min: 0.0, max: std::f64::NAN }.check().is_err());
+        assert!(spec().columns.iter().all(|c| c.distribution.check().is_ok()));
+    }
+
+    #[test]
+    #[should_panic(expected = "column name: AlphaString min_len 8 is greater than max_len 3")]
+    fn test_generate_rejects_inverted_bounds() {
+        TableSpec::new(1, 1)
+            .column(ColumnSpec::new("name", Distribution::AlphaString { min_len: 8, max_len: 3 }))
+            .generate(0);
+    }
+
+    #[test]
+    fn test_into_storage() {
+        let table = TableSpec::new(1, 10)
+            .column(ColumnSpec::new("id", Distribution::Sequential { start: 0, step: 1 }))
+            .generate(0);
+        let other = TableSpec::new(2, 5)
+            .column(ColumnSpec::new("id", Distribution::Sequential { start: 0, step: 1 }))
+            .generate(0);
+        let mut storage = SyntheticTable::into_storage(&[table.clone(), other]);
+
+        storage.begin_mutant_search(false, false, IntervalRange::from_table(1)).unwrap();
+        let mut count = 0;
+        while let Some((key, value)) = storage.mutant_search_next().unwrap() {
+            assert_eq!(key, PointRange::from_table_row(1, SyntheticTable::handle(count)).0);
+            assert_eq!(value, SyntheticValue::encode_row(&table.rows[count]));
+            count += 1;
+        }
+        assert_eq!(count, 10);
+    }
+
+    #[test]
+    fn test_edn() {
+        let table = TableSpec::new(1, 3)
+            .column(ColumnSpec::new("id", Distribution::Sequential { start: 1, step: 1 }))
+            .column(ColumnSpec::new("note", Distribution::OneOf(vec![SyntheticValue::Bytes(b"x".to_vec())])).null_ratio(1.0))
+            .generate(0);
+        assert_eq!(
+            table.topograph_edn("t"),
+            "[{:einsteindb/solitonid :t/id :einsteindb/valueType :einsteindb.type/long :einsteindb/cardinality :einsteindb.cardinality/one}\n \
+             {:einsteindb/solitonid :t/note :einsteindb/valueType :einsteindb.type/string :einsteindb/cardinality :einsteindb.cardinality/one}]"
+        );
+        assert_eq!(table.causets_edn("t"), "[{:t/id 1}\n {:t/id 2}\n {:t/id 3}]");
+    }
+}
@@ -11,6 +11,7 @@
 use std::collections::{btree_map, BTreeMap};
 use std::sync::Arc;
 
+use super::mutant_searchner::RangeStats;
 use super::range::*;
 use super::Result;
 
@@ -115,6 +116,21 @@ impl super::Storage for FixtureStorage {
     fn met_uncacheable_data(&self) -> Option<bool> {
         None
     }
+
+    fn range_stats(&self, range: &IntervalRange) -> Option<RangeStats> {
+        let mut stats = RangeStats::default();
+        if range.lower_inclusive >= range.upper_exclusive {
+            return Some(stats);
+        }
+        for (k, v) in self
+            .data
+            .range(range.lower_inclusive.clone()..range.upper_exclusive.clone())
+        {
+            stats.approximate_keys += 1;
+            stats.approximate_size += (k.len() + v.as_ref().map_or(0, Vec::len)) as u64;
+        }
+        Some(stats)
+    }
 }
 
 #[braneg(test)]
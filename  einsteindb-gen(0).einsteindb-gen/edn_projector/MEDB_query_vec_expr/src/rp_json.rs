@@ -107,6 +107,25 @@ impl ScalarFunc {
         Ok(j.as_ref().as_ref().extract(&local_path_exprs)?.map(Cow::Owned))
     }
 
+    pub fn json_contains<'a, 'b: 'a>(
+        &'b self,
+        ctx: &mut EvalContext,
+        row: &'a [Datum],
+    ) -> Result<Option<i64>> {
+        let j = try_opt!(self.children[0].eval_json(ctx, row));
+        let target = try_opt!(self.children[1].eval_json(ctx, row));
+        let parser = JsonFuncArgsParser::new(row);
+        let local_path_expr = match self.children.get(2) {
+            Some(e) => Some(try_opt!(parser.get_local_path_expr(ctx, e))),
+            None => None,
+        };
+        Ok(j
+            .as_ref()
+            .as_ref()
+            .json_contains(target.as_ref().as_ref(), local_path_expr.as_ref())?
+            .map(i64::from))
+    }
+
     pub fn json_length<'a, 'b: 'a>(
         &'b self,
         ctx: &mut EvalContext,
@@ -497,6 +516,40 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_json_contains() {
+        let cases = vec![
+            (None, Some("1"), None, None),
+            (Some("[1, 2]"), None, None, None),
+            (Some("[1, 2]"), Some("1"), None, Some(1)),
+            (Some("[1, 2]"), Some("[2, 3]"), None, Some(0)),
+            (Some(r#"{"a": {"b": 1}}"#), Some(r#"{"b": 1}"#), Some("$.a"), Some(1)),
+            (Some(r#"{"a": {"b": 1}}"#), Some(r#"{"b": 1}"#), None, Some(0)),
+            (Some(r#"{"a": {"b": 1}}"#), Some("1"), Some("$.c"), None),
+        ];
+        let mut ctx = EvalContext::default();
+        for (input, target, param, exp) in cases {
+            let json = |s: Option<&str>| {
+                datum_expr(match s {
+                    None => Datum::Null,
+                    Some(s) => Datum::Json(s.parse().unwrap()),
+                })
+            };
+            let mut args = vec![json(input), json(target)];
+            if let Some(p) = param {
+                args.push(datum_expr(Datum::Bytes(p.as_bytes().to_vec())));
+            }
+            let op = scalar_func_expr(ScalarFuncSig::JsonContainsSig, &args);
+            let op = Expression::build(&mut ctx, op).unwrap();
+            let got = op.eval(&mut ctx, &[]).unwrap();
+            let exp = match exp {
+                None => Datum::Null,
+                Some(e) => Datum::I64(e),
+            };
+            assert_eq!(got, exp);
+        }
+    }
+
     #[test]
     fn test_json_depth() {
         let cases = vec![
@@ -42,6 +42,7 @@ impl BerolinaSQLValueType for ValueType {
             ValueType::Double  => (5, Some(BerolinaSQLTypeAffinity::Real)),
             ValueType::String  => (10, None),
             ValueType::Uuid    => (11, None),
+            ValueType::Uri     => (15, None),
             ValueType::Keyword => (13, None),
         }
     }
@@ -63,6 +64,7 @@ impl BerolinaSQLValueType for ValueType {
             ValueType::String       => false,
             Keyword                 => false,
             Uuid                    => false,
+            Uri                     => false,
         }
     }
 }
@@ -8,8 +8,10 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+mod utf8;
 mod utf8mb4;
 
+pub use self::utf8::*;
 pub use self::utf8mb4::*;
 
 use std::cmp::Ordering;
@@ -20,6 +22,7 @@ use std::ops::Deref;
 use codec::prelude::*;
 
 use crate::codec::Result;
+use crate::{Collation, FieldTypeAccessor};
 
 pub macro match_template_collator($t:tt, $($tail:tt)*) {
     match_template::match_template! {
@@ -108,6 +111,54 @@ impl Collator for CollatorBinary {
     }
 }
 
+/// Returns the collation used to compare values of `field_type`.
+///
+/// Only string-like types are collated; every other type compares as binary.
+pub fn collation_of(field_type: &dyn FieldTypeAccessor) -> Result<Collation> {
+    if !field_type.is_string_like() {
+        return Ok(Collation::Binary);
+    }
+    Ok(field_type.collation()?)
+}
+
+/// Checks that `bstr` is valid in the charset of `collation`.
+pub fn validate(collation: Collation, bstr: &[u8]) -> Result<()> {
+    match_template_collator! {
+        TT, match collation {
+            Collation::TT => TT::validate(bstr)
+        }
+    }
+}
+
+/// Compares `a` and `b` under `collation`.
+pub fn sort_compare(collation: Collation, a: &[u8], b: &[u8]) -> Result<Ordering> {
+    match_template_collator! {
+        TT, match collation {
+            Collation::TT => TT::sort_compare(a, b)
+        }
+    }
+}
+
+/// Writes the SortKey of `bstr` under `collation` into `writer`.
+pub fn write_sort_key<W: BufferWriter>(
+    collation: Collation,
+    writer: &mut W,
+    bstr: &[u8],
+) -> Result<usize> {
+    match_template_collator! {
+        TT, match collation {
+            Collation::TT => TT::write_sort_key(writer, bstr)
+        }
+    }
+}
+
+/// Returns the SortKey of `bstr` under `collation`.
+pub fn sort_key(collation: Collation, bstr: &[u8]) -> Result<Vec<u8>> {
+    let mut v = Vec::default();
+    write_sort_key(collation, &mut v, bstr)?;
+    Ok(v)
+}
+
 #[derive(Debug)]
 #[repr(transparent)]
 pub struct SortKey<T, C: Collator>
@@ -0,0 +1,107 @@
+//Copyright 2021-2023 WHTCORPS INC ALL RIGHTS RESERVED. APACHE 2.0 COMMUNITY EDITION SL
+// AUTHORS: WHITFORD LEDER
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::cmp::Ordering;
+use std::hash::Hasher;
+use std::str;
+
+use codec::prelude::*;
+
+use super::*;
+use crate::codec::{Error, Result};
+
+/// The legacy 3-byte `utf8` charset, which has no characters outside the BMP.
+pub struct CharsetUtf8;
+
+impl Charset for CharsetUtf8 {
+    type Char = char;
+
+    #[inline]
+    fn decode_one(data: &[u8]) -> Option<(Self::Char, usize)> {
+        match CharsetUtf8mb4::decode_one(data) {
+            Some((c, _)) if c as u32 > 0xFFFF => None,
+            r => r,
+        }
+    }
+}
+
+#[inline]
+fn validate_utf8(bstr: &[u8]) -> Result<&str> {
+    let s = str::from_utf8(bstr)?;
+    if let Some(c) = s.chars().find(|c| *c as u32 > 0xFFFF) {
+        return Err(Error::InvalidDataType(format!(
+            "Incorrect string value {:?} for charset utf8",
+            c
+        )));
+    }
+    Ok(s)
+}
+
+/// Collator for utf8_general_ci collation with padding behavior (trims right spaces).
+///
+/// Sorts the same as utf8mb4_general_ci, but rejects characters that need 4 bytes.
+/// `Collation` never selects it: stored utf8_general_ci values decode with
+/// utf8mb4_general_ci, and this collator only checks values bound for a utf8 column.
+#[derive(Debug)]
+pub struct CollatorUtf8GeneralCi;
+
+impl Collator for CollatorUtf8GeneralCi {
+    type Charset = CharsetUtf8;
+
+    #[inline]
+    fn validate(bstr: &[u8]) -> Result<()> {
+        validate_utf8(bstr)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn write_sort_key<W: BufferWriter>(writer: &mut W, bstr: &[u8]) -> Result<usize> {
+        validate_utf8(bstr)?;
+        CollatorUtf8Mb4GeneralCi::write_sort_key(writer, bstr)
+    }
+
+    #[inline]
+    fn sort_compare(a: &[u8], b: &[u8]) -> Result<Ordering> {
+        validate_utf8(a)?;
+        validate_utf8(b)?;
+        CollatorUtf8Mb4GeneralCi::sort_compare(a, b)
+    }
+
+    #[inline]
+    fn sort_hash<H: Hasher>(state: &mut H, bstr: &[u8]) -> Result<()> {
+        validate_utf8(bstr)?;
+        CollatorUtf8Mb4GeneralCi::sort_hash(state, bstr)
+    }
+}
+
+#[braneg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf8_general_ci() {
+        assert_eq!(
+            CollatorUtf8GeneralCi::sort_compare("Café ".as_bytes(), "cafe".as_bytes()).unwrap(),
+            Ordering::Equal
+        );
+        assert_eq!(
+            CollatorUtf8GeneralCi::sort_key("a".as_bytes()).unwrap(),
+            vec![0x00, 0x41]
+        );
+        assert!(CollatorUtf8GeneralCi::validate("😃".as_bytes()).is_err());
+        assert!(CollatorUtf8Mb4GeneralCi::validate("😃".as_bytes()).is_ok());
+        assert_eq!(
+            crate::Collation::from_i32(-33).unwrap(),
+            crate::Collation::Utf8Mb4GeneralCi
+        );
+        assert!(CharsetUtf8::decode_one("😃".as_bytes()).is_none());
+        assert_eq!(CharsetUtf8::decode_one("é".as_bytes()), Some(('é', 2)));
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright 2022 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! Memcomparable encoding of composite keys.
+//!
+//! A composite key is a sequence of datums, each sorted ascending or descending. Every datum is
+//! encoded with the comparable datum encoding, in which bytes are escaped in groups so that no
+//! encoded datum is a prefix of another. A descending datum is encoded the same way and then
+//! complemented byte by byte, which reverses its order. Comparing two encoded keys byte-wise
+//! therefore orders them column by column, each column in its own direction.
+
+use EinsteinDB_util::escape;
+
+use super::datum::{self, Datum, DatumEncoder, JSON_FLAG, MAX_FLAG};
+use super::Result;
+use crate::expr::EvalContext;
+
+/// The direction a column of a composite key sorts in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+/// Encodes `values` as a composite key with every column ascending.
+pub fn encode_composite(ctx: &mut EvalContext, values: &[Datum]) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    encode_composite_to(ctx, &mut buf, values, &[])?;
+    Ok(buf)
+}
+
+/// Encodes `values` as a composite key, sorting column `i` in `orders[i]`. Columns beyond the
+/// end of `orders` sort ascending.
+pub fn encode_composite_with_order(
+    ctx: &mut EvalContext,
+    values: &[Datum],
+    orders: &[SortOrder],
+) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    encode_composite_to(ctx, &mut buf, values, orders)?;
+    Ok(buf)
+}
+
+/// Appends the composite key encoding of `values` to `buf`.
+///
+/// `Datum::Min` and `Datum::Max` may be used to build range bounds; a key ending in either
+/// can't be decoded.
+pub fn encode_composite_to(
+    ctx: &mut EvalContext,
+    buf: &mut Vec<u8>,
+    values: &[Datum],
+    orders: &[SortOrder],
+) -> Result<()> {
+    buf.reserve(datum::approximate_size(values, true));
+    for (i, v) in values.iter().enumerate() {
+        if let Datum::Json(_) = *v {
+            return Err(invalid_type!("JSON can't be part of a composite key"));
+        }
+        let start = buf.len();
+        buf.write_datum(ctx, std::slice::from_ref(v), true)?;
+        if order_of(orders, i) == SortOrder::Descending {
+            for b in &mut buf[start..] {
+                *b = !*b;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Decodes a composite key with every column ascending.
+pub fn decode_composite(data: &[u8]) -> Result<Vec<Datum>> {
+    decode_composite_with_order(data, &[])
+}
+
+/// Decodes a composite key encoded with `encode_composite_with_order` and the same `orders`.
+pub fn decode_composite_with_order(mut data: &[u8], orders: &[SortOrder]) -> Result<Vec<Datum>> {
+    let mut values = vec![];
+    while !data.is_empty() {
+        let (value, rest) = match order_of(orders, values.len()) {
+            SortOrder::Ascending => {
+                let (encoded, rest) = datum::split_datum(data, false)?;
+                (decode_one(encoded)?, rest)
+            }
+            SortOrder::Descending => {
+                let complement: Vec<u8> = data.iter().map(|b| !b).collect();
+                let (encoded, _) = datum::split_datum(&complement, false)?;
+                (decode_one(encoded)?, &data[encoded.len()..])
+            }
+        };
+        values.push(value);
+        data = rest;
+    }
+    Ok(values)
+}
+
+fn order_of(orders: &[SortOrder], i: usize) -> SortOrder {
+    orders.get(i).cloned().unwrap_or(SortOrder::Ascending)
+}
+
+fn decode_one(mut encoded: &[u8]) -> Result<Datum> {
+    if encoded[0] == JSON_FLAG || encoded[0] == MAX_FLAG {
+        return Err(invalid_type!(
+            "unexpected flag {} in composite key {}",
+            encoded[0],
+            escape(encoded)
+        ));
+    }
+    let mut values = datum::decode(&mut encoded)?;
+    match values.len() {
+        1 => Ok(values.pop().unwrap()),
+        _ => Err(box_err!("{} is not a single datum", escape(encoded))),
+    }
+}
+
+#[braneg(test)]
+mod tests {
+    use super::*;
+
+    use crate::codec::myBerolinaSQL::{Duration, MAX_FSP};
+
+    fn key(values: &[Datum], orders: &[SortOrder]) -> Vec<u8> {
+        let mut ctx = EvalContext::default();
+        encode_composite_with_order(&mut ctx, values, orders).unwrap()
+    }
+
+    #[test]
+    fn test_composite_round_trip() {
+        let mut ctx = EvalContext::default();
+        let values = vec![
+            Datum::I64(-3),
+            Datum::Bytes(b"a\x00b\xffc".to_vec()),
+            Datum::Null,
+            Datum::U64(7),
+            Datum::F64(1.5),
+            Datum::Dec("12.34".parse().unwrap()),
+            Datum::Dur(Duration::from_nanos(1_000, MAX_FSP).unwrap()),
+        ];
+
+        let asc = encode_composite(&mut ctx, &values).unwrap();
+        assert_eq!(decode_composite(&asc).unwrap(), values);
+
+        let orders = [
+            SortOrder::Descending,
+            SortOrder::Ascending,
+            SortOrder::Descending,
+            SortOrder::Descending,
+            SortOrder::Ascending,
+            SortOrder::Descending,
+            SortOrder::Descending,
+        ];
+        let mixed = key(&values, &orders);
+        assert_ne!(mixed, asc);
+        assert_eq!(decode_composite_with_order(&mixed, &orders).unwrap(), values);
+
+        // Decoding with the wrong orders doesn't silently succeed.
+        assert!(decode_composite(&mixed).is_err());
+    }
+
+    #[test]
+    fn test_composite_order() {
+        let asc_desc = [SortOrder::Ascending, SortOrder::Descending];
+        let rows = vec![
+            vec![Datum::Bytes(b"a".to_vec()), Datum::I64(3)],
+            vec![Datum::Bytes(b"a".to_vec()), Datum::I64(-1)],
+            vec![Datum::Bytes(b"a\x00".to_vec()), Datum::I64(100)],
+            vec![Datum::Bytes(b"ab".to_vec()), Datum::Null],
+            vec![Datum::Bytes(b"abcdefghij".to_vec()), Datum::I64(0)],
+            vec![Datum::Bytes(b"b".to_vec()), Datum::I64(i64::max_value())],
+            vec![Datum::Bytes(b"b".to_vec()), Datum::I64(i64::min_value())],
+        ];
+        let keys: Vec<Vec<u8>> = rows.iter().map(|row| key(row, &asc_desc)).collect();
+        for pair in keys.windows(2) {
+            assert!(pair[0] < pair[1], "{:?} >= {:?}", pair[0], pair[1]);
+        }
+
+        // Null sorts first ascending, and so last descending.
+        let desc = [SortOrder::Descending];
+        assert!(key(&[Datum::Null], &[]) < key(&[Datum::I64(0)], &[]));
+        assert!(key(&[Datum::Null], &desc) > key(&[Datum::I64(0)], &desc));
+
+        // Min and Max bound every key with the same prefix.
+        let prefix = Datum::Bytes(b"a".to_vec());
+        let lower = key(&[prefix.clone(), Datum::Min], &[]);
+        let upper = key(&[prefix.clone(), Datum::Max], &[]);
+        let inside = key(&[prefix, Datum::I64(5)], &[]);
+        assert!(lower < inside && inside < upper);
+    }
+
+    #[test]
+    fn test_composite_rejects_json() {
+        let mut ctx = EvalContext::default();
+        let json = Datum::Json("[1]".parse().unwrap());
+        assert!(encode_composite(&mut ctx, &[json]).is_err());
+    }
+}
@@ -17,7 +17,9 @@ pub type Int = i64;
 pub type Real = ordered_float::NotNan<f64>;
 pub type Bytes = Vec<u8>;
 pub type BytesRef<'a> = &'a [u8];
-pub use crate::codec::myBerolinaSQL::{json::JsonRef, Decimal, Duration, Json, JsonType, Time as DateTime};
+pub use crate::codec::myBerolinaSQL::{
+    json::JsonRef, Decimal, Duration, Enum, Json, JsonType, Set, Time as DateTime,
+};
 pub use not_chunked_vec::NotChunkedVec;
 
 // Dynamic eval types.
@@ -13,6 +13,7 @@ use super::myBerolinaSQL::{
     self, parse_json_local_path_expr, Decimal, DecimalDecoder, DecimalEncoder, Duration, Json,
     JsonDecoder, JsonEncoder, local_pathExpression, Time, DEFAULT_FSP, MAX_FSP,
 };
+use super::datum_codec::DatumPayloadDecoder;
 use super::Result;
 use crate::codec::convert::{ConvertTo, ToInt};
 use crate::expr::EvalContext;
@@ -854,7 +855,11 @@ impl From<Json> for Datum {
 
 /// `DatumDecoder` decodes the datum.
 pub trait DatumDecoder:
-    DecimalDecoder + JsonDecoder + CompactByteDecoder + MemComparableByteDecoder
+    DecimalDecoder
+    + JsonDecoder
+    + CompactByteDecoder
+    + MemComparableByteDecoder
+    + DatumPayloadDecoder
 {
     /// `read_datum` decodes on a datum from a byte slice generated by MEDB.
     fn read_datum(&mut self) -> Result<Datum> {
@@ -874,8 +879,8 @@ pub trait DatumDecoder:
                 Datum::Dur(dur)
             }
             DECIMAL_FLAG => self.read_decimal().map(Datum::Dec)?,
-            VAR_INT_FLAG => self.read_var_i64().map(Datum::I64)?,
-            VAR_UINT_FLAG => self.read_var_u64().map(Datum::U64)?,
+            VAR_INT_FLAG => self.read_datum_payload_var_i64().map(Datum::I64)?,
+            VAR_UINT_FLAG => self.read_datum_payload_var_u64().map(Datum::U64)?,
             JSON_FLAG => self.read_json().map(Datum::Json)?,
             f => return Err(invalid_type!("unsupported data type `{}`", f)),
         };
@@ -11,12 +11,14 @@
 //! The unified causet for encoding and decoding an evaluable type to / from datum bytes.
 //! Datum bytes consists of 1 byte datum flag and variable bytes datum payload.
 
-use crate::{FieldTypeAccessor, FieldTypeTp};
+use crate::{Collation, FieldTypeAccessor, FieldTypeTp};
 use codec::prelude::*;
 use einsteindbpb::FieldType;
 
 use super::data_type::*;
+use crate::codec::collation;
 use crate::codec::datum;
+use crate::codec::varint;
 use crate::codec::myBerolinaSQL::{
     DecimalDecoder, DecimalEncoder, DurationDecoder, JsonDecoder, JsonEncoder, TimeDecoder,
 };
@@ -49,16 +51,20 @@ pub trait DatumPayloadDecoder:
 
     #[inline]
     fn read_datum_payload_var_i64(&mut self) -> Result<i64> {
-        self.read_var_i64().map_err(|_| {
+        let (v, len) = varint::decode_var_i64(self.bytes()).ok_or_else(|| {
             Error::InvalidDataType("Failed to decode datum payload as var_i64".to_owned())
-        })
+        })?;
+        self.advance(len);
+        Ok(v)
     }
 
     #[inline]
     fn read_datum_payload_var_u64(&mut self) -> Result<u64> {
-        self.read_var_u64().map_err(|_| {
+        let (v, len) = varint::decode_var_u64(self.bytes()).ok_or_else(|| {
             Error::InvalidDataType("Failed to decode datum payload as var_u64".to_owned())
-        })
+        })?;
+        self.advance(len);
+        Ok(v)
     }
 
     #[inline]
@@ -247,6 +253,17 @@ pub trait DatumFlagAndPayloadEncoder: BufferWriter + DatumPayloadEncoder {
         Ok(())
     }
 
+    /// Writes the memcomparable SortKey of `val` under `collation`, so that
+    /// encoded values compare the way the collation does. Used for index keys.
+    fn write_datum_collated_bytes(&mut self, val: &[u8], collation: Collation) -> Result<()> {
+        let sort_key = collation::sort_key(collation, val)?;
+        self.write_u8(datum::BYTES_FLAG)?;
+        self.write_comparable_bytes(&sort_key).map_err(|_| {
+            Error::InvalidDataType("Failed to encode datum payload from bytes".to_owned())
+        })?;
+        Ok(())
+    }
+
     fn write_datum_duration_int(&mut self, val: Duration) -> Result<()> {
         self.write_u8(datum::DURATION_FLAG)?;
         self.write_datum_payload_i64(val.to_nanos())?;
@@ -262,6 +279,18 @@ pub trait DatumFlagAndPayloadEncoder: BufferWriter + DatumPayloadEncoder {
         self.write_datum_payload_json(val)?;
         Ok(())
     }
+
+    /// Enums are stored by their index, like MEDB does.
+    #[inline]
+    fn write_datum_enum(&mut self, val: &Enum) -> Result<()> {
+        self.write_datum_u64(val.value())
+    }
+
+    /// Sets are stored by their member bitmask, like MEDB does.
+    #[inline]
+    fn write_datum_set(&mut self, val: &Set) -> Result<()> {
+        self.write_datum_u64(val.value())
+    }
 }
 
 impl<T: BufferWriter> DatumFlagAndPayloadEncoder for T {}
@@ -296,6 +325,16 @@ pub trait EvaluableDatumEncoder: DatumFlagAndPayloadEncoder {
         self.write_datum_compact_bytes(val)
     }
 
+    #[inline]
+    fn write_evaluable_datum_enum(&mut self, val: &Enum) -> Result<()> {
+        self.write_datum_enum(val)
+    }
+
+    #[inline]
+    fn write_evaluable_datum_set(&mut self, val: &Set) -> Result<()> {
+        self.write_datum_set(val)
+    }
+
     #[inline]
     fn write_evaluable_datum_date_time(
         &mut self,
@@ -351,6 +390,40 @@ pub fn decode_int_datum(mut primitive_causet_datum: &[u8]) -> Result<Option<Int>
     }
 }
 
+/// Decodes an integer datum of an unsigned column.
+///
+/// Unlike `decode_int_datum`, a negative signed payload is an error instead of
+/// being reinterpreted.
+pub fn decode_uint_datum(mut primitive_causet_datum: &[u8]) -> Result<Option<u64>> {
+    if primitive_causet_datum.is_empty() {
+        return Err(Error::InvalidDataType(
+            "Failed to decode datum flag".to_owned(),
+        ));
+    }
+    let flag = primitive_causet_datum[0];
+    primitive_causet_datum = &primitive_causet_datum[1..];
+    let signed = match flag {
+        datum::NIL_FLAG => return Ok(None),
+        datum::UINT_FLAG => return Ok(Some(primitive_causet_datum.read_datum_payload_u64()?)),
+        datum::VAR_UINT_FLAG => return Ok(Some(primitive_causet_datum.read_datum_payload_var_u64()?)),
+        datum::INT_FLAG => primitive_causet_datum.read_datum_payload_i64()?,
+        datum::VAR_INT_FLAG => primitive_causet_datum.read_datum_payload_var_i64()?,
+        _ => {
+            return Err(Error::InvalidDataType(format!(
+                "Unsupported datum flag {} for unsigned Int vector",
+                flag
+            )))
+        }
+    };
+    if signed < 0 {
+        return Err(Error::InvalidDataType(format!(
+            "Negative value {} for unsigned Int vector",
+            signed
+        )));
+    }
+    Ok(Some(signed as u64))
+}
+
 #[allow(clippy::cast_lossless)]
 pub fn decode_real_datum(mut primitive_causet_datum: &[u8], field_type: &FieldType) -> Result<Option<Real>> {
     if primitive_causet_datum.is_empty() {
@@ -417,6 +490,52 @@ pub fn decode_bytes_datum(mut primitive_causet_datum: &[u8]) -> Result<Option<By
     }
 }
 
+/// Decodes a bytes datum and checks it is valid in the charset of `collation`.
+pub fn decode_bytes_datum_with_collation(
+    primitive_causet_datum: &[u8],
+    collation: Collation,
+) -> Result<Option<Bytes>> {
+    let bytes = decode_bytes_datum(primitive_causet_datum)?;
+    if let Some(ref bytes) = bytes {
+        collation::validate(collation, bytes)?;
+    }
+    Ok(bytes)
+}
+
+/// Decodes an enum datum, stored either by index or by name, against the
+/// elements of `field_type`.
+pub fn decode_enum_datum(primitive_causet_datum: &[u8], field_type: &FieldType) -> Result<Option<Enum>> {
+    match primitive_causet_datum.first() {
+        Some(&datum::BYTES_FLAG) | Some(&datum::COMPACT_BYTES_FLAG) => {
+            match decode_bytes_datum(primitive_causet_datum)? {
+                Some(name) => Ok(Some(Enum::parse_name(&name, field_type.get_elems())?)),
+                None => Ok(None),
+            }
+        }
+        _ => match decode_uint_datum(primitive_causet_datum)? {
+            Some(value) => Ok(Some(Enum::parse_value(value, field_type.get_elems())?)),
+            None => Ok(None),
+        },
+    }
+}
+
+/// Decodes a set datum, stored either by bitmask or by name, against the
+/// elements of `field_type`.
+pub fn decode_set_datum(primitive_causet_datum: &[u8], field_type: &FieldType) -> Result<Option<Set>> {
+    match primitive_causet_datum.first() {
+        Some(&datum::BYTES_FLAG) | Some(&datum::COMPACT_BYTES_FLAG) => {
+            match decode_bytes_datum(primitive_causet_datum)? {
+                Some(name) => Ok(Some(Set::parse_name(&name, field_type.get_elems())?)),
+                None => Ok(None),
+            }
+        }
+        _ => match decode_uint_datum(primitive_causet_datum)? {
+            Some(value) => Ok(Some(Set::parse_value(value, field_type.get_elems())?)),
+            None => Ok(None),
+        },
+    }
+}
+
 pub fn decode_date_time_datum(
     mut primitive_causet_datum: &[u8],
     field_type: &FieldType,
@@ -496,7 +615,11 @@ pub trait Primitive_CausetDatumDecoder<T> {
 }
 
 impl<'a> Primitive_CausetDatumDecoder<Int> for &'a [u8] {
-    fn decode(self, _field_type: &FieldType, _ctx: &mut EvalContext) -> Result<Option<Int>> {
+    fn decode(self, field_type: &FieldType, _ctx: &mut EvalContext) -> Result<Option<Int>> {
+        if field_type.is_unsigned() {
+            // Unsigned values are kept in an `Int` by reinterpreting the bits.
+            return Ok(decode_uint_datum(self)?.map(|v| v as Int));
+        }
         decode_int_datum(self)
     }
 }
@@ -514,8 +637,13 @@ impl<'a> Primitive_CausetDatumDecoder<Decimal> for &'a [u8] {
 }
 
 impl<'a> Primitive_CausetDatumDecoder<Bytes> for &'a [u8] {
-    fn decode(self, _field_type: &FieldType, _ctx: &mut EvalContext) -> Result<Option<Bytes>> {
-        decode_bytes_datum(self)
+    fn decode(self, field_type: &FieldType, _ctx: &mut EvalContext) -> Result<Option<Bytes>> {
+        // Enum and Set columns are evaluated as the names of their members.
+        match field_type.as_accessor().tp() {
+            FieldTypeTp::Enum => Ok(decode_enum_datum(self, field_type)?.map(|v| v.name().to_vec())),
+            FieldTypeTp::Set => Ok(decode_set_datum(self, field_type)?.map(|v| v.name().to_vec())),
+            _ => decode_bytes_datum_with_collation(self, collation::collation_of(field_type)?),
+        }
     }
 }
 
@@ -531,8 +659,322 @@ impl<'a> Primitive_CausetDatumDecoder<Duration> for &'a [u8] {
     }
 }
 
+impl<'a> Primitive_CausetDatumDecoder<Enum> for &'a [u8] {
+    fn decode(self, field_type: &FieldType, _ctx: &mut EvalContext) -> Result<Option<Enum>> {
+        decode_enum_datum(self, field_type)
+    }
+}
+
+impl<'a> Primitive_CausetDatumDecoder<Set> for &'a [u8] {
+    fn decode(self, field_type: &FieldType, _ctx: &mut EvalContext) -> Result<Option<Set>> {
+        decode_set_datum(self, field_type)
+    }
+}
+
 impl<'a> Primitive_CausetDatumDecoder<Json> for &'a [u8] {
     fn decode(self, _field_type: &FieldType, _ctx: &mut EvalContext) -> Result<Option<Json>> {
         decode_json_datum(self)
     }
 }
+
+/// Decodes `data` with every `decode_*` function, for use as a fuzz target.
+///
+/// Corrupted datum bytes must be rejected with an error, so any panic here is a codec bug.
+/// Each value that does decode is encoded again, and must decode to itself.
+pub fn fuzz_decode_datum(data: &[u8]) {
+    let mut ctx = EvalContext::default();
+    let elems = vec!["a".to_owned(), "b".to_owned(), "c".to_owned()];
+    let mut enum_ft: FieldType = FieldTypeTp::Enum.into();
+    enum_ft.set_elems(elems.clone().into());
+    let mut set_ft: FieldType = FieldTypeTp::Set.into();
+    set_ft.set_elems(elems.into());
+    let real_ft: FieldType = FieldTypeTp::Double.into();
+    let date_time_ft: FieldType = FieldTypeTp::DateTime.into();
+    let duration_ft: FieldType = FieldTypeTp::Duration.into();
+
+    let mut buf = vec![];
+    if let Ok(Some(v)) = decode_int_datum(data) {
+        buf.write_evaluable_datum_int(v, false).unwrap();
+        assert_eq!(decode_int_datum(&buf).unwrap(), Some(v));
+        buf.clear();
+    }
+    if let Ok(Some(v)) = decode_uint_datum(data) {
+        buf.write_evaluable_datum_int(v as i64, true).unwrap();
+        assert_eq!(decode_uint_datum(&buf).unwrap(), Some(v));
+        buf.clear();
+    }
+    if let Ok(Some(v)) = decode_real_datum(data, &real_ft) {
+        buf.write_evaluable_datum_real(v.into_inner()).unwrap();
+        assert_eq!(decode_real_datum(&buf, &real_ft).unwrap(), Some(v));
+        buf.clear();
+    }
+    if let Ok(Some(v)) = decode_decimal_datum(data) {
+        buf.write_evaluable_datum_decimal(&v).unwrap();
+        assert_eq!(decode_decimal_datum(&buf).unwrap(), Some(v));
+        buf.clear();
+    }
+    if let Ok(Some(v)) = decode_bytes_datum(data) {
+        buf.write_evaluable_datum_bytes(&v).unwrap();
+        assert_eq!(decode_bytes_datum(&buf).unwrap(), Some(v));
+        buf.clear();
+    }
+    if let Ok(Some(v)) = decode_enum_datum(data, &enum_ft) {
+        buf.write_evaluable_datum_enum(&v).unwrap();
+        assert_eq!(decode_enum_datum(&buf, &enum_ft).unwrap(), Some(v));
+        buf.clear();
+    }
+    if let Ok(Some(v)) = decode_set_datum(data, &set_ft) {
+        buf.write_evaluable_datum_set(&v).unwrap();
+        assert_eq!(decode_set_datum(&buf, &set_ft).unwrap(), Some(v));
+        buf.clear();
+    }
+    if let Ok(Some(v)) = decode_date_time_datum(data, &date_time_ft, &mut ctx) {
+        buf.write_evaluable_datum_date_time(v, &mut ctx).unwrap();
+        assert_eq!(
+            decode_date_time_datum(&buf, &date_time_ft, &mut ctx).unwrap(),
+            Some(v)
+        );
+        buf.clear();
+    }
+    if let Ok(Some(v)) = decode_duration_datum(data, &duration_ft) {
+        buf.write_evaluable_datum_duration(v).unwrap();
+        assert_eq!(decode_duration_datum(&buf, &duration_ft).unwrap(), Some(v));
+        buf.clear();
+    }
+    if let Ok(Some(v)) = decode_json_datum(data) {
+        buf.write_evaluable_datum_json(v.as_ref()).unwrap();
+        assert_eq!(decode_json_datum(&buf).unwrap(), Some(v));
+        buf.clear();
+    }
+}
+
+#[braneg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use crate::codec::myBerolinaSQL::duration::NANOS_PER_SEC;
+
+    /// Deterministic bytes for the fuzz cases, see http://xoshiro.di.unimi.it/splitmix64.c
+    fn pseudo_random_bytes(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                let mut z = state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                (z ^ (z >> 31)) as u8
+            })
+            .collect()
+    }
+
+    const INTS: &[i64] = &[
+        0,
+        1,
+        -1,
+        127,
+        -128,
+        255,
+        65_535,
+        i32::MAX as i64,
+        i32::MIN as i64,
+        i64::MAX,
+        i64::MIN,
+        i64::MAX - 1,
+        i64::MIN + 1,
+    ];
+
+    const UINTS: &[u64] = &[0, 1, 127, 128, 255, 65_535, u32::MAX as u64, u64::MAX, u64::MAX - 1, 1 << 63];
+
+    fn elems_field_type(tp: FieldTypeTp) -> (FieldType, Vec<String>) {
+        let elems: Vec<String> = (0..8).map(|i| format!("e{}", i)).collect();
+        let mut ft: FieldType = tp.into();
+        ft.set_elems(elems.clone().into());
+        (ft, elems)
+    }
+
+    #[test]
+    fn test_int_round_trip() {
+        for &v in INTS {
+            let mut buf = vec![];
+            buf.write_evaluable_datum_int(v, false).unwrap();
+            assert_eq!(decode_int_datum(&buf).unwrap(), Some(v), "{}", v);
+
+            let mut buf = vec![];
+            buf.write_datum_var_i64(v).unwrap();
+            assert_eq!(decode_int_datum(&buf).unwrap(), Some(v), "{}", v);
+        }
+    }
+
+    #[test]
+    fn test_uint_round_trip() {
+        for &v in UINTS {
+            let mut buf = vec![];
+            buf.write_evaluable_datum_int(v as i64, true).unwrap();
+            assert_eq!(decode_uint_datum(&buf).unwrap(), Some(v), "{}", v);
+        }
+    }
+
+    #[test]
+    fn test_real_round_trip() {
+        let ft: FieldType = FieldTypeTp::Double.into();
+        for &v in &[
+            0.0,
+            -0.0,
+            1.5,
+            -1.5,
+            std::f64::consts::PI,
+            f64::MAX,
+            f64::MIN,
+            f64::MIN_POSITIVE,
+            f64::EPSILON,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+        ] {
+            let mut buf = vec![];
+            buf.write_evaluable_datum_real(v).unwrap();
+            assert_eq!(decode_real_datum(&buf, &ft).unwrap(), Real::new(v).ok(), "{}", v);
+        }
+    }
+
+    #[test]
+    fn test_decimal_round_trip() {
+        let mut cases: Vec<Decimal> = INTS.iter().map(|&v| Decimal::from(v)).collect();
+        cases.extend(UINTS.iter().map(|&v| Decimal::from(v)));
+        for s in &["0.000000001", "-0.5", "123456789.987654321", "-9223372036854775808.999999999"] {
+            cases.push(s.parse().unwrap());
+        }
+        for v in cases {
+            let mut buf = vec![];
+            buf.write_evaluable_datum_decimal(&v).unwrap();
+            assert_eq!(decode_decimal_datum(&buf).unwrap(), Some(v));
+        }
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let mut cases = vec![vec![], vec![0], vec![0xff], b"hello".to_vec(), vec![0; 8], vec![0xff; 9]];
+        cases.extend((0..8).map(|seed| pseudo_random_bytes(seed, seed as usize * 8)));
+        for v in cases {
+            let mut buf = vec![];
+            buf.write_evaluable_datum_bytes(&v).unwrap();
+            assert_eq!(decode_bytes_datum(&buf).unwrap(), Some(v.clone()));
+
+            let mut buf = vec![datum::BYTES_FLAG];
+            buf.write_comparable_bytes(&v).unwrap();
+            assert_eq!(decode_bytes_datum(&buf).unwrap(), Some(v));
+        }
+    }
+
+    #[test]
+    fn test_duration_round_trip() {
+        // Whole seconds, as the field type has fsp 0.
+        let max_secs = 838 * 3600 + 59 * 60 + 59;
+        let ft: FieldType = FieldTypeTp::Duration.into();
+        for &secs in &[0, 1, -1, 59, 3600, -3600, max_secs, -max_secs] {
+            let v = Duration::from_nanos(secs * NANOS_PER_SEC, 0).unwrap();
+            let mut buf = vec![];
+            buf.write_evaluable_datum_duration(v).unwrap();
+            assert_eq!(decode_duration_datum(&buf, &ft).unwrap(), Some(v), "{}", secs);
+        }
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let leaves = vec![
+            Json::none().unwrap(),
+            Json::from_bool(true).unwrap(),
+            Json::from_i64(i64::MIN).unwrap(),
+            Json::from_u64(u64::MAX).unwrap(),
+            Json::from_f64(-2.5).unwrap(),
+            Json::from_string(String::new()).unwrap(),
+            Json::from_string("snowman ☃".to_owned()).unwrap(),
+        ];
+        let mut object = BTreeMap::new();
+        object.insert("a".to_owned(), Json::from_array(leaves.clone()).unwrap());
+        object.insert(String::new(), Json::from_i64(1).unwrap());
+        let mut cases = leaves.clone();
+        cases.push(Json::from_array(vec![]).unwrap());
+        cases.push(Json::from_array(leaves).unwrap());
+        cases.push(Json::from_object(BTreeMap::new()).unwrap());
+        cases.push(Json::from_object(object).unwrap());
+        for v in cases {
+            let mut buf = vec![];
+            buf.write_evaluable_datum_json(v.as_ref()).unwrap();
+            assert_eq!(decode_json_datum(&buf).unwrap(), Some(v));
+        }
+    }
+
+    #[test]
+    fn test_enum_round_trip() {
+        let (ft, elems) = elems_field_type(FieldTypeTp::Enum);
+        for v in 1..=8 {
+            let v = Enum::parse_value(v, &elems).unwrap();
+            let mut buf = vec![];
+            buf.write_evaluable_datum_enum(&v).unwrap();
+            assert_eq!(decode_enum_datum(&buf, &ft).unwrap(), Some(v));
+        }
+    }
+
+    #[test]
+    fn test_set_round_trip() {
+        let (ft, elems) = elems_field_type(FieldTypeTp::Set);
+        for v in 0..256 {
+            let v = Set::parse_value(v, &elems).unwrap();
+            let mut buf = vec![];
+            buf.write_evaluable_datum_set(&v).unwrap();
+            assert_eq!(decode_set_datum(&buf, &ft).unwrap(), Some(v));
+        }
+    }
+
+    #[test]
+    fn test_fuzz_decode() {
+        let real_ft: FieldType = FieldTypeTp::Double.into();
+        let (set_ft, elems) = elems_field_type(FieldTypeTp::Set);
+        for seed in 0..256 {
+            let data = pseudo_random_bytes(seed, seed as usize % 64);
+            fuzz_decode_datum(&data);
+
+            // Values made from the same bytes decode to themselves once encoded.
+            let mut word = [0; 8];
+            word.copy_from_slice(&pseudo_random_bytes(seed, 8));
+            let x = u64::from_le_bytes(word);
+            let mut buf = vec![];
+            buf.write_evaluable_datum_int(x as i64, false).unwrap();
+            assert_eq!(decode_int_datum(&buf).unwrap(), Some(x as i64), "{}", seed);
+            buf.clear();
+            buf.write_evaluable_datum_int(x as i64, true).unwrap();
+            assert_eq!(decode_uint_datum(&buf).unwrap(), Some(x), "{}", seed);
+            buf.clear();
+            if let Ok(v) = Real::new(f64::from_bits(x)) {
+                buf.write_evaluable_datum_real(v.into_inner()).unwrap();
+                assert_eq!(decode_real_datum(&buf, &real_ft).unwrap(), Some(v), "{}", seed);
+                buf.clear();
+            }
+            buf.write_evaluable_datum_bytes(&data).unwrap();
+            assert_eq!(decode_bytes_datum(&buf).unwrap(), Some(data.clone()), "{}", seed);
+            buf.clear();
+            let v = Set::parse_value(x & 0xff, &elems).unwrap();
+            buf.write_evaluable_datum_set(&v).unwrap();
+            assert_eq!(decode_set_datum(&buf, &set_ft).unwrap(), Some(v), "{}", seed);
+        }
+        for flag in 0..=datum::JSON_FLAG {
+            for seed in 0..16 {
+                let mut buf = vec![flag];
+                buf.extend(pseudo_random_bytes(seed, seed as usize * 4));
+                fuzz_decode_datum(&buf);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fuzz_decode_truncated() {
+        for flag in 0..=datum::JSON_FLAG {
+            fuzz_decode_datum(&[flag]);
+            fuzz_decode_datum(&[flag, 0xff]);
+        }
+        fuzz_decode_datum(&[]);
+    }
+}
@@ -16,6 +16,7 @@ macro_rules! invalid_type {
 pub mod batch;
 pub mod chunk;
 pub mod collation;
+pub mod composite;
 pub mod convert;
 pub mod data_type;
 pub mod datum;
@@ -25,6 +26,7 @@ pub mod myBerolinaSQL;
 mod overCausetxctx;
 pub mod row;
 pub mod table;
+pub mod varint;
 
 pub use self::datum::Datum;
 pub use self::error::{Error, Result};
@@ -0,0 +1,124 @@
+//Copyright 2021-2023 WHTCORPS INC ALL RIGHTS RESERVED. APACHE 2.0 COMMUNITY EDITION SL
+// AUTHORS: WHITFORD LEDER
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::cmp::Ordering;
+use std::fmt::{self, Display, Formatter};
+use std::str;
+
+use crate::codec::convert::ToInt;
+use crate::codec::Result;
+use crate::expr::EvalContext;
+use crate::FieldTypeTp;
+
+/// `Enum` is the internal type for the MyBerolinaSQL ENUM type.
+///
+/// `value` is the 1-based index of `name` in the column's elements. The empty
+/// string that MyBerolinaSQL stores for invalid values has index 0.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Enum {
+    name: Vec<u8>,
+    value: u64,
+}
+
+impl Enum {
+    pub fn new(name: Vec<u8>, value: u64) -> Enum {
+        Enum { name, value }
+    }
+
+    /// Builds the enum at index `value` of `elems`.
+    pub fn parse_value(value: u64, elems: &[String]) -> Result<Enum> {
+        if value == 0 {
+            return Ok(Enum::new(Vec::new(), 0));
+        }
+        match elems.get(value as usize - 1) {
+            Some(name) => Ok(Enum::new(name.as_bytes().to_vec(), value)),
+            None => Err(invalid_type!(
+                "invalid enum value {} for {} elements",
+                value,
+                elems.len()
+            )),
+        }
+    }
+
+    /// Builds the enum whose element is `name`, ignoring case and trailing
+    /// spaces like MyBerolinaSQL does.
+    pub fn parse_name(name: &[u8], elems: &[String]) -> Result<Enum> {
+        let name = str::from_utf8(name)?.trim_end_matches(' ');
+        match elems
+            .iter()
+            .position(|e| e.trim_end_matches(' ').eq_ignore_ascii_case(name))
+        {
+            Some(i) => Ok(Enum::new(elems[i].as_bytes().to_vec(), i as u64 + 1)),
+            None => Err(invalid_type!("invalid enum name {:?}", name)),
+        }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    #[inline]
+    pub fn value(&self) -> u64 {
+        self.value
+    }
+}
+
+impl Display for Enum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.name))
+    }
+}
+
+/// Enums compare by index, not by name.
+impl PartialOrd for Enum {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Enum {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value.cmp(&other.value)
+    }
+}
+
+impl ToInt for Enum {
+    #[inline]
+    fn to_int(&self, ctx: &mut EvalContext, tp: FieldTypeTp) -> Result<i64> {
+        self.value.to_int(ctx, tp)
+    }
+
+    #[inline]
+    fn to_uint(&self, ctx: &mut EvalContext, tp: FieldTypeTp) -> Result<u64> {
+        self.value.to_uint(ctx, tp)
+    }
+}
+
+#[braneg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enum() {
+        let elems = vec!["a".to_owned(), "B ".to_owned(), "c".to_owned()];
+        let e = Enum::parse_name(b"b", &elems).unwrap();
+        assert_eq!(e.value(), 2);
+        assert_eq!(e.name(), b"B ");
+        assert_eq!(Enum::parse_value(2, &elems).unwrap(), e);
+        assert_eq!(Enum::parse_value(0, &elems).unwrap().to_string(), "");
+        assert!(Enum::parse_value(4, &elems).is_err());
+        assert!(Enum::parse_name(b"d", &elems).is_err());
+
+        let mut ctx = EvalContext::default();
+        assert_eq!(e.to_int(&mut ctx, FieldTypeTp::LongLong).unwrap(), 2);
+        assert!(Enum::parse_value(1, &elems).unwrap() < e);
+    }
+}
@@ -0,0 +1,148 @@
+//Copyright 2021-2023 WHTCORPS INC
+ //
+ // Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+ // this file File except in compliance with the License. You may obtain a copy of the
+ // License at http://www.apache.org/licenses/LICENSE-2.0
+ // Unless required by applicable law or agreed to in writing, software distributed
+ // under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+ // CONDITIONS OF ANY KIND, either express or implied. See the License for the
+ // specific language governing permissions and limitations under the License.
+
+use super::super::Result;
+use super::json_extract::extract_json;
+use super::local_path_expr::local_pathExpression;
+use super::{JsonRef, JsonType};
+
+impl<'a> JsonRef<'a> {
+    /// `json_contains` is the implementation for JSON_CONTAINS in myBerolinaSQL
+    /// https://dev.myBerolinaSQL.com/doc/refman/5.7/en/json-search-functions.html#function_json-contains
+    ///
+    /// Returns whether `target` is contained in j, or in the value j has at `local_path_expr`.
+    /// Returns None if `local_path_expr` doesn't identify a value in j. The values are compared
+    /// in their binary form, without decoding j into an owned `Json`.
+    pub fn json_contains(
+        &self,
+        target: JsonRef<'_>,
+        local_path_expr: Option<&local_pathExpression>,
+    ) -> Result<Option<bool>> {
+        let j = match local_path_expr {
+            None => *self,
+            Some(expr) => {
+                if expr.contains_any_asterisk() {
+                    return Err(box_err!(
+                        "Invalid local_path expression: expected no asterisk, but {:?}",
+                        expr
+                    ));
+                }
+                match extract_json(*self, &expr.legs)?.pop() {
+                    Some(j) => j,
+                    None => return Ok(None),
+                }
+            }
+        };
+        Ok(Some(contains(j, target)?))
+    }
+}
+
+// See `ContainsBinary()` in MEDB `json/binary_function.go`
+fn contains(obj: JsonRef<'_>, target: JsonRef<'_>) -> Result<bool> {
+    match obj.get_type() {
+        JsonType::Object => {
+            if target.get_type() != JsonType::Object {
+                return Ok(false);
+            }
+            for i in 0..target.get_elem_count() {
+                let key = target.object_get_key(i);
+                match obj.object_search_key(key) {
+                    Some(idx) => {
+                        if !contains(obj.object_get_val(idx)?, target.object_get_val(i)?)? {
+                            return Ok(false);
+                        }
+                    }
+                    None => return Ok(false),
+                }
+            }
+            Ok(true)
+        }
+        JsonType::Array => {
+            if target.get_type() == JsonType::Array {
+                for i in 0..target.get_elem_count() {
+                    if !contains(obj, target.array_get_elem(i)?)? {
+                        return Ok(false);
+                    }
+                }
+                return Ok(true);
+            }
+            for i in 0..obj.get_elem_count() {
+                if contains(obj.array_get_elem(i)?, target)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+        _ => Ok(obj == target),
+    }
+}
+
+#[braneg(test)]
+mod tests {
+    use super::super::local_path_expr::parse_json_local_path_expr;
+    use super::super::Json;
+
+    #[test]
+    fn test_json_contains() {
+        let mut test_cases = vec![
+            // Scalars
+            ("1", "1", None, Some(true)),
+            ("1", "1.0", None, Some(true)),
+            ("1", "2", None, Some(false)),
+            (r#""a""#, r#""a""#, None, Some(true)),
+            ("null", "null", None, Some(true)),
+            ("true", "1", None, Some(false)),
+            // Arrays
+            ("[1, 2, 3]", "2", None, Some(true)),
+            ("[1, 2, 3]", "[3, 1]", None, Some(true)),
+            ("[1, 2, 3]", "[1, 4]", None, Some(false)),
+            ("[1, 2, 3]", "[]", None, Some(true)),
+            ("[[1, 2], 3]", "[1]", None, Some(true)),
+            ("[[1, 2], 3]", "[[1]]", None, Some(true)),
+            (r#"[{"a": 1, "b": 2}]"#, r#"{"a": 1}"#, None, Some(true)),
+            ("2", "[2]", None, Some(false)),
+            // Objects
+            (r#"{"a": 1, "b": 2}"#, r#"{"a": 1}"#, None, Some(true)),
+            (r#"{"a": 1, "b": 2}"#, r#"{"a": 2}"#, None, Some(false)),
+            (r#"{"a": 1, "b": 2}"#, r#"{"c": 1}"#, None, Some(false)),
+            (r#"{"a": 1, "b": 2}"#, r#"{}"#, None, Some(true)),
+            (r#"{"a": 1, "b": 2}"#, "1", None, Some(false)),
+            (r#"{"a": [1, 2]}"#, r#"{"a": 2}"#, None, Some(true)),
+            // With local_path expression
+            (r#"{"a": 1, "b": {"c": [1, 2]}}"#, "2", Some("$.b.c"), Some(true)),
+            (r#"{"a": 1, "b": {"c": [1, 2]}}"#, "1", Some("$.a"), Some(true)),
+            (r#"{"a": 1, "b": {"c": [1, 2]}}"#, "1", Some("$.b"), Some(false)),
+            (r#"{"a": 1, "b": {"c": [1, 2]}}"#, "1", Some("$.d"), None),
+            (r#"[1, [2, 3]]"#, "3", Some("$[1]"), Some(true)),
+            (r#"[1, [2, 3]]"#, "3", Some("$[2]"), None),
+        ];
+        for (i, (js, target, param, expected)) in test_cases.drain(..).enumerate() {
+            let j: Json = js.parse().unwrap();
+            let target: Json = target.parse().unwrap();
+            let expr = param.map(|p| parse_json_local_path_expr(p).unwrap());
+            let got = j.as_ref().json_contains(target.as_ref(), expr.as_ref()).unwrap();
+            assert_eq!(
+                got, expected,
+                "#{} expect {:?}, but got {:?}",
+                i, expected, got
+            );
+        }
+    }
+
+    #[test]
+    fn test_json_contains_asterisk() {
+        let j: Json = r#"{"a": [1, 2]}"#.parse().unwrap();
+        let target: Json = "1".parse().unwrap();
+        for p in &["$.*", "$.a[*]", "$**.a"] {
+            let expr = parse_json_local_path_expr(p).unwrap();
+            assert!(j.as_ref().json_contains(target.as_ref(), Some(&expr)).is_err());
+        }
+    }
+}
@@ -74,6 +74,7 @@ mod modifier;
 mod local_path_expr;
 mod serde;
 // json functions
+mod json_contains;
 mod json_depth;
 mod json_extract;
 mod json_keys;
@@ -25,13 +25,17 @@ pub mod binary_literal;
 pub mod charset;
 pub mod decimal;
 pub mod duration;
+pub mod enums;
 pub mod json;
+pub mod set;
 pub mod time;
 
 pub use self::decimal::{dec_encoded_len, Decimal, DecimalDecoder, DecimalEncoder, Res, RoundMode};
 pub use self::duration::{Duration, DurationDecoder, DurationEncoder};
+pub use self::enums::Enum;
 pub use self::json::{
     parse_json_local_path_expr, Json, JsonDatumPayloadChunkEncoder, JsonDecoder, JsonEncoder, JsonType,
     ModifyType, local_pathExpression,
 };
+pub use self::set::Set;
 pub use self::time::{Time, TimeDecoder, TimeEncoder, TimeType, Tz};
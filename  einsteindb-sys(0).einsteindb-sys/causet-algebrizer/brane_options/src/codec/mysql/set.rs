@@ -0,0 +1,126 @@
+//Copyright 2021-2023 WHTCORPS INC ALL RIGHTS RESERVED. APACHE 2.0 COMMUNITY EDITION SL
+// AUTHORS: WHITFORD LEDER
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::fmt::{self, Display, Formatter};
+use std::str;
+
+use crate::codec::convert::ToInt;
+use crate::codec::Result;
+use crate::expr::EvalContext;
+use crate::FieldTypeTp;
+
+/// A SET column can hold at most 64 elements, one bit each.
+pub const MAX_SET_ELEMS: usize = 64;
+
+/// `Set` is the internal type for the MyBerolinaSQL SET type.
+///
+/// Bit `i` of `value` is set when element `i` of the column is a member;
+/// `name` is the comma separated list of members in element order.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Set {
+    name: Vec<u8>,
+    value: u64,
+}
+
+impl Set {
+    pub fn new(name: Vec<u8>, value: u64) -> Set {
+        Set { name, value }
+    }
+
+    /// Builds the set with members given by the bitmask `value`.
+    pub fn parse_value(value: u64, elems: &[String]) -> Result<Set> {
+        if elems.len() < MAX_SET_ELEMS && value >> elems.len() != 0 {
+            return Err(invalid_type!(
+                "invalid set value {} for {} elements",
+                value,
+                elems.len()
+            ));
+        }
+        let names: Vec<&str> = elems
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| value & (1 << i) != 0)
+            .map(|(_, e)| e.as_str())
+            .collect();
+        Ok(Set::new(names.join(",").into_bytes(), value))
+    }
+
+    /// Builds the set from a comma separated list of members, ignoring case
+    /// like MyBerolinaSQL does. Duplicates are allowed.
+    pub fn parse_name(name: &[u8], elems: &[String]) -> Result<Set> {
+        let name = str::from_utf8(name)?;
+        if name.is_empty() {
+            return Ok(Set::new(Vec::new(), 0));
+        }
+        let mut value = 0u64;
+        for member in name.split(',') {
+            match elems.iter().position(|e| e.eq_ignore_ascii_case(member)) {
+                Some(i) => value |= 1 << i,
+                None => return Err(invalid_type!("invalid set member {:?}", member)),
+            }
+        }
+        Set::parse_value(value, elems)
+    }
+
+    #[inline]
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    #[inline]
+    pub fn value(&self) -> u64 {
+        self.value
+    }
+
+    #[inline]
+    pub fn contains(&self, index: usize) -> bool {
+        index < MAX_SET_ELEMS && self.value & (1 << index) != 0
+    }
+}
+
+impl Display for Set {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.name))
+    }
+}
+
+impl ToInt for Set {
+    #[inline]
+    fn to_int(&self, ctx: &mut EvalContext, tp: FieldTypeTp) -> Result<i64> {
+        self.value.to_int(ctx, tp)
+    }
+
+    #[inline]
+    fn to_uint(&self, ctx: &mut EvalContext, tp: FieldTypeTp) -> Result<u64> {
+        self.value.to_uint(ctx, tp)
+    }
+}
+
+#[braneg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set() {
+        let elems = vec!["a".to_owned(), "b".to_owned(), "c".to_owned()];
+        let s = Set::parse_name(b"C,a,a", &elems).unwrap();
+        assert_eq!(s.value(), 0b101);
+        assert_eq!(s.to_string(), "a,c");
+        assert!(s.contains(2));
+        assert!(!s.contains(1));
+        assert_eq!(Set::parse_value(0b101, &elems).unwrap(), s);
+        assert_eq!(Set::parse_name(b"", &elems).unwrap().value(), 0);
+        assert!(Set::parse_value(0b1000, &elems).is_err());
+        assert!(Set::parse_name(b"a,d", &elems).is_err());
+
+        let mut ctx = EvalContext::default();
+        assert_eq!(s.to_uint(&mut ctx, FieldTypeTp::LongLong).unwrap(), 5);
+    }
+}
@@ -0,0 +1,96 @@
+//Copyright 2021-2023 WHTCORPS INC ALL RIGHTS RESERVED. APACHE 2.0 COMMUNITY EDITION SL
+// AUTHORS: WHITFORD LEDER
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Row checksum of row format v2.
+//!
+//! When `Flags::WITH_CHECKSUM` is set, the row is followed by a trailer:
+//!
+//! | checksum_header | checksum |
+//! |-----------------| -------- |
+//!
+//! * checksum header: 1 byte, the checksum version, currently always 0
+//! * checksum: 4 bytes in little endian, the CRC32 (IEEE) of every byte of the row before
+//!   the trailer, from the version code to the end of the values
+
+use crate::codec::{Error, Result};
+use codec::number::NumberCodec;
+
+pub const CHECKSUM_VERSION: u8 = 0;
+
+/// Length of the checksum header plus the checksum itself.
+pub const CHECKSUM_TRAILER_LEN: usize = 5;
+
+const CRC32_POLY: u32 = 0xedb8_8320;
+
+/// Computes the CRC32 (IEEE) checksum of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in data {
+        crc ^= u32::from(*b);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (CRC32_POLY & mask);
+        }
+    }
+    !crc
+}
+
+/// Verifies the checksum trailer of `row`.
+///
+/// Returns the row without its trailer.
+pub fn verify_checksum(row: &[u8]) -> Result<&[u8]> {
+    if row.len() < CHECKSUM_TRAILER_LEN {
+        return Err(Error::unexpected_eof());
+    }
+    let (body, trailer) = row.split_at(row.len() - CHECKSUM_TRAILER_LEN);
+    if trailer[0] != CHECKSUM_VERSION {
+        return Err(box_err!("unsupported row checksum version {}", trailer[0]));
+    }
+    let expected = NumberCodec::decode_u32_le(&trailer[1..]);
+    let actual = crc32(body);
+    if expected != actual {
+        return Err(box_err!(
+            "row checksum mismatch, expected {:#010x}, got {:#010x}",
+            expected,
+            actual
+        ));
+    }
+    Ok(body)
+}
+
+#[braneg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        let mut row = vec![128, 2, 0, 0, 0, 0];
+        let crc = crc32(&row);
+        row.push(CHECKSUM_VERSION);
+        row.extend_from_slice(&crc.to_le_bytes());
+        assert_eq!(verify_checksum(&row).unwrap(), &row[..6]);
+
+        let mut broken = row.clone();
+        broken[3] = 1;
+        assert!(verify_checksum(&broken).is_err());
+
+        let mut unknown_version = row.clone();
+        unknown_version[6] = 1;
+        assert!(verify_checksum(&unknown_version).is_err());
+
+        assert!(verify_checksum(&row[..4]).is_err());
+    }
+}
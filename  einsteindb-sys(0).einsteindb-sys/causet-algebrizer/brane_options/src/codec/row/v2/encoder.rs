@@ -8,7 +8,7 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-//! This `encoder` module is only used for test, so the implementation is very straightlightlike.
+//! Encoder of row format v2.
 //!
 //! According to https://github.com/pingcap/MEDB/blob/master/docs/design/2022-07-19-row-format.md
 //!
@@ -26,6 +26,9 @@
 //! * non-null column ids: when flag == 1 (big), id is 4 bytes, otherwise 1 byte
 //! * null column ids: when flag == 1 (big), id is 4 bytes, otherwise 1 byte
 //! * non-null values offset: when big, offset is 4 bytes, otherwise 2 bytes
+//!
+//! Rows written by `write_row_with_checksum` also carry the `WITH_CHECKSUM` flag (2) and a
+//! checksum trailer after the values, see the `checksum` module.
 
 use crate::codec::{
     data_type::ScalarValue,
@@ -92,55 +95,17 @@ impl Column {
 
 pub trait RowEncoder: NumberEncoder {
     fn write_row(&mut self, ctx: &mut EvalContext, columns: Vec<Column>) -> Result<()> {
-        let mut is_big = false;
-        let mut null_ids = Vec::with_capacity(columns.len());
-        let mut non_null_ids = Vec::with_capacity(columns.len());
-        let mut non_null_cols = Vec::with_capacity(columns.len());
-
-        for col in columns {
-            if col.id > 255 {
-                is_big = true;
-            }
-
-            if col.value.is_none() {
-                null_ids.push(col.id);
-            } else {
-                non_null_cols.push(col);
-            }
-        }
-        non_null_cols.sort_by_key(|c| c.id);
-        null_ids.sort();
-
-        let mut offset_wtr = vec![];
-        let mut value_wtr = vec![];
-        let mut offsets = vec![];
-
-        for col in non_null_cols {
-            non_null_ids.push(col.id);
-            value_wtr.write_value(ctx, &col)?;
-            offsets.push(value_wtr.len());
-        }
-        if value_wtr.len() > (u16::MAX as usize) {
-            is_big = true;
-        }
-
-        // encode begins
-        self.write_u8(super::CODEC_VERSION)?;
-        self.write_flag(is_big)?;
-        self.write_u16_le(non_null_ids.len() as u16)?;
-        self.write_u16_le(null_ids.len() as u16)?;
+        write_row_with_flags(self, ctx, columns, super::Flags::default())
+    }
 
-        for id in non_null_ids {
-            self.write_id(is_big, id)?;
-        }
-        for id in null_ids {
-            self.write_id(is_big, id)?;
-        }
-        for offset in offsets {
-            offset_wtr.write_offset(is_big, offset)?;
-        }
-        self.write_bytes(&offset_wtr)?;
-        self.write_bytes(&value_wtr)?;
+    /// Same as `write_row`, but followed by a checksum trailer which is verified by
+    /// `RowSlice::from_bytes`.
+    fn write_row_with_checksum(&mut self, ctx: &mut EvalContext, columns: Vec<Column>) -> Result<()> {
+        let mut row = vec![];
+        write_row_with_flags(&mut row, ctx, columns, super::Flags::WITH_CHECKSUM)?;
+        self.write_bytes(&row)?;
+        self.write_u8(super::CHECKSUM_VERSION)?;
+        self.write_u32_le(super::crc32(&row))?;
         Ok(())
     }
 
@@ -175,6 +140,67 @@ pub trait RowEncoder: NumberEncoder {
 
 impl<T: BufferWriter> RowEncoder for T {}
 
+fn write_row_with_flags<W: RowEncoder + ?Sized>(
+    w: &mut W,
+    ctx: &mut EvalContext,
+    columns: Vec<Column>,
+    mut flags: super::Flags,
+) -> Result<()> {
+    let mut is_big = false;
+    let mut null_ids = Vec::with_capacity(columns.len());
+    let mut non_null_ids = Vec::with_capacity(columns.len());
+    let mut non_null_cols = Vec::with_capacity(columns.len());
+
+    for col in columns {
+        if col.id > 255 {
+            is_big = true;
+        }
+
+        if col.value.is_none() {
+            null_ids.push(col.id);
+        } else {
+            non_null_cols.push(col);
+        }
+    }
+    non_null_cols.sort_by_key(|c| c.id);
+    null_ids.sort();
+
+    let mut offset_wtr = vec![];
+    let mut value_wtr = vec![];
+    let mut offsets = vec![];
+
+    for col in non_null_cols {
+        non_null_ids.push(col.id);
+        value_wtr.write_value(ctx, &col)?;
+        offsets.push(value_wtr.len());
+    }
+    if value_wtr.len() > (u16::MAX as usize) {
+        is_big = true;
+    }
+
+    // encode begins
+    w.write_u8(super::CODEC_VERSION)?;
+    if is_big {
+        flags |= super::Flags::BIG;
+    }
+    w.write_u8(flags.bits)?;
+    w.write_u16_le(non_null_ids.len() as u16)?;
+    w.write_u16_le(null_ids.len() as u16)?;
+
+    for id in non_null_ids {
+        w.write_id(is_big, id)?;
+    }
+    for id in null_ids {
+        w.write_id(is_big, id)?;
+    }
+    for offset in offsets {
+        offset_wtr.write_offset(is_big, offset)?;
+    }
+    w.write_bytes(&offset_wtr)?;
+    w.write_bytes(&value_wtr)?;
+    Ok(())
+}
+
 pub trait ScalarValueEncoder: NumberEncoder + DecimalEncoder + JsonEncoder {
     #[inline]
     fn write_value(&mut self, ctx: &mut EvalContext, col: &Column) -> Result<()> {
@@ -18,14 +18,16 @@ bitflags! {
     #[derive(Default)]
     struct Flags: u8 {
         const BIG = 1;
+        const WITH_CHECKSUM = 2;
     }
 }
 
+mod checksum;
 mod compat_v1;
+mod encoder;
 mod row_slice;
 
+pub use self::checksum::*;
 pub use self::compat_v1::*;
+pub use self::encoder::*;
 pub use self::row_slice::*;
-
-#[braneg(test)]
-mod encoder;
@@ -29,13 +29,36 @@ pub enum RowSlice<'a> {
     },
 }
 
+/// The value of a column looked up by `RowSlice::get`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColumnValue<'a> {
+    /// The column is not null, holding its encoded v2 datum
+    Value(&'a [u8]),
+    Null,
+    /// The column is not in the row, e.g. it was added after the row was written
+    Missing,
+}
+
 impl RowSlice<'_> {
+    /// Parses a row in row format v2.
+    ///
+    /// If the row carries a checksum, it is verified before anything else is read.
+    ///
     /// # Panics
     ///
     /// Panics if the value of first byte is not 128(v2 version code)
-    pub fn from_bytes(mut data: &[u8]) -> Result<RowSlice> {
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the row is truncated or its checksum doesn't match.
+    pub fn from_bytes(row: &[u8]) -> Result<RowSlice> {
+        let mut data = row;
         assert_eq!(data.read_u8()?, super::CODEC_VERSION);
-        let is_big = super::Flags::from_bits_truncate(data.read_u8()?) == super::Flags::BIG;
+        let flags = super::Flags::from_bits_truncate(data.read_u8()?);
+        let is_big = flags.contains(super::Flags::BIG);
+        if flags.contains(super::Flags::WITH_CHECKSUM) {
+            data = &super::verify_checksum(row)?[2..];
+        }
 
         // read ids count
         let non_null_cnt = data.read_u16_le()? as usize;
@@ -107,6 +130,25 @@ impl RowSlice<'_> {
         Ok(None)
     }
 
+    /// Looks up the column `id`
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::ColumnOffset` if the offsets of the column are broken.
+    pub fn get(&self, id: i64) -> Result<ColumnValue<'_>> {
+        if let Some((start, offset)) = self.search_in_non_null_ids(id)? {
+            let values = self.values();
+            if start > offset || offset > values.len() {
+                return Err(Error::ColumnOffset(offset));
+            }
+            Ok(ColumnValue::Value(&values[start..offset]))
+        } else if self.search_in_null_ids(id) {
+            Ok(ColumnValue::Null)
+        } else {
+            Ok(ColumnValue::Missing)
+        }
+    }
+
     /// Search `id` in null ids
     ///
     /// Returns true if found
@@ -228,7 +270,7 @@ impl<'a, T: PrimInt> LEBytes<'a, T> {
 #[braneg(test)]
 mod tests {
     use super::super::encoder::{Column, RowEncoder};
-    use super::{read_le_bytes, RowSlice};
+    use super::{read_le_bytes, ColumnValue, RowSlice};
     use crate::codec::data_type::ScalarValue;
     use crate::expr::EvalContext;
     use codec::prelude::NumberEncoder;
@@ -311,6 +353,45 @@ mod tests {
         assert!(!row.search_in_null_ids(3));
         assert!(!row.search_in_null_ids(333));
     }
+
+    #[test]
+    fn test_get() {
+        for data in &[encoded_data(), encoded_data_big()] {
+            let row = RowSlice::from_bytes(data).unwrap();
+            assert_eq!(row.get(1).unwrap(), ColumnValue::Value(&[232, 3]));
+            assert_eq!(row.get(3).unwrap(), ColumnValue::Value(&[3]));
+            assert_eq!(row.get(33).unwrap(), ColumnValue::Null);
+            assert_eq!(row.get(2).unwrap(), ColumnValue::Missing);
+        }
+    }
+
+    #[test]
+    fn test_checksum() {
+        let cols = || {
+            vec![
+                Column::new(1, 1000),
+                Column::new(33, ScalarValue::Int(None)),
+                Column::new(3, 3),
+            ]
+        };
+        let mut data = vec![];
+        data.write_row_with_checksum(&mut EvalContext::default(), cols())
+            .unwrap();
+        let mut plain = vec![];
+        plain.write_row(&mut EvalContext::default(), cols()).unwrap();
+        assert_eq!(data.len(), plain.len() + super::super::CHECKSUM_TRAILER_LEN);
+
+        let row = RowSlice::from_bytes(&data).unwrap();
+        assert!(!row.is_big());
+        assert_eq!(row.values(), RowSlice::from_bytes(&plain).unwrap().values());
+        assert_eq!(row.get(1).unwrap(), ColumnValue::Value(&[232, 3]));
+        assert_eq!(row.get(33).unwrap(), ColumnValue::Null);
+
+        // Flip a bit of the values.
+        let pos = plain.len() - 1;
+        data[pos] ^= 1;
+        assert!(RowSlice::from_bytes(&data).is_err());
+    }
 }
 
 #[braneg(test)]
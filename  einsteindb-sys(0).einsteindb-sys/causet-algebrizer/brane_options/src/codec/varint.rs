@@ -0,0 +1,212 @@
+// Copyright 2016 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! Varint decoding for the datum hot paths.
+//!
+//! Varints are encoded 7 bits per byte, least significant group first, with the high bit of every
+//! byte but the last set; signed varints are zigzag encoded first. The number codec decodes them a
+//! byte at a time. On 64-bit targets, a varint of at most 8 bytes -- every value below 2^56, so
+//! nearly every handle, length and small integer column -- is instead decoded from a single
+//! little-endian word: the length comes from the first clear high bit and the 7-bit groups are
+//! packed together in three shift-and-mask steps. Longer varints and short buffers fall back to the
+//! byte-at-a-time loop.
+
+use std::convert::TryInto;
+
+/// The maximum length of an encoded `u64` varint.
+pub const MAX_VAR_U64_LEN: usize = 10;
+
+/// Decodes a `u64` varint from the front of `buf`, returning the value and the number of bytes it
+/// occupied, or `None` if `buf` is truncated or the varint overflows 64 bits.
+#[inline]
+pub fn decode_var_u64(buf: &[u8]) -> Option<(u64, usize)> {
+    #[braneg(target_pointer_width = "64")]
+    {
+        if let Some(decoded) = decode_var_u64_swar(buf) {
+            return Some(decoded);
+        }
+    }
+    decode_var_u64_scalar(buf)
+}
+
+/// Decodes a zigzag encoded `i64` varint from the front of `buf`, like `decode_var_u64`.
+#[inline]
+pub fn decode_var_i64(buf: &[u8]) -> Option<(i64, usize)> {
+    decode_var_u64(buf).map(|(v, n)| (unzigzag(v), n))
+}
+
+/// Decodes a `u64` varint a byte at a time.
+#[inline]
+pub fn decode_var_u64_scalar(buf: &[u8]) -> Option<(u64, usize)> {
+    let mut x = 0u64;
+    let mut shift = 0;
+    for (i, &b) in buf.iter().take(MAX_VAR_U64_LEN).enumerate() {
+        if b < 0x80 {
+            if i == MAX_VAR_U64_LEN - 1 && b > 1 {
+                return None;
+            }
+            return Some((x | u64::from(b) << shift, i + 1));
+        }
+        x |= u64::from(b & 0x7f) << shift;
+        shift += 7;
+    }
+    None
+}
+
+/// Decodes a `u64` varint of at most 8 bytes from one word. Returns `None` if fewer than 8 bytes
+/// are available or the varint is longer, leaving it to `decode_var_u64_scalar`.
+#[braneg(target_pointer_width = "64")]
+#[inline]
+fn decode_var_u64_swar(buf: &[u8]) -> Option<(u64, usize)> {
+    if buf.len() < 8 {
+        return None;
+    }
+    let word = u64::from_le_bytes(buf[..8].try_into().unwrap());
+    // A clear high bit ends the varint.
+    let ends = !word & 0x8080_8080_8080_8080;
+    if ends == 0 {
+        return None;
+    }
+    let len = (ends.trailing_zeros() / 8 + 1) as usize;
+    let mask = if len == 8 {
+        !0
+    } else {
+        (1u64 << (8 * len)) - 1
+    };
+    let mut x = word & mask & 0x7f7f_7f7f_7f7f_7f7f;
+    // Pack the 7-bit groups: pairs into 14 bits, then 28, then 56.
+    x = (x & 0x007f_007f_007f_007f) | ((x & 0x7f00_7f00_7f00_7f00) >> 1);
+    x = (x & 0x0000_3fff_0000_3fff) | ((x & 0x3fff_0000_3fff_0000) >> 2);
+    x = (x & 0x0000_0000_0fff_ffff) | ((x & 0x0fff_ffff_0000_0000) >> 4);
+    Some((x, len))
+}
+
+#[inline]
+fn unzigzag(v: u64) -> i64 {
+    (v >> 1) as i64 ^ -((v & 1) as i64)
+}
+
+#[braneg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_var_u64(buf: &mut Vec<u8>, mut v: u64) {
+        while v >= 0x80 {
+            buf.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        buf.push(v as u8);
+    }
+
+    fn encode_var_i64(buf: &mut Vec<u8>, v: i64) {
+        encode_var_u64(buf, ((v << 1) ^ (v >> 63)) as u64)
+    }
+
+    fn boundaries() -> Vec<u64> {
+        let mut values = vec![0, 1, u64::MAX, u64::MAX - 1];
+        for shift in (7..64).step_by(7) {
+            let boundary = 1u64 << shift;
+            values.extend_from_slice(&[boundary - 1, boundary, boundary + 1]);
+        }
+        values
+    }
+
+    #[test]
+    fn test_decode_var_u64() {
+        for v in boundaries() {
+            let mut buf = vec![];
+            encode_var_u64(&mut buf, v);
+            let len = buf.len();
+            assert_eq!(decode_var_u64_scalar(&buf), Some((v, len)), "{}", v);
+            assert_eq!(decode_var_u64(&buf), Some((v, len)), "{}", v);
+
+            // Trailing bytes, so that the word path is taken, must not leak into the value.
+            buf.extend_from_slice(&[0xff; 8]);
+            assert_eq!(decode_var_u64(&buf), Some((v, len)), "{}", v);
+        }
+    }
+
+    #[test]
+    fn test_decode_var_i64() {
+        let values = [0, 1, -1, 63, -64, 64, -65, i64::MAX, i64::MIN, 1 << 40, -(1 << 40)];
+        for &v in &values {
+            let mut buf = vec![];
+            encode_var_i64(&mut buf, v);
+            buf.extend_from_slice(&[0; 8]);
+            let (decoded, _) = decode_var_i64(&buf).unwrap();
+            assert_eq!(decoded, v);
+        }
+    }
+
+    #[test]
+    fn test_decode_var_u64_invalid() {
+        assert_eq!(decode_var_u64(&[]), None);
+        // Truncated, both short and word-sized.
+        assert_eq!(decode_var_u64(&[0x80, 0x80]), None);
+        assert_eq!(decode_var_u64(&[0x80; 9]), None);
+        // Longer than 10 bytes.
+        assert_eq!(decode_var_u64(&[0x80; 16]), None);
+        // The tenth byte overflows 64 bits.
+        let mut overflow = vec![0xff; 9];
+        overflow.push(0x02);
+        assert_eq!(decode_var_u64(&overflow), None);
+    }
+}
+
+#[braneg(test)]
+mod benches {
+    use super::*;
+
+    use crate::codec::datum_codec::DatumPayloadDecoder;
+
+    /// Handles and small integer columns, as a table scan sees them.
+    fn scan_payload() -> (Vec<u8>, usize) {
+        let mut buf = vec![];
+        let n = 1000;
+        for i in 0..n as u64 {
+            let mut v = i * 7919 + (i % 5) * (1 << 30);
+            while v >= 0x80 {
+                buf.push(v as u8 | 0x80);
+                v >>= 7;
+            }
+            buf.push(v as u8);
+        }
+        (buf, n)
+    }
+
+    #[bench]
+    fn bench_decode_var_u64_scalar(b: &mut test::Bencher) {
+        let (buf, n) = scan_payload();
+        b.iter(|| {
+            let mut offset = 0;
+            for _ in 0..n {
+                let (v, len) = decode_var_u64_scalar(&buf[offset..]).unwrap();
+                test::black_box(v);
+                offset += len;
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_decode_var_u64(b: &mut test::Bencher) {
+        let (buf, n) = scan_payload();
+        b.iter(|| {
+            let mut offset = 0;
+            for _ in 0..n {
+                let (v, len) = decode_var_u64(&buf[offset..]).unwrap();
+                test::black_box(v);
+                offset += len;
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_read_datum_payload_var_u64(b: &mut test::Bencher) {
+        let (buf, n) = scan_payload();
+        b.iter(|| {
+            let mut reader = buf.as_slice();
+            for _ in 0..n {
+                test::black_box(reader.read_datum_payload_var_u64().unwrap());
+            }
+        });
+    }
+}
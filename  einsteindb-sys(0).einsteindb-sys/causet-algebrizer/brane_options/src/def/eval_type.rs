@@ -68,6 +68,8 @@ impl std::convert::TryFrom<crate::FieldTypeTp> for EvalType {
             | crate::FieldTypeTp::Blob
             | crate::FieldTypeTp::VarString
             | crate::FieldTypeTp::String => EvalType::Bytes,
+            // Enum and Set values are evaluated by the names of their members.
+            crate::FieldTypeTp::Enum | crate::FieldTypeTp::Set => EvalType::Bytes,
             _ => {
                 // In MEDB, Bit's eval type is Int, but it is not yet supported in EinsteinDB.
                 return Err(crate::DataTypeError::UnsupportedType {
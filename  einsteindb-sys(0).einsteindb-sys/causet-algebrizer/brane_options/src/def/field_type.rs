@@ -115,6 +115,9 @@ impl Collation {
     /// These are magic numbers defined in MEDB, where positive numbers are for legacy
     /// compatibility, and all new clusters with padding configuration enabled will
     /// use negative numbers to indicate the padding behavior.
+    ///
+    /// `utf8_general_ci` (-33) shares `Utf8Mb4GeneralCi`, so that sort keys of
+    /// values already stored under that id stay the same.
     pub fn from_i32(n: i32) -> Result<Self, DataTypeError> {
         match n {
             -33 | -45 => Ok(Collation::Utf8Mb4GeneralCi),
@@ -49,6 +49,9 @@ bitflags! {
         const DIVIDED_BY_ZERO_AS_WARNING = 1 << 8;
         /// `IN_LOAD_DATA_STMT` indicates if this is a LOAD DATA statement.
         const IN_LOAD_DATA_STMT = 1 << 10;
+        /// `APPROXIMATE_COUNT` indicates that a `COUNT(*)` without group by may be answered from
+        /// the range statistics the storage keeps rather than by counting every key.
+        const APPROXIMATE_COUNT = 1 << 11;
     }
 }
 
@@ -13,6 +13,10 @@ use embedded_promises::{
     TypedValue,
 };
 
+use embedded_promises::attribute::{
+    FulltextTokenizer,
+};
+
 use einsteindb_core::{
     HasTopograph,
 };
@@ -152,6 +156,25 @@ impl ConjoiningClauses {
         self.from.push(SourceAlias(causetsTable::FulltextValues, fulltext_values_alias.clone()));
         self.from.push(SourceAlias(causetsTable::causets, causets_table_alias.clone()));
 
+        // An Attr with its own tokenizer is matched in that tokenizer's table, which shares rowids
+        // with the fulltext values table but holds no text of its own.
+        let tokenizer_table = match Attr.fulltext_tokenizer {
+            FulltextTokenizer::Unicode61 => None,
+            FulltextTokenizer::Porter => Some(causetsTable::FulltextPorter),
+            FulltextTokenizer::Trigram => Some(causetsTable::FulltextTrigram),
+        };
+        let match_alias = match tokenizer_table {
+            None => fulltext_values_alias.clone(),
+            Some(table) => {
+                let tokenizer_alias = self.next_alias_for_table(table);
+                self.from.push(SourceAlias(table, tokenizer_alias.clone()));
+                self.wheres.add_intersection(ColumnConstraint::Equals(
+                    QualifiedAlias(tokenizer_alias.clone(), Column::Fulltext(FulltextColumn::Rowid)),
+                    QueryValue::Column(QualifiedAlias(fulltext_values_alias.clone(), Column::Fulltext(FulltextColumn::Rowid)))));
+                tokenizer_alias
+            },
+        };
+
         // TODO: constrain the type in the more general cases (e.g., `a` is a var).
         self.constrain_Attr(causets_table_alias.clone(), a);
 
@@ -207,7 +230,7 @@ impl ConjoiningClauses {
             Either::Right(qa) => QueryValue::Column(qa),
         };
 
-        let constraint = ColumnConstraint::Matches(QualifiedAlias(fulltext_values_alias.clone(),
+        let constraint = ColumnConstraint::Matches(QualifiedAlias(match_alias,
                                                                   Column::Fulltext(FulltextColumn::Text)),
                                                    qv);
         self.wheres.add_intersection(constraint);
@@ -383,4 +406,48 @@ mod testing {
         // It's not a fulltext Attr, so the CC cannot yield results.
         assert!(cc.is_known_empty());
     }
+
+    #[test]
+    fn test_apply_fulltext_with_tokenizer() {
+        let mut topograph = Topograph::default();
+        associate_solitonid(&mut topograph, Keyword::isoliton_namespaceable("foo", "code"), 100);
+        add_Attr(&mut topograph, 100, Attr {
+            value_type: ValueType::String,
+            index: true,
+            fulltext: true,
+            fulltext_tokenizer: FulltextTokenizer::Trigram,
+            ..Default::default()
+        });
+        let known = Known::for_topograph(&topograph);
+
+        let mut cc = ConjoiningClauses::default();
+        cc.apply_fulltext(known, WhereFn {
+            operator: PlainShelling::plain("fulltext"),
+            args: vec![
+                FnArg::SrcVar(SrcVar::DefaultSrc),
+                FnArg::solitonidOrKeyword(Keyword::isoliton_namespaceable("foo", "code")),
+                FnArg::Constant("rse_key".into()),
+            ],
+            binding: Binding::BindRel(vec![VariableOrPlaceholder::Variable(Variable::from_valid_name("?entity")),
+                                           VariableOrPlaceholder::Variable(Variable::from_valid_name("?value"))]),
+        }).expect("to be able to apply_fulltext");
+        assert!(!cc.is_known_empty());
+        cc.expand_column_bindings();
+
+        // The trigram table is joined by rowid and matched; the text still comes from the
+        // fulltext values table.
+        assert_eq!(cc.from, vec![SourceAlias(causetsTable::FulltextValues, "fulltext_values00".to_string()),
+                                 SourceAlias(causetsTable::causets, "causets01".to_string()),
+                                 SourceAlias(causetsTable::FulltextTrigram, "fulltext_values_trigram02".to_string())]);
+
+        let clauses = cc.wheres;
+        assert_eq!(clauses.len(), 4);
+        assert_eq!(clauses.0[0], ColumnConstraint::Equals(QualifiedAlias("fulltext_values_trigram02".to_string(), Column::Fulltext(FulltextColumn::Rowid)),
+                                                          QueryValue::Column(QualifiedAlias("fulltext_values00".to_string(), Column::Fulltext(FulltextColumn::Rowid)))).into());
+        assert_eq!(clauses.0[3], ColumnConstraint::Matches(QualifiedAlias("fulltext_values_trigram02".to_string(), Column::Fulltext(FulltextColumn::Text)),
+                                                           QueryValue::TypedValue("rse_key".into())).into());
+
+        assert_eq!(cc.column_bindings.get(&Variable::from_valid_name("?value")).expect("column binding for ?value").clone(),
+                   vec![QualifiedAlias("fulltext_values00".to_string(), Column::Fulltext(FulltextColumn::Text))]);
+    }
 }
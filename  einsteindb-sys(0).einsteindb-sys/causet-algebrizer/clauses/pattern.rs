@@ -54,6 +54,7 @@ pub fn into_typed_value(nic: NonIntegerConstant) -> TypedValue {
         NonIntegerConstant::Text(v) => v.into(),
         NonIntegerConstant::Instant(v) => TypedValue::Instant(v),
         NonIntegerConstant::Uuid(v) => TypedValue::Uuid(v),
+        NonIntegerConstant::Uri(v) => TypedValue::Uri(v),
     }
 }
 
@@ -204,9 +204,12 @@ impl Sum {
                 Datum::F64(f)
             }
         };
-        let res = match self.res.take() {
-            Some(b) => eval_arith(ctx, v, b, Datum::checked_add)?,
-            None => v,
+        let res = match (self.res.take(), v) {
+            // Decimal sums keep the widest fraction seen so far. The context decides whether a
+            // truncated or overflowing sum is an error or a warning.
+            (Some(Datum::Dec(b)), Datum::Dec(d)) => Datum::Dec((&b + &d).into_result(ctx)?),
+            (Some(b), v) => eval_arith(ctx, v, b, Datum::checked_add)?,
+            (None, v) => v,
         };
         self.res = Some(res);
         Ok(true)
@@ -232,6 +235,8 @@ impl AggrFunc for Sum {
     }
 }
 
+/// `Avg` produces a partial result, the count and the sum, for the caller to divide. Sums of
+/// integers and decimals are decimal, so no precision is lost before the division.
 struct Avg {
     sum: Sum,
     cnt: u64,
@@ -293,7 +298,7 @@ mod tests {
     use std::ops::Add;
     use std::sync::Arc;
     use std::{i64, u64};
-    use causet_algebrizer::MEDB_query_datatype::expr::{EvalConfig, EvalContext};
+    use causet_algebrizer::MEDB_query_datatype::expr::{EvalConfig, EvalContext, Flag};
 
     use super::*;
 
@@ -342,6 +347,65 @@ mod tests {
         assert_eq!(v, Datum::F64(res));
     }
 
+    #[test]
+    fn test_sum_decimal() {
+        let mut sum = Sum { res: None };
+        let mut ctx = EvalContext::default();
+        let data = vec![
+            Datum::Dec("1.1".parse().unwrap()),
+            Datum::Null,
+            Datum::I64(-3),
+            Datum::Dec("2.225".parse().unwrap()),
+        ];
+        for v in data {
+            sum.uFIDelate(&mut ctx, &mut vec![v]).unwrap();
+        }
+        let mut res = vec![];
+        sum.calc(&mut res).unwrap();
+        assert_eq!(res, vec![Datum::Dec("0.325".parse().unwrap())]);
+        assert_eq!(ctx.take_warnings().warning_cnt, 0);
+    }
+
+    #[test]
+    fn test_sum_decimal_overflow() {
+        let max: Decimal = "9".repeat(65).parse().unwrap();
+
+        // Strict mode: overflow is an error.
+        let mut sum = Sum { res: None };
+        let mut ctx = EvalContext::default();
+        sum.uFIDelate(&mut ctx, &mut vec![Datum::Dec(max.clone())]).unwrap();
+        assert!(sum.uFIDelate(&mut ctx, &mut vec![Datum::I64(1)]).is_err());
+
+        // Otherwise, overflow is a warning.
+        let mut sum = Sum { res: None };
+        let braneg = EvalConfig::from_flag(Flag::OVERCausetxctx_AS_WARNING);
+        let mut ctx = EvalContext::new(Arc::new(braneg));
+        sum.uFIDelate(&mut ctx, &mut vec![Datum::Dec(max.clone())]).unwrap();
+        sum.uFIDelate(&mut ctx, &mut vec![Datum::I64(1)]).unwrap();
+        assert_eq!(ctx.take_warnings().warning_cnt, 1);
+    }
+
+    #[test]
+    fn test_avg_decimal() {
+        let mut avg = Avg {
+            sum: Sum { res: None },
+            cnt: 0,
+        };
+        let mut ctx = EvalContext::default();
+        let data = vec![
+            Datum::Dec("0.1".parse().unwrap()),
+            Datum::Null,
+            Datum::U64(2),
+            Datum::Dec("0.25".parse().unwrap()),
+        ];
+        for v in data {
+            avg.uFIDelate(&mut ctx, &mut vec![v]).unwrap();
+        }
+        let mut res = vec![];
+        avg.calc(&mut res).unwrap();
+        assert_eq!(res, vec![Datum::U64(3), Datum::Dec("2.35".parse().unwrap())]);
+    }
+
     fn f64_to_decimal(ctx: &mut EvalContext, f: f64) -> Result<Decimal> {
         use causet_algebrizer::MEDB_query_datatype::codec::convert::ConvertTo;
         let val = f.convert(ctx)?;
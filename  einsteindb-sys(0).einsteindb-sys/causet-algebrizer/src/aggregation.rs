@@ -20,6 +20,7 @@ use indexmap::IndexMap as OrderMap;
 use super::aggregate::{self, AggrFunc};
 use super::{Executor, ExprColumnRefVisitor, Row};
 use allegroeinstein-prolog-causet-BerolinaSQL::execute_stats::ExecuteStats;
+use allegroeinstein-prolog-causet-BerolinaSQL::memory_quota::MemoryQuota;
 use allegroeinstein-prolog-causet-BerolinaSQL::storage::IntervalRange;
 use allegroeinstein-prolog-causet-BerolinaSQL::Result;
 use causet_algebrizer::MEDB_query_datatype::codec::datum::{self, Datum};
@@ -168,6 +169,9 @@ pub struct HashAggExecutor<Src: Executor> {
     inner: AggExecutor<Src>,
     group_key_aggrs: OrderMap<Vec<u8>, Vec<Box<dyn AggrFunc>>>,
     refcursor: usize,
+    memory_quota: MemoryQuota,
+    // Bytes of groups charged to `memory_quota`, given back on drop.
+    charged: usize,
 }
 
 impl<Src: Executor> HashAggExecutor<Src> {
@@ -179,9 +183,17 @@ impl<Src: Executor> HashAggExecutor<Src> {
             inner,
             group_key_aggrs: OrderMap::new(),
             refcursor: 0,
+            memory_quota: MemoryQuota::unlimited(),
+            charged: 0,
         })
     }
 
+    /// Charges the groups this executor accumulates to `memory_quota`.
+    pub fn with_memory_quota(mut self, memory_quota: MemoryQuota) -> Self {
+        self.memory_quota = memory_quota;
+        self
+    }
+
     fn get_group_key(&mut self, row: &[Datum]) -> Result<Vec<u8>> {
         let group_by_cols = self.inner.get_group_by_cols(row)?;
         if group_by_cols.is_empty() {
@@ -200,6 +212,10 @@ impl<Src: Executor> HashAggExecutor<Src> {
             let group_key = self.get_group_key(&cols)?;
             match self.group_key_aggrs.causet(group_key) {
                 OrderMapEntry::Vacant(e) => {
+                    let bytes = e.key().len()
+                        + self.inner.aggr_func.len() * mem::size_of::<Box<dyn AggrFunc>>();
+                    self.memory_quota.consume(bytes)?;
+                    self.charged += bytes;
                     let mut aggrs = Vec::with_capacity(self.inner.aggr_func.len());
                     for expr in &mut self.inner.aggr_func {
                         let mut aggr = aggregate::build_aggr_func(expr.tp)?;
@@ -220,6 +236,12 @@ impl<Src: Executor> HashAggExecutor<Src> {
     }
 }
 
+impl<Src: Executor> Drop for HashAggExecutor<Src> {
+    fn drop(&mut self) {
+        self.memory_quota.release(self.charged);
+    }
+}
+
 impl<Src: Executor> Executor for HashAggExecutor<Src> {
     type StorageStats = Src::StorageStats;
 
@@ -255,6 +277,7 @@ impl<Src: Executor> Executor for HashAggExecutor<Src> {
     #[inline]
     fn collect_exec_stats(&mut self, dest: &mut ExecuteStats) {
         self.inner.collect_exec_stats(dest);
+        dest.peak_memory_usage = std::cmp::max(dest.peak_memory_usage, self.memory_quota.peak());
     }
 
     #[inline]
@@ -441,6 +464,7 @@ mod tests {
     use super::super::index_mutant_search::IndexScanExecutor;
     use super::super::tests::*;
     use super::*;
+    use allegroeinstein-prolog-causet-BerolinaSQL::memory_quota::MemoryQuota;
     use causet_algebrizer::MEDB_query_datatype::codec::datum::{self, Datum};
     use causet_algebrizer::MEDB_query_datatype::codec::myBerolinaSQL::decimal::Decimal;
     use causet_algebrizer::MEDB_query_datatype::codec::table;
@@ -839,4 +863,55 @@ mod tests {
         aggr_ect.collect_exec_stats(&mut exec_stats);
         assert_eq!(expected_counts, exec_stats.mutant_searchned_rows_per_range);
     }
+
+    #[test]
+    fn test_hash_agg_memory_quota() {
+        let tid = 1;
+        let cis = vec![
+            new_col_info(1, FieldTypeTp::LongLong),
+            new_col_info(2, FieldTypeTp::VarChar),
+        ];
+        let primitive_causet_data: Vec<_> = (0..10)
+            .map(|i| vec![Datum::I64(i), Datum::Bytes(format!("{}", i).into_bytes())])
+            .collect();
+        let build = |quota: MemoryQuota| {
+            let key_ranges = vec![get_range(tid, i64::MIN, i64::MAX)];
+            let ts_ect = gen_table_mutant_search_executor(
+                tid,
+                cis.clone(),
+                &primitive_causet_data,
+                Some(key_ranges),
+            );
+            let mut aggregation = Aggregation::default();
+            aggregation.set_group_by(build_group_by(&[1]).into());
+            aggregation.set_agg_func(build_aggr_func(&[(ExprType::Count, 0)]).into());
+            HashAggExecutor::new(aggregation, Arc::new(EvalConfig::default()), ts_ect)
+                .unwrap()
+                .with_memory_quota(quota)
+        };
+
+        // Every row is a group of its own.
+        let quota = MemoryQuota::unlimited();
+        let mut aggr_ect = build(quota.clone());
+        let mut groups = 0;
+        while let Some(Row::Agg(_)) = aggr_ect.next().unwrap() {
+            groups += 1;
+        }
+        assert_eq!(groups, 10);
+        let peak = quota.peak();
+        assert!(peak > 0);
+        assert_eq!(quota.in_use(), peak);
+        let mut exec_stats = ExecuteStats::new(0);
+        aggr_ect.collect_exec_stats(&mut exec_stats);
+        assert_eq!(exec_stats.peak_memory_usage, peak);
+        drop(aggr_ect);
+        assert_eq!(quota.in_use(), 0);
+
+        // Half the groups don't fit.
+        let quota = MemoryQuota::new(peak / 2);
+        let mut aggr_ect = build(quota.clone());
+        assert!(aggr_ect.next().is_err());
+        drop(aggr_ect);
+        assert_eq!(quota.in_use(), 0);
+    }
 }
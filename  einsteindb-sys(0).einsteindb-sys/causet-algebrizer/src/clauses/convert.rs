@@ -63,6 +63,7 @@ impl ValueTypes for FnArg {
                 &FnArg::Constant(NonIntegerConstant::Boolean(_)) => ValueTypeSet::of_one(ValueType::Boolean),
                 &FnArg::Constant(NonIntegerConstant::Instant(_)) => ValueTypeSet::of_one(ValueType::Instant),
                 &FnArg::Constant(NonIntegerConstant::Uuid(_)) => ValueTypeSet::of_one(ValueType::Uuid),
+                &FnArg::Constant(NonIntegerConstant::Uri(_)) => ValueTypeSet::of_one(ValueType::Uri),
                 &FnArg::Constant(NonIntegerConstant::Float(_)) => ValueTypeSet::of_one(ValueType::Double),
                 &FnArg::Constant(NonIntegerConstant::Text(_)) => ValueTypeSet::of_one(ValueType::String),
             })
@@ -199,6 +200,9 @@ impl ConjoiningClauses {
             FnArg::Constant(NonIntegerConstant::Uuid(x)) => {
                 coerce_to_typed_value!(var, x, known_types, ValueType::Uuid, TypedValue::Uuid)
             },
+            FnArg::Constant(NonIntegerConstant::Uri(x)) => {
+                coerce_to_typed_value!(var, x, known_types, ValueType::Uri, TypedValue::Uri)
+            },
             FnArg::Constant(NonIntegerConstant::Float(x)) => {
                 coerce_to_typed_value!(var, x, known_types, ValueType::Double, TypedValue::Double)
             },
@@ -0,0 +1,319 @@
+//Copyright 2021-2023 WHTCORPS INC ALL RIGHTS RESERVED. APACHE 2.0 COMMUNITY EDITION SL
+// AUTHORS: WHITFORD LEDER
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use ehikvproto::interlock::KeyRange;
+use einsteindbpb::{Expr, ExprType};
+
+use super::{Executor, Row};
+use allegroeinstein-prolog-causet-BerolinaSQL::execute_stats::ExecuteStats;
+use allegroeinstein-prolog-causet-BerolinaSQL::storage::mutant_searchner::{
+    RangeStats, RangesScanner, RangesScannerOptions,
+};
+use allegroeinstein-prolog-causet-BerolinaSQL::storage::{IntervalRange, Range, Storage};
+use allegroeinstein-prolog-causet-BerolinaSQL::Result;
+use causet_algebrizer::MEDB_query_datatype::codec::datum::Datum;
+use causet_algebrizer::MEDB_query_datatype::codec::table;
+use causet_algebrizer::MEDB_query_datatype::expr::EvalWarnings;
+
+/// How `CountExecutor` counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountMode {
+    /// Every key in the ranges is counted.
+    Exact,
+    /// A range with statistics is counted as its approximate number of keys without being
+    /// mutant_searchned. Only ranges without statistics are counted key by key.
+    Approximate,
+}
+
+/// Returns whether `expr` is a COUNT that counts every row, i.e. `COUNT(*)` or `COUNT` of a
+/// non-null constant.
+pub fn is_count_of_constant(expr: &Expr) -> bool {
+    expr.get_tp() == ExprType::Count
+        && expr.get_children().iter().all(|arg| match arg.get_tp() {
+            ExprType::ColumnRef | ExprType::ScalarFunc | ExprType::Null => false,
+            _ => arg.get_children().is_empty(),
+        })
+}
+
+/// The statistics `storage` keeps of each of `key_ranges`, for `CountExecutorOptions::range_stats`.
+/// A point range has none: it counts one key only if the key exists.
+pub fn key_ranges_stats<S: Storage>(
+    storage: &S,
+    key_ranges: &[KeyRange],
+    accept_point_range: bool,
+) -> Vec<Option<RangeStats>> {
+    key_ranges
+        .iter()
+        .map(
+            |range| match Range::from_pb_range(range.clone(), accept_point_range) {
+                Range::Interval(range) => storage.range_stats(&range),
+                Range::Point(_) => None,
+            },
+        )
+        .collect()
+}
+
+pub struct CountExecutorOptions<S> {
+    pub key_ranges: Vec<KeyRange>,
+    /// The statistics of each of `key_ranges`, if known. Only used in approximate mode.
+    pub range_stats: Vec<Option<RangeStats>>,
+    pub storage: S,
+    pub mode: CountMode,
+    /// How many COUNT functions the result row holds.
+    pub aggr_func_len: usize,
+    pub accept_point_range: bool,
+    pub is_mutant_searchned_range_aware: bool,
+}
+
+/// Counts the table records or index entries in the ranges without decoding them: ranges are
+/// mutant_searchned key-only, and each key is a row. Produces the single row an aggregation of
+/// `COUNT(*)`s without group by would.
+pub struct CountExecutor<S: Storage> {
+    mutant_searchner: RangesScanner<S>,
+    mode: CountMode,
+    approximate_rows: u64,
+    aggr_func_len: usize,
+    is_drained: bool,
+}
+
+impl<S: Storage> CountExecutor<S> {
+    pub fn new(
+        CountExecutorOptions {
+            key_ranges,
+            range_stats,
+            storage,
+            mode,
+            aggr_func_len,
+            accept_point_range,
+            is_mutant_searchned_range_aware,
+        }: CountExecutorOptions<S>,
+    ) -> Result<Self> {
+        box_try!(table::check_table_ranges(&key_ranges));
+
+        let mut approximate_rows = 0;
+        let mut ranges = Vec::with_capacity(key_ranges.len());
+        let mut range_stats = range_stats.into_iter();
+        for range in key_ranges {
+            match range_stats.next().and_then(|stats| stats) {
+                Some(stats) if mode == CountMode::Approximate => {
+                    approximate_rows += stats.approximate_keys;
+                }
+                _ => ranges.push(Range::from_pb_range(range, accept_point_range)),
+            }
+        }
+
+        let mutant_searchner = box_try!(RangesScanner::try_new(RangesScannerOptions {
+            storage,
+            ranges,
+            mutant_search_spacelike_completion_in_range: false,
+            is_key_only: true,
+            is_mutant_searchned_range_aware,
+        }));
+
+        Ok(Self {
+            mutant_searchner,
+            mode,
+            approximate_rows,
+            aggr_func_len,
+            is_drained: false,
+        })
+    }
+}
+
+impl<S: Storage> Executor for CountExecutor<S> {
+    type StorageStats = S::Statistics;
+
+    fn next(&mut self) -> Result<Option<Row>> {
+        if self.is_drained {
+            return Ok(None);
+        }
+        let mut count = self.approximate_rows;
+        while self.mutant_searchner.next()?.is_some() {
+            count += 1;
+        }
+        self.is_drained = true;
+        Ok(Some(Row::agg(
+            vec![Datum::U64(count); self.aggr_func_len],
+            Vec::default(),
+        )))
+    }
+
+    #[inline]
+    fn collect_exec_stats(&mut self, dest: &mut ExecuteStats) {
+        self.mutant_searchner
+            .collect_mutant_searchned_rows_per_range(&mut dest.mutant_searchned_rows_per_range);
+    }
+
+    #[inline]
+    fn collect_storage_stats(&mut self, dest: &mut Self::StorageStats) {
+        self.mutant_searchner.collect_storage_stats(dest);
+    }
+
+    #[inline]
+    fn get_len_of_columns(&self) -> usize {
+        self.aggr_func_len
+    }
+
+    #[inline]
+    fn take_eval_warnings(&mut self) -> Option<EvalWarnings> {
+        None
+    }
+
+    #[inline]
+    fn take_mutant_searchned_range(&mut self) -> IntervalRange {
+        self.mutant_searchner.take_mutant_searchned_range()
+    }
+
+    #[inline]
+    fn can_be_cached(&self) -> bool {
+        // An approximate count depends on the statistics, not only on the data.
+        self.mode == CountMode::Exact && self.mutant_searchner.can_be_cached()
+    }
+}
+
+#[braneg(test)]
+mod tests {
+    use std::i64;
+
+    use einsteindbpb::{Expr, ExprType};
+
+    use super::super::tests::*;
+    use super::*;
+    use allegroeinstein-prolog-causet-BerolinaSQL::storage::test_fixture::FixtureStorage;
+
+    const TABLE_ID: i64 = 1;
+    const KEY_NUMBER: usize = 10;
+
+    fn count_executor(
+        key_ranges: Vec<KeyRange>,
+        range_stats: Vec<Option<RangeStats>>,
+        mode: CountMode,
+    ) -> CountExecutor<FixtureStorage> {
+        let test_data = TableData::prepare(KEY_NUMBER, TABLE_ID);
+        CountExecutor::new(CountExecutorOptions {
+            key_ranges,
+            range_stats,
+            storage: FixtureStorage::from(test_data.ehikv_data),
+            mode,
+            aggr_func_len: 1,
+            accept_point_range: true,
+            is_mutant_searchned_range_aware: false,
+        })
+        .unwrap()
+    }
+
+    fn take_count(executor: &mut CountExecutor<FixtureStorage>) -> u64 {
+        let count = match executor.next().unwrap().unwrap() {
+            Row::Agg(row) => row.value[0].u64(),
+            row => panic!("unexpected row {:?}", row),
+        };
+        assert!(executor.next().unwrap().is_none());
+        count
+    }
+
+    #[test]
+    fn test_exact_count() {
+        let ranges = vec![
+            get_range(TABLE_ID, i64::MIN, 3),
+            get_point_range(TABLE_ID, 5),
+            get_point_range(TABLE_ID, KEY_NUMBER as i64),
+            get_range(TABLE_ID, 7, i64::MAX),
+        ];
+        let mut executor = count_executor(ranges, vec![], CountMode::Exact);
+        assert_eq!(take_count(&mut executor), 3 + 1 + 3);
+
+        let mut exec_stats = ExecuteStats::new(0);
+        executor.collect_exec_stats(&mut exec_stats);
+        assert_eq!(exec_stats.mutant_searchned_rows_per_range, vec![3, 1, 0, 3]);
+
+        // Statistics are ignored in exact mode.
+        let stats = RangeStats {
+            approximate_keys: 100,
+            approximate_size: 1000,
+        };
+        let ranges = vec![get_range(TABLE_ID, i64::MIN, i64::MAX)];
+        let mut executor = count_executor(ranges, vec![Some(stats)], CountMode::Exact);
+        assert_eq!(take_count(&mut executor), KEY_NUMBER as u64);
+
+        // An empty table still counts.
+        let ranges = vec![get_range(TABLE_ID + 1, i64::MIN, i64::MAX)];
+        let mut executor = count_executor(ranges, vec![], CountMode::Exact);
+        assert_eq!(take_count(&mut executor), 0);
+    }
+
+    #[test]
+    fn test_approximate_count() {
+        let stats = RangeStats {
+            approximate_keys: 100,
+            approximate_size: 1000,
+        };
+        let ranges = vec![
+            get_range(TABLE_ID, i64::MIN, 5),
+            get_range(TABLE_ID, 5, i64::MAX),
+        ];
+        let mut executor =
+            count_executor(ranges, vec![Some(stats), None], CountMode::Approximate);
+        assert_eq!(take_count(&mut executor), 100 + 5);
+        assert!(!executor.can_be_cached());
+
+        // Only the range without statistics was mutant_searchned.
+        let mut exec_stats = ExecuteStats::new(0);
+        executor.collect_exec_stats(&mut exec_stats);
+        assert_eq!(exec_stats.mutant_searchned_rows_per_range, vec![5]);
+    }
+
+    #[test]
+    fn test_key_ranges_stats() {
+        let test_data = TableData::prepare(KEY_NUMBER, TABLE_ID);
+        let storage = FixtureStorage::from(test_data.ehikv_data);
+        let ranges = vec![
+            get_range(TABLE_ID, i64::MIN, 3),
+            get_point_range(TABLE_ID, 5),
+            get_range(TABLE_ID, 7, i64::MAX),
+        ];
+        let stats = key_ranges_stats(&storage, &ranges, true);
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats[0].map(|s| s.approximate_keys), Some(3));
+        assert_eq!(stats[1], None);
+        assert_eq!(stats[2].map(|s| s.approximate_keys), Some(3));
+
+        // The storage's statistics stand in for the ranges that have them; the point range is
+        // still looked up.
+        let mut executor = count_executor(ranges, stats, CountMode::Approximate);
+        assert_eq!(take_count(&mut executor), 3 + 1 + 3);
+        let mut exec_stats = ExecuteStats::new(0);
+        executor.collect_exec_stats(&mut exec_stats);
+        assert_eq!(exec_stats.mutant_searchned_rows_per_range, vec![1]);
+    }
+
+    #[test]
+    fn test_is_count_of_constant() {
+        let mut constant = Expr::default();
+        constant.set_tp(ExprType::Int64);
+        let mut count_star = Expr::default();
+        count_star.set_tp(ExprType::Count);
+        count_star.mut_children().push(constant);
+        assert!(is_count_of_constant(&count_star));
+
+        let count_col = build_expr(
+            ExprType::Count,
+            None,
+            Some(build_expr(ExprType::ColumnRef, Some(1), None)),
+        );
+        assert!(!is_count_of_constant(&count_col));
+
+        let sum = build_expr(
+            ExprType::Sum,
+            None,
+            Some(build_expr(ExprType::ColumnRef, Some(1), None)),
+        );
+        assert!(!is_count_of_constant(&sum));
+    }
+}
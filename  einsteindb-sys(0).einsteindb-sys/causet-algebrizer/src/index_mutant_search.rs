@@ -0,0 +1,456 @@
+//Copyright 2021-2023 WHTCORPS INC ALL RIGHTS RESERVED. APACHE 2.0 COMMUNITY EDITION SL
+// AUTHORS: WHITFORD LEDER
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::sync::Arc;
+
+use codec::prelude::NumberDecoder;
+use ehikvproto::interlock::KeyRange;
+use EinsteinDB_util::collections::HashSet;
+use einsteindbpb::ColumnInfo;
+use einsteindbpb::IndexScan;
+
+use super::{mutant_search::InnerExecutor, Executor, Row, ScanExecutor, ScanExecutorOptions};
+use allegroeinstein-prolog-causet-BerolinaSQL::execute_stats::{ExecuteStats, IndexLookupStats};
+use allegroeinstein-prolog-causet-BerolinaSQL::storage::{IntervalRange, PointRange, Storage};
+use allegroeinstein-prolog-causet-BerolinaSQL::Result;
+use causet_algebrizer::MEDB_query_datatype::codec::{datum, table};
+use causet_algebrizer::MEDB_query_datatype::expr::{EvalContext, EvalWarnings};
+
+pub struct IndexInnerExecutor {
+    // The handle column, if requested. It's never part of the index columns in the key.
+    pk_col: Option<ColumnInfo>,
+    col_ids: Vec<i64>,
+}
+
+impl IndexInnerExecutor {
+    fn new(meta: &mut IndexScan) -> Self {
+        let mut pk_col = None;
+        let cols = meta.mut_columns();
+        if cols.last().map_or(false, ColumnInfo::get_pk_handle) {
+            pk_col = cols.pop();
+        }
+        let col_ids = cols.iter().map(ColumnInfo::get_column_id).collect();
+        Self { pk_col, col_ids }
+    }
+}
+
+impl InnerExecutor for IndexInnerExecutor {
+    fn decode_row(
+        &self,
+        ctx: &mut EvalContext,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        columns: Arc<Vec<ColumnInfo>>,
+    ) -> Result<Option<Row>> {
+        table::check_index_key(key.as_slice())?;
+        let (mut values, handle) = box_try!(table::cut_idx_key(key, &self.col_ids));
+        // A unique index keeps the handle in the value rather than the key.
+        let handle = match handle {
+            None => box_try!(value.as_slice().read_i64()),
+            Some(h) => h,
+        };
+
+        if let Some(ref pk_col) = self.pk_col {
+            let handle_datum = super::get_pk(pk_col, handle);
+            let mut bytes = box_try!(datum::encode_key(ctx, &[handle_datum]));
+            values.append(pk_col.get_column_id(), &mut bytes);
+        }
+        Ok(Some(Row::origin(handle, values, columns)))
+    }
+}
+
+pub type IndexScanExecutor<S> = ScanExecutor<S, IndexInnerExecutor>;
+
+impl<S: Storage> IndexScanExecutor<S> {
+    pub fn index_mutant_search(
+        mut meta: IndexScan,
+        context: EvalContext,
+        key_ranges: Vec<KeyRange>,
+        storage: S,
+        unique: bool,
+        is_mutant_searchned_range_aware: bool,
+    ) -> Result<Self> {
+        let columns = meta.get_columns().to_vec();
+        let inner = IndexInnerExecutor::new(&mut meta);
+
+        Self::new(ScanExecutorOptions {
+            inner,
+            context,
+            columns,
+            key_ranges,
+            storage,
+            is_spacelike_completion: meta.get_desc(),
+            is_key_only: false,
+            // Only a full key of a unique index identifies a single entry.
+            accept_point_range: unique,
+            is_mutant_searchned_range_aware,
+        })
+    }
+}
+
+/// Looks up the rows found by an index mutant_search in the record storage of `table_id`, and
+/// produces their `columns` rather than the index's.
+///
+/// Index entries whose row is missing are skipped, and counted in `IndexLookupStats`.
+pub struct IndexLookupExecutor<S: Storage> {
+    index: IndexScanExecutor<S>,
+    table_id: i64,
+    record_storage: S,
+    col_ids: HashSet<i64>,
+    columns: Arc<Vec<ColumnInfo>>,
+    stats: IndexLookupStats,
+}
+
+impl<S: Storage> IndexLookupExecutor<S> {
+    pub fn new(
+        index: IndexScanExecutor<S>,
+        table_id: i64,
+        columns: Vec<ColumnInfo>,
+        record_storage: S,
+    ) -> Self {
+        let col_ids = columns
+            .iter()
+            .filter(|c| !c.get_pk_handle())
+            .map(ColumnInfo::get_column_id)
+            .collect();
+        Self {
+            index,
+            table_id,
+            record_storage,
+            col_ids,
+            columns: Arc::new(columns),
+            stats: IndexLookupStats::default(),
+        }
+    }
+}
+
+impl<S: Storage> Executor for IndexLookupExecutor<S> {
+    type StorageStats = S::Statistics;
+
+    fn next(&mut self) -> Result<Option<Row>> {
+        while let Some(row) = self.index.next()? {
+            let handle = row.take_origin()?.handle;
+            self.stats.lookups += 1;
+
+            let key = PointRange(table::encode_row_key(self.table_id, handle));
+            let is_key_only = self.col_ids.is_empty();
+            if let Some((_, value)) = self.record_storage.get(is_key_only, key)? {
+                let data = box_try!(table::cut_row(value, &self.col_ids, self.columns.clone()));
+                return Ok(Some(Row::origin(handle, data, self.columns.clone())));
+            }
+            self.stats.missing_rows += 1;
+        }
+        Ok(None)
+    }
+
+    #[inline]
+    fn collect_exec_stats(&mut self, dest: &mut ExecuteStats) {
+        self.index.collect_exec_stats(dest);
+        dest.index_lookups += self.stats;
+        self.stats = IndexLookupStats::default();
+    }
+
+    #[inline]
+    fn collect_storage_stats(&mut self, dest: &mut Self::StorageStats) {
+        self.index.collect_storage_stats(dest);
+        self.record_storage.collect_statistics(dest);
+    }
+
+    #[inline]
+    fn get_len_of_columns(&self) -> usize {
+        self.columns.len()
+    }
+
+    #[inline]
+    fn take_eval_warnings(&mut self) -> Option<EvalWarnings> {
+        None
+    }
+
+    #[inline]
+    fn take_mutant_searchned_range(&mut self) -> IntervalRange {
+        self.index.take_mutant_searchned_range()
+    }
+
+    #[inline]
+    fn can_be_cached(&self) -> bool {
+        self.index.can_be_cached() && self.record_storage.met_uncacheable_data() == Some(false)
+    }
+}
+
+#[braneg(test)]
+pub mod tests {
+    use std::i64;
+
+    use codec::prelude::NumberEncoder;
+    use ehikvproto::interlock::KeyRange;
+    use einsteindbpb::{ColumnInfo, IndexScan};
+
+    use super::super::tests::*;
+    use super::super::Executor;
+    use super::*;
+    use allegroeinstein-prolog-causet-BerolinaSQL::execute_stats::ExecuteStats;
+    use allegroeinstein-prolog-causet-BerolinaSQL::storage::test_fixture::FixtureStorage;
+    use causet_algebrizer::MEDB_query_datatype::codec::datum::Datum;
+    use causet_algebrizer::MEDB_query_datatype::codec::table;
+    use causet_algebrizer::MEDB_query_datatype::expr::EvalContext;
+    use causet_algebrizer::MEDB_query_datatype::FieldTypeTp;
+
+    const TABLE_ID: i64 = 1;
+    const INDEX_ID: i64 = 1;
+    const KEY_NUMBER: usize = 10;
+
+    // Index columns 2 (b"abc") and 3 (the handle as a decimal), as `generate_index_data_for_test`
+    // lays them out.
+    pub fn prepare_index_data(key_number: usize, unique: bool) -> TableData {
+        let cols = vec![
+            new_col_info(2, FieldTypeTp::VarChar),
+            new_col_info(3, FieldTypeTp::NewDecimal),
+        ];
+
+        let mut ehikv_data = Vec::new();
+        let mut expect_rows = Vec::new();
+        for handle in 0..key_number {
+            let (expect_row, idx_key) = table::generate_index_data_for_test(
+                TABLE_ID,
+                INDEX_ID,
+                handle as i64,
+                &Datum::Bytes(b"abc".to_vec()),
+                unique,
+            );
+            let mut value = Vec::new();
+            if unique {
+                value.write_i64(handle as i64).unwrap();
+            }
+            expect_rows.push(expect_row);
+            ehikv_data.push((idx_key, value));
+        }
+        TableData {
+            ehikv_data,
+            expect_rows,
+            cols,
+        }
+    }
+
+    pub fn get_idx_range(start: i64, end: i64, unique: bool) -> KeyRange {
+        let val = Datum::Bytes(b"abc".to_vec());
+        let (_, start_key) =
+            table::generate_index_data_for_test(TABLE_ID, INDEX_ID, start, &val, unique);
+        let (_, end_key) =
+            table::generate_index_data_for_test(TABLE_ID, INDEX_ID, end, &val, unique);
+        let mut key_range = KeyRange::default();
+        key_range.set_start(start_key);
+        key_range.set_end(end_key);
+        key_range
+    }
+
+    pub fn get_idx_point_range(handle: i64) -> KeyRange {
+        let val = Datum::Bytes(b"abc".to_vec());
+        let (_, start_key) =
+            table::generate_index_data_for_test(TABLE_ID, INDEX_ID, handle, &val, true);
+        let mut end = start_key.clone();
+        allegroeinstein-prolog-causet-BerolinaSQL::util::convert_to_prefix_next(&mut end);
+        let mut key_range = KeyRange::default();
+        key_range.set_start(start_key);
+        key_range.set_end(end);
+        key_range
+    }
+
+    // The whole of index `INDEX_ID`, whatever its columns.
+    pub fn get_idx_full_range() -> KeyRange {
+        let start_key = table::encode_index_seek_key(TABLE_ID, INDEX_ID, &[]);
+        let mut end = start_key.clone();
+        allegroeinstein-prolog-causet-BerolinaSQL::util::convert_to_prefix_next(&mut end);
+        let mut key_range = KeyRange::default();
+        key_range.set_start(start_key);
+        key_range.set_end(end);
+        key_range
+    }
+
+    pub struct IndexTestWrapper {
+        data: TableData,
+        pub store: FixtureStorage,
+        pub mutant_search: IndexScan,
+        pub ranges: Vec<KeyRange>,
+        cols: Vec<ColumnInfo>,
+    }
+
+    impl IndexTestWrapper {
+        pub fn new(unique: bool, test_data: TableData) -> IndexTestWrapper {
+            let store = FixtureStorage::from(test_data.ehikv_data.clone());
+            let mut mutant_search = IndexScan::default();
+            mutant_search.set_unique(unique);
+            // prepare cols
+            let cols = test_data.cols.clone();
+            mutant_search.set_columns(cols.clone().into());
+            // prepare range
+            let ranges = vec![get_idx_full_range()];
+            IndexTestWrapper {
+                data: test_data,
+                store,
+                mutant_search,
+                ranges,
+                cols,
+            }
+        }
+
+        fn include_pk_cols() -> IndexTestWrapper {
+            let test_data = prepare_index_data(KEY_NUMBER, false);
+            let mut wrapper = IndexTestWrapper::new(false, test_data);
+            let mut cols = wrapper.mutant_search.take_columns();
+            cols.push(wrapper.data.get_col_pk());
+            wrapper.mutant_search.set_columns(cols);
+            wrapper.cols = wrapper.mutant_search.get_columns().to_vec();
+            wrapper
+        }
+
+        fn executor(self) -> IndexScanExecutor<FixtureStorage> {
+            let unique = self.mutant_search.get_unique();
+            IndexScanExecutor::index_mutant_search(
+                self.mutant_search,
+                EvalContext::default(),
+                self.ranges,
+                self.store,
+                unique,
+                false,
+            )
+            .unwrap()
+        }
+    }
+
+    #[test]
+    fn test_multiple_ranges() {
+        let test_data = prepare_index_data(KEY_NUMBER, false);
+        let mut wrapper = IndexTestWrapper::new(false, test_data);
+        let r1 = get_idx_range(0, (KEY_NUMBER / 2) as i64, false);
+        let r2 = get_idx_range((KEY_NUMBER / 2) as i64, i64::MAX, false);
+        wrapper.ranges = vec![r1, r2];
+        let cols = wrapper.cols.clone();
+        let expect_rows = wrapper.data.expect_rows.clone();
+
+        let mut mutant_searchner = wrapper.executor();
+        for handle in 0..KEY_NUMBER {
+            let row = mutant_searchner.next().unwrap().unwrap().take_origin().unwrap();
+            assert_eq!(row.handle, handle as i64);
+            assert_eq!(row.data.len(), cols.len());
+            let expect_row = &expect_rows[handle];
+            for col in &cols {
+                let cid = col.get_column_id();
+                let v = row.data.get(cid).unwrap();
+                assert_eq!(expect_row[&cid], v.to_vec());
+            }
+        }
+        assert!(mutant_searchner.next().unwrap().is_none());
+
+        let mut exec_stats = ExecuteStats::new(0);
+        mutant_searchner.collect_exec_stats(&mut exec_stats);
+        assert_eq!(
+            exec_stats.mutant_searchned_rows_per_range,
+            vec![KEY_NUMBER / 2, KEY_NUMBER / 2]
+        );
+    }
+
+    #[test]
+    fn test_unique_point_get() {
+        let test_data = prepare_index_data(KEY_NUMBER, true);
+        let mut wrapper = IndexTestWrapper::new(true, test_data);
+        let handle = 3;
+        wrapper.ranges = vec![
+            get_idx_point_range(handle),
+            get_idx_point_range(KEY_NUMBER as i64),
+        ];
+
+        let mut mutant_searchner = wrapper.executor();
+        let row = mutant_searchner.next().unwrap().unwrap().take_origin().unwrap();
+        // The handle comes from the value of a unique index.
+        assert_eq!(row.handle, handle);
+        assert!(mutant_searchner.next().unwrap().is_none());
+
+        let mut exec_stats = ExecuteStats::new(0);
+        mutant_searchner.collect_exec_stats(&mut exec_stats);
+        assert_eq!(exec_stats.mutant_searchned_rows_per_range, vec![1, 0]);
+    }
+
+    #[test]
+    fn test_reverse_mutant_search() {
+        let test_data = prepare_index_data(KEY_NUMBER, false);
+        let mut wrapper = IndexTestWrapper::new(false, test_data);
+        wrapper.mutant_search.set_desc(true);
+
+        let mut mutant_searchner = wrapper.executor();
+        for handle in (0..KEY_NUMBER).rev() {
+            let row = mutant_searchner.next().unwrap().unwrap().take_origin().unwrap();
+            assert_eq!(row.handle, handle as i64);
+        }
+        assert!(mutant_searchner.next().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_include_pk() {
+        let wrapper = IndexTestWrapper::include_pk_cols();
+        let cols = wrapper.cols.clone();
+        let pk_id = wrapper.data.get_col_pk().get_column_id();
+
+        let mut mutant_searchner = wrapper.executor();
+        for handle in 0..KEY_NUMBER {
+            let row = mutant_searchner.next().unwrap().unwrap().take_origin().unwrap();
+            assert_eq!(row.handle, handle as i64);
+            assert_eq!(row.data.len(), cols.len());
+            let expect_pk =
+                datum::encode_key(&mut EvalContext::default(), &[Datum::I64(handle as i64)])
+                    .unwrap();
+            assert_eq!(row.data.get(pk_id).unwrap(), expect_pk.as_slice());
+        }
+        assert!(mutant_searchner.next().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_index_lookup() {
+        let test_data = prepare_index_data(KEY_NUMBER, false);
+        let wrapper = IndexTestWrapper::new(false, test_data);
+
+        // The row with handle `missing` is gone, but its index entry isn't.
+        let missing = 4;
+        let mut table_data = TableData::prepare(KEY_NUMBER, TABLE_ID);
+        table_data.ehikv_data.remove(missing);
+        let record_store = FixtureStorage::from(table_data.ehikv_data.clone());
+        let cols = table_data.get_prev_2_cols();
+
+        let mut executor =
+            IndexLookupExecutor::new(wrapper.executor(), TABLE_ID, cols.clone(), record_store);
+        for handle in (0..KEY_NUMBER).filter(|h| *h != missing) {
+            let row = executor.next().unwrap().unwrap().take_origin().unwrap();
+            assert_eq!(row.handle, handle as i64);
+            assert_eq!(row.data.len(), cols.len());
+            let expect_row = &table_data.expect_rows[handle];
+            for col in &cols {
+                let cid = col.get_column_id();
+                let v = row.data.get(cid).unwrap();
+                assert_eq!(expect_row[&cid], v.to_vec());
+            }
+        }
+        assert!(executor.next().unwrap().is_none());
+
+        let mut exec_stats = ExecuteStats::new(0);
+        executor.collect_exec_stats(&mut exec_stats);
+        assert_eq!(exec_stats.mutant_searchned_rows_per_range, vec![KEY_NUMBER]);
+        assert_eq!(
+            exec_stats.index_lookups,
+            IndexLookupStats {
+                lookups: KEY_NUMBER,
+                missing_rows: 1,
+            }
+        );
+
+        // Collecting resets the counts.
+        let mut exec_stats = ExecuteStats::new(0);
+        executor.collect_exec_stats(&mut exec_stats);
+        assert_eq!(exec_stats.index_lookups, IndexLookupStats::default());
+    }
+}
@@ -17,6 +17,7 @@ extern crate allegroeinstein-prolog-causet-BerolinaSQL;
 
 mod aggregate;
 mod aggregation;
+mod count;
 mod index_mutant_search;
 mod limit;
 pub mod runner;
@@ -27,7 +28,8 @@ mod topn;
 mod topn_heap;
 
 pub use self::aggregation::{HashAggExecutor, StreamAggExecutor};
-pub use self::index_mutant_search::IndexScanExecutor;
+pub use self::count::{key_ranges_stats, CountExecutor, CountExecutorOptions, CountMode};
+pub use self::index_mutant_search::{IndexLookupExecutor, IndexScanExecutor};
 pub use self::limit::LimitExecutor;
 pub use self::runner::ExecutorsRunner;
 pub use self::mutant_search::{ScanExecutor, ScanExecutorOptions};
@@ -19,10 +19,11 @@ use EinsteinDB_util::deadline::Deadline;
 
 use super::Executor;
 use allegroeinstein-prolog-causet-BerolinaSQL::execute_stats::*;
+use allegroeinstein-prolog-causet-BerolinaSQL::memory_quota::MemoryQuota;
 use allegroeinstein-prolog-causet-BerolinaSQL::metrics::*;
 use allegroeinstein-prolog-causet-BerolinaSQL::storage::{IntervalRange, Storage};
 use allegroeinstein-prolog-causet-BerolinaSQL::Result;
-use causet_algebrizer::MEDB_query_datatype::expr::{EvalConfig, EvalContext};
+use causet_algebrizer::MEDB_query_datatype::expr::{EvalConfig, EvalContext, Flag};
 
 pub struct ExecutorsRunner<SS> {
     deadline: Deadline,
@@ -32,6 +33,9 @@ pub struct ExecutorsRunner<SS> {
     collect_exec_summary: bool,
     context: EvalContext,
     exec_stats: ExecuteStats,
+    memory_quota: MemoryQuota,
+    // Bytes of encoded rows buffered in chunks and charged to `memory_quota`.
+    buffered: usize,
 }
 
 /// Builds a normal executor pipeline.
@@ -44,13 +48,25 @@ pub fn build_executors<S: Storage + 'static, C: ExecSummaryCollector + 'static>(
     ranges: Vec<KeyRange>,
     ctx: Arc<EvalConfig>,
     is_streaming: bool,
+    memory_quota: &MemoryQuota,
 ) -> Result<Box<dyn Executor<StorageStats = S::Statistics> + Send>> {
+    if is_count_only(&exec_descriptors) {
+        return build_count_executor::<_, C>(exec_descriptors, storage, ranges, &ctx, is_streaming);
+    }
+
     let mut exec_descriptors = exec_descriptors.into_iter();
     let first = exec_descriptors
         .next()
         .ok_or_else(|| other_err!("No executor specified"))?;
 
-    let mut src = build_first_executor::<_, C>(first, storage, ranges, ctx.clone(), is_streaming)?;
+    let mut src = build_first_executor::<_, C>(
+        first,
+        storage,
+        ranges,
+        ctx.clone(),
+        is_streaming,
+        memory_quota,
+    )?;
     let mut summary_slot_index = 0;
 
     for mut exec in exec_descriptors {
@@ -70,6 +86,7 @@ pub fn build_executors<S: Storage + 'static, C: ExecSummaryCollector + 'static>(
 
                 Box::new(
                     super::HashAggExecutor::new(exec.take_aggregation(), Arc::clone(&ctx), src)?
+                        .with_memory_quota(memory_quota.clone())
                         .with_summary_collector(C::new(summary_slot_index)),
                 )
             }
@@ -86,6 +103,7 @@ pub fn build_executors<S: Storage + 'static, C: ExecSummaryCollector + 'static>(
 
                 Box::new(
                     super::TopNExecutor::new(exec.take_top_n(), Arc::clone(&ctx), src)?
+                        .with_memory_quota(memory_quota.clone())
                         .with_summary_collector(C::new(summary_slot_index)),
                 )
             }
@@ -110,6 +128,69 @@ pub fn build_executors<S: Storage + 'static, C: ExecSummaryCollector + 'static>(
     Ok(src)
 }
 
+/// Whether `exec_descriptors` only count the rows of a mutant_search: a table or index mutant_search
+/// followed by an aggregation without group by whose functions are all `COUNT(*)`.
+fn is_count_only(exec_descriptors: &[einsteindbpb::Executor]) -> bool {
+    if exec_descriptors.len() != 2 {
+        return false;
+    }
+    match exec_descriptors[0].get_tp() {
+        ExecType::TypeTableScan | ExecType::TypeIndexScan => {}
+        _ => return false,
+    }
+    match exec_descriptors[1].get_tp() {
+        ExecType::TypeAggregation | ExecType::TypeStreamAgg => {}
+        _ => return false,
+    }
+    let aggregation = exec_descriptors[1].get_aggregation();
+    aggregation.get_group_by().is_empty()
+        && !aggregation.get_agg_func().is_empty()
+        && aggregation
+            .get_agg_func()
+            .iter()
+            .all(super::count::is_count_of_constant)
+}
+
+/// Builds a `CountExecutor` in place of the mutant_search and aggregation accepted by
+/// `is_count_only`, so that no row is decoded. If the request sets `Flag::APPROXIMATE_COUNT`, the
+/// ranges the storage keeps statistics of are counted from them rather than mutant_searchned.
+fn build_count_executor<S: Storage + 'static, C: ExecSummaryCollector + 'static>(
+    exec_descriptors: Vec<einsteindbpb::Executor>,
+    storage: S,
+    ranges: Vec<KeyRange>,
+    ctx: &EvalConfig,
+    is_streaming: bool,
+) -> Result<Box<dyn Executor<StorageStats = S::Statistics> + Send>> {
+    EXECUTOR_COUNT_METRICS.count.inc();
+
+    let first = &exec_descriptors[0];
+    // Only a full key of a unique index identifies a single entry.
+    let accept_point_range = first.get_tp() == ExecType::TypeTableScan
+        || first.get_idx_mutant_search().get_unique();
+    let aggr_func_len = exec_descriptors[1].get_aggregation().get_agg_func().len();
+    let (mode, range_stats) = if ctx.flag.contains(Flag::APPROXIMATE_COUNT) {
+        let range_stats = super::count::key_ranges_stats(&storage, &ranges, accept_point_range);
+        (super::CountMode::Approximate, range_stats)
+    } else {
+        (super::CountMode::Exact, Vec::new())
+    };
+
+    let ex = Box::new(
+        super::CountExecutor::new(super::CountExecutorOptions {
+            key_ranges: ranges,
+            range_stats,
+            storage,
+            mode,
+            aggr_func_len,
+            accept_point_range,
+            is_mutant_searchned_range_aware: is_streaming,
+        })?
+        // The summary of the aggregation, which the count stands in for.
+        .with_summary_collector(C::new(1)),
+    );
+    Ok(ex)
+}
+
 /// Builds the inner-most executor for the normal executor pipeline, which can produce rows to
 /// other executors and never receive rows from other executors.
 ///
@@ -120,6 +201,7 @@ fn build_first_executor<S: Storage + 'static, C: ExecSummaryCollector + 'static>
     ranges: Vec<KeyRange>,
     context: Arc<EvalConfig>,
     is_streaming: bool,
+    memory_quota: &MemoryQuota,
 ) -> Result<Box<dyn Executor<StorageStats = S::Statistics> + Send>> {
     let context = EvalContext::new(context);
     match first.get_tp() {
@@ -134,6 +216,7 @@ fn build_first_executor<S: Storage + 'static, C: ExecSummaryCollector + 'static>
                     storage,
                     is_streaming,
                 )?
+                .with_memory_quota(memory_quota.clone())
                 .with_summary_collector(C::new(0)),
             );
             Ok(ex)
@@ -151,6 +234,7 @@ fn build_first_executor<S: Storage + 'static, C: ExecSummaryCollector + 'static>
                     unique,
                     is_streaming,
                 )?
+                .with_memory_quota(memory_quota.clone())
                 .with_summary_collector(C::new(0)),
             );
             Ok(ex)
@@ -167,11 +251,14 @@ impl<SS: 'static> ExecutorsRunner<SS> {
         deadline: Deadline,
         batch_row_limit: usize,
         is_streaming: bool,
+        memory_quota: MemoryQuota,
     ) -> Result<Self> {
         let executors_len = req.get_executors().len();
         let collect_exec_summary = req.get_collect_execution_summaries();
         let config = Arc::new(EvalConfig::from_request(&req)?);
         let context = EvalContext::new(config.clone());
+        // Usage and peak of this request alone, still bounded by the quota it shares.
+        let memory_quota = memory_quota.for_request();
 
         let executor = if !(req.get_collect_execution_summaries()) {
             build_executors::<_, ExecSummaryCollectorDisabled>(
@@ -180,6 +267,7 @@ impl<SS: 'static> ExecutorsRunner<SS> {
                 ranges,
                 config,
                 is_streaming,
+                &memory_quota,
             )?
         } else {
             build_executors::<_, ExecSummaryCollectorEnabled>(
@@ -188,6 +276,7 @@ impl<SS: 'static> ExecutorsRunner<SS> {
                 ranges,
                 config,
                 is_streaming,
+                &memory_quota,
             )?
         };
 
@@ -205,11 +294,34 @@ impl<SS: 'static> ExecutorsRunner<SS> {
             collect_exec_summary,
             context,
             exec_stats,
+            memory_quota,
+            buffered: 0,
         })
     }
 
-    fn make_stream_response(&mut self, chunk: Chunk) -> Result<StreamResponse> {
+    /// Charges `bytes` of a row added to a chunk to the memory quota.
+    fn buffer(&mut self, bytes: usize) -> Result<()> {
+        self.memory_quota.consume(bytes)?;
+        self.buffered += bytes;
+        Ok(())
+    }
+
+    /// Gives back the bytes of the chunks handed out in a response.
+    fn release_buffered(&mut self) {
+        self.memory_quota.release(self.buffered);
+        self.buffered = 0;
+    }
+
+    fn collect_exec_stats(&mut self) {
         self.executor.collect_exec_stats(&mut self.exec_stats);
+        self.exec_stats.peak_memory_usage = std::cmp::max(
+            self.exec_stats.peak_memory_usage,
+            self.memory_quota.peak(),
+        );
+    }
+
+    fn make_stream_response(&mut self, chunk: Chunk) -> Result<StreamResponse> {
+        self.collect_exec_stats();
 
         let mut s_resp = StreamResponse::default();
         s_resp.set_data(box_try!(chunk.write_to_bytes()));
@@ -246,10 +358,12 @@ impl<SS: 'static> ExecutorsRunner<SS> {
                     record_cnt += 1;
                     // for default encode type
                     let value = row.get_binary(&mut self.context, &self.output_offsets)?;
+                    self.buffer(value.len())?;
                     chunk.mut_rows_data().extend_from_slice(&value);
                 }
                 None => {
-                    self.executor.collect_exec_stats(&mut self.exec_stats);
+                    self.collect_exec_stats();
+                    self.release_buffered();
 
                     let mut sel_resp = SelectResponse::default();
                     sel_resp.set_chunks(chunks.into());
@@ -303,6 +417,7 @@ impl<SS: 'static> ExecutorsRunner<SS> {
                     self.deadline.check()?;
                     record_cnt += 1;
                     let value = row.get_binary(&mut self.context, &self.output_offsets)?;
+                    self.buffer(value.len())?;
                     chunk.mut_rows_data().extend_from_slice(&value);
                 }
                 None => {
@@ -313,9 +428,9 @@ impl<SS: 'static> ExecutorsRunner<SS> {
         }
         if record_cnt > 0 {
             let range = self.executor.take_mutant_searchned_range();
-            return self
-                .make_stream_response(chunk)
-                .map(|r| (Some((r, range)), finished));
+            let resp = self.make_stream_response(chunk);
+            self.release_buffered();
+            return resp.map(|r| (Some((r, range)), finished));
         }
         Ok((None, true))
     }
@@ -332,3 +447,10 @@ impl<SS: 'static> ExecutorsRunner<SS> {
         self.executor.can_be_cached()
     }
 }
+
+impl<SS> Drop for ExecutorsRunner<SS> {
+    fn drop(&mut self) {
+        // A failed request leaves its chunks charged.
+        self.memory_quota.release(self.buffered);
+    }
+}
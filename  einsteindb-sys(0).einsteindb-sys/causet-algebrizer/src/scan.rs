@@ -15,7 +15,10 @@ use einsteindbpb::ColumnInfo;
 
 use super::{Executor, Row};
 use allegroeinstein-prolog-causet-BerolinaSQL::execute_stats::ExecuteStats;
-use allegroeinstein-prolog-causet-BerolinaSQL::storage::mutant_searchner::{RangesScanner, RangesScannerOptions};
+use allegroeinstein-prolog-causet-BerolinaSQL::memory_quota::MemoryQuota;
+use allegroeinstein-prolog-causet-BerolinaSQL::storage::mutant_searchner::{
+    plan_mutant_search, ranges_stats, RangesScanner, ScanOrder, ScanRequirements,
+};
 use allegroeinstein-prolog-causet-BerolinaSQL::storage::{IntervalRange, Range, Storage};
 use allegroeinstein-prolog-causet-BerolinaSQL::Result;
 use causet_algebrizer::MEDB_query_datatype::codec::table;
@@ -39,6 +42,9 @@ pub struct ScanExecutor<S: Storage, T: InnerExecutor> {
     context: EvalContext,
     mutant_searchner: RangesScanner<S>,
     columns: Arc<Vec<ColumnInfo>>,
+    memory_quota: MemoryQuota,
+    // Bytes of the last row read, charged to `memory_quota` until the next one is.
+    charged: usize,
 }
 
 pub struct ScanExecutorOptions<S, T> {
@@ -72,32 +78,63 @@ impl<S: Storage, T: InnerExecutor> ScanExecutor<S, T> {
             key_ranges.reverse();
         }
 
-        let mutant_searchner = RangesScanner::new(RangesScannerOptions {
+        let ranges: Vec<Range> = key_ranges
+            .into_iter()
+            .map(|r| Range::from_pb_range(r, accept_point_range))
+            .collect();
+        let plan = plan_mutant_search(
+            &ranges_stats(&storage, &ranges).unwrap_or_default(),
+            &ScanRequirements {
+                order: if is_spacelike_completion {
+                    ScanOrder::Descending
+                } else {
+                    ScanOrder::Ascending
+                },
+                needs_values: !is_key_only,
+                limit: None,
+            },
+        );
+        let mutant_searchner = box_try!(RangesScanner::with_plan(
             storage,
-            ranges: key_ranges
-                .into_iter()
-                .map(|r| Range::from_pb_range(r, accept_point_range))
-                .collect(),
-            mutant_search_spacelike_completion_in_range: is_spacelike_completion,
-            is_key_only,
+            ranges,
+            plan,
             is_mutant_searchned_range_aware,
-        });
+        ));
 
         Ok(Self {
             inner,
             context,
             mutant_searchner,
             columns: Arc::new(columns),
+            memory_quota: MemoryQuota::unlimited(),
+            charged: 0,
         })
     }
+
+    /// Charges the row this executor has read to `memory_quota`.
+    pub fn with_memory_quota(mut self, memory_quota: MemoryQuota) -> Self {
+        self.memory_quota = memory_quota;
+        self
+    }
+}
+
+impl<S: Storage, T: InnerExecutor> Drop for ScanExecutor<S, T> {
+    fn drop(&mut self) {
+        self.memory_quota.release(self.charged);
+    }
 }
 
 impl<S: Storage, T: InnerExecutor> Executor for ScanExecutor<S, T> {
     type StorageStats = S::Statistics;
 
     fn next(&mut self) -> Result<Option<Row>> {
+        self.memory_quota.release(self.charged);
+        self.charged = 0;
         let some_row = self.mutant_searchner.next()?;
         if let Some((key, value)) = some_row {
+            let bytes = key.len() + value.len();
+            self.memory_quota.consume(bytes)?;
+            self.charged = bytes;
             self.inner
                 .decode_row(&mut self.context, key, value, self.columns.clone())
         } else {
@@ -109,6 +146,7 @@ impl<S: Storage, T: InnerExecutor> Executor for ScanExecutor<S, T> {
     fn collect_exec_stats(&mut self, dest: &mut ExecuteStats) {
         self.mutant_searchner
             .collect_mutant_searchned_rows_per_range(&mut dest.mutant_searchned_rows_per_range);
+        self.mutant_searchner.collect_scan_plans(&mut dest.scan_plans);
     }
 
     #[inline]
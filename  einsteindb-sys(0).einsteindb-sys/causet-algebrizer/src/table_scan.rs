@@ -176,6 +176,32 @@ mod tests {
         assert_eq!(expected_counts, exec_stats.mutant_searchned_rows_per_range);
     }
 
+    #[test]
+    fn test_scan_plan() {
+        let mut wrapper = TableScanTestWrapper::default();
+        wrapper.table_mutant_search.set_desc(true);
+        let mut table_mutant_searchner = super::TableScanExecutor::table_mutant_search(
+            wrapper.table_mutant_search,
+            EvalContext::default(),
+            wrapper.ranges,
+            wrapper.store,
+            false,
+        )
+        .unwrap();
+        while table_mutant_searchner.next().unwrap().is_some() {}
+
+        // The plan is made from the fixture's statistics, and reported once.
+        let mut exec_stats = ExecuteStats::new(0);
+        table_mutant_searchner.collect_exec_stats(&mut exec_stats);
+        assert_eq!(exec_stats.scan_plans.len(), 1);
+        let plan = exec_stats.scan_plans[0];
+        assert!(plan.mutant_search_spacelike_completion_in_range);
+        assert!(!plan.is_key_only);
+        assert_eq!(plan.estimated_rows, KEY_NUMBER as u64);
+        table_mutant_searchner.collect_exec_stats(&mut exec_stats);
+        assert_eq!(exec_stats.scan_plans.len(), 1);
+    }
+
     #[test]
     fn test_multiple_ranges() {
         let mut wrapper = TableScanTestWrapper::default();
@@ -16,9 +16,10 @@ use std::vec::IntoIter;
 use einsteindbpb::ByItem;
 use einsteindbpb::TopN;
 
-use super::topn_heap::TopNHeap;
+use super::topn_heap::{SortRow, TopNHeap};
 use super::{Executor, ExprColumnRefVisitor, Row};
 use allegroeinstein-prolog-causet-BerolinaSQL::execute_stats::ExecuteStats;
+use allegroeinstein-prolog-causet-BerolinaSQL::memory_quota::MemoryQuota;
 use allegroeinstein-prolog-causet-BerolinaSQL::storage::IntervalRange;
 use allegroeinstein-prolog-causet-BerolinaSQL::Result;
 use causet_algebrizer::MEDB_query_datatype::codec::datum::Datum;
@@ -61,6 +62,9 @@ pub struct TopNExecutor<Src: Executor> {
     eval_warnings: Option<EvalWarnings>,
     src: Src,
     limit: usize,
+    memory_quota: MemoryQuota,
+    // Bytes of the rows kept for ordering charged to `memory_quota`, given back on drop.
+    charged: usize,
 }
 
 impl<Src: Executor> TopNExecutor<Src> {
@@ -81,9 +85,17 @@ impl<Src: Executor> TopNExecutor<Src> {
             eval_warnings: None,
             src,
             limit: meta.get_limit() as usize,
+            memory_quota: MemoryQuota::unlimited(),
+            charged: 0,
         })
     }
 
+    /// Charges the rows this executor keeps for ordering to `memory_quota`.
+    pub fn with_memory_quota(mut self, memory_quota: MemoryQuota) -> Self {
+        self.memory_quota = memory_quota;
+        self
+    }
+
     fn fetch_all(&mut self) -> Result<()> {
         if self.limit == 0 {
             self.iter = Some(Vec::default().into_iter());
@@ -101,7 +113,16 @@ impl<Src: Executor> TopNExecutor<Src> {
             let cols =
                 row.inflate_cols_with_offsets(&mut ctx.borrow_mut(), &self.related_cols_offset)?;
             let ob_values = self.order_by.eval(&mut ctx.borrow_mut(), &cols)?;
-            heap.try_add_row(row, ob_values, Arc::clone(&self.order_by.items))?;
+            let bytes = SortRow::mem_size_of(&row, &ob_values);
+            self.memory_quota.consume(bytes)?;
+            self.charged += bytes;
+            if let Some(dropped) =
+                heap.try_add_row(row, ob_values, Arc::clone(&self.order_by.items))?
+            {
+                let bytes = dropped.mem_size();
+                self.memory_quota.release(bytes);
+                self.charged -= bytes;
+            }
         }
         let sort_rows = heap.into_sorted_vec()?;
         let data: Vec<Row> = sort_rows
@@ -114,6 +135,12 @@ impl<Src: Executor> TopNExecutor<Src> {
     }
 }
 
+impl<Src: Executor> Drop for TopNExecutor<Src> {
+    fn drop(&mut self) {
+        self.memory_quota.release(self.charged);
+    }
+}
+
 impl<Src: Executor> Executor for TopNExecutor<Src> {
     type StorageStats = Src::StorageStats;
 
@@ -128,6 +155,7 @@ impl<Src: Executor> Executor for TopNExecutor<Src> {
     #[inline]
     fn collect_exec_stats(&mut self, dest: &mut ExecuteStats) {
         self.src.collect_exec_stats(dest);
+        dest.peak_memory_usage = std::cmp::max(dest.peak_memory_usage, self.memory_quota.peak());
     }
 
     #[inline]
@@ -174,6 +202,7 @@ pub mod tests {
 
     use crate::OriginCols;
     use causet_algebrizer::MEDB_query_datatype::codec::table::RowColsDict;
+    use allegroeinstein-prolog-causet-BerolinaSQL::memory_quota::MemoryQuota;
     use causet_algebrizer::MEDB_query_datatype::codec::Datum;
 
     use super::super::tests::*;
@@ -424,6 +453,57 @@ pub mod tests {
         assert_eq!(expected_counts, exec_stats.mutant_searchned_rows_per_range);
     }
 
+    #[test]
+    fn test_topn_memory_quota() {
+        let tid = 1;
+        let cis = vec![
+            new_col_info(1, FieldTypeTp::LongLong),
+            new_col_info(2, FieldTypeTp::VarChar),
+        ];
+        let primitive_causet_data: Vec<_> = (0..10)
+            .map(|i| vec![Datum::I64(i), Datum::Bytes(format!("{}", i).into_bytes())])
+            .collect();
+        let build = |quota: MemoryQuota| {
+            let key_ranges = vec![get_range(tid, i64::MIN, i64::MAX)];
+            let ts_ect = gen_table_mutant_search_executor(
+                tid,
+                cis.clone(),
+                &primitive_causet_data,
+                Some(key_ranges),
+            );
+            let mut topn = TopN::default();
+            topn.set_order_by(vec![new_order_by(1, true)].into());
+            topn.set_limit(2);
+            TopNExecutor::new(topn, Arc::new(EvalConfig::default()), ts_ect)
+                .unwrap()
+                .with_memory_quota(quota)
+        };
+
+        // Only the kept rows stay charged, but a candidate is charged before one is dropped.
+        let quota = MemoryQuota::unlimited();
+        let mut topn_ect = build(quota.clone());
+        let mut handles = Vec::new();
+        while let Some(row) = topn_ect.next().unwrap() {
+            handles.push(row.take_origin().unwrap().handle);
+        }
+        assert_eq!(handles, vec![9, 8]);
+        let kept = quota.in_use();
+        assert!(kept > 0);
+        assert!(quota.peak() > kept);
+        let mut exec_stats = ExecuteStats::new(0);
+        topn_ect.collect_exec_stats(&mut exec_stats);
+        assert_eq!(exec_stats.peak_memory_usage, quota.peak());
+        drop(topn_ect);
+        assert_eq!(quota.in_use(), 0);
+
+        // The kept rows don't fit.
+        let quota = MemoryQuota::new(kept / 2);
+        let mut topn_ect = build(quota.clone());
+        assert!(topn_ect.next().is_err());
+        drop(topn_ect);
+        assert_eq!(quota.in_use(), 0);
+    }
+
     #[test]
     fn test_limit() {
         // prepare data and store
@@ -12,7 +12,7 @@ use std::cell::RefCell;
 use std::cmp::{self, Ordering};
 use std::collections::BinaryHeap;
 use std::sync::Arc;
-use std::usize;
+use std::{mem, usize};
 use einsteindbpb::ByItem;
 
 use crate::OriginCols;
@@ -71,6 +71,15 @@ impl SortRow {
         Ok(Ordering::Equal)
     }
 
+    /// Approximate bytes a row of `data` ordered by `key` holds in the heap.
+    pub fn mem_size_of(data: &OriginCols, key: &[Datum]) -> usize {
+        mem::size_of::<SortRow>() + data.data.value.len() + key.len() * mem::size_of::<Datum>()
+    }
+
+    pub fn mem_size(&self) -> usize {
+        SortRow::mem_size_of(&self.data, &self.key)
+    }
+
     #[inline]
     fn check_err(&self) -> Result<()> {
         if let Some(ref err_msg) = *self.err.as_ref().borrow() {
@@ -113,15 +122,14 @@ impl TopNHeap {
         Ok(())
     }
 
+    /// Adds a row if it is among the `limit` smallest so far, and returns the row the heap
+    /// doesn't keep: the added one or the one it displaced.
     pub fn try_add_row(
         &mut self,
         data: OriginCols,
         values: Vec<Datum>,
         order_cols: Arc<Vec<ByItem>>,
-    ) -> Result<()> {
-        if self.limit == 0 {
-            return Ok(());
-        }
+    ) -> Result<Option<SortRow>> {
         let row = SortRow::new(
             data,
             values,
@@ -129,18 +137,25 @@ impl TopNHeap {
             Arc::clone(&self.ctx),
             Arc::clone(&self.err),
         );
+        if self.limit == 0 {
+            return Ok(Some(row));
+        }
         // push into heap when heap is not full
-        if self.rows.len() < self.limit {
+        let dropped = if self.rows.len() < self.limit {
             self.rows.push(row);
+            None
         } else {
             // swap top value with row when heap is full and current row is less than top data
             let mut top_data = self.rows.peek_mut().unwrap();
             let order = row.cmp_and_check(&top_data)?;
             if Ordering::Less == order {
-                *top_data = row;
+                Some(mem::replace(&mut *top_data, row))
+            } else {
+                Some(row)
             }
-        }
-        self.check_err()
+        };
+        self.check_err()?;
+        Ok(dropped)
     }
 
     pub fn into_sorted_vec(self) -> Result<Vec<SortRow>> {
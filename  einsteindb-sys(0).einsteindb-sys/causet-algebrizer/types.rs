@@ -30,6 +30,8 @@ pub enum CausetsTable {
     Causets,        //non-fulltext causets table.
     FulltextValues, //ID to strings mapping sentinel.
     FulltextCausets, //fulltext-causets view
+    FulltextPorter,  //fulltext values rowids, porter-tokenized; no text.
+    FulltextTrigram, //fulltext values rowids, trigram-tokenized; no text.
     AllCausets,     //All causets
     Computed(usize),
     Transactions, //A transactions table which makes tx-data log API efficient.
@@ -71,6 +73,8 @@ impl DatomsTable {
             DatomsTable::Datoms => "causets",
             DatomsTable::FulltextValues => "fulltext_values",
             DatomsTable::FulltextDatoms => "fulltext_causets",
+            DatomsTable::FulltextPorter => "fulltext_values_porter",
+            DatomsTable::FulltextTrigram => "fulltext_values_trigram",
             DatomsTable::AllDatoms => "all_causets",
             DatomsTable::Computed(_) => "c",
             DatomsTable::Transactions => "transactions",
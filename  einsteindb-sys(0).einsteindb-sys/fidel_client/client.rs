@@ -8,22 +8,32 @@
  // CONDITIONS OF ANY KIND, either express or implied. See the License for the
  // specific language governing permissions and limitations under the License.
 
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::cmp::Ordering as CmpOrdering;
 use std::fmt;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::time::{Duration, Instant};
 
 use futures::sync::mpsc;
 use futures::sync::oneshot;
-use futures::{future, Future, Sink, Stream};
+use futures::{future, stream, Future, Sink, Stream};
 use futures03::compat::{Compat, Future01CompatExt};
 use futures03::executor::block_on;
 use futures03::future::FutureExt;
-use grpcio::{CallOption, EnvBuilder, WriteFlags};
+use grpcio::{
+    CallOption, ChannelBuilder, CompressionAlgorithms, EnvBuilder, RpcStatus, RpcStatusCode,
+    WriteFlags,
+};
 use ehikvproto::metapb;
 use ehikvproto::FIDelpb::{self, Member};
-use ehikvproto::replication_modepb::{RegionReplicationStatus, ReplicationStatus};
+use ehikvproto::replication_modepb::{RegionReplicationStatus, ReplicationMode, ReplicationStatus};
 use security::SecurityManager;
+use serde_derive::{Deserialize, Serialize};
 use EinsteinDb_util::time::duration_to_sec;
 use EinsteinDb_util::{Either, HandyRwLock};
 use txn_types::TimeStamp;
@@ -37,13 +47,780 @@ use EinsteinDb_util::timer::GLOBAL_TIMER_HANDLE;
 const CQ_COUNT: usize = 1;
 const CLIENT_PREFIX: &str = "FIDel";
 
+/// The most split keys `split_region_at_keys` asks FIDel about in one `ask_batch_split` call.
+const MAX_BATCH_SPLIT_KEYS: usize = 64;
+
+/// What became of one key passed to `RpcClient::split_region_at_keys`.
+#[derive(Debug)]
+pub enum SplitKeyOutcome {
+    /// FIDel allocated the ids of the region that will start at the key.
+    Allocated(FIDelpb::SplitId),
+    /// The key doesn't lie strictly inside the region, so splitting there makes no sense.
+    OutOfRange,
+    /// The key repeats an earlier key.
+    Duplicate,
+    /// The `ask_batch_split` call covering the key failed.
+    Failed(String),
+}
+
+/// How many `scatter_region` calls `scatter_ranges` keeps in flight unless told otherwise.
+pub const DEFAULT_SCATTER_CONCURRENCY: usize = 16;
+
+/// What became of one region scattered by `RpcClient::scatter_ranges`.
+#[derive(Debug)]
+pub struct ScatterOutcome {
+    pub region_id: u64,
+    pub result: Result<()>,
+}
+
+/// Returns whether `key` splits `region` into two non-empty halves.
+fn is_split_key_in_region(region: &metapb::Region, key: &[u8]) -> bool {
+    key > region.get_start_key() && (region.get_end_key().is_empty() || key < region.get_end_key())
+}
+
+/// Why `RpcClient::ask_merge` won't merge two regions.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MergeRejection {
+    /// Source and target are the same region.
+    SameRegion,
+    /// FIDel doesn't know the region with this id.
+    RegionNotFound(u64),
+    /// FIDel has the region with this id at a newer epoch than the caller passed: it has split,
+    /// merged or changed membership since the caller looked.
+    StaleEpoch(u64),
+    /// The key ranges of the regions don't touch.
+    NotAdjacent,
+    /// The peers of the regions aren't on the same stores, so no store can apply the merge.
+    PeersMismatch,
+}
+
+impl fmt::Display for MergeRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeRejection::SameRegion => write!(f, "can't merge a region into itself"),
+            MergeRejection::RegionNotFound(id) => write!(f, "region {} not found", id),
+            MergeRejection::StaleEpoch(id) => write!(f, "epoch of region {} is stale", id),
+            MergeRejection::NotAdjacent => write!(f, "regions are not adjacent"),
+            MergeRejection::PeersMismatch => write!(f, "regions have peers on different stores"),
+        }
+    }
+}
+
+/// What FIDel answered `RpcClient::ask_merge`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MergeOutcome {
+    /// The merge command FIDel issued, to send to the source region's leader as FIDel would send
+    /// it in a region heartbeat response.
+    Ready(RegionHeartbeatCommand),
+    Rejected(MergeRejection),
+}
+
+impl MergeOutcome {
+    /// Decodes FIDel's answer to an `AskMergeRequest`. A response with neither a merge nor a
+    /// known reason to reject it is taken as the source region being gone.
+    fn decode(source_id: u64, mut resp: FIDelpb::AskMergeResponse) -> MergeOutcome {
+        let region_id = resp.get_rejected_region_id();
+        let rejection = match resp.get_reject_reason() {
+            FIDelpb::MergeRejectReason::None if resp.has_merge() => {
+                return MergeOutcome::Ready(RegionHeartbeatCommand {
+                    region_id: source_id,
+                    region_epoch: resp.take_source_epoch(),
+                    target_peer: metapb::Peer::default(),
+                    command: RegionCommand::Merge(resp.take_merge()),
+                });
+            }
+            FIDelpb::MergeRejectReason::None => MergeRejection::RegionNotFound(source_id),
+            FIDelpb::MergeRejectReason::SameRegion => MergeRejection::SameRegion,
+            FIDelpb::MergeRejectReason::RegionNotFound => MergeRejection::RegionNotFound(region_id),
+            FIDelpb::MergeRejectReason::StaleEpoch => MergeRejection::StaleEpoch(region_id),
+            FIDelpb::MergeRejectReason::NotAdjacent => MergeRejection::NotAdjacent,
+            FIDelpb::MergeRejectReason::PeersMismatch => MergeRejection::PeersMismatch,
+        };
+        MergeOutcome::Rejected(rejection)
+    }
+}
+
+/// What `RpcClient::bootstrap_store_if_needed` did.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StoreBootstrap {
+    /// The store bootstrapped the cluster with the first region.
+    BootstrappedCluster {
+        store: metapb::Store,
+        region: metapb::Region,
+    },
+    /// The cluster was already bootstrapped, and the store was put into it.
+    Joined { store: metapb::Store },
+    /// Another store bootstrapped the cluster after this one checked, and this store was put into
+    /// it instead. Its first region was never created; its ids are simply unused.
+    LostRace { store: metapb::Store },
+}
+
+impl StoreBootstrap {
+    pub fn store(&self) -> &metapb::Store {
+        match self {
+            StoreBootstrap::BootstrappedCluster { store, .. }
+            | StoreBootstrap::Joined { store }
+            | StoreBootstrap::LostRace { store } => store,
+        }
+    }
+}
+
+/// The ttl FIDel is asked to keep a service GC safe point for, in the whole seconds it takes.
+/// A partial second rounds up: truncating a sub-second ttl would make it zero, which removes the
+/// safe point instead of keeping it.
+fn service_safe_point_ttl_secs(ttl: Duration) -> i64 {
+    let secs = ttl.as_secs().saturating_add(u64::from(ttl.subsec_nanos() > 0));
+    secs.min(i64::max_value() as u64) as i64
+}
+
+/// A snapshot of FIDel leadership and membership, as yielded by `RpcClient::watch_leader`.
+#[derive(Clone, Debug)]
+pub struct LeaderChangeEvent {
+    pub leader: Member,
+    pub members: Vec<Member>,
+    pub cluster_version: ClusterVersion,
+}
+
+impl LeaderChangeEvent {
+    fn current(client: &LeaderClient) -> LeaderChangeEvent {
+        let leader = client.get_leader();
+        let inner = client.inner.rl();
+        LeaderChangeEvent {
+            leader,
+            members: inner.members.get_members().to_vec(),
+            cluster_version: inner.cluster_version.clone(),
+        }
+    }
+
+    fn is_same_membership(&self, other: &LeaderChangeEvent) -> bool {
+        self.leader == other.leader && self.members == other.members
+    }
+}
+
+/// The receivers of `RpcClient::watch_leader`, and the membership they were last sent.
+#[derive(Default)]
+struct LeaderWatchersInner {
+    last: Option<LeaderChangeEvent>,
+    senders: Vec<mpsc::UnboundedSender<LeaderChangeEvent>>,
+}
+
+type LeaderWatchers = Arc<Mutex<LeaderWatchersInner>>;
+
+/// Sends the current membership to every watcher if it differs from the one last sent, dropping
+/// watchers whose receivers are gone.
+///
+/// The comparison and the sends happen under the watchers' lock, so concurrent reconnects can't
+/// deliver an older membership after a newer one.
+fn notify_leader_watchers(client: &LeaderClient, watchers: &LeaderWatchers) {
+    let mut watchers = watchers.lock().unwrap();
+    let current = LeaderChangeEvent::current(client);
+    if watchers
+        .last
+        .as_ref()
+        .map_or(false, |last| current.is_same_membership(last))
+    {
+        return;
+    }
+    info!("FIDel membership changed";
+        "leader" => ?current.leader,
+        "members" => current.members.len());
+    watchers
+        .senders
+        .retain(|watcher| watcher.unbounded_send(current.clone()).is_ok());
+    watchers.last = Some(current);
+}
+
+type ReconnectCallback = Arc<RwLock<Option<Box<dyn Fn() + Sync + Send>>>>;
+
+/// A cluster version: `major.minor.patch`, with an optional pre-release such as `-rc.1`, and an
+/// optional leading `v`. Build metadata after a `+` is ignored.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub pre: Option<String>,
+}
+
+impl Version {
+    pub fn new(major: u64, minor: u64, patch: u64) -> Version {
+        Version {
+            major,
+            minor,
+            patch,
+            pre: None,
+        }
+    }
+}
+
+impl FromStr for Version {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Version> {
+        let version = s.trim();
+        let version = version.strip_prefix('v').unwrap_or(version);
+        let version = version.splitn(2, '+').next().unwrap();
+        let mut release_pre = version.splitn(2, '-');
+        let release = release_pre.next().unwrap();
+        let pre = release_pre.next().map(str::to_owned);
+        if let Some(ref pre) = pre {
+            for identifier in pre.split('.') {
+                if identifier.is_empty() {
+                    return Err(box_err!("invalid version {:?}: empty pre-release identifier", s));
+                }
+                // Leading zeros would make two different pre-releases compare equal.
+                if identifier.len() > 1
+                    && identifier.starts_with('0')
+                    && identifier.bytes().all(|b| b.is_ascii_digit())
+                {
+                    return Err(box_err!(
+                        "invalid version {:?}: pre-release number {:?} has a leading zero",
+                        s,
+                        identifier
+                    ));
+                }
+            }
+        }
+        let parts: Vec<&str> = release.split('.').collect();
+        if parts.len() != 3 {
+            return Err(box_err!("invalid version {:?}: expected major.minor.patch", s));
+        }
+        let mut numbers = [0u64; 3];
+        for (number, part) in numbers.iter_mut().zip(&parts) {
+            *number = part.parse().map_err(|_| -> Error {
+                box_err!("invalid version {:?}: {:?} isn't a number", s, part)
+            })?;
+        }
+        Ok(Version {
+            major: numbers[0],
+            minor: numbers[1],
+            patch: numbers[2],
+            pre,
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(ref pre) = self.pre {
+            write!(f, "-{}", pre)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Version) -> CmpOrdering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (&self.pre, &other.pre) {
+                // A pre-release comes before its release.
+                (None, None) => CmpOrdering::Equal,
+                (None, Some(_)) => CmpOrdering::Greater,
+                (Some(_), None) => CmpOrdering::Less,
+                (Some(a), Some(b)) => cmp_pre_release(a, b),
+            })
+    }
+}
+
+/// Compares pre-releases the semver way: identifier by identifier, numbers numerically and
+/// before words, and a pre-release before any longer one it's a prefix of. So `rc.2` comes
+/// before `rc.10`, and `alpha` before `alpha.1`.
+fn cmp_pre_release(a: &str, b: &str) -> CmpOrdering {
+    let mut a = a.split('.');
+    let mut b = b.split('.');
+    loop {
+        let ord = match (a.next(), b.next()) {
+            (None, None) => return CmpOrdering::Equal,
+            (None, Some(_)) => return CmpOrdering::Less,
+            (Some(_), None) => return CmpOrdering::Greater,
+            (Some(x), Some(y)) => match (x.parse::<u64>(), y.parse::<u64>()) {
+                (Ok(x), Ok(y)) => x.cmp(&y),
+                (Ok(_), Err(_)) => CmpOrdering::Less,
+                (Err(_), Ok(_)) => CmpOrdering::Greater,
+                (Err(_), Err(_)) => x.cmp(y),
+            },
+        };
+        if ord != CmpOrdering::Equal {
+            return ord;
+        }
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Version) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Features that depend on the whole cluster running a minimum version, and whether it does.
+///
+/// Components register each feature with the version that introduced it, then ask
+/// `is_enabled` before relying on it. The cluster version is the one FIDel reports in store
+/// heartbeat responses; until the first report, no feature is enabled, so nothing is used before
+/// the cluster is known to support it.
+#[derive(Default)]
+pub struct FeatureGate {
+    cluster_version: RwLock<Option<Version>>,
+    features: RwLock<HashMap<String, Version>>,
+}
+
+impl FeatureGate {
+    /// Records the cluster version FIDel reported. Reports that don't parse are logged and
+    /// ignored.
+    pub fn observe_cluster_version(&self, version: &str) {
+        if version.is_empty() {
+            return;
+        }
+        match version.parse::<Version>() {
+            Ok(version) => {
+                let mut current = self.cluster_version.wl();
+                if current.as_ref() != Some(&version) {
+                    info!("feature gate cluster version changed";
+                        "from" => ?current.as_ref().map(|v| v.to_string()),
+                        "to" => %version);
+                    *current = Some(version);
+                }
+            }
+            Err(e) => warn!("ignoring unparsable cluster version";
+                "version" => version,
+                "err" => ?e),
+        }
+    }
+
+    pub fn cluster_version(&self) -> Option<Version> {
+        self.cluster_version.rl().clone()
+    }
+
+    /// Whether the cluster is known to run `version` or later.
+    pub fn is_at_least(&self, version: &str) -> Result<bool> {
+        let version = version.parse::<Version>()?;
+        Ok(self.cluster_version.rl().as_ref().map_or(false, |current| *current >= version))
+    }
+
+    /// Registers `feature` as needing the cluster at `min_version` or later. Registering a
+    /// feature again replaces its version.
+    pub fn register(&self, feature: &str, min_version: &str) -> Result<()> {
+        let min_version = min_version.parse::<Version>()?;
+        self.features.wl().insert(feature.to_owned(), min_version);
+        Ok(())
+    }
+
+    /// Whether `feature` can be used. Features never registered can't.
+    pub fn is_enabled(&self, feature: &str) -> bool {
+        let features = self.features.rl();
+        let min_version = match features.get(feature) {
+            Some(min_version) => min_version,
+            None => return false,
+        };
+        self.cluster_version.rl().as_ref().map_or(false, |current| current >= min_version)
+    }
+}
+
+/// Tracks the replication mode FIDel reports, for routing reads with DR in mind.
+///
+/// FIDel reports the cluster's replication status when a store bootstraps the cluster, puts its
+/// store, and in every store heartbeat response; `RpcClient` passes each report to its tracker.
+/// A report that differs in any way from the last one, be it the mode or the DR auto-sync state
+/// and its id, is a transition: it is logged and sent to every subscriber.
+#[derive(Default)]
+pub struct ReplicationModeTracker {
+    status: RwLock<Option<ReplicationStatus>>,
+    subscribers: Mutex<Vec<mpsc::UnboundedSender<ReplicationStatus>>>,
+}
+
+impl ReplicationModeTracker {
+    /// Records `status`, returning whether it is a transition.
+    pub fn observe(&self, status: ReplicationStatus) -> bool {
+        // Send while holding the status lock, so that subscribers get transitions in the order
+        // they were recorded, and the last one they get is the current status.
+        let mut current = self.status.wl();
+        if current.as_ref() == Some(&status) {
+            return false;
+        }
+        info!("replication status changed";
+            "mode" => ?status.get_mode(),
+            "state" => ?status.get_dr_auto_sync().get_state(),
+            "state_id" => status.get_dr_auto_sync().get_state_id());
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.unbounded_send(status.clone()).is_ok());
+        *current = Some(status);
+        true
+    }
+
+    /// The last status FIDel reported, if it has reported one.
+    pub fn status(&self) -> Option<ReplicationStatus> {
+        self.status.rl().clone()
+    }
+
+    pub fn mode(&self) -> Option<ReplicationMode> {
+        self.status.rl().as_ref().map(|status| status.get_mode())
+    }
+
+    /// The id of the current DR auto-sync state, which FIDel bumps on every state change.
+    pub fn state_id(&self) -> Option<u64> {
+        self.status
+            .rl()
+            .as_ref()
+            .map(|status| status.get_dr_auto_sync().get_state_id())
+    }
+
+    /// Returns a stream of transitions, starting with the current status if there is one. It
+    /// ends when the tracker is dropped.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<ReplicationStatus> {
+        let (tx, rx) = mpsc::unbounded();
+        // Hold the status lock, so that no transition slips in between the current status and
+        // the subscription.
+        let status = self.status.rl();
+        if let Some(status) = status.as_ref() {
+            // The receiver is still in hand, so this can't fail.
+            let _ = tx.unbounded_send(status.clone());
+        }
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+}
+
+/// The kinds of command FIDel sends back in a region heartbeat response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RegionCommandKind {
+    ChangePeer,
+    TransferLeader,
+    SplitRegion,
+    Merge,
+}
+
+impl RegionCommandKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RegionCommandKind::ChangePeer => "change_peer",
+            RegionCommandKind::TransferLeader => "transfer_leader",
+            RegionCommandKind::SplitRegion => "split_region",
+            RegionCommandKind::Merge => "merge",
+        }
+    }
+}
+
+/// A command decoded from a region heartbeat response.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RegionCommand {
+    ChangePeer(FIDelpb::ChangePeer),
+    TransferLeader(FIDelpb::TransferLeader),
+    SplitRegion(FIDelpb::SplitRegion),
+    Merge(FIDelpb::Merge),
+}
+
+impl RegionCommand {
+    pub fn kind(&self) -> RegionCommandKind {
+        match *self {
+            RegionCommand::ChangePeer(_) => RegionCommandKind::ChangePeer,
+            RegionCommand::TransferLeader(_) => RegionCommandKind::TransferLeader,
+            RegionCommand::SplitRegion(_) => RegionCommandKind::SplitRegion,
+            RegionCommand::Merge(_) => RegionCommandKind::Merge,
+        }
+    }
+}
+
+/// A command for one region, with the epoch FIDel saw the region at.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegionHeartbeatCommand {
+    pub region_id: u64,
+    pub region_epoch: metapb::RegionEpoch,
+    pub target_peer: metapb::Peer,
+    pub command: RegionCommand,
+}
+
+impl RegionHeartbeatCommand {
+    /// Decodes the command carried by `resp`, if any. FIDel sends at most one command per
+    /// response; should several be set, the first of change peer, transfer leader, split and
+    /// merge wins.
+    pub fn decode(mut resp: FIDelpb::RegionHeartbeatResponse) -> Option<RegionHeartbeatCommand> {
+        let command = if resp.has_change_peer() {
+            RegionCommand::ChangePeer(resp.take_change_peer())
+        } else if resp.has_transfer_leader() {
+            RegionCommand::TransferLeader(resp.take_transfer_leader())
+        } else if resp.has_split_region() {
+            RegionCommand::SplitRegion(resp.take_split_region())
+        } else if resp.has_merge() {
+            RegionCommand::Merge(resp.take_merge())
+        } else {
+            return None;
+        };
+        Some(RegionHeartbeatCommand {
+            region_id: resp.get_region_id(),
+            region_epoch: resp.take_region_epoch(),
+            target_peer: resp.take_target_peer(),
+            command,
+        })
+    }
+}
+
+/// What `RegionHeartbeatDispatcher::dispatch` did with a response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DispatchOutcome {
+    /// The response carried no command.
+    NoCommand,
+    /// The command was passed to its handler.
+    Dispatched(RegionCommandKind),
+    /// This store doesn't know the region, so the command was dropped.
+    RegionNotFound(RegionCommandKind),
+    /// FIDel saw the region at an older epoch than this store has, so the command was dropped.
+    StaleEpoch(RegionCommandKind),
+    /// No handler is registered for the command.
+    Unhandled(RegionCommandKind),
+}
+
+/// Returns whether a command FIDel issued against `epoch` is out of date, given that the
+/// region is at `current` locally.
+fn is_epoch_stale(epoch: &metapb::RegionEpoch, current: &metapb::RegionEpoch) -> bool {
+    epoch.get_conf_ver() < current.get_conf_ver() || epoch.get_version() < current.get_version()
+}
+
+type RegionCommandHandler = Box<dyn Fn(RegionHeartbeatCommand) + Send + Sync>;
+type RegionEpochLookup = Box<dyn Fn(u64) -> Option<metapb::RegionEpoch> + Send + Sync>;
+
+/// Routes the commands in region heartbeat responses to a handler per command kind.
+///
+/// Every command is checked against the local epoch of its region first, and dropped if FIDel
+/// decided on it against an older epoch: the region has split, merged or changed membership
+/// since, and FIDel will issue a fresh command on a later heartbeat if one is still needed.
+/// Each outcome is counted in `FIDel_HEARTBEAT_COUNTER_VEC`, labelled by command kind and
+/// outcome, e.g. `split_region_stale_epoch`.
+pub struct RegionHeartbeatDispatcher {
+    local_epoch: RegionEpochLookup,
+    handlers: HashMap<RegionCommandKind, RegionCommandHandler>,
+}
+
+impl RegionHeartbeatDispatcher {
+    /// `local_epoch` returns the epoch this store has for a region, or `None` if it has no
+    /// peer of the region.
+    pub fn new<F>(local_epoch: F) -> RegionHeartbeatDispatcher
+    where
+        F: Fn(u64) -> Option<metapb::RegionEpoch> + Send + Sync + 'static,
+    {
+        RegionHeartbeatDispatcher {
+            local_epoch: Box::new(local_epoch),
+            handlers: HashMap::default(),
+        }
+    }
+
+    /// Routes commands of `kind` to `handler`, replacing any handler registered before.
+    pub fn register<H>(&mut self, kind: RegionCommandKind, handler: H)
+    where
+        H: Fn(RegionHeartbeatCommand) + Send + Sync + 'static,
+    {
+        self.handlers.insert(kind, Box::new(handler));
+    }
+
+    pub fn dispatch(&self, resp: FIDelpb::RegionHeartbeatResponse) -> DispatchOutcome {
+        match RegionHeartbeatCommand::decode(resp) {
+            Some(cmd) => self.dispatch_command(cmd),
+            None => DispatchOutcome::NoCommand,
+        }
+    }
+
+    /// Like `dispatch`, for a command that didn't come in a heartbeat response, e.g. one from
+    /// `RpcClient::ask_merge`.
+    pub fn dispatch_command(&self, cmd: RegionHeartbeatCommand) -> DispatchOutcome {
+        let kind = cmd.command.kind();
+        let (outcome, result) = match (self.local_epoch)(cmd.region_id) {
+            None => (DispatchOutcome::RegionNotFound(kind), "region_not_found"),
+            Some(ref current) if is_epoch_stale(&cmd.region_epoch, current) => {
+                (DispatchOutcome::StaleEpoch(kind), "stale_epoch")
+            }
+            Some(_) => match self.handlers.get(&kind) {
+                Some(handler) => {
+                    handler(cmd.clone());
+                    (DispatchOutcome::Dispatched(kind), "dispatched")
+                }
+                None => (DispatchOutcome::Unhandled(kind), "unhandled"),
+            },
+        };
+        FIDel_HEARTBEAT_COUNTER_VEC
+            .with_label_values(&[&format!("{}_{}", kind.as_str(), result)])
+            .inc();
+        if let DispatchOutcome::Dispatched(_) = outcome {
+            return outcome;
+        }
+        debug!("drop region heartbeat command";
+            "region_id" => cmd.region_id,
+            "command" => kind.as_str(),
+            "result" => result,
+            "epoch" => ?cmd.region_epoch);
+        outcome
+    }
+}
+
+/// A client-side rate limit on one kind of RPC to FIDel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RpcRateLimit {
+    /// Requests per second sent on average.
+    pub rate: f64,
+    /// Requests sent at once after the RPC has been idle.
+    pub burst: f64,
+    /// How long a request may be held back waiting for its turn before it's shed instead. Zero
+    /// sheds every request over the limit.
+    pub max_wait: Duration,
+}
+
+/// A token bucket, which lets requests run into debt: a request that finds no token takes one
+/// anyway and waits until the bucket would have refilled to it, so queued requests go out in
+/// the order they arrived.
+struct TokenBucket {
+    limit: RpcRateLimit,
+    tokens: f64,
+    last: Instant,
+    dropped: u64,
+}
+
+enum Admission {
+    Now,
+    After(Duration),
+    Shed,
+}
+
+impl TokenBucket {
+    /// Fails unless `limit.rate` is positive and `limit.burst` is at least one request, both
+    /// finite.
+    fn new(limit: RpcRateLimit) -> Result<TokenBucket> {
+        if !limit.rate.is_finite() || limit.rate <= 0.0 {
+            return Err(box_err!("rate limit rate must be positive, got {}", limit.rate));
+        }
+        if !limit.burst.is_finite() || limit.burst < 1.0 {
+            return Err(box_err!("rate limit burst must be at least 1, got {}", limit.burst));
+        }
+        Ok(TokenBucket {
+            tokens: limit.burst,
+            limit,
+            last: Instant::now(),
+            dropped: 0,
+        })
+    }
+
+    fn admit(&mut self, now: Instant) -> Admission {
+        let elapsed = duration_to_sec(now.saturating_duration_since(self.last));
+        self.tokens = (self.tokens + elapsed * self.limit.rate).min(self.limit.burst);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Admission::Now;
+        }
+        let wait = (1.0 - self.tokens) / self.limit.rate;
+        if wait > duration_to_sec(self.limit.max_wait) {
+            self.dropped += 1;
+            return Admission::Shed;
+        }
+        self.tokens -= 1.0;
+        Admission::After(Duration::from_secs_f64(wait))
+    }
+}
+
+/// Rate limits on RPCs to FIDel, by RPC name, so that heartbeats from every region of a store
+/// don't stampede FIDel after a reconnect. RPCs without a limit pass straight through.
+#[derive(Default)]
+struct RpcRateLimiter {
+    buckets: Mutex<HashMap<&'static str, TokenBucket>>,
+}
+
+impl RpcRateLimiter {
+    fn set(&self, rpc: &'static str, limit: Option<RpcRateLimit>) -> Result<()> {
+        let mut buckets = self.buckets.lock().unwrap();
+        match limit {
+            Some(limit) => {
+                let mut bucket = TokenBucket::new(limit)?;
+                bucket.dropped = buckets.get(rpc).map_or(0, |b| b.dropped);
+                buckets.insert(rpc, bucket);
+            }
+            None => {
+                buckets.remove(rpc);
+            }
+        }
+        Ok(())
+    }
+
+    fn admit(&self, rpc: &'static str) -> Admission {
+        match self.buckets.lock().unwrap().get_mut(rpc) {
+            Some(bucket) => bucket.admit(Instant::now()),
+            None => Admission::Now,
+        }
+    }
+
+    fn dropped(&self, rpc: &str) -> u64 {
+        self.buckets.lock().unwrap().get(rpc).map_or(0, |b| b.dropped)
+    }
+}
+
+/// The largest gRPC message FIDel channels accept by default. `GetAllStores` and `GetRegion`
+/// responses of large clusters outgrow gRPC's own 4MiB default.
+const DEFAULT_MAX_MESSAGE_LEN: i32 = 64 * 1024 * 1024;
+
+/// Options for the gRPC channels to FIDel members, the `channel` section of `Config`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct ChannelOptions {
+    /// The largest message received, in bytes, or -1 for no limit.
+    pub max_receive_message_len: i32,
+    /// The largest message sent, in bytes, or -1 for no limit.
+    pub max_send_message_len: i32,
+    /// Compress requests with gzip.
+    pub gzip: bool,
+}
+
+impl Default for ChannelOptions {
+    fn default() -> ChannelOptions {
+        ChannelOptions {
+            max_receive_message_len: DEFAULT_MAX_MESSAGE_LEN,
+            max_send_message_len: DEFAULT_MAX_MESSAGE_LEN,
+            gzip: false,
+        }
+    }
+}
+
+impl ChannelOptions {
+    pub fn validate(&self) -> Result<()> {
+        for (name, len) in &[
+            ("max_receive_message_len", self.max_receive_message_len),
+            ("max_send_message_len", self.max_send_message_len),
+        ] {
+            if *len == 0 || *len < -1 {
+                return Err(box_err!("{} must be positive or -1, got {}", name, len));
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the options to a channel about to be built.
+    pub fn apply(&self, cb: ChannelBuilder) -> ChannelBuilder {
+        let cb = cb
+            .max_receive_message_len(self.max_receive_message_len)
+            .max_send_message_len(self.max_send_message_len);
+        if self.gzip {
+            cb.default_compression_algorithm(CompressionAlgorithms::GRPC_COMPRESS_GZIP)
+        } else {
+            cb
+        }
+    }
+}
+
 pub struct RpcClient {
     cluster_id: u64,
     leader_client: Arc<LeaderClient>,
+    leader_watchers: LeaderWatchers,
+    reconnect_callback: ReconnectCallback,
+    rate_limiter: RpcRateLimiter,
+    replication_mode: Arc<ReplicationModeTracker>,
+    feature_gate: Arc<FeatureGate>,
 }
 
 impl RpcClient {
+    /// Connects to FIDel, building every channel, including those to leaders reconnected to
+    /// later, with `blacklbraned.channel`.
     pub fn new(blacklbraned: &Config, security_mgr: Arc<SecurityManager>) -> Result<RpcClient> {
+        blacklbraned.channel.validate()?;
         let env = Arc::new(
             EnvBuilder::new()
                 .cq_count(CQ_COUNT)
@@ -64,14 +841,47 @@ impl RpcClient {
                         leader_client: Arc::new(LeaderClient::new(
                             env,
                             security_mgr,
+                            blacklbraned.channel.clone(),
                             client,
                             members,
                         )),
+                        leader_watchers: Arc::default(),
+                        reconnect_callback: Arc::default(),
+                        rate_limiter: RpcRateLimiter::default(),
+                        replication_mode: Arc::default(),
+                        feature_gate: Arc::default(),
                     };
 
+                    // Every reconnect, including those made by retrying requests, signals the
+                    // watchers. The signal is handled on the client's executor rather than in
+                    // the callback, which may run while the leader client is locked.
+                    let (reconnected_tx, reconnected_rx) = mpsc::unbounded();
+                    let callback = Arc::clone(&rpc_client.reconnect_callback);
+                    rpc_client.leader_client.on_reconnect(Box::new(move || {
+                        let _ = reconnected_tx.unbounded_send(());
+                        if let Some(f) = callback.read().unwrap().as_ref() {
+                            f();
+                        }
+                    }));
+                    let client = Arc::downgrade(&rpc_client.leader_client);
+                    let watchers = Arc::downgrade(&rpc_client.leader_watchers);
+                    let notify_loop = reconnected_rx.for_each(move |()| {
+                        if let (Some(cli), Some(watchers)) = (client.upgrade(), watchers.upgrade()) {
+                            notify_leader_watchers(&cli, &watchers);
+                        }
+                        Ok(())
+                    });
+                    rpc_client
+                        .leader_client
+                        .inner
+                        .rl()
+                        .client_stub
+                        .spawn(notify_loop);
+
                     // spawn a background future to FIDelio FIDel information periodically
                     let duration = blacklbraned.FIDelio_interval.0;
                     let client = Arc::downgrade(&rpc_client.leader_client);
+                    let watchers = Arc::downgrade(&rpc_client.leader_watchers);
                     let fidelio_loop = async move {
                         loop {
                             let ok = GLOBAL_TIMER_HANDLE
@@ -92,6 +902,10 @@ impl RpcClient {
                                         warn!("FIDelio FIDel information failed");
                                         // will FIDelio later anyway
                                     }
+                                    // The member list can change without the leader changing.
+                                    if let Some(watchers) = watchers.upgrade() {
+                                        notify_leader_watchers(&cli, &watchers);
+                                    }
                                 }
                                 // if the client has been dropped, we can stop
                                 None => break,
@@ -116,76 +930,1387 @@ impl RpcClient {
                 }
             }
         }
-        Err(box_err!("endpoints are invalid"))
+        Err(box_err!("endpoints are invalid"))
+    }
+
+    /// Creates a new request header.
+    fn header(&self) -> FIDelpb::RequestHeader {
+        let mut header = FIDelpb::RequestHeader::default();
+        header.set_cluster_id(self.cluster_id);
+        header
+    }
+
+    /// Gets the leader of FIDel.
+    pub fn get_leader(&self) -> Member {
+        self.leader_client.get_leader()
+    }
+
+    /// Re-establishes connection with FIDel leader in synchronized fashion.
+    pub fn reconnect(&self) -> Result<()> {
+        let res = block_on(self.leader_client.reconnect());
+        notify_leader_watchers(&self.leader_client, &self.leader_watchers);
+        res
+    }
+
+    /// Returns a stream of FIDel leadership and membership changes.
+    ///
+    /// The stream starts with the current leader and members, and yields a new event whenever a
+    /// reconnect observes a different leader or member list, so callers can refresh routing
+    /// caches before requests start failing. Reconnects made by retrying requests count too. It
+    /// ends when the client is dropped.
+    pub fn watch_leader(&self) -> mpsc::UnboundedReceiver<LeaderChangeEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        let mut watchers = self.leader_watchers.lock().unwrap();
+        let current = LeaderChangeEvent::current(&self.leader_client);
+        // The receiver is still in hand, so this can't fail.
+        let _ = tx.unbounded_send(current.clone());
+        watchers.senders.push(tx);
+        if watchers.last.is_none() {
+            watchers.last = Some(current);
+        }
+        rx
+    }
+
+    /// The tracker of the replication mode FIDel reports to this client.
+    pub fn replication_mode(&self) -> Arc<ReplicationModeTracker> {
+        Arc::clone(&self.replication_mode)
+    }
+
+    /// Records a replication status FIDel reported, and passes it on.
+    fn observe_replication_status(
+        &self,
+        status: Option<ReplicationStatus>,
+    ) -> Option<ReplicationStatus> {
+        if let Some(ref status) = status {
+            self.replication_mode.observe(status.clone());
+        }
+        status
+    }
+
+    pub fn cluster_version(&self) -> ClusterVersion {
+        self.leader_client.inner.rl().cluster_version.clone()
+    }
+
+    /// The gate on features that need a minimum cluster version, kept current by store
+    /// heartbeats.
+    pub fn feature_gate(&self) -> Arc<FeatureGate> {
+        Arc::clone(&self.feature_gate)
+    }
+
+    /// Whether the cluster is known to run `version`, e.g. `"5.0.0"`, or later.
+    pub fn is_cluster_version_at_least(&self, version: &str) -> Result<bool> {
+        self.feature_gate.is_at_least(version)
+    }
+
+    /// Limits how often `rpc` is sent, e.g. `"region_heartbeat"` or `"store_heartbeat"`, or
+    /// lifts its limit if `limit` is `None`. Requests over the limit are delayed up to the
+    /// limit's `max_wait`, and fail with an error `is_rate_limited` recognizes beyond that.
+    ///
+    /// Fails, leaving any earlier limit in place, unless the limit's rate is positive and its
+    /// burst at least one request.
+    pub fn set_rate_limit(&self, rpc: &'static str, limit: Option<RpcRateLimit>) -> Result<()> {
+        self.rate_limiter.set(rpc, limit)
+    }
+
+    /// Returns how many `rpc` requests have been shed by its rate limit.
+    pub fn rate_limited_count(&self, rpc: &str) -> u64 {
+        self.rate_limiter.dropped(rpc)
+    }
+
+    /// Sends the request `send` makes once the rate limit of `rpc` admits it.
+    fn rate_limited<T, F>(&self, rpc: &'static str, send: F) -> FIDelFuture<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> FIDelFuture<T> + Send + 'static,
+    {
+        match self.rate_limiter.admit(rpc) {
+            Admission::Now => send(),
+            Admission::After(wait) => Box::new(
+                GLOBAL_TIMER_HANDLE
+                    .delay(Instant::now() + wait)
+                    .then(move |_| send()),
+            ) as FIDelFuture<_>,
+            Admission::Shed => {
+                FIDel_HEARTBEAT_COUNTER_VEC
+                    .with_label_values(&["rate_limited"])
+                    .inc();
+                Box::new(future::err(rate_limited(rpc))) as FIDelFuture<_>
+            }
+        }
+    }
+
+    /// Creates a new call option with default request timeout.
+    #[inline]
+    fn call_option() -> CallOption {
+        CallOption::default().timeout(Duration::from_secs(REQUEST_TIMEOUT))
+    }
+
+    /// Sends a request synchronously, retrying on leader change. Without a deadline, gives up
+    /// after `LEADER_CHANGE_RETRY` attempts; with one, also gives up once it passes.
+    fn sync_request<F, R>(&self, deadline: Option<Instant>, func: F) -> Result<R>
+    where
+        F: Fn(&FIDelpb::FIDelClient, CallOption) -> grpcio::Result<R>,
+    {
+        match deadline {
+            None => sync_request(&self.leader_client, LEADER_CHANGE_RETRY, |client| {
+                func(client, Self::call_option())
+            }),
+            Some(deadline) => {
+                sync_request_before(&self.leader_client, LEADER_CHANGE_RETRY, deadline, func)
+            }
+        }
+    }
+
+    fn is_cluster_bootstrapped_before(&self, deadline: Option<Instant>) -> Result<bool> {
+        let _timer = FIDel_REQUEST_HISTOGRAM_VEC
+            .with_label_values(&["is_cluster_bootstrapped"])
+            .start_coarse_timer();
+
+        let mut req = FIDelpb::IsBootstrappedRequest::default();
+        req.set_header(self.header());
+
+        let resp = self.sync_request(deadline, |client, option| {
+            client.is_bootstrapped_opt(&req, option)
+        })?;
+        check_resp_header(resp.get_header())?;
+
+        Ok(resp.get_bootstrapped())
+    }
+
+    fn alloc_id_before(&self, deadline: Option<Instant>) -> Result<u64> {
+        let _timer = FIDel_REQUEST_HISTOGRAM_VEC
+            .with_label_values(&["alloc_id"])
+            .start_coarse_timer();
+
+        let mut req = FIDelpb::AllocIdRequest::default();
+        req.set_header(self.header());
+
+        let resp = self.sync_request(deadline, |client, option| client.alloc_id_opt(&req, option))?;
+        check_resp_header(resp.get_header())?;
+
+        Ok(resp.get_id())
+    }
+
+    fn put_store_before(
+        &self,
+        store: metapb::Store,
+        deadline: Option<Instant>,
+    ) -> Result<Option<ReplicationStatus>> {
+        let _timer = FIDel_REQUEST_HISTOGRAM_VEC
+            .with_label_values(&["put_store"])
+            .start_coarse_timer();
+
+        let mut req = FIDelpb::PutStoreRequest::default();
+        req.set_header(self.header());
+        req.set_store(store);
+
+        let mut resp =
+            self.sync_request(deadline, |client, option| client.put_store_opt(&req, option))?;
+        check_resp_header(resp.get_header())?;
+
+        Ok(self.observe_replication_status(resp.replication_status.take()))
+    }
+
+    fn get_store_before(&self, store_id: u64, deadline: Option<Instant>) -> Result<metapb::Store> {
+        let _timer = FIDel_REQUEST_HISTOGRAM_VEC
+            .with_label_values(&["get_store"])
+            .start_coarse_timer();
+
+        let mut req = FIDelpb::GetStoreRequest::default();
+        req.set_header(self.header());
+        req.set_store_id(store_id);
+
+        let mut resp =
+            self.sync_request(deadline, |client, option| client.get_store_opt(&req, option))?;
+        check_resp_header(resp.get_header())?;
+
+        let store = resp.take_store();
+        if store.get_state() != metapb::StoreState::Tombstone {
+            Ok(store)
+        } else {
+            Err(Error::StoreTombstone(format!("{:?}", store)))
+        }
+    }
+
+    /// Like `is_cluster_bootstrapped`, but spends at most `budget` in total, retries included.
+    /// Fails with a `DEADLINE_EXCEEDED` gRPC error once the budget runs out; see
+    /// `is_deadline_exceeded`.
+    pub fn is_cluster_bootstrapped_within(&self, budget: Duration) -> Result<bool> {
+        self.is_cluster_bootstrapped_before(Some(Instant::now() + budget))
+    }
+
+    /// Like `alloc_id`, but spends at most `budget` in total, retries included.
+    pub fn alloc_id_within(&self, budget: Duration) -> Result<u64> {
+        self.alloc_id_before(Some(Instant::now() + budget))
+    }
+
+    /// Like `put_store`, but spends at most `budget` in total, retries included.
+    pub fn put_store_within(
+        &self,
+        store: metapb::Store,
+        budget: Duration,
+    ) -> Result<Option<ReplicationStatus>> {
+        self.put_store_before(store, Some(Instant::now() + budget))
+    }
+
+    /// Like `get_store`, but spends at most `budget` in total, retries included.
+    pub fn get_store_within(&self, store_id: u64, budget: Duration) -> Result<metapb::Store> {
+        self.get_store_before(store_id, Some(Instant::now() + budget))
+    }
+
+    /// Registers `store` with the cluster, bootstrapping the cluster with `first_region` if no
+    /// store has yet.
+    ///
+    /// A zero id on the store, the region or any of the region's peers is allocated from FIDel
+    /// and written back before it's sent anywhere, and the peers are placed on the store. The
+    /// caller must persist the ids it gets back, on failure too, and pass them in again on a
+    /// retry or after a restart; only then does a retry reuse them rather than register another
+    /// store. A retry whose earlier attempt did bootstrap the cluster, though its response was
+    /// lost, is still reported as `StoreBootstrap::BootstrappedCluster`. Losing the bootstrap
+    /// race to another store isn't an error either; it's reported as `StoreBootstrap::LostRace`.
+    pub fn bootstrap_store_if_needed(
+        &self,
+        store: &mut metapb::Store,
+        first_region: &mut metapb::Region,
+    ) -> Result<StoreBootstrap> {
+        bootstrap_store(self, self.cluster_id, store, first_region)
+    }
+
+    /// Asks FIDel for the ids needed to split `region` at each of `keys`.
+    ///
+    /// Keys that don't lie strictly inside the region, or repeat an earlier key, are rejected
+    /// without asking FIDel. The rest are sorted and covered by as few `ask_batch_split` calls as
+    /// possible. The result has an outcome for every key, in the order given; the caller proposes
+    /// the actual splits with the allocated ids.
+    pub fn split_region_at_keys(
+        &self,
+        region: metapb::Region,
+        keys: Vec<Vec<u8>>,
+    ) -> FIDelFuture<Vec<(Vec<u8>, SplitKeyOutcome)>> {
+        let mut valid: Vec<(usize, Vec<u8>)> = keys
+            .iter()
+            .enumerate()
+            .filter(|(_, key)| is_split_key_in_region(&region, key))
+            .map(|(i, key)| (i, key.clone()))
+            .collect();
+        valid.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+        valid.dedup_by(|later, earlier| later.1 == earlier.1);
+
+        let mut outcomes: Vec<(Vec<u8>, SplitKeyOutcome)> = keys
+            .into_iter()
+            .map(|key| {
+                let outcome = if is_split_key_in_region(&region, &key) {
+                    SplitKeyOutcome::Duplicate
+                } else {
+                    SplitKeyOutcome::OutOfRange
+                };
+                (key, outcome)
+            })
+            .collect();
+
+        let batches: Vec<Vec<usize>> = valid
+            .chunks(MAX_BATCH_SPLIT_KEYS)
+            .map(|batch| batch.iter().map(|(i, _)| *i).collect())
+            .collect();
+        let requests: Vec<_> = batches
+            .into_iter()
+            .map(|batch| {
+                self.ask_batch_split(region.clone(), batch.len())
+                    .then(move |res| Ok::<_, Error>((batch, res)))
+            })
+            .collect();
+
+        Box::new(future::join_all(requests).map(move |responses| {
+            for (batch, res) in responses {
+                match res {
+                    Ok(mut resp) => {
+                        let mut ids = resp.take_ids().into_iter();
+                        for i in batch {
+                            outcomes[i].1 = match ids.next() {
+                                Some(id) => SplitKeyOutcome::Allocated(id),
+                                None => SplitKeyOutcome::Failed(
+                                    "FIDel allocated fewer ids than asked".to_owned(),
+                                ),
+                            };
+                        }
+                    }
+                    Err(e) => {
+                        for i in batch {
+                            outcomes[i].1 = SplitKeyOutcome::Failed(format!("{:?}", e));
+                        }
+                    }
+                }
+            }
+            outcomes
+        }))
+    }
+
+    /// Scatters the regions covering each of `ranges`, after a bulk load has ingested them.
+    ///
+    /// A range is a start key and an end key, where an empty end key means the end of the key
+    /// space. The covering regions are looked up before anything is scattered, and a region
+    /// covering several ranges is scattered once. At most `concurrency` regions are scattered at
+    /// a time. The result has an outcome for every region, by region id; failing to look up a
+    /// region fails the whole call.
+    pub fn scatter_ranges(
+        &self,
+        ranges: &[(Vec<u8>, Vec<u8>)],
+        concurrency: usize,
+    ) -> FIDelFuture<Vec<ScatterOutcome>> {
+        let regions = match self.regions_covering(ranges) {
+            Ok(regions) => regions,
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        let leader_client = Arc::clone(&self.leader_client);
+        let header = self.header();
+        let scatters = stream::iter_ok(regions.into_iter().map(|(_, region)| region)).map(
+            move |region| {
+                let region_id = region.get_id();
+                Self::scatter_region_async(&leader_client, header.clone(), region)
+                    .then(move |result| Ok::<_, Error>(ScatterOutcome { region_id, result }))
+            },
+        );
+        Box::new(
+            scatters
+                .buffer_unordered(concurrency.max(1))
+                .collect()
+                .map(|mut outcomes| {
+                    outcomes.sort_by_key(|o: &ScatterOutcome| o.region_id);
+                    outcomes
+                }),
+        )
+    }
+
+    /// The regions covering `ranges`, by region id.
+    fn regions_covering(&self, ranges: &[(Vec<u8>, Vec<u8>)]) -> Result<BTreeMap<u64, RegionInfo>> {
+        let mut regions = BTreeMap::new();
+        for (start, end) in ranges {
+            if !end.is_empty() && start >= end {
+                continue;
+            }
+            let mut key = start.clone();
+            loop {
+                let (region, leader) = self.get_region_and_leader(&key)?;
+                let region_end = region.get_end_key().to_vec();
+                regions
+                    .entry(region.get_id())
+                    .or_insert_with(|| RegionInfo::new(region, leader));
+                if region_end.is_empty() || (!end.is_empty() && region_end >= *end) {
+                    break;
+                }
+                key = region_end;
+            }
+        }
+        Ok(regions)
+    }
+
+    fn scatter_region_async(
+        leader_client: &LeaderClient,
+        header: FIDelpb::RequestHeader,
+        mut region: RegionInfo,
+    ) -> FIDelFuture<()> {
+        let timer = Instant::now();
+
+        let mut req = FIDelpb::ScatterRegionRequest::default();
+        req.set_header(header);
+        req.set_region_id(region.get_id());
+        if let Some(leader) = region.leader.take() {
+            req.set_leader(leader);
+        }
+        req.set_region(region.region);
+
+        let executor = move |client: &RwLock<Inner>, req: FIDelpb::ScatterRegionRequest| {
+            let handler = client
+                .rl()
+                .client_stub
+                .scatter_region_async_opt(&req, Self::call_option())
+                .unwrap_or_else(|e| {
+                    panic!("fail to request FIDel {} err {:?}", "scatter_region", e)
+                });
+            Box::new(handler.map_err(Error::Grpc).and_then(move |resp| {
+                FIDel_REQUEST_HISTOGRAM_VEC
+                    .with_label_values(&["scatter_region"])
+                    .observe(duration_to_sec(timer.elapsed()));
+                check_resp_header(resp.get_header())
+            })) as FIDelFuture<_>
+        };
+
+        leader_client
+            .request(req, executor, LEADER_CHANGE_RETRY)
+            .execute()
+    }
+
+    /// Sets the GC safe point of `service_id` to `safe_point` for `ttl`, keeping FIDel from
+    /// advancing the cluster's GC safe point past it until the ttl runs out. FIDel counts the ttl
+    /// in whole seconds, so a partial second is rounded up. A zero `ttl` removes the service safe
+    /// point. Resolves to the cluster's minimum service safe point, which is
+    /// greater than `safe_point` if GC had already moved past it.
+    pub fn update_service_gc_safe_point(
+        &self,
+        service_id: &str,
+        ttl: Duration,
+        safe_point: u64,
+    ) -> FIDelFuture<u64> {
+        let timer = Instant::now();
+
+        let mut req = FIDelpb::UpdateServiceGcSafePointRequest::default();
+        req.set_header(self.header());
+        req.set_service_id(service_id.as_bytes().to_vec());
+        req.set_ttl(service_safe_point_ttl_secs(ttl));
+        req.set_safe_point(safe_point);
+
+        let executor = move |client: &RwLock<Inner>, req: FIDelpb::UpdateServiceGcSafePointRequest| {
+            let handler = client
+                .rl()
+                .client_stub
+                .update_service_gc_safe_point_async_opt(&req, Self::call_option())
+                .unwrap_or_else(|e| {
+                    panic!("fail to request FIDel {} err {:?}", "update_service_gc_safe_point", e)
+                });
+            Box::new(handler.map_err(Error::Grpc).and_then(move |resp| {
+                FIDel_REQUEST_HISTOGRAM_VEC
+                    .with_label_values(&["update_service_gc_safe_point"])
+                    .observe(duration_to_sec(timer.elapsed()));
+                check_resp_header(resp.get_header())?;
+                Ok(resp.get_min_safe_point())
+            })) as FIDelFuture<_>
+        };
+
+        self.leader_client
+            .request(req, executor, LEADER_CHANGE_RETRY)
+            .execute()
+    }
+
+    /// Holds a service GC safe point at `safe_point` for as long as the returned guard lives, so
+    /// that a long scan can read at `safe_point` without GC removing versions under it.
+    ///
+    /// The safe point is set for `ttl` and renewed in the background every third of `ttl`; if
+    /// this store dies, FIDel lets it lapse after `ttl`. Dropping the guard removes it. Fails if
+    /// GC has already moved past `safe_point`.
+    pub fn keep_service_gc_safe_point(
+        client: &Arc<RpcClient>,
+        service_id: String,
+        ttl: Duration,
+        safe_point: u64,
+    ) -> Result<ServiceSafePointGuard> {
+        if ttl == Duration::from_secs(0) {
+            return Err(box_err!(
+                "service GC safe point of {} can't be kept with a zero ttl",
+                service_id
+            ));
+        }
+        let min_safe_point =
+            block_on(client.update_service_gc_safe_point(&service_id, ttl, safe_point).compat())?;
+        if min_safe_point > safe_point {
+            return Err(box_err!(
+                "GC safe point {} is already past {}",
+                min_safe_point,
+                safe_point
+            ));
+        }
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let renew_interval = ttl / 3;
+        let renewing_client = Arc::downgrade(client);
+        let renewing_stopped = Arc::clone(&stopped);
+        let renewing_service_id = service_id.clone();
+        let renew_loop = async move {
+            loop {
+                let ok = GLOBAL_TIMER_HANDLE
+                    .delay(Instant::now() + renew_interval)
+                    .compat()
+                    .await
+                    .is_ok();
+
+                if !ok {
+                    warn!("failed to delay with global timer");
+                    continue;
+                }
+
+                if renewing_stopped.load(Ordering::Acquire) {
+                    break;
+                }
+                match renewing_client.upgrade() {
+                    Some(cli) => {
+                        let res = cli
+                            .update_service_gc_safe_point(&renewing_service_id, ttl, safe_point)
+                            .compat()
+                            .await;
+                        if let Err(e) = res {
+                            warn!("renew service GC safe point failed";
+                                "service_id" => &renewing_service_id,
+                                "err" => ?e);
+                            // will renew later anyway
+                        }
+                    }
+                    // if the client has been dropped, the safe point lapses after its ttl
+                    None => break,
+                }
+            }
+        };
+
+        client
+            .leader_client
+            .inner
+            .rl()
+            .client_stub
+            .spawn(Compat::new(renew_loop.unit_error().boxed()));
+
+        Ok(ServiceSafePointGuard {
+            client: Arc::clone(client),
+            service_id,
+            stopped,
+        })
+    }
+
+    /// Asks FIDel to merge `source` into `target`, and for the command to do it.
+    ///
+    /// FIDel decides: the regions must still be at the epochs the caller passed, adjacent, and
+    /// have their peers on the same stores, and FIDel must have no other operator running on
+    /// them. The command carries the regions as FIDel has them; operator tooling sends it to the
+    /// leader of `source`, or through `RegionHeartbeatDispatcher::dispatch_command` on that store,
+    /// which drops it should the source change before it gets there.
+    pub fn ask_merge(
+        &self,
+        source: metapb::Region,
+        target: metapb::Region,
+    ) -> FIDelFuture<MergeOutcome> {
+        if source.get_id() == target.get_id() {
+            return Box::new(future::ok(MergeOutcome::Rejected(MergeRejection::SameRegion)));
+        }
+        let timer = Instant::now();
+        let source_id = source.get_id();
+
+        let mut req = FIDelpb::AskMergeRequest::default();
+        req.set_header(self.header());
+        req.set_source(source);
+        req.set_target(target);
+
+        let executor = move |client: &RwLock<Inner>, req: FIDelpb::AskMergeRequest| {
+            let handler = client
+                .rl()
+                .client_stub
+                .ask_merge_async_opt(&req, Self::call_option())
+                .unwrap_or_else(|e| panic!("fail to request FIDel {} err {:?}", "ask_merge", e));
+            Box::new(handler.map_err(Error::Grpc).and_then(move |resp| {
+                FIDel_REQUEST_HISTOGRAM_VEC
+                    .with_label_values(&["ask_merge"])
+                    .observe(duration_to_sec(timer.elapsed()));
+                check_resp_header(resp.get_header())?;
+                Ok(MergeOutcome::decode(source_id, resp))
+            })) as FIDelFuture<_>
+        };
+
+        self.leader_client
+            .request(req, executor, LEADER_CHANGE_RETRY)
+            .execute()
+    }
+
+    /// Routes the commands in this store's region heartbeat responses through `dispatcher`.
+    pub fn dispatch_region_heartbeat_responses(
+        &self,
+        store_id: u64,
+        dispatcher: Arc<RegionHeartbeatDispatcher>,
+    ) -> FIDelFuture<()> {
+        self.handle_region_heartbeat_response(store_id, move |resp| {
+            dispatcher.dispatch(resp);
+        })
+    }
+
+    /// Gets given key's Region and Region's leader from FIDel.
+    fn get_region_and_leader(&self, key: &[u8]) -> Result<(metapb::Region, Option<metapb::Causet>)> {
+        let _timer = FIDel_REQUEST_HISTOGRAM_VEC
+            .with_label_values(&["get_region"])
+            .start_coarse_timer();
+
+        let mut req = FIDelpb::GetRegionRequest::default();
+        req.set_header(self.header());
+        req.set_region_key(key.to_vec());
+
+        let mut resp = sync_request(&self.leader_client, LEADER_CHANGE_RETRY, |client| {
+            client.get_region_opt(&req, Self::call_option())
+        })?;
+        check_resp_header(resp.get_header())?;
+
+        let region = if resp.has_region() {
+            resp.take_region()
+        } else {
+            return Err(Error::RegionNotFound(key.to_owned()));
+        };
+        let leader = if resp.has_leader() {
+            Some(resp.take_leader())
+        } else {
+            None
+        };
+        Ok((region, leader))
+    }
+}
+
+/// Keeps a service GC safe point alive; see `RpcClient::keep_service_gc_safe_point`.
+pub struct ServiceSafePointGuard {
+    client: Arc<RpcClient>,
+    service_id: String,
+    stopped: Arc<AtomicBool>,
+}
+
+impl ServiceSafePointGuard {
+    pub fn service_id(&self) -> &str {
+        &self.service_id
+    }
+}
+
+impl Drop for ServiceSafePointGuard {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Release);
+        let service_id = self.service_id.clone();
+        let remove = self
+            .client
+            .update_service_gc_safe_point(&self.service_id, Duration::from_secs(0), 0)
+            .then(move |res| {
+                if let Err(e) = res {
+                    // FIDel drops it once its ttl runs out anyway.
+                    warn!("remove service GC safe point failed";
+                        "service_id" => &service_id,
+                        "err" => ?e);
+                }
+                Ok(())
+            });
+        self.client
+            .leader_client
+            .inner
+            .rl()
+            .client_stub
+            .spawn(remove);
+    }
+}
+
+/// How `HeartbeatScheduler` paces store heartbeats.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeartbeatConfig {
+    pub interval: Duration,
+    /// Each delay is the interval stretched or shrunk by a random fraction of up to this much,
+    /// so that stores started together don't heartbeat together.
+    pub jitter: f64,
+    /// The longest delay after repeated failures. Delays double with each failure in a row.
+    pub max_backoff: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> HeartbeatConfig {
+        HeartbeatConfig {
+            interval: Duration::from_secs(10),
+            jitter: 0.1,
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    /// The delay before the next heartbeat, after `failures` failed heartbeats in a row, given a
+    /// `sample` in `[0, 1)`.
+    fn delay(&self, failures: u64, sample: f64) -> Duration {
+        let base = if failures == 0 {
+            self.interval
+        } else {
+            let factor = 1u32.checked_shl(failures.min(31) as u32).unwrap_or(std::u32::MAX);
+            std::cmp::min(
+                self.interval.checked_mul(factor).unwrap_or(self.max_backoff),
+                std::cmp::max(self.max_backoff, self.interval),
+            )
+        };
+        let jitter = self.jitter.max(0.0).min(1.0) * (2.0 * sample - 1.0);
+        base.mul_f64(1.0 + jitter)
+    }
+}
+
+/// A number in `[0, 1)`, different on every call.
+fn jitter_sample(seq: u64) -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(seq);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Sends store heartbeats in the background; see `HeartbeatScheduler::start`. Dropping it stops
+/// the heartbeats.
+pub struct HeartbeatScheduler {
+    stopped: Arc<AtomicBool>,
+    sent: Arc<AtomicU64>,
+    failures: Arc<AtomicU64>,
+}
+
+impl HeartbeatScheduler {
+    /// Heartbeats the store with the stats `stats` returns, every `cfg.interval` give or take
+    /// the jitter, backing off while FIDel can't be reached.
+    ///
+    /// `on_cluster_version` is called with the cluster version whenever a response reports a
+    /// different one than the last; the client itself has already taken it up by then.
+    pub fn start<S, V>(
+        client: &Arc<RpcClient>,
+        cfg: HeartbeatConfig,
+        stats: S,
+        on_cluster_version: V,
+    ) -> HeartbeatScheduler
+    where
+        S: Fn() -> FIDelpb::StoreStats + Send + Sync + 'static,
+        V: Fn(&str) + Send + Sync + 'static,
+    {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let sent = Arc::new(AtomicU64::new(0));
+        let failures = Arc::new(AtomicU64::new(0));
+
+        let heartbeat_client = Arc::downgrade(client);
+        let (heartbeat_stopped, heartbeat_sent, heartbeat_failures) =
+            (Arc::clone(&stopped), Arc::clone(&sent), Arc::clone(&failures));
+        let heartbeat_loop = async move {
+            let mut cluster_version = String::new();
+            let mut seq = 0;
+            loop {
+                seq += 1;
+                let failures = heartbeat_failures.load(Ordering::Acquire);
+                let delay = cfg.delay(failures, jitter_sample(seq));
+                let ok = GLOBAL_TIMER_HANDLE
+                    .delay(Instant::now() + delay)
+                    .compat()
+                    .await
+                    .is_ok();
+
+                if !ok {
+                    warn!("failed to delay with global timer");
+                    continue;
+                }
+
+                if heartbeat_stopped.load(Ordering::Acquire) {
+                    break;
+                }
+                let cli = match heartbeat_client.upgrade() {
+                    Some(cli) => cli,
+                    // if the client has been dropped, we can stop
+                    None => break,
+                };
+                match cli.store_heartbeat(stats()).compat().await {
+                    Ok(resp) => {
+                        heartbeat_sent.fetch_add(1, Ordering::Release);
+                        heartbeat_failures.store(0, Ordering::Release);
+                        if !resp.get_cluster_version().is_empty()
+                            && resp.get_cluster_version() != cluster_version
+                        {
+                            cluster_version = resp.get_cluster_version().to_owned();
+                            on_cluster_version(&cluster_version);
+                        }
+                    }
+                    Err(e) => {
+                        let failures = heartbeat_failures.fetch_add(1, Ordering::AcqRel) + 1;
+                        warn!("store heartbeat failed";
+                            "failures" => failures,
+                            "err" => ?e);
+                    }
+                }
+            }
+        };
+
+        client
+            .leader_client
+            .inner
+            .rl()
+            .client_stub
+            .spawn(Compat::new(heartbeat_loop.unit_error().boxed()));
+
+        HeartbeatScheduler {
+            stopped,
+            sent,
+            failures,
+        }
+    }
+
+    /// The number of heartbeats FIDel has answered.
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Acquire)
+    }
+
+    /// The number of heartbeats in a row that failed, zero once one gets through.
+    pub fn consecutive_failures(&self) -> u64 {
+        self.failures.load(Ordering::Acquire)
+    }
+
+    /// Stops sending heartbeats; the one in flight, if any, still completes.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+    }
+}
+
+impl Drop for HeartbeatScheduler {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// The disk space of a store, in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DiskCapacity {
+    pub capacity: u64,
+    pub available: u64,
+}
+
+/// Tells `StatsCollector` how much disk space the store has, given `used_size`, the bytes its
+/// engine takes up.
+pub trait CapacityProvider: Send + Sync {
+    fn capacity(&self, used_size: u64) -> DiskCapacity;
+}
+
+impl<F> CapacityProvider for F
+where
+    F: Fn(u64) -> DiskCapacity + Send + Sync,
+{
+    fn capacity(&self, used_size: u64) -> DiskCapacity {
+        self(used_size)
+    }
+}
+
+/// A configured capacity, of which whatever the engine doesn't use is available.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FixedCapacity(pub u64);
+
+impl CapacityProvider for FixedCapacity {
+    fn capacity(&self, used_size: u64) -> DiskCapacity {
+        DiskCapacity {
+            capacity: self.0,
+            available: self.0.saturating_sub(used_size),
+        }
+    }
+}
+
+/// The kinds of requests `StoreFlow` counts for the query stats of a store heartbeat.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum QueryKind {
+    Get,
+    Scan,
+    Put,
+    Delete,
+}
+
+/// The reads, writes and requests served since the last store heartbeat. Handed out by
+/// `StatsCollector::flow` to whatever serves them; `StatsCollector::collect` empties it.
+#[derive(Debug, Default)]
+pub struct StoreFlow {
+    bytes_read: AtomicU64,
+    keys_read: AtomicU64,
+    bytes_written: AtomicU64,
+    keys_written: AtomicU64,
+    queries: [AtomicU64; 4],
+}
+
+impl StoreFlow {
+    pub fn record_read(&self, bytes: u64, keys: u64) {
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+        self.keys_read.fetch_add(keys, Ordering::Relaxed);
+    }
+
+    pub fn record_write(&self, bytes: u64, keys: u64) {
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+        self.keys_written.fetch_add(keys, Ordering::Relaxed);
+    }
+
+    pub fn record_query(&self, kind: QueryKind) {
+        self.queries[kind as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn take_query(&self, kind: QueryKind) -> u64 {
+        self.queries[kind as usize].swap(0, Ordering::Relaxed)
+    }
+}
+
+/// The factors of a column family that make the engine stall writes, where the engine reports
+/// them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FlowFactors {
+    pub l0_files: Option<u64>,
+    pub immutable_memtables: Option<u64>,
+    pub pending_compaction_bytes: Option<u64>,
+}
+
+/// What `StatsCollector` reads of the store's engine. The store implements it for its engine,
+/// so that this crate doesn't depend on one.
+pub trait EngineStats: Send + Sync {
+    /// The column families of the engine and the bytes each takes up.
+    fn namespaced_sizes(&self) -> std::result::Result<Vec<(String, u64)>, String>;
+
+    fn flow_factors(&self, namespaced: &str) -> std::result::Result<FlowFactors, String>;
+}
+
+/// When `StatsCollector` reports a store as busy: when any column family of its engine is past
+/// one of these, which are the marks at which the engine starts stalling writes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BusyThresholds {
+    pub l0_files: u64,
+    pub immutable_memtables: u64,
+    pub pending_compaction_bytes: u64,
+}
+
+impl Default for BusyThresholds {
+    fn default() -> BusyThresholds {
+        BusyThresholds {
+            l0_files: 20,
+            immutable_memtables: 5,
+            pending_compaction_bytes: 64 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// Assembles the `StoreStats` of store heartbeats: the used size and busyness from the engine's
+/// `EngineStats`, the capacity from a `CapacityProvider`, and the reads,
+/// writes and queries since the last heartbeat from the `StoreFlow` it hands out.
+///
+/// `collect` fits `HeartbeatScheduler::start` as is:
+/// `HeartbeatScheduler::start(&client, cfg, move || collector.collect(), |_| {})`.
+pub struct StatsCollector<E> {
+    engine: E,
+    store_id: u64,
+    start_time: u64,
+    capacity: Box<dyn CapacityProvider>,
+    busy: BusyThresholds,
+    flow: Arc<StoreFlow>,
+    last_collected: Mutex<u64>,
+}
+
+impl<E: EngineStats> StatsCollector<E> {
+    pub fn new<C>(engine: E, store_id: u64, capacity: C) -> StatsCollector<E>
+    where
+        C: CapacityProvider + 'static,
+    {
+        let now = UnixSecs::now().into_inner();
+        StatsCollector {
+            engine,
+            store_id,
+            start_time: now,
+            capacity: Box::new(capacity),
+            busy: BusyThresholds::default(),
+            flow: Arc::new(StoreFlow::default()),
+            last_collected: Mutex::new(now),
+        }
+    }
+
+    pub fn with_busy_thresholds(mut self, busy: BusyThresholds) -> StatsCollector<E> {
+        self.busy = busy;
+        self
+    }
+
+    /// Where the store records what it serves.
+    pub fn flow(&self) -> Arc<StoreFlow> {
+        Arc::clone(&self.flow)
+    }
+
+    /// The stats of the store now, covering the flow since the previous call. What the engine
+    /// fails to report is logged and left out.
+    pub fn collect(&self) -> FIDelpb::StoreStats {
+        let mut stats = FIDelpb::StoreStats::default();
+        stats.set_store_id(self.store_id);
+        stats.set_start_time(self.start_time as u32);
+
+        let mut used_size = 0;
+        match self.engine.namespaced_sizes() {
+            Ok(sizes) => {
+                used_size = sizes.iter().map(|(_, size)| size).sum();
+                stats.set_is_busy(sizes.iter().any(|(namespaced, _)| self.is_namespaced_busy(namespaced)));
+            }
+            Err(e) => warn!("failed to get engine sizes for store stats"; "err" => e),
+        }
+        let capacity = self.capacity.capacity(used_size);
+        stats.set_used_size(used_size);
+        stats.set_capacity(capacity.capacity);
+        stats.set_available(capacity.available.min(capacity.capacity));
+
+        let flow = &self.flow;
+        stats.set_bytes_read(flow.bytes_read.swap(0, Ordering::Relaxed));
+        stats.set_keys_read(flow.keys_read.swap(0, Ordering::Relaxed));
+        stats.set_bytes_written(flow.bytes_written.swap(0, Ordering::Relaxed));
+        stats.set_keys_written(flow.keys_written.swap(0, Ordering::Relaxed));
+        let query_stats = stats.mut_query_stats();
+        query_stats.set_get(flow.take_query(QueryKind::Get));
+        query_stats.set_scan(flow.take_query(QueryKind::Scan));
+        query_stats.set_put(flow.take_query(QueryKind::Put));
+        query_stats.set_delete(flow.take_query(QueryKind::Delete));
+
+        // The window the flow covers, which FIDel turns the counts into rates over.
+        let now = UnixSecs::now().into_inner();
+        let start = std::mem::replace(&mut *self.last_collected.lock().unwrap(), now);
+        let interval = stats.mut_interval();
+        interval.set_start_timestamp(start);
+        interval.set_end_timestamp(now);
+        stats
+    }
+
+    fn is_namespaced_busy(&self, namespaced: &str) -> bool {
+        let factors = match self.engine.flow_factors(namespaced) {
+            Ok(factors) => factors,
+            Err(e) => {
+                warn!("failed to get flow control factors for store stats";
+                    "namespaced" => namespaced,
+                    "err" => e);
+                return false;
+            }
+        };
+        let past = |factor: Option<u64>, threshold: u64| factor.map_or(false, |v| v >= threshold);
+        past(factors.l0_files, self.busy.l0_files)
+            || past(factors.immutable_memtables, self.busy.immutable_memtables)
+            || past(factors.pending_compaction_bytes, self.busy.pending_compaction_bytes)
+    }
+}
+
+/// Where to send a request for a key: the region holding it, the region's leader, and the address
+/// of the leader's store.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoreEndpoint {
+    pub region: metapb::Region,
+    pub leader: metapb::Causet,
+    pub address: String,
+}
+
+impl StoreEndpoint {
+    pub fn store_id(&self) -> u64 {
+        self.leader.get_store_id()
+    }
+}
+
+/// Returns whether `key` lies in `region`.
+fn is_key_in_region(region: &metapb::Region, key: &[u8]) -> bool {
+    key >= region.get_start_key() && (region.get_end_key().is_empty() || key < region.get_end_key())
+}
+
+/// Returns whether the key ranges of `a` and `b` overlap.
+fn is_region_overlapping(a: &metapb::Region, b: &metapb::Region) -> bool {
+    (a.get_end_key().is_empty() || b.get_start_key() < a.get_end_key())
+        && (b.get_end_key().is_empty() || a.get_start_key() < b.get_end_key())
+}
+
+/// What `Router` asks FIDel: the region holding a key, with its leader, and a store's address.
+pub trait RouteSource: Send + Sync {
+    fn get_region_and_leader(&self, key: &[u8]) -> Result<(metapb::Region, Option<metapb::Causet>)>;
+    fn get_store(&self, store_id: u64) -> Result<metapb::Store>;
+}
+
+impl RouteSource for RpcClient {
+    fn get_region_and_leader(&self, key: &[u8]) -> Result<(metapb::Region, Option<metapb::Causet>)> {
+        RpcClient::get_region_and_leader(self, key)
+    }
+
+    fn get_store(&self, store_id: u64) -> Result<metapb::Store> {
+        FIDelClient::get_store(self, store_id)
+    }
+}
+
+/// Resolves keys to the region that holds them and the address of that region's leader, caching
+/// what it learns from FIDel.
+///
+/// Cached routes go stale as leadership moves and regions split or merge. Callers report a
+/// `NotLeader` error with `on_not_leader`, and any other sign of a stale region with
+/// `invalidate_region`; the next `route` then asks FIDel again if it has to. Caching a region
+/// evicts every cached region it overlaps, so routes from before a split or merge don't linger.
+pub struct Router<S = RpcClient> {
+    client: Arc<S>,
+    /// Regions and their leaders, by start key.
+    regions: RwLock<BTreeMap<Vec<u8>, (metapb::Region, metapb::Causet)>>,
+    /// Store addresses, by store id.
+    stores: RwLock<HashMap<u64, String>>,
+}
+
+impl<S: RouteSource> Router<S> {
+    pub fn new(client: Arc<S>) -> Router<S> {
+        Router {
+            client,
+            regions: RwLock::new(BTreeMap::new()),
+            stores: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Returns where to send a request for `key`, asking FIDel only about what isn't cached.
+    pub fn route(&self, key: &[u8]) -> Result<StoreEndpoint> {
+        let (region, leader) = match self.cached_region(key) {
+            Some(cached) => cached,
+            None => {
+                let (region, leader) = self.client.get_region_and_leader(key)?;
+                let leader = match leader {
+                    Some(leader) => leader,
+                    None => return Err(box_err!("region {} has no leader", region.get_id())),
+                };
+                self.cache_region(region.clone(), leader.clone());
+                (region, leader)
+            }
+        };
+        let address = self.store_address(leader.get_store_id())?;
+        Ok(StoreEndpoint {
+            region,
+            leader,
+            address,
+        })
+    }
+
+    /// Updates the cached leader of `region_id` from a `NotLeader` error. If the error names no
+    /// leader, or one that isn't a peer of the cached region, the region is dropped instead.
+    pub fn on_not_leader(&self, region_id: u64, leader: Option<metapb::Causet>) {
+        let mut regions = self.regions.wl();
+        let start_key = match regions.iter().find(|(_, (r, _))| r.get_id() == region_id) {
+            Some((start_key, _)) => start_key.clone(),
+            None => return,
+        };
+        let (region, cached_leader) = regions.get_mut(&start_key).unwrap();
+        match leader {
+            Some(leader) if region.get_peers().iter().any(|p| p.get_id() == leader.get_id()) => {
+                *cached_leader = leader;
+            }
+            _ => {
+                regions.remove(&start_key);
+            }
+        }
+    }
+
+    /// Drops `region_id` from the cache.
+    pub fn invalidate_region(&self, region_id: u64) {
+        self.regions.wl().retain(|_, (r, _)| r.get_id() != region_id);
+    }
+
+    /// Drops the address of `store_id` from the cache, e.g. after failing to connect to it.
+    pub fn invalidate_store(&self, store_id: u64) {
+        self.stores.wl().remove(&store_id);
+    }
+
+    /// Drops everything cached.
+    pub fn clear(&self) {
+        self.regions.wl().clear();
+        self.stores.wl().clear();
+    }
+
+    fn cached_region(&self, key: &[u8]) -> Option<(metapb::Region, metapb::Causet)> {
+        let regions = self.regions.rl();
+        regions
+            .range(..=key.to_vec())
+            .next_back()
+            .map(|(_, cached)| cached)
+            .filter(|(region, _)| is_key_in_region(region, key))
+            .cloned()
+    }
+
+    fn cache_region(&self, region: metapb::Region, leader: metapb::Causet) {
+        let mut regions = self.regions.wl();
+        regions.retain(|_, (cached, _)| !is_region_overlapping(cached, &region));
+        regions.insert(region.get_start_key().to_vec(), (region, leader));
+    }
+
+    fn store_address(&self, store_id: u64) -> Result<String> {
+        if let Some(address) = self.stores.rl().get(&store_id) {
+            return Ok(address.clone());
+        }
+        let store = self.client.get_store(store_id)?;
+        let address = store.get_address().to_owned();
+        self.stores.wl().insert(store_id, address.clone());
+        Ok(address)
+    }
+}
+
+impl fmt::Debug for RpcClient {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("RpcClient")
+            .field("cluster_id", &self.cluster_id)
+            .field("leader", &self.get_leader())
+            .finish()
+    }
+}
+
+const LEADER_CHANGE_RETRY: usize = 10;
+
+fn deadline_exceeded() -> Error {
+    Error::Grpc(grpcio::Error::RpcFailure(RpcStatus::new(
+        RpcStatusCode::DEADLINE_EXCEEDED,
+        Some("deadline passed before the request to FIDel succeeded".to_owned()),
+    )))
+}
+
+/// Returns whether `e` means a request to FIDel ran out of time, rather than failed outright.
+pub fn is_deadline_exceeded(e: &Error) -> bool {
+    match e {
+        Error::Grpc(grpcio::Error::RpcFailure(status)) => {
+            status.status == RpcStatusCode::DEADLINE_EXCEEDED
+        }
+        _ => false,
+    }
+}
+
+fn rate_limited(rpc: &str) -> Error {
+    Error::Grpc(grpcio::Error::RpcFailure(RpcStatus::new(
+        RpcStatusCode::RESOURCE_EXHAUSTED,
+        Some(format!("{} request shed by the client-side rate limit", rpc)),
+    )))
+}
+
+/// Returns whether `e` means a request was never sent to FIDel because of its rate limit.
+pub fn is_rate_limited(e: &Error) -> bool {
+    match e {
+        Error::Grpc(grpcio::Error::RpcFailure(status)) => {
+            status.status == RpcStatusCode::RESOURCE_EXHAUSTED
+        }
+        _ => false,
+    }
+}
+
+/// Like `sync_request`, but stops retrying once `deadline` passes. Every attempt's timeout is
+/// capped by the time left, so the whole request never outlives the deadline by more than a
+/// reconnect.
+fn sync_request_before<F, R>(
+    client: &LeaderClient,
+    retry: usize,
+    deadline: Instant,
+    func: F,
+) -> Result<R>
+where
+    F: Fn(&FIDelpb::FIDelClient, CallOption) -> grpcio::Result<R>,
+{
+    for _ in 0..retry {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(deadline_exceeded());
+        }
+        let timeout = (deadline - now).min(Duration::from_secs(REQUEST_TIMEOUT));
+        let option = CallOption::default().timeout(timeout);
+
+        // DO NOT put any lock operation in match statement, or it will cause dead lock!
+        let ret = { func(&client.inner.rl().client_stub, option).map_err(Error::Grpc) };
+        match ret {
+            Ok(r) => return Ok(r),
+            Err(e) => {
+                error!("request failed"; "err" => ?e);
+                if Instant::now() >= deadline {
+                    return Err(deadline_exceeded());
+                }
+                if let Err(e) = block_on(client.reconnect()) {
+                    error!("reconnect failed"; "err" => ?e);
+                }
+            }
+        }
     }
+    Err(box_err!("fail to request"))
+}
 
-    /// Creates a new request header.
-    fn header(&self) -> FIDelpb::RequestHeader {
-        let mut header = FIDelpb::RequestHeader::default();
-        header.set_cluster_id(self.cluster_id);
-        header
+/// The FIDel calls `bootstrap_store` makes.
+trait BootstrapClient {
+    fn alloc_id(&self) -> Result<u64>;
+    fn is_cluster_bootstrapped(&self) -> Result<bool>;
+    fn bootstrap_cluster(&self, store: metapb::Store, region: metapb::Region) -> Result<()>;
+    fn put_store(&self, store: metapb::Store) -> Result<()>;
+    fn get_region_by_id(&self, region_id: u64) -> Result<Option<metapb::Region>>;
+}
+
+impl BootstrapClient for RpcClient {
+    fn alloc_id(&self) -> Result<u64> {
+        FIDelClient::alloc_id(self)
     }
 
-    /// Gets the leader of FIDel.
-    pub fn get_leader(&self) -> Member {
-        self.leader_client.get_leader()
+    fn is_cluster_bootstrapped(&self) -> Result<bool> {
+        FIDelClient::is_cluster_bootstrapped(self)
     }
 
-    /// Re-establishes connection with FIDel leader in synchronized fashion.
-    pub fn reconnect(&self) -> Result<()> {
-        block_on(self.leader_client.reconnect())
+    fn bootstrap_cluster(&self, store: metapb::Store, region: metapb::Region) -> Result<()> {
+        FIDelClient::bootstrap_cluster(self, store, region).map(|_| ())
     }
 
-    pub fn cluster_version(&self) -> ClusterVersion {
-        self.leader_client.inner.rl().cluster_version.clone()
+    fn put_store(&self, store: metapb::Store) -> Result<()> {
+        FIDelClient::put_store(self, store).map(|_| ())
     }
 
-    /// Creates a new call option with default request timeout.
-    #[inline]
-    fn call_option() -> CallOption {
-        CallOption::default().timeout(Duration::from_secs(REQUEST_TIMEOUT))
+    fn get_region_by_id(&self, region_id: u64) -> Result<Option<metapb::Region>> {
+        FIDelClient::get_region_by_id(self, region_id).wait()
     }
+}
 
-    /// Gets given key's Region and Region's leader from FIDel.
-    fn get_region_and_leader(&self, key: &[u8]) -> Result<(metapb::Region, Option<metapb::Causet>)> {
-        let _timer = FIDel_REQUEST_HISTOGRAM_VEC
-            .with_label_values(&["get_region"])
-            .start_coarse_timer();
+/// Returns whether FIDel has `region` with a peer on `store`, which is only the case when an
+/// earlier attempt by this store bootstrapped the cluster with it.
+fn is_bootstrapped_by<C: BootstrapClient>(
+    client: &C,
+    store: &metapb::Store,
+    region: &metapb::Region,
+) -> Result<bool> {
+    if region.get_id() == 0 {
+        return Ok(false);
+    }
+    Ok(match client.get_region_by_id(region.get_id())? {
+        Some(region) => region
+            .get_peers()
+            .iter()
+            .any(|peer| peer.get_store_id() == store.get_id()),
+        None => false,
+    })
+}
 
-        let mut req = FIDelpb::GetRegionRequest::default();
-        req.set_header(self.header());
-        req.set_region_key(key.to_vec());
+/// The logic of `RpcClient::bootstrap_store_if_needed`.
+fn bootstrap_store<C: BootstrapClient>(
+    client: &C,
+    cluster_id: u64,
+    store: &mut metapb::Store,
+    first_region: &mut metapb::Region,
+) -> Result<StoreBootstrap> {
+    if store.get_id() == 0 {
+        store.set_id(client.alloc_id()?);
+    }
 
-        let mut resp = sync_request(&self.leader_client, LEADER_CHANGE_RETRY, |client| {
-            client.get_region_opt(&req, Self::call_option())
-        })?;
-        check_resp_header(resp.get_header())?;
+    if client.is_cluster_bootstrapped()? {
+        if is_bootstrapped_by(client, store, first_region)? {
+            return Ok(StoreBootstrap::BootstrappedCluster {
+                store: store.clone(),
+                region: first_region.clone(),
+            });
+        }
+        client.put_store(store.clone())?;
+        return Ok(StoreBootstrap::Joined {
+            store: store.clone(),
+        });
+    }
 
-        let region = if resp.has_region() {
-            resp.take_region()
-        } else {
-            return Err(Error::RegionNotFound(key.to_owned()));
-        };
-        let leader = if resp.has_leader() {
-            Some(resp.take_leader())
-        } else {
-            None
-        };
-        Ok((region, leader))
+    if first_region.get_id() == 0 {
+        first_region.set_id(client.alloc_id()?);
+    }
+    for peer in first_region.mut_peers().iter_mut() {
+        if peer.get_id() == 0 {
+            peer.set_id(client.alloc_id()?);
+        }
+        peer.set_store_id(store.get_id());
     }
-}
 
-impl fmt::Debug for RpcClient {
-    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt.debug_struct("RpcClient")
-            .field("cluster_id", &self.cluster_id)
-            .field("leader", &self.get_leader())
-            .finish()
+    match client.bootstrap_cluster(store.clone(), first_region.clone()) {
+        Ok(()) => {
+            info!("bootstrapped cluster";
+                "cluster_id" => cluster_id,
+                "store_id" => store.get_id(),
+                "region_id" => first_region.get_id());
+            Ok(StoreBootstrap::BootstrappedCluster {
+                store: store.clone(),
+                region: first_region.clone(),
+            })
+        }
+        // A retry of the bootstrap request finds the cluster bootstrapped by its own first try.
+        Err(Error::ClusterBootstrapped(_)) if is_bootstrapped_by(client, store, first_region)? => {
+            Ok(StoreBootstrap::BootstrappedCluster {
+                store: store.clone(),
+                region: first_region.clone(),
+            })
+        }
+        Err(Error::ClusterBootstrapped(_)) => {
+            warn!("cluster bootstrapped by another store first";
+                "cluster_id" => cluster_id,
+                "store_id" => store.get_id());
+            client.put_store(store.clone())?;
+            Ok(StoreBootstrap::LostRace {
+                store: store.clone(),
+            })
+        }
+        Err(e) => Err(box_err!(
+            "bootstrapping cluster {} with store {} and region {} failed: {:?}",
+            cluster_id,
+            store.get_id(),
+            first_region.get_id(),
+            e
+        )),
     }
 }
 
-const LEADER_CHANGE_RETRY: usize = 10;
-
 impl FIDelClient for RpcClient {
     fn get_cluster_id(&self) -> Result<u64> {
         Ok(self.cluster_id)
@@ -209,78 +2334,23 @@ impl FIDelClient for RpcClient {
             client.bootstrap_opt(&req, Self::call_option())
         })?;
         check_resp_header(resp.get_header())?;
-        Ok(resp.replication_status.take())
+        Ok(self.observe_replication_status(resp.replication_status.take()))
     }
 
     fn is_cluster_bootstrapped(&self) -> Result<bool> {
-        let _timer = FIDel_REQUEST_HISTOGRAM_VEC
-            .with_label_values(&["is_cluster_bootstrapped"])
-            .start_coarse_timer();
-
-        let mut req = FIDelpb::IsBootstrappedRequest::default();
-        req.set_header(self.header());
-
-        let resp = sync_request(&self.leader_client, LEADER_CHANGE_RETRY, |client| {
-            client.is_bootstrapped_opt(&req, Self::call_option())
-        })?;
-        check_resp_header(resp.get_header())?;
-
-        Ok(resp.get_bootstrapped())
+        self.is_cluster_bootstrapped_before(None)
     }
 
     fn alloc_id(&self) -> Result<u64> {
-        let _timer = FIDel_REQUEST_HISTOGRAM_VEC
-            .with_label_values(&["alloc_id"])
-            .start_coarse_timer();
-
-        let mut req = FIDelpb::AllocIdRequest::default();
-        req.set_header(self.header());
-
-        let resp = sync_request(&self.leader_client, LEADER_CHANGE_RETRY, |client| {
-            client.alloc_id_opt(&req, Self::call_option())
-        })?;
-        check_resp_header(resp.get_header())?;
-
-        Ok(resp.get_id())
+        self.alloc_id_before(None)
     }
 
     fn put_store(&self, store: metapb::Store) -> Result<Option<ReplicationStatus>> {
-        let _timer = FIDel_REQUEST_HISTOGRAM_VEC
-            .with_label_values(&["put_store"])
-            .start_coarse_timer();
-
-        let mut req = FIDelpb::PutStoreRequest::default();
-        req.set_header(self.header());
-        req.set_store(store);
-
-        let mut resp = sync_request(&self.leader_client, LEADER_CHANGE_RETRY, |client| {
-            client.put_store_opt(&req, Self::call_option())
-        })?;
-        check_resp_header(resp.get_header())?;
-
-        Ok(resp.replication_status.take())
+        self.put_store_before(store, None)
     }
 
     fn get_store(&self, store_id: u64) -> Result<metapb::Store> {
-        let _timer = FIDel_REQUEST_HISTOGRAM_VEC
-            .with_label_values(&["get_store"])
-            .start_coarse_timer();
-
-        let mut req = FIDelpb::GetStoreRequest::default();
-        req.set_header(self.header());
-        req.set_store_id(store_id);
-
-        let mut resp = sync_request(&self.leader_client, LEADER_CHANGE_RETRY, |client| {
-            client.get_store_opt(&req, Self::call_option())
-        })?;
-        check_resp_header(resp.get_header())?;
-
-        let store = resp.take_store();
-        if store.get_state() != metapb::StoreState::Tombstone {
-            Ok(store)
-        } else {
-            Err(Error::StoreTombstone(format!("{:?}", store)))
-        }
+        self.get_store_before(store_id, None)
     }
 
     fn get_all_stores(&self, exclude_tombstone: bool) -> Result<Vec<metapb::Store>> {
@@ -427,9 +2497,12 @@ impl FIDelClient for RpcClient {
             ) as FIDelFuture<_>
         };
 
-        self.leader_client
-            .request(req, executor, LEADER_CHANGE_RETRY)
-            .execute()
+        let leader_client = Arc::clone(&self.leader_client);
+        self.rate_limited("region_heartbeat", move || {
+            leader_client
+                .request(req, executor, LEADER_CHANGE_RETRY)
+                .execute()
+        })
     }
 
     fn handle_region_heartbeat_response<F>(&self, _: u64, f: F) -> FIDelFuture<()>
@@ -510,8 +2583,12 @@ impl FIDelClient for RpcClient {
             .mut_interval()
             .set_end_timestamp(UnixSecs::now().into_inner());
         req.set_stats(stats);
+        let replication_mode = Arc::clone(&self.replication_mode);
+        let feature_gate = Arc::clone(&self.feature_gate);
         let executor = move |client: &RwLock<Inner>, req: FIDelpb::StoreHeartbeatRequest| {
             let cluster_version = client.rl().cluster_version.clone();
+            let replication_mode = Arc::clone(&replication_mode);
+            let feature_gate = Arc::clone(&feature_gate);
             let handler = client
                 .rl()
                 .client_stub
@@ -527,13 +2604,20 @@ impl FIDelClient for RpcClient {
                     Ok(true) => info!("set cluster version to {}", resp.get_cluster_version()),
                     _ => {}
                 };
+                feature_gate.observe_cluster_version(resp.get_cluster_version());
+                if resp.has_replication_status() {
+                    replication_mode.observe(resp.get_replication_status().clone());
+                }
                 Ok(resp)
             })) as FIDelFuture<_>
         };
 
-        self.leader_client
-            .request(req, executor, LEADER_CHANGE_RETRY)
-            .execute()
+        let leader_client = Arc::clone(&self.leader_client);
+        self.rate_limited("store_heartbeat", move || {
+            leader_client
+                .request(req, executor, LEADER_CHANGE_RETRY)
+                .execute()
+        })
     }
 
     fn report_batch_split(&self, regions: Vec<metapb::Region>) -> FIDelFuture<()> {
@@ -585,7 +2669,8 @@ impl FIDelClient for RpcClient {
     }
 
     fn handle_reconnect<F: Fn() + Sync + Send + 'static>(&self, f: F) {
-        self.leader_client.on_reconnect(Box::new(f))
+        // The leader client's own callback also signals the leader watchers; keep it.
+        *self.reconnect_callback.write().unwrap() = Some(Box::new(f));
     }
 
     fn get_gc_safe_point(&self) -> FIDelFuture<u64> {
@@ -706,3 +2791,714 @@ impl FIDelClient for RpcClient {
             .execute()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_safe_point_ttl_secs() {
+        assert_eq!(service_safe_point_ttl_secs(Duration::from_secs(0)), 0);
+        assert_eq!(service_safe_point_ttl_secs(Duration::from_millis(1)), 1);
+        assert_eq!(service_safe_point_ttl_secs(Duration::from_millis(999)), 1);
+        assert_eq!(service_safe_point_ttl_secs(Duration::from_secs(1)), 1);
+        assert_eq!(service_safe_point_ttl_secs(Duration::from_millis(1500)), 2);
+        assert_eq!(service_safe_point_ttl_secs(Duration::from_secs(u64::max_value())), i64::max_value());
+    }
+
+    fn epoch(conf_ver: u64, version: u64) -> metapb::RegionEpoch {
+        let mut epoch = metapb::RegionEpoch::default();
+        epoch.set_conf_ver(conf_ver);
+        epoch.set_version(version);
+        epoch
+    }
+
+    fn transfer_leader_response(region_id: u64, epoch: metapb::RegionEpoch) -> FIDelpb::RegionHeartbeatResponse {
+        let mut resp = FIDelpb::RegionHeartbeatResponse::default();
+        resp.set_region_id(region_id);
+        resp.set_region_epoch(epoch);
+        resp.set_transfer_leader(FIDelpb::TransferLeader::default());
+        resp
+    }
+
+    #[derive(Clone)]
+    struct MockEngineStats {
+        sizes: Vec<(String, u64)>,
+        factors: HashMap<String, FlowFactors>,
+    }
+
+    impl EngineStats for MockEngineStats {
+        fn namespaced_sizes(&self) -> std::result::Result<Vec<(String, u64)>, String> {
+            Ok(self.sizes.clone())
+        }
+
+        fn flow_factors(&self, namespaced: &str) -> std::result::Result<FlowFactors, String> {
+            self.factors.get(namespaced).cloned().ok_or_else(|| format!("no factors of {}", namespaced))
+        }
+    }
+
+    #[test]
+    fn test_stats_collector() {
+        let mut factors = HashMap::new();
+        factors.insert("default".to_owned(), FlowFactors { l0_files: Some(3), ..Default::default() });
+        factors.insert(
+            "write".to_owned(),
+            FlowFactors { pending_compaction_bytes: Some(100), ..Default::default() },
+        );
+        // The factors of "lock" can't be read, which doesn't make the store busy.
+        let engine = MockEngineStats {
+            sizes: vec![("default".to_owned(), 300), ("write".to_owned(), 200), ("lock".to_owned(), 10)],
+            factors,
+        };
+        let collector = StatsCollector::new(engine.clone(), 7, FixedCapacity(1000));
+        let flow = collector.flow();
+        flow.record_read(10, 1);
+        flow.record_write(20, 2);
+        flow.record_write(5, 1);
+        flow.record_query(QueryKind::Get);
+        flow.record_query(QueryKind::Get);
+        flow.record_query(QueryKind::Put);
+
+        let stats = collector.collect();
+        assert_eq!(stats.get_store_id(), 7);
+        assert_eq!((stats.get_used_size(), stats.get_capacity(), stats.get_available()), (510, 1000, 490));
+        assert!(!stats.get_is_busy());
+        assert_eq!((stats.get_bytes_read(), stats.get_keys_read()), (10, 1));
+        assert_eq!((stats.get_bytes_written(), stats.get_keys_written()), (25, 3));
+        let queries = stats.get_query_stats();
+        assert_eq!(
+            (queries.get_get(), queries.get_scan(), queries.get_put(), queries.get_delete()),
+            (2, 0, 1, 0)
+        );
+        let interval = stats.get_interval();
+        assert!(interval.get_start_timestamp() <= interval.get_end_timestamp());
+
+        // The flow is emptied, and the next interval starts where the last ended.
+        let next = collector.collect();
+        assert_eq!((next.get_bytes_read(), next.get_bytes_written()), (0, 0));
+        assert_eq!(next.get_query_stats().get_get(), 0);
+        assert_eq!(next.get_interval().get_start_timestamp(), interval.get_end_timestamp());
+
+        // Reaching any threshold in any column family makes the store busy.
+        let collector = collector.with_busy_thresholds(BusyThresholds { l0_files: 3, ..Default::default() });
+        assert!(collector.collect().get_is_busy());
+        let collector = collector.with_busy_thresholds(BusyThresholds {
+            l0_files: 4,
+            immutable_memtables: 1,
+            pending_compaction_bytes: 101,
+        });
+        assert!(!collector.collect().get_is_busy());
+        let collector = collector.with_busy_thresholds(BusyThresholds {
+            pending_compaction_bytes: 100,
+            ..Default::default()
+        });
+        assert!(collector.collect().get_is_busy());
+
+        // A provider's available space is capped at its capacity.
+        let collector = StatsCollector::new(engine, 7, |_| DiskCapacity { capacity: 100, available: 200 });
+        assert_eq!(collector.collect().get_available(), 100);
+    }
+
+    #[test]
+    fn test_version_parse() {
+        let v: Version = "v4.0.1-rc.2+build.7".parse().unwrap();
+        assert_eq!((v.major, v.minor, v.patch), (4, 0, 1));
+        assert_eq!(v.pre.as_deref(), Some("rc.2"));
+        assert_eq!(v.to_string(), "4.0.1-rc.2");
+        assert_eq!(" 5.1.0 ".parse::<Version>().unwrap(), Version::new(5, 1, 0));
+
+        for bad in &["", "4.0", "4.0.1.2", "4.x.1", "4.0.1-", "4.0.1-rc..1", "4.0.1-rc.01"] {
+            assert!(bad.parse::<Version>().is_err(), "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn test_version_cmp() {
+        let ordered = [
+            "3.9.9",
+            "4.0.0-alpha",
+            "4.0.0-alpha.1",
+            "4.0.0-alpha.beta",
+            "4.0.0-beta",
+            "4.0.0-beta.2",
+            "4.0.0-beta.11",
+            "4.0.0-rc.1",
+            "4.0.0-rc.2",
+            "4.0.0-rc.10",
+            "4.0.0",
+            "4.0.1",
+            "4.1.0",
+            "10.0.0",
+        ];
+        for pair in ordered.windows(2) {
+            let (a, b) = (pair[0].parse::<Version>().unwrap(), pair[1].parse::<Version>().unwrap());
+            assert!(a < b, "{} < {}", a, b);
+            assert!(b > a, "{} > {}", b, a);
+        }
+        assert_eq!(
+            "v4.0.0-rc.1+a".parse::<Version>().unwrap().cmp(&"4.0.0-rc.1".parse().unwrap()),
+            CmpOrdering::Equal
+        );
+    }
+
+    #[test]
+    fn test_feature_gate() {
+        let gate = FeatureGate::default();
+        gate.register("f", "4.0.0").unwrap();
+        assert!(!gate.is_enabled("f"));
+        gate.observe_cluster_version("4.0.0-rc.10");
+        assert!(!gate.is_enabled("f"));
+        assert!(gate.is_at_least("4.0.0-rc.2").unwrap());
+        gate.observe_cluster_version("not a version");
+        assert_eq!(gate.cluster_version().unwrap().to_string(), "4.0.0-rc.10");
+        gate.observe_cluster_version("4.0.0");
+        assert!(gate.is_enabled("f"));
+        assert!(!gate.is_enabled("unregistered"));
+    }
+
+    #[test]
+    fn test_channel_options_validate() {
+        ChannelOptions::default().validate().unwrap();
+        let mut opts = ChannelOptions::default();
+        opts.max_receive_message_len = -1;
+        opts.validate().unwrap();
+        opts.max_send_message_len = 0;
+        opts.validate().unwrap_err();
+        opts.max_send_message_len = -2;
+        opts.validate().unwrap_err();
+    }
+
+    /// A FIDel that bootstraps with the first store and region it's given.
+    #[derive(Default)]
+    struct MockBootstrapClient {
+        next_id: std::cell::Cell<u64>,
+        region: std::cell::RefCell<Option<metapb::Region>>,
+        stores: std::cell::RefCell<Vec<u64>>,
+        /// Bootstraps, then fails the request as if its response were lost.
+        lose_bootstrap_response: std::cell::Cell<bool>,
+        /// Reports the cluster unbootstrapped even when it is, as a racing store would see it.
+        hide_bootstrap: std::cell::Cell<bool>,
+    }
+
+    impl MockBootstrapClient {
+        fn bootstrapped_by(store_id: u64) -> MockBootstrapClient {
+            let client = MockBootstrapClient::default();
+            client.next_id.set(100);
+            let mut peer = metapb::Peer::default();
+            peer.set_id(2);
+            peer.set_store_id(store_id);
+            let mut region = metapb::Region::default();
+            region.set_id(1);
+            region.mut_peers().push(peer);
+            *client.region.borrow_mut() = Some(region);
+            client.stores.borrow_mut().push(store_id);
+            client
+        }
+    }
+
+    impl BootstrapClient for MockBootstrapClient {
+        fn alloc_id(&self) -> Result<u64> {
+            self.next_id.set(self.next_id.get() + 1);
+            Ok(self.next_id.get())
+        }
+
+        fn is_cluster_bootstrapped(&self) -> Result<bool> {
+            Ok(self.region.borrow().is_some() && !self.hide_bootstrap.get())
+        }
+
+        fn bootstrap_cluster(&self, store: metapb::Store, region: metapb::Region) -> Result<()> {
+            if self.region.borrow().is_some() {
+                return Err(Error::ClusterBootstrapped(1));
+            }
+            *self.region.borrow_mut() = Some(region);
+            self.stores.borrow_mut().push(store.get_id());
+            if self.lose_bootstrap_response.get() {
+                return Err(box_err!("response lost"));
+            }
+            Ok(())
+        }
+
+        fn put_store(&self, store: metapb::Store) -> Result<()> {
+            self.stores.borrow_mut().push(store.get_id());
+            Ok(())
+        }
+
+        fn get_region_by_id(&self, region_id: u64) -> Result<Option<metapb::Region>> {
+            Ok(self.region.borrow().clone().filter(|r| r.get_id() == region_id))
+        }
+    }
+
+    fn first_region() -> metapb::Region {
+        let mut region = metapb::Region::default();
+        region.mut_peers().push(metapb::Peer::default());
+        region
+    }
+
+    #[test]
+    fn test_bootstrap_store() {
+        let client = MockBootstrapClient::default();
+        let (mut store, mut region) = (metapb::Store::default(), first_region());
+        match bootstrap_store(&client, 1, &mut store, &mut region).unwrap() {
+            StoreBootstrap::BootstrappedCluster { store: s, region: r } => {
+                assert_eq!(s, store);
+                assert_eq!(r, region);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(store.get_id(), 1);
+        assert_eq!(region.get_id(), 2);
+        assert_eq!(region.get_peers()[0].get_id(), 3);
+        assert_eq!(region.get_peers()[0].get_store_id(), 1);
+        assert_eq!(*client.stores.borrow(), vec![1]);
+
+        // Calling again allocates nothing and still reports the bootstrap.
+        let res = bootstrap_store(&client, 1, &mut store, &mut region).unwrap();
+        assert!(matches!(res, StoreBootstrap::BootstrappedCluster { .. }));
+        assert_eq!(store.get_id(), 1);
+        assert_eq!(client.next_id.get(), 3);
+    }
+
+    #[test]
+    fn test_bootstrap_store_joins() {
+        let client = MockBootstrapClient::bootstrapped_by(7);
+        let (mut store, mut region) = (metapb::Store::default(), first_region());
+        let res = bootstrap_store(&client, 1, &mut store, &mut region).unwrap();
+        assert_eq!(res, StoreBootstrap::Joined { store: store.clone() });
+        assert_eq!(store.get_id(), 101);
+        assert_eq!(region.get_id(), 0);
+        assert_eq!(*client.stores.borrow(), vec![7, 101]);
+
+        // A retry with the id written back registers the same store again, not a new one.
+        let res = bootstrap_store(&client, 1, &mut store, &mut region).unwrap();
+        assert_eq!(res.store().get_id(), 101);
+        assert_eq!(*client.stores.borrow(), vec![7, 101, 101]);
+    }
+
+    #[test]
+    fn test_bootstrap_store_lost_response() {
+        let client = MockBootstrapClient::default();
+        client.lose_bootstrap_response.set(true);
+        let (mut store, mut region) = (metapb::Store::default(), first_region());
+        bootstrap_store(&client, 1, &mut store, &mut region).unwrap_err();
+        assert_eq!(store.get_id(), 1);
+        assert_eq!(region.get_id(), 2);
+
+        client.lose_bootstrap_response.set(false);
+        match bootstrap_store(&client, 1, &mut store, &mut region).unwrap() {
+            StoreBootstrap::BootstrappedCluster { store: s, region: r } => {
+                assert_eq!(s.get_id(), 1);
+                assert_eq!(r.get_id(), 2);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(client.next_id.get(), 3);
+
+        // Its own retried bootstrap request finding the cluster bootstrapped isn't a lost race.
+        client.hide_bootstrap.set(true);
+        let res = bootstrap_store(&client, 1, &mut store, &mut region).unwrap();
+        assert!(matches!(res, StoreBootstrap::BootstrappedCluster { .. }));
+    }
+
+    #[test]
+    fn test_bootstrap_store_lost_race() {
+        let client = MockBootstrapClient::bootstrapped_by(7);
+        client.hide_bootstrap.set(true);
+        let (mut store, mut region) = (metapb::Store::default(), first_region());
+        let res = bootstrap_store(&client, 1, &mut store, &mut region).unwrap();
+        assert_eq!(res, StoreBootstrap::LostRace { store: store.clone() });
+        assert_eq!(store.get_id(), 101);
+        assert_eq!(region.get_id(), 102);
+        assert_eq!(*client.stores.borrow(), vec![7, 101]);
+    }
+
+    #[test]
+    fn test_decode_region_heartbeat_command() {
+        let resp = FIDelpb::RegionHeartbeatResponse::default();
+        assert!(RegionHeartbeatCommand::decode(resp).is_none());
+
+        let cmd = RegionHeartbeatCommand::decode(transfer_leader_response(2, epoch(1, 3))).unwrap();
+        assert_eq!(cmd.region_id, 2);
+        assert_eq!(cmd.region_epoch, epoch(1, 3));
+        assert_eq!(cmd.command.kind(), RegionCommandKind::TransferLeader);
+
+        // Should several commands be set, change peer wins.
+        let mut resp = transfer_leader_response(2, epoch(1, 3));
+        resp.set_change_peer(FIDelpb::ChangePeer::default());
+        let cmd = RegionHeartbeatCommand::decode(resp).unwrap();
+        assert_eq!(cmd.command.kind(), RegionCommandKind::ChangePeer);
+    }
+
+    #[test]
+    fn test_replication_mode_tracker_order() {
+        let status = |state_id: u64| {
+            let mut status = ReplicationStatus::default();
+            status.set_mode(ReplicationMode::DrAutoSync);
+            status.mut_dr_auto_sync().set_state_id(state_id);
+            status
+        };
+        let tracker = Arc::new(ReplicationModeTracker::default());
+        assert!(tracker.observe(status(1)));
+        assert!(!tracker.observe(status(1)));
+        let rx = tracker.subscribe();
+
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let tracker = tracker.clone();
+                thread::spawn(move || {
+                    for i in 0..100 {
+                        tracker.observe(status(2 + (i * 4 + t) % 7));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let current = tracker.status().unwrap();
+        drop(tracker);
+
+        // The stream starts with the status at subscription, never repeats a status back to back,
+        // and ends with the current one.
+        let received: Vec<_> = rx.wait().map(|status| status.unwrap()).collect();
+        assert_eq!(received[0], status(1));
+        assert!(received.windows(2).all(|w| w[0] != w[1]));
+        assert_eq!(received.last(), Some(&current));
+    }
+
+    #[test]
+    fn test_decode_ask_merge_response() {
+        let mut resp = FIDelpb::AskMergeResponse::default();
+        resp.set_source_epoch(epoch(1, 3));
+        let mut target = metapb::Region::default();
+        target.set_id(3);
+        let mut merge = FIDelpb::Merge::default();
+        merge.set_target(target);
+        resp.set_merge(merge.clone());
+        assert_eq!(
+            MergeOutcome::decode(2, resp),
+            MergeOutcome::Ready(RegionHeartbeatCommand {
+                region_id: 2,
+                region_epoch: epoch(1, 3),
+                target_peer: metapb::Peer::default(),
+                command: RegionCommand::Merge(merge),
+            })
+        );
+
+        let mut resp = FIDelpb::AskMergeResponse::default();
+        resp.set_reject_reason(FIDelpb::MergeRejectReason::StaleEpoch);
+        resp.set_rejected_region_id(3);
+        assert_eq!(
+            MergeOutcome::decode(2, resp),
+            MergeOutcome::Rejected(MergeRejection::StaleEpoch(3))
+        );
+
+        let mut resp = FIDelpb::AskMergeResponse::default();
+        resp.set_reject_reason(FIDelpb::MergeRejectReason::NotAdjacent);
+        assert_eq!(
+            MergeOutcome::decode(2, resp),
+            MergeOutcome::Rejected(MergeRejection::NotAdjacent)
+        );
+
+        // Neither a merge nor a reason.
+        assert_eq!(
+            MergeOutcome::decode(2, FIDelpb::AskMergeResponse::default()),
+            MergeOutcome::Rejected(MergeRejection::RegionNotFound(2))
+        );
+    }
+
+    #[test]
+    fn test_region_heartbeat_dispatcher() {
+        let mut dispatcher = RegionHeartbeatDispatcher::new(|region_id| match region_id {
+            1 => Some(epoch(2, 5)),
+            _ => None,
+        });
+        let handled = Arc::new(Mutex::new(vec![]));
+        let h = handled.clone();
+        dispatcher.register(RegionCommandKind::TransferLeader, move |cmd| {
+            h.lock().unwrap().push(cmd.region_id)
+        });
+
+        let kind = RegionCommandKind::TransferLeader;
+        assert_eq!(
+            dispatcher.dispatch(FIDelpb::RegionHeartbeatResponse::default()),
+            DispatchOutcome::NoCommand
+        );
+        assert_eq!(
+            dispatcher.dispatch(transfer_leader_response(7, epoch(2, 5))),
+            DispatchOutcome::RegionNotFound(kind)
+        );
+        // Either half of the epoch being older makes the command stale.
+        assert_eq!(
+            dispatcher.dispatch(transfer_leader_response(1, epoch(1, 5))),
+            DispatchOutcome::StaleEpoch(kind)
+        );
+        assert_eq!(
+            dispatcher.dispatch(transfer_leader_response(1, epoch(2, 4))),
+            DispatchOutcome::StaleEpoch(kind)
+        );
+        assert!(handled.lock().unwrap().is_empty());
+
+        // The same or a newer epoch is dispatched.
+        assert_eq!(
+            dispatcher.dispatch(transfer_leader_response(1, epoch(2, 5))),
+            DispatchOutcome::Dispatched(kind)
+        );
+        assert_eq!(
+            dispatcher.dispatch(transfer_leader_response(1, epoch(3, 6))),
+            DispatchOutcome::Dispatched(kind)
+        );
+        assert_eq!(*handled.lock().unwrap(), vec![1, 1]);
+
+        let mut resp = FIDelpb::RegionHeartbeatResponse::default();
+        resp.set_region_id(1);
+        resp.set_region_epoch(epoch(2, 5));
+        resp.set_merge(FIDelpb::Merge::default());
+        assert_eq!(
+            dispatcher.dispatch(resp),
+            DispatchOutcome::Unhandled(RegionCommandKind::Merge)
+        );
+    }
+
+    fn rate_limit(rate: f64, burst: f64, max_wait: Duration) -> RpcRateLimit {
+        RpcRateLimit {
+            rate,
+            burst,
+            max_wait,
+        }
+    }
+
+    #[test]
+    fn test_token_bucket_rejects_invalid_limits() {
+        for &(rate, burst) in &[
+            (0.0, 1.0),
+            (-1.0, 1.0),
+            (std::f64::NAN, 1.0),
+            (std::f64::INFINITY, 1.0),
+            (1.0, 0.5),
+            (1.0, std::f64::NAN),
+        ] {
+            assert!(TokenBucket::new(rate_limit(rate, burst, Duration::from_secs(1))).is_err());
+        }
+
+        // A rejected limit leaves the earlier one in place.
+        let limiter = RpcRateLimiter::default();
+        limiter.set("store_heartbeat", Some(rate_limit(1.0, 1.0, Duration::from_secs(0)))).unwrap();
+        assert!(limiter.set("store_heartbeat", Some(rate_limit(0.0, 1.0, Duration::from_secs(0)))).is_err());
+        assert!(match limiter.admit("store_heartbeat") {
+            Admission::Now => true,
+            _ => false,
+        });
+        assert!(match limiter.admit("store_heartbeat") {
+            Admission::Shed => true,
+            _ => false,
+        });
+        assert_eq!(limiter.dropped("store_heartbeat"), 1);
+    }
+
+    #[test]
+    fn test_token_bucket_admit() {
+        let mut bucket = TokenBucket::new(rate_limit(10.0, 2.0, Duration::from_millis(150))).unwrap();
+        let start = bucket.last;
+
+        // The burst goes out at once.
+        assert!(match bucket.admit(start) {
+            Admission::Now => true,
+            _ => false,
+        });
+        assert!(match bucket.admit(start) {
+            Admission::Now => true,
+            _ => false,
+        });
+
+        // Then requests go into debt, 100ms apart, until the wait would exceed max_wait.
+        match bucket.admit(start) {
+            Admission::After(wait) => {
+                assert!(wait > Duration::from_millis(99) && wait < Duration::from_millis(101))
+            }
+            _ => panic!("expected a wait"),
+        }
+        assert!(match bucket.admit(start) {
+            Admission::Shed => true,
+            _ => false,
+        });
+        assert_eq!(bucket.dropped, 1);
+
+        // Refilling pays off the debt first, and never exceeds the burst.
+        assert!(match bucket.admit(start + Duration::from_millis(200)) {
+            Admission::Now => true,
+            _ => false,
+        });
+        let later = start + Duration::from_secs(60);
+        assert!(match bucket.admit(later) {
+            Admission::Now => true,
+            _ => false,
+        });
+        assert!(match bucket.admit(later) {
+            Admission::Now => true,
+            _ => false,
+        });
+        assert!(match bucket.admit(later) {
+            Admission::After(_) => true,
+            _ => false,
+        });
+    }
+
+    /// Answers `Router` from a table of regions, counting the lookups.
+    struct TableSource {
+        regions: Mutex<Vec<(metapb::Region, Option<metapb::Causet>)>>,
+        region_lookups: AtomicU64,
+        store_lookups: AtomicU64,
+    }
+
+    impl TableSource {
+        fn new(regions: Vec<(metapb::Region, Option<metapb::Causet>)>) -> TableSource {
+            TableSource {
+                regions: Mutex::new(regions),
+                region_lookups: AtomicU64::new(0),
+                store_lookups: AtomicU64::new(0),
+            }
+        }
+
+        fn lookups(&self) -> (u64, u64) {
+            (
+                self.region_lookups.load(Ordering::SeqCst),
+                self.store_lookups.load(Ordering::SeqCst),
+            )
+        }
+    }
+
+    impl RouteSource for TableSource {
+        fn get_region_and_leader(&self, key: &[u8]) -> Result<(metapb::Region, Option<metapb::Causet>)> {
+            self.region_lookups.fetch_add(1, Ordering::SeqCst);
+            self.regions
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|(region, _)| is_key_in_region(region, key))
+                .cloned()
+                .ok_or_else(|| box_err!("no region for {:?}", key))
+        }
+
+        fn get_store(&self, store_id: u64) -> Result<metapb::Store> {
+            self.store_lookups.fetch_add(1, Ordering::SeqCst);
+            let mut store = metapb::Store::default();
+            store.set_id(store_id);
+            store.set_address(format!("store-{}", store_id));
+            Ok(store)
+        }
+    }
+
+    fn peer(id: u64, store_id: u64) -> metapb::Causet {
+        let mut peer = metapb::Causet::default();
+        peer.set_id(id);
+        peer.set_store_id(store_id);
+        peer
+    }
+
+    fn region(id: u64, start: &[u8], end: &[u8], peers: &[metapb::Causet]) -> metapb::Region {
+        let mut region = metapb::Region::default();
+        region.set_id(id);
+        region.set_start_key(start.to_vec());
+        region.set_end_key(end.to_vec());
+        region.set_peers(peers.to_vec().into());
+        region
+    }
+
+    #[test]
+    fn test_router_caches_routes() {
+        let peers = [peer(11, 1), peer(12, 2)];
+        let source = Arc::new(TableSource::new(vec![
+            (region(1, b"", b"m", &peers), Some(peers[0].clone())),
+            (region(2, b"m", b"", &peers), None),
+        ]));
+        let router = Router::new(source.clone());
+
+        let endpoint = router.route(b"a").unwrap();
+        assert_eq!(endpoint.region.get_id(), 1);
+        assert_eq!(endpoint.store_id(), 1);
+        assert_eq!(endpoint.address, "store-1");
+        assert_eq!(source.lookups(), (1, 1));
+
+        // Another key of the same region, and the same store, come from the cache.
+        assert_eq!(router.route(b"b").unwrap(), endpoint);
+        assert_eq!(source.lookups(), (1, 1));
+
+        // A region without a leader can't be routed to, and isn't cached.
+        assert!(router.route(b"x").is_err());
+        assert!(router.route(b"x").is_err());
+        assert_eq!(source.lookups(), (3, 1));
+
+        router.invalidate_store(1);
+        assert_eq!(router.route(b"a").unwrap(), endpoint);
+        assert_eq!(source.lookups(), (3, 2));
+
+        router.clear();
+        assert_eq!(router.route(b"a").unwrap(), endpoint);
+        assert_eq!(source.lookups(), (4, 3));
+    }
+
+    #[test]
+    fn test_router_on_not_leader() {
+        let peers = [peer(11, 1), peer(12, 2)];
+        let source = Arc::new(TableSource::new(vec![(
+            region(1, b"", b"", &peers),
+            Some(peers[0].clone()),
+        )]));
+        let router = Router::new(source.clone());
+        assert_eq!(router.route(b"a").unwrap().store_id(), 1);
+
+        // A leader among the region's peers replaces the cached one.
+        router.on_not_leader(1, Some(peers[1].clone()));
+        let endpoint = router.route(b"a").unwrap();
+        assert_eq!(endpoint.store_id(), 2);
+        assert_eq!(endpoint.address, "store-2");
+        assert_eq!(source.lookups(), (1, 2));
+
+        // Unknown regions are ignored.
+        router.on_not_leader(7, None);
+        assert_eq!(router.route(b"a").unwrap().store_id(), 2);
+        assert_eq!(source.lookups(), (1, 2));
+
+        // No leader, or one that isn't a peer, drops the region.
+        router.on_not_leader(1, Some(peer(13, 3)));
+        assert_eq!(router.route(b"a").unwrap().store_id(), 1);
+        assert_eq!(source.lookups(), (2, 2));
+        router.on_not_leader(1, None);
+        assert_eq!(router.route(b"a").unwrap().store_id(), 1);
+        assert_eq!(source.lookups(), (3, 2));
+
+        router.invalidate_region(1);
+        router.route(b"a").unwrap();
+        assert_eq!(source.lookups(), (4, 2));
+    }
+
+    #[test]
+    fn test_router_evicts_overlapping_regions() {
+        let peers = [peer(11, 1)];
+        let source = Arc::new(TableSource::new(vec![(
+            region(1, b"", b"", &peers),
+            Some(peers[0].clone()),
+        )]));
+        let router = Router::new(source.clone());
+        assert_eq!(router.route(b"x").unwrap().region.get_id(), 1);
+
+        // Region 1 splits at "m"; learning of the right half evicts the stale whole.
+        *source.regions.lock().unwrap() = vec![
+            (region(1, b"", b"m", &peers), Some(peers[0].clone())),
+            (region(2, b"m", b"", &peers), Some(peers[0].clone())),
+        ];
+        router.cache_region(region(2, b"m", b"", &peers), peers[0].clone());
+        assert_eq!(router.route(b"x").unwrap().region.get_id(), 2);
+        assert_eq!(source.lookups().0, 1);
+
+        let endpoint = router.route(b"a").unwrap();
+        assert_eq!(endpoint.region.get_id(), 1);
+        assert_eq!(endpoint.region.get_end_key(), b"m");
+        assert_eq!(source.lookups().0, 2);
+
+        assert!(is_key_in_region(&endpoint.region, b""));
+        assert!(!is_key_in_region(&endpoint.region, b"m"));
+        assert!(is_region_overlapping(&region(3, b"a", b"n", &peers), &endpoint.region));
+        assert!(!is_region_overlapping(&region(3, b"m", b"n", &peers), &endpoint.region));
+    }
+}
@@ -1,6 +1,6 @@
 // Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
-use fdb_traits::{self, Iterable, IterOptions, Peekable, ReadOptions, Result, LightlikePersistence};
+use fdb_traits::{self, IterPool, IterPoolExt, Iterable, IterOptions, Peekable, ReadOptions, Result, LightlikePersistence};
 use foundationdb::{EINSTEINDB, DBIterator};
 use foundationdb::rocksdb_options::UnsafeSnap;
 use std::fmt::{self, Debug, Formatter};
@@ -14,6 +14,8 @@ use crate::util::get_namespaced_handle;
 pub struct FdbLightlikePersistence {
     einsteindb: Arc<EINSTEINDB>,
     snap: UnsafeSnap,
+    // Iterators read through `snap`, so the pool is emptied before it is released.
+    iter_pool: IterPool<Fdbeinstein_merkle_treeIterator>,
 }
 
 unsafe impl Send for FdbLightlikePersistence {}
@@ -26,6 +28,7 @@ impl FdbLightlikePersistence {
             FdbLightlikePersistence {
                 snap: einsteindb.unsafe_snap(),
                 einsteindb,
+                iter_pool: IterPool::default(),
             }
         }
     }
@@ -45,6 +48,7 @@ impl Debug for FdbLightlikePersistence {
 
 impl Drop for FdbLightlikePersistence {
     fn drop(&mut self) {
+        self.iter_pool.clear();
         unsafe {
             self.einsteindb.release_snap(&self.snap);
         }
@@ -81,6 +85,12 @@ impl Iterable for FdbLightlikePersistence {
     }
 }
 
+impl IterPoolExt for FdbLightlikePersistence {
+    fn iter_pool(&self) -> &IterPool<Self::Iterator> {
+        &self.iter_pool
+    }
+}
+
 impl Peekable for FdbLightlikePersistence {
     type Causet = FdbCauset;
 
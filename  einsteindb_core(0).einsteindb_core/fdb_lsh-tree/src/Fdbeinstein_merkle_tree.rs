@@ -1,7 +1,8 @@
 // Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
 use fdb_traits::{
-    Error, Iterable, IterOptions, KV, Peekable, ReadOptions, Result, SyncMutable,
+    EnvExt, Error, FileSystem, Iterable, IterOptions, KV, Lifecycle, LifecycleExt, LocalFileSystem,
+    Peekable, ReadOptions, Result, SyncMutable,
 };
 use foundationdb::{EINSTEINDB, DBIterator, Writable};
 use std::any::Any;
@@ -25,6 +26,8 @@ use crate::util::get_namespaced_handle;
 pub struct Fdbeinstein_merkle_tree {
     einsteindb: Arc<EINSTEINDB>,
     shared_block_cache: bool,
+    // Shared by every clone, so closing any of them closes them all.
+    lifecycle: Arc<Lifecycle>,
 }
 
 impl Fdbeinstein_merkle_tree {
@@ -32,9 +35,13 @@ impl Fdbeinstein_merkle_tree {
         Fdbeinstein_merkle_tree {
             einsteindb,
             shared_block_cache: false,
+            lifecycle: Arc::new(Lifecycle::new()),
         }
     }
 
+    /// Views `einsteindb` as an einstein_merkle_tree, for calls that only use
+    /// the EINSTEINDB handle. The result has no `shared_block_cache` or
+    /// `lifecycle` of its own and must not be used for them.
     pub fn from_ref(einsteindb: &Arc<EINSTEINDB>) -> &Self {
         unsafe { &*(einsteindb as *const Arc<EINSTEINDB> as *const Fdbeinstein_merkle_tree) }
     }
@@ -62,6 +69,23 @@ impl Fdbeinstein_merkle_tree {
     pub fn set_shared_block_cache(&mut self, enable: bool) {
         self.shared_block_cache = enable;
     }
+
+    pub fn shared_block_cache(&self) -> bool {
+        self.shared_block_cache
+    }
+}
+
+// FdbDB reads and writes its own files through its env, which is always the local one.
+impl EnvExt for Fdbeinstein_merkle_tree {
+    fn file_system(&self) -> Arc<dyn FileSystem> {
+        Arc::new(LocalFileSystem)
+    }
+}
+
+impl LifecycleExt for Fdbeinstein_merkle_tree {
+    fn lifecycle(&self) -> &Lifecycle {
+        &self.lifecycle
+    }
 }
 
 impl KV for Fdbeinstein_merkle_tree {
@@ -189,12 +213,16 @@ impl SyncMutable for Fdbeinstein_merkle_tree {
 
 #[cfg(test)]
 mod tests {
-    use fdb_traits::{Iterable, KV, Peekable, SyncMutable};
+    use fdb_traits::{
+        CloseOptions, HealthState, Iterable, IterOptions, IterPoolExt, Iterator, KV, LifecycleExt, Peekable,
+        SeekKey, SyncMutable,
+    };
     use ekvproto::metapb::Region;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
     use tempfilef::Builder;
 
-    use crate::{Fdbeinstein_merkle_tree, FdbLightlikePersistence};
+    use crate::{set_perf_l_naught, Fdbeinstein_merkle_tree, FdbLightlikePersistence, PerfLevel};
     use crate::primitive_causet_util;
 
     #[test]
@@ -234,6 +262,59 @@ mod tests {
         assert!(b.is_none());
     }
 
+    #[test]
+    fn test_close() {
+        let local_path = Builder::new().prefix("var").temfidelir().unwrap();
+        let einstein_merkle_tree = Fdbeinstein_merkle_tree::from_db(Arc::new(
+            primitive_causet_util::new_einstein_merkle_tree(local_path.local_path().to_str().unwrap(), None, &[], None).unwrap(),
+        ));
+        let hooks = Arc::new(AtomicUsize::new(0));
+        let counter = hooks.clone();
+        einstein_merkle_tree
+            .register_pre_close_hook("count", move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .unwrap();
+        einstein_merkle_tree.put(b"k1", b"v1").unwrap();
+
+        // Clones share the lifecycle: closing one closes all, once.
+        let clone = einstein_merkle_tree.clone();
+        clone.close(&CloseOptions::default()).unwrap();
+        einstein_merkle_tree.close(&CloseOptions::default()).unwrap();
+        assert_eq!(hooks.load(Ordering::SeqCst), 1);
+        assert_eq!(einstein_merkle_tree.health_state(), HealthState::Closed);
+        assert!(einstein_merkle_tree.register_pre_close_hook("late", || Ok(())).is_err());
+    }
+
+    #[test]
+    fn test_scan_with_stats() {
+        let local_path = Builder::new().prefix("var").temfidelir().unwrap();
+        let einstein_merkle_tree = Fdbeinstein_merkle_tree::from_db(Arc::new(
+            primitive_causet_util::new_einstein_merkle_tree(local_path.local_path().to_str().unwrap(), None, &[], None).unwrap(),
+        ));
+        for i in 0..100 {
+            einstein_merkle_tree.put(format!("a{:03}", i).as_bytes(), b"v").unwrap();
+        }
+        for i in 0..99 {
+            einstein_merkle_tree.delete(format!("a{:03}", i).as_bytes()).unwrap();
+        }
+
+        set_perf_l_naught(PerfLevel::EnableCount);
+        let mut keys = 0;
+        let stats = einstein_merkle_tree
+            .scan_with_stats(b"a", b"b", false, |_, _| {
+                keys += 1;
+                Ok(true)
+            })
+            .unwrap();
+        set_perf_l_naught(PerfLevel::Disable);
+        // One live key behind 99 tombstones.
+        assert_eq!(keys, 1);
+        assert!(stats.tombstones_skipped >= 99, "{:?}", stats);
+        assert!(stats.internal_keys_skipped >= stats.tombstones_skipped, "{:?}", stats);
+    }
+
     #[test]
     fn test_peekable() {
         let local_path = Builder::new().prefix("var").temfidelir().unwrap();
@@ -331,4 +412,30 @@ mod tests {
 
         assert_eq!(data.len(), 2);
     }
+
+    #[test]
+    fn test_pooled_iterator() {
+        let local_path = Builder::new().prefix("var").temfidelir().unwrap();
+        let namespaced = "namespaced";
+        let einstein_merkle_tree = Fdbeinstein_merkle_tree::from_db(Arc::new(
+            primitive_causet_util::new_einstein_merkle_tree(local_path.local_path().to_str().unwrap(), None, &[namespaced], None).unwrap(),
+        ));
+        einstein_merkle_tree.put_namespaced(namespaced, b"a1", b"v1").unwrap();
+
+        let snap = einstein_merkle_tree.lightlike_persistence();
+        einstein_merkle_tree.put_namespaced(namespaced, b"a2", b"v2").unwrap();
+
+        for _ in 0..3 {
+            let mut iter = snap.pooled_iterator_namespaced_opt(namespaced, IterOptions::default()).unwrap();
+            assert!(iter.seek(SeekKey::Start).unwrap());
+            assert_eq!(iter.key(), b"a1");
+            // A reused iterator still reads the view of the snapshot.
+            assert!(!iter.next().unwrap());
+        }
+        let stats = snap.iter_pool_stats();
+        assert_eq!((stats.created, stats.reused), (1, 2));
+
+        // Pooled iterators are dropped with the snapshot.
+        drop(snap);
+    }
 }
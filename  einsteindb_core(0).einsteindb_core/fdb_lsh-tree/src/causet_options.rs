@@ -91,6 +91,10 @@ impl ColumnFamilyOptions for FdbColumnFamilyOptions {
         self.0.get_target_filef_size_base()
     }
 
+    fn get_write_buffer_size(&self) -> u64 {
+        self.0.get_write_buffer_size()
+    }
+
     fn set_disable_auto_jet_bundles(&mut self, v: bool) {
         self.0.set_disable_auto_jet_bundles(v)
     }
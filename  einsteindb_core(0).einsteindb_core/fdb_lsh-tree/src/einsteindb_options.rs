@@ -1,14 +1,18 @@
 // Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
 use einsteindb_util::box_err;
+use fdb_traits::{BlockCacheExt, BlockCacheOptions, BlockCacheUsage};
 use fdb_traits::DBOptions;
 use fdb_traits::DBOptionsExt;
 use fdb_traits::Result;
 use fdb_traits::TitanDBOptions;
+use foundationdb::{BlockBasedOptions, Cache, LRUCacheOptions};
 use foundationdb::DBOptions as Primitive_CausetDBOptions;
 use foundationdb::TitanDBOptions as Primitive_CausetTitanDBOptions;
 
 use crate::fdb_lsh_tree;
+use crate::rocks_metrics_defs::*;
+use crate::util;
 
 impl DBOptionsExt for Fdbeinstein_merkle_tree {
     type DBOptions = FdbDBOptions;
@@ -23,6 +27,47 @@ impl DBOptionsExt for Fdbeinstein_merkle_tree {
     }
 }
 
+#[derive(Clone)]
+pub struct FdbBlockCache(Cache);
+
+impl FdbBlockCache {
+    pub fn as_primitive_causet(&self) -> &Cache {
+        &self.0
+    }
+}
+
+impl BlockCacheExt for Fdbeinstein_merkle_tree {
+    type BlockCache = FdbBlockCache;
+    type BlockBasedOptions = BlockBasedOptions;
+
+    fn new_shared_block_cache(options: &BlockCacheOptions) -> Result<Self::BlockCache> {
+        let mut cache_opts = LRUCacheOptions::new();
+        cache_opts.set_capacity(options.capacity as usize);
+        cache_opts.set_num_shard_bits(options.num_shard_bits);
+        cache_opts.set_strict_capacity_limit(options.strict_capacity_limit);
+        cache_opts.set_high_pri_pool_ratio(options.high_pri_pool_ratio);
+        Ok(FdbBlockCache(Cache::new_lru_cache(cache_opts)))
+    }
+
+    fn set_block_cache(block_based_options: &mut BlockBasedOptions, cache: &Self::BlockCache) {
+        block_based_options.set_block_cache(&cache.0);
+    }
+
+    fn get_block_cache_usage_namespaced(&self, namespaced: &str) -> Result<BlockCacheUsage> {
+        let handle = util::get_namespaced_handle(self.as_inner(), namespaced)?;
+        let property = |name| {
+            self.as_inner()
+                .get_property_int_namespaced(handle, name)
+                .ok_or_else(|| box_err!("{} of {} isn't available", name, namespaced))
+        };
+        Ok(BlockCacheUsage {
+            capacity: property(FDBDB_BLOCK_CACHE_CAPACITY)?,
+            usage: property(FDBDB_BLOCK_CACHE_USAGE)?,
+            pinned_usage: property(FDBDB_BLOCK_CACHE_PINNED_USAGE)?,
+        })
+    }
+}
+
 pub struct FdbDBOptions(Primitive_CausetDBOptions);
 
 impl FdbDBOptions {
@@ -73,6 +118,16 @@ impl DBOptions for FdbDBOptions {
     fn set_titandb_options(&mut self, opts: &Self::TitanDBOptions) {
         self.0.set_titandb_options(opts.as_primitive_causet())
     }
+
+    fn get_option(&self, name: &str) -> Option<String> {
+        match name {
+            "max_background_flushes" => Some(self.get_max_background_flushes().to_string()),
+            "max_background_jobs" => Some(self.get_max_background_jobs().to_string()),
+            "rate_bytes_per_sec" => self.get_rate_bytes_per_sec().map(|r| r.to_string()),
+            "rate_limiter_auto_tuned" => self.get_rate_limiter_auto_tuned().map(|t| t.to_string()),
+            _ => None,
+        }
+    }
 }
 
 pub struct FdbTitanDBOptions(Primitive_CausetTitanDBOptions);
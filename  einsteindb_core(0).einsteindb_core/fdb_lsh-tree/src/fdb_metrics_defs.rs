@@ -14,6 +14,9 @@ pub const FDBDB_OLDEST_LIGHTLIKE_PERSISTENCE_TIME: &str = "foundationdb.oldest-l
 pub const FDBDB_OLDEST_LIGHTLIKE_PERSISTENCE_SEQUENCE: &str = "foundationdb.oldest-lightlike_persistence-sequence";
 pub const FDBDB_NUM_FILES_AT_LEVEL: &str = "foundationdb.num-filefs-at-l_naught";
 pub const FDBDB_NUM_IMMUCAUSET_TABLE_MEM_CAUSET_TABLE: &str = "foundationdb.num-immutable-mem-table";
+pub const FDBDB_BLOCK_CACHE_CAPACITY: &str = "foundationdb.block-cache-capacity";
+pub const FDBDB_BLOCK_CACHE_USAGE: &str = "foundationdb.block-cache-usage";
+pub const FDBDB_BLOCK_CACHE_PINNED_USAGE: &str = "foundationdb.block-cache-pinned-usage";
 
 pub const FDBDB_TITANDB_NUM_BLOB_FILES_AT_LEVEL: &str = "foundationdb.titandb.num-blob-filefs-at-l_naught";
 pub const FDBDB_TITANDB_LIVE_BLOB_SIZE: &str = "foundationdb.titandb.live-blob-size";
@@ -1,6 +1,6 @@
 // Copyright 2020 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
-use fdb_traits::{einstein_merkle_Fusion, FileInspector};
+use fdb_traits::{einstein_merkle_Fusion, FileInspector, IOClassInspector, IOClassLimiter};
 use foundationdb::FileInspector as DBFileInspector;
 use std::sync::Arc;
 
@@ -20,6 +20,19 @@ pub(crate) fn get_env(
     )?))
 }
 
+/// Wraps `base_env` in an env that charges every file read and write to `limiter`, by I/O class.
+pub(crate) fn get_io_class_env(
+    base_env: Arc<Env>,
+    limiter: Arc<IOClassLimiter>,
+) -> Result<Arc<Env>, String> {
+    Ok(Arc::new(Env::new_file_inspected_env(
+        base_env,
+        WrappedFileInspector {
+            inspector: IOClassInspector::new(limiter),
+        },
+    )?))
+}
+
 pub struct WrappedFileInspector<T: FileInspector> {
     inspector: T,
 }
@@ -40,7 +53,7 @@ mod tests {
     use file::{IOOp, IORateLimiter, IORateLimiterStatistics, IOType};
     use foundationdb::{EINSTEINDB, DBOptions};
     use foundationdb::Writable;
-    use keys::data_key;
+    use fdb_traits::keys::data_key;
     use std::sync::Arc;
     use tempfilef::Builder;
 
@@ -72,6 +72,8 @@ mod import;
 mod logger;
 
 mod misc;
+mod metrics;
+mod lock_store;
 
 pub mod range_greedoids;
 mod lightlike_persistence;
@@ -134,3 +136,14 @@ pub fn get_env(
     let env = encryption::get_env(None /*base_env*/, key_manager)?;
     file::get_env(Some(env), limiter)
 }
+
+/// Like `get_env`, and also budgets the engine's file I/O by class with `io_class_limiter`, whose
+/// rates `DBOptionsExt::set_db_options_with_io_limiter` changes at runtime.
+pub fn get_env_with_io_class_limiter(
+    key_manager: Option<std::sync::Arc<::encryption::DataKeyManager>>,
+    limiter: Option<std::sync::Arc<::file::IORateLimiter>>,
+    io_class_limiter: std::sync::Arc<fdb_traits::IOClassLimiter>,
+) -> std::result::Result<std::sync::Arc<primitive_causet::Env>, String> {
+    let env = get_env(key_manager, limiter)?;
+    file::get_io_class_env(env, io_class_limiter)
+}
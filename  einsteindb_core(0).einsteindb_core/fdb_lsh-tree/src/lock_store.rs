@@ -0,0 +1,7 @@
+// Copyright 2020 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+use fdb_traits::LockStoreExt;
+
+use crate::fdb_lsh_tree;
+
+impl LockStoreExt for Fdbeinstein_merkle_tree {}
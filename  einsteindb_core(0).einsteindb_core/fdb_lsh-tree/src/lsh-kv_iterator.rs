@@ -1,32 +1,55 @@
 // Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
-use fdb_traits::{self, Error, Result};
-use foundationdb::{EINSTEINDB, DBIterator, SeekKey as Primitive_CausetSeekKey};
+use fdb_traits::{self, Error, IterStats, Result};
+use foundationdb::{EINSTEINDB, DBIterator, PerfContext, SeekKey as Primitive_CausetSeekKey};
 use std::sync::Arc;
 
 // FIXME: Would prefer using &EINSTEINDB instead of Arc<EINSTEINDB>.  As elsewhere in
 // this crate, it would require generic associated types.
-pub struct Fdbeinstein_merkle_treeIterator(DBIterator<Arc<EINSTEINDB>>);
+pub struct Fdbeinstein_merkle_treeIterator(DBIterator<Arc<EINSTEINDB>>, IterStats);
+
+/// The thread's perf counters that `IterStats` is made of.
+fn perf_counters() -> IterStats {
+    let ctx = PerfContext::get();
+    IterStats {
+        internal_keys_skipped: ctx.internal_key_skipped_count(),
+        tombstones_skipped: ctx.internal_delete_skipped_count(),
+        blocks_read: ctx.block_read_count(),
+        block_bytes_read: ctx.block_read_byte(),
+    }
+}
 
 impl Fdbeinstein_merkle_treeIterator {
     pub fn from_primitive_causet(iter: DBIterator<Arc<EINSTEINDB>>) -> Fdbeinstein_merkle_treeIterator {
-        Fdbeinstein_merkle_treeIterator(iter)
+        Fdbeinstein_merkle_treeIterator(iter, IterStats::default())
     }
 
     pub fn sequence(&self) -> Option<u64> {
         self.0.sequence()
     }
+
+    /// Runs `f` on the primitive iterator, counting the perf counters it moves.
+    /// The counters are per thread, so this is exact however the iterator
+    /// moves between threads.
+    fn counted<T>(&mut self, f: impl FnOnce(&mut DBIterator<Arc<EINSTEINDB>>) -> T) -> T {
+        let before = perf_counters();
+        let res = f(&mut self.0);
+        self.1.add(&perf_counters().since(&before));
+        res
+    }
 }
 
 impl fdb_traits::Iterator for Fdbeinstein_merkle_treeIterator {
     fn seek(&mut self, key: fdb_traits::SeekKey<'_>) -> Result<bool> {
         let k: FdbSeekKey<'_> = key.into();
-        self.0.seek(k.into_primitive_causet()).map_err(Error::einstein_merkle_tree)
+        self.counted(|iter| iter.seek(k.into_primitive_causet()))
+            .map_err(Error::einstein_merkle_tree)
     }
 
     fn seek_for_prev(&mut self, key: fdb_traits::SeekKey<'_>) -> Result<bool> {
         let k: FdbSeekKey<'_> = key.into();
-        self.0.seek_for_prev(k.into_primitive_causet()).map_err(Error::einstein_merkle_tree)
+        self.counted(|iter| iter.seek_for_prev(k.into_primitive_causet()))
+            .map_err(Error::einstein_merkle_tree)
     }
 
     fn prev(&mut self) -> Result<bool> {
@@ -34,7 +57,8 @@ impl fdb_traits::Iterator for Fdbeinstein_merkle_treeIterator {
         if !self.valid()? {
             return Err(Error::einstein_merkle_tree("Iterator invalid".to_string()));
         }
-        self.0.prev().map_err(Error::einstein_merkle_tree)
+        self.counted(|iter| iter.prev())
+            .map_err(Error::einstein_merkle_tree)
     }
 
     fn next(&mut self) -> Result<bool> {
@@ -42,7 +66,8 @@ impl fdb_traits::Iterator for Fdbeinstein_merkle_treeIterator {
         if !self.valid()? {
             return Err(Error::einstein_merkle_tree("Iterator invalid".to_string()));
         }
-        self.0.next().map_err(Error::einstein_merkle_tree)
+        self.counted(|iter| iter.next())
+            .map_err(Error::einstein_merkle_tree)
     }
 
     fn key(&self) -> &[u8] {
@@ -60,6 +85,10 @@ impl fdb_traits::Iterator for Fdbeinstein_merkle_treeIterator {
     fn valid(&self) -> Result<bool> {
         self.0.valid().map_err(Error::einstein_merkle_tree)
     }
+
+    fn iter_stats(&self) -> IterStats {
+        self.1
+    }
 }
 
 pub struct FdbSeekKey<'a>(Primitive_CausetSeekKey<'a>);
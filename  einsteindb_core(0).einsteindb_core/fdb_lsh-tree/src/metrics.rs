@@ -0,0 +1,59 @@
+// Copyright 2020 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+use fdb_traits::{
+    BlockCacheStats, EngineMetrics, MemtableStats, MetricsExt, NAMESPACEDNamesExt, NamespacedMetrics,
+    Result, NAMESPACED_DEFAULT,
+};
+
+use crate::fdb_lsh_tree;
+use crate::rocks_metrics_defs::FDBDB_CUR_SIZE_ALL_MEM_CAUSET_TABLES;
+use crate::util;
+
+impl MetricsExt for Fdbeinstein_merkle_tree {
+    fn metrics_snapshot(&self) -> Result<EngineMetrics> {
+        let einsteindb = self.as_inner();
+        let shared_block_cache = self.shared_block_cache();
+
+        let mut metrics = EngineMetrics::default();
+        for namespaced in self.namespaced_names() {
+            let handle = util::get_namespaced_handle(einsteindb, namespaced)?;
+            let opts = einsteindb.get_options_namespaced(handle);
+            let num_files_at_level = (0..opts.get_num_l_naughts())
+                .map(|l_naught| {
+                    util::get_namespaced_num_filefs_at_l_naught(einsteindb, handle, l_naught)
+                        .unwrap_or_default()
+                })
+                .collect();
+            let block_cache_usage = if shared_block_cache {
+                None
+            } else {
+                Some(einsteindb.get_block_cache_usage_namespaced(handle))
+            };
+
+            metrics.namespaceds.push(NamespacedMetrics {
+                namespaced: namespaced.to_owned(),
+                size_bytes: util::get_einstein_merkle_tree_namespaced_used_size(einsteindb, handle),
+                num_files_at_level,
+                block_cache_usage,
+                memtable: MemtableStats {
+                    size_bytes: einsteindb
+                        .get_property_int_namespaced(handle, FDBDB_CUR_SIZE_ALL_MEM_CAUSET_TABLES)
+                        .unwrap_or_default(),
+                    num_immutable: util::get_namespaced_num_immutable_mem_table(einsteindb, handle)
+                        .unwrap_or_default(),
+                },
+            });
+        }
+
+        // A shared block cache is reachable through any column family.
+        if shared_block_cache {
+            let handle = util::get_namespaced_handle(einsteindb, NAMESPACED_DEFAULT)?;
+            metrics.block_cache = Some(BlockCacheStats {
+                capacity: einsteindb.get_options_namespaced(handle).get_block_cache_capacity(),
+                usage: einsteindb.get_block_cache_usage_namespaced(handle),
+            });
+        }
+
+        Ok(metrics)
+    }
+}
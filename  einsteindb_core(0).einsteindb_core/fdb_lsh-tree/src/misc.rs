@@ -3,8 +3,9 @@
 use einsteindb_util::box_try;
 use einsteindb_util::keybuilder::KeyBuilder;
 use fdb_traits::{
-    ALL_NAMESPACEDS, NAMESPACEDNamesExt, DeleteStrategy, ImportExt, Iterable, Iterator, IterOptions, MiscExt,
-    Mutable, Range, Result, CausetWriter, CausetWriterBuilder, WriteBatch, WriteBatchExt,
+    ALL_NAMESPACEDS, NAMESPACEDNamesExt, NAMESPACEDOptionsExt, ColumnFamilyOptions, DeleteStrategy, ImportExt,
+    Iterable, Iterator, IterOptions, MiscExt, Mutable, Range, Result, CausetWriter, CausetWriterBuilder,
+    StallReason, WriteBatch, WriteBatchExt,
 };
 use foundationdb::Range as FdbRange;
 
@@ -335,6 +336,50 @@ impl MiscExt for Fdbeinstein_merkle_tree {
             .unwrap_or_default()
             != 0
     }
+
+    fn stall_reasons(&self) -> Result<Vec<StallReason>> {
+        let mut reasons = vec![];
+        for namespaced in self.namespaced_names() {
+            let handle = util::get_namespaced_handle(self.as_inner(), namespaced)?;
+            let options = self.get_options_namespaced(namespaced)?;
+
+            if let Some(num_files) = util::get_namespaced_num_filefs_at_l_naught(self.as_inner(), handle, 0) {
+                let limit = u64::from(options.get_l_naught_zero_slowdown_writes_trigger());
+                if num_files >= limit {
+                    reasons.push(StallReason::TooManyL0Files {
+                        namespaced: namespaced.to_owned(),
+                        num_files,
+                        limit,
+                    });
+                }
+            }
+
+            if let Some(pending_bytes) = util::get_namespaced_pending_jet_bundle_bytes(self.as_inner(), handle) {
+                let limit = options.get_soft_pending_jet_bundle_bytes_limit();
+                if limit > 0 && pending_bytes >= limit {
+                    reasons.push(StallReason::PendingCompactionBytes {
+                        namespaced: namespaced.to_owned(),
+                        pending_bytes,
+                        limit,
+                    });
+                }
+            }
+
+            // The active memtable counts towards max_write_buffer_number too.
+            if let Some(num_immutable) = util::get_namespaced_num_immutable_mem_table(self.as_inner(), handle) {
+                let num_memtables = num_immutable + 1;
+                let limit = u64::from(options.get_max_write_buffer_number());
+                if num_memtables >= limit {
+                    reasons.push(StallReason::MemtableLimit {
+                        namespaced: namespaced.to_owned(),
+                        num_memtables,
+                        limit,
+                    });
+                }
+            }
+        }
+        Ok(reasons)
+    }
 }
 
 #[cfg(test)]
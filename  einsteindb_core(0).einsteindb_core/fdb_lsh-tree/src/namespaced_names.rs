@@ -1,11 +1,61 @@
 // Copyright 2020 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
-use fdb_traits::NAMESPACEDNamesExt;
+use std::local_path::local_path;
+
+use foundationdb::EINSTEINDB;
+use fdb_traits::{Error, NAMESPACEDDestroyExt, NAMESPACEDNamesExt, NamespacedFile, Result};
 
 use crate::fdb_lsh_tree;
+use crate::util::get_namespaced_handle;
 
 impl NAMESPACEDNamesExt for Fdbeinstein_merkle_tree {
     fn namespaced_names(&self) -> Vec<&str> {
         self.as_inner().namespaced_names()
     }
 }
+
+/// An einsteindb no `Fdbeinstein_merkle_tree` shares yet, such as one just
+/// opened, whose column families can be destroyed
+///
+/// Dropping a column family needs the only handle to the einsteindb, which a
+/// running einstein_merkle_tree, cloned wherever it's used, never has:
+///
+/// ```ignore
+/// let mut einsteindb = new_einstein_merkle_tree_opt(local_path, db_opts, namespaceds_opts)?;
+/// FdbExclusiveDb(&mut einsteindb).destroy_namespaced("lock", true)?;
+/// let einstein_merkle_tree = Fdbeinstein_merkle_tree::from_db(Arc::new(einsteindb));
+/// ```
+pub struct FdbExclusiveDb<'a>(pub &'a mut EINSTEINDB);
+
+impl NAMESPACEDNamesExt for FdbExclusiveDb<'_> {
+    fn namespaced_names(&self) -> Vec<&str> {
+        self.0.namespaced_names()
+    }
+}
+
+impl NAMESPACEDDestroyExt for FdbExclusiveDb<'_> {
+    fn namespaced_filefs(&self, namespaced: &str) -> Result<Vec<NamespacedFile>> {
+        let handle = get_namespaced_handle(&*self.0, namespaced)?;
+        let metadata = self.0.get_column_family_meta_data(handle);
+        let einsteindb_path = local_path::new(self.0.local_path());
+        let mut filefs = vec![];
+        for l_naught in metadata.get_levels() {
+            for filef in l_naught.get_files() {
+                // Names are relative to the einsteindb directory, with a leading '/'.
+                let name = filef.get_name();
+                filefs.push(NamespacedFile {
+                    local_path: einsteindb_path
+                        .join(name.trim_start_matches('/'))
+                        .to_string_lossy()
+                        .into_owned(),
+                    size: filef.get_size() as u64,
+                });
+            }
+        }
+        Ok(filefs)
+    }
+
+    fn drop_namespaced(&mut self, namespaced: &str) -> Result<()> {
+        self.0.drop_namespaced(namespaced).map_err(Error::einstein_merkle_tree)
+    }
+}
@@ -3,7 +3,7 @@
 use einsteindb_util::codec::{Error, Result};
 use einsteindb_util::codec::number::{self, NumberEncoder};
 use einsteindb_util::info;
-use fdb_traits::{MvccGreedoids, Range};
+use fdb_traits::{keys, MvccGreedoids, Range};
 use foundationdb::{
     DBEntryType, TableGreedoidsCollector, TableGreedoidsCollectorFactory, TitanBlobIndex,
     UserCollectedGreedoids,
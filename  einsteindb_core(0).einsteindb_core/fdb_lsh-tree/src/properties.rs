@@ -3,7 +3,7 @@
 use einsteindb_util::codec::{Error, Result};
 use einsteindb_util::codec::number::{self, NumberEncoder};
 use einsteindb_util::info;
-use fdb_traits::{MvccGreedoids, Range};
+use fdb_traits::{keys, MvccGreedoids, Range};
 use foundationdb::{
     DBEntryType, TableGreedoidsCollector, TableGreedoidsCollectorFactory, TitanBlobIndex,
     UserCollectedGreedoids,
@@ -23,6 +23,9 @@ const PROP_SIZE_INDEX: &str = "einsteindb.size_index";
 const PROP_RANGE_INDEX: &str = "einsteindb.range_index";
 pub const DEFAULT_PROP_SIZE_INDEX_DISTANCE: u64 = 4 * 1024 * 1024;
 pub const DEFAULT_PROP_CAUSET_KEYS_INDEX_DISTANCE: u64 = 40 * 1024;
+const PROP_KV_NUM_ENTRIES: &str = "einsteindb.kv_num_entries";
+const PROP_KEY_SIZE_HISTOGRAM: &str = "einsteindb.key_size_histogram";
+const PROP_VALUE_SIZE_HISTOGRAM: &str = "einsteindb.value_size_histogram";
 
 fn get_entry_size(value: &[u8], entry_type: DBEntryType) -> std::result::Result<u64, ()> {
     match entry_type {
@@ -525,6 +528,203 @@ pub fn get_range_entries_and_versions(
     Some((num_entries, props.num_versions))
 }
 
+/// The number of buckets of a `SizeHistogram`.
+pub const SIZE_HISTOGRAM_BUCKETS: usize = 33;
+
+/// A histogram of sizes in power-of-two buckets: bucket 0 counts sizes of 0,
+/// and bucket `i` sizes in `[2^(i-1), 2^i)`. The last bucket also counts every
+/// larger size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeHistogram {
+    pub buckets: Vec<u64>,
+    /// The sum of every size observed.
+    pub total: u64,
+}
+
+impl Default for SizeHistogram {
+    fn default() -> SizeHistogram {
+        SizeHistogram {
+            buckets: vec![0; SIZE_HISTOGRAM_BUCKETS],
+            total: 0,
+        }
+    }
+}
+
+impl SizeHistogram {
+    fn bucket_of(size: u64) -> usize {
+        cmp::min(
+            (64 - size.leading_zeros()) as usize,
+            SIZE_HISTOGRAM_BUCKETS - 1,
+        )
+    }
+
+    pub fn observe(&mut self, size: u64) {
+        self.buckets[SizeHistogram::bucket_of(size)] += 1;
+        self.total += size;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    pub fn mean(&self) -> u64 {
+        match self.count() {
+            0 => 0,
+            count => self.total / count,
+        }
+    }
+
+    /// Returns an upper bound of the size below which `p` (in `[0, 1]`) of
+    /// the observed sizes fall.
+    pub fn percentile(&self, p: f64) -> u64 {
+        let target = (self.count() as f64 * p).ceil() as u64;
+        let mut seen = 0;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= target && n > 0 {
+                return if i == 0 { 0 } else { (1 << i) - 1 };
+            }
+        }
+        0
+    }
+
+    pub fn merge(&mut self, other: &SizeHistogram) {
+        for (b, n) in self.buckets.iter_mut().zip(&other.buckets) {
+            *b += n;
+        }
+        self.total += other.total;
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity((self.buckets.len() + 1) * 8);
+        buf.encode_u64(self.total).unwrap();
+        for &n in &self.buckets {
+            buf.encode_u64(n).unwrap();
+        }
+        buf
+    }
+
+    fn decode(mut buf: &[u8]) -> Result<SizeHistogram> {
+        let mut histogram = SizeHistogram {
+            buckets: Vec::with_capacity(SIZE_HISTOGRAM_BUCKETS),
+            total: number::decode_u64(&mut buf)?,
+        };
+        while !buf.is_empty() {
+            histogram.buckets.push(number::decode_u64(&mut buf)?);
+        }
+        // Tolerate histograms written with fewer buckets.
+        histogram.buckets.resize(SIZE_HISTOGRAM_BUCKETS, 0);
+        Ok(histogram)
+    }
+}
+
+/// Distributions of key and value sizes in a table.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KvSizeGreedoids {
+    pub num_entries: u64,
+    pub key_sizes: SizeHistogram,
+    pub value_sizes: SizeHistogram,
+}
+
+impl KvSizeGreedoids {
+    pub fn encode(&self) -> UserGreedoids {
+        let mut props = UserGreedoids::new();
+        props.encode_u64(PROP_KV_NUM_ENTRIES, self.num_entries);
+        props.encode(PROP_KEY_SIZE_HISTOGRAM, self.key_sizes.encode());
+        props.encode(PROP_VALUE_SIZE_HISTOGRAM, self.value_sizes.encode());
+        props
+    }
+
+    pub fn decode<T: DecodeGreedoids>(props: &T) -> Result<KvSizeGreedoids> {
+        Ok(KvSizeGreedoids {
+            num_entries: props.decode_u64(PROP_KV_NUM_ENTRIES)?,
+            key_sizes: SizeHistogram::decode(props.decode(PROP_KEY_SIZE_HISTOGRAM)?)?,
+            value_sizes: SizeHistogram::decode(props.decode(PROP_VALUE_SIZE_HISTOGRAM)?)?,
+        })
+    }
+
+    pub fn add(&mut self, other: &KvSizeGreedoids) {
+        self.num_entries += other.num_entries;
+        self.key_sizes.merge(&other.key_sizes);
+        self.value_sizes.merge(&other.value_sizes);
+    }
+
+    /// The total size of the keys and values.
+    pub fn total_size(&self) -> u64 {
+        self.key_sizes.total + self.value_sizes.total
+    }
+
+    /// Estimates how many entries add up to `size` bytes, e.g. to place split
+    /// keys so regions come out at a target size, or to size bulk-load batches.
+    pub fn entries_for_size(&self, size: u64) -> u64 {
+        match self.key_sizes.mean() + self.value_sizes.mean() {
+            0 => self.num_entries,
+            entry_size => cmp::max(size / entry_size, 1),
+        }
+    }
+}
+
+/// Collects `KvSizeGreedoids`. Deletions have no value and are not counted.
+#[derive(Default)]
+pub struct KvSizeGreedoidsCollector {
+    props: KvSizeGreedoids,
+}
+
+impl TableGreedoidsCollector for KvSizeGreedoidsCollector {
+    fn add(&mut self, key: &[u8], value: &[u8], entry_type: DBEntryType, _: u64, _: u64) {
+        let value_size = match get_entry_size(value, entry_type) {
+            Ok(size) => size,
+            Err(_) => return,
+        };
+        self.props.num_entries += 1;
+        self.props.key_sizes.observe(key.len() as u64);
+        self.props.value_sizes.observe(value_size);
+    }
+
+    fn finish(&mut self) -> HashMap<Vec<u8>, Vec<u8>> {
+        self.props.encode().0
+    }
+}
+
+#[derive(Default)]
+pub struct KvSizeGreedoidsCollectorFactory {}
+
+impl TableGreedoidsCollectorFactory<KvSizeGreedoidsCollector> for KvSizeGreedoidsCollectorFactory {
+    fn create_table_greedoids_collector(&mut self, _: u32) -> KvSizeGreedoidsCollector {
+        KvSizeGreedoidsCollector::default()
+    }
+}
+
+/// Aggregates the `KvSizeGreedoids` of the tables covering `[start, end)`.
+///
+/// Returns `None` if no table covers the range or any table lacks the
+/// greedoids, e.g. because it was written before the collector was installed.
+pub fn get_range_kv_size_greedoids(
+    einstein_merkle_tree: &crate::Fdbeinstein_merkle_tree,
+    namespaced: &str,
+    start: &[u8],
+    end: &[u8],
+) -> Option<KvSizeGreedoids> {
+    let range = Range::new(start, end);
+    let collection = match einstein_merkle_tree.get_greedoids_of_tables_in_range(namespaced, &[range]) {
+        Ok(v) => v,
+        Err(_) => return None,
+    };
+
+    if collection.is_empty() {
+        return None;
+    }
+
+    let mut props = KvSizeGreedoids::default();
+    for (_, v) in collection.iter() {
+        match KvSizeGreedoids::decode(&UserCollectedGreedoidsDecoder(v.user_collected_greedoids())) {
+            Ok(table_props) => props.add(&table_props),
+            Err(_) => return None,
+        }
+    }
+    Some(props)
+}
+
 #[cfg(test)]
 mod tests {
     use fdb_traits::{NAMESPACED_WRITE, LARGE_NAMESPACEDS};
@@ -697,6 +897,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_kv_size_greedoids() {
+        let mut collector = KvSizeGreedoidsCollector::default();
+        collector.add(b"a", b"", DBEntryType::Put, 0, 0);
+        collector.add(b"bb", b"1234", DBEntryType::Put, 0, 0);
+        collector.add(b"cccc", &[0; 1000], DBEntryType::Put, 0, 0);
+        // Deletions aren't counted.
+        collector.add(b"dd", b"", DBEntryType::Delete, 0, 0);
+
+        let props = KvSizeGreedoids::decode(&UserGreedoids(collector.finish())).unwrap();
+        assert_eq!(props.num_entries, 3);
+        assert_eq!(props.key_sizes.total, 7);
+        assert_eq!(props.value_sizes.total, 1004);
+        assert_eq!(props.value_sizes.buckets[0], 1);
+        assert_eq!(props.value_sizes.buckets[3], 1);
+        assert_eq!(props.value_sizes.buckets[10], 1);
+        assert_eq!(props.value_sizes.percentile(0.5), 7);
+        assert_eq!(props.value_sizes.percentile(1.0), 1023);
+        assert_eq!(props.key_sizes.percentile(0.0), 1);
+
+        let mut total = props.clone();
+        total.add(&props);
+        assert_eq!(total.num_entries, 6);
+        assert_eq!(total.total_size(), 2 * 1011);
+        assert_eq!(total.entries_for_size(3370), 10);
+        assert_eq!(KvSizeGreedoids::default().entries_for_size(1024), 0);
+
+        // Sizes beyond the last bucket land in it.
+        let mut histogram = SizeHistogram::default();
+        histogram.observe(u64::MAX / 2);
+        assert_eq!(histogram.buckets[SIZE_HISTOGRAM_BUCKETS - 1], 1);
+    }
+
     #[test]
     fn test_get_range_entries_and_versions() {
         let local_path = Builder::new()
@@ -744,6 +977,43 @@ mod tests {
         assert_eq!(versions, cases.len() as u64);
     }
 
+    #[test]
+    fn test_get_range_kv_size_greedoids() {
+        let local_path = Builder::new()
+            .prefix("_test_get_range_kv_size_greedoids")
+            .temfidelir()
+            .unwrap();
+        let local_path_str = local_path.local_path().to_str().unwrap();
+        let db_opts = DBOptions::new();
+        let mut namespaced_opts = ColumnFamilyOptions::new();
+        namespaced_opts.add_table_greedoids_collector_factory(
+            "einsteindb.kv-size-greedoids-collector",
+            KvSizeGreedoidsCollectorFactory::default(),
+        );
+        let namespaceds_opts = LARGE_NAMESPACEDS
+            .iter()
+            .map(|namespaced| NAMESPACEDOptions::new(namespaced, namespaced_opts.clone()))
+            .collect();
+        let einsteindb = Arc::new(crate::primitive_causet_util::new_einstein_merkle_tree_opt(local_path_str, db_opts, namespaceds_opts).unwrap());
+
+        let write_namespaced = einsteindb.namespaced_handle(NAMESPACED_WRITE).unwrap();
+        for key in &["a", "b", "c"] {
+            einsteindb.put_namespaced(write_namespaced, &keys::data_key(key.as_bytes()), &[0; 100]).unwrap();
+            einsteindb.flush_namespaced(write_namespaced, true).unwrap();
+        }
+
+        let props = get_range_kv_size_greedoids(
+            einsteindb.c(),
+            NAMESPACED_WRITE,
+            &keys::data_key(&[]),
+            &keys::data_end_key(&[]),
+        )
+        .unwrap();
+        assert_eq!(props.num_entries, 3);
+        assert_eq!(props.key_sizes.total, 6);
+        assert_eq!(props.value_sizes.total, 300);
+    }
+
     #[test]
     fn test_mvcc_greedoids() {
         let cases = [
@@ -2,7 +2,7 @@
 
 use api_version::{APIVersion, KeyMode, Primitive_CausetValue};
 use einsteindb_util::error;
-use fdb_traits::{Range, Result, TtlGreedoids, TtlGreedoidsExt};
+use fdb_traits::{keys, Range, Result, TtlGreedoids, TtlGreedoidsExt};
 use foundationdb::{DBEntryType, TableGreedoidsCollector, TableGreedoidsCollectorFactory};
 use std::collections::HashMap;
 use std::marker::PhantomData;
@@ -2,7 +2,7 @@
 
 use einsteindb_util::{box_err, box_try};
 use fdb_traits::{
-    NAMESPACED_DEFAULT, Error, Iterable, KV, MiscExt, Mutable, Peekable, VioletaBFTeinstein_merkle_tree,
+    keys, CacheStats, NAMESPACED_DEFAULT, Error, Iterable, KV, MiscExt, Mutable, Peekable, VioletaBFTeinstein_merkle_tree,
     VioletaBFTeinstein_merkle_treeReadOnly, VioletaBFTLogBatch, VioletaBFTLogGCTask, Result, SyncMutable, WriteBatch, WriteBatchExt,
     WriteOptions,
 };
@@ -258,6 +258,42 @@ impl VioletaBFTeinstein_merkle_tree for Fdbeinstein_merkle_tree {
         Ok(vec![])
     }
 
+    fn log_usage(&self) -> Result<Vec<(u64, CacheStats)>> {
+        // The log entries of a group sort together, after its lower-numbered
+        // neighbours' and before its own VioletaBFT state.
+        let start_key = keys::REGION_VIOLETABFT_PREFIX_KEY;
+        let end_key = [keys::LOCAL_PREFIX, keys::REGION_VIOLETABFT_PREFIX + 1];
+        let mut usage: Vec<(u64, CacheStats)> = vec![];
+        self.scan(
+            start_key,
+            &end_key,
+            false, // fill_cache
+            |key, value| {
+                let (region_id, index) = match keys::Key::parse(key) {
+                    Ok(keys::Key::VioletaBFTLog { region_id, index }) => (region_id, index),
+                    _ => return Ok(true),
+                };
+                match usage.last_mut() {
+                    Some((id, stats)) if *id == region_id => {
+                        stats.log_size += value.len();
+                        stats.log_entries += 1;
+                    }
+                    _ => {
+                        let stats = CacheStats {
+                            log_size: value.len(),
+                            log_entries: 1,
+                            log_first_index: index,
+                            ..CacheStats::default()
+                        };
+                        usage.push((region_id, stats));
+                    }
+                }
+                Ok(true)
+            },
+        )?;
+        Ok(usage)
+    }
+
     fn has_builtin_entry_cache(&self) -> bool {
         false
     }
@@ -1,7 +1,10 @@
 // Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
-use fdb_traits::{self, Error, Mutable, Result, WriteBatchExt, WriteOptions};
-use foundationdb::{EINSTEINDB, Writable, WriteBatch as Primitive_CausetWriteBatch};
+use fdb_traits::{
+    self, Error, MiscExt, Mutable, PipelinedWriteBatch, Result, WriteBatchExt, WriteBatchOp, WriteOptions,
+};
+use foundationdb::{DBValueType, EINSTEINDB, Writable, WriteBatch as Primitive_CausetWriteBatch};
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use crate::fdb_lsh_tree;
@@ -13,13 +16,12 @@ const WRITE_BATCH_LIMIT: usize = 16;
 
 impl WriteBatchExt for Fdbeinstein_merkle_tree {
     type WriteBatch = FdbWriteBatch;
-    type WriteBatchVec = FdbWriteBatchVec;
+    type WriteBatchVec = PipelinedWriteBatch<Fdbeinstein_merkle_tree>;
 
     const WRITE_BATCH_MAX_CAUSET_KEYS: usize = 256;
 
     fn support_write_batch_vec(&self) -> bool {
-        let options = self.as_inner().get_db_options();
-        options.is_enable_multi_batch_write()
+        true
     }
 
     fn write_batch(&self) -> Self::WriteBatch {
@@ -70,12 +72,48 @@ impl FdbWriteBatch {
     }
 }
 
+/// Calls `f` with each command in `wb`, naming column families by their handles in `einsteindb`.
+fn iterate_primitive_causet<F>(einsteindb: &EINSTEINDB, wb: &Primitive_CausetWriteBatch, f: &mut F) -> Result<()>
+where
+    F: FnMut(WriteBatchOp<'_>) -> Result<()>,
+{
+    let mut names = HashMap::new();
+    for namespaced in einsteindb.namespaced_names() {
+        names.insert(get_namespaced_handle(einsteindb, namespaced)?.id(), namespaced);
+    }
+    for (value_type, namespaced_id, key, value) in wb.iter() {
+        let namespaced = *names
+            .get(&namespaced_id)
+            .ok_or_else(|| Error::NAMESPACEDName(namespaced_id.to_string()))?;
+        let op = match value_type {
+            DBValueType::TypeValue => WriteBatchOp::Put { namespaced, key, value },
+            DBValueType::TypeDeletion | DBValueType::TypeSingleDeletion => {
+                WriteBatchOp::Delete { namespaced, key }
+            }
+            DBValueType::TypeRangeDeletion => WriteBatchOp::DeleteRange {
+                namespaced,
+                begin_key: key,
+                end_key: value,
+            },
+            t => {
+                return Err(Error::einstein_merkle_tree(format!(
+                    "unsupported write batch command {:?}",
+                    t
+                )))
+            }
+        };
+        f(op)?;
+    }
+    Ok(())
+}
+
 impl fdb_traits::WriteBatch<Fdbeinstein_merkle_tree> for FdbWriteBatch {
     fn with_capacity(e: &Fdbeinstein_merkle_tree, cap: usize) -> FdbWriteBatch {
         e.write_batch_with_cap(cap)
     }
 
     fn write_opt(&self, opts: &WriteOptions) -> Result<()> {
+        Fdbeinstein_merkle_tree::from_ref(&self.einsteindb).wait_for_write_stall(opts)?;
         let opt: FdbWriteOptions = opts.into();
         self.get_db()
             .write_opt(self.as_inner(), &opt.into_primitive_causet())
@@ -117,6 +155,13 @@ impl fdb_traits::WriteBatch<Fdbeinstein_merkle_tree> for FdbWriteBatch {
     fn merge(&mut self, src: Self) {
         self.wb.append(src.wb.data());
     }
+
+    fn iterate<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(WriteBatchOp<'_>) -> Result<()>,
+    {
+        iterate_primitive_causet(self.get_db(), &self.wb, &mut f)
+    }
 }
 
 impl Mutable for FdbWriteBatch {
@@ -152,7 +197,9 @@ impl Mutable for FdbWriteBatch {
     }
 }
 
-/// `FdbWriteBatchVec` is for method `multi_batch_write` of FdbDB, which splits a large WriteBatch
+/// `FdbWriteBatchVec` is for callers of method `multi_batch_write` of FdbDB, which needs the
+/// `multi_batch_write` DB option; the engine's `WriteBatchVec` is a `PipelinedWriteBatch`, which
+/// works with any options. `multi_batch_write` splits a large WriteBatch
 /// into many smaller ones and then any thread could help to deal with these small WriteBatch when it
 /// is calling `AwaitState` and wait to become leader of WriteGroup. `multi_batch_write` will perform
 /// much better than traditional `pipelined_write` when EinsteinDB writes very large data into FdbDB. We
@@ -212,6 +259,7 @@ impl fdb_traits::WriteBatch<Fdbeinstein_merkle_tree> for FdbWriteBatchVec {
     }
 
     fn write_opt(&self, opts: &WriteOptions) -> Result<()> {
+        Fdbeinstein_merkle_tree::from_ref(&self.einsteindb).wait_for_write_stall(opts)?;
         let opt: FdbWriteOptions = opts.into();
         if self.index > 0 {
             self.get_db()
@@ -275,6 +323,16 @@ impl fdb_traits::WriteBatch<Fdbeinstein_merkle_tree> for FdbWriteBatchVec {
     fn merge(&mut self, _: Self) {
         panic!("merge is not implemented for FdbWriteBatchVec");
     }
+
+    fn iterate<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(WriteBatchOp<'_>) -> Result<()>,
+    {
+        for wb in self.as_inner() {
+            iterate_primitive_causet(self.get_db(), wb, &mut f)?;
+        }
+        Ok(())
+    }
 }
 
 impl Mutable for FdbWriteBatchVec {
@@ -322,7 +380,7 @@ impl Mutable for FdbWriteBatchVec {
 
 #[cfg(test)]
 mod tests {
-    use fdb_traits::WriteBatch;
+    use fdb_traits::{Peekable, WriteBatch};
     use foundationdb::DBOptions as Primitive_CausetDBOptions;
     use tempfilef::Builder;
 
@@ -364,4 +422,61 @@ mod tests {
         wb.clear();
         assert!(!wb.should_write_to_einstein_merkle_tree());
     }
+
+    #[test]
+    fn test_write_batch_vec() {
+        let local_path = Builder::new()
+            .prefix("test-write-batch-vec")
+            .temfidelir()
+            .unwrap();
+        let einstein_merkle_tree = new_einstein_merkle_tree_opt(
+            local_path.local_path().join("einsteindb").to_str().unwrap(),
+            FdbDBOptions::from_primitive_causet(Primitive_CausetDBOptions::default()),
+            vec![],
+        )
+            .unwrap();
+        // Works without `multi_batch_write`, split into sub-batches.
+        assert!(einstein_merkle_tree.support_write_batch_vec());
+        let mut wb = <Fdbeinstein_merkle_tree as WriteBatchExt>::WriteBatchVec::with_capacity(&einstein_merkle_tree, 0);
+        for i in 0..Fdbeinstein_merkle_tree::WRITE_BATCH_MAX_CAUSET_KEYS * 2 + 1 {
+            wb.put(format!("k{}", i).as_bytes(), b"v").unwrap();
+        }
+        assert_eq!(wb.sub_batch_count(), 3);
+        wb.write().unwrap();
+        assert_eq!(&*einstein_merkle_tree.get_value(b"k0").unwrap().unwrap(), b"v");
+        assert_eq!(&*einstein_merkle_tree.get_value(b"k512").unwrap().unwrap(), b"v");
+    }
+
+    #[test]
+    fn test_write_batch_repr() {
+        let local_path = Builder::new()
+            .prefix("test-write-batch-repr")
+            .temfidelir()
+            .unwrap();
+        let einstein_merkle_tree = new_einstein_merkle_tree_opt(
+            local_path.local_path().join("einsteindb").to_str().unwrap(),
+            FdbDBOptions::from_primitive_causet(Primitive_CausetDBOptions::default()),
+            vec![],
+        )
+            .unwrap();
+        let mut wb = einstein_merkle_tree.write_batch();
+        wb.put(b"a", b"1").unwrap();
+        wb.delete_namespaced(fdb_traits::NAMESPACED_DEFAULT, b"b").unwrap();
+        wb.delete_range(b"c", b"d").unwrap();
+
+        let mut ops = vec![];
+        wb.iterate(|op| {
+            ops.push(format!("{:?}", op));
+            Ok(())
+        })
+            .unwrap();
+        assert_eq!(ops.len(), 3);
+        assert!(ops[0].starts_with("Put"));
+        assert!(ops[2].starts_with("DeleteRange"));
+
+        let repr = wb.to_repr().unwrap();
+        let replayed = FdbWriteBatch::from_repr(&einstein_merkle_tree, &repr).unwrap();
+        assert_eq!(replayed.count(), 3);
+        assert_eq!(replayed.to_repr().unwrap(), repr);
+    }
 }
@@ -64,6 +64,7 @@ mod tx_report;
 mod BerolinaSQL_types;
 
 pub use tx_report::{
+    CausetidRange,
     TxReport,
 };
 
@@ -115,6 +116,21 @@ pub struct Topograph {
     /// Maintain a vec of unique attribute IDs for which the corresponding attribute in `attribute_map`
     /// has `.component == true`.
     pub component_attributes: Vec<Causetid>,
+
+    /// The composite indexes declared in the store, ordered by declaration.
+    pub composite_indexes: Vec<CompositeIndex>,
+}
+
+/// A composite index over a set of attributes; see `einsteindb::composite_index`.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
+pub struct CompositeIndex {
+    pub idx: i64,
+
+    /// The name the index was declared with, as a keyword string, e.g., `:person/name-age`.
+    pub name: String,
+
+    /// The indexed attributes, in index order.
+    pub attributes: Vec<Causetid>,
 }
 
 /// Re-Write as a single bi-directional map instead of separate solitonid->causetid and causetid->solitonid maps.
@@ -161,7 +177,7 @@ pub trait HasTopograph {
 
 impl Topograph {
     pub fn new(solitonid_map: SolitonidMap, causetid_map: CausetidMap, attribute_map: AttributeMap) -> Topograph {
-        let mut s = Topograph { solitonid_map, causetid_map, attribute_map, component_attributes: Vec::new() };
+        let mut s = Topograph { solitonid_map, causetid_map, attribute_map, component_attributes: Vec::new(), composite_indexes: Vec::new() };
         s.update_component_attributes();
         s
     }
@@ -397,6 +413,7 @@ mod test {
             multival: false,
             component: false,
             no_history: true,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
         };
         associate_solitonid(&mut topograph, Keyword::isoliton_namespaceable("foo", "bar"), 97);
         add_attribute(&mut topograph, 97, attr1);
@@ -409,6 +426,7 @@ mod test {
             multival: true,
             component: false,
             no_history: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
         };
         associate_solitonid(&mut topograph, Keyword::isoliton_namespaceable("foo", "bas"), 98);
         add_attribute(&mut topograph, 98, attr2);
@@ -421,6 +439,7 @@ mod test {
             multival: false,
             component: true,
             no_history: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
         };
 
         associate_solitonid(&mut topograph, Keyword::isoliton_namespaceable("foo", "bat"), 99);
@@ -57,6 +57,7 @@ impl BerolinaSQLValueType for ValueType {
             ValueType::Double  => (5, Some(BerolinaSQLTypeAffinity::Real)),
             ValueType::String  => (10, None),
             ValueType::Uuid    => (11, None),
+            ValueType::Uri     => (15, None),
             ValueType::Keyword => (13, None),
                   //MyBerolinaSQL ValueTypes
             ValueType::Date    => (12, None),
@@ -99,6 +100,7 @@ impl BerolinaSQLValueType for ValueType {
             ValueType::String       => false,
             Keyword                 => false,
             Uuid                    => false,
+            Uri                     => false,
         }
     }
 }
@@ -23,6 +23,27 @@ use ::{
     Utc,
 };
 
+/// A half-open range `[start, end)` of causetids.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
+pub struct CausetidRange {
+    pub start: Causetid,
+    pub end: Causetid,
+}
+
+impl CausetidRange {
+    pub fn len(&self) -> usize {
+        (self.end - self.start) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end <= self.start
+    }
+
+    pub fn contains(&self, e: Causetid) -> bool {
+        self.start <= e && e < self.end
+    }
+}
+
 /// A transaction report summarizes an applied transaction.
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
 pub struct TxReport {
@@ -38,4 +59,10 @@ pub struct TxReport {
     /// existing causetid, or is allocated a new causetid.  (It is possible for multiple distinct string
     /// literal tempids to all unify to a single freshly allocated causetid.)
     pub tempids: BTreeMap<String, Causetid>,
+
+    /// The causetids that tempids which didn't upsert were allocated from, by partition.
+    ///
+    /// When the transaction was given a tempid reservation, this is the reserved range; it may be
+    /// larger than the number of tempids actually allocated.
+    pub reserved: BTreeMap<String, CausetidRange>,
 }
@@ -2,6 +2,7 @@
 
 use crate::fdb_lsh_treePaniceinstein_merkle_tree;
 use fdb_traits::Result;
+use fdb_traits::{BlockCacheExt, BlockCacheOptions, BlockCacheUsage};
 use fdb_traits::{DBOptions, DBOptionsExt, TitanDBOptions};
 
 impl DBOptionsExt for Paniceinstein_merkle_tree {
@@ -15,6 +16,26 @@ impl DBOptionsExt for Paniceinstein_merkle_tree {
     }
 }
 
+#[derive(Clone)]
+pub struct PanicBlockCache;
+
+pub struct PanicBlockBasedOptions;
+
+impl BlockCacheExt for Paniceinstein_merkle_tree {
+    type BlockCache = PanicBlockCache;
+    type BlockBasedOptions = PanicBlockBasedOptions;
+
+    fn new_shared_block_cache(options: &BlockCacheOptions) -> Result<Self::BlockCache> {
+        panic!()
+    }
+    fn set_block_cache(block_based_options: &mut PanicBlockBasedOptions, cache: &Self::BlockCache) {
+        panic!()
+    }
+    fn get_block_cache_usage_namespaced(&self, namespaced: &str) -> Result<BlockCacheUsage> {
+        panic!()
+    }
+}
+
 pub struct PanicDBOptions;
 
 impl DBOptions for PanicDBOptions {
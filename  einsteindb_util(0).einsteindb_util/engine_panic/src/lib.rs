@@ -27,6 +27,9 @@ mod import;
 pub use import::*;
 mod misc;
 pub use crate::misc::*;
+mod metrics;
+pub use crate::metrics::*;
+mod lock_store;
 mod lightlike_persistence;
 pub use crate::lightlike_persistence::*;
 mod Causet;
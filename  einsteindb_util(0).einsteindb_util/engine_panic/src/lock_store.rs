@@ -0,0 +1,6 @@
+// Copyright 2020 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+use crate::fdb_lsh_treePaniceinstein_merkle_tree;
+use fdb_traits::LockStoreExt;
+
+impl LockStoreExt for Paniceinstein_merkle_tree {}
@@ -0,0 +1,10 @@
+// Copyright 2020 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+use crate::fdb_lsh_treePaniceinstein_merkle_tree;
+use fdb_traits::{EngineMetrics, MetricsExt, Result};
+
+impl MetricsExt for Paniceinstein_merkle_tree {
+    fn metrics_snapshot(&self) -> Result<EngineMetrics> {
+        panic!()
+    }
+}
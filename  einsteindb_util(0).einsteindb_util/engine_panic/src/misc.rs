@@ -1,7 +1,7 @@
 // Copyright 2020 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
 use crate::fdb_lsh_treePaniceinstein_merkle_tree;
-use fdb_traits::{DeleteStrategy, MiscExt, Range, Result};
+use fdb_traits::{DeleteStrategy, MiscExt, Range, Result, StallReason};
 
 impl MiscExt for Paniceinstein_merkle_tree {
     fn flush(&self, sync: bool) -> Result<()> {
@@ -77,4 +77,8 @@ impl MiscExt for Paniceinstein_merkle_tree {
     fn is_stalled_or_stopped(&self) -> bool {
         panic!()
     }
+
+    fn stall_reasons(&self) -> Result<Vec<StallReason>> {
+        panic!()
+    }
 }
@@ -1,10 +1,20 @@
 // Copyright 2020 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
 use crate::fdb_lsh_treePaniceinstein_merkle_tree;
-use fdb_traits::NAMESPACEDNamesExt;
+use fdb_traits::{NAMESPACEDDestroyExt, NAMESPACEDNamesExt, NamespacedFile, Result};
 
 impl NAMESPACEDNamesExt for Paniceinstein_merkle_tree {
     fn namespaced_names(&self) -> Vec<&str> {
         panic!()
     }
 }
+
+impl NAMESPACEDDestroyExt for Paniceinstein_merkle_tree {
+    fn namespaced_filefs(&self, namespaced: &str) -> Result<Vec<NamespacedFile>> {
+        panic!()
+    }
+
+    fn drop_namespaced(&mut self, namespaced: &str) -> Result<()> {
+        panic!()
+    }
+}
@@ -54,6 +54,9 @@ impl ColumnFamilyOptions for PanicColumnFamilyOptions {
     fn get_target_file_size_base(&self) -> u64 {
         panic!()
     }
+    fn get_write_buffer_size(&self) -> u64 {
+        panic!()
+    }
     fn set_disable_auto_jet_bundles(&mut self, v: bool) {
         panic!()
     }
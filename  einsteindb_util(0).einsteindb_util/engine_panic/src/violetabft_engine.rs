@@ -2,7 +2,7 @@
 
 use crate::fdb_lsh_treePaniceinstein_merkle_tree;
 use crate::write_batch::PanicWriteBatch;
-use fdb_traits::{Error, VioletaBFTeinstein_merkle_tree, VioletaBFTeinstein_merkle_treeReadOnly, VioletaBFTLogBatch, Result};
+use fdb_traits::{CacheStats, Error, VioletaBFTeinstein_merkle_tree, VioletaBFTeinstein_merkle_treeReadOnly, VioletaBFTLogBatch, Result};
 use ekvproto::violetabft_serverpb::VioletaBFTLocalState;
 use violetabft::evioletabftpb::Entry;
 
@@ -82,6 +82,10 @@ impl VioletaBFTeinstein_merkle_tree for Paniceinstein_merkle_tree {
         panic!()
     }
 
+    fn log_usage(&self) -> Result<Vec<(u64, CacheStats)>> {
+        panic!()
+    }
+
     fn has_builtin_entry_cache(&self) -> bool {
         panic!()
     }
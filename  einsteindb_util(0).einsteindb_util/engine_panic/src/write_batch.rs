@@ -1,7 +1,7 @@
 // Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
 use crate::fdb_lsh_treePaniceinstein_merkle_tree;
-use fdb_traits::{Mutable, Result, WriteBatch, WriteBatchExt, WriteOptions};
+use fdb_traits::{Mutable, Result, WriteBatch, WriteBatchExt, WriteBatchOp, WriteOptions};
 
 impl WriteBatchExt for Paniceinstein_merkle_tree {
     type WriteBatch = PanicWriteBatch;
@@ -60,6 +60,13 @@ impl WriteBatch<Paniceinstein_merkle_tree> for PanicWriteBatch {
     fn merge(&mut self, src: Self) {
         panic!()
     }
+
+    fn iterate<F>(&self, f: F) -> Result<()>
+    where
+        F: FnMut(WriteBatchOp<'_>) -> Result<()>,
+    {
+        panic!()
+    }
 }
 
 impl Mutable for PanicWriteBatch {
@@ -0,0 +1,310 @@
+// Copyright 2020 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! Generic conformance tests for `KV` implementations
+//!
+//! Each test is a plain function taking an `einstein_merkle_treeFactory`, so a
+//! new einstein_merkle_tree can be validated by implementing that one trait and
+//! instantiating the whole battery with the `einstein_merkle_tree_test_suite!`
+//! macro:
+//!
+//! ```ignore
+//! mod tests {
+//!     einstein_merkle_tree_test::einstein_merkle_tree_test_suite!(MyFactory::default());
+//! }
+//! ```
+//!
+//! The tests only use the `fdb_traits` abstractions, and are the first step of
+//! stage 4 of the port, isolating test cases from FdbDB.
+
+use std::sync::mpsc;
+use std::time::Duration;
+
+use fdb_traits::{
+    keys, Iterable, Iterator, KV, LightlikePersistence, MiscExt, Mutable, Peekable, Result, SeekKey,
+    SyncMutable, TtlGreedoidsExt, WriteBatch, WriteBatchExt, NAMESPACED_DEFAULT, NAMESPACED_LOCK,
+    NAMESPACED_WRITE,
+};
+
+/// Creates the einstein_merkle_trees under test
+pub trait einstein_merkle_treeFactory {
+    type KV: KV;
+
+    /// Creates an empty einstein_merkle_tree at `local_path` with the column families
+    /// in `namespaceds`.
+    fn create(&self, local_path: &str, namespaceds: &[&str]) -> Result<Self::KV>;
+
+    /// Encodes `value` with an expire timestamp, the way the einstein_merkle_tree's TTL
+    /// greedoids collector expects it.
+    ///
+    /// Returns `None` if the einstein_merkle_tree doesn't support TTL, in which case
+    /// `test_ttl_greedoids` checks nothing.
+    fn encode_ttl_value(&self, _value: &[u8], _expire_ts: u64) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// The factory for the `kv` einstein_merkle_tree selected by this crate's features
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KvTesteinstein_merkle_treeFactory;
+
+impl einstein_merkle_treeFactory for KvTesteinstein_merkle_treeFactory {
+    type KV = crate::kv::KvTesteinstein_merkle_tree;
+
+    fn create(&self, local_path: &str, namespaceds: &[&str]) -> Result<Self::KV> {
+        crate::kv::new_einstein_merkle_tree(local_path, None, namespaceds, None)
+    }
+}
+
+fn with_einstein_merkle_tree<F, T>(factory: &F, namespaceds: &[&str], test: T)
+where
+    F: einstein_merkle_treeFactory,
+    T: FnOnce(&F::KV),
+{
+    let local_path = tempfile::Builder::new()
+        .prefix("einstein_merkle_tree_test_suite")
+        .tempdir()
+        .unwrap();
+    let einstein_merkle_tree = factory
+        .create(local_path.local_path().to_str().unwrap(), namespaceds)
+        .unwrap();
+    test(&einstein_merkle_tree);
+}
+
+fn get<P: Peekable>(p: &P, namespaced: &str, key: &[u8]) -> Option<Vec<u8>> {
+    p.get_value_namespaced(namespaced, key)
+        .unwrap()
+        .map(|v| v.to_vec())
+}
+
+fn collect_keys<I: Iterable>(i: &I, namespaced: &str) -> Vec<Vec<u8>> {
+    let mut keys = vec![];
+    i.scan_namespaced(namespaced, b"", b"", false, |k, _| {
+        keys.push(k.to_vec());
+        Ok(true)
+    })
+    .unwrap();
+    keys
+}
+
+/// Iterators yield keys in bytewise order in both directions, and seeks land
+/// on the nearest key in the right direction.
+pub fn test_iterator_ordering<F: einstein_merkle_treeFactory>(factory: &F) {
+    with_einstein_merkle_tree(factory, &[NAMESPACED_DEFAULT], |einstein_merkle_tree| {
+        for k in &[b"c".as_ref(), b"a", b"b\x00", b"b", b"d"] {
+            einstein_merkle_tree.put(k, k).unwrap();
+        }
+        let expected: Vec<&[u8]> = vec![b"a", b"b", b"b\x00", b"c", b"d"];
+
+        let mut iter = einstein_merkle_tree.iterator().unwrap();
+        let mut keys = vec![];
+        let mut valid = iter.seek_to_first().unwrap();
+        while valid {
+            assert_eq!(iter.key(), iter.value());
+            keys.push(iter.key().to_vec());
+            valid = iter.next().unwrap();
+        }
+        assert_eq!(keys, expected);
+        assert!(!iter.valid().unwrap());
+
+        let mut keys = vec![];
+        let mut valid = iter.seek_to_last().unwrap();
+        while valid {
+            keys.push(iter.key().to_vec());
+            valid = iter.prev().unwrap();
+        }
+        keys.reverse();
+        assert_eq!(keys, expected);
+
+        assert!(iter.seek(SeekKey::Key(b"b\x01")).unwrap());
+        assert_eq!(iter.key(), b"c");
+        assert!(iter.seek_for_prev(SeekKey::Key(b"b\x01")).unwrap());
+        assert_eq!(iter.key(), b"b\x00");
+        assert!(iter.seek(SeekKey::Key(b"b")).unwrap());
+        assert_eq!(iter.key(), b"b");
+        assert!(!iter.seek(SeekKey::Key(b"e")).unwrap());
+        assert!(!iter.seek_for_prev(SeekKey::Key(b"0")).unwrap());
+    });
+}
+
+/// Nothing in a write batch is visible until it is written, and then all of
+/// it is, across column families. Operations rolled back to a save point are
+/// never written.
+pub fn test_write_batch_atomicity<F: einstein_merkle_treeFactory>(factory: &F) {
+    with_einstein_merkle_tree(factory, &[NAMESPACED_DEFAULT, NAMESPACED_WRITE], |einstein_merkle_tree| {
+        einstein_merkle_tree.put(b"gone", b"v").unwrap();
+
+        let mut wb = einstein_merkle_tree.write_batch();
+        wb.put(b"a", b"1").unwrap();
+        wb.put_namespaced(NAMESPACED_WRITE, b"b", b"2").unwrap();
+        wb.delete(b"gone").unwrap();
+        wb.set_save_point();
+        wb.put(b"rolled_back", b"3").unwrap();
+        wb.rollback_to_save_point().unwrap();
+        assert_eq!(wb.count(), 3);
+
+        assert_eq!(get(einstein_merkle_tree, NAMESPACED_DEFAULT, b"a"), None);
+        assert_eq!(get(einstein_merkle_tree, NAMESPACED_WRITE, b"b"), None);
+        assert_eq!(get(einstein_merkle_tree, NAMESPACED_DEFAULT, b"gone"), Some(b"v".to_vec()));
+
+        wb.write().unwrap();
+        assert_eq!(get(einstein_merkle_tree, NAMESPACED_DEFAULT, b"a"), Some(b"1".to_vec()));
+        assert_eq!(get(einstein_merkle_tree, NAMESPACED_WRITE, b"b"), Some(b"2".to_vec()));
+        assert_eq!(get(einstein_merkle_tree, NAMESPACED_DEFAULT, b"gone"), None);
+        assert_eq!(get(einstein_merkle_tree, NAMESPACED_DEFAULT, b"rolled_back"), None);
+    });
+}
+
+/// A lightlike_persistence sees the einstein_merkle_tree as of its creation, through both
+/// point reads and iterators.
+pub fn test_lightlike_persistence_isolation<F: einstein_merkle_treeFactory>(factory: &F) {
+    with_einstein_merkle_tree(factory, &[NAMESPACED_DEFAULT], |einstein_merkle_tree| {
+        einstein_merkle_tree.put(b"k1", b"v1").unwrap();
+        einstein_merkle_tree.put(b"k2", b"v2").unwrap();
+        let snap = einstein_merkle_tree.lightlike_persistence();
+
+        einstein_merkle_tree.put(b"k1", b"v1'").unwrap();
+        einstein_merkle_tree.delete(b"k2").unwrap();
+        einstein_merkle_tree.put(b"k3", b"v3").unwrap();
+
+        assert_eq!(get(&snap, NAMESPACED_DEFAULT, b"k1"), Some(b"v1".to_vec()));
+        assert_eq!(get(&snap, NAMESPACED_DEFAULT, b"k2"), Some(b"v2".to_vec()));
+        assert_eq!(get(&snap, NAMESPACED_DEFAULT, b"k3"), None);
+        assert_eq!(collect_keys(&snap, NAMESPACED_DEFAULT), vec![b"k1".to_vec(), b"k2".to_vec()]);
+        assert!(snap.namespaced_names().contains(&NAMESPACED_DEFAULT));
+
+        assert_eq!(get(einstein_merkle_tree, NAMESPACED_DEFAULT, b"k1"), Some(b"v1'".to_vec()));
+        assert_eq!(
+            collect_keys(einstein_merkle_tree, NAMESPACED_DEFAULT),
+            vec![b"k1".to_vec(), b"k3".to_vec()]
+        );
+    });
+}
+
+/// An asynchronously acquired lightlike_persistence is delivered to the
+/// callback exactly once, and sees at least the writes made before it was
+/// requested.
+pub fn test_async_lightlike_persistence<F: einstein_merkle_treeFactory>(factory: &F) {
+    with_einstein_merkle_tree(factory, &[NAMESPACED_DEFAULT], |einstein_merkle_tree| {
+        einstein_merkle_tree.put(b"k1", b"v1").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        einstein_merkle_tree.async_lightlike_persistence(Box::new(move |snap| {
+            tx.send(snap).unwrap();
+        }));
+        let snap = rx.recv_timeout(Duration::from_secs(10)).unwrap().unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+        einstein_merkle_tree.put(b"k2", b"v2").unwrap();
+        assert_eq!(get(&snap, NAMESPACED_DEFAULT, b"k1"), Some(b"v1".to_vec()));
+        assert_eq!(get(&snap, NAMESPACED_DEFAULT, b"k2"), None);
+    });
+}
+
+/// Column families are separate keyspaces: the same key may hold different
+/// values in each, and writes, deletes and iteration stay in their own.
+pub fn test_namespaced_isolation<F: einstein_merkle_treeFactory>(factory: &F) {
+    let namespaceds = &[NAMESPACED_DEFAULT, NAMESPACED_LOCK, NAMESPACED_WRITE];
+    with_einstein_merkle_tree(factory, namespaceds, |einstein_merkle_tree| {
+        for namespaced in namespaceds {
+            einstein_merkle_tree.put_namespaced(namespaced, b"k", namespaced.as_bytes()).unwrap();
+            einstein_merkle_tree.put_namespaced(namespaced, b"k2", namespaced.as_bytes()).unwrap();
+        }
+        einstein_merkle_tree.put_namespaced(NAMESPACED_LOCK, b"lock_only", b"v").unwrap();
+
+        for namespaced in namespaceds {
+            assert_eq!(get(einstein_merkle_tree, namespaced, b"k"), Some(namespaced.as_bytes().to_vec()));
+        }
+
+        einstein_merkle_tree.delete_namespaced(NAMESPACED_WRITE, b"k").unwrap();
+        einstein_merkle_tree.delete_range_namespaced(NAMESPACED_DEFAULT, b"k", b"k3").unwrap();
+        assert_eq!(get(einstein_merkle_tree, NAMESPACED_WRITE, b"k"), None);
+        assert_eq!(get(einstein_merkle_tree, NAMESPACED_DEFAULT, b"k2"), None);
+        assert_eq!(get(einstein_merkle_tree, NAMESPACED_LOCK, b"k"), Some(NAMESPACED_LOCK.as_bytes().to_vec()));
+
+        assert!(collect_keys(einstein_merkle_tree, NAMESPACED_DEFAULT).is_empty());
+        assert_eq!(
+            collect_keys(einstein_merkle_tree, NAMESPACED_LOCK),
+            vec![b"k".to_vec(), b"k2".to_vec(), b"lock_only".to_vec()]
+        );
+        assert_eq!(collect_keys(einstein_merkle_tree, NAMESPACED_WRITE), vec![b"k2".to_vec()]);
+    });
+}
+
+/// Flushed values with expire timestamps are reflected in the TTL greedoids
+/// of their range. Only data keys carry TTL greedoids.
+pub fn test_ttl_greedoids<F: einstein_merkle_treeFactory>(factory: &F) {
+    let encoded: Option<Vec<(Vec<u8>, Vec<u8>)>> = [10u64, 30, 20]
+        .iter()
+        .map(|ts| {
+            let key = keys::data_key(format!("k{}", ts).as_bytes());
+            factory.encode_ttl_value(b"v", *ts).map(|v| (key, v))
+        })
+        .collect();
+    let encoded = match encoded {
+        Some(encoded) => encoded,
+        None => return,
+    };
+
+    with_einstein_merkle_tree(factory, &[NAMESPACED_DEFAULT], |einstein_merkle_tree| {
+        for (k, v) in &encoded {
+            einstein_merkle_tree.put(k, v).unwrap();
+        }
+        einstein_merkle_tree.flush_namespaced(NAMESPACED_DEFAULT, true).unwrap();
+
+        let greedoids = einstein_merkle_tree
+            .get_range_ttl_greedoids_namespaced(
+                NAMESPACED_DEFAULT,
+                &keys::data_key(b"k"),
+                &keys::data_key(b"l"),
+            )
+            .unwrap();
+        assert!(!greedoids.is_empty());
+        let min = greedoids.iter().map(|(_, p)| p.min_expire_ts).min().unwrap();
+        let max = greedoids.iter().map(|(_, p)| p.max_expire_ts).max().unwrap();
+        assert_eq!((min, max), (10, 30));
+    });
+}
+
+/// Instantiates every test of the suite as a `#[test]` function, running
+/// against the einstein_merkle_trees created by the `einstein_merkle_treeFactory` `$factory`.
+#[macro_export]
+macro_rules! einstein_merkle_tree_test_suite {
+    ($factory:expr) => {
+        #[test]
+        fn test_iterator_ordering() {
+            $crate::einstein_merkle_tree_test_suite::test_iterator_ordering(&$factory);
+        }
+
+        #[test]
+        fn test_write_batch_atomicity() {
+            $crate::einstein_merkle_tree_test_suite::test_write_batch_atomicity(&$factory);
+        }
+
+        #[test]
+        fn test_lightlike_persistence_isolation() {
+            $crate::einstein_merkle_tree_test_suite::test_lightlike_persistence_isolation(&$factory);
+        }
+
+        #[test]
+        fn test_async_lightlike_persistence() {
+            $crate::einstein_merkle_tree_test_suite::test_async_lightlike_persistence(&$factory);
+        }
+
+        #[test]
+        fn test_namespaced_isolation() {
+            $crate::einstein_merkle_tree_test_suite::test_namespaced_isolation(&$factory);
+        }
+
+        #[test]
+        fn test_ttl_greedoids() {
+            $crate::einstein_merkle_tree_test_suite::test_ttl_greedoids(&$factory);
+        }
+    };
+}
+
+#[cfg(all(test, feature = "test-einstein_merkle_tree-kv-foundationdb"))]
+mod tests {
+    use super::KvTesteinstein_merkle_treeFactory;
+
+    einstein_merkle_tree_test_suite!(KvTesteinstein_merkle_treeFactory);
+}
@@ -47,6 +47,9 @@
 //! We'll probably revisit the einstein_merkle_tree-testing strategy in the future,
 //! e.g. by using einstein_merkle_tree-parameterized tests instead.
 //!
+//! The `einstein_merkle_tree_test_suite` module contains einstein_merkle_tree-parameterized
+//! conformance tests that any `KV` implementation can be run against.
+//!
 //! This create also contains a `ctor` module that contains constructor methods
 //! appropriate for constructing timelike_storage einstein_merkle_trees of any type. It is intended
 //! that this module is _the only_ module within EinsteinDB that knows about concrete
@@ -340,7 +343,8 @@ pub mod ctor {
         use fdb_traits::{ColumnFamilyOptions as ColumnFamilyOptionsTrait, Result};
 
         use fdb_einstein_merkle_tree::greedoids::{
-            MvccGreedoidsCollectorFactory, RangeGreedoidsCollectorFactory,
+            KvSizeGreedoidsCollectorFactory, MvccGreedoidsCollectorFactory,
+            RangeGreedoidsCollectorFactory,
         };
         use fdb_einstein_merkle_tree::primitive_causet::ColumnFamilyOptions as Primitive_CausetFdbColumnFamilyOptions;
         use fdb_einstein_merkle_tree::primitive_causet::{DBOptions as Primitive_CausetFdbDBOptions, Env};
@@ -419,6 +423,10 @@ pub mod ctor {
                     "einsteindb.causet_model-greedoids-collector",
                     MvccGreedoidsCollectorFactory::default(),
                 );
+                rocks_namespaced_opts.add_table_greedoids_collector_factory(
+                    "einsteindb.kv-size-greedoids-collector",
+                    KvSizeGreedoidsCollectorFactory::default(),
+                );
             }
         }
 
@@ -454,6 +462,10 @@ pub mod ctor {
     }
 }
 
+/// Generic conformance tests for any KV einstein_merkle_tree
+#[macro_use]
+pub mod einstein_merkle_tree_test_suite;
+
 /// Create a new set of einstein_merkle_trees in a temporary directory
 ///
 /// This is little-used and probably shouldn't exist.
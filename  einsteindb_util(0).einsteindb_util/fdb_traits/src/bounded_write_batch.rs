@@ -0,0 +1,232 @@
+// Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! Write batches that split themselves
+//!
+//! A write batch holds every mutation in memory until it is written, so a
+//! batch built from a large scan or import can grow without bound. A
+//! `BoundedWriteBatch` writes its buffered mutations as soon as they reach
+//! `max_keys` mutations or `max_bytes` bytes, and starts buffering again.
+//!
+//! Mutations are written in the order they were made, so later writes to a key
+//! still win. What is lost is atomicity: each write is atomic on its own, and a
+//! reader may see the first writes of a `BoundedWriteBatch` before the last
+//! ones are made. If a write fails, the writes before it stay written. Use it
+//! where the mutations don't need to land together, like bulk loads that can
+//! be restarted.
+//!
+//! A mutation that returns an error is not buffered, even if it was the
+//! write it set off that failed, so it can be retried as it is.
+
+use crate::errors::Result;
+use crate::options::WriteOptions;
+use crate::write_batch::{Mutable, WriteBatch, WriteBatchExt};
+
+/// When a `BoundedWriteBatch` writes its buffered mutations
+#[derive(Clone, Debug, PartialEq)]
+pub struct WriteBatchLimits {
+    /// Write once this many mutations are buffered
+    pub max_keys: usize,
+    /// Write once the buffered mutations are this many bytes
+    pub max_bytes: usize,
+}
+
+impl WriteBatchLimits {
+    pub fn new<E: WriteBatchExt>() -> WriteBatchLimits {
+        WriteBatchLimits {
+            max_keys: E::WRITE_BATCH_MAX_CAUSET_KEYS,
+            max_bytes: 32 * 1024 * 1024,
+        }
+    }
+}
+
+/// A write batch that writes itself in parts to stay within `WriteBatchLimits`
+pub struct BoundedWriteBatch<E: WriteBatchExt> {
+    batch: E::WriteBatch,
+    limits: WriteBatchLimits,
+    write_options: WriteOptions,
+    commits: usize,
+    keys_written: usize,
+}
+
+impl<E: WriteBatchExt> BoundedWriteBatch<E> {
+    pub fn new(einstein_merkle_tree: &E, limits: WriteBatchLimits, write_options: WriteOptions) -> BoundedWriteBatch<E> {
+        BoundedWriteBatch {
+            batch: einstein_merkle_tree.write_batch_with_cap(limits.max_keys),
+            limits,
+            write_options,
+            commits: 0,
+            keys_written: 0,
+        }
+    }
+
+    pub fn limits(&self) -> &WriteBatchLimits {
+        &self.limits
+    }
+
+    /// The number of writes made so far
+    pub fn commits(&self) -> usize {
+        self.commits
+    }
+
+    /// The number of mutations written so far
+    pub fn keys_written(&self) -> usize {
+        self.keys_written
+    }
+
+    /// The number of buffered mutations
+    pub fn pending_count(&self) -> usize {
+        self.batch.count()
+    }
+
+    /// Writes the buffered mutations, if any
+    ///
+    /// If the write fails, the mutations stay buffered, and the next write
+    /// tries them again.
+    pub fn write(&mut self) -> Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        self.batch.write_opt(&self.write_options)?;
+        self.commits += 1;
+        self.keys_written += self.batch.count();
+        self.batch.clear();
+        Ok(())
+    }
+
+    /// Writes the remaining mutations, returning the number of writes made in
+    /// all
+    pub fn finish(mut self) -> Result<usize> {
+        self.write()?;
+        Ok(self.commits)
+    }
+
+    fn mutate<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut E::WriteBatch) -> Result<()>,
+    {
+        self.batch.set_save_point();
+        if let Err(e) = f(&mut self.batch) {
+            self.batch.rollback_to_save_point()?;
+            return Err(e);
+        }
+        if self.batch.count() >= self.limits.max_keys || self.batch.data_size() >= self.limits.max_bytes {
+            if let Err(e) = self.write() {
+                // Unbuffer the mutation that set off the write, which the
+                // caller will see fail.
+                self.batch.rollback_to_save_point()?;
+                return Err(e);
+            }
+            // A successful write cleared the batch and its save point.
+            return Ok(());
+        }
+        self.batch.pop_save_point()
+    }
+}
+
+impl<E: WriteBatchExt> Mutable for BoundedWriteBatch<E> {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.mutate(|wb| wb.put(key, value))
+    }
+
+    fn put_namespaced(&mut self, namespaced: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.mutate(|wb| wb.put_namespaced(namespaced, key, value))
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.mutate(|wb| wb.delete(key))
+    }
+
+    fn delete_namespaced(&mut self, namespaced: &str, key: &[u8]) -> Result<()> {
+        self.mutate(|wb| wb.delete_namespaced(namespaced, key))
+    }
+
+    fn delete_range(&mut self, begin_key: &[u8], end_key: &[u8]) -> Result<()> {
+        self.mutate(|wb| wb.delete_range(begin_key, end_key))
+    }
+
+    fn delete_range_namespaced(&mut self, namespaced: &str, begin_key: &[u8], end_key: &[u8]) -> Result<()> {
+        self.mutate(|wb| wb.delete_range_namespaced(namespaced, begin_key, end_key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+
+    use crate::mock_write_batch::MockEngine;
+
+    #[test]
+    fn test_bounded_write_batch() {
+        let engine = MockEngine::default();
+        let limits = WriteBatchLimits {
+            max_keys: 2,
+            max_bytes: usize::MAX,
+        };
+        let mut wb = BoundedWriteBatch::new(&engine, limits, WriteOptions::default());
+        wb.put(b"a", b"1").unwrap();
+        assert_eq!(wb.commits(), 0);
+        wb.put(b"b", b"1").unwrap();
+        assert_eq!(wb.commits(), 1);
+        wb.put(b"a", b"2").unwrap();
+        wb.delete(b"b").unwrap();
+        wb.delete_range(b"c", b"d").unwrap();
+        assert_eq!(wb.pending_count(), 1);
+        assert_eq!(wb.keys_written(), 4);
+        assert_eq!(wb.finish().unwrap(), 3);
+
+        // Split in order, so the last write to each key wins.
+        assert_eq!(
+            *engine.written.lock().unwrap(),
+            vec![
+                vec!["put default a 1".to_owned(), "put default b 1".to_owned()],
+                vec!["put default a 2".to_owned(), "delete default b".to_owned()],
+                vec!["delete_range default c d".to_owned()],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bounded_write_batch_by_size() {
+        let engine = MockEngine::default();
+        let limits = WriteBatchLimits {
+            max_keys: usize::MAX,
+            max_bytes: 20,
+        };
+        let mut wb = BoundedWriteBatch::new(&engine, limits, WriteOptions::default());
+        wb.put(b"a", b"1").unwrap();
+        wb.put(b"b", b"2").unwrap();
+        wb.put(b"c", b"3").unwrap();
+        assert_eq!(wb.commits(), 1);
+        assert_eq!(wb.pending_count(), 1);
+
+        // Nothing buffered, nothing written.
+        let wb = BoundedWriteBatch::new(&engine, WriteBatchLimits::new::<MockEngine>(), WriteOptions::default());
+        assert_eq!(wb.finish().unwrap(), 0);
+        assert_eq!(engine.written.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_bounded_write_batch_failed_write() {
+        let engine = MockEngine::default();
+        let limits = WriteBatchLimits {
+            max_keys: 2,
+            max_bytes: usize::MAX,
+        };
+        let mut wb = BoundedWriteBatch::new(&engine, limits, WriteOptions::default());
+        wb.put(b"a", b"1").unwrap();
+        engine.fail_writes.store(true, Ordering::SeqCst);
+        // The failed mutation is not buffered, the ones before it are.
+        assert!(wb.put(b"b", b"1").is_err());
+        assert_eq!(wb.pending_count(), 1);
+        assert_eq!(wb.commits(), 0);
+
+        engine.fail_writes.store(false, Ordering::SeqCst);
+        wb.put(b"b", b"1").unwrap();
+        assert_eq!(wb.finish().unwrap(), 1);
+        assert_eq!(
+            *engine.written.lock().unwrap(),
+            vec![vec!["put default a 1".to_owned(), "put default b 1".to_owned()]]
+        );
+    }
+}
@@ -1,6 +1,21 @@
 // Copyright 2020 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
+//! Causet file partitioners
+//!
+//! An einstein_merkle_tree asks a `CausetPartitioner` before every key of a
+//! jet bundle output whether to cut the output file there. The raw callback
+//! only sees the two keys and the output size; a `ContextPartitioner` sees a
+//! `PartitionerContext` that also carries the namespaced, the output level and
+//! the key range of the jet bundle. `PartitionerRegistry` registers context
+//! partitioners per namespaced and installs them in `ColumnFamilyOptions`, and
+//! `replay_key_stream` runs any partitioner over a recorded key stream so its
+//! decisions can be tested without an einstein_merkle_tree.
+
+use std::collections::HashMap;
 use std::ffi::CString;
+use std::sync::Arc;
+
+use crate::namespaced_options::ColumnFamilyOptions;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct CausetPartitionerRequest<'a> {
@@ -38,3 +53,287 @@ pub trait CausetPartitionerFactory: Sync + Send {
     fn name(&self) -> &CString;
     fn create_partitioner(&self, context: &CausetPartitionerContext<'_>) -> Option<Self::Partitioner>;
 }
+
+/// What a `ContextPartitioner` sees when deciding whether to cut the output file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartitionerContext<'a> {
+    pub namespaced: &'a str,
+    pub output_l_naught: i32,
+    pub is_full_jet_bundle: bool,
+    pub is_manual_jet_bundle: bool,
+    /// The key range of the jet bundle
+    pub smallest_key: &'a [u8],
+    pub largest_key: &'a [u8],
+    pub prev_user_key: &'a [u8],
+    pub current_user_key: &'a [u8],
+    /// The size of the output file so far
+    pub current_output_file_size: u64,
+}
+
+pub trait ContextPartitioner {
+    fn should_partition(&mut self, context: &PartitionerContext<'_>) -> CausetPartitionerResult;
+
+    fn can_do_trivial_move(&mut self, _smallest_key: &[u8], _largest_key: &[u8]) -> bool {
+        true
+    }
+}
+
+pub trait ContextPartitionerFactory: Sync + Send {
+    fn name(&self) -> &CString;
+
+    /// Creates the partitioner for one jet bundle. Only the namespaced, level,
+    /// jet bundle kind and key range of `context` are meaningful here.
+    fn create_partitioner(
+        &self,
+        context: &PartitionerContext<'_>,
+    ) -> Option<Box<dyn ContextPartitioner>>;
+}
+
+/// Adapts a `ContextPartitionerFactory` to the `CausetPartitionerFactory` of
+/// one namespaced.
+#[derive(Clone)]
+pub struct NamespacedPartitionerFactory {
+    namespaced: String,
+    factory: Arc<dyn ContextPartitionerFactory>,
+}
+
+impl NamespacedPartitionerFactory {
+    pub fn new(namespaced: &str, factory: Arc<dyn ContextPartitionerFactory>) -> Self {
+        NamespacedPartitionerFactory {
+            namespaced: namespaced.to_owned(),
+            factory,
+        }
+    }
+
+    pub fn namespaced(&self) -> &str {
+        &self.namespaced
+    }
+}
+
+impl CausetPartitionerFactory for NamespacedPartitionerFactory {
+    type Partitioner = NamespacedPartitioner;
+
+    fn name(&self) -> &CString {
+        self.factory.name()
+    }
+
+    fn create_partitioner(
+        &self,
+        context: &CausetPartitionerContext<'_>,
+    ) -> Option<Self::Partitioner> {
+        let ctx = PartitionerContext {
+            namespaced: &self.namespaced,
+            output_l_naught: context.output_l_naught,
+            is_full_jet_bundle: context.is_full_jet_bundle,
+            is_manual_jet_bundle: context.is_manual_jet_bundle,
+            smallest_key: context.smallest_key,
+            largest_key: context.largest_key,
+            prev_user_key: &[],
+            current_user_key: &[],
+            current_output_file_size: 0,
+        };
+        let inner = self.factory.create_partitioner(&ctx)?;
+        Some(NamespacedPartitioner {
+            namespaced: self.namespaced.clone(),
+            output_l_naught: context.output_l_naught,
+            is_full_jet_bundle: context.is_full_jet_bundle,
+            is_manual_jet_bundle: context.is_manual_jet_bundle,
+            smallest_key: context.smallest_key.to_vec(),
+            largest_key: context.largest_key.to_vec(),
+            inner,
+        })
+    }
+}
+
+/// A `ContextPartitioner` with the context of the jet bundle it was created for.
+pub struct NamespacedPartitioner {
+    namespaced: String,
+    output_l_naught: i32,
+    is_full_jet_bundle: bool,
+    is_manual_jet_bundle: bool,
+    smallest_key: Vec<u8>,
+    largest_key: Vec<u8>,
+    inner: Box<dyn ContextPartitioner>,
+}
+
+impl CausetPartitioner for NamespacedPartitioner {
+    fn should_partition(&mut self, req: &CausetPartitionerRequest<'_>) -> CausetPartitionerResult {
+        let ctx = PartitionerContext {
+            namespaced: &self.namespaced,
+            output_l_naught: self.output_l_naught,
+            is_full_jet_bundle: self.is_full_jet_bundle,
+            is_manual_jet_bundle: self.is_manual_jet_bundle,
+            smallest_key: &self.smallest_key,
+            largest_key: &self.largest_key,
+            prev_user_key: req.prev_user_key,
+            current_user_key: req.current_user_key,
+            current_output_file_size: req.current_output_file_size,
+        };
+        self.inner.should_partition(&ctx)
+    }
+
+    fn can_do_trivial_move(&mut self, smallest_key: &[u8], largest_key: &[u8]) -> bool {
+        self.inner.can_do_trivial_move(smallest_key, largest_key)
+    }
+}
+
+/// Context partitioners by namespaced.
+#[derive(Clone, Default)]
+pub struct PartitionerRegistry {
+    factories: HashMap<String, Arc<dyn ContextPartitionerFactory>>,
+}
+
+impl PartitionerRegistry {
+    pub fn new() -> Self {
+        PartitionerRegistry::default()
+    }
+
+    /// Registers the partitioner of `namespaced`, replacing any registered before.
+    pub fn register<F>(mut self, namespaced: &str, factory: F) -> Self
+    where
+        F: ContextPartitionerFactory + 'static,
+    {
+        self.factories.insert(namespaced.to_owned(), Arc::new(factory));
+        self
+    }
+
+    pub fn factory(&self, namespaced: &str) -> Option<NamespacedPartitionerFactory> {
+        self.factories
+            .get(namespaced)
+            .map(|f| NamespacedPartitionerFactory::new(namespaced, f.clone()))
+    }
+
+    /// Installs the partitioner of `namespaced` in `opts`. Returns whether one
+    /// was registered.
+    pub fn apply<O: ColumnFamilyOptions>(&self, namespaced: &str, opts: &mut O) -> bool {
+        match self.factory(namespaced) {
+            Some(factory) => {
+                opts.set_Causet_partitioner_factory(factory);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Runs a partitioner created by `factory` for `context` over `keys`, a
+/// recorded stream of user keys and the size each adds to the output file, as
+/// a jet bundle would. Returns the indexes of the keys that start a new output
+/// file.
+pub fn replay_key_stream<F: CausetPartitionerFactory>(
+    factory: &F,
+    context: &CausetPartitionerContext<'_>,
+    keys: &[(&[u8], u64)],
+) -> Vec<usize> {
+    let mut partitioner = match factory.create_partitioner(context) {
+        Some(p) => p,
+        None => return vec![],
+    };
+    let mut cuts = vec![];
+    let mut output_file_size = 0;
+    for (i, &(key, size)) in keys.iter().enumerate() {
+        if i > 0 {
+            let req = CausetPartitionerRequest {
+                prev_user_key: keys[i - 1].0,
+                current_user_key: key,
+                current_output_file_size: output_file_size,
+            };
+            if partitioner.should_partition(&req) == CausetPartitionerResult::Required {
+                cuts.push(i);
+                output_file_size = 0;
+            }
+        }
+        output_file_size += size;
+    }
+    cuts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cuts at a change of the first key byte, and at `max_size` on level 6.
+    struct PrefixFactory {
+        name: CString,
+        max_size: u64,
+    }
+
+    struct PrefixPartitioner {
+        max_size: Option<u64>,
+    }
+
+    impl ContextPartitioner for PrefixPartitioner {
+        fn should_partition(&mut self, ctx: &PartitionerContext<'_>) -> CausetPartitionerResult {
+            assert_eq!(ctx.namespaced, "write");
+            let prefix_changed = ctx.prev_user_key[..1] != ctx.current_user_key[..1];
+            let too_big = self
+                .max_size
+                .map_or(false, |max| ctx.current_output_file_size >= max);
+            if prefix_changed || too_big {
+                CausetPartitionerResult::Required
+            } else {
+                CausetPartitionerResult::NotRequired
+            }
+        }
+    }
+
+    impl ContextPartitionerFactory for PrefixFactory {
+        fn name(&self) -> &CString {
+            &self.name
+        }
+
+        fn create_partitioner(
+            &self,
+            ctx: &PartitionerContext<'_>,
+        ) -> Option<Box<dyn ContextPartitioner>> {
+            if ctx.is_manual_jet_bundle {
+                return None;
+            }
+            let max_size = if ctx.output_l_naught == 6 {
+                Some(self.max_size)
+            } else {
+                None
+            };
+            Some(Box::new(PrefixPartitioner { max_size }))
+        }
+    }
+
+    fn context(
+        output_l_naught: i32,
+        is_manual_jet_bundle: bool,
+    ) -> CausetPartitionerContext<'static> {
+        CausetPartitionerContext {
+            is_full_jet_bundle: false,
+            is_manual_jet_bundle,
+            output_l_naught,
+            smallest_key: b"a",
+            largest_key: b"c",
+        }
+    }
+
+    #[test]
+    fn test_replay_key_stream() {
+        let registry = PartitionerRegistry::new().register(
+            "write",
+            PrefixFactory {
+                name: CString::new("prefix").unwrap(),
+                max_size: 20,
+            },
+        );
+        assert!(registry.factory("default").is_none());
+        let factory = registry.factory("write").unwrap();
+        assert_eq!(factory.name().to_str().unwrap(), "prefix");
+
+        let keys: Vec<(&[u8], u64)> = vec![
+            (b"a1", 10),
+            (b"a2", 10),
+            (b"a3", 10),
+            (b"b1", 10),
+            (b"c1", 10),
+            (b"c2", 10),
+        ];
+        assert_eq!(replay_key_stream(&factory, &context(1, false), &keys), vec![3, 4]);
+        assert_eq!(replay_key_stream(&factory, &context(6, false), &keys), vec![2, 3, 4]);
+        assert!(replay_key_stream(&factory, &context(6, true), &keys).is_empty());
+    }
+}
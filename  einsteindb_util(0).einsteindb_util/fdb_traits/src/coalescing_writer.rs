@@ -0,0 +1,304 @@
+// Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! Coalescing of single writes into write batches
+//!
+//! Writing keys one `put_namespaced` at a time pays the cost of a write,
+//! including its WAL append, per key. A `CoalescingWriter` implements
+//! `SyncMutable`, so it can stand in for an einstein_merkle_tree at such
+//! call sites, but buffers the mutations in a write batch and writes the batch
+//! once it holds enough keys or bytes, or its oldest mutation has waited long
+//! enough.
+//!
+//! Buffered mutations are not visible to readers of the einstein_merkle_tree
+//! until they are flushed. There is no background thread: the delay threshold
+//! is checked on each mutation and by `flush_if_due`. Dropping the writer
+//! flushes whatever is buffered; callers that need to see the result of the
+//! last write should call `flush` before dropping it.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::errors::Result;
+use crate::mutable::SyncMutable;
+use crate::options::WriteOptions;
+use crate::write_batch::{Mutable, WriteBatch, WriteBatchExt};
+
+/// When a `CoalescingWriter` writes its buffered mutations
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoalescingConfig {
+    /// Flush once this many mutations are buffered
+    pub max_keys: usize,
+    /// Flush once the buffered batch is this many bytes
+    pub max_bytes: usize,
+    /// Flush once the oldest buffered mutation has waited this long
+    pub max_delay: Duration,
+}
+
+impl Default for CoalescingConfig {
+    fn default() -> CoalescingConfig {
+        CoalescingConfig {
+            max_keys: 256,
+            max_bytes: 1024 * 1024,
+            max_delay: Duration::from_millis(10),
+        }
+    }
+}
+
+struct Pending<W> {
+    batch: W,
+    since: Option<Instant>,
+}
+
+/// A `SyncMutable` that buffers mutations into write batches
+pub struct CoalescingWriter<E: WriteBatchExt> {
+    einstein_merkle_tree: E,
+    config: CoalescingConfig,
+    write_options: WriteOptions,
+    pending: Mutex<Pending<E::WriteBatch>>,
+}
+
+impl<E: WriteBatchExt> CoalescingWriter<E> {
+    pub fn new(einstein_merkle_tree: E, config: CoalescingConfig) -> CoalescingWriter<E> {
+        let batch = einstein_merkle_tree.write_batch_with_cap(config.max_keys);
+        CoalescingWriter {
+            einstein_merkle_tree,
+            config,
+            write_options: WriteOptions::default(),
+            pending: Mutex::new(Pending { batch, since: None }),
+        }
+    }
+
+    /// Sets the options every flush writes with
+    pub fn set_write_options(&mut self, opts: WriteOptions) {
+        self.write_options = opts;
+    }
+
+    pub fn config(&self) -> &CoalescingConfig {
+        &self.config
+    }
+
+    /// The number of buffered mutations
+    pub fn pending_count(&self) -> usize {
+        self.pending.lock().unwrap().batch.count()
+    }
+
+    /// Writes every buffered mutation
+    ///
+    /// If the write fails, the mutations stay buffered, and the next flush
+    /// tries to write them again.
+    pub fn flush(&self) -> Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        self.write(&mut pending)
+    }
+
+    /// Writes the buffered mutations if the oldest has waited at least
+    /// `max_delay`, returning whether it did
+    pub fn flush_if_due(&self) -> Result<bool> {
+        let mut pending = self.pending.lock().unwrap();
+        match pending.since {
+            Some(since) if since.elapsed() >= self.config.max_delay => {
+                self.write(&mut pending)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn write(&self, pending: &mut Pending<E::WriteBatch>) -> Result<()> {
+        if !pending.batch.is_empty() {
+            pending.batch.write_opt(&self.write_options)?;
+            pending.batch.clear();
+        }
+        pending.since = None;
+        Ok(())
+    }
+
+    /// Buffers a mutation, flushing if that crosses a threshold
+    ///
+    /// A mutation that returns an error is not buffered: if `f` or the flush
+    /// it triggers fails, the batch is rolled back to what it held before, so
+    /// a caller retrying the mutation does not apply it twice. Mutations
+    /// buffered by earlier calls stay buffered.
+    fn mutate<F>(&self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut E::WriteBatch) -> Result<()>,
+    {
+        let mut pending = self.pending.lock().unwrap();
+        let since = pending.since;
+        pending.batch.set_save_point();
+        if let Err(e) = f(&mut pending.batch) {
+            pending.batch.rollback_to_save_point()?;
+            return Err(e);
+        }
+        let oldest = *pending.since.get_or_insert_with(Instant::now);
+        if pending.batch.count() >= self.config.max_keys
+            || pending.batch.data_size() >= self.config.max_bytes
+            || oldest.elapsed() >= self.config.max_delay
+        {
+            // A successful write clears the batch, save point included.
+            if let Err(e) = self.write(&mut pending) {
+                pending.batch.rollback_to_save_point()?;
+                pending.since = since;
+                return Err(e);
+            }
+        } else {
+            pending.batch.pop_save_point()?;
+        }
+        Ok(())
+    }
+}
+
+impl<E: WriteBatchExt> SyncMutable for CoalescingWriter<E> {
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.mutate(|wb| wb.put(key, value))
+    }
+
+    fn put_namespaced(&self, namespaced: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.mutate(|wb| wb.put_namespaced(namespaced, key, value))
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        self.mutate(|wb| wb.delete(key))
+    }
+
+    fn delete_namespaced(&self, namespaced: &str, key: &[u8]) -> Result<()> {
+        self.mutate(|wb| wb.delete_namespaced(namespaced, key))
+    }
+
+    fn delete_range(&self, begin_key: &[u8], end_key: &[u8]) -> Result<()> {
+        self.mutate(|wb| wb.delete_range(begin_key, end_key))
+    }
+
+    fn delete_range_namespaced(&self, namespaced: &str, begin_key: &[u8], end_key: &[u8]) -> Result<()> {
+        self.mutate(|wb| wb.delete_range_namespaced(namespaced, begin_key, end_key))
+    }
+}
+
+impl<E: WriteBatchExt> Drop for CoalescingWriter<E> {
+    fn drop(&mut self) {
+        let pending = match self.pending.get_mut() {
+            Ok(pending) => pending,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if pending.batch.is_empty() {
+            return;
+        }
+        if let Err(e) = pending.batch.write_opt(&self.write_options) {
+            slog_global::error!(
+                "failed to flush coalesced writes on drop";
+                "count" => pending.batch.count(),
+                "err" => ?e,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+
+    use crate::mock_write_batch::MockEngine;
+
+    #[test]
+    fn test_coalescing_writer() {
+        let engine = MockEngine::default();
+        let config = CoalescingConfig {
+            max_keys: 3,
+            max_bytes: usize::MAX,
+            max_delay: Duration::from_secs(3600),
+        };
+        let writer = CoalescingWriter::new(engine.clone(), config);
+
+        // Flushes once max_keys mutations are buffered.
+        writer.put(b"a", b"1").unwrap();
+        writer.put_namespaced("write", b"b", b"2").unwrap();
+        assert_eq!(writer.pending_count(), 2);
+        assert!(engine.written.lock().unwrap().is_empty());
+        writer.delete(b"a").unwrap();
+        assert_eq!(writer.pending_count(), 0);
+        assert_eq!(
+            *engine.written.lock().unwrap(),
+            vec![vec![
+                "put default a 1".to_owned(),
+                "put write b 2".to_owned(),
+                "delete default a".to_owned(),
+            ]]
+        );
+
+        // Explicit flushes, which do nothing when nothing is buffered.
+        writer.delete_range_namespaced("lock", b"c", b"d").unwrap();
+        assert!(!writer.flush_if_due().unwrap());
+        writer.flush().unwrap();
+        writer.flush().unwrap();
+        assert_eq!(engine.written.lock().unwrap().len(), 2);
+
+        // Dropping flushes.
+        writer.put(b"e", b"5").unwrap();
+        drop(writer);
+        assert_eq!(
+            engine.written.lock().unwrap().last().unwrap(),
+            &vec!["put default e 5".to_owned()]
+        );
+    }
+
+    #[test]
+    fn test_coalescing_writer_thresholds() {
+        let engine = MockEngine::default();
+        let by_size = CoalescingWriter::new(
+            engine.clone(),
+            CoalescingConfig {
+                max_keys: usize::MAX,
+                max_bytes: 20,
+                max_delay: Duration::from_secs(3600),
+            },
+        );
+        by_size.put(b"a", b"1").unwrap();
+        assert_eq!(by_size.pending_count(), 1);
+        by_size.put(b"b", b"2").unwrap();
+        assert_eq!(by_size.pending_count(), 0);
+        assert_eq!(engine.written.lock().unwrap().len(), 1);
+
+        let by_delay = CoalescingWriter::new(
+            engine.clone(),
+            CoalescingConfig {
+                max_keys: usize::MAX,
+                max_bytes: usize::MAX,
+                max_delay: Duration::from_millis(0),
+            },
+        );
+        by_delay.put(b"a", b"1").unwrap();
+        assert_eq!(by_delay.pending_count(), 0);
+        assert_eq!(engine.written.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_coalescing_writer_retry_after_failed_flush() {
+        let engine = MockEngine::default();
+        let writer = CoalescingWriter::new(
+            engine.clone(),
+            CoalescingConfig {
+                max_keys: 2,
+                max_bytes: usize::MAX,
+                max_delay: Duration::from_secs(3600),
+            },
+        );
+        writer.put(b"a", b"1").unwrap();
+
+        // The flush fails: the failed mutation is not left buffered, the
+        // earlier one is.
+        engine.fail_writes.store(true, Ordering::SeqCst);
+        assert!(writer.put(b"b", b"2").is_err());
+        assert_eq!(writer.pending_count(), 1);
+        assert!(engine.written.lock().unwrap().is_empty());
+
+        // Retrying writes each mutation once.
+        engine.fail_writes.store(false, Ordering::SeqCst);
+        writer.put(b"b", b"2").unwrap();
+        assert_eq!(writer.pending_count(), 0);
+        assert_eq!(
+            *engine.written.lock().unwrap(),
+            vec![vec!["put default a 1".to_owned(), "put default b 2".to_owned()]]
+        );
+    }
+}
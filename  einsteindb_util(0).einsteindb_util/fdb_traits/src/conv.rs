@@ -0,0 +1,216 @@
+// Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! Conversions between this crate's `Error` and the errors of its neighbours
+//!
+//! Crates that sit beside each other in the dependency graph, like `violetabft`
+//! and the coprocessor, can't name each other's errors. Every conversion
+//! between them goes through `Error` here, so that it happens in one place and
+//! loses as little as possible: an `Error` converted to another type and back
+//! is the same error, context included, and `class_of` still classifies an
+//! `Error` buried in another error's source chain.
+//!
+//! Crates that can name `Error` convert with `From`; `IntoOther` adapts
+//! whole `Result`s. Crates that can't, because they don't depend on this one,
+//! take `Error::into_boxed` and recover the class with `class_of`.
+//!
+//! Crates built on `failure`, like the store's `einsteindbError` and the
+//! coprocessor's `StorageError`, keep an `Error` as a cause: `class_of_fail`
+//! finds it in their cause chain.
+
+use std::error;
+use std::result;
+
+use einsteindb_traits::errors::{einsteindbError, einsteindbErrorKind};
+use failure::Fail;
+use violetabft::{Error as VioletaBFTError, StorageError};
+
+use crate::errors::{Error, ErrorClass};
+
+/// Converts into a type from an unrelated crate
+pub trait IntoOther<O> {
+    fn into_other(self) -> O;
+}
+
+impl<T, E, O> IntoOther<result::Result<T, O>> for result::Result<T, E>
+where
+    E: IntoOther<O>,
+{
+    fn into_other(self) -> result::Result<T, O> {
+        self.map_err(|e| e.into_other())
+    }
+}
+
+impl IntoOther<VioletaBFTError> for Error {
+    fn into_other(self) -> VioletaBFTError {
+        self.into()
+    }
+}
+
+impl IntoOther<Error> for VioletaBFTError {
+    fn into_other(self) -> Error {
+        self.into()
+    }
+}
+
+impl From<Error> for VioletaBFTError {
+    fn from(e: Error) -> VioletaBFTError {
+        match e.root() {
+            Error::EntriesUnavailable => return VioletaBFTError::TimelikeStore(StorageError::Unavailable),
+            Error::EntriesCompacted => return VioletaBFTError::TimelikeStore(StorageError::Compacted),
+            _ => {}
+        }
+        VioletaBFTError::TimelikeStore(StorageError::Other(e.into_boxed()))
+    }
+}
+
+impl From<VioletaBFTError> for Error {
+    fn from(e: VioletaBFTError) -> Error {
+        match e {
+            VioletaBFTError::TimelikeStore(StorageError::Unavailable) => Error::EntriesUnavailable,
+            VioletaBFTError::TimelikeStore(StorageError::Compacted) => Error::EntriesCompacted,
+            VioletaBFTError::TimelikeStore(StorageError::Other(boxed)) => Error::from_boxed(boxed),
+            VioletaBFTError::Io(e) => Error::Io(e),
+            e => Error::Other(Box::new(e)),
+        }
+    }
+}
+
+impl IntoOther<einsteindbError> for Error {
+    fn into_other(self) -> einsteindbError {
+        self.into()
+    }
+}
+
+impl IntoOther<Error> for einsteindbError {
+    fn into_other(self) -> Error {
+        self.into()
+    }
+}
+
+/// The store has no kind of its own for engine failures, so they take the
+/// catch-all `NotYetImplemented` kind, with the `Error` kept as the cause for
+/// `class_of_fail`.
+impl From<Error> for einsteindbError {
+    fn from(e: Error) -> einsteindbError {
+        let kind = einsteindbErrorKind::NotYetImplemented(format!("storage engine: {}", e));
+        Fail::context(e, kind).into()
+    }
+}
+
+impl From<einsteindbError> for Error {
+    fn from(e: einsteindbError) -> Error {
+        Error::Other(Box::new(e.compat()))
+    }
+}
+
+impl Error {
+    /// Boxes the error for crates that can't name it
+    pub fn into_boxed(self) -> Box<dyn error::Error + Send + Sync> {
+        Box::new(self)
+    }
+
+    /// Unboxes an error, returning the original if it was an `Error`
+    pub fn from_boxed(boxed: Box<dyn error::Error + Send + Sync>) -> Error {
+        match boxed.downcast::<Error>() {
+            Ok(e) => *e,
+            Err(boxed) => Error::Other(boxed),
+        }
+    }
+}
+
+/// Classifies any error by the first `Error` or `std::io::Error` in its source
+/// chain, or as fatal if there is none.
+pub fn class_of(err: &(dyn error::Error + 'static)) -> ErrorClass {
+    let mut next = Some(err);
+    while let Some(err) = next {
+        if let Some(e) = err.downcast_ref::<Error>() {
+            return e.class();
+        }
+        if let Some(e) = err.downcast_ref::<std::io::Error>() {
+            return ErrorClass::from_io_error(e);
+        }
+        next = err.source();
+    }
+    ErrorClass::Fatal
+}
+
+/// Classifies a `failure::Fail` by the first `Error` or `std::io::Error` in
+/// its cause chain, or as fatal if there is none.
+pub fn class_of_fail(err: &dyn Fail) -> ErrorClass {
+    for cause in err.iter_chain() {
+        if let Some(e) = cause.downcast_ref::<Error>() {
+            return e.class();
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return ErrorClass::from_io_error(e);
+        }
+    }
+    ErrorClass::Fatal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_violetabft_round_trip() {
+        let e: VioletaBFTError = Error::EntriesCompacted.context("fetching entries").into_other();
+        assert!(matches!(e, VioletaBFTError::TimelikeStore(StorageError::Compacted)));
+        let e: Error = e.into_other();
+        assert!(matches!(e, Error::EntriesCompacted));
+
+        let e = Error::NAMESPACEDName("lock".to_owned()).context("opening");
+        let msg = e.to_string();
+        let e: Error = VioletaBFTError::from(e).into();
+        assert_eq!(e.to_string(), msg);
+        assert_eq!(e.class(), ErrorClass::InvalidArgument);
+
+        let r: result::Result<(), Error> = Err(Error::EntriesUnavailable);
+        let r: result::Result<(), VioletaBFTError> = r.into_other();
+        assert!(matches!(
+            r,
+            Err(VioletaBFTError::TimelikeStore(StorageError::Unavailable))
+        ));
+    }
+
+    #[test]
+    fn test_einsteindb_error() {
+        let e: einsteindbError = Error::EntriesCompacted.context("fetching entries").into_other();
+        assert_eq!(class_of_fail(&e), ErrorClass::Recoverable);
+        assert!(e.to_string().contains("fetching entries"));
+
+        let e: einsteindbError = Error::einstein_merkle_tree("Resource busy: ".to_owned()).into();
+        assert_eq!(class_of_fail(&e), ErrorClass::Retryable);
+        let e: Error = e.into_other();
+        assert!(matches!(e, Error::Other(_)));
+
+        let e: einsteindbError = einsteindbErrorKind::NotYetImplemented("x".to_owned()).into();
+        assert_eq!(class_of_fail(&e), ErrorClass::Fatal);
+    }
+
+    #[test]
+    fn test_class_of() {
+        #[derive(Debug)]
+        struct Wrapper(Error);
+
+        impl std::fmt::Display for Wrapper {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "wrapped")
+            }
+        }
+
+        impl error::Error for Wrapper {
+            fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+                Some(&self.0)
+            }
+        }
+
+        let wrapped = Wrapper(Error::einstein_merkle_tree("Resource busy: ".to_owned()));
+        assert_eq!(class_of(&wrapped), ErrorClass::Retryable);
+        let boxed = Error::EntriesCompacted.into_boxed();
+        assert_eq!(class_of(boxed.as_ref()), ErrorClass::Recoverable);
+        let io = std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out");
+        assert_eq!(class_of(&io), ErrorClass::Retryable);
+        assert_eq!(class_of(&std::fmt::Error), ErrorClass::Fatal);
+    }
+}
@@ -40,6 +40,21 @@ pub trait KV:
     /// Create a lightlike_persistence
     fn lightlike_persistence(&self) -> Self::LightlikePersistence;
 
+    /// Create a lightlike_persistence without blocking on its acquisition
+    ///
+    /// `cb` is called with the lightlike_persistence once it is acquired,
+    /// possibly on another thread, so a poll thread can go on serving other
+    /// requests meanwhile. einstein_merkle_trees whose lightlike_persistences
+    /// can stall under heavy writes should override this; by default the
+    /// lightlike_persistence is created synchronously and `cb` is called before
+    /// returning.
+    fn async_lightlike_persistence(
+        &self,
+        cb: LightlikePersistenceCallback<Self::LightlikePersistence>,
+    ) {
+        cb(Ok(self.lightlike_persistence()))
+    }
+
     /// Syncs any writes to disk
     fn sync(&self) -> Result<()>;
 
@@ -3,7 +3,7 @@
 use crate::fdb_lsh_treeKV;
 use crate::errors::Result;
 use crate::options::WriteOptions;
-use crate::violetabft_einstein_merkle_tree::VioletaBFTeinstein_merkle_tree;
+use crate::violetabft_engine::VioletaBFTeinstein_merkle_tree;
 use crate::write_batch::WriteBatch;
 
 #[derive(Clone, Debug)]
@@ -1,6 +1,10 @@
 // Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
-use crate::errors::Result;
+use std::sync::{Arc, Mutex};
+
+use crate::errors::{Error, Result};
+use crate::io_limiter::IOClassLimiter;
+use crate::namespaced_options::{ColumnFamilyOptions, NAMESPACEDOptionsExt};
 
 /// A trait for einstein_merkle_trees that support setting global options
 pub trait DBOptionsExt {
@@ -8,6 +12,391 @@ pub trait DBOptionsExt {
 
     fn get_db_options(&self) -> Self::DBOptions;
     fn set_db_options(&self, options: &[(&str, &str)]) -> Result<()>;
+
+    /// Like `set_db_options`, but the per-class I/O rates named by
+    /// `IOClass::rate_option` are applied to `limiter` instead
+    fn set_db_options_with_io_limiter(
+        &self,
+        limiter: &IOClassLimiter,
+        options: &[(&str, &str)],
+    ) -> Result<()> {
+        let rest = limiter.apply_options(options)?;
+        if rest.is_empty() {
+            return Ok(());
+        }
+        self.set_db_options(&rest)
+    }
+
+    /// Whether the option `name` can be changed while the einstein_merkle_tree is open
+    fn supports_dynamic_db_option(&self, name: &str) -> bool {
+        DYNAMIC_DB_OPTIONS.contains(&name)
+    }
+
+    /// The current value of the option `name`, if the einstein_merkle_tree can report it
+    ///
+    /// See `DBOptions::get_option`.
+    fn get_db_option(&self, name: &str) -> Option<String> {
+        self.get_db_options().get_option(name)
+    }
+
+    /// Applies `diff` as a unit
+    ///
+    /// Every option is first checked with `supports_dynamic_db_option`; if
+    /// any isn't supported nothing is applied. The options are then set one at
+    /// a time and, where `get_db_option` can report them, read back. A value
+    /// reads back the same if it's spelled differently but means the same, as
+    /// `1k` and `1024` or `true` and `1` do; see `option_values_match`. If one
+    /// is rejected or reads back differently, those already set are restored
+    /// to their previous values, latest first, and the rest are skipped.
+    fn apply_options(&self, diff: &[(&str, &str)]) -> ApplyOptionsResult {
+        let mut results: Vec<OptionResult> = diff
+            .iter()
+            .map(|&(name, value)| OptionResult {
+                name: name.to_owned(),
+                value: value.to_owned(),
+                outcome: OptionOutcome::Skipped,
+            })
+            .collect();
+
+        let mut unsupported = false;
+        for result in &mut results {
+            if !self.supports_dynamic_db_option(&result.name) {
+                result.outcome = OptionOutcome::Unsupported;
+                unsupported = true;
+            }
+        }
+        if unsupported {
+            return ApplyOptionsResult { options: results };
+        }
+
+        let mut failed = None;
+        for (i, result) in results.iter_mut().enumerate() {
+            let previous = self.get_db_option(&result.name);
+            if let Err(e) = self.set_db_options(&[(result.name.as_str(), result.value.as_str())]) {
+                result.outcome = OptionOutcome::Rejected(e.to_string());
+                failed = Some(i);
+                break;
+            }
+            match self.get_db_option(&result.name) {
+                Some(ref actual) if !option_values_match(&result.value, actual) => {
+                    result.outcome = OptionOutcome::Mismatch {
+                        previous,
+                        actual: actual.clone(),
+                    };
+                    failed = Some(i);
+                    break;
+                }
+                actual => {
+                    result.outcome = OptionOutcome::Applied {
+                        previous,
+                        verified: actual.is_some(),
+                    }
+                }
+            }
+        }
+
+        if let Some(failed) = failed {
+            for result in results[..=failed].iter_mut().rev() {
+                let previous = match &result.outcome {
+                    OptionOutcome::Applied { previous, .. }
+                    | OptionOutcome::Mismatch { previous, .. } => previous.clone(),
+                    _ => continue,
+                };
+                let previous = match previous {
+                    Some(previous) => previous,
+                    None => {
+                        result.outcome =
+                            OptionOutcome::RollbackFailed("previous value unknown".to_owned());
+                        continue;
+                    }
+                };
+                result.outcome = match self.set_db_options(&[(result.name.as_str(), previous.as_str())]) {
+                    Ok(()) => OptionOutcome::RolledBack,
+                    Err(e) => OptionOutcome::RollbackFailed(e.to_string()),
+                };
+            }
+        }
+        ApplyOptionsResult { options: results }
+    }
+}
+
+/// The database options FdbDB can change while open
+pub const DYNAMIC_DB_OPTIONS: &[&str] = &[
+    "max_background_jobs",
+    "max_background_compactions",
+    "max_background_flushes",
+    "avoid_flush_during_shutdown",
+    "writable_file_max_buffer_size",
+    "delayed_write_rate",
+    "max_total_wal_size",
+    "delete_obsolete_files_period_micros",
+    "stats_dump_period_sec",
+    "max_open_files",
+    "bytes_per_sync",
+    "wal_bytes_per_sync",
+    "compaction_readahead_size",
+    "rate_bytes_per_sec",
+    "rate_limiter_auto_tuned",
+];
+
+/// The number an option value spells, with FdbDB's size suffixes: `k`, `m`,
+/// `g` and `t`, in either case, multiply by powers of 1024
+fn parse_option_number(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (digits, shift) = match value.chars().last()?.to_ascii_lowercase() {
+        'k' => (&value[..value.len() - 1], 10),
+        'm' => (&value[..value.len() - 1], 20),
+        'g' => (&value[..value.len() - 1], 30),
+        't' => (&value[..value.len() - 1], 40),
+        _ => (value, 0),
+    };
+    digits.parse::<i64>().ok()?.checked_mul(1 << shift)
+}
+
+fn parse_option_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Whether the option value `actual`, as read back, is the value `expected`
+/// that was set
+///
+/// FdbDB reads options back in a canonical form, so values are compared as
+/// numbers or booleans where both parse as one, and as strings otherwise.
+pub fn option_values_match(expected: &str, actual: &str) -> bool {
+    if expected.trim() == actual.trim() {
+        return true;
+    }
+    if let (Some(expected), Some(actual)) = (parse_option_number(expected), parse_option_number(actual)) {
+        return expected == actual;
+    }
+    if let (Some(expected), Some(actual)) = (parse_option_bool(expected), parse_option_bool(actual)) {
+        return expected == actual;
+    }
+    false
+}
+
+/// What `DBOptionsExt::apply_options` did with an option
+#[derive(Clone, Debug, PartialEq)]
+pub enum OptionOutcome {
+    /// Set, and read back as set if `verified`
+    Applied {
+        previous: Option<String>,
+        verified: bool,
+    },
+    /// Not set: the option can't be changed while open
+    Unsupported,
+    /// Not set: the einstein_merkle_tree rejected the value
+    Rejected(String),
+    /// Set, but read back as `actual`; restored if the rollback succeeded
+    Mismatch {
+        previous: Option<String>,
+        actual: String,
+    },
+    /// Set, then restored because a later option failed
+    RolledBack,
+    /// Set, and couldn't be restored when a later option failed
+    RollbackFailed(String),
+    /// Not set because another option failed
+    Skipped,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OptionResult {
+    pub name: String,
+    pub value: String,
+    pub outcome: OptionOutcome,
+}
+
+/// The outcome of `DBOptionsExt::apply_options` for each option, in order
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApplyOptionsResult {
+    pub options: Vec<OptionResult>,
+}
+
+impl ApplyOptionsResult {
+    /// Whether every option was applied
+    pub fn is_applied(&self) -> bool {
+        self.options
+            .iter()
+            .all(|o| matches!(o.outcome, OptionOutcome::Applied { .. }))
+    }
+
+    /// Whether a failed rollback left the options partly applied
+    pub fn is_partial(&self) -> bool {
+        !self.is_applied()
+            && self.options.iter().any(|o| {
+                matches!(
+                    o.outcome,
+                    OptionOutcome::Applied { .. } | OptionOutcome::RollbackFailed(_)
+                )
+            })
+    }
+}
+
+/// How `BlockCacheExt::new_shared_block_cache` builds a block cache
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockCacheOptions {
+    pub capacity: u64,
+    /// Whether an insert into a full cache fails rather than exceeding
+    /// `capacity`
+    pub strict_capacity_limit: bool,
+    /// The cache is split into `2^num_shard_bits` shards; negative picks a
+    /// number based on `capacity`
+    pub num_shard_bits: i32,
+    /// The share of `capacity` reserved for index and filter blocks
+    pub high_pri_pool_ratio: f64,
+}
+
+/// The most shards a block cache can be split into is `2^MAX_BLOCK_CACHE_SHARD_BITS`
+pub const MAX_BLOCK_CACHE_SHARD_BITS: i32 = 19;
+
+impl BlockCacheOptions {
+    pub fn new(capacity: u64) -> BlockCacheOptions {
+        BlockCacheOptions {
+            capacity,
+            strict_capacity_limit: false,
+            num_shard_bits: -1,
+            high_pri_pool_ratio: 0.0,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.capacity == 0 {
+            return Err(Error::Other("block cache capacity must be positive".into()));
+        }
+        if self.num_shard_bits > MAX_BLOCK_CACHE_SHARD_BITS {
+            return Err(Error::Other(
+                format!(
+                    "block cache num_shard_bits {} exceeds {}",
+                    self.num_shard_bits, MAX_BLOCK_CACHE_SHARD_BITS
+                )
+                .into(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.high_pri_pool_ratio) {
+            return Err(Error::Other(
+                format!(
+                    "block cache high_pri_pool_ratio {} isn't in [0, 1]",
+                    self.high_pri_pool_ratio
+                )
+                .into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How much of a block cache is in use
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockCacheUsage {
+    pub capacity: u64,
+    pub usage: u64,
+    /// The bytes of entries in use by readers, which can't be evicted
+    pub pinned_usage: u64,
+}
+
+/// A trait for einstein_merkle_trees whose column families can share a
+/// block cache, so that many open namespaces are bounded by one capacity
+/// rather than each by its own
+pub trait BlockCacheExt: NAMESPACEDOptionsExt {
+    type BlockCache: Clone + Send + Sync;
+    /// The block-based table options a column family's table factory is
+    /// built from
+    type BlockBasedOptions;
+
+    fn new_shared_block_cache(options: &BlockCacheOptions) -> Result<Self::BlockCache>;
+
+    /// Makes tables built with `block_based_options` read through `cache`,
+    /// keeping the other settings of `block_based_options`
+    fn set_block_cache(block_based_options: &mut Self::BlockBasedOptions, cache: &Self::BlockCache);
+
+    /// The usage of the block cache of the open column family `namespaced`
+    fn get_block_cache_usage_namespaced(&self, namespaced: &str) -> Result<BlockCacheUsage>;
+}
+
+/// A block cache and the column families it's attached to
+///
+/// Clones share the cache and the list of column families.
+pub struct SharedBlockCache<E: BlockCacheExt> {
+    cache: E::BlockCache,
+    options: BlockCacheOptions,
+    namespaces: Arc<Mutex<Vec<String>>>,
+}
+
+impl<E: BlockCacheExt> Clone for SharedBlockCache<E> {
+    fn clone(&self) -> Self {
+        SharedBlockCache {
+            cache: self.cache.clone(),
+            options: self.options.clone(),
+            namespaces: self.namespaces.clone(),
+        }
+    }
+}
+
+impl<E: BlockCacheExt> SharedBlockCache<E> {
+    pub fn new(options: BlockCacheOptions) -> Result<SharedBlockCache<E>> {
+        options.validate()?;
+        let cache = E::new_shared_block_cache(&options)?;
+        Ok(SharedBlockCache {
+            cache,
+            options,
+            namespaces: Arc::new(Mutex::new(vec![])),
+        })
+    }
+
+    /// The options the cache was built with
+    pub fn options(&self) -> &BlockCacheOptions {
+        &self.options
+    }
+
+    pub fn cache(&self) -> &E::BlockCache {
+        &self.cache
+    }
+
+    /// Attaches the cache to the column family `namespaced`, whose table
+    /// factory is to be built from `block_based_options`
+    pub fn attach(&self, namespaced: &str, block_based_options: &mut E::BlockBasedOptions) {
+        E::set_block_cache(block_based_options, &self.cache);
+        let mut namespaces = self.namespaces.lock().unwrap();
+        if !namespaces.iter().any(|n| n == namespaced) {
+            namespaces.push(namespaced.to_owned());
+        }
+    }
+
+    /// The column families attached, in the order they were
+    pub fn namespaces(&self) -> Vec<String> {
+        self.namespaces.lock().unwrap().clone()
+    }
+
+    fn any_namespace(&self) -> Result<String> {
+        self.namespaces
+            .lock()
+            .unwrap()
+            .first()
+            .cloned()
+            .ok_or_else(|| Error::Other("the block cache isn't attached to a column family".into()))
+    }
+
+    /// The usage of the cache, read through an attached column family of
+    /// `engine`
+    pub fn usage(&self, engine: &E) -> Result<BlockCacheUsage> {
+        engine.get_block_cache_usage_namespaced(&self.any_namespace()?)
+    }
+
+    /// Resizes the cache, for every column family attached
+    pub fn set_capacity(&self, engine: &E, capacity: u64) -> Result<()> {
+        if capacity == 0 {
+            return Err(Error::Other("block cache capacity must be positive".into()));
+        }
+        engine
+            .get_options_namespaced(&self.any_namespace()?)?
+            .set_block_cache_capacity(capacity)
+            .map_err(|e| Error::Other(e.into()))
+    }
 }
 
 /// A handle to a database's options
@@ -21,6 +410,21 @@ pub trait DBOptions {
     fn get_rate_limiter_auto_tuned(&self) -> Option<bool>;
     fn set_rate_limiter_auto_tuned(&mut self, rate_limiter_auto_tuned: bool) -> Result<()>;
     fn set_titandb_options(&mut self, opts: &Self::TitanDBOptions);
+
+    /// The value of the option `name`, of those in `DYNAMIC_DB_OPTIONS`, if
+    /// this handle can report it
+    ///
+    /// The default reports the options with getters above; einstein_merkle_trees
+    /// that can read more of the options back override it. Options it can't
+    /// report are applied unverified by `DBOptionsExt::apply_options`.
+    fn get_option(&self, name: &str) -> Option<String> {
+        match name {
+            "max_background_jobs" => Some(self.get_max_background_jobs().to_string()),
+            "rate_bytes_per_sec" => self.get_rate_bytes_per_sec().map(|r| r.to_string()),
+            "rate_limiter_auto_tuned" => self.get_rate_limiter_auto_tuned().map(|t| t.to_string()),
+            _ => None,
+        }
+    }
 }
 
 /// Titan-specefic options
@@ -28,3 +432,382 @@ pub trait TitanDBOptions {
     fn new() -> Self;
     fn set_min_blob_size(&mut self, size: u64);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    use crate::causet_partitioner::CausetPartitionerFactory;
+
+    /// Accepts numeric and boolean values of the options in
+    /// `DYNAMIC_DB_OPTIONS`, which read back in canonical form, but clamps
+    /// `max_open_files` to 100.
+    #[derive(Default)]
+    struct MockEngine {
+        options: Mutex<HashMap<String, String>>,
+        namespaces: Mutex<HashMap<String, MockColumnFamilyOptions>>,
+    }
+
+    impl MockEngine {
+        fn open_namespaced(&self, namespaced: &str, options: MockColumnFamilyOptions) {
+            self.namespaces
+                .lock()
+                .unwrap()
+                .insert(namespaced.to_owned(), options);
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct MockBlockCache(Arc<Mutex<BlockCacheUsage>>);
+
+    #[derive(Clone, Default)]
+    struct MockBlockBasedOptions {
+        block_size: usize,
+        block_cache: Option<MockBlockCache>,
+    }
+
+    /// Column family options as their names and values
+    #[derive(Clone, Default)]
+    struct MockColumnFamilyOptions {
+        table: MockBlockBasedOptions,
+        options: HashMap<String, String>,
+    }
+
+    impl MockColumnFamilyOptions {
+        fn set_block_based_table_factory(&mut self, table: &MockBlockBasedOptions) {
+            self.table = table.clone();
+        }
+
+        fn get_parsed<T: std::str::FromStr>(&self, name: &str) -> Option<T> {
+            self.options.get(name).and_then(|v| v.parse().ok())
+        }
+
+        fn set(&mut self, name: &str, value: impl ToString) {
+            self.options.insert(name.to_owned(), value.to_string());
+        }
+    }
+
+    /// A snapshot of the options of a `MockEngine`
+    struct MockDBOptions(HashMap<String, String>);
+
+    impl MockDBOptions {
+        fn get_parsed<T: std::str::FromStr>(&self, name: &str) -> Option<T> {
+            self.0.get(name).and_then(|v| v.parse().ok())
+        }
+    }
+
+    struct MockTitanDBOptions;
+
+    impl TitanDBOptions for MockTitanDBOptions {
+        fn new() -> Self {
+            MockTitanDBOptions
+        }
+        fn set_min_blob_size(&mut self, _: u64) {}
+    }
+
+    impl DBOptions for MockDBOptions {
+        type TitanDBOptions = MockTitanDBOptions;
+
+        fn new() -> Self {
+            MockDBOptions(HashMap::new())
+        }
+        fn get_max_background_jobs(&self) -> i32 {
+            self.get_parsed("max_background_jobs").unwrap_or(2)
+        }
+        fn get_rate_bytes_per_sec(&self) -> Option<i64> {
+            self.get_parsed("rate_bytes_per_sec")
+        }
+        fn set_rate_bytes_per_sec(&mut self, rate_bytes_per_sec: i64) -> Result<()> {
+            self.0.insert("rate_bytes_per_sec".to_owned(), rate_bytes_per_sec.to_string());
+            Ok(())
+        }
+        fn get_rate_limiter_auto_tuned(&self) -> Option<bool> {
+            self.get_parsed("rate_limiter_auto_tuned")
+        }
+        fn set_rate_limiter_auto_tuned(&mut self, rate_limiter_auto_tuned: bool) -> Result<()> {
+            self.0.insert("rate_limiter_auto_tuned".to_owned(), rate_limiter_auto_tuned.to_string());
+            Ok(())
+        }
+        fn set_titandb_options(&mut self, _: &MockTitanDBOptions) {}
+
+        fn get_option(&self, name: &str) -> Option<String> {
+            self.0.get(name).cloned()
+        }
+    }
+
+    impl DBOptionsExt for MockEngine {
+        type DBOptions = MockDBOptions;
+
+        fn get_db_options(&self) -> MockDBOptions {
+            MockDBOptions(self.options.lock().unwrap().clone())
+        }
+
+        fn set_db_options(&self, options: &[(&str, &str)]) -> Result<()> {
+            let mut current = self.options.lock().unwrap();
+            for &(name, value) in options {
+                let canonical = if name == "rate_limiter_auto_tuned" {
+                    parse_option_bool(value).map(|b| b.to_string())
+                } else {
+                    parse_option_number(value)
+                        .filter(|&n| n >= 0)
+                        .map(|n| if name == "max_open_files" { n.min(100) } else { n })
+                        .map(|n| n.to_string())
+                };
+                let canonical = canonical
+                    .ok_or_else(|| Error::Other(format!("invalid {}: {}", name, value).into()))?;
+                current.insert(name.to_owned(), canonical);
+            }
+            Ok(())
+        }
+    }
+
+    impl ColumnFamilyOptions for MockColumnFamilyOptions {
+        type TitanDBOptions = MockTitanDBOptions;
+
+        fn new() -> Self {
+            MockColumnFamilyOptions::default()
+        }
+        fn get_max_write_buffer_number(&self) -> u32 {
+            self.get_parsed("max_write_buffer_number").unwrap_or(2)
+        }
+        fn get_l_naught_zero_slowdown_writes_trigger(&self) -> u32 {
+            self.get_parsed("level0_slowdown_writes_trigger").unwrap_or(20)
+        }
+        fn get_l_naught_zero_stop_writes_trigger(&self) -> u32 {
+            self.get_parsed("level0_stop_writes_trigger").unwrap_or(36)
+        }
+        fn set_l_naught_zero_file_num_jet_bundle_trigger(&mut self, v: i32) {
+            self.set("level0_file_num_compaction_trigger", v);
+        }
+        fn get_soft_pending_jet_bundle_bytes_limit(&self) -> u64 {
+            self.get_parsed("soft_pending_compaction_bytes_limit").unwrap_or(0)
+        }
+        fn get_hard_pending_jet_bundle_bytes_limit(&self) -> u64 {
+            self.get_parsed("hard_pending_compaction_bytes_limit").unwrap_or(0)
+        }
+        fn get_block_cache_capacity(&self) -> u64 {
+            self.table.block_cache.as_ref().unwrap().0.lock().unwrap().capacity
+        }
+        fn set_block_cache_capacity(&self, capacity: u64) -> std::result::Result<(), String> {
+            let cache = self.table.block_cache.as_ref().ok_or("no block cache")?;
+            cache.0.lock().unwrap().capacity = capacity;
+            Ok(())
+        }
+        fn set_titandb_options(&mut self, _: &MockTitanDBOptions) {}
+        fn get_target_file_size_base(&self) -> u64 {
+            self.get_parsed("target_file_size_base").unwrap_or(64 << 20)
+        }
+        fn get_write_buffer_size(&self) -> u64 {
+            self.get_parsed("write_buffer_size").unwrap_or(64 << 20)
+        }
+        fn set_disable_auto_jet_bundles(&mut self, v: bool) {
+            self.set("disable_auto_compactions", v);
+        }
+        fn get_disable_auto_jet_bundles(&self) -> bool {
+            self.get_parsed("disable_auto_compactions").unwrap_or(false)
+        }
+        fn get_disable_write_stall(&self) -> bool {
+            self.get_parsed("disable_write_stall").unwrap_or(false)
+        }
+        fn set_Causet_partitioner_factory<F: CausetPartitionerFactory>(&mut self, _: F) {
+            self.set("sst_partitioner_factory", std::any::type_name::<F>());
+        }
+    }
+
+    impl NAMESPACEDOptionsExt for MockEngine {
+        type ColumnFamilyOptions = MockColumnFamilyOptions;
+
+        fn get_options_namespaced(&self, namespaced: &str) -> Result<MockColumnFamilyOptions> {
+            match self.namespaces.lock().unwrap().get(namespaced) {
+                Some(options) => Ok(options.clone()),
+                None => Err(Error::NAMESPACEDName(namespaced.to_owned())),
+            }
+        }
+
+        fn set_options_namespaced(&self, namespaced: &str, options: &[(&str, &str)]) -> Result<()> {
+            let mut namespaces = self.namespaces.lock().unwrap();
+            let current = namespaces
+                .get_mut(namespaced)
+                .ok_or_else(|| Error::NAMESPACEDName(namespaced.to_owned()))?;
+            for &(name, value) in options {
+                current.set(name, value);
+            }
+            Ok(())
+        }
+    }
+
+    impl BlockCacheExt for MockEngine {
+        type BlockCache = MockBlockCache;
+        type BlockBasedOptions = MockBlockBasedOptions;
+
+        fn new_shared_block_cache(options: &BlockCacheOptions) -> Result<MockBlockCache> {
+            Ok(MockBlockCache(Arc::new(Mutex::new(BlockCacheUsage {
+                capacity: options.capacity,
+                ..Default::default()
+            }))))
+        }
+
+        fn set_block_cache(options: &mut MockBlockBasedOptions, cache: &MockBlockCache) {
+            options.block_cache = Some(cache.clone());
+        }
+
+        fn get_block_cache_usage_namespaced(&self, namespaced: &str) -> Result<BlockCacheUsage> {
+            let options = self.get_options_namespaced(namespaced)?;
+            let cache = options.table.block_cache.unwrap_or_default();
+            let usage = *cache.0.lock().unwrap();
+            Ok(usage)
+        }
+    }
+
+    fn outcomes(result: &ApplyOptionsResult) -> Vec<&OptionOutcome> {
+        result.options.iter().map(|o| &o.outcome).collect()
+    }
+
+    #[test]
+    fn test_apply_options() {
+        let engine = MockEngine::default();
+        engine
+            .set_db_options(&[("max_background_jobs", "2"), ("bytes_per_sync", "0")])
+            .unwrap();
+
+        let result = engine.apply_options(&[("max_background_jobs", "4"), ("bytes_per_sync", "1048576")]);
+        assert!(result.is_applied());
+        assert_eq!(
+            result.options[0].outcome,
+            OptionOutcome::Applied {
+                previous: Some("2".to_owned()),
+                verified: true,
+            }
+        );
+        assert_eq!(engine.get_db_option("bytes_per_sync").unwrap(), "1048576");
+
+        // Nothing is applied if any option can't be changed while open.
+        let result = engine.apply_options(&[("max_background_jobs", "8"), ("create_if_missing", "1")]);
+        assert_eq!(
+            outcomes(&result),
+            vec![&OptionOutcome::Skipped, &OptionOutcome::Unsupported]
+        );
+        assert_eq!(engine.get_db_option("max_background_jobs").unwrap(), "4");
+
+        // A rejected value rolls back the options before it.
+        let result = engine.apply_options(&[
+            ("max_background_jobs", "8"),
+            ("bytes_per_sync", "lots"),
+            ("wal_bytes_per_sync", "1"),
+        ]);
+        assert!(!result.is_applied());
+        assert!(!result.is_partial());
+        assert_eq!(result.options[0].outcome, OptionOutcome::RolledBack);
+        assert!(matches!(result.options[1].outcome, OptionOutcome::Rejected(_)));
+        assert_eq!(result.options[2].outcome, OptionOutcome::Skipped);
+        assert_eq!(engine.get_db_option("max_background_jobs").unwrap(), "4");
+
+        // So does a value that doesn't read back as set, which can't be
+        // restored if it was never set before.
+        let result = engine.apply_options(&[("max_background_jobs", "8"), ("max_open_files", "1000")]);
+        assert_eq!(result.options[0].outcome, OptionOutcome::RolledBack);
+        assert!(matches!(result.options[1].outcome, OptionOutcome::RollbackFailed(_)));
+        assert!(result.is_partial());
+        assert_eq!(engine.get_db_option("max_background_jobs").unwrap(), "4");
+
+        // Values read back in canonical form match however they were spelled.
+        let result = engine.apply_options(&[
+            ("bytes_per_sync", "1k"),
+            ("rate_limiter_auto_tuned", "1"),
+            ("rate_bytes_per_sec", "10M"),
+        ]);
+        assert!(result.is_applied(), "{:?}", result);
+        assert_eq!(engine.get_db_option("bytes_per_sync").unwrap(), "1024");
+        assert_eq!(engine.get_db_options().get_rate_limiter_auto_tuned(), Some(true));
+        assert_eq!(engine.get_db_options().get_rate_bytes_per_sec(), Some(10 << 20));
+    }
+
+    #[test]
+    fn test_option_values_match() {
+        assert!(option_values_match("1048576", "1048576"));
+        assert!(option_values_match("1m", "1048576"));
+        assert!(option_values_match("4K", " 4096"));
+        assert!(option_values_match("true", "1"));
+        assert!(option_values_match("False", "0"));
+        assert!(option_values_match("kSnappyCompression", "kSnappyCompression"));
+        assert!(!option_values_match("1000", "100"));
+        assert!(!option_values_match("true", "false"));
+        assert!(!option_values_match("1x", "1"));
+        assert!(!option_values_match("9999999999t", "0"));
+    }
+
+    #[test]
+    fn test_block_cache_options() {
+        assert!(BlockCacheOptions::new(1 << 30).validate().is_ok());
+        assert!(BlockCacheOptions::new(0).validate().is_err());
+
+        let mut options = BlockCacheOptions::new(1 << 30);
+        options.num_shard_bits = MAX_BLOCK_CACHE_SHARD_BITS + 1;
+        assert!(options.validate().is_err());
+
+        let mut options = BlockCacheOptions::new(1 << 30);
+        options.high_pri_pool_ratio = 1.5;
+        assert!(options.validate().is_err());
+        assert!(SharedBlockCache::<MockEngine>::new(options).is_err());
+    }
+
+    #[test]
+    fn test_shared_block_cache() {
+        let engine = MockEngine::default();
+        let mut options = BlockCacheOptions::new(1024);
+        options.strict_capacity_limit = true;
+        let cache = SharedBlockCache::<MockEngine>::new(options).unwrap();
+        assert!(cache.options().strict_capacity_limit);
+
+        // Usage can't be read before the cache is attached.
+        assert!(cache.usage(&engine).is_err());
+
+        let table = |block_size| MockBlockBasedOptions {
+            block_size,
+            ..Default::default()
+        };
+        let (mut default_table, mut write_table) = (table(4096), table(8192));
+        cache.attach("default", &mut default_table);
+        cache.attach("write", &mut write_table);
+        cache.attach("write", &mut write_table);
+        for (namespaced, table) in vec![("default", default_table), ("write", write_table)] {
+            let mut options = MockColumnFamilyOptions::new();
+            options.set_block_based_table_factory(&table);
+            engine.open_namespaced(namespaced, options);
+        }
+        // A column family with a cache of its own.
+        engine.open_namespaced("dagger", MockColumnFamilyOptions::new());
+        assert_eq!(cache.namespaces(), vec!["default".to_owned(), "write".to_owned()]);
+        // Attaching keeps the rest of the table options.
+        assert_eq!(engine.get_options_namespaced("write").unwrap().table.block_size, 8192);
+
+        cache.cache().0.lock().unwrap().usage = 512;
+        assert_eq!(
+            cache.usage(&engine).unwrap(),
+            BlockCacheUsage {
+                capacity: 1024,
+                usage: 512,
+                pinned_usage: 0,
+            }
+        );
+
+        // Resizing through one column family resizes it for all.
+        cache.set_capacity(&engine, 2048).unwrap();
+        assert_eq!(engine.get_block_cache_usage_namespaced("write").unwrap().capacity, 2048);
+        assert_eq!(engine.get_options_namespaced("default").unwrap().get_block_cache_capacity(), 2048);
+        assert_eq!(engine.get_block_cache_usage_namespaced("dagger").unwrap().capacity, 0);
+        assert!(cache.set_capacity(&engine, 0).is_err());
+
+        // Setting other options keeps the cache.
+        engine.set_options_namespaced("write", &[("disable_auto_compactions", "true")]).unwrap();
+        let write = engine.get_options_namespaced("write").unwrap();
+        assert!(write.get_disable_auto_jet_bundles());
+        assert_eq!(write.get_block_cache_capacity(), 2048);
+        assert!(engine.set_options_namespaced("lock", &[]).is_err());
+
+        let clone = cache.clone();
+        clone.attach("dagger", &mut MockBlockBasedOptions::default());
+        assert_eq!(cache.namespaces().len(), 3);
+    }
+}
@@ -0,0 +1,807 @@
+// Copyright 2021 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! A size-bounded cache of VioletaBFT log entries
+//!
+//! `EntryCache` is the builtin entry cache a `VioletaBFTeinstein_merkle_tree`
+//! can keep, see `VioletaBFTeinstein_merkle_tree::has_builtin_entry_cache`.
+//! Entries are keyed by group id and log index and charged the size the
+//! caller gives for them. When the cache is over capacity, entries are evicted
+//! by the configured `EvictionPolicy`:
+//!
+//! - `Lru` evicts the least recently used entry.
+//! - `Segmented` keeps entries that were hit again in a protected segment,
+//!   evicting from the probation segment first, so one scan of cold logs
+//!   doesn't flush the entries of busy regions.
+//!
+//! The entries of a pinned region are never evicted, which keeps hot regions
+//! cached under memory pressure. Hits and misses are counted per region.
+//!
+//! `CachedVioletaBFTeinstein_merkle_tree` gives an einstein_merkle_tree without a
+//! builtin entry cache one: it caches the entries written through it and
+//! serves reads from the cache where it can.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use ekvproto::violetabft_serverpb::VioletaBFTLocalState;
+use protobuf::Message;
+use violetabft::evioletabftpb::Entry;
+
+use crate::errors::Result;
+use crate::violetabft_engine::{
+    CacheStats, VioletaBFTLogBatch, VioletaBFTLogGCTask, VioletaBFTeinstein_merkle_tree,
+    VioletaBFTeinstein_merkle_treeReadOnly,
+};
+
+/// How entries are chosen for eviction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EvictionPolicy {
+    Lru,
+    /// Segmented LRU, with `protected_ratio` of the capacity reserved for
+    /// entries hit since they were cached
+    Segmented { protected_ratio: f64 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EntryCacheConfig {
+    /// The bytes the cache may hold, not counting pinned regions' entries
+    /// beyond it
+    pub capacity: usize,
+    pub policy: EvictionPolicy,
+}
+
+impl Default for EntryCacheConfig {
+    fn default() -> EntryCacheConfig {
+        EntryCacheConfig {
+            capacity: 256 * 1024 * 1024,
+            policy: EvictionPolicy::Lru,
+        }
+    }
+}
+
+type Key = (u64, u64);
+
+#[derive(Clone, Copy, PartialEq)]
+enum Segment {
+    Probation,
+    Protected,
+    /// The entry of a pinned region, in no eviction queue
+    Pinned,
+}
+
+struct Slot<V> {
+    value: V,
+    size: usize,
+    tick: u64,
+    segment: Segment,
+}
+
+#[derive(Default)]
+struct RegionStats {
+    hit: usize,
+    miss: usize,
+    size: usize,
+}
+
+pub struct EntryCache<V> {
+    config: EntryCacheConfig,
+    entries: BTreeMap<Key, Slot<V>>,
+    /// Eviction queues, oldest tick first
+    probation: BTreeMap<u64, Key>,
+    protected: BTreeMap<u64, Key>,
+    protected_size: usize,
+    pinned: HashSet<u64>,
+    regions: HashMap<u64, RegionStats>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl<V> EntryCache<V> {
+    pub fn new(config: EntryCacheConfig) -> EntryCache<V> {
+        EntryCache {
+            config,
+            entries: BTreeMap::new(),
+            probation: BTreeMap::new(),
+            protected: BTreeMap::new(),
+            protected_size: 0,
+            pinned: HashSet::new(),
+            regions: HashMap::new(),
+            tick: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn config(&self) -> EntryCacheConfig {
+        self.config
+    }
+
+    /// Sets the capacity, evicting entries to fit it
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.config.capacity = capacity;
+        self.evict();
+    }
+
+    /// Switches the eviction policy. Recency is kept, but every unpinned
+    /// entry starts over in the probation segment.
+    pub fn set_policy(&mut self, policy: EvictionPolicy) {
+        self.config.policy = policy;
+        let protected = std::mem::replace(&mut self.protected, BTreeMap::new());
+        for (tick, key) in protected {
+            self.entries.get_mut(&key).unwrap().segment = Segment::Probation;
+            self.probation.insert(tick, key);
+        }
+        self.protected_size = 0;
+        self.evict();
+    }
+
+    /// Keeps the entries of `region_id` from being evicted until it's unpinned
+    pub fn pin_region(&mut self, region_id: u64) {
+        if !self.pinned.insert(region_id) {
+            return;
+        }
+        for (_, slot) in self.entries.range_mut((region_id, 0)..=(region_id, u64::MAX)) {
+            match slot.segment {
+                Segment::Probation => {
+                    self.probation.remove(&slot.tick);
+                }
+                Segment::Protected => {
+                    self.protected.remove(&slot.tick);
+                    self.protected_size -= slot.size;
+                }
+                Segment::Pinned => continue,
+            }
+            slot.segment = Segment::Pinned;
+            self.stats.pinned_size += slot.size;
+        }
+    }
+
+    /// Makes the entries of `region_id` evictable again, as if just used
+    pub fn unpin_region(&mut self, region_id: u64) {
+        if !self.pinned.remove(&region_id) {
+            return;
+        }
+        for (key, slot) in self.entries.range_mut((region_id, 0)..=(region_id, u64::MAX)) {
+            self.tick += 1;
+            slot.tick = self.tick;
+            slot.segment = Segment::Probation;
+            self.probation.insert(slot.tick, *key);
+            self.stats.pinned_size -= slot.size;
+        }
+        self.evict();
+    }
+
+    pub fn is_pinned(&self, region_id: u64) -> bool {
+        self.pinned.contains(&region_id)
+    }
+
+    /// Caches `value` as the entry at `index` of `region_id`, charging it
+    /// `size` bytes
+    pub fn insert(&mut self, region_id: u64, index: u64, value: V, size: usize) {
+        self.remove((region_id, index));
+        self.tick += 1;
+        let segment = if self.pinned.contains(&region_id) {
+            self.stats.pinned_size += size;
+            Segment::Pinned
+        } else {
+            self.probation.insert(self.tick, (region_id, index));
+            Segment::Probation
+        };
+        self.entries.insert(
+            (region_id, index),
+            Slot {
+                value,
+                size,
+                tick: self.tick,
+                segment,
+            },
+        );
+        self.stats.cache_size += size;
+        self.regions.entry(region_id).or_default().size += size;
+        self.evict();
+    }
+
+    /// Looks up the entry at `index` of `region_id`, counting a hit or miss
+    pub fn get(&mut self, region_id: u64, index: u64) -> Option<&V> {
+        let region = self.regions.entry(region_id).or_default();
+        let slot = match self.entries.get_mut(&(region_id, index)) {
+            Some(slot) => slot,
+            None => {
+                region.miss += 1;
+                self.stats.miss += 1;
+                return None;
+            }
+        };
+        region.hit += 1;
+        self.stats.hit += 1;
+
+        if slot.segment != Segment::Pinned {
+            let queue = match slot.segment {
+                Segment::Probation => &mut self.probation,
+                _ => &mut self.protected,
+            };
+            queue.remove(&slot.tick);
+            self.tick += 1;
+            slot.tick = self.tick;
+            match self.config.policy {
+                EvictionPolicy::Segmented { .. } => {
+                    if slot.segment == Segment::Probation {
+                        slot.segment = Segment::Protected;
+                        self.protected_size += slot.size;
+                    }
+                    self.protected.insert(slot.tick, (region_id, index));
+                }
+                EvictionPolicy::Lru => {
+                    self.probation.insert(slot.tick, (region_id, index));
+                }
+            }
+        }
+        self.demote();
+        self.entries.get(&(region_id, index)).map(|slot| &slot.value)
+    }
+
+    /// Removes the entries of `region_id` below `to`
+    pub fn compact_to(&mut self, region_id: u64, to: u64) {
+        self.remove_range(region_id, 0, to);
+    }
+
+    /// Removes the entries of `region_id` in [`from`, `to`)
+    pub fn remove_range(&mut self, region_id: u64, from: u64, to: u64) {
+        if from >= to {
+            return;
+        }
+        let keys: Vec<Key> = self
+            .entries
+            .range((region_id, from)..(region_id, to))
+            .map(|(key, _)| *key)
+            .collect();
+        for key in keys {
+            self.remove(key);
+        }
+    }
+
+    /// Removes every entry and the statistics of `region_id`, and unpins it
+    pub fn remove_region(&mut self, region_id: u64) {
+        self.compact_to(region_id, u64::MAX);
+        self.remove((region_id, u64::MAX));
+        self.pinned.remove(&region_id);
+        self.regions.remove(&region_id);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// The hits, misses and cached bytes of `region_id`
+    pub fn region_stats(&self, region_id: u64) -> Option<CacheStats> {
+        self.regions.get(&region_id).map(|r| CacheStats {
+            hit: r.hit,
+            miss: r.miss,
+            cache_size: r.size,
+            evicted: 0,
+            pinned_size: if self.is_pinned(region_id) { r.size } else { 0 },
+            ..CacheStats::default()
+        })
+    }
+
+    /// Clears the hit and miss counts, keeping the entries
+    pub fn reset_statistics(&mut self) {
+        self.stats.hit = 0;
+        self.stats.miss = 0;
+        self.stats.evicted = 0;
+        for region in self.regions.values_mut() {
+            region.hit = 0;
+            region.miss = 0;
+        }
+    }
+
+    fn remove(&mut self, key: Key) -> Option<Slot<V>> {
+        let slot = self.entries.remove(&key)?;
+        match slot.segment {
+            Segment::Probation => {
+                self.probation.remove(&slot.tick);
+            }
+            Segment::Protected => {
+                self.protected.remove(&slot.tick);
+                self.protected_size -= slot.size;
+            }
+            Segment::Pinned => self.stats.pinned_size -= slot.size,
+        }
+        self.stats.cache_size -= slot.size;
+        if let Some(region) = self.regions.get_mut(&key.0) {
+            region.size -= slot.size;
+        }
+        Some(slot)
+    }
+
+    /// Moves the oldest protected entries back to probation while the
+    /// protected segment is over its share of the capacity
+    fn demote(&mut self) {
+        let protected_capacity = match self.config.policy {
+            EvictionPolicy::Segmented { protected_ratio } => {
+                (self.config.capacity as f64 * protected_ratio) as usize
+            }
+            EvictionPolicy::Lru => 0,
+        };
+        while self.protected_size > protected_capacity {
+            let (tick, key) = match self.protected.iter().next() {
+                Some((tick, key)) => (*tick, *key),
+                None => break,
+            };
+            self.protected.remove(&tick);
+            let slot = self.entries.get_mut(&key).unwrap();
+            slot.segment = Segment::Probation;
+            self.protected_size -= slot.size;
+            self.probation.insert(tick, key);
+        }
+    }
+
+    /// Evicts unpinned entries, probation first, while over capacity
+    fn evict(&mut self) {
+        self.demote();
+        while self.stats.cache_size > self.config.capacity {
+            let key = match self
+                .probation
+                .values()
+                .next()
+                .or_else(|| self.protected.values().next())
+            {
+                Some(key) => *key,
+                None => break,
+            };
+            self.remove(key);
+            self.stats.evicted += 1;
+        }
+    }
+}
+
+/// A change a `CachedVioletaBFTLogBatch` makes to the cache once consumed
+enum CacheOp {
+    Append(u64, Vec<Entry>),
+    Cut(u64, u64, u64),
+    Clean(u64),
+}
+
+fn apply_ops(cache: &mut EntryCache<Entry>, ops: Vec<CacheOp>) {
+    for op in ops {
+        match op {
+            CacheOp::Append(violetabft_group_id, entries) => {
+                cache_entries(cache, violetabft_group_id, entries)
+            }
+            CacheOp::Cut(violetabft_group_id, from, to) => {
+                cache.remove_range(violetabft_group_id, from, to)
+            }
+            CacheOp::Clean(violetabft_group_id) => cache.remove_region(violetabft_group_id),
+        }
+    }
+}
+
+/// Caches `entries`, dropping the cached entries they overwrite: appending
+/// at an index truncates the log after it.
+fn cache_entries(cache: &mut EntryCache<Entry>, violetabft_group_id: u64, entries: Vec<Entry>) {
+    let first = match entries.first() {
+        Some(e) => e.get_index(),
+        None => return,
+    };
+    cache.remove_range(violetabft_group_id, first, u64::MAX);
+    for e in entries {
+        let size = e.compute_size() as usize;
+        cache.insert(violetabft_group_id, e.get_index(), e, size);
+    }
+}
+
+/// The log batch of a `CachedVioletaBFTeinstein_merkle_tree`, which remembers
+/// what to change in the cache once it's consumed
+pub struct CachedVioletaBFTLogBatch<B> {
+    batch: B,
+    ops: Vec<CacheOp>,
+}
+
+impl<B: VioletaBFTLogBatch> VioletaBFTLogBatch for CachedVioletaBFTLogBatch<B> {
+    fn append(&mut self, violetabft_group_id: u64, entries: Vec<Entry>) -> Result<()> {
+        self.batch.append(violetabft_group_id, entries.clone())?;
+        self.ops.push(CacheOp::Append(violetabft_group_id, entries));
+        Ok(())
+    }
+
+    fn cut_logs(&mut self, violetabft_group_id: u64, from: u64, to: u64) {
+        self.batch.cut_logs(violetabft_group_id, from, to);
+        self.ops.push(CacheOp::Cut(violetabft_group_id, from, to));
+    }
+
+    fn put_violetabft_state(&mut self, violetabft_group_id: u64, state: &VioletaBFTLocalState) -> Result<()> {
+        self.batch.put_violetabft_state(violetabft_group_id, state)
+    }
+
+    fn persist_size(&self) -> usize {
+        self.batch.persist_size()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.batch.is_empty()
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.batch.merge(other.batch);
+        self.ops.extend(other.ops);
+    }
+}
+
+/// An einstein_merkle_tree with an `EntryCache` in front of it
+///
+/// Entries written through it are cached once they're written, and entries
+/// it removes are dropped from the cache. Reads of a range are served from
+/// the cache only if every entry of the range is cached, and from the
+/// einstein_merkle_tree otherwise; entries read from the einstein_merkle_tree
+/// aren't cached. Entries written to the einstein_merkle_tree other than
+/// through the wrapper are never seen, so every writer of the groups it reads
+/// must go through it.
+#[derive(Clone)]
+pub struct CachedVioletaBFTeinstein_merkle_tree<E> {
+    einstein_merkle_tree: E,
+    cache: Arc<Mutex<EntryCache<Entry>>>,
+}
+
+impl<E: VioletaBFTeinstein_merkle_tree> CachedVioletaBFTeinstein_merkle_tree<E> {
+    pub fn new(einstein_merkle_tree: E, config: EntryCacheConfig) -> CachedVioletaBFTeinstein_merkle_tree<E> {
+        CachedVioletaBFTeinstein_merkle_tree {
+            einstein_merkle_tree,
+            cache: Arc::new(Mutex::new(EntryCache::new(config))),
+        }
+    }
+
+    pub fn inner(&self) -> &E {
+        &self.einstein_merkle_tree
+    }
+
+    fn consumed(&self, batch: &mut CachedVioletaBFTLogBatch<E::LogBatch>) {
+        let ops = mem::replace(&mut batch.ops, vec![]);
+        apply_ops(&mut self.cache.lock().unwrap(), ops);
+    }
+}
+
+impl<E: VioletaBFTeinstein_merkle_tree> VioletaBFTeinstein_merkle_treeReadOnly for CachedVioletaBFTeinstein_merkle_tree<E> {
+    fn get_violetabft_state(&self, violetabft_group_id: u64) -> Result<Option<VioletaBFTLocalState>> {
+        self.einstein_merkle_tree.get_violetabft_state(violetabft_group_id)
+    }
+
+    fn get_entry(&self, violetabft_group_id: u64, index: u64) -> Result<Option<Entry>> {
+        if let Some(e) = self.cache.lock().unwrap().get(violetabft_group_id, index) {
+            return Ok(Some(e.clone()));
+        }
+        self.einstein_merkle_tree.get_entry(violetabft_group_id, index)
+    }
+
+    fn fetch_entries_to(
+        &self,
+        violetabft_group_id: u64,
+        begin: u64,
+        end: u64,
+        max_size: Option<usize>,
+        to: &mut Vec<Entry>,
+    ) -> Result<usize> {
+        let mut fetched = vec![];
+        {
+            let mut cache = self.cache.lock().unwrap();
+            let mut size = 0;
+            for index in begin..end {
+                let e = match cache.get(violetabft_group_id, index) {
+                    Some(e) => e,
+                    None => {
+                        fetched.clear();
+                        break;
+                    }
+                };
+                // The first entry is returned whatever its size.
+                size += e.compute_size() as usize;
+                if !fetched.is_empty() && max_size.map_or(false, |max| size > max) {
+                    break;
+                }
+                fetched.push(e.clone());
+            }
+        }
+        if fetched.is_empty() && begin < end {
+            return self
+                .einstein_merkle_tree
+                .fetch_entries_to(violetabft_group_id, begin, end, max_size, to);
+        }
+        let count = fetched.len();
+        to.extend(fetched);
+        Ok(count)
+    }
+
+    fn get_all_entries_to(&self, region_id: u64, buf: &mut Vec<Entry>) -> Result<()> {
+        self.einstein_merkle_tree.get_all_entries_to(region_id, buf)
+    }
+}
+
+impl<E: VioletaBFTeinstein_merkle_tree> VioletaBFTeinstein_merkle_tree for CachedVioletaBFTeinstein_merkle_tree<E> {
+    type LogBatch = CachedVioletaBFTLogBatch<E::LogBatch>;
+
+    fn log_batch(&self, capacity: usize) -> Self::LogBatch {
+        CachedVioletaBFTLogBatch {
+            batch: self.einstein_merkle_tree.log_batch(capacity),
+            ops: vec![],
+        }
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.einstein_merkle_tree.sync()
+    }
+
+    fn consume(&self, batch: &mut Self::LogBatch, sync: bool) -> Result<usize> {
+        let written = self.einstein_merkle_tree.consume(&mut batch.batch, sync)?;
+        self.consumed(batch);
+        Ok(written)
+    }
+
+    fn consume_and_shrink(
+        &self,
+        batch: &mut Self::LogBatch,
+        sync: bool,
+        max_capacity: usize,
+        shrink_to: usize,
+    ) -> Result<usize> {
+        let written = self
+            .einstein_merkle_tree
+            .consume_and_shrink(&mut batch.batch, sync, max_capacity, shrink_to)?;
+        self.consumed(batch);
+        Ok(written)
+    }
+
+    fn clean(
+        &self,
+        violetabft_group_id: u64,
+        first_index: u64,
+        state: &VioletaBFTLocalState,
+        batch: &mut Self::LogBatch,
+    ) -> Result<()> {
+        self.einstein_merkle_tree
+            .clean(violetabft_group_id, first_index, state, &mut batch.batch)?;
+        batch.ops.push(CacheOp::Clean(violetabft_group_id));
+        Ok(())
+    }
+
+    fn append(&self, violetabft_group_id: u64, entries: Vec<Entry>) -> Result<usize> {
+        let written = self.einstein_merkle_tree.append(violetabft_group_id, entries.clone())?;
+        cache_entries(&mut self.cache.lock().unwrap(), violetabft_group_id, entries);
+        Ok(written)
+    }
+
+    fn put_violetabft_state(&self, violetabft_group_id: u64, state: &VioletaBFTLocalState) -> Result<()> {
+        self.einstein_merkle_tree.put_violetabft_state(violetabft_group_id, state)
+    }
+
+    fn gc(&self, violetabft_group_id: u64, from: u64, to: u64) -> Result<usize> {
+        let deleted = self.einstein_merkle_tree.gc(violetabft_group_id, from, to)?;
+        self.cache.lock().unwrap().remove_range(violetabft_group_id, from, to);
+        Ok(deleted)
+    }
+
+    fn batch_gc(&self, tasks: Vec<VioletaBFTLogGCTask>) -> Result<usize> {
+        let deleted = self.einstein_merkle_tree.batch_gc(tasks.clone())?;
+        let mut cache = self.cache.lock().unwrap();
+        for task in tasks {
+            cache.remove_range(task.violetabft_group_id, task.from, task.to);
+        }
+        Ok(deleted)
+    }
+
+    fn purge_expired_filefs(&self) -> Result<Vec<u64>> {
+        self.einstein_merkle_tree.purge_expired_filefs()
+    }
+
+    fn log_usage(&self) -> Result<Vec<(u64, CacheStats)>> {
+        self.einstein_merkle_tree.log_usage()
+    }
+
+    fn has_builtin_entry_cache(&self) -> bool {
+        true
+    }
+
+    fn gc_entry_cache(&self, violetabft_group_id: u64, to: u64) {
+        self.cache.lock().unwrap().compact_to(violetabft_group_id, to);
+    }
+
+    fn configure_entry_cache(&self, config: EntryCacheConfig) -> Result<()> {
+        let mut cache = self.cache.lock().unwrap();
+        cache.set_policy(config.policy);
+        cache.set_capacity(config.capacity);
+        Ok(())
+    }
+
+    fn pin_entry_cache(&self, violetabft_group_id: u64, pinned: bool) {
+        let mut cache = self.cache.lock().unwrap();
+        if pinned {
+            cache.pin_region(violetabft_group_id);
+        } else {
+            cache.unpin_region(violetabft_group_id);
+        }
+    }
+
+    fn group_cache_stats(&self, violetabft_group_id: u64) -> Option<CacheStats> {
+        self.cache.lock().unwrap().region_stats(violetabft_group_id)
+    }
+
+    fn flush_metrics(&self, instance: &str) {
+        self.einstein_merkle_tree.flush_metrics(instance)
+    }
+
+    fn flush_stats(&self) -> Option<CacheStats> {
+        Some(self.cache.lock().unwrap().stats())
+    }
+
+    fn reset_statistics(&self) {
+        self.cache.lock().unwrap().reset_statistics();
+        self.einstein_merkle_tree.reset_statistics()
+    }
+
+    fn stop(&self) {
+        self.einstein_merkle_tree.stop()
+    }
+
+    fn dump_stats(&self) -> Result<String> {
+        self.einstein_merkle_tree.dump_stats()
+    }
+
+    fn get_einstein_merkle_tree_size(&self) -> Result<u64> {
+        self.einstein_merkle_tree.get_einstein_merkle_tree_size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::violetabft_sync::tests::{entry, MemEngine};
+
+    fn cache(capacity: usize, policy: EvictionPolicy) -> EntryCache<u64> {
+        EntryCache::new(EntryCacheConfig { capacity, policy })
+    }
+
+    #[test]
+    fn test_entry_cache_lru() {
+        let mut c = cache(30, EvictionPolicy::Lru);
+        for i in 1..=3 {
+            c.insert(1, i, i, 10);
+        }
+        assert_eq!(c.get(1, 1), Some(&1));
+        c.insert(2, 1, 21, 10);
+        assert_eq!(c.get(1, 2), None);
+        assert_eq!(c.get(1, 1), Some(&1));
+        assert_eq!(c.stats().evicted, 1);
+        assert_eq!(c.stats().cache_size, 30);
+
+        let region = c.region_stats(1).unwrap();
+        assert_eq!((region.hit, region.miss, region.cache_size), (2, 1, 20));
+        assert_eq!(region.hit_rate(), 2.0 / 3.0);
+
+        c.set_capacity(10);
+        assert_eq!(c.stats().cache_size, 10);
+        assert_eq!(c.get(1, 1), Some(&1));
+
+        c.compact_to(1, 2);
+        assert_eq!(c.stats().cache_size, 0);
+        c.remove_region(1);
+        assert!(c.region_stats(1).is_none());
+    }
+
+    #[test]
+    fn test_entry_cache_segmented() {
+        let mut c = cache(40, EvictionPolicy::Segmented { protected_ratio: 0.5 });
+        c.insert(1, 1, 1, 10);
+        c.insert(1, 2, 2, 10);
+        assert!(c.get(1, 1).is_some());
+        assert!(c.get(1, 2).is_some());
+
+        // A scan of cold entries evicts only from probation.
+        for i in 1..=4 {
+            c.insert(2, i, i, 10);
+        }
+        assert!(c.get(1, 1).is_some());
+        assert!(c.get(1, 2).is_some());
+        assert!(c.get(2, 1).is_none());
+        assert!(c.get(2, 4).is_some());
+
+        // Under LRU the same scan would have evicted them.
+        c.set_policy(EvictionPolicy::Lru);
+        for i in 5..=8 {
+            c.insert(2, i, i, 10);
+        }
+        assert!(c.get(1, 1).is_none());
+    }
+
+    #[test]
+    fn test_entry_cache_pinned() {
+        let mut c = cache(20, EvictionPolicy::Lru);
+        c.insert(1, 1, 1, 10);
+        c.pin_region(1);
+        c.insert(1, 2, 2, 10);
+        for i in 1..=4 {
+            c.insert(2, i, i, 10);
+        }
+        assert!(c.get(1, 1).is_some());
+        assert!(c.get(1, 2).is_some());
+        assert_eq!(c.stats().pinned_size, 20);
+        assert_eq!(c.region_stats(1).unwrap().pinned_size, 20);
+
+        // Pinned entries may hold the cache over capacity.
+        assert_eq!(c.stats().cache_size, 20);
+        c.insert(1, 3, 3, 10);
+        assert_eq!(c.stats().cache_size, 30);
+
+        c.unpin_region(1);
+        assert_eq!(c.stats().pinned_size, 0);
+        assert_eq!(c.stats().cache_size, 20);
+        assert!(c.get(1, 1).is_none());
+
+        c.reset_statistics();
+        assert_eq!(c.stats().hit, 0);
+        assert_eq!(c.region_stats(1).unwrap().hit, 0);
+    }
+
+    fn indexes(entries: &[Entry]) -> Vec<(u64, u64)> {
+        entries.iter().map(|e| (e.get_index(), e.get_term())).collect()
+    }
+
+    #[test]
+    fn test_cached_einstein_merkle_tree() {
+        let engine = CachedVioletaBFTeinstein_merkle_tree::new(MemEngine::default(), EntryCacheConfig::default());
+        assert!(engine.has_builtin_entry_cache());
+        engine.append(1, (1..=5).map(|i| entry(i, 1)).collect()).unwrap();
+
+        // Reads of cached entries hit.
+        assert_eq!(engine.get_entry(1, 3).unwrap().unwrap().get_term(), 1);
+        let mut entries = vec![];
+        assert_eq!(engine.fetch_entries_to(1, 2, 5, None, &mut entries).unwrap(), 3);
+        assert_eq!(indexes(&entries), vec![(2, 1), (3, 1), (4, 1)]);
+        let stats = engine.group_cache_stats(1).unwrap();
+        assert_eq!((stats.hit, stats.miss), (4, 0));
+
+        // The first entry is returned however small max_size is.
+        entries.clear();
+        assert_eq!(engine.fetch_entries_to(1, 1, 5, Some(0), &mut entries).unwrap(), 1);
+
+        // Appending at an index overwrites the cached entries from it on.
+        let mut batch = engine.log_batch(0);
+        batch.append(1, vec![entry(4, 2)]).unwrap();
+        engine.consume(&mut batch, false).unwrap();
+        assert_eq!(engine.get_entry(1, 4).unwrap().unwrap().get_term(), 2);
+        assert_eq!(engine.cache.lock().unwrap().get(1, 5).map(|e| e.get_term()), None);
+
+        // GC drops the entries from the cache; uncached reads go to the
+        // einstein_merkle_tree.
+        engine.gc(1, 0, 3).unwrap();
+        assert!(engine.cache.lock().unwrap().get(1, 2).is_none());
+        assert!(engine.get_entry(1, 2).unwrap().is_none());
+        entries.clear();
+        engine.fetch_entries_to(1, 3, 6, None, &mut entries).unwrap();
+        assert_eq!(indexes(&entries), vec![(3, 1), (4, 2), (5, 1)]);
+
+        // Cleaning a group drops it from the cache once the batch is consumed.
+        let mut batch = engine.log_batch(0);
+        engine.clean(1, 0, &VioletaBFTLocalState::default(), &mut batch).unwrap();
+        assert!(engine.group_cache_stats(1).is_some());
+        engine.consume(&mut batch, false).unwrap();
+        assert!(engine.group_cache_stats(1).is_none());
+        assert_eq!(engine.flush_stats().unwrap().cache_size, 0);
+    }
+
+    #[test]
+    fn test_cached_einstein_merkle_tree_configure() {
+        let engine = CachedVioletaBFTeinstein_merkle_tree::new(MemEngine::default(), EntryCacheConfig::default());
+        engine.append(1, (1..=4).map(|i| entry(i, 1)).collect()).unwrap();
+        engine.append(2, (1..=4).map(|i| entry(i, 1)).collect()).unwrap();
+        engine.pin_entry_cache(1, true);
+
+        let size = engine.flush_stats().unwrap().cache_size;
+        engine
+            .configure_entry_cache(EntryCacheConfig {
+                capacity: size / 2,
+                policy: EvictionPolicy::Segmented { protected_ratio: 0.5 },
+            })
+            .unwrap();
+        assert_eq!(engine.flush_stats().unwrap().pinned_size, size / 2);
+        assert_eq!(engine.group_cache_stats(2).unwrap().cache_size, 0);
+        assert_eq!(engine.group_cache_stats(1).unwrap().cache_size, size / 2);
+    }
+}
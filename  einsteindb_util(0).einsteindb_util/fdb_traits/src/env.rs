@@ -0,0 +1,300 @@
+// Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! The storage an einstein_merkle_tree keeps its files on
+//!
+//! Engines open, read, write and delete their SST and Causet files through a
+//! `FileSystem` rather than `std::fs`, so the files needn't live on a local
+//! disk. `LocalFileSystem` is the plain local implementation. A
+//! `TieredFileSystem` routes each path to the file system mounted at its
+//! longest matching prefix, which is how a tier backed by object storage is
+//! added: implement `FileSystem` for it and mount it under the directory the
+//! tier's files are placed in, without changes to engine code.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+#[cfg(windows)]
+use std::os::windows::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use crate::errors::{Error, Result};
+
+/// A file opened for reading at arbitrary offsets
+pub trait RandomAccessFile: Send + Sync {
+    /// Reads into `buf` from `offset`, returning how many bytes were read,
+    /// which is less than `buf.len()` only at the end of the file
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize>;
+
+    /// The size of the file in bytes
+    fn size(&self) -> Result<u64>;
+}
+
+/// A file opened for appending
+pub trait WritableFile: Send {
+    fn write(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Makes everything written so far durable
+    fn sync(&mut self) -> Result<()>;
+}
+
+pub trait FileSystem: Send + Sync {
+    fn open(&self, path: &Path) -> Result<Box<dyn RandomAccessFile>>;
+
+    /// Creates the file at `path`, truncating it if it exists
+    fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>>;
+
+    fn delete(&self, path: &Path) -> Result<()>;
+
+    fn exists(&self, path: &Path) -> Result<bool>;
+
+    /// Lists the paths of the files directly under `dir`, in no particular order
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>>;
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+}
+
+/// An einstein_merkle_tree whose files are accessed through a `FileSystem`
+pub trait EnvExt {
+    fn file_system(&self) -> Arc<dyn FileSystem>;
+}
+
+/// The local file system
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LocalFileSystem;
+
+struct LocalRandomAccessFile(File);
+
+#[cfg(unix)]
+fn read_file_at(f: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    f.read_at(buf, offset)
+}
+
+// Unlike `read_at`, `seek_read` moves the file cursor, which nothing else
+// uses on a file opened for random access.
+#[cfg(windows)]
+fn read_file_at(f: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    f.seek_read(buf, offset)
+}
+
+impl RandomAccessFile for LocalRandomAccessFile {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            match read_file_at(&self.0, &mut buf[read..], offset + read as u64)? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        Ok(read)
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.0.metadata()?.len())
+    }
+}
+
+struct LocalWritableFile(File);
+
+impl WritableFile for LocalWritableFile {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        Ok(self.0.write_all(data)?)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(self.0.sync_all()?)
+    }
+}
+
+impl FileSystem for LocalFileSystem {
+    fn open(&self, path: &Path) -> Result<Box<dyn RandomAccessFile>> {
+        Ok(Box::new(LocalRandomAccessFile(File::open(path)?)))
+    }
+
+    fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let f = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Box::new(LocalWritableFile(f)))
+    }
+
+    fn delete(&self, path: &Path) -> Result<()> {
+        Ok(fs::remove_file(path)?)
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        Ok(path.exists())
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                paths.push(entry.path());
+            }
+        }
+        Ok(paths)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        Ok(fs::rename(from, to)?)
+    }
+}
+
+/// A `FileSystem` that routes each path to the file system mounted at the
+/// longest prefix of it, or to a default one
+pub struct TieredFileSystem {
+    default: Arc<dyn FileSystem>,
+    mounts: RwLock<Vec<(PathBuf, Arc<dyn FileSystem>)>>,
+}
+
+impl TieredFileSystem {
+    pub fn new(default: Arc<dyn FileSystem>) -> TieredFileSystem {
+        TieredFileSystem {
+            default,
+            mounts: RwLock::new(vec![]),
+        }
+    }
+
+    /// Routes the paths under `prefix` to `fs`, replacing any file system
+    /// mounted there before
+    pub fn mount(&self, prefix: impl Into<PathBuf>, fs: Arc<dyn FileSystem>) {
+        let prefix = prefix.into();
+        let mut mounts = self.mounts.write().unwrap();
+        mounts.retain(|(p, _)| *p != prefix);
+        mounts.push((prefix, fs));
+        // Longest first, so the first match is the most specific.
+        mounts.sort_by(|(a, _), (b, _)| b.components().count().cmp(&a.components().count()));
+    }
+
+    /// Removes the file system mounted at `prefix`, returning it
+    pub fn unmount(&self, prefix: &Path) -> Option<Arc<dyn FileSystem>> {
+        let mut mounts = self.mounts.write().unwrap();
+        let i = mounts.iter().position(|(p, _)| p == prefix)?;
+        Some(mounts.remove(i).1)
+    }
+
+    /// The file system `path` is routed to
+    pub fn route(&self, path: &Path) -> Arc<dyn FileSystem> {
+        self.mounts
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix))
+            .map_or_else(|| self.default.clone(), |(_, fs)| fs.clone())
+    }
+}
+
+impl FileSystem for TieredFileSystem {
+    fn open(&self, path: &Path) -> Result<Box<dyn RandomAccessFile>> {
+        self.route(path).open(path)
+    }
+
+    fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        self.route(path).create(path)
+    }
+
+    fn delete(&self, path: &Path) -> Result<()> {
+        self.route(path).delete(path)
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        self.route(path).exists(path)
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        self.route(dir).list(dir)
+    }
+
+    /// Renames within one tier only; moving a file between tiers is a copy,
+    /// which is the caller's to make
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let fs = self.route(from);
+        if !Arc::ptr_eq(&fs, &self.route(to)) {
+            return Err(Error::Other(
+                format!(
+                    "can't rename {} to {} across file systems",
+                    from.display(),
+                    to.display()
+                )
+                .into(),
+            ));
+        }
+        fs.rename(from, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fdb_traits_env_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_local_file_system() {
+        let dir = temp_dir("local");
+        let local = LocalFileSystem;
+        let path = dir.join("000001.sst");
+
+        let mut f = local.create(&path).unwrap();
+        f.write(b"hello ").unwrap();
+        f.write(b"world").unwrap();
+        f.sync().unwrap();
+        drop(f);
+
+        let f = local.open(&path).unwrap();
+        assert_eq!(f.size().unwrap(), 11);
+        let mut buf = [0; 5];
+        assert_eq!(f.read_at(6, &mut buf).unwrap(), 5);
+        assert_eq!(&buf, b"world");
+        assert_eq!(f.read_at(9, &mut buf).unwrap(), 2);
+
+        let renamed = dir.join("000002.sst");
+        local.rename(&path, &renamed).unwrap();
+        assert!(!local.exists(&path).unwrap());
+        assert_eq!(local.list(&dir).unwrap(), vec![renamed.clone()]);
+        local.delete(&renamed).unwrap();
+        assert!(local.list(&dir).unwrap().is_empty());
+        assert!(local.open(&renamed).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tiered_file_system() {
+        let dir = temp_dir("tiered");
+        let cold_dir = dir.join("cold");
+        fs::create_dir_all(cold_dir.join("deep")).unwrap();
+
+        let local: Arc<dyn FileSystem> = Arc::new(LocalFileSystem);
+        let cold: Arc<dyn FileSystem> = Arc::new(LocalFileSystem);
+        let deep: Arc<dyn FileSystem> = Arc::new(LocalFileSystem);
+        let tiered = TieredFileSystem::new(local.clone());
+        tiered.mount(&cold_dir, cold.clone());
+        tiered.mount(cold_dir.join("deep"), deep.clone());
+
+        assert!(Arc::ptr_eq(&tiered.route(&dir.join("a.sst")), &local));
+        assert!(Arc::ptr_eq(&tiered.route(&cold_dir.join("a.sst")), &cold));
+        assert!(Arc::ptr_eq(&tiered.route(&cold_dir.join("deep/a.sst")), &deep));
+
+        tiered.create(&dir.join("a.sst")).unwrap();
+        assert!(tiered
+            .rename(&dir.join("a.sst"), &cold_dir.join("a.sst"))
+            .is_err());
+        tiered.rename(&dir.join("a.sst"), &dir.join("b.sst")).unwrap();
+
+        assert!(tiered.unmount(&cold_dir).is_some());
+        assert!(Arc::ptr_eq(&tiered.route(&cold_dir.join("a.sst")), &local));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,11 +1,14 @@
 // Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
 use std::{error, result};
+use std::time::Duration;
 
 use error_code::{self, ErrorCode, ErrorCodeExt};
-use violetabft::{Error as VioletaBFTError, StorageError};
 use thiserror::Error;
 
+use crate::lifecycle::HealthState;
+use crate::misc::StallReason;
+
 #[derive(Debug, Error)]
 pub enum Error {
     // einstein_merkle_tree uses plain string as the error.
@@ -36,6 +39,125 @@ pub enum Error {
     EntriesUnavailable,
     #[error("The entries of region is compacted")]
     EntriesCompacted,
+    #[error("Write stalled for {waited:?}: {}", format_stall_reasons(.reasons))]
+    WriteStalled {
+        waited: Duration,
+        reasons: Vec<StallReason>,
+    },
+    #[error("einstein_merkle_tree is not open: {0:?}")]
+    Closed(HealthState),
+    #[error("{context}: {source}")]
+    Context {
+        context: String,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+fn format_stall_reasons(reasons: &[StallReason]) -> String {
+    if reasons.is_empty() {
+        return "unknown reason".to_owned();
+    }
+    reasons
+        .iter()
+        .map(|r| r.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// How a caller should react to an `Error`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The operation may succeed if retried as is, possibly after a backoff.
+    Retryable,
+    /// The operation can't succeed as is, but the engine is healthy and the caller can
+    /// recover by other means, e.g. refreshing its region or sending a snapshot.
+    Recoverable,
+    /// The engine or its data is broken. Retrying is pointless.
+    Fatal,
+    /// The request itself is invalid. Retrying is pointless.
+    InvalidArgument,
+}
+
+impl ErrorClass {
+    /// Classifies an error message of the underlying engine by its status prefix,
+    /// e.g. `"Resource busy: ..."` or `"Corruption: ..."`.
+    pub fn from_engine_message(msg: &str) -> ErrorClass {
+        const CLASSES: &[(&str, ErrorClass)] = &[
+            ("Resource busy", ErrorClass::Retryable),
+            ("Operation failed. Try again.", ErrorClass::Retryable),
+            ("Operation timed out", ErrorClass::Retryable),
+            ("Operation expired", ErrorClass::Retryable),
+            ("Operation aborted", ErrorClass::Retryable),
+            ("Merge in progress", ErrorClass::Retryable),
+            ("Result incomplete", ErrorClass::Recoverable),
+            ("NotFound", ErrorClass::Recoverable),
+            ("Column family dropped", ErrorClass::Recoverable),
+            ("Shutdown in progress", ErrorClass::Fatal),
+            ("Corruption", ErrorClass::Fatal),
+            ("IO error", ErrorClass::Fatal),
+            ("Invalid argument", ErrorClass::InvalidArgument),
+            ("Not implemented", ErrorClass::InvalidArgument),
+        ];
+        let msg = msg.trim_start();
+        CLASSES
+            .iter()
+            .find(|(prefix, _)| msg.starts_with(prefix))
+            .map_or(ErrorClass::Fatal, |(_, class)| *class)
+    }
+
+    pub(crate) fn from_io_error(err: &std::io::Error) -> ErrorClass {
+        use std::io::ErrorKind;
+
+        match err.kind() {
+            ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                ErrorClass::Retryable
+            }
+            ErrorKind::NotFound | ErrorKind::AlreadyExists => ErrorClass::Recoverable,
+            ErrorKind::InvalidInput => ErrorClass::InvalidArgument,
+            _ => ErrorClass::Fatal,
+        }
+    }
+}
+
+impl Error {
+    /// Classifies the error, so that callers can apply a uniform retry policy.
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Error::einstein_merkle_tree(msg) => ErrorClass::from_engine_message(msg),
+            Error::Io(e) => ErrorClass::from_io_error(e),
+            Error::NotInRange { .. }
+            | Error::EntriesUnavailable
+            | Error::EntriesCompacted => ErrorClass::Recoverable,
+            Error::WriteStalled { .. } => ErrorClass::Retryable,
+            Error::NAMESPACEDName(_) => ErrorClass::InvalidArgument,
+            Error::Protobuf(_) | Error::Codec(_) | Error::Other(_) | Error::Closed(_) => {
+                ErrorClass::Fatal
+            }
+            Error::Context { source, .. } => source.class(),
+        }
+    }
+
+    /// Wraps the error with a description of what was being done, keeping
+    /// its class and error code.
+    pub fn context<C: Into<String>>(self, context: C) -> Error {
+        Error::Context {
+            context: context.into(),
+            source: Box::new(self),
+        }
+    }
+
+    /// The error without any `context` wrapping it
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context { source, .. } => source.root(),
+            e => e,
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.class() == ErrorClass::Retryable
+    }
 }
 
 impl From<String> for Error {
@@ -58,19 +180,9 @@ impl ErrorCodeExt for Error {
             Error::Other(_) => error_code::UNKNOWN,
             Error::EntriesUnavailable => error_code::einstein_merkle_tree::DATALOSS,
             Error::EntriesCompacted => error_code::einstein_merkle_tree::DATACOMPACTED,
-        }
-    }
-}
-
-impl From<Error> for VioletaBFTError {
-    fn from(e: Error) -> VioletaBFTError {
-        match e {
-            Error::EntriesUnavailable => VioletaBFTError::TimelikeStore(StorageError::Unavailable),
-            Error::EntriesCompacted => VioletaBFTError::TimelikeStore(StorageError::Compacted),
-            e => {
-                let boxed = Box::new(e) as Box<dyn std::error::Error + Sync + Send>;
-                violetabft::Error::TimelikeStore(StorageError::Other(boxed))
-            }
+            Error::WriteStalled { .. } => error_code::einstein_merkle_tree::einstein_merkle_tree,
+            Error::Closed(_) => error_code::einstein_merkle_tree::einstein_merkle_tree,
+            Error::Context { source, .. } => source.error_code(),
         }
     }
 }
@@ -80,3 +192,43 @@ impl From<Error> for String {
         format!("{:?}", e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_class() {
+        let cases = vec![
+            ("Resource busy: ", ErrorClass::Retryable),
+            ("Operation failed. Try again.: memtable full", ErrorClass::Retryable),
+            ("Corruption: block checksum mismatch", ErrorClass::Fatal),
+            ("IO error: No space left on device", ErrorClass::Fatal),
+            ("Invalid argument: Column family not found", ErrorClass::InvalidArgument),
+            ("NotFound: ", ErrorClass::Recoverable),
+            ("something unexpected", ErrorClass::Fatal),
+        ];
+        for (msg, class) in cases {
+            assert_eq!(Error::einstein_merkle_tree(msg.to_owned()).class(), class, "{}", msg);
+        }
+
+        let e = Error::Io(std::io::Error::new(std::io::ErrorKind::Interrupted, "interrupted"));
+        assert!(e.is_retryable());
+        let e = Error::WriteStalled {
+            waited: Duration::from_secs(1),
+            reasons: vec![],
+        };
+        assert!(e.is_retryable());
+        assert_eq!(Error::EntriesCompacted.class(), ErrorClass::Recoverable);
+        assert_eq!(Error::NAMESPACEDName("foo".to_owned()).class(), ErrorClass::InvalidArgument);
+
+        let e = Error::EntriesCompacted.context("fetching entries").context("region 1");
+        assert_eq!(e.class(), ErrorClass::Recoverable);
+        assert_eq!(e.error_code(), error_code::einstein_merkle_tree::DATACOMPACTED);
+        assert!(matches!(e.root(), Error::EntriesCompacted));
+        assert_eq!(
+            e.to_string(),
+            "region 1: fetching entries: The entries of region is compacted"
+        );
+    }
+}
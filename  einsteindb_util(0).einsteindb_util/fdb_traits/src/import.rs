@@ -1,11 +1,117 @@
 // Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
 use crate::errors::Result;
+use crate::namespaced_options::{ColumnFamilyOptions, NAMESPACEDOptionsExt};
+
+/// Write buffer size used while a namespaced is in bulk-load mode.
+pub const BULK_LOAD_WRITE_BUFFER_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// The options of a single namespaced that bulk-load mode overrides.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BulkLoadNamespacedOptions {
+    pub namespaced: String,
+    pub disable_auto_jet_bundles: bool,
+    pub disable_write_stall: bool,
+    pub write_buffer_size: u64,
+}
+
+impl BulkLoadNamespacedOptions {
+    fn from_options<O: ColumnFamilyOptions>(namespaced: &str, opts: &O) -> BulkLoadNamespacedOptions {
+        BulkLoadNamespacedOptions {
+            namespaced: namespaced.to_owned(),
+            disable_auto_jet_bundles: opts.get_disable_auto_jet_bundles(),
+            disable_write_stall: opts.get_disable_write_stall(),
+            write_buffer_size: opts.get_write_buffer_size(),
+        }
+    }
+
+    fn apply<E: NAMESPACEDOptionsExt + ?Sized>(&self, einstein_merkle_tree: &E) -> Result<()> {
+        let write_buffer_size = self.write_buffer_size.to_string();
+        einstein_merkle_tree.set_options_namespaced(
+            &self.namespaced,
+            &[
+                ("disable_auto_compactions", bool_option(self.disable_auto_jet_bundles)),
+                ("disable_write_stall", bool_option(self.disable_write_stall)),
+                ("write_buffer_size", &write_buffer_size),
+            ],
+        )
+    }
+}
+
+fn bool_option(v: bool) -> &'static str {
+    if v { "true" } else { "false" }
+}
+
+/// The options in effect before `ImportExt::prepare_bulk_load`, needed to
+/// leave bulk-load mode again.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BulkLoadState {
+    previous: Vec<BulkLoadNamespacedOptions>,
+}
+
+impl BulkLoadState {
+    pub fn namespaceds(&self) -> impl Iterator<Item = &str> {
+        self.previous.iter().map(|o| o.namespaced.as_str())
+    }
+
+    pub fn previous_options(&self) -> &[BulkLoadNamespacedOptions] {
+        &self.previous
+    }
+}
 
 pub trait ImportExt {
     type IngestlightlikeFileOptions: IngestlightlikeFileOptions;
 
     fn ingest_lightlike_file_namespaced(&self, namespaced: &str, filefs: &[&str]) -> Result<()>;
+
+    /// Switches `namespaceds` to options suited to a large ingest: auto
+    /// jet_bundle and write stalls are disabled and write buffers are enlarged,
+    /// so the ingest doesn't fight background jet_bundle.
+    ///
+    /// The returned state must be passed to `finish_bulk_load` to restore the
+    /// previous options. If switching any namespaced fails, those already
+    /// switched are restored before returning the error.
+    fn prepare_bulk_load(&self, namespaceds: &[&str]) -> Result<BulkLoadState>
+    where
+        Self: NAMESPACEDOptionsExt,
+    {
+        let mut state = BulkLoadState::default();
+        for namespaced in namespaceds {
+            let opts = self.get_options_namespaced(namespaced)?;
+            let previous = BulkLoadNamespacedOptions::from_options(namespaced, &opts);
+            let bulk_load = BulkLoadNamespacedOptions {
+                namespaced: namespaced.to_string(),
+                disable_auto_jet_bundles: true,
+                disable_write_stall: true,
+                write_buffer_size: std::cmp::max(previous.write_buffer_size, BULK_LOAD_WRITE_BUFFER_SIZE),
+            };
+            if let Err(e) = bulk_load.apply(self) {
+                let _ = self.finish_bulk_load(state);
+                return Err(e);
+            }
+            state.previous.push(previous);
+        }
+        Ok(state)
+    }
+
+    /// Restores the options saved by `prepare_bulk_load`.
+    ///
+    /// Every namespaced is restored even if some fail; the first error is
+    /// returned.
+    fn finish_bulk_load(&self, state: BulkLoadState) -> Result<()>
+    where
+        Self: NAMESPACEDOptionsExt,
+    {
+        let mut res = Ok(());
+        for previous in state.previous.iter().rev() {
+            if let Err(e) = previous.apply(self) {
+                if res.is_ok() {
+                    res = Err(e);
+                }
+            }
+        }
+        res
+    }
 }
 
 pub trait IngestlightlikeFileOptions {
@@ -0,0 +1,349 @@
+// Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! Rate limiting of disk I/O by class
+//!
+//! Flush, compaction and ingest share one disk with foreground reads and
+//! writes. An `IOClassLimiter` gives each `IOClass` its own budget of bytes
+//! per second, so that heavy compaction can be held back without starving
+//! scans. Budgets can be changed at runtime through the same option pairs as
+//! `DBOptionsExt::set_db_options`, see `DBOptionsExt::set_db_options_with_io_limiter`.
+//!
+//! A request larger than the bytes available is let through at once but
+//! leaves the class in debt, which later requests wait out. The bytes that
+//! had to wait are counted per class.
+//!
+//! Engines charge their file reads and writes to a limiter through an
+//! `IOClassInspector`, which classifies each by the `IOType` of the thread
+//! doing it.
+
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use file::{get_io_type, IOType};
+
+use crate::errors::{Error, Result};
+use crate::file_system::FileInspector;
+
+/// The classes of I/O budgeted separately
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IOClass {
+    /// Reads and writes serving requests
+    Foreground,
+    /// Flush, compaction and ingest
+    Background,
+    /// Garbage collection of old versions
+    Gc,
+}
+
+impl IOClass {
+    pub const ALL: [IOClass; 3] = [IOClass::Foreground, IOClass::Background, IOClass::Gc];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IOClass::Foreground => "foreground",
+            IOClass::Background => "background",
+            IOClass::Gc => "gc",
+        }
+    }
+
+    /// The option setting this class' rate, e.g. `background_io_rate_bytes_per_sec`
+    pub fn rate_option(self) -> &'static str {
+        match self {
+            IOClass::Foreground => "foreground_io_rate_bytes_per_sec",
+            IOClass::Background => "background_io_rate_bytes_per_sec",
+            IOClass::Gc => "gc_io_rate_bytes_per_sec",
+        }
+    }
+
+    /// The class I/O of `io_type` is charged to
+    pub fn of(io_type: IOType) -> IOClass {
+        match io_type {
+            IOType::Flush
+            | IOType::LevelZeroCompaction
+            | IOType::Compaction
+            | IOType::Import
+            | IOType::Export => IOClass::Background,
+            IOType::Gc => IOClass::Gc,
+            _ => IOClass::Foreground,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            IOClass::Foreground => 0,
+            IOClass::Background => 1,
+            IOClass::Gc => 2,
+        }
+    }
+}
+
+impl FromStr for IOClass {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<IOClass> {
+        IOClass::ALL
+            .iter()
+            .find(|c| c.as_str() == s)
+            .cloned()
+            .ok_or_else(|| Error::Other(format!("unknown I/O class {}", s).into()))
+    }
+}
+
+/// Statistics of one class since the limiter was created
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IOClassStats {
+    /// Bytes requested
+    pub requested_bytes: u64,
+    /// Bytes whose request had to wait for budget
+    pub throttled_bytes: u64,
+    /// Total time requests waited for budget
+    pub throttled_duration: Duration,
+}
+
+/// How much of a rate can be spent at once after idling
+const BURST: Duration = Duration::from_millis(100);
+
+struct Bucket {
+    /// Bytes per second, or 0 for no limit
+    rate: u64,
+    /// Bytes that may be spent now; negative while in debt
+    available: f64,
+    last: Instant,
+    stats: IOClassStats,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Bucket {
+        Bucket {
+            rate,
+            available: Bucket::burst(rate),
+            last: Instant::now(),
+            stats: IOClassStats::default(),
+        }
+    }
+
+    fn burst(rate: u64) -> f64 {
+        rate as f64 * BURST.as_secs_f64()
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.available = (self.available + elapsed * self.rate as f64).min(Bucket::burst(self.rate));
+        self.last = now;
+    }
+}
+
+/// A rate limiter with a separate budget per `IOClass`
+pub struct IOClassLimiter {
+    buckets: [Mutex<Bucket>; 3],
+}
+
+impl IOClassLimiter {
+    /// Creates a limiter with every class unlimited
+    pub fn new() -> IOClassLimiter {
+        IOClassLimiter {
+            buckets: [
+                Mutex::new(Bucket::new(0)),
+                Mutex::new(Bucket::new(0)),
+                Mutex::new(Bucket::new(0)),
+            ],
+        }
+    }
+
+    /// Sets the rate of `class` in bytes per second, 0 meaning unlimited
+    pub fn set_rate(&self, class: IOClass, bytes_per_sec: u64) {
+        let mut bucket = self.buckets[class.index()].lock().unwrap();
+        bucket.refill(Instant::now());
+        bucket.rate = bytes_per_sec;
+        // Forgive any debt run up under the old rate.
+        bucket.available = Bucket::burst(bytes_per_sec);
+    }
+
+    pub fn rate(&self, class: IOClass) -> u64 {
+        self.buckets[class.index()].lock().unwrap().rate
+    }
+
+    /// Takes `bytes` from the budget of `class`, blocking until the class is
+    /// out of debt
+    pub fn acquire(&self, class: IOClass, bytes: usize) {
+        let wait = {
+            let mut bucket = self.buckets[class.index()].lock().unwrap();
+            bucket.stats.requested_bytes += bytes as u64;
+            if bucket.rate == 0 {
+                return;
+            }
+            bucket.refill(Instant::now());
+            let wait = if bucket.available < 0.0 {
+                Duration::from_secs_f64(-bucket.available / bucket.rate as f64)
+            } else {
+                Duration::from_secs(0)
+            };
+            bucket.available -= bytes as f64;
+            if wait > Duration::from_secs(0) {
+                bucket.stats.throttled_bytes += bytes as u64;
+                bucket.stats.throttled_duration += wait;
+            }
+            wait
+        };
+        if wait > Duration::from_secs(0) {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Takes `bytes` from the budget of `class` if that many are available
+    /// now, without going into debt
+    pub fn try_acquire(&self, class: IOClass, bytes: usize) -> bool {
+        let mut bucket = self.buckets[class.index()].lock().unwrap();
+        if bucket.rate != 0 {
+            bucket.refill(Instant::now());
+            if bucket.available < bytes as f64 {
+                return false;
+            }
+            bucket.available -= bytes as f64;
+        }
+        bucket.stats.requested_bytes += bytes as u64;
+        true
+    }
+
+    pub fn stats(&self, class: IOClass) -> IOClassStats {
+        self.buckets[class.index()].lock().unwrap().stats
+    }
+
+    /// Applies the `IOClass::rate_option` pairs in `options`, returning the
+    /// other pairs. Nothing is applied if any rate fails to parse.
+    pub fn apply_options<'a>(&self, options: &[(&'a str, &'a str)]) -> Result<Vec<(&'a str, &'a str)>> {
+        let mut rates = vec![];
+        let mut rest = vec![];
+        for &(name, value) in options {
+            match IOClass::ALL.iter().find(|c| c.rate_option() == name) {
+                Some(&class) => {
+                    let rate = value.parse::<u64>().map_err(|e| {
+                        Error::Other(format!("invalid {} {:?}: {}", name, value, e).into())
+                    })?;
+                    rates.push((class, rate));
+                }
+                None => rest.push((name, value)),
+            }
+        }
+        for (class, rate) in rates {
+            self.set_rate(class, rate);
+        }
+        Ok(rest)
+    }
+}
+
+impl Default for IOClassLimiter {
+    fn default() -> IOClassLimiter {
+        IOClassLimiter::new()
+    }
+}
+
+/// A `FileInspector` that charges every read and write to the class of the
+/// I/O type of the calling thread
+#[derive(Clone)]
+pub struct IOClassInspector {
+    limiter: Arc<IOClassLimiter>,
+}
+
+impl IOClassInspector {
+    pub fn new(limiter: Arc<IOClassLimiter>) -> IOClassInspector {
+        IOClassInspector { limiter }
+    }
+
+    pub fn limiter(&self) -> &Arc<IOClassLimiter> {
+        &self.limiter
+    }
+}
+
+impl FileInspector for IOClassInspector {
+    fn read(&self, len: usize) -> std::result::Result<usize, String> {
+        self.limiter.acquire(IOClass::of(get_io_type()), len);
+        Ok(len)
+    }
+
+    fn write(&self, len: usize) -> std::result::Result<usize, String> {
+        self.limiter.acquire(IOClass::of(get_io_type()), len);
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_class_limiter() {
+        let limiter = IOClassLimiter::new();
+
+        // Unlimited classes never wait.
+        limiter.acquire(IOClass::Foreground, 1 << 30);
+        assert!(limiter.try_acquire(IOClass::Foreground, 1 << 30));
+        assert_eq!(limiter.stats(IOClass::Foreground).requested_bytes, 2 << 30);
+        assert_eq!(limiter.stats(IOClass::Foreground).throttled_bytes, 0);
+
+        // 100KiB/s allows a 10KiB burst.
+        limiter.set_rate(IOClass::Background, 100 * 1024);
+        assert!(!limiter.try_acquire(IOClass::Background, 20 * 1024));
+        assert!(limiter.try_acquire(IOClass::Background, 8 * 1024));
+        assert!(!limiter.try_acquire(IOClass::Background, 8 * 1024));
+
+        // Going into debt is let through; paying it off waits.
+        limiter.set_rate(IOClass::Gc, 100 * 1024);
+        limiter.acquire(IOClass::Gc, 12 * 1024);
+        assert_eq!(limiter.stats(IOClass::Gc).throttled_bytes, 0);
+        let start = Instant::now();
+        limiter.acquire(IOClass::Gc, 1024);
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        let stats = limiter.stats(IOClass::Gc);
+        assert_eq!(stats.throttled_bytes, 1024);
+        assert!(stats.throttled_duration > Duration::from_secs(0));
+
+        // Other classes are unaffected.
+        assert!(limiter.try_acquire(IOClass::Foreground, 1 << 20));
+    }
+
+    #[test]
+    fn test_io_class_limiter_options() {
+        let limiter = IOClassLimiter::new();
+        let rest = limiter
+            .apply_options(&[
+                ("background_io_rate_bytes_per_sec", "1048576"),
+                ("max_background_jobs", "4"),
+                ("gc_io_rate_bytes_per_sec", "0"),
+            ])
+            .unwrap();
+        assert_eq!(rest, vec![("max_background_jobs", "4")]);
+        assert_eq!(limiter.rate(IOClass::Background), 1 << 20);
+        assert_eq!(limiter.rate(IOClass::Gc), 0);
+
+        assert!(limiter
+            .apply_options(&[
+                ("foreground_io_rate_bytes_per_sec", "1"),
+                ("gc_io_rate_bytes_per_sec", "fast"),
+            ])
+            .is_err());
+        assert_eq!(limiter.rate(IOClass::Foreground), 0);
+
+        assert_eq!("gc".parse::<IOClass>().unwrap(), IOClass::Gc);
+        assert!("compaction".parse::<IOClass>().is_err());
+    }
+
+    #[test]
+    fn test_io_class_inspector() {
+        let limiter = Arc::new(IOClassLimiter::new());
+        let inspector = IOClassInspector::new(limiter.clone());
+
+        file::set_io_type(IOType::Compaction);
+        assert_eq!(inspector.write(4096), Ok(4096));
+        file::set_io_type(IOType::Gc);
+        assert_eq!(inspector.read(1024), Ok(1024));
+        file::set_io_type(IOType::ForegroundRead);
+        assert_eq!(inspector.read(512), Ok(512));
+
+        assert_eq!(limiter.stats(IOClass::Background).requested_bytes, 4096);
+        assert_eq!(limiter.stats(IOClass::Gc).requested_bytes, 1024);
+        assert_eq!(limiter.stats(IOClass::Foreground).requested_bytes, 512);
+    }
+}
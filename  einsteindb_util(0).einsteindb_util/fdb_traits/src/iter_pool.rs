@@ -0,0 +1,340 @@
+// Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! Reuse of iterators across scans
+//!
+//! Creating an engine iterator allocates, which shows up in hot scan paths
+//! that create one iterator per scan. An `IterPool` keeps the iterators of
+//! finished scans, keyed by column family and iterator options, and hands
+//! them out again to later scans with the same key.
+//!
+//! An iterator sees the data as of its creation, so a pool must only be
+//! shared by scans of one unchanging view, such as a `LightlikePersistence`.
+//! A pooled iterator is in no particular position when it is handed out:
+//! like a new iterator, it must be seeked before use.
+
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::errors::Result;
+use crate::iterable::{Iterable, Iterator};
+use crate::options::IterOptions;
+
+/// The number of idle iterators an `IterPool` keeps per key by default
+pub const DEFAULT_ITER_POOL_CAPACITY: usize = 4;
+
+/// Every option that affects what an iterator yields, compared in full so
+/// that iterators with different options never share a key
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct IterOptionsKey {
+    lower_bound: Option<Vec<u8>>,
+    upper_bound: Option<Vec<u8>>,
+    prefix_same_as_start: bool,
+    fill_cache: bool,
+    hint_min_ts: Option<u64>,
+    hint_max_ts: Option<u64>,
+    key_only: bool,
+    prefix_seek_used: bool,
+    max_skippable_internal_keys: u64,
+}
+
+impl IterOptionsKey {
+    fn new(opts: &IterOptions) -> IterOptionsKey {
+        IterOptionsKey {
+            lower_bound: opts.lower_bound().map(<[u8]>::to_vec),
+            upper_bound: opts.upper_bound().map(<[u8]>::to_vec),
+            prefix_same_as_start: opts.prefix_same_as_start(),
+            fill_cache: opts.fill_cache(),
+            hint_min_ts: opts.hint_min_ts(),
+            hint_max_ts: opts.hint_max_ts(),
+            key_only: opts.key_only(),
+            prefix_seek_used: opts.prefix_seek_used(),
+            max_skippable_internal_keys: opts.max_skippable_internal_keys(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct IterPoolKey {
+    namespaced: String,
+    options: IterOptionsKey,
+}
+
+/// Counters of an `IterPool`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IterPoolStats {
+    /// Iterators created because the pool had none to hand out
+    pub created: u64,
+    /// Iterators handed out again
+    pub reused: u64,
+    /// Iterators given back to the pool and kept
+    pub returned: u64,
+    /// Iterators given back to a full pool, or discarded by their user
+    pub discarded: u64,
+}
+
+impl IterPoolStats {
+    /// The fraction of iterators handed out that were reused
+    pub fn reuse_rate(&self) -> f64 {
+        let total = self.created + self.reused;
+        if total == 0 {
+            0.0
+        } else {
+            self.reused as f64 / total as f64
+        }
+    }
+}
+
+/// A pool of idle iterators, keyed by column family and options
+pub struct IterPool<I: Iterator> {
+    capacity_per_key: usize,
+    idle: Mutex<HashMap<IterPoolKey, Vec<I>>>,
+    created: AtomicU64,
+    reused: AtomicU64,
+    returned: AtomicU64,
+    discarded: AtomicU64,
+}
+
+impl<I: Iterator> IterPool<I> {
+    pub fn new(capacity_per_key: usize) -> IterPool<I> {
+        IterPool {
+            capacity_per_key,
+            idle: Mutex::new(HashMap::default()),
+            created: AtomicU64::new(0),
+            reused: AtomicU64::new(0),
+            returned: AtomicU64::new(0),
+            discarded: AtomicU64::new(0),
+        }
+    }
+
+    /// Takes an idle iterator over `namespaced` with `opts`, calling `create`
+    /// for a new one if there is none
+    pub fn get<F>(&self, namespaced: &str, opts: &IterOptions, create: F) -> Result<PooledIter<'_, I>>
+    where
+        F: FnOnce() -> Result<I>,
+    {
+        let key = IterPoolKey {
+            namespaced: namespaced.to_owned(),
+            options: IterOptionsKey::new(opts),
+        };
+        let idle = self.idle.lock().unwrap().get_mut(&key).and_then(Vec::pop);
+        let iter = match idle {
+            Some(iter) => {
+                self.reused.fetch_add(1, Ordering::Relaxed);
+                iter
+            }
+            None => {
+                let iter = create()?;
+                self.created.fetch_add(1, Ordering::Relaxed);
+                iter
+            }
+        };
+        Ok(PooledIter {
+            pool: self,
+            key,
+            iter: Some(iter),
+        })
+    }
+
+    fn put_back(&self, key: IterPoolKey, iter: I) {
+        let mut idle = self.idle.lock().unwrap();
+        let iters = idle.entry(key).or_insert_with(Vec::new);
+        if iters.len() < self.capacity_per_key {
+            iters.push(iter);
+            self.returned.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.discarded.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Drops every idle iterator
+    pub fn clear(&self) {
+        self.idle.lock().unwrap().clear();
+    }
+
+    /// The number of idle iterators
+    pub fn idle_count(&self) -> usize {
+        let mut count = 0;
+        for iters in self.idle.lock().unwrap().values() {
+            count += iters.len();
+        }
+        count
+    }
+
+    pub fn stats(&self) -> IterPoolStats {
+        IterPoolStats {
+            created: self.created.load(Ordering::Relaxed),
+            reused: self.reused.load(Ordering::Relaxed),
+            returned: self.returned.load(Ordering::Relaxed),
+            discarded: self.discarded.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl<I: Iterator> Default for IterPool<I> {
+    fn default() -> IterPool<I> {
+        IterPool::new(DEFAULT_ITER_POOL_CAPACITY)
+    }
+}
+
+/// An iterator on loan from an `IterPool`, given back when dropped
+pub struct PooledIter<'a, I: Iterator> {
+    pool: &'a IterPool<I>,
+    key: IterPoolKey,
+    iter: Option<I>,
+}
+
+impl<'a, I: Iterator> PooledIter<'a, I> {
+    /// Drops the iterator rather than giving it back, e.g. after an error
+    /// left it in a state not worth reusing
+    pub fn discard(mut self) {
+        self.iter = None;
+        self.pool.discarded.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl<'a, I: Iterator> Deref for PooledIter<'a, I> {
+    type Target = I;
+
+    fn deref(&self) -> &I {
+        self.iter.as_ref().unwrap()
+    }
+}
+
+impl<'a, I: Iterator> DerefMut for PooledIter<'a, I> {
+    fn deref_mut(&mut self) -> &mut I {
+        self.iter.as_mut().unwrap()
+    }
+}
+
+impl<'a, I: Iterator> Drop for PooledIter<'a, I> {
+    fn drop(&mut self) {
+        if let Some(iter) = self.iter.take() {
+            self.pool.put_back(self.key.clone(), iter);
+        }
+    }
+}
+
+/// Iterables that keep a pool of their iterators
+pub trait IterPoolExt: Iterable {
+    fn iter_pool(&self) -> &IterPool<Self::Iterator>;
+
+    fn pooled_iterator_namespaced_opt(
+        &self,
+        namespaced: &str,
+        opts: IterOptions,
+    ) -> Result<PooledIter<'_, Self::Iterator>> {
+        self.iter_pool().get(namespaced, &opts, || {
+            self.iterator_namespaced_opt(namespaced, opts.clone())
+        })
+    }
+
+    fn iter_pool_stats(&self) -> IterPoolStats {
+        self.iter_pool().stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iterable::SeekKey;
+
+    struct VecIter {
+        id: usize,
+        pos: Option<usize>,
+    }
+
+    impl Iterator for VecIter {
+        fn seek(&mut self, _: SeekKey<'_>) -> Result<bool> {
+            self.pos = Some(0);
+            Ok(true)
+        }
+
+        fn seek_for_prev(&mut self, key: SeekKey<'_>) -> Result<bool> {
+            self.seek(key)
+        }
+
+        fn prev(&mut self) -> Result<bool> {
+            Ok(false)
+        }
+
+        fn next(&mut self) -> Result<bool> {
+            Ok(false)
+        }
+
+        fn key(&self) -> &[u8] {
+            b"k"
+        }
+
+        fn value(&self) -> &[u8] {
+            b"v"
+        }
+
+        fn valid(&self) -> Result<bool> {
+            Ok(self.pos.is_some())
+        }
+    }
+
+    #[test]
+    fn test_iter_pool() {
+        let pool = IterPool::new(1);
+        let opts = IterOptions::default();
+        let mut key_only = IterOptions::default();
+        key_only.set_key_only(true);
+        assert_ne!(IterOptionsKey::new(&opts), IterOptionsKey::new(&key_only));
+        let mut bounded_a = IterOptions::default();
+        bounded_a.set_vec_upper_bound(b"a".to_vec());
+        let mut bounded_b = IterOptions::default();
+        bounded_b.set_vec_upper_bound(b"b".to_vec());
+        assert_ne!(IterOptionsKey::new(&bounded_a), IterOptionsKey::new(&bounded_b));
+        assert_eq!(IterOptionsKey::new(&bounded_a), IterOptionsKey::new(&bounded_a.clone()));
+
+        let mut next_id = 0;
+        let mut create = || {
+            next_id += 1;
+            Ok(VecIter { id: next_id, pos: None })
+        };
+
+        let first_id = {
+            let mut iter = pool.get("default", &opts, &mut create).unwrap();
+            iter.seek_to_first().unwrap();
+            iter.id
+        };
+        assert_eq!(pool.idle_count(), 1);
+
+        // Same key: reused.
+        let iter = pool.get("default", &opts, &mut create).unwrap();
+        assert_eq!(iter.id, first_id);
+        // Different options or column family: created.
+        let other = pool.get("default", &key_only, &mut create).unwrap();
+        assert_ne!(other.id, first_id);
+        let another = pool.get("write", &opts, &mut create).unwrap();
+        assert_ne!(another.id, first_id);
+        drop(iter);
+        drop(other);
+        drop(another);
+        assert_eq!(pool.idle_count(), 3);
+
+        // The pool keeps one iterator per key.
+        let a = pool.get("default", &opts, &mut create).unwrap();
+        let b = pool.get("default", &opts, &mut create).unwrap();
+        drop(a);
+        drop(b);
+        pool.get("write", &opts, &mut create).unwrap().discard();
+
+        assert_eq!(
+            pool.stats(),
+            IterPoolStats {
+                created: 4,
+                reused: 3,
+                returned: 5,
+                discarded: 2,
+            }
+        );
+        assert!((pool.stats().reuse_rate() - 3.0 / 7.0).abs() < 1e-9);
+
+        pool.clear();
+        assert_eq!(pool.idle_count(), 0);
+    }
+}
@@ -38,6 +38,44 @@ pub enum SeekKey<'a> {
     Key(&'a [u8]),
 }
 
+/// Work an iterator did beyond the keys it stopped at, as counted by the
+/// einstein_merkle_tree's perf counters.
+///
+/// Many internal keys skipped per key returned point at a pileup of
+/// tombstones or old versions in the scanned range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IterStats {
+    /// Internal keys skipped over, including tombstones, overwritten versions
+    /// and versions newer than the iterator's sequence number
+    pub internal_keys_skipped: u64,
+    /// Tombstones skipped over
+    pub tombstones_skipped: u64,
+    /// Blocks read from Causet filefs
+    pub blocks_read: u64,
+    pub block_bytes_read: u64,
+}
+
+impl IterStats {
+    pub fn add(&mut self, other: &IterStats) {
+        self.internal_keys_skipped += other.internal_keys_skipped;
+        self.tombstones_skipped += other.tombstones_skipped;
+        self.blocks_read += other.blocks_read;
+        self.block_bytes_read += other.block_bytes_read;
+    }
+
+    /// The counts from `earlier` to `self`, two readings of the same counters.
+    /// Counters reset in between count from zero.
+    pub fn since(&self, earlier: &IterStats) -> IterStats {
+        let delta = |now: u64, then: u64| now.checked_sub(then).unwrap_or(now);
+        IterStats {
+            internal_keys_skipped: delta(self.internal_keys_skipped, earlier.internal_keys_skipped),
+            tombstones_skipped: delta(self.tombstones_skipped, earlier.tombstones_skipped),
+            blocks_read: delta(self.blocks_read, earlier.blocks_read),
+            block_bytes_read: delta(self.block_bytes_read, earlier.block_bytes_read),
+        }
+    }
+}
+
 /// An iterator over a consistent set of keys and values.
 ///
 /// Iterators are implemented for `KV`s and for `LightlikePersistence`s. They see a
@@ -125,6 +163,15 @@ pub trait Iterator: Send {
 
     /// Returns `true` if the iterator points to a `key`/`value` pair.
     fn valid(&self) -> Result<bool>;
+
+    /// The work done by the seeks and moves of this iterator so far.
+    ///
+    /// Counts are only kept while the perf l_naught is at least
+    /// `PerfLevel::EnableCount`, and are all zero for einstein_merkle_trees
+    /// without perf counters.
+    fn iter_stats(&self) -> IterStats {
+        IterStats::default()
+    }
 }
 
 pub trait Iterable {
@@ -144,6 +191,15 @@ pub trait Iterable {
     /// scan the key between start_key(inclusive) and end_key(exclusive),
     /// the upper bound is omitted if end_key is empty
     fn scan<F>(&self, start_key: &[u8], end_key: &[u8], fill_cache: bool, f: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<bool>,
+    {
+        self.scan_with_stats(start_key, end_key, fill_cache, f).map(|_| ())
+    }
+
+    /// like `scan`, returning the `IterStats` of the scan, e.g. to tell a
+    /// slow scan over a pileup of tombstones from one over many keys.
+    fn scan_with_stats<F>(&self, start_key: &[u8], end_key: &[u8], fill_cache: bool, f: F) -> Result<IterStats>
     where
         F: FnMut(&[u8], &[u8]) -> Result<bool>,
     {
@@ -163,6 +219,22 @@ pub trait Iterable {
         fill_cache: bool,
         f: F,
     ) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<bool>,
+    {
+        self.scan_namespaced_with_stats(namespaced, start_key, end_key, fill_cache, f)
+            .map(|_| ())
+    }
+
+    // like `scan_with_stats`, only on a specific column family.
+    fn scan_namespaced_with_stats<F>(
+        &self,
+        namespaced: &str,
+        start_key: &[u8],
+        end_key: &[u8],
+        fill_cache: bool,
+        f: F,
+    ) -> Result<IterStats>
     where
         F: FnMut(&[u8], &[u8]) -> Result<bool>,
     {
@@ -173,6 +245,34 @@ pub trait Iterable {
         scan_impl(self.iterator_namespaced_opt(namespaced, iter_opt)?, start_key, f)
     }
 
+    /// like `scan`, over every key in `ranges`, in key order.
+    ///
+    /// Overlapping and adjacent ranges are coalesced first, so each key is
+    /// visited once however many of `ranges` cover it. `f` returning `false`
+    /// stops the whole scan.
+    fn scan_ranges<F>(&self, ranges: &[Range<'_>], fill_cache: bool, f: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<bool>,
+    {
+        scan_ranges_impl(ranges, f, |range, f| self.scan(range.start_key, range.end_key, fill_cache, f))
+    }
+
+    // like `scan_ranges`, only on a specific column family.
+    fn scan_ranges_namespaced<F>(
+        &self,
+        namespaced: &str,
+        ranges: &[Range<'_>],
+        fill_cache: bool,
+        f: F,
+    ) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<bool>,
+    {
+        scan_ranges_impl(ranges, f, |range, f| {
+            self.scan_namespaced(namespaced, range.start_key, range.end_key, fill_cache, f)
+        })
+    }
+
     // Seek the first key >= given key, if not found, return None.
     fn seek(&self, key: &[u8]) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
         let mut iter = self.iterator()?;
@@ -193,7 +293,7 @@ pub trait Iterable {
     }
 }
 
-fn scan_impl<Iter, F>(mut it: Iter, start_key: &[u8], mut f: F) -> Result<()>
+fn scan_impl<Iter, F>(mut it: Iter, start_key: &[u8], mut f: F) -> Result<IterStats>
 where
     Iter: Iterator,
     F: FnMut(&[u8], &[u8]) -> Result<bool>,
@@ -202,6 +302,26 @@ where
     while remained {
         remained = f(it.key(), it.value())? && it.next()?;
     }
+    Ok(it.iter_stats())
+}
+
+/// Scans the coalesced `ranges` in order with `scan`, until `f` returns `false`.
+fn scan_ranges_impl<F, S>(ranges: &[Range<'_>], mut f: F, mut scan: S) -> Result<()>
+where
+    F: FnMut(&[u8], &[u8]) -> Result<bool>,
+    S: FnMut(Range<'_>, &mut dyn FnMut(&[u8], &[u8]) -> Result<bool>) -> Result<()>,
+{
+    let mut stopped = false;
+    for range in RangeSet::from_ranges(ranges).iter() {
+        scan(range, &mut |k, v| {
+            let more = f(k, v)?;
+            stopped = !more;
+            Ok(more)
+        })?;
+        if stopped {
+            break;
+        }
+    }
     Ok(())
 }
 
@@ -226,3 +346,109 @@ pub fn collect<I: Iterator>(mut it: I) -> Vec<(Vec<u8>, Vec<u8>)> {
     }
     v
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct VecIter {
+        pairs: Vec<(Vec<u8>, Vec<u8>)>,
+        upper_bound: Option<Vec<u8>>,
+        pos: usize,
+    }
+
+    impl Iterator for VecIter {
+        fn seek(&mut self, key: SeekKey<'_>) -> Result<bool> {
+            self.pos = match key {
+                SeekKey::Start => 0,
+                SeekKey::End => self.pairs.len().saturating_sub(1),
+                SeekKey::Key(key) => self.pairs.iter().take_while(|(k, _)| k.as_slice() < key).count(),
+            };
+            self.valid()
+        }
+
+        fn seek_for_prev(&mut self, key: SeekKey<'_>) -> Result<bool> {
+            self.seek(key)
+        }
+
+        fn prev(&mut self) -> Result<bool> {
+            self.pos = self.pos.wrapping_sub(1);
+            self.valid()
+        }
+
+        fn next(&mut self) -> Result<bool> {
+            self.pos += 1;
+            self.valid()
+        }
+
+        fn key(&self) -> &[u8] {
+            &self.pairs[self.pos].0
+        }
+
+        fn value(&self) -> &[u8] {
+            &self.pairs[self.pos].1
+        }
+
+        fn valid(&self) -> Result<bool> {
+            Ok(self.pos < self.pairs.len()
+                && self.upper_bound.as_ref().map_or(true, |b| self.pairs[self.pos].0 < *b))
+        }
+    }
+
+    struct VecEngine(Vec<(Vec<u8>, Vec<u8>)>);
+
+    impl Iterable for VecEngine {
+        type Iterator = VecIter;
+
+        fn iterator_opt(&self, opts: IterOptions) -> Result<VecIter> {
+            Ok(VecIter {
+                pairs: self.0.clone(),
+                upper_bound: opts.upper_bound().map(|b| b.to_vec()),
+                pos: 0,
+            })
+        }
+
+        fn iterator_namespaced_opt(&self, _: &str, opts: IterOptions) -> Result<VecIter> {
+            self.iterator_opt(opts)
+        }
+    }
+
+    fn engine() -> VecEngine {
+        VecEngine(
+            ["a", "b", "c", "d", "e", "f"]
+                .iter()
+                .map(|k| (k.as_bytes().to_vec(), k.to_uppercase().into_bytes()))
+                .collect(),
+        )
+    }
+
+    fn scanned(ranges: &[(&str, &str)], stop_at: Option<&str>) -> Vec<String> {
+        let ranges: Vec<_> = ranges
+            .iter()
+            .map(|(s, e)| Range::new(s.as_bytes(), e.as_bytes()))
+            .collect();
+        let mut keys = vec![];
+        engine()
+            .scan_ranges(&ranges, false, |k, _| {
+                let k = String::from_utf8(k.to_vec()).unwrap();
+                let more = stop_at != Some(k.as_str());
+                keys.push(k);
+                Ok(more)
+            })
+            .unwrap();
+        keys
+    }
+
+    #[test]
+    fn test_scan_ranges_visits_each_key_once() {
+        assert_eq!(scanned(&[("c", "e"), ("b", "d"), ("a", "b")], None), ["a", "b", "c", "d"]);
+        assert_eq!(scanned(&[("b", "c"), ("e", "")], None), ["b", "e", "f"]);
+        assert!(scanned(&[], None).is_empty());
+    }
+
+    #[test]
+    fn test_scan_ranges_stops_early() {
+        assert_eq!(scanned(&[("a", "b"), ("c", "")], Some("c")), ["a", "c"]);
+        assert_eq!(scanned(&[("a", "b"), ("c", "")], Some("a")), ["a"]);
+    }
+}
@@ -0,0 +1,567 @@
+// Copyright 2020 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! Compaction jobs
+//!
+//! `CompactionJobInfo` describes a compaction the einstein_merkle_tree ran.
+//!
+//! A `CompactionJobScheduler` runs manual compactions that were asked for:
+//! a `CompactionJob` names a column family and the ranges of it to compact
+//! with `CompactExt::compact_range`, and a priority. Jobs run highest
+//! priority first, in submission order within a priority, and at most
+//! `max_concurrent_jobs` at once, so that manual compaction can't take over
+//! the disk. Each job's progress is the number of its ranges compacted;
+//! subscribers are sent a `CompactionJobEvent` when a job finishes, fails or
+//! is cancelled.
+//!
+//! A compaction can't be interrupted, so cancelling a running job stops it
+//! before its next range; until then it stays `Running`.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::local_path::local_path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::compact::CompactExt;
+
+pub trait CompactionJobInfo {
+    type TableGreedoidsCollectionView;
+    type CompactionReason;
+    fn status(&self) -> Result<(), String>;
+    fn namespaced_name(&self) -> &str;
+    fn input_file_count(&self) -> usize;
+    fn num_input_filefs_at_output_l_naught(&self) -> usize;
+    fn input_file_at(&self, pos: usize) -> &local_path;
+    fn output_file_count(&self) -> usize;
+    fn output_file_at(&self, pos: usize) -> &local_path;
+    fn table_greedoids(&self) -> &Self::TableGreedoidsCollectionView;
+    fn base_input_l_naught(&self) -> i32;
+    fn elapsed_micros(&self) -> u64;
+    fn num_corrupt_keys(&self) -> u64;
+    fn output_l_naught(&self) -> i32;
+    fn input_records(&self) -> u64;
+    fn output_records(&self) -> u64;
+    fn total_input_bytes(&self) -> u64;
+    fn total_output_bytes(&self) -> u64;
+    fn jet_bundle_reason(&self) -> Self::CompactionReason;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CompactionPriority {
+    Low,
+    Normal,
+    High,
+}
+
+/// A range to compact; `None` bounds are unbounded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompactionRange {
+    pub start_key: Option<Vec<u8>>,
+    pub end_key: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CompactionJob {
+    pub namespaced: String,
+    pub ranges: Vec<CompactionRange>,
+    pub priority: CompactionPriority,
+    pub exclusive_manual: bool,
+    pub max_subjet_bundles: u32,
+}
+
+impl CompactionJob {
+    /// A normal-priority job compacting `ranges` of `namespaced`
+    pub fn new(namespaced: &str, ranges: Vec<CompactionRange>) -> CompactionJob {
+        CompactionJob {
+            namespaced: namespaced.to_owned(),
+            ranges,
+            priority: CompactionPriority::Normal,
+            exclusive_manual: false,
+            max_subjet_bundles: 1,
+        }
+    }
+}
+
+pub type CompactionJobId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompactionJobState {
+    Pending,
+    Running,
+    Finished,
+    Cancelled,
+    /// A range failed to compact with the given message
+    Failed(String),
+}
+
+impl CompactionJobState {
+    pub fn is_done(&self) -> bool {
+        !matches!(self, CompactionJobState::Pending | CompactionJobState::Running)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompactionJobProgress {
+    pub state: CompactionJobState,
+    pub ranges_done: usize,
+    pub ranges_total: usize,
+}
+
+/// Sent to subscribers when a job is done
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompactionJobEvent {
+    pub id: CompactionJobId,
+    pub namespaced: String,
+    pub progress: CompactionJobProgress,
+    /// From submission to being done
+    pub elapsed: Duration,
+}
+
+struct JobEntry {
+    job: Arc<CompactionJob>,
+    progress: CompactionJobProgress,
+    submitted: Instant,
+    /// Set when a running job is cancelled, for its worker to stop at
+    cancel_requested: bool,
+}
+
+struct SchedulerState {
+    next_id: CompactionJobId,
+    pending: BinaryHeap<(CompactionPriority, Reverse<CompactionJobId>)>,
+    jobs: HashMap<CompactionJobId, JobEntry>,
+    subscribers: Vec<Sender<CompactionJobEvent>>,
+    shutdown: bool,
+}
+
+impl SchedulerState {
+    /// Moves the job to `state`, notifying subscribers if it's done
+    fn set_state(&mut self, id: CompactionJobId, state: CompactionJobState) {
+        let entry = match self.jobs.get_mut(&id) {
+            Some(entry) => entry,
+            None => return,
+        };
+        entry.progress.state = state;
+        if !entry.progress.state.is_done() {
+            return;
+        }
+        let event = CompactionJobEvent {
+            id,
+            namespaced: entry.job.namespaced.clone(),
+            progress: entry.progress.clone(),
+            elapsed: entry.submitted.elapsed(),
+        };
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+struct SchedulerInner<E> {
+    engine: E,
+    state: Mutex<SchedulerState>,
+    work: Condvar,
+}
+
+/// Runs `CompactionJob`s against an einstein_merkle_tree on
+/// `max_concurrent_jobs` threads of its own
+///
+/// Dropping the scheduler cancels the pending jobs and waits for the running
+/// ones to finish their current range.
+pub struct CompactionJobScheduler<E: CompactExt + Send + Sync + 'static> {
+    inner: Arc<SchedulerInner<E>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<E: CompactExt + Send + Sync + 'static> CompactionJobScheduler<E> {
+    pub fn new(engine: E, max_concurrent_jobs: usize) -> CompactionJobScheduler<E> {
+        let inner = Arc::new(SchedulerInner {
+            engine,
+            state: Mutex::new(SchedulerState {
+                next_id: 1,
+                pending: BinaryHeap::new(),
+                jobs: HashMap::new(),
+                subscribers: vec![],
+                shutdown: false,
+            }),
+            work: Condvar::new(),
+        });
+        let workers = (0..max_concurrent_jobs.max(1))
+            .map(|i| {
+                let inner = inner.clone();
+                thread::Builder::new()
+                    .name(format!("compaction-job-{}", i))
+                    .spawn(move || run_jobs(&inner))
+                    .unwrap()
+            })
+            .collect();
+        CompactionJobScheduler { inner, workers }
+    }
+
+    pub fn max_concurrent_jobs(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn submit(&self, job: CompactionJob) -> CompactionJobId {
+        let mut state = self.inner.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.pending.push((job.priority, Reverse(id)));
+        state.jobs.insert(
+            id,
+            JobEntry {
+                progress: CompactionJobProgress {
+                    state: CompactionJobState::Pending,
+                    ranges_done: 0,
+                    ranges_total: job.ranges.len(),
+                },
+                job: Arc::new(job),
+                submitted: Instant::now(),
+                cancel_requested: false,
+            },
+        );
+        self.inner.work.notify_one();
+        id
+    }
+
+    /// The progress of the job, until `clear_done` forgets it
+    pub fn progress(&self, id: CompactionJobId) -> Option<CompactionJobProgress> {
+        let state = self.inner.state.lock().unwrap();
+        state.jobs.get(&id).map(|entry| entry.progress.clone())
+    }
+
+    /// Cancels the job if it isn't done; a running job stops before its next
+    /// range, or finishes if it was on its last. Returns whether the job was
+    /// cancelled.
+    pub fn cancel(&self, id: CompactionJobId) -> bool {
+        let mut state = self.inner.state.lock().unwrap();
+        let entry = match state.jobs.get_mut(&id) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        if entry.progress.state == CompactionJobState::Running && !entry.cancel_requested {
+            // The worker sees it before the next range, and notifies.
+            entry.cancel_requested = true;
+            return true;
+        }
+        if entry.progress.state != CompactionJobState::Pending {
+            return false;
+        }
+        state.set_state(id, CompactionJobState::Cancelled);
+        true
+    }
+
+    /// Receives an event for each job done from now on
+    pub fn subscribe(&self) -> Receiver<CompactionJobEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.inner.state.lock().unwrap().subscribers.push(tx);
+        rx
+    }
+
+    /// Forgets the jobs that are done; a cancelled job still running is kept
+    /// until it stops
+    pub fn clear_done(&self) {
+        let mut state = self.inner.state.lock().unwrap();
+        state
+            .jobs
+            .retain(|_, entry| !entry.progress.state.is_done());
+    }
+}
+
+impl<E: CompactExt + Send + Sync + 'static> Drop for CompactionJobScheduler<E> {
+    fn drop(&mut self) {
+        {
+            let mut state = self.inner.state.lock().unwrap();
+            state.shutdown = true;
+            while let Some((_, Reverse(id))) = state.pending.pop() {
+                if is_pending(&state, id) {
+                    state.set_state(id, CompactionJobState::Cancelled);
+                }
+            }
+            for entry in state.jobs.values_mut() {
+                if entry.progress.state == CompactionJobState::Running {
+                    entry.cancel_requested = true;
+                }
+            }
+        }
+        self.inner.work.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn is_pending(state: &SchedulerState, id: CompactionJobId) -> bool {
+    state
+        .jobs
+        .get(&id)
+        .map_or(false, |entry| entry.progress.state == CompactionJobState::Pending)
+}
+
+fn run_jobs<E: CompactExt>(inner: &SchedulerInner<E>) {
+    loop {
+        let (id, job) = {
+            let mut state = inner.state.lock().unwrap();
+            loop {
+                if state.shutdown {
+                    return;
+                }
+                match state.pending.pop() {
+                    Some((_, Reverse(id))) => {
+                        // Cancelled while pending, and maybe cleared since.
+                        let job = match state.jobs.get(&id) {
+                            Some(entry) if entry.progress.state == CompactionJobState::Pending => {
+                                entry.job.clone()
+                            }
+                            _ => continue,
+                        };
+                        state.set_state(id, CompactionJobState::Running);
+                        break (id, job);
+                    }
+                    None => state = inner.work.wait(state).unwrap(),
+                }
+            }
+        };
+
+        let mut outcome = CompactionJobState::Finished;
+        for range in &job.ranges {
+            let cancelled = inner
+                .state
+                .lock()
+                .unwrap()
+                .jobs
+                .get(&id)
+                .map_or(true, |entry| entry.cancel_requested);
+            if cancelled {
+                outcome = CompactionJobState::Cancelled;
+                break;
+            }
+            if let Err(e) = inner.engine.compact_range(
+                &job.namespaced,
+                range.start_key.as_deref(),
+                range.end_key.as_deref(),
+                job.exclusive_manual,
+                job.max_subjet_bundles,
+            ) {
+                outcome = CompactionJobState::Failed(e.to_string());
+                break;
+            }
+            if let Some(entry) = inner.state.lock().unwrap().jobs.get_mut(&id) {
+                entry.progress.ranges_done += 1;
+            }
+        }
+        inner.state.lock().unwrap().set_state(id, outcome);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    use crate::compact::CompactedEvent;
+    use crate::errors::{Error, Result};
+
+    /// The mock never reports compactions
+    enum MockCompactedEvent {}
+
+    impl CompactedEvent for MockCompactedEvent {
+        fn total_bytes_declined(&self) -> u64 {
+            match *self {}
+        }
+        fn is_size_declining_trivial(&self, _: u64) -> bool {
+            match *self {}
+        }
+        fn output_l_naught_label(&self) -> String {
+            match *self {}
+        }
+        fn calc_ranges_declined_bytes(self, _: &BTreeMap<Vec<u8>, u64>, _: u64) -> Vec<(u64, u64)> {
+            match self {}
+        }
+        fn namespaced(&self) -> &str {
+            match *self {}
+        }
+    }
+
+    /// Records the ranges compacted. Compacting from `block` waits for
+    /// `open`, and compacting from `bad` fails.
+    #[derive(Clone, Default)]
+    struct MockEngine {
+        compacted: Arc<Mutex<Vec<(String, Vec<u8>)>>>,
+        gate: Arc<(Mutex<bool>, Condvar)>,
+    }
+
+    impl MockEngine {
+        fn open(&self) {
+            *self.gate.0.lock().unwrap() = true;
+            self.gate.1.notify_all();
+        }
+
+        fn compacted(&self) -> Vec<(String, Vec<u8>)> {
+            self.compacted.lock().unwrap().clone()
+        }
+    }
+
+    impl CompactExt for MockEngine {
+        type CompactedEvent = MockCompactedEvent;
+
+        fn auto_jet_bundles_is_disabled(&self) -> Result<bool> {
+            Ok(false)
+        }
+
+        fn compact_range(
+            &self,
+            namespaced: &str,
+            start_key: Option<&[u8]>,
+            _: Option<&[u8]>,
+            _: bool,
+            _: u32,
+        ) -> Result<()> {
+            let start_key = start_key.unwrap_or_default();
+            if start_key == b"block" {
+                let mut open = self.gate.0.lock().unwrap();
+                while !*open {
+                    open = self.gate.1.wait(open).unwrap();
+                }
+            }
+            if start_key == b"bad" {
+                return Err(Error::einstein_merkle_tree("corruption".to_owned()));
+            }
+            self.compacted
+                .lock()
+                .unwrap()
+                .push((namespaced.to_owned(), start_key.to_vec()));
+            Ok(())
+        }
+
+        fn compact_filefs_in_range(&self, _: Option<&[u8]>, _: Option<&[u8]>, _: Option<i32>) -> Result<()> {
+            Err(files_unsupported())
+        }
+
+        fn compact_filefs_in_range_namespaced(
+            &self,
+            _: &str,
+            _: Option<&[u8]>,
+            _: Option<&[u8]>,
+            _: Option<i32>,
+        ) -> Result<()> {
+            Err(files_unsupported())
+        }
+
+        fn compact_filefs_namespaced(&self, _: &str, _: Vec<String>, _: Option<i32>, _: u32, _: bool) -> Result<()> {
+            Err(files_unsupported())
+        }
+    }
+
+    fn files_unsupported() -> Error {
+        Error::Other("the mock only compacts ranges".into())
+    }
+
+    fn range(start_key: &[u8]) -> CompactionRange {
+        CompactionRange {
+            start_key: Some(start_key.to_vec()),
+            end_key: None,
+        }
+    }
+
+    fn job(namespaced: &str, keys: &[&[u8]], priority: CompactionPriority) -> CompactionJob {
+        let mut job = CompactionJob::new(namespaced, keys.iter().map(|k| range(k)).collect());
+        job.priority = priority;
+        job
+    }
+
+    fn next_event(events: &Receiver<CompactionJobEvent>) -> CompactionJobEvent {
+        events.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    fn wait_running(scheduler: &CompactionJobScheduler<MockEngine>, id: CompactionJobId) {
+        while scheduler.progress(id).unwrap().state != CompactionJobState::Running {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_compaction_job_scheduler() {
+        let engine = MockEngine::default();
+        let scheduler = CompactionJobScheduler::new(engine.clone(), 1);
+        let events = scheduler.subscribe();
+
+        // Occupies the only worker until the gate opens.
+        let blocker = scheduler.submit(job("default", &[b"block"], CompactionPriority::Low));
+        wait_running(&scheduler, blocker);
+        let low = scheduler.submit(job("write", &[b"a", b"b"], CompactionPriority::Low));
+        let high = scheduler.submit(job("lock", &[b"c"], CompactionPriority::High));
+        let cancelled = scheduler.submit(job("default", &[b"d"], CompactionPriority::High));
+        assert_eq!(
+            scheduler.progress(low).unwrap(),
+            CompactionJobProgress {
+                state: CompactionJobState::Pending,
+                ranges_done: 0,
+                ranges_total: 2,
+            }
+        );
+
+        assert!(scheduler.cancel(cancelled));
+        let event = next_event(&events);
+        assert_eq!(event.id, cancelled);
+        assert_eq!(event.progress.state, CompactionJobState::Cancelled);
+        assert!(!scheduler.cancel(cancelled));
+
+        engine.open();
+        let done: Vec<_> = (0..3).map(|_| next_event(&events)).collect();
+        assert_eq!(
+            done.iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![blocker, high, low]
+        );
+        assert!(done.iter().all(|e| e.progress.state == CompactionJobState::Finished));
+        assert_eq!(scheduler.progress(low).unwrap().ranges_done, 2);
+        assert_eq!(
+            engine.compacted(),
+            vec![
+                ("default".to_owned(), b"block".to_vec()),
+                ("lock".to_owned(), b"c".to_vec()),
+                ("write".to_owned(), b"a".to_vec()),
+                ("write".to_owned(), b"b".to_vec()),
+            ]
+        );
+
+        // A failed range fails the job, and the rest isn't compacted.
+        let failed = scheduler.submit(job("write", &[b"e", b"bad", b"f"], CompactionPriority::Normal));
+        let event = next_event(&events);
+        assert_eq!(event.id, failed);
+        assert!(matches!(event.progress.state, CompactionJobState::Failed(_)));
+        assert_eq!(event.progress.ranges_done, 1);
+        assert_eq!(engine.compacted().len(), 5);
+
+        scheduler.clear_done();
+        assert!(scheduler.progress(failed).is_none());
+    }
+
+    #[test]
+    fn test_compaction_job_scheduler_cancel_running() {
+        let engine = MockEngine::default();
+        let scheduler = CompactionJobScheduler::new(engine.clone(), 2);
+        assert_eq!(scheduler.max_concurrent_jobs(), 2);
+        let events = scheduler.subscribe();
+
+        let running = scheduler.submit(job("default", &[b"block", b"a"], CompactionPriority::Normal));
+        wait_running(&scheduler, running);
+        assert!(scheduler.cancel(running));
+        assert!(!scheduler.cancel(running));
+        // It's still running, so clearing done jobs keeps it.
+        scheduler.clear_done();
+        assert_eq!(scheduler.progress(running).unwrap().state, CompactionJobState::Running);
+        engine.open();
+
+        // The range being compacted finishes; the next doesn't start.
+        let event = next_event(&events);
+        assert_eq!(event.progress.state, CompactionJobState::Cancelled);
+        assert_eq!(event.progress.ranges_done, 1);
+        assert_eq!(engine.compacted(), vec![("default".to_owned(), b"block".to_vec())]);
+        assert_eq!(scheduler.progress(running).unwrap(), event.progress);
+
+        // Dropping the scheduler disconnects subscribers.
+        drop(scheduler);
+        assert!(events.recv().is_err());
+    }
+}
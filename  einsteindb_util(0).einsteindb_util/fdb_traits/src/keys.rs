@@ -0,0 +1,264 @@
+// Copyright 2021 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! The layout of the keys an einstein_merkle_tree stores
+//!
+//! Keys fall in two spaces, told apart by their first byte:
+//!
+//! - data keys, `DATA_PREFIX` followed by the key a client wrote;
+//! - local keys, `LOCAL_PREFIX` followed by the kind of local key, which the
+//!   store keeps for itself:
+//!   - `STORE_IDENT_KEY` and `PREPARE_BOOTSTRAP_KEY`;
+//!   - VioletaBFT keys, `REGION_VIOLETABFT_PREFIX`, the region id and a suffix: a
+//!     log entry with its index, the VioletaBFT state or the apply state;
+//!   - meta keys, `REGION_META_PREFIX`, the region id and a suffix: the region
+//!     state.
+//!
+//! Ids and indexes are big endian so that keys sort in their order. The
+//! builders here make keys and `Key::parse` takes them apart again.
+
+use std::convert::TryInto;
+
+use ekvproto::metapb::Region;
+
+use crate::errors::{Error, Result};
+
+pub const MIN_KEY: &[u8] = &[];
+pub const MAX_KEY: &[u8] = &[0xFF];
+
+pub const LOCAL_PREFIX: u8 = 0x01;
+pub const LOCAL_MIN_KEY: &[u8] = &[LOCAL_PREFIX];
+pub const LOCAL_MAX_KEY: &[u8] = &[LOCAL_PREFIX + 1];
+
+pub const DATA_PREFIX: u8 = b'z';
+pub const DATA_PREFIX_CAUSET_KEY: &[u8] = &[DATA_PREFIX];
+pub const DATA_MIN_KEY: &[u8] = &[DATA_PREFIX];
+pub const DATA_MAX_KEY: &[u8] = &[DATA_PREFIX + 1];
+
+pub const STORE_IDENT_KEY: &[u8] = &[LOCAL_PREFIX, 0x01];
+pub const PREPARE_BOOTSTRAP_KEY: &[u8] = &[LOCAL_PREFIX, 0x02];
+
+pub const REGION_VIOLETABFT_PREFIX: u8 = 0x02;
+pub const REGION_VIOLETABFT_PREFIX_KEY: &[u8] = &[LOCAL_PREFIX, REGION_VIOLETABFT_PREFIX];
+pub const REGION_META_PREFIX: u8 = 0x03;
+pub const REGION_META_PREFIX_KEY: &[u8] = &[LOCAL_PREFIX, REGION_META_PREFIX];
+pub const REGION_META_MIN_KEY: &[u8] = &[LOCAL_PREFIX, REGION_META_PREFIX];
+pub const REGION_META_MAX_KEY: &[u8] = &[LOCAL_PREFIX, REGION_META_PREFIX + 1];
+
+pub const VIOLETABFT_LOG_SUFFIX: u8 = 0x01;
+pub const VIOLETABFT_STATE_SUFFIX: u8 = 0x02;
+pub const APPLY_STATE_SUFFIX: u8 = 0x03;
+pub const REGION_STATE_SUFFIX: u8 = 0x01;
+
+/// The length of the prefix shared by the VioletaBFT keys of a region
+pub const fn region_violetabft_prefix_len() -> usize {
+    REGION_VIOLETABFT_PREFIX_KEY.len() + 8
+}
+
+const VIOLETABFT_LOG_KEY_LEN: usize = region_violetabft_prefix_len() + 1 + 8;
+const REGION_SUFFIX_KEY_LEN: usize = REGION_META_PREFIX_KEY.len() + 8 + 1;
+
+fn make_region_key(prefix: &[u8], region_id: u64, suffix: u8, extra_cap: usize) -> Vec<u8> {
+    let mut key = Vec::with_capacity(prefix.len() + 8 + 1 + extra_cap);
+    key.extend_from_slice(prefix);
+    key.extend_from_slice(&region_id.to_be_bytes());
+    key.push(suffix);
+    key
+}
+
+pub fn region_violetabft_prefix(region_id: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(region_violetabft_prefix_len());
+    key.extend_from_slice(REGION_VIOLETABFT_PREFIX_KEY);
+    key.extend_from_slice(&region_id.to_be_bytes());
+    key
+}
+
+pub fn violetabft_log_key(region_id: u64, index: u64) -> Vec<u8> {
+    let mut key = make_region_key(REGION_VIOLETABFT_PREFIX_KEY, region_id, VIOLETABFT_LOG_SUFFIX, 8);
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+/// The prefix of every log entry key of a region
+pub fn violetabft_log_prefix(region_id: u64) -> Vec<u8> {
+    make_region_key(REGION_VIOLETABFT_PREFIX_KEY, region_id, VIOLETABFT_LOG_SUFFIX, 0)
+}
+
+pub fn violetabft_state_key(region_id: u64) -> Vec<u8> {
+    make_region_key(REGION_VIOLETABFT_PREFIX_KEY, region_id, VIOLETABFT_STATE_SUFFIX, 0)
+}
+
+pub fn apply_state_key(region_id: u64) -> Vec<u8> {
+    make_region_key(REGION_VIOLETABFT_PREFIX_KEY, region_id, APPLY_STATE_SUFFIX, 0)
+}
+
+pub fn region_state_key(region_id: u64) -> Vec<u8> {
+    make_region_key(REGION_META_PREFIX_KEY, region_id, REGION_STATE_SUFFIX, 0)
+}
+
+/// The index of a log entry key
+pub fn violetabft_log_index(key: &[u8]) -> Result<u64> {
+    match Key::parse(key)? {
+        Key::VioletaBFTLog { index, .. } => Ok(index),
+        _ => Err(invalid_key("violetabft log", key)),
+    }
+}
+
+pub fn data_key(key: &[u8]) -> Vec<u8> {
+    let mut v = Vec::with_capacity(DATA_PREFIX_CAUSET_KEY.len() + key.len());
+    v.extend_from_slice(DATA_PREFIX_CAUSET_KEY);
+    v.extend_from_slice(key);
+    v
+}
+
+/// The data key of `key` as the exclusive end of a range, where an empty
+/// `key` is unbounded and so ends past every data key
+pub fn data_end_key(key: &[u8]) -> Vec<u8> {
+    if key.is_empty() {
+        DATA_MAX_KEY.to_vec()
+    } else {
+        data_key(key)
+    }
+}
+
+pub fn validate_data_key(key: &[u8]) -> bool {
+    key.starts_with(DATA_PREFIX_CAUSET_KEY)
+}
+
+/// The key a client wrote, given its data key
+pub fn origin_key(key: &[u8]) -> &[u8] {
+    assert!(validate_data_key(key), "invalid data key {:?}", key);
+    &key[DATA_PREFIX_CAUSET_KEY.len()..]
+}
+
+/// The data key `region` starts at
+pub fn enc_start_key(region: &Region) -> Vec<u8> {
+    data_key(region.get_start_key())
+}
+
+/// The data key `region` ends before
+pub fn enc_end_key(region: &Region) -> Vec<u8> {
+    data_end_key(region.get_end_key())
+}
+
+/// A key, taken apart
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key<'a> {
+    /// A data key, holding the key a client wrote
+    Data(&'a [u8]),
+    StoreIdent,
+    PrepareBootstrap,
+    VioletaBFTLog { region_id: u64, index: u64 },
+    VioletaBFTState(u64),
+    ApplyState(u64),
+    RegionState(u64),
+}
+
+fn invalid_key(kind: &str, key: &[u8]) -> Error {
+    Error::Other(format!("invalid {} key {:?}", kind, key).into())
+}
+
+impl<'a> Key<'a> {
+    pub fn parse(key: &'a [u8]) -> Result<Key<'a>> {
+        if validate_data_key(key) {
+            return Ok(Key::Data(origin_key(key)));
+        }
+        if key == STORE_IDENT_KEY {
+            return Ok(Key::StoreIdent);
+        }
+        if key == PREPARE_BOOTSTRAP_KEY {
+            return Ok(Key::PrepareBootstrap);
+        }
+
+        let (prefix, rest) = (&key[..key.len().min(2)], &key[key.len().min(2)..]);
+        if rest.len() < 9 {
+            return Err(invalid_key("local", key));
+        }
+        let region_id = u64::from_be_bytes(rest[..8].try_into().unwrap());
+        let suffix = rest[8];
+        match (prefix, suffix) {
+            (REGION_VIOLETABFT_PREFIX_KEY, VIOLETABFT_LOG_SUFFIX) if key.len() == VIOLETABFT_LOG_KEY_LEN => {
+                let index = u64::from_be_bytes(rest[9..].try_into().unwrap());
+                Ok(Key::VioletaBFTLog { region_id, index })
+            }
+            (REGION_VIOLETABFT_PREFIX_KEY, VIOLETABFT_STATE_SUFFIX) if key.len() == REGION_SUFFIX_KEY_LEN => {
+                Ok(Key::VioletaBFTState(region_id))
+            }
+            (REGION_VIOLETABFT_PREFIX_KEY, APPLY_STATE_SUFFIX) if key.len() == REGION_SUFFIX_KEY_LEN => {
+                Ok(Key::ApplyState(region_id))
+            }
+            (REGION_META_PREFIX_KEY, REGION_STATE_SUFFIX) if key.len() == REGION_SUFFIX_KEY_LEN => {
+                Ok(Key::RegionState(region_id))
+            }
+            _ => Err(invalid_key("local", key)),
+        }
+    }
+
+    /// The region a VioletaBFT or meta key belongs to
+    pub fn region_id(&self) -> Option<u64> {
+        match *self {
+            Key::VioletaBFTLog { region_id, .. }
+            | Key::VioletaBFTState(region_id)
+            | Key::ApplyState(region_id)
+            | Key::RegionState(region_id) => Some(region_id),
+            Key::Data(_) | Key::StoreIdent | Key::PrepareBootstrap => None,
+        }
+    }
+
+    /// Build the key back
+    pub fn encode(&self) -> Vec<u8> {
+        match *self {
+            Key::Data(key) => data_key(key),
+            Key::StoreIdent => STORE_IDENT_KEY.to_vec(),
+            Key::PrepareBootstrap => PREPARE_BOOTSTRAP_KEY.to_vec(),
+            Key::VioletaBFTLog { region_id, index } => violetabft_log_key(region_id, index),
+            Key::VioletaBFTState(region_id) => violetabft_state_key(region_id),
+            Key::ApplyState(region_id) => apply_state_key(region_id),
+            Key::RegionState(region_id) => region_state_key(region_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        let keys = vec![
+            Key::Data(b"k"),
+            Key::Data(b""),
+            Key::StoreIdent,
+            Key::PrepareBootstrap,
+            Key::VioletaBFTLog { region_id: 2, index: 300 },
+            Key::VioletaBFTState(2),
+            Key::ApplyState(2),
+            Key::RegionState(u64::MAX),
+        ];
+        for key in &keys {
+            assert_eq!(Key::parse(&key.encode()).unwrap(), *key);
+        }
+
+        // Local keys sort before data keys, and log keys by index.
+        assert!(violetabft_log_key(1, u64::MAX) < violetabft_state_key(1));
+        assert!(violetabft_log_key(1, 255) < violetabft_log_key(1, 256));
+        assert!(violetabft_log_key(1, 0) > violetabft_log_key(0, u64::MAX));
+        assert!(region_state_key(u64::MAX).as_slice() < DATA_MIN_KEY);
+        assert!(violetabft_log_key(3, 7).starts_with(&violetabft_log_prefix(3)));
+        assert!(violetabft_log_key(3, 7).starts_with(&region_violetabft_prefix(3)));
+        assert_eq!(region_violetabft_prefix(3).len(), region_violetabft_prefix_len());
+        assert_eq!(violetabft_log_index(&violetabft_log_key(3, 7)).unwrap(), 7);
+
+        assert!(violetabft_log_index(&violetabft_state_key(3)).is_err());
+        assert!(Key::parse(&violetabft_log_prefix(3)).is_err());
+        assert!(Key::parse(&[LOCAL_PREFIX, 0x09]).is_err());
+        assert!(Key::parse(MIN_KEY).is_err());
+
+        let mut region = Region::default();
+        region.set_start_key(b"a".to_vec());
+        assert_eq!(enc_start_key(&region), b"za");
+        assert_eq!(enc_end_key(&region), DATA_MAX_KEY);
+        assert_eq!(origin_key(&enc_start_key(&region)), b"a");
+        assert_eq!(Key::RegionState(4).region_id(), Some(4));
+        assert_eq!(Key::Data(b"k").region_id(), None);
+    }
+}
@@ -62,7 +62,9 @@
 //!
 //! - [`WriteBatch`] - types that can commit multiple key/value pairs in batches.
 //!   A `WriteBatchExt::WriteBtach` commits all pairs in one atomic transaction.
-//!   A `WriteBatchExt::WriteBatchVec` does not (FIXME: is this correct?).
+//!   A `WriteBatchExt::WriteBatchVec` does not: it is written as sub-batches,
+//!   each atomic, that keep the order of writes to the same key. See
+//!   [`PipelinedWriteBatch`].
 //!
 //! The `KV` instance generally acts as a factory for types that implement
 //! other traits in the crate. These factory methods, associated types, and
@@ -246,7 +248,7 @@
 //! - Use `IntoOther` to adapt between error types of dependencies that are not
 //!   themselves interdependent. E.g. violetabft::Error can be created from
 //!   fdb_traits::Error even though neither `violetabft` tor `fdb_traits` know
-//!   about each other.
+//!   about each other. The conversions live in the `conv` module.
 //!
 //! - "Plain old data" types in `einstein_merkle_tree` can be moved directly into
 //!   `fdb_traits` and reexported from `einstein_merkle_tree` to ease the transition.
@@ -269,32 +271,50 @@ mod namespaced_options;
 pub use crate::namespaced_options::*;
 mod compact;
 pub use crate::compact::*;
-mod db_options;
-pub use crate::db_options::*;
+mod einsteindb_options;
+pub use crate::einsteindb_options::*;
 mod db_vector;
 pub use crate::db_vector::*;
 mod einstein_merkle_tree;
 pub use crate::fdb_lsh_tree*;
-mod file;
-pub use crate::file::*;
+mod file_system;
+pub use crate::file_system::*;
+mod env;
+pub use crate::env::*;
+mod io_limiter;
+pub use crate::io_limiter::*;
 mod import;
 pub use import::*;
 mod misc;
 pub use misc::*;
+mod metrics;
+pub use crate::metrics::*;
+mod lock_store;
+pub use crate::lock_store::*;
 mod lightlike_persistence;
 pub use crate::lightlike_persistence::*;
 mod Causet;
 pub use crate::Causet::*;
 mod write_batch;
 pub use crate::write_batch::*;
+mod write_batch_vec;
+pub use crate::write_batch_vec::*;
+mod bounded_write_batch;
+pub use crate::bounded_write_batch::*;
+mod coalescing_writer;
+pub use crate::coalescing_writer::*;
+#[cfg(test)]
+mod mock_write_batch;
+mod lifecycle;
+pub use crate::lifecycle::*;
 mod encryption;
 pub use crate::encryption::*;
-mod mvcc_greedoids;
-mod Causet_partitioner;
-pub use crate::Causet_partitioner::*;
-mod range_greedoids;
-pub use crate::mvcc_greedoids::*;
-pub use crate::range_greedoids::*;
+mod mvcc_properties;
+mod causet_partitioner;
+pub use crate::causet_partitioner::*;
+mod range_properties;
+pub use crate::mvcc_properties::*;
+pub use crate::range_properties::*;
 mod ttl_greedoids;
 pub use crate::ttl_greedoids::*;
 mod perf_context;
@@ -309,6 +329,8 @@ pub use crate::table_greedoids::*;
 
 mod iterable;
 pub use crate::iterable::*;
+mod iter_pool;
+pub use crate::iter_pool::*;
 mod mutable;
 pub use crate::mutable::*;
 mod peekable;
@@ -323,19 +345,28 @@ mod einstein_merkle_trees;
 pub use einstein_merkle_trees::*;
 mod errors;
 pub use crate::errors::*;
+mod conv;
+pub use crate::conv::*;
 mod options;
 pub use crate::options::*;
 pub mod range;
 pub use crate::range::*;
-mod violetabft_einstein_merkle_tree;
-pub use violetabft_einstein_merkle_tree::{CacheStats, VioletaBFTeinstein_merkle_tree, VioletaBFTeinstein_merkle_treeReadOnly, VioletaBFTLogBatch, VioletaBFTLogGCTask};
+mod violetabft_engine;
+pub use crate::violetabft_engine::{CacheStats, VioletaBFTeinstein_merkle_tree, VioletaBFTeinstein_merkle_treeReadOnly, VioletaBFTLogBatch, VioletaBFTLogGCTask};
+mod violetabft_sync;
+pub use crate::violetabft_sync::{VioletaBFTSyncOptions, VioletaBFTSyncProgress};
+mod violetabft_purge;
+pub use crate::violetabft_purge::{total_log_size, VioletaBFTPurgeWatermark};
+mod entry_cache;
+pub use crate::entry_cache::*;
 
 // These modules need further scrutiny
 
 pub mod jet_bundle_job;
+pub mod keys;
 pub mod primitive_causet_ttl;
 pub mod util;
 pub use jet_bundle_job::*;
 
 // FIXME: This should live somewhere else
-pub const FILE_CAUSET_PREFIX_LEN_FLUSH: usize = 1;
+pub const FILE_CAUSET_PREFIX_LEN_FLUSH: usize = keys::DATA_PREFIX_CAUSET_KEY.len();
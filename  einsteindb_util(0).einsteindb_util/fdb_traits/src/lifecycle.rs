@@ -0,0 +1,284 @@
+// Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! Closing einstein_merkle_trees
+//!
+//! Dropping the last handle to an einstein_merkle_tree closes it, but whatever
+//! is still in the memtables is only recovered from the WAL on the next open,
+//! and nothing else gets a chance to react. `LifecycleExt::close` closes it
+//! explicitly: it runs the pre-close hooks that were registered, in the order
+//! they were registered, then flushes, waits for compactions and syncs the WAL
+//! as `CloseOptions` asks.
+//!
+//! An einstein_merkle_tree implements `LifecycleExt` by owning a `Lifecycle`,
+//! which keeps its `HealthState` and hooks. Close happens once: calls to
+//! `close` during a close wait for it, and calls after it return immediately.
+//! A close that fails leaves the einstein_merkle_tree `Failed`; closing it
+//! again retries the flush, but not the hooks.
+
+use std::fmt;
+use std::mem;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::errors::{Error, Result};
+use crate::misc::MiscExt;
+
+/// How often `close` rechecks for pending compactions.
+const COMPACTION_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug)]
+pub struct CloseOptions {
+    /// Flush the memtables of every namespaced
+    pub flush: bool,
+    /// Wait at most this long for pending compactions to finish
+    pub wait_for_compaction: Option<Duration>,
+    /// Sync the WAL
+    pub sync_wal: bool,
+}
+
+impl Default for CloseOptions {
+    fn default() -> CloseOptions {
+        CloseOptions {
+            flush: true,
+            wait_for_compaction: None,
+            sync_wal: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HealthState {
+    Open,
+    /// `close` is running
+    Closing,
+    Closed,
+    /// A close failed with the given message
+    Failed(String),
+}
+
+pub type PreCloseHook = Box<dyn Fn() -> Result<()> + Send + Sync>;
+
+struct Inner {
+    state: HealthState,
+    hooks: Vec<(String, PreCloseHook)>,
+}
+
+/// The health state and pre-close hooks of an einstein_merkle_tree
+pub struct Lifecycle {
+    inner: Mutex<Inner>,
+    closed: Condvar,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Lifecycle {
+        Lifecycle {
+            inner: Mutex::new(Inner {
+                state: HealthState::Open,
+                hooks: vec![],
+            }),
+            closed: Condvar::new(),
+        }
+    }
+}
+
+impl fmt::Debug for Lifecycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lifecycle").field("state", &self.state()).finish()
+    }
+}
+
+impl Lifecycle {
+    pub fn new() -> Lifecycle {
+        Lifecycle::default()
+    }
+
+    pub fn state(&self) -> HealthState {
+        self.inner.lock().unwrap().state.clone()
+    }
+
+    /// Returns `Error::Closed` unless the einstein_merkle_tree is open, for
+    /// operations to check before they start.
+    pub fn check_open(&self) -> Result<()> {
+        match self.state() {
+            HealthState::Open => Ok(()),
+            state => Err(Error::Closed(state)),
+        }
+    }
+
+    /// Registers a hook to run when the einstein_merkle_tree is closed, e.g.
+    /// to invalidate lightlike_persistences or flush metrics.
+    pub fn register_pre_close_hook(&self, name: &str, hook: PreCloseHook) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state != HealthState::Open {
+            return Err(Error::Closed(inner.state.clone()));
+        }
+        inner.hooks.push((name.to_owned(), hook));
+        Ok(())
+    }
+
+    /// Starts closing, returning the hooks to run, or `None` if another close
+    /// already finished.
+    fn begin_close(&self) -> Option<Vec<(String, PreCloseHook)>> {
+        let mut inner = self.inner.lock().unwrap();
+        while inner.state == HealthState::Closing {
+            inner = self.closed.wait(inner).unwrap();
+        }
+        if inner.state == HealthState::Closed {
+            return None;
+        }
+        inner.state = HealthState::Closing;
+        Some(mem::take(&mut inner.hooks))
+    }
+
+    fn finish_close(&self, res: &Result<()>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state = match res {
+            Ok(()) => HealthState::Closed,
+            Err(e) => HealthState::Failed(e.to_string()),
+        };
+        self.closed.notify_all();
+    }
+
+    /// Runs `close` as the einstein_merkle_tree's one close.
+    pub fn close_with<F>(&self, close: F) -> Result<()>
+    where
+        F: FnOnce(Vec<(String, PreCloseHook)>) -> Result<()>,
+    {
+        let hooks = match self.begin_close() {
+            Some(hooks) => hooks,
+            None => return Ok(()),
+        };
+        let res = close(hooks);
+        self.finish_close(&res);
+        res
+    }
+}
+
+pub trait LifecycleExt: MiscExt {
+    fn lifecycle(&self) -> &Lifecycle;
+
+    fn health_state(&self) -> HealthState {
+        self.lifecycle().state()
+    }
+
+    fn register_pre_close_hook<F>(&self, name: &str, hook: F) -> Result<()>
+    where
+        F: Fn() -> Result<()> + Send + Sync + 'static,
+    {
+        self.lifecycle().register_pre_close_hook(name, Box::new(hook))
+    }
+
+    /// Closes the einstein_merkle_tree. Stops at the first hook or step that
+    /// fails.
+    fn close(&self, opts: &CloseOptions) -> Result<()> {
+        self.lifecycle().close_with(|hooks| {
+            for (name, hook) in hooks {
+                hook().map_err(|e| e.context(format!("pre-close hook {}", name)))?;
+            }
+            if opts.flush {
+                self.flush(true)?;
+            }
+            if let Some(timeout) = opts.wait_for_compaction {
+                self.wait_for_compaction(timeout)?;
+            }
+            if opts.sync_wal {
+                self.sync_wal()?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Blocks until no namespaced has bytes pending compaction, for at most
+    /// `timeout`.
+    fn wait_for_compaction(&self, timeout: Duration) -> Result<()> {
+        let start = Instant::now();
+        loop {
+            let mut pending = 0;
+            for namespaced in self.namespaced_names() {
+                pending += self
+                    .get_namespaced_pending_jet_bundle_bytes(namespaced)?
+                    .unwrap_or(0);
+            }
+            if pending == 0 {
+                return Ok(());
+            }
+            let waited = start.elapsed();
+            if waited >= timeout {
+                return Err(Error::Other(
+                    format!("{} bytes still pending compaction after {:?}", pending, waited).into(),
+                ));
+            }
+            thread::sleep(std::cmp::min(COMPACTION_POLL_INTERVAL, timeout - waited));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_lifecycle_close() {
+        let lifecycle = Arc::new(Lifecycle::new());
+        let order = Arc::new(Mutex::new(vec![]));
+        for name in &["lightlike_persistences", "metrics"] {
+            let order = order.clone();
+            lifecycle
+                .register_pre_close_hook(
+                    name,
+                    Box::new(move || {
+                        order.lock().unwrap().push(name.to_string());
+                        Ok(())
+                    }),
+                )
+                .unwrap();
+        }
+        assert_eq!(lifecycle.state(), HealthState::Open);
+        assert!(lifecycle.check_open().is_ok());
+
+        let closes = Arc::new(AtomicUsize::new(0));
+        let close = |lifecycle: &Lifecycle, closes: &AtomicUsize| {
+            lifecycle.close_with(|hooks| {
+                for (_, hook) in hooks {
+                    hook()?;
+                }
+                thread::sleep(Duration::from_millis(20));
+                closes.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+        };
+        let handle = {
+            let (lifecycle, closes) = (lifecycle.clone(), closes.clone());
+            thread::spawn(move || close(&lifecycle, &closes))
+        };
+        close(&lifecycle, &closes).unwrap();
+        handle.join().unwrap().unwrap();
+
+        // Two calls, one close, each hook once and in order.
+        assert_eq!(closes.load(Ordering::SeqCst), 1);
+        assert_eq!(*order.lock().unwrap(), vec!["lightlike_persistences", "metrics"]);
+        assert_eq!(lifecycle.state(), HealthState::Closed);
+        assert!(matches!(lifecycle.check_open(), Err(Error::Closed(HealthState::Closed))));
+        assert!(lifecycle.register_pre_close_hook("late", Box::new(|| Ok(()))).is_err());
+    }
+
+    #[test]
+    fn test_lifecycle_failed_close() {
+        let lifecycle = Lifecycle::new();
+        let res = lifecycle.close_with(|_| Err(Error::Other("flush failed".into())));
+        assert!(res.is_err());
+        assert!(matches!(lifecycle.state(), HealthState::Failed(_)));
+
+        // A failed close can be retried.
+        lifecycle
+            .close_with(|hooks| {
+                assert!(hooks.is_empty());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(lifecycle.state(), HealthState::Closed);
+    }
+}
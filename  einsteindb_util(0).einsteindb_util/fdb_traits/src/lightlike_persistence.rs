@@ -1,9 +1,13 @@
 // Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
+use crate::errors::Result;
 use crate::iterable::Iterable;
 use crate::peekable::Peekable;
 use std::fmt::Debug;
 
+/// Receives the lightlike_persistence requested by `KV::async_lightlike_persistence`
+pub type LightlikePersistenceCallback<S> = Box<dyn FnOnce(Result<S>) + Send + 'static>;
+
 /// A consistent read-only view of the database.
 ///
 /// LightlikePersistences can be sent and shared, but not cloned. To make a lightlike_persistence
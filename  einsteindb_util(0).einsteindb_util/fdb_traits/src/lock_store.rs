@@ -0,0 +1,174 @@
+// Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! Typed access to pessimistic and percolator locks
+//!
+//! By default locks live in the `NAMESPACED_LOCK` column family, keyed by
+//! their encoded user key. Engines that keep locks off the main LSM can
+//! override every method of `LockStoreExt` and delegate to a
+//! `MemoryLockTable` instead.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::{Arc, RwLock};
+
+use txn_types::{Key, Lock};
+
+use crate::errors::{Error, Result};
+use crate::iterable::Iterable;
+use crate::mutable::SyncMutable;
+use crate::namespaced_defs::NAMESPACED_LOCK;
+use crate::peekable::Peekable;
+use crate::range::Range;
+
+fn parse_lock(value: &[u8]) -> Result<Lock> {
+    Lock::parse(value).map_err(|e| Error::Other(Box::new(e)))
+}
+
+pub trait LockStoreExt: Peekable + SyncMutable + Iterable {
+    fn put_lock(&self, key: &Key, lock: &Lock) -> Result<()> {
+        self.put_namespaced(NAMESPACED_LOCK, key.as_encoded(), &lock.to_bytes())
+    }
+
+    fn get_lock(&self, key: &Key) -> Result<Option<Lock>> {
+        match self.get_value_namespaced(NAMESPACED_LOCK, key.as_encoded())? {
+            Some(v) => Ok(Some(parse_lock(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn delete_lock(&self, key: &Key) -> Result<()> {
+        self.delete_namespaced(NAMESPACED_LOCK, key.as_encoded())
+    }
+
+    /// Scans the locks whose encoded keys fall in `range`, in key order.
+    ///
+    /// Only locks accepted by `filter` are returned, and at most `limit` of
+    /// them. A `limit` of 0 means no limit.
+    fn scan_locks<F>(&self, range: Range<'_>, filter: F, limit: usize) -> Result<Vec<(Key, Lock)>>
+    where
+        F: Fn(&Key, &Lock) -> bool,
+    {
+        let mut locks = vec![];
+        let mut err = None;
+        self.scan_namespaced(NAMESPACED_LOCK, range.start_key, range.end_key, false, |k, v| {
+            let lock = match parse_lock(v) {
+                Ok(lock) => lock,
+                Err(e) => {
+                    err = Some(e);
+                    return Ok(false);
+                }
+            };
+            let key = Key::from_encoded_slice(k);
+            if filter(&key, &lock) {
+                locks.push((key, lock));
+            }
+            Ok(limit == 0 || locks.len() < limit)
+        })?;
+        match err {
+            Some(e) => Err(e),
+            None => Ok(locks),
+        }
+    }
+}
+
+/// A lock table kept entirely in memory.
+///
+/// Cloning the table gives another handle to the same locks.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryLockTable {
+    locks: Arc<RwLock<BTreeMap<Vec<u8>, Lock>>>,
+}
+
+impl MemoryLockTable {
+    pub fn new() -> MemoryLockTable {
+        MemoryLockTable::default()
+    }
+
+    pub fn put_lock(&self, key: &Key, lock: &Lock) {
+        self.locks
+            .write()
+            .unwrap()
+            .insert(key.as_encoded().clone(), lock.clone());
+    }
+
+    pub fn get_lock(&self, key: &Key) -> Option<Lock> {
+        self.locks.read().unwrap().get(key.as_encoded()).cloned()
+    }
+
+    pub fn delete_lock(&self, key: &Key) -> Option<Lock> {
+        self.locks.write().unwrap().remove(key.as_encoded())
+    }
+
+    /// Same as `LockStoreExt::scan_locks`.
+    pub fn scan_locks<F>(&self, range: Range<'_>, filter: F, limit: usize) -> Vec<(Key, Lock)>
+    where
+        F: Fn(&Key, &Lock) -> bool,
+    {
+        let end = if range.end_key.is_empty() {
+            Bound::Unbounded
+        } else {
+            Bound::Excluded(range.end_key)
+        };
+        let locks = self.locks.read().unwrap();
+        let iter = locks
+            .range::<[u8], _>((Bound::Included(range.start_key), end))
+            .map(|(k, lock)| (Key::from_encoded_slice(k), lock))
+            .filter(|(key, lock)| filter(key, lock))
+            .map(|(key, lock)| (key, lock.clone()));
+        if limit == 0 {
+            iter.collect()
+        } else {
+            iter.take(limit).collect()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.locks.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locks.read().unwrap().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use txn_types::{LockType, TimeStamp};
+
+    fn lock(ts: u64) -> Lock {
+        Lock::new(
+            LockType::Pessimistic,
+            b"pk".to_vec(),
+            ts.into(),
+            3000,
+            None,
+            ts.into(),
+            0,
+            TimeStamp::zero(),
+        )
+    }
+
+    #[test]
+    fn test_memory_lock_table() {
+        let table = MemoryLockTable::new();
+        for (k, ts) in &[(b"a", 10), (b"b", 20), (b"c", 30), (b"d", 40)] {
+            table.put_lock(&Key::from_raw(*k), &lock(*ts));
+        }
+        assert_eq!(table.len(), 4);
+        assert_eq!(table.get_lock(&Key::from_raw(b"b")), Some(lock(20)));
+
+        let start = Key::from_raw(b"b");
+        let end = Key::from_raw(b"d");
+        let range = Range::new(start.as_encoded(), end.as_encoded());
+        let locks = table.scan_locks(range, |_, _| true, 0);
+        assert_eq!(locks, vec![(Key::from_raw(b"b"), lock(20)), (Key::from_raw(b"c"), lock(30))]);
+
+        let range = Range::new(start.as_encoded(), b"");
+        let locks = table.scan_locks(range, |_, l| l.ts >= 30.into(), 1);
+        assert_eq!(locks, vec![(Key::from_raw(b"c"), lock(30))]);
+
+        assert_eq!(table.delete_lock(&Key::from_raw(b"a")), Some(lock(10)));
+        assert_eq!(table.get_lock(&Key::from_raw(b"a")), None);
+    }
+}
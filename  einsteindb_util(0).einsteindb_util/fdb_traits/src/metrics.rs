@@ -0,0 +1,274 @@
+// Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! An engine-neutral snapshot of storage metrics
+//!
+//! `KV::flush_metrics` writes straight into the process-global prometheus
+//! registry. `MetricsExt` instead hands a plain `EngineMetrics` value to a
+//! caller-supplied `MetricsCollector`, so that metrics can be inspected,
+//! shipped elsewhere, or rendered with `render_prometheus`.
+
+use std::fmt::Write;
+
+use crate::errors::Result;
+
+/// Memtable statistics of a single column family
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemtableStats {
+    /// Approximate size of the active and unflushed immutable memtables
+    pub size_bytes: u64,
+    /// Number of immutable memtables not yet flushed
+    pub num_immutable: u64,
+}
+
+/// Metrics of a single column family
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NamespacedMetrics {
+    pub namespaced: String,
+    /// Size on disk, including blob files if any
+    pub size_bytes: u64,
+    /// Number of files at each level, starting from level 0
+    pub num_files_at_level: Vec<u64>,
+    /// Block cache usage of this column family, or `None` if the block cache
+    /// is shared and only reported engine-wide
+    pub block_cache_usage: Option<u64>,
+    pub memtable: MemtableStats,
+}
+
+/// Block cache statistics of the whole engine
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockCacheStats {
+    pub capacity: u64,
+    pub usage: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EngineMetrics {
+    pub namespaceds: Vec<NamespacedMetrics>,
+    /// Engine-wide block cache statistics, if the engine has a block cache
+    pub block_cache: Option<BlockCacheStats>,
+}
+
+/// Receives metrics snapshots from `MetricsExt::flush_metrics`
+pub trait MetricsCollector {
+    fn collect(&mut self, metrics: EngineMetrics);
+}
+
+impl MetricsCollector for Vec<EngineMetrics> {
+    fn collect(&mut self, metrics: EngineMetrics) {
+        self.push(metrics);
+    }
+}
+
+/// Renders every collected snapshot in the Prometheus text exposition format,
+/// labelled with `instance`
+pub struct PrometheusTextCollector {
+    instance: String,
+    text: String,
+}
+
+impl PrometheusTextCollector {
+    pub fn new(instance: &str) -> PrometheusTextCollector {
+        PrometheusTextCollector {
+            instance: instance.to_owned(),
+            text: String::new(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn into_text(self) -> String {
+        self.text
+    }
+}
+
+impl MetricsCollector for PrometheusTextCollector {
+    fn collect(&mut self, metrics: EngineMetrics) {
+        self.text
+            .push_str(&render_prometheus(&metrics, &self.instance));
+    }
+}
+
+pub trait MetricsExt {
+    /// Take a snapshot of the current engine metrics
+    fn metrics_snapshot(&self) -> Result<EngineMetrics>;
+
+    /// Hand a snapshot of the current engine metrics to `collector`
+    ///
+    /// Unlike `KV::flush_metrics` this doesn't touch any global registry.
+    fn flush_metrics(&self, collector: &mut dyn MetricsCollector) -> Result<()> {
+        collector.collect(self.metrics_snapshot()?);
+        Ok(())
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn write_header(out: &mut String, name: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} gauge", name).unwrap();
+}
+
+fn write_sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: u64) {
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape_label_value(v)))
+        .collect();
+    writeln!(out, "{}{{{}}} {}", name, labels.join(","), value).unwrap();
+}
+
+/// Render `metrics` in the Prometheus text exposition format
+pub fn render_prometheus(metrics: &EngineMetrics, instance: &str) -> String {
+    let mut out = String::new();
+
+    write_header(
+        &mut out,
+        "einsteindb_engine_size_bytes",
+        "Sizes of each column family",
+    );
+    for m in &metrics.namespaceds {
+        write_sample(
+            &mut out,
+            "einsteindb_engine_size_bytes",
+            &[("db", instance), ("namespaced", &m.namespaced)],
+            m.size_bytes,
+        );
+    }
+
+    write_header(
+        &mut out,
+        "einsteindb_engine_num_files_at_level",
+        "Number of files at each level",
+    );
+    for m in &metrics.namespaceds {
+        for (level, n) in m.num_files_at_level.iter().enumerate() {
+            write_sample(
+                &mut out,
+                "einsteindb_engine_num_files_at_level",
+                &[
+                    ("db", instance),
+                    ("namespaced", &m.namespaced),
+                    ("level", &level.to_string()),
+                ],
+                *n,
+            );
+        }
+    }
+
+    write_header(
+        &mut out,
+        "einsteindb_engine_memtable_size_bytes",
+        "Size of active and unflushed immutable memtables",
+    );
+    for m in &metrics.namespaceds {
+        write_sample(
+            &mut out,
+            "einsteindb_engine_memtable_size_bytes",
+            &[("db", instance), ("namespaced", &m.namespaced)],
+            m.memtable.size_bytes,
+        );
+    }
+
+    write_header(
+        &mut out,
+        "einsteindb_engine_num_immutable_memtables",
+        "Number of immutable memtables not yet flushed",
+    );
+    for m in &metrics.namespaceds {
+        write_sample(
+            &mut out,
+            "einsteindb_engine_num_immutable_memtables",
+            &[("db", instance), ("namespaced", &m.namespaced)],
+            m.memtable.num_immutable,
+        );
+    }
+
+    write_header(
+        &mut out,
+        "einsteindb_engine_block_cache_usage_bytes",
+        "Block cache usage",
+    );
+    for m in &metrics.namespaceds {
+        if let Some(usage) = m.block_cache_usage {
+            write_sample(
+                &mut out,
+                "einsteindb_engine_block_cache_usage_bytes",
+                &[("db", instance), ("namespaced", &m.namespaced)],
+                usage,
+            );
+        }
+    }
+    if let Some(ref cache) = metrics.block_cache {
+        write_sample(
+            &mut out,
+            "einsteindb_engine_block_cache_usage_bytes",
+            &[("db", instance), ("namespaced", "all")],
+            cache.usage,
+        );
+        write_header(
+            &mut out,
+            "einsteindb_engine_block_cache_capacity_bytes",
+            "Block cache capacity",
+        );
+        write_sample(
+            &mut out,
+            "einsteindb_engine_block_cache_capacity_bytes",
+            &[("db", instance)],
+            cache.capacity,
+        );
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_prometheus() {
+        let metrics = EngineMetrics {
+            namespaceds: vec![NamespacedMetrics {
+                namespaced: "default".to_owned(),
+                size_bytes: 1024,
+                num_files_at_level: vec![3, 0],
+                block_cache_usage: None,
+                memtable: MemtableStats {
+                    size_bytes: 64,
+                    num_immutable: 1,
+                },
+            }],
+            block_cache: Some(BlockCacheStats {
+                capacity: 4096,
+                usage: 128,
+            }),
+        };
+
+        let mut collector = PrometheusTextCollector::new("k\"v");
+        collector.collect(metrics);
+        let text = collector.into_text();
+        assert!(text.contains("# TYPE einsteindb_engine_size_bytes gauge\n"));
+        assert!(text.contains("einsteindb_engine_size_bytes{db=\"k\\\"v\",namespaced=\"default\"} 1024\n"));
+        assert!(text.contains(
+            "einsteindb_engine_num_files_at_level{db=\"k\\\"v\",namespaced=\"default\",level=\"0\"} 3\n"
+        ));
+        assert!(text.contains(
+            "einsteindb_engine_num_files_at_level{db=\"k\\\"v\",namespaced=\"default\",level=\"1\"} 0\n"
+        ));
+        assert!(text.contains("einsteindb_engine_num_immutable_memtables{db=\"k\\\"v\",namespaced=\"default\"} 1\n"));
+        assert!(text.contains("einsteindb_engine_block_cache_usage_bytes{db=\"k\\\"v\",namespaced=\"all\"} 128\n"));
+        assert!(text.contains("einsteindb_engine_block_cache_capacity_bytes{db=\"k\\\"v\"} 4096\n"));
+    }
+}
@@ -5,10 +5,15 @@
 //!
 //! FIXME: Things here need to be moved elsewhere.
 
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
 use crate::namespaced_names::NAMESPACEDNamesExt;
-use crate::errors::Result;
+use crate::errors::{Error, Result};
+use crate::options::WriteOptions;
 use crate::symplectic_control_factors::SymplecticControlFactorsExt;
-use crate::range::Range;
+use crate::range::{Range, RangeSet};
 
 #[derive(Clone, Debug)]
 pub enum DeleteStrategy {
@@ -26,14 +31,68 @@ pub enum DeleteStrategy {
     DeleteByWriter { Causet_local_path: String },
 }
 
+/// How often `MiscExt::wait_for_write_stall` rechecks whether writes are still stalled.
+const STALL_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A reason the einstein_merkle_tree is slowing down or stopping writes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StallReason {
+    /// Too many Causet filefs in level 0 of the namespaced.
+    TooManyL0Files {
+        namespaced: String,
+        num_files: u64,
+        limit: u64,
+    },
+    /// Too many bytes waiting to be compacted in the namespaced.
+    PendingCompactionBytes {
+        namespaced: String,
+        pending_bytes: u64,
+        limit: u64,
+    },
+    /// All memtables of the namespaced are full and waiting to be flushed.
+    MemtableLimit {
+        namespaced: String,
+        num_memtables: u64,
+        limit: u64,
+    },
+}
+
+impl fmt::Display for StallReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StallReason::TooManyL0Files { namespaced, num_files, limit } => write!(
+                f,
+                "[{}] {} level 0 filefs, limit {}",
+                namespaced, num_files, limit
+            ),
+            StallReason::PendingCompactionBytes { namespaced, pending_bytes, limit } => write!(
+                f,
+                "[{}] {} pending jet_bundle bytes, limit {}",
+                namespaced, pending_bytes, limit
+            ),
+            StallReason::MemtableLimit { namespaced, num_memtables, limit } => write!(
+                f,
+                "[{}] {} memtables, limit {}",
+                namespaced, num_memtables, limit
+            ),
+        }
+    }
+}
+
 pub trait MiscExt: NAMESPACEDNamesExt + SymplecticControlFactorsExt {
     fn flush(&self, sync: bool) -> Result<()>;
 
     fn flush_namespaced(&self, namespaced: &str, sync: bool) -> Result<()>;
 
+    /// Deletes the given ranges in every namespaced.
+    ///
+    /// Overlapping and adjacent ranges are coalesced first, so each key is
+    /// only covered by a single range deletion.
     fn delete_all_in_range(&self, strategy: DeleteStrategy, ranges: &[Range<'_>]) -> Result<()> {
+        let range_set = RangeSet::from_ranges(ranges);
+        let ranges: Vec<Range<'_>> = range_set.iter().collect();
         for namespaced in self.namespaced_names() {
-            self.delete_ranges_namespaced(namespaced, strategy.clone(), ranges)?;
+            self.delete_ranges_namespaced(namespaced, strategy.clone(), &ranges)?;
         }
         Ok(())
     }
@@ -95,4 +154,34 @@ pub trait MiscExt: NAMESPACEDNamesExt + SymplecticControlFactorsExt {
     ) -> Result<Option<(u64, u64)>>;
 
     fn is_stalled_or_stopped(&self) -> bool;
+
+    /// Returns the reasons writes are currently being slowed down or stopped.
+    ///
+    /// An empty result means no namespaced has hit a stall condition.
+    fn stall_reasons(&self) -> Result<Vec<StallReason>>;
+
+    /// Blocks while writes are stalled or stopped, for at most the stall
+    /// timeout of `opts`.
+    ///
+    /// Returns `Error::WriteStalled` with the current stall reasons if the
+    /// stall outlasts the timeout. Returns immediately if `opts` has no stall
+    /// timeout.
+    fn wait_for_write_stall(&self, opts: &WriteOptions) -> Result<()> {
+        let timeout = match opts.stall_timeout() {
+            Some(timeout) => timeout,
+            None => return Ok(()),
+        };
+        let start = Instant::now();
+        while self.is_stalled_or_stopped() {
+            let waited = start.elapsed();
+            if waited >= timeout {
+                return Err(Error::WriteStalled {
+                    waited,
+                    reasons: self.stall_reasons()?,
+                });
+            }
+            thread::sleep(std::cmp::min(STALL_POLL_INTERVAL, timeout - waited));
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,209 @@
+// Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! A write batch engine for the tests of write batch wrappers
+//!
+//! `MockEngine` records each batch written as the list of its commands,
+//! rendered as strings like `put default a 1`, and fails writes while
+//! `fail_writes` is set. Batches report their commands through `iterate`, so
+//! they also round-trip through `to_repr` and `from_repr`.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::errors::{Error, Result};
+use crate::options::WriteOptions;
+use crate::write_batch::{Mutable, WriteBatch, WriteBatchExt, WriteBatchOp};
+
+pub(crate) type Log = Arc<Mutex<Vec<Vec<String>>>>;
+
+#[derive(Clone, Default)]
+pub(crate) struct MockEngine {
+    /// The commands of each batch written, in order
+    pub(crate) written: Log,
+    /// Fail every write while set
+    pub(crate) fail_writes: Arc<AtomicBool>,
+}
+
+/// A command issued to a `MockBatch`
+enum Cmd {
+    Put(String, Vec<u8>, Vec<u8>),
+    Delete(String, Vec<u8>),
+    DeleteRange(String, Vec<u8>, Vec<u8>),
+}
+
+impl Cmd {
+    fn op(&self) -> WriteBatchOp<'_> {
+        match *self {
+            Cmd::Put(ref namespaced, ref key, ref value) => WriteBatchOp::Put {
+                namespaced,
+                key,
+                value,
+            },
+            Cmd::Delete(ref namespaced, ref key) => WriteBatchOp::Delete { namespaced, key },
+            Cmd::DeleteRange(ref namespaced, ref begin_key, ref end_key) => {
+                WriteBatchOp::DeleteRange {
+                    namespaced,
+                    begin_key,
+                    end_key,
+                }
+            }
+        }
+    }
+
+    fn render(&self) -> String {
+        match *self {
+            Cmd::Put(ref namespaced, ref key, ref value) => format!(
+                "put {} {} {}",
+                namespaced,
+                String::from_utf8_lossy(key),
+                String::from_utf8_lossy(value)
+            ),
+            Cmd::Delete(ref namespaced, ref key) => {
+                format!("delete {} {}", namespaced, String::from_utf8_lossy(key))
+            }
+            Cmd::DeleteRange(ref namespaced, ref begin_key, ref end_key) => format!(
+                "delete_range {} {} {}",
+                namespaced,
+                String::from_utf8_lossy(begin_key),
+                String::from_utf8_lossy(end_key)
+            ),
+        }
+    }
+}
+
+pub(crate) struct MockBatch {
+    written: Log,
+    fail_writes: Arc<AtomicBool>,
+    cmds: Vec<Cmd>,
+    save_points: Vec<usize>,
+}
+
+impl Mutable for MockBatch {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.put_namespaced("default", key, value)
+    }
+
+    fn put_namespaced(&mut self, namespaced: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.cmds
+            .push(Cmd::Put(namespaced.to_owned(), key.to_vec(), value.to_vec()));
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.delete_namespaced("default", key)
+    }
+
+    fn delete_namespaced(&mut self, namespaced: &str, key: &[u8]) -> Result<()> {
+        self.cmds.push(Cmd::Delete(namespaced.to_owned(), key.to_vec()));
+        Ok(())
+    }
+
+    fn delete_range(&mut self, begin_key: &[u8], end_key: &[u8]) -> Result<()> {
+        self.delete_range_namespaced("default", begin_key, end_key)
+    }
+
+    fn delete_range_namespaced(&mut self, namespaced: &str, begin_key: &[u8], end_key: &[u8]) -> Result<()> {
+        self.cmds.push(Cmd::DeleteRange(
+            namespaced.to_owned(),
+            begin_key.to_vec(),
+            end_key.to_vec(),
+        ));
+        Ok(())
+    }
+}
+
+impl WriteBatch<MockEngine> for MockBatch {
+    fn with_capacity(e: &MockEngine, cap: usize) -> MockBatch {
+        MockBatch {
+            written: e.written.clone(),
+            fail_writes: e.fail_writes.clone(),
+            cmds: Vec::with_capacity(cap),
+            save_points: vec![],
+        }
+    }
+
+    fn write_opt(&self, _: &WriteOptions) -> Result<()> {
+        if self.fail_writes.load(Ordering::SeqCst) {
+            return Err(Error::einstein_merkle_tree("injected write failure".to_owned()));
+        }
+        self.written
+            .lock()
+            .unwrap()
+            .push(self.cmds.iter().map(Cmd::render).collect());
+        Ok(())
+    }
+
+    fn data_size(&self) -> usize {
+        self.cmds.iter().map(|c| c.render().len()).sum()
+    }
+
+    fn count(&self) -> usize {
+        self.cmds.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.cmds.is_empty()
+    }
+
+    fn should_write_to_einstein_merkle_tree(&self) -> bool {
+        false
+    }
+
+    fn clear(&mut self) {
+        self.cmds.clear();
+        self.save_points.clear();
+    }
+
+    fn set_save_point(&mut self) {
+        self.save_points.push(self.cmds.len());
+    }
+
+    fn pop_save_point(&mut self) -> Result<()> {
+        self.save_points
+            .pop()
+            .map(|_| ())
+            .ok_or_else(|| Error::einstein_merkle_tree("no save point".to_owned()))
+    }
+
+    fn rollback_to_save_point(&mut self) -> Result<()> {
+        let len = self
+            .save_points
+            .pop()
+            .ok_or_else(|| Error::einstein_merkle_tree("no save point".to_owned()))?;
+        self.cmds.truncate(len);
+        Ok(())
+    }
+
+    fn merge(&mut self, src: MockBatch) {
+        self.cmds.extend(src.cmds);
+    }
+
+    fn iterate<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(WriteBatchOp<'_>) -> Result<()>,
+    {
+        for cmd in &self.cmds {
+            f(cmd.op())?;
+        }
+        Ok(())
+    }
+}
+
+impl WriteBatchExt for MockEngine {
+    type WriteBatch = MockBatch;
+    type WriteBatchVec = MockBatch;
+
+    const WRITE_BATCH_MAX_CAUSET_KEYS: usize = 256;
+
+    fn support_write_batch_vec(&self) -> bool {
+        false
+    }
+
+    fn write_batch(&self) -> MockBatch {
+        MockBatch::with_capacity(self, 0)
+    }
+
+    fn write_batch_with_cap(&self, cap: usize) -> MockBatch {
+        MockBatch::with_capacity(self, cap)
+    }
+}
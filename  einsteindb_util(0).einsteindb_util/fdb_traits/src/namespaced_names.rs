@@ -1,5 +1,176 @@
 // Copyright 2020 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::encryption::EncryptionKeyManager;
+use crate::errors::{Error, Result};
+use crate::namespaced_defs::NAMESPACED_DEFAULT;
+
 pub trait NAMESPACEDNamesExt {
     fn namespaced_names(&self) -> Vec<&str>;
 }
+
+/// A table file of a column family
+#[derive(Clone, Debug, PartialEq)]
+pub struct NamespacedFile {
+    pub local_path: String,
+    pub size: u64,
+}
+
+const SHRED_CHUNK_SIZE: usize = 64 * 1024;
+
+pub trait NAMESPACEDDestroyExt: NAMESPACEDNamesExt {
+    /// The table files of `namespaced`
+    fn namespaced_filefs(&self, namespaced: &str) -> Result<Vec<NamespacedFile>>;
+
+    /// Drop `namespaced` from the einstein_merkle_tree, leaving its files for the
+    /// einstein_merkle_tree to delete whenever it gets to them
+    fn drop_namespaced(&mut self, namespaced: &str) -> Result<()>;
+
+    /// The manager of the keys of the einstein_merkle_tree's files, if they are encrypted
+    fn encryption_key_manager(&self) -> Option<Arc<dyn EncryptionKeyManager>> {
+        None
+    }
+
+    /// Drop `namespaced` and delete its files now, returning the bytes reclaimed
+    ///
+    /// With `shred`, each file is overwritten with zeros and synced before it's
+    /// deleted, unless it's hard-linked elsewhere, as by a checkpoint or a
+    /// backup, which still reads it; then only this link is removed. The
+    /// encryption keys of the files are removed too. The default
+    /// column family is never destroyed. Fails if a file is still there after.
+    fn destroy_namespaced(&mut self, namespaced: &str, shred: bool) -> Result<u64> {
+        if namespaced == NAMESPACED_DEFAULT {
+            return Err(Error::Other(
+                "refusing to destroy the default namespaced".into(),
+            ));
+        }
+        if !self.namespaced_names().contains(&namespaced) {
+            return Err(Error::NAMESPACEDName(namespaced.to_owned()));
+        }
+
+        let filefs = self.namespaced_filefs(namespaced)?;
+        self.drop_namespaced(namespaced)?;
+
+        let key_manager = self.encryption_key_manager();
+        let mut reclaimed = 0;
+        for filef in &filefs {
+            let path = Path::new(&filef.local_path);
+            match fs::metadata(path) {
+                Ok(metadata) => {
+                    if shred && !is_hard_linked(&metadata) {
+                        shred_filef(path, filef.size)?;
+                    }
+                    fs::remove_file(path)?;
+                }
+                // The einstein_merkle_tree got to it first.
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+            if let Some(ref manager) = key_manager {
+                manager.delete_file(&filef.local_path)?;
+            }
+            if path.exists() {
+                return Err(Error::Other(
+                    format!("filef {} of namespaced {} wasn't deleted", filef.local_path, namespaced).into(),
+                ));
+            }
+            reclaimed += filef.size;
+        }
+        Ok(reclaimed)
+    }
+}
+
+#[cfg(unix)]
+fn is_hard_linked(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink() > 1
+}
+
+#[cfg(not(unix))]
+fn is_hard_linked(_: &fs::Metadata) -> bool {
+    false
+}
+
+fn shred_filef(path: &Path, size: u64) -> Result<()> {
+    let mut filef = OpenOptions::new().write(true).open(path)?;
+    let size = size.max(filef.metadata()?.len());
+    let zeros = vec![0; SHRED_CHUNK_SIZE];
+    let mut written = 0;
+    while written < size {
+        let n = (size - written).min(SHRED_CHUNK_SIZE as u64) as usize;
+        filef.write_all(&zeros[..n])?;
+        written += n as u64;
+    }
+    filef.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct MockEngine {
+        namespaceds: HashMap<&'static str, Vec<NamespacedFile>>,
+    }
+
+    impl NAMESPACEDNamesExt for MockEngine {
+        fn namespaced_names(&self) -> Vec<&str> {
+            self.namespaceds.keys().copied().collect()
+        }
+    }
+
+    impl NAMESPACEDDestroyExt for MockEngine {
+        fn namespaced_filefs(&self, namespaced: &str) -> Result<Vec<NamespacedFile>> {
+            Ok(self.namespaceds[namespaced].clone())
+        }
+
+        fn drop_namespaced(&mut self, namespaced: &str) -> Result<()> {
+            self.namespaceds.remove(namespaced);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_destroy_namespaced() {
+        let dir = tempfile::Builder::new().prefix("destroy_namespaced").tempdir().unwrap();
+        let mut filefs = vec![];
+        for (name, size) in &[("000001.sst", 100_000), ("000002.sst", 10), ("000003.sst", 20)] {
+            let path = dir.path().join(name);
+            fs::write(&path, vec![1; *size]).unwrap();
+            filefs.push(NamespacedFile {
+                local_path: path.to_str().unwrap().to_owned(),
+                size: *size as u64,
+            });
+        }
+        // A file the engine already deleted still counts as reclaimed.
+        fs::remove_file(&filefs[1].local_path).unwrap();
+        // A file a checkpoint links to isn't shredded under it.
+        let checkpoint = dir.path().join("checkpoint.sst");
+        fs::hard_link(&filefs[2].local_path, &checkpoint).unwrap();
+
+        let mut engine = MockEngine {
+            namespaceds: vec![(NAMESPACED_DEFAULT, vec![]), ("lock", filefs.clone())]
+                .into_iter()
+                .collect(),
+        };
+        match engine.destroy_namespaced(NAMESPACED_DEFAULT, false) {
+            Err(Error::Other(_)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        match engine.destroy_namespaced("write", false) {
+            Err(Error::NAMESPACEDName(name)) => assert_eq!(name, "write"),
+            r => panic!("unexpected {:?}", r),
+        }
+
+        assert_eq!(engine.destroy_namespaced("lock", true).unwrap(), 100_030);
+        assert_eq!(engine.namespaced_names(), vec![NAMESPACED_DEFAULT]);
+        assert!(filefs.iter().all(|f| !Path::new(&f.local_path).exists()));
+        #[cfg(unix)]
+        assert_eq!(fs::read(&checkpoint).unwrap(), vec![1; 20]);
+    }
+}
@@ -1,7 +1,7 @@
 // Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
 use crate::Result;
-use crate::{db_options::TitanDBOptions, Causet_partitioner::CausetPartitionerFactory};
+use crate::{einsteindb_options::TitanDBOptions, causet_partitioner::CausetPartitionerFactory};
 
 /// Trait for einstein_merkle_trees with column family options
 pub trait NAMESPACEDOptionsExt {
@@ -25,6 +25,7 @@ pub trait ColumnFamilyOptions {
     fn set_block_cache_capacity(&self, capacity: u64) -> std::result::Result<(), String>;
     fn set_titandb_options(&mut self, opts: &Self::TitanDBOptions);
     fn get_target_file_size_base(&self) -> u64;
+    fn get_write_buffer_size(&self) -> u64;
     fn set_disable_auto_jet_bundles(&mut self, v: bool);
     fn get_disable_auto_jet_bundles(&self) -> bool;
     fn get_disable_write_stall(&self) -> bool;
@@ -1,5 +1,6 @@
 // Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
 use std::ops::Bound;
+use std::time::Duration;
 use einsteindb_util::keybuilder::KeyBuilder;
 
 #[derive(Clone)]
@@ -33,6 +34,9 @@ impl Default for ReadOptions {
 pub struct WriteOptions {
     sync: bool,
     no_slowdown: bool,
+    // How long a write may wait for a write stall to clear before failing.
+    // `None` waits for as long as the einstein_merkle_tree stalls.
+    stall_timeout: Option<Duration>,
 }
 
 impl WriteOptions {
@@ -40,6 +44,7 @@ impl WriteOptions {
         WriteOptions {
             sync: false,
             no_slowdown: false,
+            stall_timeout: None,
         }
     }
 
@@ -58,6 +63,14 @@ impl WriteOptions {
     pub fn no_slowdown(&self) -> bool {
         self.no_slowdown
     }
+
+    pub fn set_stall_timeout(&mut self, timeout: Option<Duration>) {
+        self.stall_timeout = timeout;
+    }
+
+    pub fn stall_timeout(&self) -> Option<Duration> {
+        self.stall_timeout
+    }
 }
 
 #[derive(Clone, PartialEq)]
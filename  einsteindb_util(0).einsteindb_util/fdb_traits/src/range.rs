@@ -1,5 +1,7 @@
 // Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
+use std::cmp::Ordering;
+
 /// A range of keys, `start_key` is included, but not `end_key`.
 ///
 /// You should make sure `end_key` is not less than `start_key`.
@@ -14,3 +16,238 @@ impl<'a> Range<'a> {
         Range { start_key, end_key }
     }
 }
+
+/// Compares two exclusive end keys, where an empty end key means "unbounded".
+fn cmp_end_key(a: &[u8], b: &[u8]) -> Ordering {
+    match (a.is_empty(), b.is_empty()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.cmp(b),
+    }
+}
+
+/// Whether `key` is strictly before the exclusive `end_key`.
+fn is_before_end(key: &[u8], end_key: &[u8]) -> bool {
+    end_key.is_empty() || key < end_key
+}
+
+/// A set of disjoint key ranges.
+///
+/// Ranges are kept sorted by start key, and overlapping or adjacent ranges
+/// are coalesced on insertion, so the set always holds the minimal number of
+/// ranges covering the same keys. As elsewhere in this crate, an empty
+/// `end_key` means the range is unbounded above.
+///
+/// `MiscExt::delete_all_in_range` and `Iterable::scan_ranges` use it to
+/// plan range deletions and scans without re-implementing overlap and merge
+/// logic.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RangeSet {
+    ranges: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl RangeSet {
+    pub fn new() -> RangeSet {
+        RangeSet::default()
+    }
+
+    /// Builds a set from possibly overlapping, unsorted ranges.
+    pub fn from_ranges(ranges: &[Range<'_>]) -> RangeSet {
+        Self::coalesce(
+            ranges
+                .iter()
+                .map(|r| (r.start_key.to_vec(), r.end_key.to_vec()))
+                .collect(),
+        )
+    }
+
+    fn coalesce(mut ranges: Vec<(Vec<u8>, Vec<u8>)>) -> RangeSet {
+        ranges.retain(|(start, end)| is_before_end(start, end));
+        ranges.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut merged: Vec<(Vec<u8>, Vec<u8>)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            if let Some(last) = merged.last_mut() {
+                // Adjacent ranges (`last.1 == start`) are merged as well.
+                if last.1.is_empty() || start <= last.1 {
+                    if cmp_end_key(&end, &last.1) == Ordering::Greater {
+                        last.1 = end;
+                    }
+                    continue;
+                }
+            }
+            merged.push((start, end));
+        }
+        RangeSet { ranges: merged }
+    }
+
+    /// Adds `[start_key, end_key)` to the set, merging it with any ranges it
+    /// overlaps or touches.
+    pub fn insert(&mut self, start_key: &[u8], end_key: &[u8]) {
+        let mut ranges = std::mem::take(&mut self.ranges);
+        ranges.push((start_key.to_vec(), end_key.to_vec()));
+        *self = Self::coalesce(ranges);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The number of disjoint ranges in the set.
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Range<'_>> {
+        self.ranges.iter().map(|(s, e)| Range::new(s, e))
+    }
+
+    pub fn into_inner(self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.ranges
+    }
+
+    pub fn union(&self, other: &RangeSet) -> RangeSet {
+        let mut ranges = self.ranges.clone();
+        ranges.extend(other.ranges.iter().cloned());
+        Self::coalesce(ranges)
+    }
+
+    pub fn intersection(&self, other: &RangeSet) -> RangeSet {
+        let mut ranges = vec![];
+        let (mut i, mut j) = (0, 0);
+        while i < self.ranges.len() && j < other.ranges.len() {
+            let (a_start, a_end) = &self.ranges[i];
+            let (b_start, b_end) = &other.ranges[j];
+            let start = std::cmp::max(a_start, b_start);
+            let end = match cmp_end_key(a_end, b_end) {
+                Ordering::Greater => {
+                    j += 1;
+                    b_end
+                }
+                _ => {
+                    i += 1;
+                    a_end
+                }
+            };
+            if is_before_end(start, end) {
+                ranges.push((start.clone(), end.clone()));
+            }
+        }
+        RangeSet { ranges }
+    }
+
+    /// Returns the keys covered by `self` but not by `other`.
+    pub fn difference(&self, other: &RangeSet) -> RangeSet {
+        let mut ranges = vec![];
+        let mut j = 0;
+        for (start, end) in &self.ranges {
+            // Skip ranges of `other` that end before this one begins. They
+            // cannot overlap any later range of `self` either.
+            while j < other.ranges.len() && !is_before_end(start, &other.ranges[j].1) {
+                j += 1;
+            }
+            let mut cur = start.clone();
+            let mut k = j;
+            loop {
+                if k >= other.ranges.len() || !is_before_end(&other.ranges[k].0, end) {
+                    if is_before_end(&cur, end) {
+                        ranges.push((cur, end.clone()));
+                    }
+                    break;
+                }
+                let (cut_start, cut_end) = &other.ranges[k];
+                if cur < *cut_start {
+                    ranges.push((cur.clone(), cut_start.clone()));
+                }
+                if cut_end.is_empty() || !is_before_end(cut_end, end) {
+                    break;
+                }
+                if cur < *cut_end {
+                    cur = cut_end.clone();
+                }
+                k += 1;
+            }
+        }
+        RangeSet { ranges }
+    }
+
+    /// Checks whether `key` falls in any range of the set, in `O(log n)`.
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        let idx = self.ranges.partition_point(|(start, _)| start.as_slice() <= key);
+        idx > 0 && is_before_end(key, &self.ranges[idx - 1].1)
+    }
+
+    /// Checks whether any key of `range` is in the set, in `O(log n)`.
+    pub fn overlaps(&self, range: Range<'_>) -> bool {
+        if !is_before_end(range.start_key, range.end_key) {
+            return false;
+        }
+        let idx = self
+            .ranges
+            .partition_point(|(_, end)| !is_before_end(range.start_key, end));
+        idx < self.ranges.len() && is_before_end(&self.ranges[idx].0, range.end_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(ranges: &[(&str, &str)]) -> RangeSet {
+        let ranges: Vec<_> = ranges
+            .iter()
+            .map(|(s, e)| Range::new(s.as_bytes(), e.as_bytes()))
+            .collect();
+        RangeSet::from_ranges(&ranges)
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let s = set(&[("c", "e"), ("a", "b"), ("b", "c"), ("x", "y"), ("d", "f"), ("k", "k")]);
+        assert_eq!(s, set(&[("a", "f"), ("x", "y")]));
+        assert_eq!(s.len(), 2);
+
+        let s = set(&[("m", ""), ("a", "b"), ("n", "z")]);
+        assert_eq!(s, set(&[("a", "b"), ("m", "")]));
+
+        let mut s = RangeSet::new();
+        s.insert(b"d", b"e");
+        s.insert(b"a", b"b");
+        s.insert(b"b", b"d");
+        assert_eq!(s, set(&[("a", "e")]));
+    }
+
+    #[test]
+    fn test_set_algebra() {
+        let a = set(&[("a", "d"), ("f", "h"), ("m", "")]);
+        let b = set(&[("c", "g"), ("k", "n")]);
+
+        assert_eq!(a.union(&b), set(&[("a", "h"), ("k", "")]));
+        assert_eq!(
+            a.intersection(&b),
+            set(&[("c", "d"), ("f", "g"), ("m", "n")])
+        );
+        assert_eq!(a.difference(&b), set(&[("a", "c"), ("g", "h"), ("n", "")]));
+        assert_eq!(b.difference(&a), set(&[("d", "f"), ("k", "m")]));
+        assert_eq!(a.difference(&a), RangeSet::new());
+        assert_eq!(a.difference(&set(&[("", "")])), RangeSet::new());
+    }
+
+    #[test]
+    fn test_contains_and_overlaps() {
+        let s = set(&[("b", "d"), ("f", "h"), ("x", "")]);
+        assert!(!s.contains_key(b"a"));
+        assert!(s.contains_key(b"b"));
+        assert!(s.contains_key(b"c"));
+        assert!(!s.contains_key(b"d"));
+        assert!(s.contains_key(b"g"));
+        assert!(s.contains_key(b"zzz"));
+
+        assert!(s.overlaps(Range::new(b"a", b"c")));
+        assert!(!s.overlaps(Range::new(b"d", b"f")));
+        assert!(s.overlaps(Range::new(b"d", b"")));
+        assert!(!s.overlaps(Range::new(b"h", b"x")));
+        assert!(!s.overlaps(Range::new(b"c", b"c")));
+    }
+}
@@ -5,7 +5,11 @@
 //! In FdbDB these are typically implemented with user collected greedoids,
 //! which might require the database to be constructed with specific options.
 
+use txn_types::TimeStamp;
+
 use crate::errors::Result;
+use crate::mvcc_properties::MvccGreedoidsExt;
+use crate::namespaced_defs::{DATA_NAMESPACEDS, NAMESPACED_WRITE};
 use crate::Range;
 
 pub trait RangeGreedoidsExt {
@@ -47,3 +51,127 @@ pub trait RangeGreedoidsExt {
         key_count: usize,
     ) -> Result<Vec<Vec<u8>>>;
 }
+
+/// The approximate size and number of keys of a region, as reported in its
+/// heartbeat.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ApproximateRegionStats {
+    /// The approximate size of the region in every data namespaced
+    pub approximate_size: u64,
+    /// The approximate number of user keys in the region
+    pub approximate_keys: u64,
+}
+
+/// Computes the approximate size and keys of the region covering `range`.
+///
+/// The size is summed over the data namespaceds. The keys are the rows of the
+/// write namespaced's MVCC greedoids, which count each user key once however
+/// many versions it has; without MVCC greedoids they fall back to the
+/// approximate number of keys in the write namespaced.
+pub fn approximate_region_stats<E>(
+    einstein_merkle_tree: &E,
+    range: Range<'_>,
+) -> Result<ApproximateRegionStats>
+where
+    E: RangeGreedoidsExt + MvccGreedoidsExt,
+{
+    let mut stats = ApproximateRegionStats::default();
+    for namespaced in DATA_NAMESPACEDS {
+        stats.approximate_size +=
+            einstein_merkle_tree.get_range_approximate_size_namespaced(namespaced, range, 0)?;
+        if *namespaced == NAMESPACED_WRITE {
+            let greedoids = einstein_merkle_tree.get_mvcc_greedoids_namespaced(
+                namespaced,
+                TimeStamp::max(),
+                range.start_key,
+                range.end_key,
+            );
+            stats.approximate_keys = match greedoids {
+                Some(greedoids) => greedoids.num_rows,
+                None => einstein_merkle_tree
+                    .get_range_approximate_keys_namespaced(namespaced, range, 0)?,
+            };
+        }
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mvcc_properties::MvccGreedoids;
+
+    /// Reports 100 bytes and 10 keys per namespaced, and 3 MVCC rows if `mvcc`.
+    struct Fake {
+        mvcc: bool,
+    }
+
+    impl RangeGreedoidsExt for Fake {
+        fn get_range_approximate_keys(&self, range: Range<'_>, threshold: u64) -> Result<u64> {
+            self.get_range_approximate_keys_namespaced(NAMESPACED_WRITE, range, threshold)
+        }
+
+        fn get_range_approximate_keys_namespaced(
+            &self,
+            _: &str,
+            _: Range<'_>,
+            _: u64,
+        ) -> Result<u64> {
+            Ok(10)
+        }
+
+        fn get_range_approximate_size(&self, range: Range<'_>, threshold: u64) -> Result<u64> {
+            self.get_range_approximate_size_namespaced(NAMESPACED_WRITE, range, threshold)
+        }
+
+        fn get_range_approximate_size_namespaced(
+            &self,
+            _: &str,
+            _: Range<'_>,
+            _: u64,
+        ) -> Result<u64> {
+            Ok(100)
+        }
+
+        fn get_range_approximate_split_keys(&self, _: Range<'_>, _: usize) -> Result<Vec<Vec<u8>>> {
+            Ok(vec![])
+        }
+
+        fn get_range_approximate_split_keys_namespaced(
+            &self,
+            _: &str,
+            _: Range<'_>,
+            _: usize,
+        ) -> Result<Vec<Vec<u8>>> {
+            Ok(vec![])
+        }
+    }
+
+    impl MvccGreedoidsExt for Fake {
+        fn get_mvcc_greedoids_namespaced(
+            &self,
+            namespaced: &str,
+            _: TimeStamp,
+            _: &[u8],
+            _: &[u8],
+        ) -> Option<MvccGreedoids> {
+            assert_eq!(namespaced, NAMESPACED_WRITE);
+            if !self.mvcc {
+                return None;
+            }
+            let mut greedoids = MvccGreedoids::new();
+            greedoids.num_rows = 3;
+            greedoids.num_versions = 10;
+            Some(greedoids)
+        }
+    }
+
+    #[test]
+    fn test_approximate_region_stats() {
+        let range = Range::new(b"a", b"z");
+        let stats = approximate_region_stats(&Fake { mvcc: true }, range).unwrap();
+        assert_eq!(stats, ApproximateRegionStats { approximate_size: 300, approximate_keys: 3 });
+        let stats = approximate_region_stats(&Fake { mvcc: false }, range).unwrap();
+        assert_eq!(stats, ApproximateRegionStats { approximate_size: 300, approximate_keys: 10 });
+    }
+}
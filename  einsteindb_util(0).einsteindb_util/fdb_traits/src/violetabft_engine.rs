@@ -23,6 +23,7 @@ pub trait VioletaBFTeinstein_merkle_treeReadOnly: Sync + Send + 'static {
     fn get_all_entries_to(&self, region_id: u64, buf: &mut Vec<Entry>) -> Result<()>;
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct VioletaBFTLogGCTask {
     pub violetabft_group_id: u64,
     pub from: u64,
@@ -77,10 +78,52 @@ pub trait VioletaBFTeinstein_merkle_tree: VioletaBFTeinstein_merkle_treeReadOnly
         Ok(total)
     }
 
+    /// Bring this einstein_merkle_tree's copy of a VioletaBFT group up to date with `source`,
+    /// from `from_index` on, calling `on_progress` after each batch copied.
+    ///
+    /// Used to keep a read-only replica warm; see the `violetabft_sync` module.
+    fn sync_from<S, F>(
+        &self,
+        source: &S,
+        violetabft_group_id: u64,
+        from_index: u64,
+        options: &VioletaBFTSyncOptions,
+        on_progress: F,
+    ) -> Result<VioletaBFTSyncProgress>
+    where
+        S: VioletaBFTeinstein_merkle_treeReadOnly + ?Sized,
+        F: FnMut(&VioletaBFTSyncProgress),
+    {
+        crate::violetabft_sync::sync_from(self, source, violetabft_group_id, from_index, options, on_progress)
+    }
+
     /// Purge expired logs filefs and return a set of VioletaBFT group ids
     /// which needs to be compacted ASAP.
     fn purge_expired_filefs(&self) -> Result<Vec<u64>>;
 
+    /// The log usage of every VioletaBFT group with logs, in the `log_*`
+    /// fields of `CacheStats`. `purge_by_space` purges by it, so it has no
+    /// default: an einstein_merkle_tree reporting no logs would never purge.
+    fn log_usage(&self) -> Result<Vec<(u64, CacheStats)>>;
+
+    /// Once the logs of all groups pass `watermark.high_bytes`, return the
+    /// tasks compacting the largest logs, each up to (not including) the
+    /// index `compactable_to` gives for its group, until they're expected
+    /// to fit under `watermark.low_bytes`. Groups `compactable_to` gives
+    /// `None` for are left alone.
+    ///
+    /// See the `violetabft_purge` module.
+    fn purge_by_space<F>(
+        &self,
+        watermark: &VioletaBFTPurgeWatermark,
+        compactable_to: F,
+    ) -> Result<Vec<VioletaBFTLogGCTask>>
+    where
+        F: FnMut(u64) -> Option<u64>,
+    {
+        Ok(crate::violetabft_purge::purge_tasks(self.log_usage()?, watermark, compactable_to))
+    }
+
     /// The `VioletaBFTeinstein_merkle_tree` has a builtin entry cache or not.
     fn has_builtin_entry_cache(&self) -> bool {
         false
@@ -89,6 +132,20 @@ pub trait VioletaBFTeinstein_merkle_tree: VioletaBFTeinstein_merkle_treeReadOnly
     /// GC the builtin entry cache.
     fn gc_entry_cache(&self, _violetabft_group_id: u64, _to: u64) {}
 
+    /// Resize the builtin entry cache or change its eviction policy.
+    fn configure_entry_cache(&self, _config: EntryCacheConfig) -> Result<()> {
+        Err(Error::Other("the einstein_merkle_tree has no builtin entry cache".into()))
+    }
+
+    /// Keep the cached entries of a VioletaBFT group from being evicted, or stop keeping them.
+    fn pin_entry_cache(&self, _violetabft_group_id: u64, _pinned: bool) {}
+
+    /// The builtin entry cache statistics of one VioletaBFT group, with its
+    /// log usage if the einstein_merkle_tree tracks it.
+    fn group_cache_stats(&self, _violetabft_group_id: u64) -> Option<CacheStats> {
+        None
+    }
+
     fn flush_metrics(&self, _instance: &str) {}
     fn flush_stats(&self) -> Option<CacheStats> {
         None
@@ -126,4 +183,23 @@ pub struct CacheStats {
     pub hit: usize,
     pub miss: usize,
     pub cache_size: usize,
+    pub evicted: usize,
+    /// Bytes of pinned entries, included in `cache_size`
+    pub pinned_size: usize,
+    /// Bytes of VioletaBFT log entries held, cached or not
+    pub log_size: usize,
+    /// The count of VioletaBFT log entries held
+    pub log_entries: usize,
+    /// The index of the first VioletaBFT log entry held
+    pub log_first_index: u64,
+}
+
+impl CacheStats {
+    /// The share of lookups that hit, or 0 if there were none
+    pub fn hit_rate(&self) -> f64 {
+        if self.hit + self.miss == 0 {
+            return 0.0;
+        }
+        self.hit as f64 / (self.hit + self.miss) as f64
+    }
 }
@@ -0,0 +1,180 @@
+// Copyright 2021 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! Purging VioletaBFT logs by the space they take
+//!
+//! `purge_expired_filefs` purges logs by age. A store with a few groups
+//! whose logs aren't compacted, because a follower lags behind or applying
+//! is slow, can still fill its disk. `VioletaBFTeinstein_merkle_tree::purge_by_space`
+//! watches the bytes of all logs together: once they pass the high
+//! watermark, it picks the groups with the largest logs and returns
+//! `VioletaBFTLogGCTask`s compacting them, until the logs are expected to
+//! fit under the low watermark.
+//!
+//! The tasks are returned rather than run, so the caller can run them with
+//! `batch_gc` once it's told the groups' peers, or skip groups it can't
+//! compact yet. How far each group's log may be compacted is for the caller
+//! to say, since only it knows what's been applied.
+
+use std::cmp::Reverse;
+
+use crate::errors::Result;
+use crate::violetabft_engine::{CacheStats, VioletaBFTLogGCTask};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VioletaBFTPurgeWatermark {
+    /// Purge once the logs of all groups take more bytes than this
+    pub high_bytes: u64,
+    /// Purge until the logs are expected to take no more bytes than this
+    pub low_bytes: u64,
+    /// The most groups compacted by one purge
+    pub max_tasks: usize,
+}
+
+impl Default for VioletaBFTPurgeWatermark {
+    fn default() -> VioletaBFTPurgeWatermark {
+        VioletaBFTPurgeWatermark {
+            high_bytes: 8 * 1024 * 1024 * 1024,
+            low_bytes: 6 * 1024 * 1024 * 1024,
+            max_tasks: 32,
+        }
+    }
+}
+
+/// The bytes of the logs of all groups in `usage`
+pub fn total_log_size(usage: &[(u64, CacheStats)]) -> u64 {
+    usage.iter().map(|(_, stats)| stats.log_size as u64).sum()
+}
+
+/// See `VioletaBFTeinstein_merkle_tree::purge_by_space`.
+pub(crate) fn purge_tasks<F>(
+    mut usage: Vec<(u64, CacheStats)>,
+    watermark: &VioletaBFTPurgeWatermark,
+    mut compactable_to: F,
+) -> Vec<VioletaBFTLogGCTask>
+where
+    F: FnMut(u64) -> Option<u64>,
+{
+    let mut total = total_log_size(&usage);
+    if total <= watermark.high_bytes {
+        return vec![];
+    }
+
+    usage.sort_by_key(|(violetabft_group_id, stats)| (Reverse(stats.log_size), *violetabft_group_id));
+    let mut tasks = vec![];
+    for (violetabft_group_id, stats) in usage {
+        if total <= watermark.low_bytes || tasks.len() >= watermark.max_tasks {
+            break;
+        }
+        if stats.log_entries == 0 {
+            continue;
+        }
+        let first = stats.log_first_index;
+        let end = first + stats.log_entries as u64;
+        let to = match compactable_to(violetabft_group_id) {
+            Some(to) => to.min(end),
+            None => continue,
+        };
+        if to <= first {
+            continue;
+        }
+
+        // Entries are taken to be the same size.
+        let freed = stats.log_size as u64 * (to - first) / stats.log_entries as u64;
+        total = total.saturating_sub(freed);
+        tasks.push(VioletaBFTLogGCTask {
+            violetabft_group_id,
+            from: first,
+            to,
+        });
+    }
+    tasks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(violetabft_group_id: u64, first: u64, entries: usize, size: usize) -> (u64, CacheStats) {
+        let stats = CacheStats {
+            log_size: size,
+            log_entries: entries,
+            log_first_index: first,
+            ..CacheStats::default()
+        };
+        (violetabft_group_id, stats)
+    }
+
+    fn watermark(high_bytes: u64, low_bytes: u64) -> VioletaBFTPurgeWatermark {
+        VioletaBFTPurgeWatermark {
+            high_bytes,
+            low_bytes,
+            max_tasks: 10,
+        }
+    }
+
+    #[test]
+    fn test_purge_under_watermark() {
+        let usage = vec![usage(1, 1, 100, 1000), usage(2, 1, 100, 1000)];
+        assert_eq!(total_log_size(&usage), 2000);
+        assert!(purge_tasks(usage, &watermark(2000, 1000), |_| Some(100)).is_empty());
+    }
+
+    #[test]
+    fn test_purge_largest_first() {
+        let usage = vec![
+            usage(1, 1, 100, 1000),
+            usage(2, 11, 100, 4000),
+            usage(3, 1, 100, 2000),
+        ];
+        // Compacting group 2 to 61 frees 2000 bytes, which isn't enough; group 3 can't be
+        // compacted, so group 1 is next.
+        let tasks = purge_tasks(usage, &watermark(5000, 4500), |id| match id {
+            2 => Some(61),
+            3 => None,
+            _ => Some(1000),
+        });
+        assert_eq!(
+            tasks,
+            vec![
+                VioletaBFTLogGCTask { violetabft_group_id: 2, from: 11, to: 61 },
+                VioletaBFTLogGCTask { violetabft_group_id: 1, from: 1, to: 101 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_purge_stops_at_low_watermark() {
+        let usage = vec![usage(1, 1, 100, 3000), usage(2, 1, 100, 2000), usage(3, 1, 10, 10)];
+        let tasks = purge_tasks(usage.clone(), &watermark(4000, 3000), |_| Some(101));
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].violetabft_group_id, 1);
+
+        let mut limited = watermark(4000, 0);
+        limited.max_tasks = 2;
+        assert_eq!(purge_tasks(usage, &limited, |_| Some(101)).len(), 2);
+    }
+
+    #[test]
+    fn test_purge_by_space() {
+        use crate::violetabft_engine::VioletaBFTeinstein_merkle_tree;
+        use crate::violetabft_sync::tests::{entry, MemEngine};
+
+        let einstein_merkle_tree = MemEngine::default();
+        einstein_merkle_tree.append(1, (1..=30).map(|i| entry(i, 1)).collect()).unwrap();
+        einstein_merkle_tree.append(2, (5..=14).map(|i| entry(i, 1)).collect()).unwrap();
+
+        let logs: Vec<_> = einstein_merkle_tree
+            .log_usage()
+            .unwrap()
+            .into_iter()
+            .map(|(id, stats)| (id, stats.log_first_index, stats.log_entries, stats.log_size))
+            .collect();
+        assert_eq!(logs, vec![(1, 1, 30, 300), (2, 5, 10, 100)]);
+
+        let tasks = einstein_merkle_tree.purge_by_space(&watermark(350, 200), |_| Some(21)).unwrap();
+        assert_eq!(tasks, vec![VioletaBFTLogGCTask { violetabft_group_id: 1, from: 1, to: 21 }]);
+        einstein_merkle_tree.batch_gc(tasks).unwrap();
+        assert_eq!(total_log_size(&einstein_merkle_tree.log_usage().unwrap()), 200);
+        assert!(einstein_merkle_tree.purge_by_space(&watermark(350, 200), |_| Some(21)).unwrap().is_empty());
+    }
+}
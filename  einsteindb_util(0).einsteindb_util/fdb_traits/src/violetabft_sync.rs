@@ -0,0 +1,450 @@
+// Copyright 2021 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! Catching a replica VioletaBFT einstein_merkle_tree up with another
+//!
+//! A warm standby keeps a copy of the VioletaBFT log of a writable
+//! einstein_merkle_tree and serves it through
+//! `VioletaBFTeinstein_merkle_treeReadOnly`. `VioletaBFTeinstein_merkle_tree::sync_from`
+//! brings the copy of one VioletaBFT group up to date: it copies the entries
+//! of the source from `from_index` to the source's last index in batches,
+//! drops any entries the replica has past that index, since the source may
+//! have overwritten them, and then copies the source's `VioletaBFTLocalState`.
+//! If the source runs out of entries before its last index, the sync stops
+//! there and the replica keeps its own state, which still describes the
+//! entries it has.
+//!
+//! When the source has already compacted `from_index`, the replica can't be
+//! brought forward entry by entry. It then restarts from the source's log as
+//! it stands, a snapshot of it: the replica's entries of the group are
+//! cleaned and every entry the source still has is copied.
+//!
+//! Progress is reported after each batch, so a caller can show it or give up
+//! between batches.
+
+use std::mem;
+
+use ekvproto::violetabft_serverpb::VioletaBFTLocalState;
+use violetabft::evioletabftpb::Entry;
+
+use crate::errors::Result;
+use crate::violetabft_engine::{
+    VioletaBFTLogBatch, VioletaBFTeinstein_merkle_tree, VioletaBFTeinstein_merkle_treeReadOnly,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VioletaBFTSyncOptions {
+    /// The most entries copied in one batch
+    pub batch_entries: u64,
+    /// The most bytes of entries fetched in one batch, if limited
+    pub max_batch_size: Option<usize>,
+    /// Sync the replica once caught up
+    pub sync: bool,
+}
+
+impl Default for VioletaBFTSyncOptions {
+    fn default() -> VioletaBFTSyncOptions {
+        VioletaBFTSyncOptions {
+            batch_entries: 1024,
+            max_batch_size: Some(8 * 1024 * 1024),
+            sync: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VioletaBFTSyncProgress {
+    pub violetabft_group_id: u64,
+    /// The last index copied, or `from_index - 1` before any was
+    pub synced_to: u64,
+    /// The last index of the source when the sync started
+    pub target: u64,
+    pub entries: usize,
+    /// The bytes the replica wrote for the entries
+    pub bytes: usize,
+    /// Whether `from_index` was compacted in the source, so the replica
+    /// restarted from the source's log as it stands
+    pub from_snapshot: bool,
+}
+
+impl VioletaBFTSyncProgress {
+    pub fn is_caught_up(&self) -> bool {
+        self.synced_to >= self.target
+    }
+}
+
+/// See `VioletaBFTeinstein_merkle_tree::sync_from`.
+pub(crate) fn sync_from<R, S, F>(
+    replica: &R,
+    source: &S,
+    violetabft_group_id: u64,
+    from_index: u64,
+    options: &VioletaBFTSyncOptions,
+    mut on_progress: F,
+) -> Result<VioletaBFTSyncProgress>
+where
+    R: VioletaBFTeinstein_merkle_tree + ?Sized,
+    S: VioletaBFTeinstein_merkle_treeReadOnly + ?Sized,
+    F: FnMut(&VioletaBFTSyncProgress),
+{
+    let from_index = from_index.max(1);
+    let mut progress = VioletaBFTSyncProgress {
+        violetabft_group_id,
+        synced_to: from_index - 1,
+        ..Default::default()
+    };
+    let state = match source.get_violetabft_state(violetabft_group_id)? {
+        Some(state) => state,
+        None => return Ok(progress),
+    };
+    progress.target = state.get_last_index();
+
+    let mut entries = vec![];
+    let mut next = from_index;
+    if next <= progress.target && source.get_entry(violetabft_group_id, next)?.is_none() {
+        source.get_all_entries_to(violetabft_group_id, &mut entries)?;
+        let replica_state = replica
+            .get_violetabft_state(violetabft_group_id)?
+            .unwrap_or_else(VioletaBFTLocalState::default);
+        let mut batch = replica.log_batch(0);
+        replica.clean(violetabft_group_id, 0, &replica_state, &mut batch)?;
+        replica.consume(&mut batch, false)?;
+
+        progress.from_snapshot = true;
+        next = entries.first().map_or(progress.target + 1, |e| e.get_index());
+        progress.synced_to = next - 1;
+        while !entries.is_empty() {
+            let rest = entries.split_off(entries.len().min(options.batch_entries.max(1) as usize));
+            let batch = mem::replace(&mut entries, rest);
+            append(replica, violetabft_group_id, batch, &mut progress)?;
+            on_progress(&progress);
+        }
+        next = progress.synced_to + 1;
+    }
+
+    while next <= progress.target {
+        let end = progress.target.min(next + options.batch_entries.max(1) - 1) + 1;
+        let fetched = source.fetch_entries_to(
+            violetabft_group_id,
+            next,
+            end,
+            options.max_batch_size,
+            &mut entries,
+        )?;
+        if fetched == 0 {
+            break;
+        }
+        append(replica, violetabft_group_id, mem::take(&mut entries), &mut progress)?;
+        next = progress.synced_to + 1;
+        on_progress(&progress);
+    }
+
+    let mut batch = replica.log_batch(0);
+    if let Some(replica_state) = replica.get_violetabft_state(violetabft_group_id)? {
+        if replica_state.get_last_index() > progress.synced_to {
+            batch.cut_logs(
+                violetabft_group_id,
+                progress.synced_to + 1,
+                replica_state.get_last_index() + 1,
+            );
+        }
+    }
+    if progress.is_caught_up() {
+        batch.put_violetabft_state(violetabft_group_id, &state)?;
+    }
+    replica.consume(&mut batch, options.sync)?;
+    Ok(progress)
+}
+
+fn append<R: VioletaBFTeinstein_merkle_tree + ?Sized>(
+    replica: &R,
+    violetabft_group_id: u64,
+    entries: Vec<Entry>,
+    progress: &mut VioletaBFTSyncProgress,
+) -> Result<()> {
+    let (count, last) = (entries.len(), entries.last().map(|e| e.get_index()));
+    progress.bytes += replica.append(violetabft_group_id, entries)?;
+    progress.entries += count;
+    if let Some(last) = last {
+        progress.synced_to = last;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::{Arc, Mutex};
+
+    use crate::errors::Error;
+    use crate::violetabft_engine::{CacheStats, VioletaBFTLogGCTask};
+
+    #[derive(Default)]
+    struct Group {
+        state: Option<VioletaBFTLocalState>,
+        entries: BTreeMap<u64, Entry>,
+    }
+
+    /// An in-memory `VioletaBFTeinstein_merkle_tree`, shared with the tests of
+    /// the modules built on the trait
+    #[derive(Clone, Default)]
+    pub(crate) struct MemEngine {
+        groups: Arc<Mutex<HashMap<u64, Group>>>,
+    }
+
+    #[derive(Default)]
+    pub(crate) struct MemLogBatch {
+        appends: Vec<(u64, Vec<Entry>)>,
+        cuts: Vec<(u64, u64, u64)>,
+        states: Vec<(u64, VioletaBFTLocalState)>,
+    }
+
+    impl VioletaBFTLogBatch for MemLogBatch {
+        fn append(&mut self, violetabft_group_id: u64, entries: Vec<Entry>) -> Result<()> {
+            self.appends.push((violetabft_group_id, entries));
+            Ok(())
+        }
+        fn cut_logs(&mut self, violetabft_group_id: u64, from: u64, to: u64) {
+            self.cuts.push((violetabft_group_id, from, to));
+        }
+        fn put_violetabft_state(&mut self, violetabft_group_id: u64, state: &VioletaBFTLocalState) -> Result<()> {
+            self.states.push((violetabft_group_id, state.clone()));
+            Ok(())
+        }
+        fn persist_size(&self) -> usize {
+            0
+        }
+        fn is_empty(&self) -> bool {
+            self.appends.is_empty() && self.cuts.is_empty() && self.states.is_empty()
+        }
+        fn merge(&mut self, other: Self) {
+            self.appends.extend(other.appends);
+            self.cuts.extend(other.cuts);
+            self.states.extend(other.states);
+        }
+    }
+
+    impl VioletaBFTeinstein_merkle_treeReadOnly for MemEngine {
+        fn get_violetabft_state(&self, violetabft_group_id: u64) -> Result<Option<VioletaBFTLocalState>> {
+            let groups = self.groups.lock().unwrap();
+            Ok(groups.get(&violetabft_group_id).and_then(|g| g.state.clone()))
+        }
+
+        fn get_entry(&self, violetabft_group_id: u64, index: u64) -> Result<Option<Entry>> {
+            let groups = self.groups.lock().unwrap();
+            Ok(groups
+                .get(&violetabft_group_id)
+                .and_then(|g| g.entries.get(&index).cloned()))
+        }
+
+        fn fetch_entries_to(
+            &self,
+            violetabft_group_id: u64,
+            begin: u64,
+            end: u64,
+            _: Option<usize>,
+            to: &mut Vec<Entry>,
+        ) -> Result<usize> {
+            let groups = self.groups.lock().unwrap();
+            let group = groups.get(&violetabft_group_id).ok_or(Error::EntriesUnavailable)?;
+            if group.entries.keys().next().map_or(true, |&first| first > begin) {
+                return Err(Error::EntriesCompacted);
+            }
+            let before = to.len();
+            to.extend(group.entries.range(begin..end).map(|(_, e)| e.clone()));
+            Ok(to.len() - before)
+        }
+
+        fn get_all_entries_to(&self, region_id: u64, buf: &mut Vec<Entry>) -> Result<()> {
+            let groups = self.groups.lock().unwrap();
+            if let Some(group) = groups.get(&region_id) {
+                buf.extend(group.entries.values().cloned());
+            }
+            Ok(())
+        }
+    }
+
+    impl VioletaBFTeinstein_merkle_tree for MemEngine {
+        type LogBatch = MemLogBatch;
+
+        fn log_batch(&self, _: usize) -> MemLogBatch {
+            MemLogBatch::default()
+        }
+
+        fn sync(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn consume(&self, batch: &mut MemLogBatch, _: bool) -> Result<usize> {
+            let batch = mem::take(batch);
+            for (id, entries) in batch.appends {
+                self.append(id, entries)?;
+            }
+            let mut groups = self.groups.lock().unwrap();
+            for (id, from, to) in batch.cuts {
+                let group = groups.entry(id).or_default();
+                group.entries.retain(|&i, _| i < from || i >= to);
+            }
+            for (id, state) in batch.states {
+                groups.entry(id).or_default().state = Some(state);
+            }
+            Ok(0)
+        }
+
+        fn consume_and_shrink(&self, batch: &mut MemLogBatch, sync: bool, _: usize, _: usize) -> Result<usize> {
+            self.consume(batch, sync)
+        }
+
+        fn clean(&self, violetabft_group_id: u64, _: u64, _: &VioletaBFTLocalState, _: &mut MemLogBatch) -> Result<()> {
+            self.groups.lock().unwrap().remove(&violetabft_group_id);
+            Ok(())
+        }
+
+        fn append(&self, violetabft_group_id: u64, entries: Vec<Entry>) -> Result<usize> {
+            let mut groups = self.groups.lock().unwrap();
+            let group = groups.entry(violetabft_group_id).or_default();
+            let count = entries.len();
+            for entry in entries {
+                group.entries.insert(entry.get_index(), entry);
+            }
+            Ok(count * 10)
+        }
+
+        fn put_violetabft_state(&self, violetabft_group_id: u64, state: &VioletaBFTLocalState) -> Result<()> {
+            self.groups.lock().unwrap().entry(violetabft_group_id).or_default().state = Some(state.clone());
+            Ok(())
+        }
+
+        fn gc(&self, violetabft_group_id: u64, from: u64, to: u64) -> Result<usize> {
+            let mut groups = self.groups.lock().unwrap();
+            let group = groups.entry(violetabft_group_id).or_default();
+            let before = group.entries.len();
+            group.entries.retain(|&i, _| i < from || i >= to);
+            Ok(before - group.entries.len())
+        }
+
+        fn batch_gc(&self, tasks: Vec<VioletaBFTLogGCTask>) -> Result<usize> {
+            tasks.into_iter().map(|t| self.gc(t.violetabft_group_id, t.from, t.to)).sum()
+        }
+
+        fn purge_expired_filefs(&self) -> Result<Vec<u64>> {
+            Ok(vec![])
+        }
+
+        fn log_usage(&self) -> Result<Vec<(u64, CacheStats)>> {
+            let groups = self.groups.lock().unwrap();
+            let mut usage: Vec<_> = groups
+                .iter()
+                .filter_map(|(&id, group)| {
+                    let &first = group.entries.keys().next()?;
+                    // Entries take 10 bytes each, as `append` counts them.
+                    let stats = CacheStats {
+                        log_size: group.entries.len() * 10,
+                        log_entries: group.entries.len(),
+                        log_first_index: first,
+                        ..CacheStats::default()
+                    };
+                    Some((id, stats))
+                })
+                .collect();
+            usage.sort_by_key(|(id, _)| *id);
+            Ok(usage)
+        }
+
+        fn dump_stats(&self) -> Result<String> {
+            Ok(String::new())
+        }
+
+        fn get_einstein_merkle_tree_size(&self) -> Result<u64> {
+            Ok(0)
+        }
+    }
+
+    pub(crate) fn entry(index: u64, term: u64) -> Entry {
+        let mut entry = Entry::default();
+        entry.set_index(index);
+        entry.set_term(term);
+        entry
+    }
+
+    fn write(engine: &MemEngine, id: u64, indexes: std::ops::RangeInclusive<u64>, term: u64) {
+        let last = *indexes.end();
+        engine.append(id, indexes.map(|i| entry(i, term)).collect()).unwrap();
+        let mut state = VioletaBFTLocalState::default();
+        state.set_last_index(last);
+        engine.put_violetabft_state(id, &state).unwrap();
+    }
+
+    fn terms(engine: &MemEngine, id: u64) -> Vec<(u64, u64)> {
+        let mut entries = vec![];
+        engine.get_all_entries_to(id, &mut entries).unwrap();
+        entries.iter().map(|e| (e.get_index(), e.get_term())).collect()
+    }
+
+    #[test]
+    fn test_sync_from() {
+        let (source, replica) = (MemEngine::default(), MemEngine::default());
+        write(&source, 1, 1..=10, 1);
+        let options = VioletaBFTSyncOptions {
+            batch_entries: 4,
+            ..Default::default()
+        };
+
+        let mut reported = vec![];
+        let progress = replica
+            .sync_from(&source, 1, 1, &options, |p| reported.push(p.synced_to))
+            .unwrap();
+        assert_eq!(reported, vec![4, 8, 10]);
+        assert!(progress.is_caught_up());
+        assert_eq!((progress.entries, progress.bytes), (10, 100));
+        assert!(!progress.from_snapshot);
+        assert_eq!(terms(&replica, 1), terms(&source, 1));
+        assert_eq!(replica.get_violetabft_state(1).unwrap(), source.get_violetabft_state(1).unwrap());
+
+        // Catching up again copies only what's new, and drops the entries
+        // the source overwrote.
+        write(&source, 1, 9..=12, 2);
+        let progress = replica.sync_from(&source, 1, 9, &options, |_| {}).unwrap();
+        assert_eq!(progress.entries, 4);
+        assert_eq!(terms(&replica, 1), terms(&source, 1));
+        let mut state = VioletaBFTLocalState::default();
+        state.set_last_index(10);
+        source.put_violetabft_state(1, &state).unwrap();
+        source.gc(1, 11, 13).unwrap();
+        replica.sync_from(&source, 1, 11, &options, |_| {}).unwrap();
+        assert_eq!(terms(&replica, 1).last(), Some(&(10, 2)));
+
+        // The source's state is ahead of its entries: the sync stops short,
+        // and the replica's state isn't moved past what it copied.
+        state.set_last_index(12);
+        source.put_violetabft_state(1, &state).unwrap();
+        let progress = replica.sync_from(&source, 1, 11, &options, |_| {}).unwrap();
+        assert!(!progress.is_caught_up());
+        assert_eq!(progress.synced_to, 10);
+        assert_eq!(replica.get_violetabft_state(1).unwrap().unwrap().get_last_index(), 10);
+
+        // An unknown group is trivially caught up.
+        let progress = replica.sync_from(&source, 2, 1, &options, |_| {}).unwrap();
+        assert!(progress.is_caught_up());
+        assert!(replica.get_violetabft_state(2).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_sync_from_compacted() {
+        let (source, replica) = (MemEngine::default(), MemEngine::default());
+        write(&source, 1, 1..=5, 1);
+        replica.sync_from(&source, 1, 1, &VioletaBFTSyncOptions::default(), |_| {}).unwrap();
+
+        // The replica fell behind past what the source kept.
+        write(&source, 1, 6..=20, 1);
+        source.gc(1, 0, 15).unwrap();
+        let progress = replica
+            .sync_from(&source, 1, 6, &VioletaBFTSyncOptions::default(), |_| {})
+            .unwrap();
+        assert!(progress.from_snapshot);
+        assert!(progress.is_caught_up());
+        assert_eq!(progress.entries, 6);
+        assert_eq!(terms(&replica, 1), terms(&source, 1));
+        assert_eq!(replica.get_violetabft_state(1).unwrap().unwrap().get_last_index(), 20);
+    }
+}
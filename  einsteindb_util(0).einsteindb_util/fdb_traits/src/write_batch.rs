@@ -1,5 +1,8 @@
 // Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
 
+use einsteindb_util::codec::number::{self, NumberEncoder};
+use einsteindb_util::codec::Error as CodecError;
+
 use crate::errors::Result;
 use crate::options::WriteOptions;
 
@@ -8,6 +11,10 @@ pub trait WriteBatchExt: Sized {
     type WriteBatch: WriteBatch<Self>;
     /// `WriteBatchVec` is used for `multi_batch_write` of Fdbeinstein_merkle_tree and other einstein_merkle_tree could also
     /// implement another kind of WriteBatch according to their needs.
+    ///
+    /// Unlike `WriteBatch`, it is not written atomically as a whole, only as
+    /// sub-batches that keep the order of writes to the same key.
+    /// `PipelinedWriteBatch` implements it over `WriteBatch`.
     type WriteBatchVec: WriteBatch<Self>;
 
     /// The number of puts/deletes made to a write batch before the batch should
@@ -129,4 +136,225 @@ pub trait WriteBatch<E: WriteBatchExt + Sized>: Mutable {
 
     /// Merge another WriteBatch to itself
     fn merge(&mut self, src: Self);
+
+    /// Call `f` with each command in the batch, in the order issued
+    ///
+    /// Commands issued without a column family are reported in
+    /// `NAMESPACED_DEFAULT`. Stops at, and returns, the first error `f` returns.
+    fn iterate<F>(&self, f: F) -> Result<()>
+    where
+        F: FnMut(WriteBatchOp<'_>) -> Result<()>;
+
+    /// Serialize the commands in the batch
+    ///
+    /// The representation names column families rather than referring to
+    /// einstein_merkle_tree handles, so it can be logged, shipped to another
+    /// einstein_merkle_tree, and turned back into a batch with `from_repr`.
+    fn to_repr(&self) -> Result<Vec<u8>> {
+        let mut ops = Vec::with_capacity(self.data_size());
+        let mut count = 0usize;
+        self.iterate(|op| {
+            op.encode(&mut ops)?;
+            count += 1;
+            Ok(())
+        })?;
+
+        let mut repr = Vec::with_capacity(ops.len() + 1 + number::MAX_VAR_U64_LEN);
+        repr.push(WRITE_BATCH_REPR_VERSION);
+        repr.encode_var_u64(count as u64)?;
+        repr.extend_from_slice(&ops);
+        Ok(repr)
+    }
+
+    /// Create a batch of the commands serialized by `to_repr`
+    fn from_repr(e: &E, repr: &[u8]) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        let mut wb = Self::with_capacity(e, 0);
+        iterate_repr(repr, |op| op.apply(&mut wb))?;
+        Ok(wb)
+    }
+}
+
+/// The version of the `WriteBatch::to_repr` format
+pub const WRITE_BATCH_REPR_VERSION: u8 = 1;
+
+const OP_PUT: u8 = 0;
+const OP_DELETE: u8 = 1;
+const OP_DELETE_RANGE: u8 = 2;
+
+/// A command in a write batch
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteBatchOp<'a> {
+    Put {
+        namespaced: &'a str,
+        key: &'a [u8],
+        value: &'a [u8],
+    },
+    Delete {
+        namespaced: &'a str,
+        key: &'a [u8],
+    },
+    DeleteRange {
+        namespaced: &'a str,
+        begin_key: &'a [u8],
+        end_key: &'a [u8],
+    },
+}
+
+impl<'a> WriteBatchOp<'a> {
+    pub fn namespaced(&self) -> &'a str {
+        match *self {
+            WriteBatchOp::Put { namespaced, .. }
+            | WriteBatchOp::Delete { namespaced, .. }
+            | WriteBatchOp::DeleteRange { namespaced, .. } => namespaced,
+        }
+    }
+
+    /// Issue the command to `m`
+    pub fn apply<M: Mutable + ?Sized>(&self, m: &mut M) -> Result<()> {
+        match *self {
+            WriteBatchOp::Put { namespaced, key, value } => {
+                m.put_namespaced(namespaced, key, value)
+            }
+            WriteBatchOp::Delete { namespaced, key } => m.delete_namespaced(namespaced, key),
+            WriteBatchOp::DeleteRange {
+                namespaced,
+                begin_key,
+                end_key,
+            } => m.delete_range_namespaced(namespaced, begin_key, end_key),
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<()> {
+        let (tag, namespaced, first, second) = match *self {
+            WriteBatchOp::Put { namespaced, key, value } => (OP_PUT, namespaced, key, Some(value)),
+            WriteBatchOp::Delete { namespaced, key } => (OP_DELETE, namespaced, key, None),
+            WriteBatchOp::DeleteRange {
+                namespaced,
+                begin_key,
+                end_key,
+            } => (OP_DELETE_RANGE, namespaced, begin_key, Some(end_key)),
+        };
+        buf.push(tag);
+        for field in [Some(namespaced.as_bytes()), Some(first), second].iter().flatten() {
+            buf.encode_var_u64(field.len() as u64)?;
+            buf.extend_from_slice(field);
+        }
+        Ok(())
+    }
+}
+
+/// Call `f` with each command serialized in `repr` by `WriteBatch::to_repr`
+pub fn iterate_repr<F>(mut repr: &[u8], mut f: F) -> Result<()>
+where
+    F: FnMut(WriteBatchOp<'_>) -> Result<()>,
+{
+    fn read_bytes<'a>(repr: &mut &'a [u8]) -> Result<&'a [u8]> {
+        let len = number::decode_var_u64(repr)? as usize;
+        if repr.len() < len {
+            return Err(CodecError::unexpected_eof().into());
+        }
+        let (bytes, rest) = repr.split_at(len);
+        *repr = rest;
+        Ok(bytes)
+    }
+
+    fn read_str<'a>(repr: &mut &'a [u8]) -> Result<&'a str> {
+        let bytes = read_bytes(repr)?;
+        std::str::from_utf8(bytes).map_err(|e| crate::Error::Other(Box::new(e)))
+    }
+
+    match repr.split_first() {
+        Some((&WRITE_BATCH_REPR_VERSION, rest)) => repr = rest,
+        Some((version, _)) => {
+            return Err(crate::Error::Other(
+                format!("unknown write batch repr version {}", version).into(),
+            ))
+        }
+        None => return Err(CodecError::unexpected_eof().into()),
+    }
+    let count = number::decode_var_u64(&mut repr)?;
+    for _ in 0..count {
+        let (&tag, rest) = repr.split_first().ok_or_else(CodecError::unexpected_eof)?;
+        repr = rest;
+        let namespaced = read_str(&mut repr)?;
+        let op = match tag {
+            OP_PUT => WriteBatchOp::Put {
+                namespaced,
+                key: read_bytes(&mut repr)?,
+                value: read_bytes(&mut repr)?,
+            },
+            OP_DELETE => WriteBatchOp::Delete {
+                namespaced,
+                key: read_bytes(&mut repr)?,
+            },
+            OP_DELETE_RANGE => WriteBatchOp::DeleteRange {
+                namespaced,
+                begin_key: read_bytes(&mut repr)?,
+                end_key: read_bytes(&mut repr)?,
+            },
+            tag => {
+                return Err(crate::Error::Other(
+                    format!("unknown write batch op {}", tag).into(),
+                ))
+            }
+        };
+        f(op)?;
+    }
+    if !repr.is_empty() {
+        return Err(crate::Error::Other(
+            format!("{} trailing bytes in write batch repr", repr.len()).into(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_write_batch::MockEngine;
+
+    #[test]
+    fn test_write_batch_repr() {
+        let engine = MockEngine::default();
+        let mut wb = engine.write_batch();
+        wb.put(b"a", b"1").unwrap();
+        wb.delete_namespaced("write", b"b").unwrap();
+        wb.delete_range_namespaced("lock", b"c", b"d").unwrap();
+
+        let mut ops = vec![];
+        wb.iterate(|op| {
+            ops.push(format!("{:?}", op));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(ops.len(), 3);
+
+        let repr = wb.to_repr().unwrap();
+        let copy = <MockEngine as WriteBatchExt>::WriteBatch::from_repr(&engine, &repr).unwrap();
+        assert_eq!(copy.to_repr().unwrap(), repr);
+        copy.write().unwrap();
+        assert_eq!(
+            *engine.written.lock().unwrap(),
+            vec![vec![
+                "put default a 1".to_owned(),
+                "delete write b".to_owned(),
+                "delete_range lock c d".to_owned(),
+            ]]
+        );
+
+        // `iterate` stops at the first error.
+        let mut seen = 0;
+        assert!(wb
+            .iterate(|_| {
+                seen += 1;
+                Err(crate::Error::Other("stop".into()))
+            })
+            .is_err());
+        assert_eq!(seen, 1);
+
+        assert!(iterate_repr(&repr[..repr.len() - 1], |_| Ok(())).is_err());
+    }
 }
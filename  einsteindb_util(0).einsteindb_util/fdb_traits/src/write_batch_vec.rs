@@ -0,0 +1,701 @@
+// Copyright 2019 EinsteinDB Project Authors. Licensed under Apache-2.0.
+
+//! Write batches split into pipelined sub-batches
+//!
+//! A `WriteBatch` is written atomically, which for a large batch means one
+//! large write holding up every write queued behind it. A
+//! `PipelinedWriteBatch` splits its mutations into sub-batches of at most
+//! `sub_batch_keys` mutations and writes them separately, up to
+//! `parallelism` at a time. An einstein_merkle_tree can use it as its
+//! `WriteBatchExt::WriteBatchVec`.
+//!
+//! What is and isn't guaranteed:
+//!
+//! - Each sub-batch is written atomically. The batch as a whole is not: a
+//!   reader may see some sub-batches and not others, and if a write fails the
+//!   sub-batches before the failing one may already be written.
+//! - Mutations of the same key are applied in the order they were issued.
+//!   A sub-batch that touches a key an earlier sub-batch touched, or any
+//!   sub-batch next to a range delete, waits for every earlier sub-batch to
+//!   be written before it is written. Only sub-batches with disjoint keys are
+//!   written in parallel.
+//! - With `atomic` set, all mutations go to a single sub-batch, written as one
+//!   atomic write, exactly like a `WriteBatch`.
+//!
+//! A write with `parallelism` above one starts at most `parallelism - 1`
+//! scoped worker threads, whatever the number of sub-batches. The caller and
+//! the workers take sub-batches off a shared queue in order; one with a
+//! barrier is not taken until every sub-batch before it is written.
+
+use std::collections::HashSet;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use crate::errors::{Error, Result};
+use crate::options::WriteOptions;
+use crate::write_batch::{Mutable, WriteBatch, WriteBatchExt, WriteBatchOp};
+
+/// How a `PipelinedWriteBatch` splits and writes its mutations
+#[derive(Clone, Debug, PartialEq)]
+pub struct WriteBatchVecConfig {
+    /// The most mutations in one sub-batch
+    pub sub_batch_keys: usize,
+    /// The most sub-batches written at the same time
+    pub parallelism: usize,
+    /// Write everything as one atomic sub-batch
+    pub atomic: bool,
+}
+
+impl WriteBatchVecConfig {
+    pub fn new<E: WriteBatchExt>() -> WriteBatchVecConfig {
+        WriteBatchVecConfig {
+            sub_batch_keys: E::WRITE_BATCH_MAX_CAUSET_KEYS,
+            parallelism: 1,
+            atomic: false,
+        }
+    }
+}
+
+type KeyRef = (String, Vec<u8>);
+
+struct SubBatch<W> {
+    // Only locked by `write_opt`, to share the batch between its workers.
+    batch: Mutex<W>,
+    /// Wait for every earlier sub-batch before writing this one
+    barrier: bool,
+}
+
+/// Progress of a `PipelinedWriteBatch::write_opt` shared by its workers
+struct WriteQueue {
+    /// The next sub-batch to take
+    next: usize,
+    /// The number of sub-batches taken and written
+    written: usize,
+    /// The first error, after which no sub-batch is taken
+    error: Option<Error>,
+}
+
+/// A write batch made of sub-batches written in a pipeline
+pub struct PipelinedWriteBatch<E: WriteBatchExt> {
+    einstein_merkle_tree: E,
+    config: WriteBatchVecConfig,
+    sub_batches: Vec<SubBatch<E::WriteBatch>>,
+    /// Start a new sub-batch with the next mutation
+    seal: bool,
+    /// The save points, as indexes of the sub-batch each was set on
+    save_points: Vec<usize>,
+
+    // Keys touched since the last barrier, for parallel writes only. Those
+    // of the current sub-batch are kept apart from those of the sub-batches
+    // before it, which may be written at the same time.
+    current_keys: HashSet<KeyRef>,
+    current_range: bool,
+    earlier_keys: HashSet<KeyRef>,
+    earlier_range: bool,
+}
+
+impl<E: WriteBatchExt + Clone> PipelinedWriteBatch<E> {
+    pub fn new(einstein_merkle_tree: &E, config: WriteBatchVecConfig) -> PipelinedWriteBatch<E> {
+        assert!(config.sub_batch_keys > 0 && config.parallelism > 0);
+        PipelinedWriteBatch {
+            einstein_merkle_tree: einstein_merkle_tree.clone(),
+            config,
+            sub_batches: vec![],
+            seal: true,
+            save_points: vec![],
+            current_keys: HashSet::new(),
+            current_range: false,
+            earlier_keys: HashSet::new(),
+            earlier_range: false,
+        }
+    }
+
+    pub fn config(&self) -> &WriteBatchVecConfig {
+        &self.config
+    }
+
+    /// The number of sub-batches the mutations were split into
+    pub fn sub_batch_count(&self) -> usize {
+        self.sub_batches.len()
+    }
+
+    /// The sub-batches as `write_opt` writes them: groups of sub-batch
+    /// indexes, each written after the group before it, whose members may be
+    /// written at the same time.
+    pub fn write_plan(&self) -> Vec<Vec<usize>> {
+        let mut plan: Vec<Vec<usize>> = vec![];
+        for (i, sub) in self.sub_batches.iter().enumerate() {
+            match plan.last_mut() {
+                Some(group) if !sub.barrier => group.push(i),
+                _ => plan.push(vec![i]),
+            }
+        }
+        plan
+    }
+
+    fn tracks_keys(&self) -> bool {
+        !self.config.atomic && self.config.parallelism > 1
+    }
+
+    fn start_sub_batch(&mut self, barrier: bool) {
+        let cap = self.config.sub_batch_keys;
+        self.sub_batches.push(SubBatch {
+            batch: Mutex::new(self.einstein_merkle_tree.write_batch_with_cap(cap)),
+            barrier,
+        });
+        if barrier {
+            self.earlier_keys.clear();
+            self.earlier_range = false;
+        } else {
+            self.earlier_keys.extend(self.current_keys.drain());
+            self.earlier_range |= self.current_range;
+        }
+        self.current_keys.clear();
+        self.current_range = false;
+        self.seal = false;
+    }
+
+    /// Returns the sub-batch a mutation of `key`, or of a range if `None`,
+    /// goes to.
+    fn sub_batch_for(&mut self, namespaced: &str, key: Option<&[u8]>) -> &mut E::WriteBatch {
+        let full = self
+            .sub_batches
+            .last()
+            .map_or(true, |sub| !self.config.atomic && batch(sub).count() >= self.config.sub_batch_keys);
+        if self.seal || full {
+            let barrier = self.seal && !self.sub_batches.is_empty();
+            self.start_sub_batch(barrier);
+        }
+
+        if self.tracks_keys() {
+            let key = key.map(|key| (namespaced.to_owned(), key.to_vec()));
+            let conflict = self.earlier_range
+                || match &key {
+                    Some(key) => self.earlier_keys.contains(key),
+                    None => !self.earlier_keys.is_empty(),
+                };
+            if conflict {
+                self.sub_batches.last_mut().unwrap().barrier = true;
+                self.earlier_keys.clear();
+                self.earlier_range = false;
+            }
+            match key {
+                Some(key) => {
+                    self.current_keys.insert(key);
+                }
+                None => self.current_range = true,
+            }
+        }
+        self.sub_batches.last_mut().unwrap().batch.get_mut().unwrap()
+    }
+
+    /// Takes sub-batches off `queue` and writes them until none is left or a
+    /// write fails.
+    fn write_worker(&self, queue: &Mutex<WriteQueue>, ready: &Condvar, opts: &WriteOptions) {
+        let mut q = queue.lock().unwrap();
+        loop {
+            if q.error.is_some() || q.next == self.sub_batches.len() {
+                return;
+            }
+            let i = q.next;
+            if self.sub_batches[i].barrier && q.written < i {
+                q = ready.wait(q).unwrap();
+                continue;
+            }
+            q.next += 1;
+            drop(q);
+            let res = self.sub_batches[i].batch.lock().unwrap().write_opt(opts);
+            q = queue.lock().unwrap();
+            q.written += 1;
+            if let Err(e) = res {
+                q.error.get_or_insert(e);
+            }
+            ready.notify_all();
+        }
+    }
+}
+
+fn batch<W>(sub: &SubBatch<W>) -> std::sync::MutexGuard<'_, W> {
+    sub.batch.lock().unwrap()
+}
+
+impl<E: WriteBatchExt + Clone + Send> Mutable for PipelinedWriteBatch<E> {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.sub_batch_for(crate::NAMESPACED_DEFAULT, Some(key)).put(key, value)
+    }
+
+    fn put_namespaced(&mut self, namespaced: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.sub_batch_for(namespaced, Some(key)).put_namespaced(namespaced, key, value)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.sub_batch_for(crate::NAMESPACED_DEFAULT, Some(key)).delete(key)
+    }
+
+    fn delete_namespaced(&mut self, namespaced: &str, key: &[u8]) -> Result<()> {
+        self.sub_batch_for(namespaced, Some(key)).delete_namespaced(namespaced, key)
+    }
+
+    fn delete_range(&mut self, begin_key: &[u8], end_key: &[u8]) -> Result<()> {
+        self.sub_batch_for(crate::NAMESPACED_DEFAULT, None).delete_range(begin_key, end_key)
+    }
+
+    fn delete_range_namespaced(&mut self, namespaced: &str, begin_key: &[u8], end_key: &[u8]) -> Result<()> {
+        self.sub_batch_for(namespaced, None)
+            .delete_range_namespaced(namespaced, begin_key, end_key)
+    }
+}
+
+impl<E> WriteBatch<E> for PipelinedWriteBatch<E>
+where
+    E: WriteBatchExt + Clone + Send + Sync,
+    E::WriteBatch: Send,
+{
+    fn with_capacity(e: &E, _: usize) -> Self {
+        PipelinedWriteBatch::new(e, WriteBatchVecConfig::new::<E>())
+    }
+
+    /// Writes the sub-batches in the order of `write_plan`, up to
+    /// `parallelism` at a time. Stops taking sub-batches at the first failed
+    /// one.
+    fn write_opt(&self, opts: &WriteOptions) -> Result<()> {
+        let widest = self.write_plan().iter().map(Vec::len).max().unwrap_or(0);
+        let workers = self.config.parallelism.min(widest);
+        if workers <= 1 {
+            for sub in &self.sub_batches {
+                batch(sub).write_opt(opts)?;
+            }
+            return Ok(());
+        }
+        let queue = Mutex::new(WriteQueue {
+            next: 0,
+            written: 0,
+            error: None,
+        });
+        let ready = Condvar::new();
+        thread::scope(|s| {
+            for _ in 1..workers {
+                s.spawn(|| self.write_worker(&queue, &ready, opts));
+            }
+            self.write_worker(&queue, &ready, opts);
+        });
+        match queue.into_inner().unwrap().error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn data_size(&self) -> usize {
+        self.sub_batches.iter().map(|sub| batch(sub).data_size()).sum()
+    }
+
+    fn count(&self) -> usize {
+        self.sub_batches.iter().map(|sub| batch(sub).count()).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.sub_batches.iter().all(|sub| batch(sub).is_empty())
+    }
+
+    fn should_write_to_einstein_merkle_tree(&self) -> bool {
+        self.count() > E::WRITE_BATCH_MAX_CAUSET_KEYS * self.config.parallelism
+    }
+
+    fn clear(&mut self) {
+        self.sub_batches.clear();
+        self.save_points.clear();
+        self.seal = true;
+        self.current_keys.clear();
+        self.current_range = false;
+        self.earlier_keys.clear();
+        self.earlier_range = false;
+    }
+
+    fn set_save_point(&mut self) {
+        if self.sub_batches.is_empty() || self.seal {
+            let barrier = !self.sub_batches.is_empty();
+            self.start_sub_batch(barrier);
+        }
+        let i = self.sub_batches.len() - 1;
+        self.sub_batches[i].batch.get_mut().unwrap().set_save_point();
+        self.save_points.push(i);
+    }
+
+    fn pop_save_point(&mut self) -> Result<()> {
+        match self.save_points.pop() {
+            Some(i) => self.sub_batches[i].batch.get_mut().unwrap().pop_save_point(),
+            None => Err(Error::Other("no save point to pop".into())),
+        }
+    }
+
+    /// Reverts to the last save point. Later mutations go to a new sub-batch
+    /// that waits for all earlier ones, since the keys the reverted commands
+    /// touched are no longer known.
+    fn rollback_to_save_point(&mut self) -> Result<()> {
+        let i = match self.save_points.pop() {
+            Some(i) => i,
+            None => return Err(Error::Other("no save point to roll back to".into())),
+        };
+        self.sub_batches.truncate(i + 1);
+        self.sub_batches[i].batch.get_mut().unwrap().rollback_to_save_point()?;
+        self.seal = true;
+        Ok(())
+    }
+
+    /// Appends the sub-batches of `src`, the first of which waits for all of
+    /// this batch's sub-batches.
+    fn merge(&mut self, src: Self) {
+        let mut src_sub_batches = src.sub_batches.into_iter();
+        if let Some(mut first) = src_sub_batches.next() {
+            first.barrier = !self.sub_batches.is_empty();
+            self.sub_batches.push(first);
+        }
+        self.sub_batches.extend(src_sub_batches);
+        self.seal = true;
+    }
+
+    fn iterate<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(WriteBatchOp<'_>) -> Result<()>,
+    {
+        for sub in &self.sub_batches {
+            batch(sub).iterate(&mut f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    enum Cmd {
+        Put(Vec<u8>, Vec<u8>),
+        Delete(Vec<u8>),
+        DeleteRange(Vec<u8>, Vec<u8>),
+    }
+
+    /// Applies each written batch to a map, recording the size of each
+    /// batch written. Writing a batch that puts `fail_key` fails.
+    #[derive(Clone, Default)]
+    struct MockEngine {
+        state: Arc<Mutex<BTreeMap<Vec<u8>, Vec<u8>>>>,
+        written: Arc<Mutex<Vec<usize>>>,
+        fail_key: Option<Vec<u8>>,
+    }
+
+    struct MockBatch {
+        e: MockEngine,
+        cmds: Vec<Cmd>,
+        save_points: Vec<usize>,
+    }
+
+    impl Mutable for MockBatch {
+        fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+            self.cmds.push(Cmd::Put(key.to_vec(), value.to_vec()));
+            Ok(())
+        }
+
+        fn put_namespaced(&mut self, _: &str, key: &[u8], value: &[u8]) -> Result<()> {
+            self.put(key, value)
+        }
+
+        fn delete(&mut self, key: &[u8]) -> Result<()> {
+            self.cmds.push(Cmd::Delete(key.to_vec()));
+            Ok(())
+        }
+
+        fn delete_namespaced(&mut self, _: &str, key: &[u8]) -> Result<()> {
+            self.delete(key)
+        }
+
+        fn delete_range(&mut self, begin_key: &[u8], end_key: &[u8]) -> Result<()> {
+            self.cmds.push(Cmd::DeleteRange(begin_key.to_vec(), end_key.to_vec()));
+            Ok(())
+        }
+
+        fn delete_range_namespaced(&mut self, _: &str, begin_key: &[u8], end_key: &[u8]) -> Result<()> {
+            self.delete_range(begin_key, end_key)
+        }
+    }
+
+    impl WriteBatch<MockEngine> for MockBatch {
+        fn with_capacity(e: &MockEngine, cap: usize) -> MockBatch {
+            MockBatch {
+                e: e.clone(),
+                cmds: Vec::with_capacity(cap),
+                save_points: vec![],
+            }
+        }
+
+        fn write_opt(&self, _: &WriteOptions) -> Result<()> {
+            let fails = self.cmds.iter().any(|cmd| match (cmd, &self.e.fail_key) {
+                (Cmd::Put(key, _), Some(fail_key)) => key == fail_key,
+                _ => false,
+            });
+            if fails {
+                return Err(Error::Other("injected write failure".into()));
+            }
+            let mut state = self.e.state.lock().unwrap();
+            for cmd in &self.cmds {
+                match cmd {
+                    Cmd::Put(key, value) => {
+                        state.insert(key.clone(), value.clone());
+                    }
+                    Cmd::Delete(key) => {
+                        state.remove(key);
+                    }
+                    Cmd::DeleteRange(begin, end) => {
+                        let keys: Vec<_> = state.range(begin.clone()..end.clone()).map(|(k, _)| k.clone()).collect();
+                        for key in keys {
+                            state.remove(&key);
+                        }
+                    }
+                }
+            }
+            self.e.written.lock().unwrap().push(self.cmds.len());
+            Ok(())
+        }
+
+        fn data_size(&self) -> usize {
+            self.cmds.len()
+        }
+
+        fn count(&self) -> usize {
+            self.cmds.len()
+        }
+
+        fn is_empty(&self) -> bool {
+            self.cmds.is_empty()
+        }
+
+        fn should_write_to_einstein_merkle_tree(&self) -> bool {
+            false
+        }
+
+        fn clear(&mut self) {
+            self.cmds.clear();
+        }
+
+        fn set_save_point(&mut self) {
+            self.save_points.push(self.cmds.len());
+        }
+
+        fn pop_save_point(&mut self) -> Result<()> {
+            self.save_points.pop();
+            Ok(())
+        }
+
+        fn rollback_to_save_point(&mut self) -> Result<()> {
+            let len = self.save_points.pop().unwrap();
+            self.cmds.truncate(len);
+            Ok(())
+        }
+
+        fn merge(&mut self, src: MockBatch) {
+            self.cmds.extend(src.cmds);
+        }
+
+        fn iterate<F>(&self, mut f: F) -> Result<()>
+        where
+            F: FnMut(WriteBatchOp<'_>) -> Result<()>,
+        {
+            let namespaced = crate::NAMESPACED_DEFAULT;
+            for cmd in &self.cmds {
+                match cmd {
+                    Cmd::Put(key, value) => f(WriteBatchOp::Put { namespaced, key, value })?,
+                    Cmd::Delete(key) => f(WriteBatchOp::Delete { namespaced, key })?,
+                    Cmd::DeleteRange(begin_key, end_key) => f(WriteBatchOp::DeleteRange {
+                        namespaced,
+                        begin_key,
+                        end_key,
+                    })?,
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl WriteBatchExt for MockEngine {
+        type WriteBatch = MockBatch;
+        type WriteBatchVec = PipelinedWriteBatch<MockEngine>;
+
+        const WRITE_BATCH_MAX_CAUSET_KEYS: usize = 2;
+
+        fn support_write_batch_vec(&self) -> bool {
+            true
+        }
+
+        fn write_batch(&self) -> MockBatch {
+            MockBatch::with_capacity(self, 0)
+        }
+
+        fn write_batch_with_cap(&self, cap: usize) -> MockBatch {
+            MockBatch::with_capacity(self, cap)
+        }
+    }
+
+    fn config(parallelism: usize, atomic: bool) -> WriteBatchVecConfig {
+        WriteBatchVecConfig {
+            sub_batch_keys: 2,
+            parallelism,
+            atomic,
+        }
+    }
+
+    fn keys(engine: &MockEngine) -> Vec<Vec<u8>> {
+        engine.state.lock().unwrap().keys().cloned().collect()
+    }
+
+    #[test]
+    fn test_pipelined_write_batch_ordering() {
+        let engine = MockEngine::default();
+        let mut wb = PipelinedWriteBatch::new(&engine, config(4, false));
+        // Sub-batches [a b] [c d] [a e] [b]: the third rewrites `a`, so it
+        // waits for the first two. The fourth only conflicts with the first,
+        // which the third already waits for, so it goes with the third.
+        for (k, v) in &[("a", "1"), ("b", "1"), ("c", "1"), ("d", "1"), ("a", "2"), ("e", "1")] {
+            wb.put(k.as_bytes(), v.as_bytes()).unwrap();
+        }
+        wb.delete(b"b").unwrap();
+        assert_eq!(wb.sub_batch_count(), 4);
+        assert_eq!(wb.count(), 7);
+        assert_eq!(wb.write_plan(), vec![vec![0, 1], vec![2, 3]]);
+        wb.write().unwrap();
+
+        let state = engine.state.lock().unwrap();
+        assert_eq!(state.get(&b"a".to_vec()), Some(&b"2".to_vec()));
+        assert_eq!(state.get(&b"b".to_vec()), None);
+        assert_eq!(state.len(), 3);
+        assert_eq!(engine.written.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_pipelined_write_batch_range_barrier() {
+        let engine = MockEngine::default();
+        let mut wb = PipelinedWriteBatch::new(&engine, config(4, false));
+        // Sub-batches [a b] [c d] [range e] [f g]: the range delete waits for
+        // all earlier sub-batches, and the one after it for the range delete.
+        for k in &["a", "b", "c", "d"] {
+            wb.put(k.as_bytes(), b"1").unwrap();
+        }
+        wb.delete_range(b"b", b"d").unwrap();
+        wb.put(b"e", b"1").unwrap();
+        wb.put(b"f", b"1").unwrap();
+        wb.put(b"g", b"1").unwrap();
+        assert_eq!(wb.write_plan(), vec![vec![0, 1], vec![2], vec![3]]);
+        wb.write().unwrap();
+        assert_eq!(keys(&engine), vec![b"a".to_vec(), b"d".to_vec(), b"e".to_vec(), b"f".to_vec(), b"g".to_vec()]);
+    }
+
+    #[test]
+    fn test_pipelined_write_batch_parallel() {
+        let engine = MockEngine::default();
+        let mut wb = PipelinedWriteBatch::new(&engine, config(3, false));
+        for i in 0..20u8 {
+            wb.put(&[i], b"1").unwrap();
+        }
+        // No key is written twice, so every sub-batch may be written at once.
+        assert_eq!(wb.write_plan(), vec![(0..10).collect::<Vec<_>>()]);
+        wb.write().unwrap();
+        assert_eq!(engine.state.lock().unwrap().len(), 20);
+        assert_eq!(*engine.written.lock().unwrap(), vec![2; 10]);
+    }
+
+    #[test]
+    fn test_pipelined_write_batch_failure() {
+        let engine = MockEngine {
+            fail_key: Some(b"c".to_vec()),
+            ..Default::default()
+        };
+        let mut wb = PipelinedWriteBatch::new(&engine, config(2, false));
+        for k in &["a", "b", "c", "d", "a"] {
+            wb.put(k.as_bytes(), b"1").unwrap();
+        }
+        assert_eq!(wb.write_plan(), vec![vec![0, 1], vec![2]]);
+        assert!(wb.write().is_err());
+        // The sub-batch after the failed one's group is never written.
+        assert_eq!(*engine.written.lock().unwrap(), vec![2]);
+        assert_eq!(keys(&engine), vec![b"a".to_vec(), b"b".to_vec()]);
+    }
+
+    #[test]
+    fn test_pipelined_write_batch_atomic() {
+        let engine = MockEngine::default();
+        let mut wb = PipelinedWriteBatch::new(&engine, config(4, true));
+        for k in &["a", "b", "c", "d", "e"] {
+            wb.put(k.as_bytes(), b"1").unwrap();
+        }
+        assert_eq!(wb.sub_batch_count(), 1);
+        assert_eq!(wb.write_plan(), vec![vec![0]]);
+        wb.write().unwrap();
+        assert_eq!(*engine.written.lock().unwrap(), vec![5]);
+    }
+
+    #[test]
+    fn test_pipelined_write_batch_save_points() {
+        let engine = MockEngine::default();
+        let mut wb: PipelinedWriteBatch<MockEngine> = WriteBatch::with_capacity(&engine, 0);
+        wb.put(b"a", b"1").unwrap();
+        wb.set_save_point();
+        wb.put(b"b", b"1").unwrap();
+        wb.put(b"c", b"1").unwrap();
+        wb.put(b"d", b"1").unwrap();
+        wb.rollback_to_save_point().unwrap();
+        assert_eq!(wb.count(), 1);
+        wb.put(b"e", b"1").unwrap();
+        assert!(wb.rollback_to_save_point().is_err());
+
+        let mut other = PipelinedWriteBatch::new(&engine, config(1, false));
+        other.put(b"a", b"2").unwrap();
+        wb.merge(other);
+        wb.write().unwrap();
+
+        assert_eq!(keys(&engine), vec![b"a".to_vec(), b"e".to_vec()]);
+        assert_eq!(engine.state.lock().unwrap().get(&b"a".to_vec()), Some(&b"2".to_vec()));
+    }
+    #[test]
+    fn test_pipelined_write_batch_repr() {
+        let engine = MockEngine::default();
+        let mut wb = PipelinedWriteBatch::new(&engine, config(2, false));
+        wb.put(b"a", b"1").unwrap();
+        wb.put(b"b", b"2").unwrap();
+        wb.delete(b"a").unwrap();
+        assert_eq!(wb.sub_batch_count(), 2);
+
+        let mut ops = vec![];
+        wb.iterate(|op| {
+            assert_eq!(op.namespaced(), crate::NAMESPACED_DEFAULT);
+            ops.push(format!("{:?}", op));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(ops.len(), 3);
+
+        let repr = wb.to_repr().unwrap();
+        let replayed: PipelinedWriteBatch<MockEngine> = WriteBatch::from_repr(&engine, &repr).unwrap();
+        assert_eq!(replayed.count(), 3);
+        assert_eq!(replayed.to_repr().unwrap(), repr);
+        replayed.write().unwrap();
+        let state = engine.state.lock().unwrap();
+        assert_eq!(state.keys().cloned().collect::<Vec<_>>(), vec![b"b".to_vec()]);
+        drop(state);
+
+        // Iteration stops at the first error.
+        let mut seen = 0;
+        assert!(wb
+            .iterate(|_| {
+                seen += 1;
+                Err(crate::Error::EntriesUnavailable)
+            })
+            .is_err());
+        assert_eq!(seen, 1);
+
+        // Corrupt representations are rejected.
+        assert!(crate::write_batch::iterate_repr(&repr[..repr.len() - 1], |_| Ok(())).is_err());
+        assert!(crate::write_batch::iterate_repr(&[2, 0], |_| Ok(())).is_err());
+        assert!(crate::write_batch::iterate_repr(&[], |_| Ok(())).is_err());
+    }
+}
@@ -0,0 +1,309 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Per-attribute cardinality statistics.
+//!
+//! How many causets `[?e :person/email ?x]` matches, compared with `[?e :event/kind ?k]`, isn't
+//! recorded anywhere.  `refresh_attribute_stats` counts, for every attribute, its causets, its distinct values and the
+//! average size of its values as stored, into the `attribute_stats` table.  After that the
+//! transactor keeps the table current: after materializing a transaction, it applies the causets
+//! the transaction actually asserted and retracted to the statistics of their attributes, without
+//! recounting.  Stores that never refresh their statistics don't pay for them.
+//!
+//! Causet counts and average sizes are kept exact.  Distinct values are exact for attributes with
+//! an AVET or unique value index, which can tell cheaply whether a value is still present; for
+//! other attributes they are scaled with the number of causets, and the next refresh makes them
+//! exact again.
+//!
+//! `read_attribute_stats` reads the table into `Stats`.  Nothing in the query path reads them yet:
+//! the algebrizer still orders clauses as written.  The values of fulltext attributes are stored out of line, so their sizes are those of the rowids
+//! referring to them.
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+
+use rusqlite;
+use rusqlite::types::{
+    ToBerolinaSQL,
+};
+
+use core_traits::{
+    AttributeBitFlags,
+    Causetid,
+};
+
+use einsteindb_traits::errors::{
+    Result,
+};
+
+/// Statistics about the causets of one attribute.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AttributeStats {
+    /// The number of causets of the attribute.
+    pub causets: i64,
+
+    /// The number of distinct values of the attribute.
+    pub distinct_values: i64,
+
+    /// The average size of a value, in bytes, as stored.
+    pub average_value_size: f64,
+}
+
+impl AttributeStats {
+    /// The average number of causets sharing a value: how many causets a lookup by value is
+    /// expected to match.
+    pub fn causets_per_value(&self) -> f64 {
+        if self.distinct_values == 0 {
+            0.0
+        } else {
+            self.causets as f64 / self.distinct_values as f64
+        }
+    }
+}
+
+/// Statistics about every attribute with causets.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats(pub BTreeMap<Causetid, AttributeStats>);
+
+impl Stats {
+    /// The number of causets of attribute `a`, or `None` if there are no statistics for it.
+    /// Attributes without causets have no statistics.
+    pub fn cardinality(&self, a: Causetid) -> Option<i64> {
+        self.0.get(&a).map(|stats| stats.causets)
+    }
+
+    pub fn get(&self, a: Causetid) -> Option<&AttributeStats> {
+        self.0.get(&a)
+    }
+}
+
+const COUNT_QUERY: &str = "SELECT a, count(*), count(DISTINCT v), COALESCE(avg(length(CAST(v AS BLOB))), 0.0) FROM causets";
+
+/// Statistics are kept once refreshed, and a refresh always counts the bootstrap attributes.
+fn is_refreshed(conn: &rusqlite::Connection) -> Result<bool> {
+    let refreshed: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM attribute_stats)", &[], |row| row.get(0))?;
+    Ok(refreshed)
+}
+
+/// Recount the statistics of every attribute, and have the transactor keep them current from now on.
+pub fn refresh_attribute_stats(conn: &rusqlite::Connection) -> Result<()> {
+    conn.execute("DELETE FROM attribute_stats", &[])?;
+    conn.execute(&format!("INSERT INTO attribute_stats (a, causets, distinct_values, average_value_size) {} GROUP BY a", COUNT_QUERY), &[])?;
+    Ok(())
+}
+
+/// Return the statistics of every attribute, which are empty if they were never refreshed.
+pub fn read_attribute_stats(conn: &rusqlite::Connection) -> Result<Stats> {
+    let mut stmt = conn.prepare_cached("SELECT a, causets, distinct_values, average_value_size FROM attribute_stats")?;
+    let stats: Result<BTreeMap<Causetid, AttributeStats>> = stmt.query_and_then(&[], |row| {
+        Ok((row.get_checked(0)?, AttributeStats {
+            causets: row.get_checked(1)?,
+            distinct_values: row.get_checked(2)?,
+            average_value_size: row.get_checked(3)?,
+        }))
+    })?.collect();
+    Ok(Stats(stats?))
+}
+
+/// The causets a transaction changed for one attribute.
+#[derive(Debug, Default)]
+struct Delta {
+    causets: i64,
+    size: i64,
+    /// The change in distinct values, if the attribute's index can tell.
+    distinct_values: Option<i64>,
+    /// The values with more causets than before.
+    values_added: i64,
+}
+
+/// Apply the causets the last materialized transaction asserted and retracted, as left in
+/// `temp.search_results`, to the statistics of the attributes in `touched`.  An attribute left
+/// without causets loses its statistics.
+pub(crate) fn maintain(conn: &rusqlite::Connection, touched: &BTreeMap<Causetid, BTreeSet<Causetid>>) -> Result<()> {
+    if touched.is_empty() || !is_refreshed(conn)? {
+        return Ok(());
+    }
+
+    // The same predicates `update_causets` applies: an assertion not already present, a retraction
+    // of a present causet, and the old value of a :einsteindb.cardinality/one attribute replaced.
+    // `present` is how many causets the value has now, if an index can count them cheaply.
+    let s = format!(r#"
+        SELECT a, count, size, CASE
+            WHEN flags & {avet} IS NOT 0 THEN
+                (SELECT count(*) FROM causets AS d
+                 WHERE d.a = c.a AND d.value_type_tag = c.value_type_tag AND d.v = c.v AND d.index_avet IS NOT 0)
+            WHEN flags & {unique} IS NOT 0 THEN
+                (SELECT count(*) FROM causets AS d
+                 WHERE d.a = c.a AND d.value_type_tag = c.value_type_tag AND d.v = c.v AND d.unique_value IS NOT 0)
+            ELSE NULL END AS present
+        FROM (SELECT a, value_type_tag, v, sum(added) AS count, sum(added * length(CAST(v AS BLOB))) AS size, max(flags) AS flags
+              FROM (SELECT a0 AS a, value_type_tag0 AS value_type_tag, v0 AS v, 1 AS added, flags0 AS flags
+                    FROM temp.search_results
+                    WHERE added0 IS 1 AND ((rid IS NULL) OR ((rid IS NOT NULL) AND (v0 IS NOT v)))
+                    UNION ALL
+                    SELECT a0, value_type_tag0, v, -1, flags0
+                    FROM temp.search_results
+                    WHERE rid IS NOT NULL AND
+                          ((added0 IS 0) OR
+                           (added0 IS 1 AND search_type IS ':einsteindb.cardinality/one' AND v0 IS NOT v)))
+              GROUP BY a, value_type_tag, v) AS c"#,
+        avet = AttributeBitFlags::IndexAVET as u8,
+        unique = AttributeBitFlags::UniqueValue as u8);
+
+    let mut deltas: BTreeMap<Causetid, Delta> = BTreeMap::default();
+    {
+        let mut stmt = conn.prepare_cached(&s)?;
+        let mut rows = stmt.query(&[])?;
+        while let Some(row) = rows.next() {
+            let row = row?;
+            let a: Causetid = row.get_checked(0)?;
+            let count: i64 = row.get_checked(1)?;
+            let size: i64 = row.get_checked(2)?;
+            let present: Option<i64> = row.get_checked(3)?;
+            if !touched.contains_key(&a) || count == 0 {
+                continue;
+            }
+            let delta = deltas.entry(a).or_insert_with(|| Delta {
+                distinct_values: present.map(|_| 0),
+                ..Delta::default()
+            });
+            delta.causets += count;
+            delta.size += size;
+            if count > 0 {
+                delta.values_added += 1;
+            }
+            if let (Some(distinct_values), Some(present)) = (delta.distinct_values.as_mut(), present) {
+                let before = present - count;
+                *distinct_values += (present > 0) as i64 - (before > 0) as i64;
+            }
+        }
+    }
+
+    let mut read = conn.prepare_cached("SELECT causets, distinct_values, average_value_size FROM attribute_stats WHERE a = ?")?;
+    let mut delete = conn.prepare_cached("DELETE FROM attribute_stats WHERE a = ?")?;
+    let mut write = conn.prepare_cached("INSERT OR REPLACE INTO attribute_stats (a, causets, distinct_values, average_value_size) VALUES (?, ?, ?, ?)")?;
+    for (a, delta) in deltas {
+        let (causets, distinct_values, average_value_size): (i64, i64, f64) = {
+            let mut rows = read.query(&[&a])?;
+            match rows.next() {
+                Some(row) => {
+                    let row = row?;
+                    (row.get_checked(0)?, row.get_checked(1)?, row.get_checked(2)?)
+                },
+                None => (0, 0, 0.0),
+            }
+        };
+
+        let new_causets = causets + delta.causets;
+        if new_causets <= 0 {
+            delete.execute(&[&a])?;
+            continue;
+        }
+        let new_distinct_values = match delta.distinct_values {
+            Some(change) => distinct_values + change,
+            None if causets == 0 => delta.values_added,
+            None => (distinct_values as f64 * new_causets as f64 / causets as f64).round() as i64,
+        };
+        let new_distinct_values = new_distinct_values.max(1).min(new_causets);
+        let new_average_value_size = ((average_value_size * causets as f64 + delta.size as f64) / new_causets as f64).max(0.0);
+        write.execute(&[&a as &ToBerolinaSQL, &new_causets, &new_distinct_values, &new_average_value_size])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use edn::{
+        Keyword,
+    };
+
+    use einsteindb_core::{
+        HasTopograph,
+    };
+
+    use debug::TestConn;
+
+    #[test]
+    fn test_attribute_stats() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[{:einsteindb/solitonid :test/kind
+                           :einsteindb/valueType :einsteindb.type/string
+                           :einsteindb/cardinality :einsteindb.cardinality/one
+                           :einsteindb/index true}
+                          {:einsteindb/solitonid :test/tag
+                           :einsteindb/valueType :einsteindb.type/string
+                           :einsteindb/cardinality :einsteindb.cardinality/many}]"#).expect("transacted topograph");
+        conn.transact(r#"[[:einsteindb/add 100 :test/kind "ab"]
+                          [:einsteindb/add 101 :test/kind "ab"]
+                          [:einsteindb/add 102 :test/kind "abcd"]]"#).expect("transacted");
+        let kind = conn.topograph.get_causetid(&Keyword::isoliton_namespaceable("test", "kind")).expect("kind").0;
+
+        // Nothing until refreshed.
+        assert_eq!(read_attribute_stats(&conn.SQLite).expect("stats"), Stats::default());
+        conn.transact(r#"[[:einsteindb/add 103 :test/kind "ab"]]"#).expect("transacted");
+        assert!(!is_refreshed(&conn.SQLite).expect("refreshed"));
+
+        refresh_attribute_stats(&conn.SQLite).expect("refreshed");
+        let stats = read_attribute_stats(&conn.SQLite).expect("stats");
+        assert_eq!(stats.cardinality(kind), Some(4));
+        assert_eq!(stats.get(kind), Some(&AttributeStats { causets: 4, distinct_values: 2, average_value_size: 2.5 }));
+        assert_eq!(stats.get(kind).unwrap().causets_per_value(), 2.0);
+
+        // Kept current by the transactor, from the causets each transaction changed.
+        conn.transact(r#"[[:einsteindb/add 104 :test/kind "xyz"]
+                          [:einsteindb/retract 100 :test/kind "ab"]]"#).expect("transacted");
+        let stats = read_attribute_stats(&conn.SQLite).expect("stats");
+        assert_eq!(stats.get(kind), Some(&AttributeStats { causets: 4, distinct_values: 3, average_value_size: 2.75 }));
+
+        // Redundant assertions and retractions of absent causets change nothing; replacing a
+        // :einsteindb.cardinality/one value retracts the old one.
+        conn.transact(r#"[[:einsteindb/add 104 :test/kind "xyz"]
+                          [:einsteindb/retract 100 :test/kind "ab"]
+                          [:einsteindb/add 101 :test/kind "abcd"]]"#).expect("transacted");
+        let stats = read_attribute_stats(&conn.SQLite).expect("stats");
+        assert_eq!(stats.get(kind), Some(&AttributeStats { causets: 4, distinct_values: 3, average_value_size: 3.25 }));
+        refresh_attribute_stats(&conn.SQLite).expect("refreshed");
+        assert_eq!(read_attribute_stats(&conn.SQLite).expect("stats").get(kind), stats.get(kind));
+
+        // Without an index to consult, distinct values are scaled with the causets.
+        let tag = conn.topograph.get_causetid(&Keyword::isoliton_namespaceable("test", "tag")).expect("tag").0;
+        conn.transact(r#"[[:einsteindb/add 100 :test/tag "a"]
+                          [:einsteindb/add 100 :test/tag "b"]]"#).expect("transacted");
+        assert_eq!(read_attribute_stats(&conn.SQLite).expect("stats").get(tag),
+                   Some(&AttributeStats { causets: 2, distinct_values: 2, average_value_size: 1.0 }));
+        conn.transact(r#"[[:einsteindb/add 101 :test/tag "a"]
+                          [:einsteindb/add 101 :test/tag "b"]]"#).expect("transacted");
+        assert_eq!(read_attribute_stats(&conn.SQLite).expect("stats").get(tag),
+                   Some(&AttributeStats { causets: 4, distinct_values: 4, average_value_size: 1.0 }));
+        refresh_attribute_stats(&conn.SQLite).expect("refreshed");
+        assert_eq!(read_attribute_stats(&conn.SQLite).expect("stats").get(tag),
+                   Some(&AttributeStats { causets: 4, distinct_values: 2, average_value_size: 1.0 }));
+        conn.transact(r#"[[:einsteindb/retract 100 :test/tag "a"]
+                          [:einsteindb/retract 100 :test/tag "b"]
+                          [:einsteindb/retract 101 :test/tag "a"]
+                          [:einsteindb/retract 101 :test/tag "b"]]"#).expect("transacted");
+        assert_eq!(read_attribute_stats(&conn.SQLite).expect("stats").cardinality(tag), None);
+
+        conn.transact(r#"[[:einsteindb/retract 101 :test/kind "abcd"]
+                          [:einsteindb/retract 102 :test/kind "abcd"]
+                          [:einsteindb/retract 103 :test/kind "ab"]
+                          [:einsteindb/retract 104 :test/kind "xyz"]]"#).expect("transacted");
+        assert_eq!(read_attribute_stats(&conn.SQLite).expect("stats").cardinality(kind), None);
+
+        // Refreshing covers every attribute, including the bootstrap ones.
+        refresh_attribute_stats(&conn.SQLite).expect("refreshed");
+        let solitonid = conn.topograph.get_causetid(&Keyword::isoliton_namespaceable("einsteindb", "solitonid")).expect("solitonid").0;
+        assert!(read_attribute_stats(&conn.SQLite).expect("stats").cardinality(solitonid).unwrap() > 0);
+    }
+}
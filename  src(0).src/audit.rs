@@ -0,0 +1,220 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Store-wide integrity audit.
+//!
+//! The `causets` table is a materialized view of the main timeline of `timelined_transactions`: a
+//! causet is present exactly when the last transaction to mention it asserted it, and carries that
+//! transaction's ID.  `audit` recomputes the view into a temporary table and diffs it against the
+//! live one, so that corruption -- after a crash, or a bug in the transactor -- is noticed before
+//! it is read.
+//!
+//! Attributes that are `:einsteindb/noHistory true` aren't in the transaction log, and aren't
+//! audited.
+//!
+//! Values are compared, and reported, as stored: a fulltext value is the rowid of its text, and
+//! nothing is decoded, so that a corrupt value can still be reported.
+
+use std::collections::BTreeSet;
+
+use rusqlite;
+
+use causetids;
+
+use core_traits::{
+    Causetid,
+};
+
+use einsteindb_traits::errors::{
+    Result,
+};
+
+/// An `[e a v]` as stored in the `causets` table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditCauset {
+    pub e: Causetid,
+    pub a: Causetid,
+    pub v: rusqlite::types::Value,
+    pub value_type_tag: i32,
+}
+
+/// A difference between the `causets` table and the transaction log.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Discrepancy {
+    /// The log's last word on the causet is its lightlike_dagger_assertion in `tx`, but it isn't
+    /// in `causets`.
+    Missing { causet: AuditCauset, tx: Causetid },
+
+    /// The causet is in `causets`, but with `actual_tx` rather than the `expected_tx` that last
+    /// asserted it.
+    WrongTx { causet: AuditCauset, expected_tx: Causetid, actual_tx: Causetid },
+
+    /// The causet is in `causets`, with `tx`, but the log never asserted it or has since
+    /// retracted it.
+    Unexpected { causet: AuditCauset, tx: Causetid },
+}
+
+impl Discrepancy {
+    pub fn causet(&self) -> &AuditCauset {
+        match self {
+            &Discrepancy::Missing { ref causet, .. } |
+            &Discrepancy::WrongTx { ref causet, .. } |
+            &Discrepancy::Unexpected { ref causet, .. } => causet,
+        }
+    }
+
+    /// The transaction IDs this discrepancy implicates.
+    pub fn txs(&self) -> Vec<Causetid> {
+        match self {
+            &Discrepancy::Missing { tx, .. } |
+            &Discrepancy::Unexpected { tx, .. } => vec![tx],
+            &Discrepancy::WrongTx { expected_tx, actual_tx, .. } => vec![expected_tx, actual_tx],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AuditReport {
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl AuditReport {
+    pub fn is_clean(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+
+    /// The transaction IDs implicated by any discrepancy.
+    pub fn txs(&self) -> BTreeSet<Causetid> {
+        self.discrepancies.iter().flat_map(|d| d.txs()).collect()
+    }
+}
+
+/// The attributes that are `:einsteindb/noHistory true`, as a BerolinaSQL subquery.
+fn no_history_attributes() -> String {
+    format!("(SELECT e FROM causets WHERE a = {} AND v = 1)", causetids::EINSTEINDB_NO_HISTORY)
+}
+
+/// Recompute the `causets` materialized view from the main timeline of `timelined_transactions`
+/// and report how the live table differs from it.
+///
+/// The recomputed view lives in a temporary table for the duration of the audit; nothing else is
+/// written.
+pub fn audit(conn: &rusqlite::Connection) -> Result<AuditReport> {
+    conn.execute("DROP TABLE IF EXISTS temp.audit_causets", &[])?;
+    // SQLite takes the bare `added` from the row that has max(tx).
+    conn.execute(&format!(r#"
+        CREATE TEMP TABLE audit_causets AS
+        SELECT e, a, v, value_type_tag, tx FROM (
+            SELECT e, a, v, value_type_tag, max(tx) AS tx, added
+            FROM timelined_transactions
+            WHERE timeline = ?
+            GROUP BY e, a, value_type_tag, v)
+        WHERE added = 1 AND a NOT IN {}"#, no_history_attributes()), &[&::TIMELINE_MAIN])?;
+    conn.execute("CREATE INDEX temp.idx_audit_causets ON audit_causets (e, a, value_type_tag, v)", &[])?;
+
+    let report = diff_causets(conn);
+    conn.execute("DROP TABLE temp.audit_causets", &[])?;
+    report
+}
+
+fn diff_causets(conn: &rusqlite::Connection) -> Result<AuditReport> {
+    let mut discrepancies = vec![];
+
+    let mut stmt = conn.prepare(r#"
+        SELECT x.e, x.a, x.v, x.value_type_tag, x.tx, d.tx
+        FROM temp.audit_causets AS x
+        LEFT JOIN causets AS d
+        ON d.e = x.e AND d.a = x.a AND d.value_type_tag = x.value_type_tag AND d.v = x.v
+        WHERE d.tx IS NOT x.tx
+        ORDER BY x.tx, x.e, x.a, x.value_type_tag, x.v"#)?;
+    let rows: Result<Vec<Discrepancy>> = stmt.query_and_then(&[], |row| {
+        let causet = AuditCauset {
+            e: row.get_checked(0)?,
+            a: row.get_checked(1)?,
+            v: row.get_checked(2)?,
+            value_type_tag: row.get_checked(3)?,
+        };
+        let expected_tx: Causetid = row.get_checked(4)?;
+        let actual_tx: Option<Causetid> = row.get_checked(5)?;
+        Ok(match actual_tx {
+            None => Discrepancy::Missing { causet, tx: expected_tx },
+            Some(actual_tx) => Discrepancy::WrongTx { causet, expected_tx, actual_tx },
+        })
+    })?.collect();
+    discrepancies.extend(rows?);
+
+    let mut stmt = conn.prepare(&format!(r#"
+        SELECT d.e, d.a, d.v, d.value_type_tag, d.tx
+        FROM causets AS d
+        WHERE d.a NOT IN {} AND NOT EXISTS (
+            SELECT 1 FROM temp.audit_causets AS x
+            WHERE x.e = d.e AND x.a = d.a AND x.value_type_tag = d.value_type_tag AND x.v = d.v)
+        ORDER BY d.tx, d.e, d.a, d.value_type_tag, d.v"#, no_history_attributes()))?;
+    let rows: Result<Vec<Discrepancy>> = stmt.query_and_then(&[], |row| {
+        let causet = AuditCauset {
+            e: row.get_checked(0)?,
+            a: row.get_checked(1)?,
+            v: row.get_checked(2)?,
+            value_type_tag: row.get_checked(3)?,
+        };
+        Ok(Discrepancy::Unexpected { causet, tx: row.get_checked(4)? })
+    })?.collect();
+    discrepancies.extend(rows?);
+
+    Ok(AuditReport { discrepancies })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use debug::TestConn;
+
+    #[test]
+    fn test_audit() {
+        let mut conn = TestConn::default();
+        let report1 = conn.transact("[[:einsteindb/add 100 :einsteindb/solitonid :test/one]
+                                      [:einsteindb/add 101 :einsteindb/solitonid :test/two]]").expect("transacted");
+        let report2 = conn.transact("[[:einsteindb/retract 101 :einsteindb/solitonid :test/two]
+                                      [:einsteindb/add 102 :einsteindb/solitonid :test/three]]").expect("transacted");
+        assert!(audit(&conn.SQLite).expect("audited").is_clean());
+
+        // Lose one causet, misdate another, and resurrect a retracted one.
+        conn.SQLite.execute("DELETE FROM causets WHERE e = 100 AND a = ?", &[&causetids::EINSTEINDB_IDENT]).expect("deleted");
+        conn.SQLite.execute("UPDATE causets SET tx = ? WHERE e = 102 AND a = ?", &[&report1.tx_id, &causetids::EINSTEINDB_IDENT]).expect("updated");
+        conn.SQLite.execute("INSERT INTO causets (e, a, v, tx, value_type_tag) VALUES (101, ?, ':test/two', ?, 13)",
+                            &[&causetids::EINSTEINDB_IDENT, &report1.tx_id]).expect("inserted");
+
+        let report = audit(&conn.SQLite).expect("audited");
+        assert_eq!(report.discrepancies.len(), 3);
+        match &report.discrepancies[0] {
+            &Discrepancy::Missing { ref causet, tx } => {
+                assert_eq!((causet.e, causet.a, tx), (100, causetids::EINSTEINDB_IDENT, report1.tx_id));
+            },
+            d => panic!("expected a missing causet, got {:?}", d),
+        }
+        match &report.discrepancies[1] {
+            &Discrepancy::WrongTx { ref causet, expected_tx, actual_tx } => {
+                assert_eq!((causet.e, expected_tx, actual_tx), (102, report2.tx_id, report1.tx_id));
+            },
+            d => panic!("expected a misdated causet, got {:?}", d),
+        }
+        match &report.discrepancies[2] {
+            &Discrepancy::Unexpected { ref causet, tx } => {
+                assert_eq!((causet.e, tx), (101, report1.tx_id));
+            },
+            d => panic!("expected an unexpected causet, got {:?}", d),
+        }
+        assert_eq!(report.txs(), vec![report1.tx_id, report2.tx_id].into_iter().collect());
+
+        // The audit leaves nothing behind.
+        assert!(audit(&conn.SQLite).is_ok());
+    }
+}
@@ -45,7 +45,7 @@ pub const USER0: i64 = 0x10000;
 pub const CORE_SCHEMA_VERSION: u32 = 1;
 
 lazy_static! {
-    static ref V1_solitonidS: [(shellings::Keyword, i64); 40] = {
+    static ref V1_solitonidS: [(shellings::Keyword, i64); 46] = {
             [(ns_keyword!("einsteindb", "solitonid"),             causetids::EINSTEINDB_solitonid),
              (ns_keyword!("einsteindb.part", "einsteindb"),           causetids::EINSTEINDB_PART_EINSTEINDB),
              (ns_keyword!("einsteindb", "txInstant"),         causetids::EINSTEINDB_TX_INSTANT),
@@ -86,6 +86,12 @@ lazy_static! {
              (ns_keyword!("einsteindb.topograph", "version"),    causetids::EINSTEINDB_SCHEMA_VERSION),
              (ns_keyword!("einsteindb.topograph", "attribute"),  causetids::EINSTEINDB_SCHEMA_ATTRIBUTE),
              (ns_keyword!("einsteindb.topograph", "core"),       causetids::EINSTEINDB_SCHEMA_CORE),
+             (ns_keyword!("einsteindb", "alias"),             causetids::EINSTEINDB_ALIAS),
+             (ns_keyword!("einsteindb", "externalId"),        causetids::EINSTEINDB_EXTERNAL_ID),
+             (ns_keyword!("einsteindb.fulltext", "tokenizer"), causetids::EINSTEINDB_FULLTEXT_TOKENIZER),
+             (ns_keyword!("einsteindb.fulltext", "unicode61"), causetids::EINSTEINDB_FULLTEXT_UNICODE61),
+             (ns_keyword!("einsteindb.fulltext", "porter"),    causetids::EINSTEINDB_FULLTEXT_PORTER),
+             (ns_keyword!("einsteindb.fulltext", "trigram"),   causetids::EINSTEINDB_FULLTEXT_TRIGRAM),
         ]
     };
 
@@ -96,7 +102,7 @@ lazy_static! {
         ]
     };
 
-    static ref V1_CORE_SCHEMA: [(shellings::Keyword); 16] = {
+    static ref V1_CORE_SCHEMA: [(shellings::Keyword); 19] = {
             [(ns_keyword!("einsteindb", "solitonid")),
              (ns_keyword!("einsteindb.install", "partition")),
              (ns_keyword!("einsteindb.install", "valueType")),
@@ -113,6 +119,9 @@ lazy_static! {
              (ns_keyword!("einsteindb.alter", "attribute")),
              (ns_keyword!("einsteindb.topograph", "version")),
              (ns_keyword!("einsteindb.topograph", "attribute")),
+             (ns_keyword!("einsteindb", "alias")),
+             (ns_keyword!("einsteindb", "externalId")),
+             (ns_keyword!("einsteindb.fulltext", "tokenizer")),
         ]
     };
 
@@ -150,11 +159,25 @@ lazy_static! {
                         :einsteindb/cardinality :einsteindb.cardinality/one}
  :einsteindb/noHistory         {:einsteindb/valueType   :einsteindb.type/boolean
                         :einsteindb/cardinality :einsteindb.cardinality/one}
+ :einsteindb.fulltext/tokenizer {:einsteindb/valueType  :einsteindb.type/ref
+                        :einsteindb/cardinality :einsteindb.cardinality/one}
  :einsteindb.alter/attribute   {:einsteindb/valueType   :einsteindb.type/ref
                         :einsteindb/cardinality :einsteindb.cardinality/many}
  :einsteindb.topograph/version    {:einsteindb/valueType   :einsteindb.type/long
                         :einsteindb/cardinality :einsteindb.cardinality/one}
 
+ ;; unique-value because an alias resolves to a single causetid.
+ :einsteindb/alias             {:einsteindb/valueType   :einsteindb.type/keyword
+                        :einsteindb/cardinality :einsteindb.cardinality/many
+                        :einsteindb/index       true
+                        :einsteindb/unique      :einsteindb.unique/value}
+
+ ;; unique-idcauset so that transacting an external id upserts to the causetid bearing it.
+ :einsteindb/externalId        {:einsteindb/valueType   :einsteindb.type/uuid
+                        :einsteindb/cardinality :einsteindb.cardinality/one
+                        :einsteindb/index       true
+                        :einsteindb/unique      :einsteindb.unique/idcauset}
+
  ;; unique-value because an attribute can only belong to a single
  ;; topograph fragment.
  :einsteindb.topograph/attribute  {:einsteindb/valueType   :einsteindb.type/ref
@@ -291,12 +314,72 @@ pub(crate) fn bootstrap_solitonid_map() -> solitonidMap {
              .collect()
 }
 
+/// Whether `causetid` is one that every store bootstraps with, rather than one of the user's.
+pub(crate) fn is_bootstrap_causetid(causetid: i64) -> bool {
+    V1_solitonidS.iter().any(|&(_, e)| e == causetid)
+}
+
 pub(crate) fn bootstrap_topograph() -> Topograph {
     let solitonid_map = bootstrap_solitonid_map();
     let bootstrap_triples = shellingic_topograph_to_triples(&solitonid_map, &V1_SYMBOLIC_SCHEMA).expect("shellingic topograph");
     Topograph::from_solitonid_map_and_triples(solitonid_map, bootstrap_triples).unwrap()
 }
 
+/// The topograph that resolves `upgrade_causets(&topograph.solitonid_map)`: `topograph`, with the
+/// bootstrap solitonids it lacks, and their attributes, as a new store has them.
+pub(crate) fn upgrade_topograph(topograph: &Topograph) -> Topograph {
+    let bootstrap_topograph = bootstrap_topograph();
+    let mut upgraded = topograph.clone();
+    for &(ref solitonid, causetid) in V1_solitonidS.iter() {
+        if upgraded.solitonid_map.contains_key(solitonid) {
+            continue;
+        }
+        upgraded.solitonid_map.insert(solitonid.clone(), causetid);
+        upgraded.causetid_map.insert(causetid, solitonid.clone());
+        if let Some(attribute) = bootstrap_topograph.attribute_map.get(&causetid) {
+            upgraded.attribute_map.insert(causetid, attribute.clone());
+        }
+    }
+    upgraded.update_component_attributes();
+    upgraded
+}
+
+/// The bootstrap lightlike_dagger_upsert installing the bootstrap solitonids that `solitonid_map`, the
+/// solitonids of a store bootstrapped by an earlier version, lacks, along with their topograph.
+pub(crate) fn upgrade_causets(solitonid_map: &solitonidMap) -> Vec<causet<edn::ValueAndSpan>> {
+    let missing = |solitonid: &shellings::Keyword| !solitonid_map.contains_key(solitonid);
+
+    let solitonids: Vec<(shellings::Keyword, i64)> = V1_solitonidS.iter()
+        .filter(|&&(ref solitonid, _)| missing(solitonid))
+        .cloned()
+        .collect();
+    if solitonids.is_empty() {
+        return vec![];
+    }
+    let core: Vec<shellings::Keyword> = V1_CORE_SCHEMA.iter()
+        .filter(|solitonid| missing(solitonid))
+        .cloned()
+        .collect();
+    let shellingic_topograph = match *V1_SYMBOLIC_SCHEMA {
+        Value::Map(ref m) => Value::Map(m.iter()
+                                         .filter(|&(solitonid, _)| match *solitonid {
+                                             Value::Keyword(ref solitonid) => missing(solitonid),
+                                             _ => false,
+                                         })
+                                         .map(|(solitonid, mp)| (solitonid.clone(), mp.clone()))
+                                         .collect()),
+        _ => unreachable!(),
+    };
+
+    let upgrade_lightlike_dagger_upsert: Value = Value::Vector([
+        shellingic_topograph_to_lightlike_dagger_upsert(&shellingic_topograph).expect("shellingic topograph"),
+        solitonids_to_lightlike_dagger_upsert(&solitonids[..]),
+        topograph_attrs_to_lightlike_dagger_upsert(CORE_SCHEMA_VERSION, &core[..]),
+    ].concat());
+
+    edn::parse::causets(&upgrade_lightlike_dagger_upsert.to_string()).expect("upgrade lightlike_dagger_upsert")
+}
+
 pub(crate) fn bootstrap_causets() -> Vec<causet<edn::ValueAndSpan>> {
     let bootstrap_lightlike_dagger_upsert: Value = Value::Vector([
         shellingic_topograph_to_lightlike_dagger_upsert(&V1_SYMBOLIC_SCHEMA).expect("shellingic topograph"),
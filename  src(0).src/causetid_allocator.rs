@@ -0,0 +1,309 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Pluggable allocation of fresh causetids.
+//!
+//! The partition map allocates causetids locally, so two stores allocating in the same partition
+//! allocate the same causetids.  That's fine for a store on its own, but not for stores whose
+//! causets end up side by side.  An `IdAllocator` decides where a partition's fresh causetids come
+//! from, and `IdAllocators` selects one per partition, leaving the others to the partition map;
+//! see `transact_with_allocators`.
+//!
+//! `LeasedIdAllocator` leases blocks of causetids using a global id source, like FIDel's
+//! `alloc_id`: the `k`th id maps to the `k`th block of `lease_size` causetids of the partition.
+//! Global ids are never handed out twice, so neither are blocks, and stores leasing from the same
+//! source never collide.  A store that has allocated locally shouldn't switch to leasing in that
+//! partition, since its local causetids may lie in the blocks of others; blocks below the
+//! partition's local allocations are skipped, but that can't protect other stores.
+//!
+//! Transaction ids always come from the partition map: readers of the transaction log, like
+//! `Conn::last_tx_id` and change data capture, rely on them being allocated in order, without
+//! holes.  `IdAllocators` refuses to allocate in `:einsteindb.part/tx`.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::{
+    Arc,
+    Mutex,
+};
+
+use core_traits::{
+    Causetid,
+};
+
+use einsteindb_traits::errors::{
+    einsteindbErrorKind,
+    Result,
+};
+
+use types::{
+    Partition,
+    PartitionMap,
+};
+
+/// The partition transaction ids are allocated in.
+const TX_PART: &str = ":einsteindb.part/tx";
+
+/// Where the fresh causetids of a partition come from.
+pub trait IdAllocator: Send + Sync {
+    /// Allocate `n` fresh causetids in `partition`, named `part`, advancing `partition` past them.
+    fn allocate(&self, part: &str, partition: &mut Partition, n: usize) -> Result<Vec<Causetid>>;
+}
+
+/// Allocation from the partition map, as without an allocator.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LocalIdAllocator;
+
+impl IdAllocator for LocalIdAllocator {
+    fn allocate(&self, _part: &str, partition: &mut Partition, n: usize) -> Result<Vec<Causetid>> {
+        Ok(partition.allocate_causetids(n).collect())
+    }
+}
+
+/// A source of ids unique across every store using it, like FIDel's `alloc_id`.
+pub trait GlobalIdSource: Send + Sync {
+    fn alloc_id(&self) -> Result<u64>;
+}
+
+impl<F> GlobalIdSource for F where F: Fn() -> Result<u64> + Send + Sync {
+    fn alloc_id(&self) -> Result<u64> {
+        self()
+    }
+}
+
+/// Allocation from blocks of causetids leased with a `GlobalIdSource`.
+pub struct LeasedIdAllocator<S> where S: GlobalIdSource {
+    source: S,
+    lease_size: i64,
+
+    /// The rest of the current lease, by partition.
+    leases: Mutex<BTreeMap<String, Range<Causetid>>>,
+}
+
+impl<S> LeasedIdAllocator<S> where S: GlobalIdSource {
+    pub fn new(source: S, lease_size: usize) -> LeasedIdAllocator<S> {
+        LeasedIdAllocator {
+            source,
+            lease_size: lease_size.max(1) as i64,
+            leases: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Lease the next block of `partition` that lies past its local allocations.
+    fn lease(&self, part: &str, partition: &Partition) -> Result<Range<Causetid>> {
+        loop {
+            let k = self.source.alloc_id()? as i64;
+            let start = k.checked_mul(self.lease_size).and_then(|offset| offset.checked_add(partition.start));
+            let start = match start {
+                Some(start) if start < partition.end => start,
+                _ => bail!(einsteindbErrorKind::InputError(format!("partition {} has no block of {} causetids for lease {}", part, self.lease_size, k))),
+            };
+            let end = (start + self.lease_size).min(partition.end);
+            if end > partition.next_causetid() {
+                return Ok(start.max(partition.next_causetid())..end);
+            }
+        }
+    }
+}
+
+impl<S> IdAllocator for LeasedIdAllocator<S> where S: GlobalIdSource {
+    fn allocate(&self, part: &str, partition: &mut Partition, n: usize) -> Result<Vec<Causetid>> {
+        let mut leases = self.leases.lock().unwrap();
+        let mut causetids = Vec::with_capacity(n);
+        while causetids.len() < n {
+            // The partition map may have allocated locally since the lease was taken, from
+            // `transact` without allocators say: skip what it has handed out.
+            let next = partition.next_causetid();
+            let lease = match leases.remove(part) {
+                Some(ref lease) if lease.start.max(next) < lease.end => lease.start.max(next)..lease.end,
+                _ => self.lease(part, partition)?,
+            };
+            let take = (n - causetids.len()).min((lease.end - lease.start) as usize) as i64;
+            causetids.extend(lease.start..lease.start + take);
+            leases.insert(part.to_string(), lease.start + take..lease.end);
+        }
+        // Keep the partition's high water mark past everything leased, so that the causetids are
+        // known to be allocated.
+        if let Some(&last) = causetids.last() {
+            if last >= partition.next_causetid() {
+                partition.set_next_causetid(last + 1);
+            }
+        }
+        Ok(causetids)
+    }
+}
+
+/// The `IdAllocator` of each partition.  Partitions without one allocate from the partition map.
+#[derive(Clone, Default)]
+pub struct IdAllocators {
+    allocators: BTreeMap<String, Arc<dyn IdAllocator>>,
+}
+
+impl IdAllocators {
+    pub fn new() -> IdAllocators {
+        IdAllocators::default()
+    }
+
+    /// Allocate the fresh causetids of `part` with `allocator`.
+    pub fn with_allocator<P>(mut self, part: P, allocator: Arc<dyn IdAllocator>) -> IdAllocators where P: Into<String> {
+        self.allocators.insert(part.into(), allocator);
+        self
+    }
+
+    /// Allocate `n` fresh causetids in `part` of `partition_map`.  Fails for the transaction
+    /// partition, whose ids only the partition map allocates.
+    pub fn allocate(&self, partition_map: &mut PartitionMap, part: &str, n: usize) -> Result<Vec<Causetid>> {
+        if part == TX_PART {
+            bail!(einsteindbErrorKind::InputError(format!("cannot allocate transaction ids with an allocator: {}", part)));
+        }
+        let partition = partition_map.get_mut(part)
+            .ok_or_else(|| einsteindbErrorKind::InputError(format!("cannot allocate causetid from unknown partition: {}", part)))?;
+        match self.allocators.get(part) {
+            Some(allocator) => allocator.allocate(part, partition, n),
+            None => LocalIdAllocator.allocate(part, partition, n),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    };
+
+    use rusqlite::TransactionBehavior;
+
+    use edn;
+
+    use bootstrap::USER0;
+    use debug::TestConn;
+    use tx::transact_with_allocators;
+    use watcher::NullWatcher;
+
+    const USER: &str = ":einsteindb.part/user";
+
+    #[test]
+    fn test_leased_allocation() {
+        let ids = Arc::new(AtomicUsize::new(0));
+        let source = {
+            let ids = ids.clone();
+            move || -> Result<u64> { Ok(ids.fetch_add(1, Ordering::SeqCst) as u64) }
+        };
+        let allocators = IdAllocators::new().with_allocator(USER, Arc::new(LeasedIdAllocator::new(source, 10)));
+
+        // Two stores sharing a source lease disjoint blocks.
+        let mut a = TestConn::default().partition_map;
+        let mut b = a.clone();
+        assert_eq!(allocators.allocate(&mut a, USER, 3).expect("allocated"), vec![USER0, USER0 + 1, USER0 + 2]);
+        let other = IdAllocators::new().with_allocator(USER, Arc::new(LeasedIdAllocator::new({
+            let ids = ids.clone();
+            move || -> Result<u64> { Ok(ids.fetch_add(1, Ordering::SeqCst) as u64) }
+        }, 10)));
+        assert_eq!(other.allocate(&mut b, USER, 2).expect("allocated"), vec![USER0 + 10, USER0 + 11]);
+
+        // The rest of the lease, then a new one.
+        let allocated = allocators.allocate(&mut a, USER, 9).expect("allocated");
+        assert_eq!(allocated[..7].to_vec(), (USER0 + 3..USER0 + 10).collect::<Vec<_>>());
+        assert_eq!(allocated[7..].to_vec(), vec![USER0 + 20, USER0 + 21]);
+        assert_eq!(a[USER].next_causetid(), USER0 + 22);
+        assert!(a.contains_causetid(USER0 + 21));
+
+        // Other partitions still allocate from the partition map.
+        let causetid = a[":einsteindb.part/einsteindb"].next_causetid();
+        assert_eq!(allocators.allocate(&mut a, ":einsteindb.part/einsteindb", 1).expect("allocated"), vec![causetid]);
+        assert!(allocators.allocate(&mut a, ":einsteindb.part/unknown", 1).is_err());
+    }
+
+    #[test]
+    fn test_tx_partition_rejected() {
+        let leased = LeasedIdAllocator::new(move || -> Result<u64> { Ok(1) }, 10);
+        let allocators = IdAllocators::new().with_allocator(TX_PART, Arc::new(leased));
+        let mut partition_map = TestConn::default().partition_map;
+        let next = partition_map[TX_PART].next_causetid();
+        assert!(allocators.allocate(&mut partition_map, TX_PART, 1).is_err());
+        assert!(IdAllocators::new().allocate(&mut partition_map, TX_PART, 1).is_err());
+        assert_eq!(partition_map[TX_PART].next_causetid(), next);
+    }
+
+    #[test]
+    fn test_transact_with_allocators() {
+        let mut conn = TestConn::default();
+        let source = move || -> Result<u64> { Ok(3) };
+        let allocators = IdAllocators::new().with_allocator(USER, Arc::new(LeasedIdAllocator::new(source, 100)));
+
+        let causets = edn::parse::causets(r#"[[:einsteindb/add "a" :einsteindb.topograph/version 1]]"#).expect("parsed");
+        let tx = conn.SQLite.transaction_with_behavior(TransactionBehavior::Immediate).expect("began");
+        let (report, partition_map, _, _) = transact_with_allocators(&tx, conn.partition_map.clone(), &conn.topograph, &conn.topograph, NullWatcher(), causets, &allocators).expect("transacted");
+        tx.commit().expect("committed");
+
+        assert_eq!(report.tempids["a"], USER0 + 300);
+        assert_eq!(partition_map[USER].next_causetid(), USER0 + 301);
+        // The transaction's own causetid comes from the partition map.
+        assert_eq!(report.tx_id, conn.partition_map[":einsteindb.part/tx"].next_causetid());
+    }
+
+    #[test]
+    fn test_leases_skip_interleaved_local_allocations() {
+        let mut conn = TestConn::default();
+        let ids = Mutex::new(0..);
+        let source = move || -> Result<u64> { Ok(ids.lock().unwrap().next().unwrap()) };
+        let allocators = IdAllocators::new().with_allocator(USER, Arc::new(LeasedIdAllocator::new(source, 10)));
+
+        let transact_leased = |conn: &mut TestConn, tempid: &str| -> Causetid {
+            let causets = edn::parse::causets(&format!(r#"[[:einsteindb/add "{}" :einsteindb.topograph/version 1]]"#, tempid)).expect("parsed");
+            let tx = conn.SQLite.transaction_with_behavior(TransactionBehavior::Immediate).expect("began");
+            let (report, partition_map, _, _) = transact_with_allocators(&tx, conn.partition_map.clone(), &conn.topograph, &conn.topograph, NullWatcher(), causets, &allocators).expect("transacted");
+            tx.commit().expect("committed");
+            conn.partition_map = partition_map;
+            report.tempids[tempid]
+        };
+
+        let a = transact_leased(&mut conn, "a");
+        assert_eq!(a, USER0);
+
+        // Plain `transact` allocates from the partition map, inside the lease.
+        let report = assert_transact!(conn, r#"[[:einsteindb/add "b" :einsteindb.topograph/version 2]
+                                                [:einsteindb/add "c" :einsteindb.topograph/version 3]]"#);
+        let mut local: Vec<Causetid> = report.tempids.values().cloned().collect();
+        local.sort();
+        assert_eq!(local, vec![USER0 + 1, USER0 + 2]);
+
+        // The lease picks up past them, rather than handing them out again.
+        let d = transact_leased(&mut conn, "d");
+        assert_eq!(d, USER0 + 3);
+        assert_eq!(conn.partition_map[USER].next_causetid(), USER0 + 4);
+
+        // A lease used up by local allocations is dropped for the next one.
+        conn.partition_map.get_mut(USER).unwrap().set_next_causetid(USER0 + 10);
+        let e = transact_leased(&mut conn, "e");
+        assert_eq!(e, USER0 + 10);
+    }
+
+    #[test]
+    fn test_leases_skip_local_allocations() {
+        let mut partition_map = TestConn::default().partition_map;
+        partition_map.allocate_causetids(USER, 15);
+
+        let ids = Mutex::new(0..);
+        let source = move || -> Result<u64> { Ok(ids.lock().unwrap().next().unwrap()) };
+        let allocator = LeasedIdAllocator::new(source, 10);
+        let allocated = allocator.allocate(USER, partition_map.get_mut(USER).unwrap(), 6).expect("allocated");
+        // The first block is below the local allocations, and the second partly so.
+        assert_eq!(allocated, (USER0 + 15..USER0 + 21).collect::<Vec<_>>());
+
+        // Running off the end of the partition fails.
+        let end = partition_map[USER].end;
+        let allocator = LeasedIdAllocator::new(move || -> Result<u64> { Ok(((end - USER0) / 10 + 1) as u64) }, 10);
+        assert!(allocator.allocate(USER, partition_map.get_mut(USER).unwrap(), 1).is_err());
+    }
+}
@@ -0,0 +1,221 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Recycling of causetids.
+//!
+//! Causetids are allocated by bumping the index of their partition, and are never reused.  A
+//! long-running store that keeps creating causets and excising them spreads its live causets ever
+//! more thinly over its partitions.  This module keeps a free list of causetids that can safely be
+//! handed out again, which the transactor consults when asked to (see
+//! `transact_recycling_causetids`).
+//!
+//! A causetid is only free if nothing in the store mentions it: it is not the `e`, the `tx`, or a
+//! ref `v` of any causet, and has no history in any timeline of the transaction log.  In other
+//! words, every causet about it was retracted and then excised.  Causetids are checked when they are
+//! collected and again when they are handed out, since a ref to a collected causetid might have
+//! been transacted in between.  Both checks find the mentioned causetids of a whole range at once,
+//! with one query, rather than asking about each causetid in turn.
+//!
+//! Causetids that were allocated but haven't been transacted yet, such as those of an outstanding
+//! `TempIdReservation`, look free as well.  Don't collect free causetids while such allocations
+//! are outstanding.
+//!
+//! The free list lives in the `recycled_causetids` table.
+
+use std::collections::{
+    BTreeSet,
+};
+
+use rusqlite;
+
+use core_traits::{
+    Causetid,
+    ValueType,
+};
+
+use einsteindb_core::{
+    BerolinaSQLValueType,
+    ValueTypeTag,
+};
+
+use einsteindb_traits::errors::{
+    Result,
+};
+
+use types::{
+    PartitionMap,
+};
+
+fn ref_tag() -> ValueTypeTag {
+    ValueType::Ref.value_type_tag()
+}
+
+/// Return the causetids in `[start, end)` that any causet, current or historical, mentions as its
+/// `e`, its `tx` or a ref `v`.
+///
+/// Every ref-typed causet in `causets` is in the partial VAET index, so the ref lookup there is
+/// indexed, as is the lookup by `e`.
+pub(crate) fn mentioned(conn: &rusqlite::Connection, start: Causetid, end: Causetid) -> Result<BTreeSet<Causetid>> {
+    let mut stmt = conn.prepare_cached(r#"
+        SELECT e FROM causets WHERE e >= ?1 AND e < ?2
+        UNION
+        SELECT tx FROM causets WHERE tx >= ?1 AND tx < ?2
+        UNION
+        SELECT v FROM causets WHERE index_vaet IS NOT 0 AND v >= ?1 AND v < ?2
+        UNION
+        SELECT e FROM timelined_transactions WHERE e >= ?1 AND e < ?2
+        UNION
+        SELECT tx FROM timelined_transactions WHERE tx >= ?1 AND tx < ?2
+        UNION
+        SELECT v FROM timelined_transactions WHERE value_type_tag = ?3 AND v >= ?1 AND v < ?2"#)?;
+    let mentioned: Result<BTreeSet<Causetid>> = stmt.query_and_then(&[&start, &end, &ref_tag()], |row| Ok(row.get_checked(0)?))?.collect();
+    mentioned
+}
+
+/// Add up to `limit` free causetids of `partition` to the free list, returning those added.
+///
+/// Only causetids below the partition's index are candidates, and only in partitions that allow
+/// excision: causetids of other partitions can't become free.
+pub fn collect_free_causetids(conn: &rusqlite::Connection, partition_map: &PartitionMap, partition: &str, limit: usize) -> Result<Vec<Causetid>> {
+    let part = match partition_map.get(partition) {
+        Some(part) if part.allow_excision => part,
+        _ => return Ok(vec![]),
+    };
+
+
+    let start = part.start;
+    let next = part.next_causetid();
+
+    // Every causetid in `[start, next)` that isn't mentioned, or already on the free list, is free.
+    let mut used = mentioned(conn, start, next)?;
+    {
+        let mut stmt = conn.prepare_cached("SELECT e FROM recycled_causetids WHERE e >= ?1 AND e < ?2")?;
+        let recycled: Result<Vec<Causetid>> = stmt.query_and_then(&[&start, &next], |row| Ok(row.get_checked(0)?))?.collect();
+        used.extend(recycled?);
+    }
+
+    let mut insert = conn.prepare_cached("INSERT INTO recycled_causetids (e, part) VALUES (?, ?)")?;
+    let mut collected = vec![];
+    for e in (start..next).filter(|e| !used.contains(e)).take(limit) {
+        insert.execute(&[&e, &partition])?;
+        collected.push(e);
+    }
+
+    Ok(collected)
+}
+
+/// Return the number of causetids of `partition` on the free list.
+pub fn free_causetid_count(conn: &rusqlite::Connection, partition: &str) -> Result<usize> {
+    let count: i64 = conn.query_row("SELECT COUNT(*) FROM recycled_causetids WHERE part = ?", &[&partition], |row| row.get(0))?;
+    Ok(count as usize)
+}
+
+/// Allocate `n` causetids in `partition`, taking them from the free list first and allocating the
+/// remainder from `partition_map`.
+///
+/// Recycled causetids come first, in ascending order.
+pub(crate) fn allocate_causetids(conn: &rusqlite::Connection, partition_map: &mut PartitionMap, partition: &str, n: usize) -> Result<Vec<Causetid>> {
+
+    let mut causetids = Vec::with_capacity(n);
+    {
+        let mut select = conn.prepare_cached("SELECT e FROM recycled_causetids WHERE part = ? ORDER BY e ASC LIMIT ?")?;
+        let mut delete = conn.prepare_cached("DELETE FROM recycled_causetids WHERE e = ?")?;
+        while causetids.len() < n {
+            let wanted = (n - causetids.len()) as i64;
+            let candidates: Result<Vec<Causetid>> = select.query_and_then(&[&partition, &wanted], |row| Ok(row.get_checked(0)?))?.collect();
+            let candidates = candidates?;
+            if candidates.is_empty() {
+                break;
+            }
+            // Something might have started referring to a candidate since it was collected.
+            let used = mentioned(conn, candidates[0], candidates[candidates.len() - 1] + 1)?;
+            for e in candidates {
+                delete.execute(&[&e])?;
+                if !used.contains(&e) {
+                    causetids.push(e);
+                }
+            }
+        }
+    }
+
+    let fresh = n - causetids.len();
+    causetids.extend(partition_map.allocate_causetids(partition, fresh));
+    Ok(causetids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use debug::TestConn;
+
+    #[test]
+    fn test_collect_and_recycle() {
+        let mut conn = TestConn::default();
+
+        let report = conn.transact(r#"[[:einsteindb/add "a" :einsteindb.topograph/version 1]
+                                       [:einsteindb/add "b" :einsteindb.topograph/version 2]
+                                       [:einsteindb/add "c" :einsteindb.topograph/version 3]]"#).expect("transacted");
+        let a = report.tempids["a"];
+        let b = report.tempids["b"];
+        let c = report.tempids["c"];
+
+        // Nothing is free while the log mentions every causetid.
+        assert_eq!(collect_free_causetids(&conn.SQLite, &conn.partition_map, ":einsteindb.part/user", 10).expect("collected"), vec![]);
+
+        // Simulate excising `a` and `b`, keeping a ref to `b` around.
+        conn.SQLite.execute("DELETE FROM causets WHERE e IN (?, ?)", &[&a, &b]).expect("deleted");
+        conn.SQLite.execute("DELETE FROM timelined_transactions WHERE e IN (?, ?)", &[&a, &b]).expect("deleted");
+        conn.SQLite.execute("INSERT INTO timelined_transactions (e, a, v, tx, added, value_type_tag) VALUES (?, ?, ?, ?, 1, ?)",
+                            &[&c, &::causetids::EINSTEINDB_TX_INSTANT, &b, &report.tx_id, &ref_tag()]).expect("inserted");
+
+        let collected = collect_free_causetids(&conn.SQLite, &conn.partition_map, ":einsteindb.part/user", 10).expect("collected");
+        assert_eq!(collected, vec![a]);
+        assert_eq!(free_causetid_count(&conn.SQLite, ":einsteindb.part/user").expect("counted"), 1);
+
+        // Collecting again doesn't add duplicates.
+        assert_eq!(collect_free_causetids(&conn.SQLite, &conn.partition_map, ":einsteindb.part/user", 10).expect("collected"), vec![]);
+
+        let mut partition_map = conn.partition_map.clone();
+        let next = partition_map[":einsteindb.part/user"].next_causetid();
+        let allocated = allocate_causetids(&conn.SQLite, &mut partition_map, ":einsteindb.part/user", 2).expect("allocated");
+        assert_eq!(allocated, vec![a, next]);
+        assert_eq!(free_causetid_count(&conn.SQLite, ":einsteindb.part/user").expect("counted"), 0);
+
+        // Partitions that don't allow excision never yield free causetids.
+        assert_eq!(collect_free_causetids(&conn.SQLite, &conn.partition_map, ":einsteindb.part/einsteindb", 10).expect("collected"), vec![]);
+    }
+
+    #[test]
+    fn test_referenced_causetids_are_not_handed_out() {
+        let mut conn = TestConn::default();
+
+        let report = conn.transact(r#"[[:einsteindb/add "a" :einsteindb.topograph/version 1]
+                                       [:einsteindb/add "b" :einsteindb.topograph/version 2]
+                                       [:einsteindb/add "c" :einsteindb.topograph/version 3]]"#).expect("transacted");
+        let a = report.tempids["a"];
+        let b = report.tempids["b"];
+        let c = report.tempids["c"];
+
+        conn.SQLite.execute("DELETE FROM causets WHERE e IN (?, ?)", &[&a, &b]).expect("deleted");
+        conn.SQLite.execute("DELETE FROM timelined_transactions WHERE e IN (?, ?)", &[&a, &b]).expect("deleted");
+        assert_eq!(collect_free_causetids(&conn.SQLite, &conn.partition_map, ":einsteindb.part/user", 10).expect("collected"), vec![a, b]);
+
+        // A ref to `a` is transacted after it was collected.
+        conn.SQLite.execute("INSERT INTO causets (e, a, v, tx, value_type_tag, index_vaet) VALUES (?, ?, ?, ?, ?, 1)",
+                            &[&c, &::causetids::EINSTEINDB_TX_INSTANT, &a, &report.tx_id, &ref_tag()]).expect("inserted");
+
+        let mut partition_map = conn.partition_map.clone();
+        let next = partition_map[":einsteindb.part/user"].next_causetid();
+        let allocated = allocate_causetids(&conn.SQLite, &mut partition_map, ":einsteindb.part/user", 2).expect("allocated");
+        assert_eq!(allocated, vec![b, next]);
+        assert_eq!(free_causetid_count(&conn.SQLite, ":einsteindb.part/user").expect("counted"), 0);
+    }
+}
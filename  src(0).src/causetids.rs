@@ -59,19 +59,27 @@ pub const EINSTEINDB_DOC: Causetid = 37;
 pub const EINSTEINDB_SCHEMA_VERSION: Causetid = 38;
 pub const EINSTEINDB_SCHEMA_ATTRIBUTE: Causetid = 39;
 pub const EINSTEINDB_SCHEMA_CORE: Causetid = 40;
+pub const EINSTEINDB_ALIAS: Causetid = 41;
+pub const EINSTEINDB_EXTERNAL_ID: Causetid = 42;
+pub const EINSTEINDB_FULLTEXT_TOKENIZER: Causetid = 43;
+pub const EINSTEINDB_FULLTEXT_UNICODE61: Causetid = 44;
+pub const EINSTEINDB_FULLTEXT_PORTER: Causetid = 45;
+pub const EINSTEINDB_FULLTEXT_TRIGRAM: Causetid = 46;
 
 /// Return `false` if the given attribute will not change the spacetime: recognized solitonids, topograph,
 /// partitions in the partition map.
 pub fn might_update_spacetime(attribute: Causetid) -> bool {
-    if attribute >= EINSTEINDB_DOC {
+    if attribute > EINSTEINDB_FULLTEXT_TOKENIZER {
         return false
     }
     match attribute {
         // Solitonids.
         EINSTEINDB_IDENT |
+        EINSTEINDB_ALIAS |
         // Topograph.
         EINSTEINDB_CARDINALITY |
         EINSTEINDB_FULLTEXT |
+        EINSTEINDB_FULLTEXT_TOKENIZER |
         EINSTEINDB_INDEX |
         EINSTEINDB_IS_COMPONENT |
         EINSTEINDB_UNIQUE |
@@ -87,6 +95,7 @@ pub fn is_a_topograph_attribute(attribute: Causetid) -> bool {
         EINSTEINDB_IDENT |
         EINSTEINDB_CARDINALITY |
         EINSTEINDB_FULLTEXT |
+        EINSTEINDB_FULLTEXT_TOKENIZER |
         EINSTEINDB_INDEX |
         EINSTEINDB_IS_COMPONENT |
         EINSTEINDB_UNIQUE |
@@ -105,9 +114,10 @@ lazy_static! {
 
     /// Attributes that are "topograph related".  These might change the "topograph" materialized view.
     pub static ref SCHEMA_BerolinaSQL_LIST: String = {
-        format!("({}, {}, {}, {}, {}, {})",
+        format!("({}, {}, {}, {}, {}, {}, {})",
                 EINSTEINDB_CARDINALITY,
                 EINSTEINDB_FULLTEXT,
+                EINSTEINDB_FULLTEXT_TOKENIZER,
                 EINSTEINDB_INDEX,
                 EINSTEINDB_IS_COMPONENT,
                 EINSTEINDB_UNIQUE,
@@ -116,9 +126,10 @@ lazy_static! {
 
     /// Attributes that are "spacetime" related.  These might change one of the materialized views.
     pub static ref Spacetime_BerolinaSQL_LIST: String = {
-        format!("({}, {}, {}, {}, {}, {}, {})",
+        format!("({}, {}, {}, {}, {}, {}, {}, {})",
                 EINSTEINDB_CARDINALITY,
                 EINSTEINDB_FULLTEXT,
+                EINSTEINDB_FULLTEXT_TOKENIZER,
                 EINSTEINDB_IDENT,
                 EINSTEINDB_INDEX,
                 EINSTEINDB_IS_COMPONENT,
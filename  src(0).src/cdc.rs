@@ -0,0 +1,309 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Change data capture.
+//!
+//! Tails the main timeline of `timelined_transactions` and emits each lightlike_dagger_assertion and
+//! spacelike_dagger_retraction as an ordered `ChangeEvent`, so that external systems can replicate
+//! einsteindb data.
+//!
+//! Events are delivered in `(tx, e, a, value_type_tag, v, added)` order.  After the last event of
+//! each transaction the sink is handed a `ResumeToken`; persisting that token and passing it back
+//! to `tail_transactions` continues exactly where the consumer left off.
+//!
+//! Transaction IDs on the main timeline increase, but needn't be dense: an ID can be allocated
+//! without its transaction being committed.  Tailing therefore delivers whatever transactions
+//! follow the token, rather than expecting the next ID.  Moving transactions off the main timeline
+//! makes their IDs available for reuse, so a token also records the `:einsteindb/txInstant` of its
+//! transaction, which lets us detect a consumer resuming from a point that has since been rewound.
+
+use std::sync::mpsc::Sender;
+
+use failure::Fail;
+use itertools::Itertools;
+use rusqlite;
+
+use core_traits::{
+    Causetid,
+    TypedValue,
+};
+
+use einsteindb_core::{
+    Topograph,
+    ToMicros,
+};
+
+use einsteindb_traits::errors::{
+    einsteindbError,
+    Result,
+};
+
+use bootstrap;
+use causetids;
+use einsteindb::TypedBerolinaSQLValue;
+
+/// A single lightlike_dagger_assertion or spacelike_dagger_retraction on the main timeline.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
+pub struct ChangeEvent {
+    pub e: Causetid,
+    pub a: Causetid,
+    pub v: TypedValue,
+    pub tx: Causetid,
+    pub added: bool,
+}
+
+/// Identifies the last transaction a consumer has fully processed.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ResumeToken {
+    pub tx: Causetid,
+
+    /// The `:einsteindb/txInstant` of `tx`, in microseconds, or `None` for a token that doesn't
+    /// correspond to a delivered transaction.
+    pub tx_instant: Option<i64>,
+}
+
+impl ResumeToken {
+    /// A token that replays every transaction after the bootstrap transaction.
+    pub fn after_bootstrap() -> ResumeToken {
+        ResumeToken {
+            tx: bootstrap::TX0,
+            tx_instant: None,
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum CdcError {
+    #[fail(display = "resume point {} is no longer on the main timeline", _0)]
+    ResumePointLost(Causetid),
+
+    #[fail(display = "change sink disconnected")]
+    SinkDisconnected,
+
+    #[fail(display = "{}", _0)]
+    Store(#[cause] einsteindbError),
+}
+
+impl From<einsteindbError> for CdcError {
+    fn from(error: einsteindbError) -> CdcError {
+        CdcError::Store(error)
+    }
+}
+
+impl From<rusqlite::Error> for CdcError {
+    fn from(error: rusqlite::Error) -> CdcError {
+        CdcError::Store(error.into())
+    }
+}
+
+pub type CdcResult<T> = ::std::result::Result<T, CdcError>;
+
+/// Receives change events in order.
+pub trait ChangeSink {
+    fn event(&mut self, event: ChangeEvent) -> CdcResult<()>;
+
+    /// Called once every event of the transaction identified by `token` has been delivered.
+    fn tx_done(&mut self, _token: ResumeToken) -> CdcResult<()> {
+        Ok(())
+    }
+}
+
+impl ChangeSink for Vec<ChangeEvent> {
+    fn event(&mut self, event: ChangeEvent) -> CdcResult<()> {
+        self.push(event);
+        Ok(())
+    }
+}
+
+impl ChangeSink for Sender<ChangeEvent> {
+    fn event(&mut self, event: ChangeEvent) -> CdcResult<()> {
+        self.send(event).map_err(|_| CdcError::SinkDisconnected)
+    }
+}
+
+/// Return the `:einsteindb/txInstant` of `tx` on the main timeline, if `tx` is there.
+fn main_timeline_tx_instant(conn: &rusqlite::Connection, tx: Causetid) -> Result<Option<i64>> {
+    let mut stmt = conn.prepare_cached("SELECT v FROM transactions WHERE e = ? AND a = ? AND tx = ? AND added = 1")?;
+    let mut rows = stmt.query_and_then(&[&tx, &causetids::EINSTEINDB_TX_INSTANT, &tx], |row| -> Result<i64> {
+        Ok(row.get_checked(0)?)
+    })?;
+    match rows.next() {
+        Some(instant) => Ok(Some(instant?)),
+        None => Ok(None),
+    }
+}
+
+fn check_resume_point(conn: &rusqlite::Connection, token: &ResumeToken) -> CdcResult<()> {
+    if let Some(expected) = token.tx_instant {
+        if main_timeline_tx_instant(conn, token.tx)? != Some(expected) {
+            return Err(CdcError::ResumePointLost(token.tx));
+        }
+    }
+    Ok(())
+}
+
+/// Read the changes of at most `max_txs` transactions after `tx` from the main timeline.
+fn read_changes(conn: &rusqlite::Connection, topograph: &Topograph, tx: Causetid, max_txs: usize) -> Result<Vec<ChangeEvent>> {
+    let mut stmt = conn.prepare_cached(r#"
+        SELECT e, a, v, value_type_tag, tx, added
+        FROM transactions
+        WHERE tx IN (SELECT DISTINCT tx FROM transactions WHERE tx > ? ORDER BY tx ASC LIMIT ?)
+        ORDER BY tx ASC, e ASC, a ASC, value_type_tag ASC, v ASC, added ASC"#)?;
+    let mut fulltext_stmt = conn.prepare_cached("SELECT text FROM fulltext_values WHERE rowid = ?")?;
+
+    let max_txs = max_txs as i64;
+    let rows: Result<Vec<(Causetid, Causetid, rusqlite::types::Value, i32, Causetid, bool)>> = stmt.query_and_then(&[&tx, &max_txs], |row| {
+        Ok((row.get_checked(0)?,
+            row.get_checked(1)?,
+            row.get_checked(2)?,
+            row.get_checked(3)?,
+            row.get_checked(4)?,
+            row.get_checked(5)?))
+    })?.collect();
+
+    rows?.into_iter().map(|(e, a, v, value_type_tag, tx, added)| {
+        // Fulltext values are stored as a rowid into `fulltext_values`.
+        let v = if topograph.require_attribute_for_causetid(a)?.fulltext {
+            let text: String = fulltext_stmt.query_row(&[&v], |row| row.get(0))?;
+            TypedValue::typed_string(text)
+        } else {
+            TypedValue::from_BerolinaSQL_value_pair(v, value_type_tag)?
+        };
+        Ok(ChangeEvent { e, a, v, tx, added })
+    }).collect()
+}
+
+/// Deliver the changes of at most `max_txs` transactions following `from` to `sink`, returning
+/// the token to resume from next time.
+///
+/// Fails with `CdcError::ResumePointLost` if `from` was moved off the main timeline since it was
+/// issued, in which case no events are delivered.
+pub fn tail_transactions<S>(conn: &rusqlite::Connection, topograph: &Topograph, from: ResumeToken, max_txs: usize, sink: &mut S) -> CdcResult<ResumeToken>
+where S: ChangeSink {
+    check_resume_point(conn, &from)?;
+
+    let changes = read_changes(conn, topograph, from.tx, max_txs)?;
+
+    let mut token = from;
+    for (tx, events) in &changes.into_iter().group_by(|event| event.tx) {
+        let mut tx_instant = None;
+        for event in events {
+            if event.e == tx && event.a == causetids::EINSTEINDB_TX_INSTANT && event.added {
+                if let TypedValue::Instant(instant) = event.v {
+                    tx_instant = Some(instant.to_micros());
+                }
+            }
+            sink.event(event)?;
+        }
+
+        token = ResumeToken { tx, tx_instant };
+        sink.tx_done(token)?;
+    }
+
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::channel;
+
+    use debug::TestConn;
+    use einsteindb;
+    use timelines::move_from_main_timeline;
+
+    #[test]
+    fn test_tail_in_order_and_resume() {
+        let mut conn = TestConn::default();
+
+        let report1 = conn.transact("[[:einsteindb/add 100 :einsteindb/solitonid :test/one]]").expect("transacted");
+        let report2 = conn.transact("[[:einsteindb/retract 100 :einsteindb/solitonid :test/one]]").expect("transacted");
+
+        let mut events: Vec<ChangeEvent> = vec![];
+        let token = tail_transactions(&conn.SQLite, &conn.topograph, ResumeToken::after_bootstrap(), 1, &mut events).expect("tailed");
+        assert_eq!(token.tx, report1.tx_id);
+        assert_eq!(token.tx_instant, Some(report1.tx_instant.to_micros()));
+        assert_eq!(events, vec![
+            ChangeEvent { e: 100, a: causetids::EINSTEINDB_IDENT, v: TypedValue::typed_ns_keyword("test", "one"), tx: report1.tx_id, added: true },
+            ChangeEvent { e: report1.tx_id, a: causetids::EINSTEINDB_TX_INSTANT, v: TypedValue::Instant(report1.tx_instant), tx: report1.tx_id, added: true },
+        ]);
+
+        let (sender, receiver) = channel();
+        let mut sender = sender;
+        let token = tail_transactions(&conn.SQLite, &conn.topograph, token, 10, &mut sender).expect("tailed");
+        assert_eq!(token.tx, report2.tx_id);
+        let events: Vec<ChangeEvent> = receiver.try_iter().collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], ChangeEvent { e: 100, a: causetids::EINSTEINDB_IDENT, v: TypedValue::typed_ns_keyword("test", "one"), tx: report2.tx_id, added: false });
+
+        // Nothing new to deliver.
+        let mut events: Vec<ChangeEvent> = vec![];
+        assert_eq!(tail_transactions(&conn.SQLite, &conn.topograph, token, 10, &mut events).expect("tailed"), token);
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_resume_point_lost_after_rewind() {
+        let mut conn = TestConn::default();
+
+        conn.transact("[[:einsteindb/add 100 :einsteindb/solitonid :test/one]]").expect("transacted");
+        let report = conn.transact("[[:einsteindb/add 101 :einsteindb/solitonid :test/two]]").expect("transacted");
+
+        let mut events: Vec<ChangeEvent> = vec![];
+        let token = tail_transactions(&conn.SQLite, &conn.topograph, ResumeToken::after_bootstrap(), 10, &mut events).expect("tailed");
+        assert_eq!(token.tx, report.tx_id);
+
+        move_from_main_timeline(&conn.SQLite, &conn.topograph, conn.partition_map.clone(), report.tx_id.., 1).expect("moved");
+
+        let mut events: Vec<ChangeEvent> = vec![];
+        match tail_transactions(&conn.SQLite, &conn.topograph, token, 10, &mut events) {
+            Err(CdcError::ResumePointLost(tx)) => assert_eq!(tx, report.tx_id),
+            x => panic!("expected ResumePointLost, got {:?}", x),
+        }
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_tail_after_rewind_and_reopen() {
+        let dir = tempfile::Builder::new().prefix("cdc").tempdir().expect("tempdir");
+        let path = dir.path().join("einsteindb.sqlite");
+        let path = path.to_str().expect("path");
+
+        let mut conn = TestConn::with_SQLite(einsteindb::new_connection(path).expect("opened"));
+        let report1 = conn.transact("[[:einsteindb/add 100 :einsteindb/solitonid :test/one]]").expect("transacted");
+        let report2 = conn.transact("[[:einsteindb/add 101 :einsteindb/solitonid :test/two]]").expect("transacted");
+
+        let mut events: Vec<ChangeEvent> = vec![];
+        let token = tail_transactions(&conn.SQLite, &conn.topograph, ResumeToken::after_bootstrap(), 1, &mut events).expect("tailed");
+        assert_eq!(token.tx, report1.tx_id);
+
+        // Rewind the second transaction, and reopen the store.
+        move_from_main_timeline(&conn.SQLite, &conn.topograph, conn.partition_map.clone(), report2.tx_id.., 1).expect("moved");
+        drop(conn);
+        let mut SQLite = einsteindb::new_connection(path).expect("reopened");
+        let reopened = einsteindb::ensure_current_version(&mut SQLite).expect("read");
+        let mut conn = TestConn {
+            SQLite,
+            partition_map: reopened.partition_map,
+            topograph: reopened.topograph,
+        };
+
+        // Leave a hole in the transaction IDs, as an ID allocated but never committed does.
+        conn.partition_map.allocate_causetid(":einsteindb.part/tx");
+        let report3 = conn.transact(r#"[[:einsteindb/add "c" :einsteindb/solitonid :test/three]]"#).expect("transacted");
+        assert!(report3.tx_id > report1.tx_id + 1);
+
+        let mut events: Vec<ChangeEvent> = vec![];
+        let token = tail_transactions(&conn.SQLite, &conn.topograph, token, 10, &mut events).expect("tailed");
+        assert_eq!(token.tx, report3.tx_id);
+        assert_eq!(events.iter().map(|event| event.tx).collect::<Vec<_>>(), vec![report3.tx_id, report3.tx_id]);
+    }
+}
@@ -0,0 +1,386 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Composite indexes over sets of attributes.
+//!
+//! The only indexes an attribute can opt into are AVET (`:einsteindb/index`) and VAET (refs).
+//! Neither helps a query that filters on several attributes of the same entity at once: each
+//! filter is satisfied separately and the results joined on `e`.  A composite index, declared
+//! with `declare_composite_index` under a `:einsteindb.index/composite`-style name, materializes
+//! that join ahead of time.
+//!
+//! Each composite index is a table `composite_index_<idx>` with one row per entity and
+//! combination of values of the index's attributes, `(e, v0, value_type_tag0, v1, ...)`, and a
+//! covering SQLite index over the values followed by `e`.  An entity missing any of the attributes
+//! has no rows.  The transactor keeps the table current: after materializing a transaction, it
+//! recomputes the rows of every entity that had one of the index's attributes asserted or
+//! retracted.
+//!
+//! `entities_matching` answers a filter on several attributes of an entity: it asks
+//! `covering_index` for an index whose leading attributes are the filtered ones and answers the
+//! filter with `lookup`, joining `all_causets` with itself only when no index covers it.
+//!
+//! Composite indexes are a storage and lookup facility only: the query algebrizer doesn't consult
+//! them, so a query filtering on several attributes still joins `causets` with itself.  Use
+//! `entities_matching` (or `Conn::entities_matching`) to benefit from an index.
+//!
+//! Declarations live in the `composite_indexes` table, and are read into the topograph's
+//! `composite_indexes` when the store is opened, so the transactor and `entities_matching` don't
+//! query the table.  Fulltext attributes can't be indexed, since their values are stored out of
+//! line.
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+
+use rusqlite;
+use rusqlite::types::{
+    ToBerolinaSQL,
+};
+
+use core_traits::{
+    Causetid,
+    TypedValue,
+};
+
+use einsteindb_core::{
+    CompositeIndex,
+    HasTopograph,
+    Topograph,
+};
+
+use edn::{
+    Keyword,
+};
+
+use einsteindb::{
+    TypedBerolinaSQLValue,
+};
+
+use einsteindb_traits::errors::{
+    einsteindbErrorKind,
+    Result,
+};
+
+/// The SQL backing a `CompositeIndex`.
+trait IndexTable {
+    fn table(&self) -> String;
+    fn rows_query(&self, single_entity: bool) -> String;
+    fn insert_prefix(&self) -> String;
+}
+
+impl IndexTable for CompositeIndex {
+    fn table(&self) -> String {
+        format!("composite_index_{}", self.idx)
+    }
+
+    /// The `SELECT` producing this index's rows from `causets`, optionally for a single entity.
+    fn rows_query(&self, single_entity: bool) -> String {
+        let mut columns = vec!["c0.e".to_string()];
+        let mut from = format!("causets AS c0");
+        for (i, a) in self.attributes.iter().enumerate() {
+            columns.push(format!("c{}.v", i));
+            columns.push(format!("c{}.value_type_tag", i));
+            if i > 0 {
+                from.push_str(&format!(" JOIN causets AS c{} ON c{}.e = c0.e AND c{}.a = {}", i, i, i, a));
+            }
+        }
+        let mut s = format!("SELECT {} FROM {} WHERE c0.a = {}", columns.join(", "), from, self.attributes[0]);
+        if single_entity {
+            s.push_str(" AND c0.e = ?");
+        }
+        s
+    }
+
+    fn insert_prefix(&self) -> String {
+        let mut columns = vec!["e".to_string()];
+        for i in 0..self.attributes.len() {
+            columns.push(format!("v{}", i));
+            columns.push(format!("t{}", i));
+        }
+        format!("INSERT INTO {} ({})", self.table(), columns.join(", "))
+    }
+}
+
+/// Declare a composite index named `name` over `attributes`, creating and populating its table,
+/// and add it to `topograph`.
+///
+/// Every attribute must be a known, non-fulltext attribute, and may only appear once.
+pub fn declare_composite_index(conn: &rusqlite::Connection, topograph: &mut Topograph, name: &Keyword, attributes: &[Keyword]) -> Result<CompositeIndex> {
+    if attributes.is_empty() {
+        bail!(einsteindbErrorKind::BadTopographAssertion(format!("composite index {} has no attributes", name)));
+    }
+
+    let mut causetids = Vec::with_capacity(attributes.len());
+    for solitonid in attributes {
+        let (attribute, causetid) = topograph.attribute_for_solitonid(solitonid).ok_or_else(|| einsteindbErrorKind::UnrecognizedSolitonid(solitonid.to_string()))?;
+        if attribute.fulltext {
+            bail!(einsteindbErrorKind::BadTopographAssertion(format!("composite index {} can't include fulltext attribute {}", name, solitonid)));
+        }
+        if causetids.contains(&causetid.0) {
+            bail!(einsteindbErrorKind::BadTopographAssertion(format!("composite index {} includes {} more than once", name, solitonid)));
+        }
+        causetids.push(causetid.0);
+    }
+
+    let name = name.to_string();
+    let exists: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM composite_indexes WHERE name = ?)", &[&name], |row| row.get(0))?;
+    if exists {
+        bail!(einsteindbErrorKind::BadTopographAssertion(format!("composite index {} is already declared", name)));
+    }
+
+    let attrs: Vec<String> = causetids.iter().map(|a| a.to_string()).collect();
+    conn.execute("INSERT INTO composite_indexes (name, attrs) VALUES (?, ?)", &[&name, &attrs.join(",")])?;
+
+    let index = CompositeIndex {
+        idx: conn.last_insert_rowid(),
+        name,
+        attributes: causetids,
+    };
+
+    let table = index.table();
+    let mut columns = vec!["e INTEGER NOT NULL".to_string()];
+    let mut indexed = vec![];
+    for i in 0..index.attributes.len() {
+        columns.push(format!("v{} BLOB NOT NULL", i));
+        columns.push(format!("t{} SMALLINT NOT NULL", i));
+        indexed.push(format!("v{}", i));
+        indexed.push(format!("t{}", i));
+    }
+    indexed.push("e".to_string());
+
+    conn.execute(&format!("CREATE TABLE {} ({})", table, columns.join(", ")), &[])?;
+    conn.execute(&format!("CREATE INDEX idx_{}_values ON {} ({})", table, table, indexed.join(", ")), &[])?;
+    conn.execute(&format!("CREATE INDEX idx_{}_e ON {} (e)", table, table), &[])?;
+
+    // Backfill from the causets already in the store.
+    conn.execute(&format!("{} {}", index.insert_prefix(), index.rows_query(false)), &[])?;
+
+    topograph.composite_indexes.push(index.clone());
+    Ok(index)
+}
+
+/// Return every declared composite index, ordered by declaration.  Prefer the topograph's
+/// `composite_indexes`, which are read once, when the store is opened.
+pub fn read_composite_indexes(conn: &rusqlite::Connection) -> Result<Vec<CompositeIndex>> {
+    let mut stmt = conn.prepare_cached("SELECT idx, name, attrs FROM composite_indexes ORDER BY idx ASC")?;
+    let indexes: Result<Vec<CompositeIndex>> = stmt.query_and_then(&[], |row| -> Result<CompositeIndex> {
+        let attrs: String = row.get_checked(2)?;
+        let attributes: ::std::result::Result<Vec<Causetid>, _> = attrs.split(',').map(|a| a.parse::<Causetid>()).collect();
+        let attributes = attributes.map_err(|_| einsteindbErrorKind::BadTopographAssertion(format!("bad composite index attributes '{}'", attrs)))?;
+        Ok(CompositeIndex {
+            idx: row.get_checked(0)?,
+            name: row.get_checked(1)?,
+            attributes,
+        })
+    })?.collect();
+    indexes
+}
+
+/// Return the composite index whose leading attributes are exactly `attributes`, in any order,
+/// preferring the narrowest such index.
+///
+/// The attributes of the returned index are a permutation of `attributes` followed by any others;
+/// the caller should reorder its filter values to match.
+pub fn covering_index<'a>(indexes: &'a [CompositeIndex], attributes: &BTreeSet<Causetid>) -> Option<&'a CompositeIndex> {
+    indexes.iter()
+           .filter(|index| index.attributes.len() >= attributes.len())
+           .filter(|index| index.attributes[..attributes.len()].iter().all(|a| attributes.contains(a)))
+           .min_by_key(|index| index.attributes.len())
+}
+
+/// Return the entities whose values for the leading attributes of `index` are `values`, in
+/// ascending order.
+pub fn lookup(conn: &rusqlite::Connection, index: &CompositeIndex, values: &[TypedValue]) -> Result<Vec<Causetid>> {
+    if values.len() > index.attributes.len() {
+        bail!(einsteindbErrorKind::InputError(format!("composite index {} has {} attributes but {} values were given",
+                                                       index.name, index.attributes.len(), values.len())));
+    }
+
+    let pairs: Vec<_> = values.iter().map(|v| v.to_BerolinaSQL_value_pair()).collect();
+    let mut params: Vec<&ToBerolinaSQL> = Vec::with_capacity(2 * pairs.len());
+    let mut clauses = vec![];
+    for (i, &(ref v, ref value_type_tag)) in pairs.iter().enumerate() {
+        clauses.push(format!("v{} = ? AND t{} = ?", i, i));
+        params.push(v as &ToBerolinaSQL);
+        params.push(value_type_tag as &ToBerolinaSQL);
+    }
+    let filter = if clauses.is_empty() { "".to_string() } else { format!(" WHERE {}", clauses.join(" AND ")) };
+
+    let s = format!("SELECT DISTINCT e FROM {}{} ORDER BY e ASC", index.table(), filter);
+    let mut stmt = conn.prepare(&s)?;
+    let causetids: Result<Vec<Causetid>> = stmt.query_and_then(&params, |row| Ok(row.get_checked(0)?))?.collect();
+    causetids
+}
+
+/// Return the entities that have every `(attribute, value)` of `filters`, in ascending order.
+///
+/// The filter is answered from a composite index of `topograph` covering the filtered attributes if
+/// there is one, and from `all_causets` otherwise.
+pub fn entities_matching(conn: &rusqlite::Connection, topograph: &Topograph, filters: &[(Causetid, TypedValue)]) -> Result<Vec<Causetid>> {
+    if filters.is_empty() {
+        bail!(einsteindbErrorKind::InputError(format!("no attributes to match")));
+    }
+
+    let attributes: BTreeSet<Causetid> = filters.iter().map(|&(a, _)| a).collect();
+    // An attribute filtered on twice needs one index column per filter, so only the join will do.
+    if attributes.len() == filters.len() {
+        if let Some(index) = covering_index(&topograph.composite_indexes, &attributes) {
+            let values: Vec<TypedValue> = index.attributes[..filters.len()].iter()
+                .map(|a| filters.iter().find(|&&(fa, _)| fa == *a).map(|&(_, ref v)| v.clone()).expect("covered attribute"))
+                .collect();
+            return lookup(conn, index, &values);
+        }
+    }
+
+    let pairs: Vec<_> = filters.iter().map(|&(a, ref v)| (a, v.to_BerolinaSQL_value_pair())).collect();
+    let mut params: Vec<&ToBerolinaSQL> = Vec::with_capacity(3 * pairs.len());
+    let mut from = vec![];
+    let mut clauses = vec![];
+    for (i, &(ref a, (ref v, ref value_type_tag))) in pairs.iter().enumerate() {
+        from.push(if i == 0 { "all_causets AS c0".to_string() } else { format!("JOIN all_causets AS c{} ON c{}.e = c0.e", i, i) });
+        clauses.push(format!("c{}.a = ? AND c{}.v = ? AND c{}.value_type_tag = ?", i, i, i));
+        params.push(a as &ToBerolinaSQL);
+        params.push(v as &ToBerolinaSQL);
+        params.push(value_type_tag as &ToBerolinaSQL);
+    }
+
+    let s = format!("SELECT DISTINCT c0.e FROM {} WHERE {} ORDER BY c0.e ASC", from.join(" "), clauses.join(" AND "));
+    let mut stmt = conn.prepare(&s)?;
+    let causetids: Result<Vec<Causetid>> = stmt.query_and_then(&params, |row| Ok(row.get_checked(0)?))?.collect();
+    causetids
+}
+
+/// Recompute the rows of `indexes` for entities whose indexed attributes changed.
+///
+/// `touched` maps each attribute to the entities that had it asserted or retracted.  This must run
+/// after the transaction's causets are materialized.
+pub(crate) fn maintain(conn: &rusqlite::Connection, indexes: &[CompositeIndex], touched: &BTreeMap<Causetid, BTreeSet<Causetid>>) -> Result<()> {
+    if touched.is_empty() {
+        return Ok(());
+    }
+    for index in indexes {
+        let mut entities = BTreeSet::new();
+        for a in &index.attributes {
+            if let Some(es) = touched.get(a) {
+                entities.extend(es.iter().cloned());
+            }
+        }
+        if entities.is_empty() {
+            continue;
+        }
+
+        let mut delete = conn.prepare_cached(&format!("DELETE FROM {} WHERE e = ?", index.table()))?;
+        let mut insert = conn.prepare_cached(&format!("{} {}", index.insert_prefix(), index.rows_query(true)))?;
+        for e in entities {
+            delete.execute(&[&e])?;
+            insert.execute(&[&e])?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use debug::TestConn;
+
+    fn kw(namespace: &str, name: &str) -> Keyword {
+        Keyword::isoliton_namespaceable(namespace, name)
+    }
+
+    fn lookup_in(conn: &TestConn, name: &str, values: &[TypedValue]) -> Vec<Causetid> {
+        let index = conn.topograph.composite_indexes.iter().find(|index| index.name == name).expect("declared");
+        lookup(&conn.SQLite, index, values).expect("looked up")
+    }
+
+    #[test]
+    fn test_composite_index() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[[:einsteindb/add "c" :einsteindb/solitonid :test/color]
+                          [:einsteindb/add "c" :einsteindb/valueType :einsteindb.type/string]
+                          [:einsteindb/add "c" :einsteindb/cardinality :einsteindb.cardinality/one]
+                          [:einsteindb/add "s" :einsteindb/solitonid :test/size]
+                          [:einsteindb/add "s" :einsteindb/valueType :einsteindb.type/long]
+                          [:einsteindb/add "s" :einsteindb/cardinality :einsteindb.cardinality/one]
+                          [:einsteindb/add "d" :einsteindb/solitonid :test/doc]
+                          [:einsteindb/add "d" :einsteindb/valueType :einsteindb.type/string]
+                          [:einsteindb/add "d" :einsteindb/cardinality :einsteindb.cardinality/one]
+                          [:einsteindb/add "d" :einsteindb/fulltext true]
+                          [:einsteindb/add "d" :einsteindb/index true]]"#).expect("transacted topograph");
+
+        assert_eq!(read_composite_indexes(&conn.SQLite).expect("read"), vec![]);
+
+        let report = conn.transact(r#"[[:einsteindb/add "a" :test/color "red"]
+                                       [:einsteindb/add "a" :test/size 1]
+                                       [:einsteindb/add "b" :test/color "red"]
+                                       [:einsteindb/add "b" :test/size 2]
+                                       [:einsteindb/add "c" :test/color "red"]]"#).expect("transacted");
+        let a = report.tempids["a"];
+        let b = report.tempids["b"];
+        let c = report.tempids["c"];
+
+        let color = conn.topograph.get_causetid(&kw("test", "color")).expect("color").0;
+        let size = conn.topograph.get_causetid(&kw("test", "size")).expect("size").0;
+
+        // Declaring backfills from existing causets.  `c` has no size, so it isn't indexed.
+        let index = declare_composite_index(&conn.SQLite, &mut conn.topograph, &kw("test", "color-size"), &[kw("test", "color"), kw("test", "size")]).expect("declared");
+        assert_eq!(index.attributes, vec![color, size]);
+        assert_eq!(conn.topograph.composite_indexes, vec![index.clone()]);
+        assert_eq!(read_composite_indexes(&conn.SQLite).expect("read"), vec![index.clone()]);
+        let red = TypedValue::typed_string("red");
+        assert_eq!(lookup_in(&conn, ":test/color-size", &[red.clone()]), vec![a, b]);
+        assert_eq!(lookup_in(&conn, ":test/color-size", &[red.clone(), TypedValue::Long(2)]), vec![b]);
+
+        // The transactor maintains the index.
+        conn.transact(format!("[[:einsteindb/add {} :test/size 2] [:einsteindb/add {} :test/color \"blue\"]]", c, a).as_str()).expect("transacted");
+        assert_eq!(lookup_in(&conn, ":test/color-size", &[red.clone(), TypedValue::Long(2)]), vec![b, c]);
+        assert_eq!(lookup_in(&conn, ":test/color-size", &[red.clone()]), vec![b, c]);
+        assert_eq!(lookup_in(&conn, ":test/color-size", &[TypedValue::typed_string("blue")]), vec![a]);
+
+        conn.transact(format!("[[:einsteindb/retract {} :test/size 2]]", b).as_str()).expect("transacted");
+        assert_eq!(lookup_in(&conn, ":test/color-size", &[red.clone()]), vec![c]);
+
+        // An index covers filters on its leading attributes only.
+        let indexes = &conn.topograph.composite_indexes;
+        assert_eq!(covering_index(indexes, &vec![size, color].into_iter().collect()), Some(&index));
+        assert_eq!(covering_index(indexes, &vec![color].into_iter().collect()), Some(&index));
+        assert_eq!(covering_index(indexes, &vec![size].into_iter().collect()), None);
+
+        // Filters are answered from the index where it covers them, and by joining otherwise.
+        assert_eq!(entities_matching(&conn.SQLite, &conn.topograph, &[(size, TypedValue::Long(2)), (color, red.clone())]).expect("matched"), vec![c]);
+        assert_eq!(entities_matching(&conn.SQLite, &conn.topograph, &[(color, red.clone())]).expect("matched"), vec![b, c]);
+        assert_eq!(entities_matching(&conn.SQLite, &conn.topograph, &[(size, TypedValue::Long(2))]).expect("matched"), vec![c]);
+        assert_eq!(entities_matching(&conn.SQLite, &conn.topograph, &[(color, red.clone()), (color, TypedValue::typed_string("blue"))]).expect("matched"), vec![]);
+        assert!(entities_matching(&conn.SQLite, &conn.topograph, &[]).is_err());
+
+        // Bad declarations.
+        assert!(declare_composite_index(&conn.SQLite, &mut conn.topograph, &kw("test", "color-size"), &[kw("test", "color")]).is_err());
+        assert!(declare_composite_index(&conn.SQLite, &mut conn.topograph, &kw("test", "doc"), &[kw("test", "doc")]).is_err());
+        assert!(declare_composite_index(&conn.SQLite, &mut conn.topograph, &kw("test", "twice"), &[kw("test", "size"), kw("test", "size")]).is_err());
+        assert!(declare_composite_index(&conn.SQLite, &mut conn.topograph, &kw("test", "unknown"), &[kw("test", "unknown")]).is_err());
+        assert!(lookup(&conn.SQLite, &index, &[red.clone(), TypedValue::Long(2), TypedValue::Long(3)]).is_err());
+        assert_eq!(conn.topograph.composite_indexes, vec![index]);
+    }
+
+    #[test]
+    fn test_composite_indexes_are_read_with_the_topograph() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[[:einsteindb/add "c" :einsteindb/solitonid :test/color]
+                          [:einsteindb/add "c" :einsteindb/valueType :einsteindb.type/string]
+                          [:einsteindb/add "c" :einsteindb/cardinality :einsteindb.cardinality/one]]"#).expect("transacted topograph");
+        let index = declare_composite_index(&conn.SQLite, &mut conn.topograph, &kw("test", "color"), &[kw("test", "color")]).expect("declared");
+
+        let topograph = ::einsteindb::read_einsteindb(&conn.SQLite).expect("read").topograph;
+        assert_eq!(topograph.composite_indexes, vec![index]);
+    }
+}
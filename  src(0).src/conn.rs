@@ -22,13 +22,37 @@ use std::sync::{
     Arc,
     Mutex,
 };
+use std::sync::atomic::{
+    AtomicBool,
+    AtomicU64,
+    Ordering,
+};
+
+use std::time::{
+    Duration,
+    Instant,
+};
 
+use chrono::{
+    DateTime,
+    FixedOffset,
+};
+use failure::Fail;
 use rusqlite;
 use rusqlite::{
     TransactionBehavior,
 };
+use rusqlite::backup::{
+    Backup,
+};
 
 use edn;
+use edn::{
+    Uuid,
+};
+use edn::causets::{
+    TempId,
+};
 
 pub use core_traits::{
     Attribute,
@@ -40,6 +64,7 @@ pub use core_traits::{
 };
 
 use einsteindb_core::{
+    CompositeIndex,
     HasSchema,
     Keyword,
     Schema,
@@ -53,11 +78,31 @@ use einsteindb_core::cache::{
 };
 
 use einsteindb_core::einsteindb;
+use einsteindb_core::causetids;
+use einsteindb_core::instant_options::{
+    OffsetEntity,
+};
+use einsteindb_core::retract_where::{
+    RetractChunk,
+    ValuePredicate,
+    next_retract_chunk,
+};
+use einsteindb_core::external_ids::{
+    ExternalIds,
+};
+use einsteindb_core::tx_builder::{
+    self,
+    TxBuilder,
+    TxMetadata,
+};
 use einsteindb_core::{
     InProgressObserverTransactWatcher,
     PartitionMap,
+    TempIdReservation,
+    TransactOptions,
     TxObservationService,
     TxObserver,
+    WriteMetricsSink,
 };
 
 use einsteindb_query_pull::{
@@ -71,6 +116,7 @@ use einsteindb_transaction::{
     Spacetime,
     InProgress,
     InProgressRead,
+    InProgressTransactWatcher,
 };
 
 use public_traits::errors::{
@@ -92,6 +138,158 @@ use einsteindb_transaction::query::{
     q_uncached,
 };
 
+/// The head of the main timeline a transaction was based on; see `Conn::transact_based_on`.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct HeadToken(pub Causetid);
+
+#[derive(Debug, Fail)]
+pub enum OptimisticError {
+    /// Another writer committed since the transaction's head token was taken.
+    #[fail(display = "conflict: based on tx {} but the head has moved to tx {}", _0, _1)]
+    Conflict(Causetid, Causetid),
+
+    #[fail(display = "{}", _0)]
+    Store(#[cause] einsteindbError),
+}
+
+impl From<einsteindbError> for OptimisticError {
+    fn from(error: einsteindbError) -> OptimisticError {
+        OptimisticError::Store(error)
+    }
+}
+
+/// How long `create_checkpoint` waits before retrying when the store is locked.
+const CHECKPOINT_BUSY_PAUSE: Duration = Duration::from_millis(10);
+
+/// The longest `wait_for_basis` sleeps between looks at the store's head.
+const BASIS_POLL_MAX_PAUSE: Duration = Duration::from_millis(50);
+
+/// Why `wait_for_basis` didn't see the transaction it waited for.
+#[derive(Debug, Fail)]
+pub enum BasisError {
+    /// The store's head was still before the transaction when the wait timed out.
+    #[fail(display = "waited {:?} for tx {} but the basis is still tx {}", timeout, wanted, basis)]
+    TimedOut { wanted: Causetid, basis: Causetid, timeout: Duration },
+
+    #[fail(display = "{}", _0)]
+    Store(#[cause] einsteindbError),
+}
+
+impl From<einsteindbError> for BasisError {
+    fn from(error: einsteindbError) -> BasisError {
+        BasisError::Store(error)
+    }
+}
+
+/// How many SQLite virtual machine instructions an interruptible operation runs between checks
+/// for cancellation and timeout.
+const INTERRUPT_CHECK_OPS: i32 = 1000;
+
+/// Why an interruptible operation didn't complete.
+#[derive(Debug, Fail)]
+pub enum CancellableError {
+    /// The operation's `CancellationToken` was cancelled, or `Conn::cancel_all` was called while it
+    /// ran.
+    #[fail(display = "operation cancelled")]
+    Cancelled,
+
+    #[fail(display = "operation timed out after {:?}", _0)]
+    TimedOut(Duration),
+
+    #[fail(display = "{}", _0)]
+    Store(#[cause] einsteindbError),
+}
+
+impl From<einsteindbError> for CancellableError {
+    fn from(error: einsteindbError) -> CancellableError {
+        CancellableError::Store(error)
+    }
+}
+
+/// Cancels the interruptible operations it's passed to.  Clones share the cancellation, so one
+/// token can be handed to an operation and kept to cancel it from another thread.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// How an interruptible operation, like `Conn::q_once_interruptible`, may be stopped early.
+#[derive(Clone, Debug, Default)]
+pub struct InterruptOptions {
+    /// Fail with `CancellableError::TimedOut` once the operation has run this long.
+    pub timeout: Option<Duration>,
+    pub token: Option<CancellationToken>,
+}
+
+/// Cancels every interruptible operation of a `Conn` running when `cancel_all` is called; see
+/// `Conn::cancel_handle`.
+#[derive(Clone, Debug)]
+pub struct CancelHandle {
+    epoch: Arc<AtomicU64>,
+}
+
+impl CancelHandle {
+    pub fn cancel_all(&self) {
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// The progress handler of one interruptible operation, installed on its SQLite connection.
+struct Interrupter {
+    interrupted: Arc<Mutex<Option<CancellableError>>>,
+}
+
+impl Interrupter {
+    /// Interrupt SQLite whenever `options` or `epoch` say the operation should stop.
+    fn install(SQLite: &rusqlite::Connection, epoch: &Arc<AtomicU64>, options: &InterruptOptions) -> Interrupter {
+        let interrupted = Arc::new(Mutex::new(None));
+
+        let started = Instant::now();
+        let (epoch, started_epoch) = (epoch.clone(), epoch.load(Ordering::SeqCst));
+        let (timeout, token) = (options.timeout, options.token.clone());
+        let handler_interrupted = interrupted.clone();
+        SQLite.progress_handler(INTERRUPT_CHECK_OPS, Some(move || {
+            let reason = if epoch.load(Ordering::SeqCst) != started_epoch || token.as_ref().map_or(false, |t| t.is_cancelled()) {
+                CancellableError::Cancelled
+            } else {
+                match timeout {
+                    Some(timeout) if started.elapsed() >= timeout => CancellableError::TimedOut(timeout),
+                    _ => return false,
+                }
+            };
+            *handler_interrupted.lock().unwrap() = Some(reason);
+            true
+        }));
+
+        Interrupter { interrupted }
+    }
+
+    /// Remove the handler, and blame a failure on the interruption if there was one.
+    fn finish<R>(self, SQLite: &rusqlite::Connection, result: Result<R>) -> ::std::result::Result<R, CancellableError> {
+        SQLite.progress_handler(0, None::<fn() -> bool>);
+        let interrupted = self.interrupted.lock().unwrap().take();
+        match (result, interrupted) {
+            (Ok(r), _) => Ok(r),
+            (Err(_), Some(reason)) => Err(reason),
+            (Err(e), None) => Err(CancellableError::Store(e)),
+        }
+    }
+}
+
 /// A mutable, safe reference to the current einsteindb store.
 pub struct Conn {
 
@@ -102,14 +300,31 @@ pub struct Conn {
     // TODO: maintain cache of query plans that could be shared across threads and invalidated when
     // the schema changes. #315.
     pub(crate) tx_observer_service: Mutex<TxObservationService>,
+
+    /// External ids already resolved to causetids.  See `resolve_external`.  Shared with the
+    /// transact options, so that transacts evict the external ids they retract.
+    external_ids: Arc<Mutex<ExternalIds>>,
+
+    /// Bumped by `cancel_all`; interruptible operations stop when it moves.
+    cancel_epoch: Arc<AtomicU64>,
+
+    /// What every transact on this `Conn` is started with.
+    transact_options: Mutex<TransactOptions>,
 }
 
 impl Conn {
     // Intentionally not public.
     fn new(partition_map: PartitionMap, schema: Schema) -> Conn {
+        let external_ids = Arc::new(Mutex::new(ExternalIds::default()));
         Conn {
             spacetime: Mutex::new(Spacetime::new(0, partition_map, Arc::new(schema), Default::default())),
             tx_observer_service: Mutex::new(TxObservationService::new()),
+            external_ids: external_ids.clone(),
+            cancel_epoch: Arc::new(AtomicU64::new(0)),
+            transact_options: Mutex::new(TransactOptions {
+                external_ids: Some(external_ids),
+                ..TransactOptions::default()
+            }),
         }
     }
 
@@ -118,6 +333,12 @@ impl Conn {
         Ok(Conn::new(einsteindb.partition_map, einsteindb.schema))
     }
 
+    /// Like `connect`, but never writes to `SQLite`: the store must already exist.
+    pub fn connect_read_only(SQLite: &rusqlite::Connection) -> Result<Conn> {
+        let einsteindb = einsteindb::read_current_version(SQLite)?;
+        Ok(Conn::new(einsteindb.partition_map, einsteindb.schema))
+    }
+
     /// Yield a clone of the current `Schema` instance.
     pub fn current_schema(&self) -> Arc<Schema> {
         // We always unwrap the mutex lock: if it's poisoned, this will propogate panics to all
@@ -146,6 +367,20 @@ impl Conn {
         spacetime.partition_map[":einsteindb.part/tx"].next_causetid() - 1
     }
 
+    /// Copy the store behind `SQLite` to `local_path`, replacing any store there, as of a single point
+    /// in time.
+    ///
+    /// The copy is made with SQLite's online backup API in a single step, which reads the whole store
+    /// in one read transaction: the copy holds every transaction committed before it started and none
+    /// committed after, and in WAL mode other connections keep transacting while it runs.  Open the
+    /// copy with `Store::open_checkpoint`.
+    pub fn create_checkpoint(&self, SQLite: &rusqlite::Connection, local_path: &str) -> Result<()> {
+        let mut checkpoint = rusqlite::Connection::open(local_path)?;
+        let backup = Backup::new(SQLite, &mut checkpoint)?;
+        backup.run_to_completion(-1, CHECKPOINT_BUSY_PAUSE, None)?;
+        Ok(())
+    }
+
     /// Query the einsteindb store, using the given connection and the current spacetime.
     pub fn q_once<T>(&self,
                      SQLite: &rusqlite::Connection,
@@ -270,7 +505,7 @@ impl Conn {
             cache: InProgressSQLiteAttributeCache::from_cache(cache_cow),
             use_caching: true,
             tx_observer: &self.tx_observer_service,
-            tx_observer_watcher: InProgressObserverTransactWatcher::new(),
+            tx_observer_watcher: InProgressObserverTransactWatcher::with_options(self.transact_options.lock().unwrap().clone()),
         })
     }
 
@@ -310,11 +545,411 @@ impl Conn {
 
         let mut in_progress = self.begin_transaction(SQLite)?;
         let report = in_progress.transact_causets(causets)?;
-        in_progress.commit()?;
+        commit_and_report(in_progress)?;
+
+        Ok(report)
+    }
+
+    /// Transact the causets collected by `builder`, annotations included.
+    pub fn transact_builder(&mut self,
+                            SQLite: &mut rusqlite::Connection,
+                            builder: TxBuilder) -> Result<TxReport> {
+        let mut in_progress = self.begin_transaction(SQLite)?;
+        let report = in_progress.transact_causets(builder.into_causets())?;
+        commit_and_report(in_progress)?;
+
+        Ok(report)
+    }
+
+    /// Reserve `n` fresh causetids in `partition`, committing the reservation before returning it.
+    /// Transact with it using `transact_with_reservation`, here or on any `Conn` to the same store.
+    pub fn reserve_tempids(&mut self,
+                           SQLite: &mut rusqlite::Connection,
+                           partition: &str,
+                           n: usize) -> Result<TempIdReservation> {
+        let mut in_progress = self.begin_transaction(SQLite)?;
+        let reservation = in_progress.reserve_tempids(partition, n)?;
+        commit_and_report(in_progress)?;
+
+        Ok(reservation)
+    }
+
+    /// Like `transact`, but allocate tempids that don't upsert from `reservation`, which must come
+    /// from `reserve_tempids`.
+    pub fn transact_with_reservation<B>(&mut self,
+                                        SQLite: &mut rusqlite::Connection,
+                                        transaction: B,
+                                        reservation: TempIdReservation) -> Result<TxReport> where B: Borrow<str> {
+        let mut in_progress = self.begin_transaction(SQLite)?;
+        let report = in_progress.transact_with_reservation(transaction, reservation)?;
+        commit_and_report(in_progress)?;
+
+        Ok(report)
+    }
+
+    /// The entities having every `(attribute, value)` of `filters`, in ascending order.  A declared
+    /// composite index covering the attributes answers the filter; see
+    /// `einsteindb_core::composite_index`.
+    pub fn entities_matching(&self,
+                             SQLite: &rusqlite::Connection,
+                             filters: &[(Keyword, TypedValue)]) -> Result<Vec<Causetid>> {
+        let schema = self.current_schema();
+        let mut resolved = Vec::with_capacity(filters.len());
+        for &(ref attribute, ref value) in filters {
+            let a = schema.get_causetid(attribute).ok_or_else(|| einsteindbError::UnknownAttribute(attribute.to_string()))?;
+            resolved.push((a.0, value.clone()));
+        }
+        Ok(einsteindb_core::composite_index::entities_matching(SQLite, &schema, &resolved)?)
+    }
+
+    /// Declare a composite index named `name` over `attributes`; see
+    /// `einsteindb_core::composite_index`.  The index is maintained by every subsequent transact.
+    pub fn declare_composite_index(&mut self,
+                                   SQLite: &mut rusqlite::Connection,
+                                   name: &Keyword,
+                                   attributes: &[Keyword]) -> Result<CompositeIndex> {
+        let mut in_progress = self.begin_transaction(SQLite)?;
+        let index = einsteindb_core::composite_index::declare_composite_index(&in_progress.transaction, &mut in_progress.schema, name, attributes)?;
+        commit_and_report(in_progress)?;
+
+        Ok(index)
+    }
+
+    /// What transaction `tx` asserted about itself; see `einsteindb_core::tx_builder`.
+    pub fn tx_metadata(&self,
+                       SQLite: &rusqlite::Connection,
+                       tx: Causetid) -> Result<TxMetadata> {
+        Ok(tx_builder::tx_metadata(SQLite, &self.current_schema(), tx)?)
+    }
+
+    /// The transactions annotated with `[attribute value]`, in order.
+    pub fn annotated_txs(&self,
+                         SQLite: &rusqlite::Connection,
+                         attribute: &Keyword,
+                         value: &TypedValue) -> Result<Vec<Causetid>> {
+        let schema = self.current_schema();
+        let a = schema.get_causetid(attribute).ok_or_else(|| einsteindbError::UnknownAttribute(attribute.to_string()))?;
+        Ok(tx_builder::annotated_txs(SQLite, &schema, a.0, value)?)
+    }
+
+    /// Like `transact`, and record the offsets the instants of `offsets` were written in, in the same
+    /// BerolinaSQL transaction: either the causets and their offsets are stored, or neither is.  Each
+    /// attribute must preserve offsets; see `einsteindb_core::instant_options`.
+    pub fn transact_with_offsets<B>(&mut self,
+                                    SQLite: &mut rusqlite::Connection,
+                                    transaction: B,
+                                    offsets: &[(OffsetEntity, Keyword, DateTime<FixedOffset>)]) -> Result<TxReport> where B: Borrow<str> {
+        let causets = edn::parse::causets(transaction.borrow())?;
+
+        let mut in_progress = self.begin_transaction(SQLite)?;
+        let report = in_progress.transact_causets(causets)?;
+        let mut resolved = Vec::with_capacity(offsets.len());
+        for &(ref e, ref attribute, zoned) in offsets {
+            let a = in_progress.schema.get_causetid(attribute).ok_or_else(|| einsteindbError::UnknownAttribute(attribute.to_string()))?;
+            resolved.push((e.clone(), a.0, zoned));
+        }
+        einsteindb_core::instant_options::record_transacted_offsets(&in_progress.transaction, &report.tempids, &resolved)?;
+        commit_and_report(in_progress)?;
 
         Ok(report)
     }
 
+    /// The causetid bearing `:einsteindb/externalId` `uuid`, if any.  Resolutions are cached.
+    pub fn resolve_external(&self,
+                            SQLite: &rusqlite::Connection,
+                            uuid: &Uuid) -> Result<Option<Causetid>> {
+        let mut external_ids = self.external_ids.lock().unwrap();
+        Ok(external_ids.resolve(SQLite, uuid)?)
+    }
+
+    /// The causetid bearing `:einsteindb/externalId` `uuid`, allocating one if there is none.
+    pub fn ensure_external(&mut self,
+                           SQLite: &mut rusqlite::Connection,
+                           uuid: &Uuid) -> Result<Causetid> {
+        if let Some(e) = self.resolve_external(SQLite, uuid)? {
+            return Ok(e);
+        }
+
+        let tempid = TempId::lightlike("external".to_string());
+        let mut builder = TxBuilder::new();
+        builder.add(tempid, causetids::EINSTEINDB_EXTERNAL_ID, TypedValue::Uuid(*uuid));
+        let report = self.transact_builder(SQLite, builder)?;
+
+        // The tempid either upserted, if the external id was transacted concurrently, or was
+        // allocated.
+        let e = report.tempids["external"];
+        self.external_ids.lock().unwrap().insert(*uuid, e);
+        Ok(e)
+    }
+
+    /// Retract `:einsteindb/externalId` `uuid` from the causet bearing it, if any.  Any transact
+    /// on this connection that retracts an external id, this one included, evicts it from the
+    /// cache.
+    pub fn retract_external(&mut self,
+                            SQLite: &mut rusqlite::Connection,
+                            uuid: &Uuid) -> Result<Option<Causetid>> {
+        let e = match einsteindb_core::external_ids::resolve_external(SQLite, uuid)? {
+            Some(e) => e,
+            None => return Ok(None),
+        };
+
+        let mut builder = TxBuilder::new();
+        builder.retract(e, causetids::EINSTEINDB_EXTERNAL_ID, TypedValue::Uuid(*uuid));
+        self.transact_builder(SQLite, builder)?;
+        Ok(Some(e))
+    }
+
+    /// Retract every causet of `attribute` whose value matches `predicate`, in transactions of at
+    /// most `chunk_size` causets each.  Each chunk is matched and retracted within one
+    /// `InProgress`, and committed before the next is matched; if a chunk fails, the chunks
+    /// before it stay committed.
+    pub fn retract_where(&mut self,
+                         SQLite: &mut rusqlite::Connection,
+                         attribute: &Keyword,
+                         predicate: &ValuePredicate,
+                         chunk_size: usize) -> Result<Vec<RetractChunk>> {
+        let mut chunks = vec![];
+        loop {
+            let mut in_progress = self.begin_transaction(SQLite)?;
+            let causets = next_retract_chunk(&in_progress.transaction, &in_progress.schema, attribute, predicate, chunk_size)?;
+            if causets.is_empty() {
+                in_progress.rollback()?;
+                break;
+            }
+
+            let retracted = causets.len();
+            let mut builder = TxBuilder::new();
+            for (e, a, v) in causets {
+                builder.retract(e, a, v);
+            }
+            let report = in_progress.transact_causets(builder.into_causets())?;
+            commit_and_report(in_progress)?;
+            chunks.push(RetractChunk {
+                tx_id: report.tx_id,
+                retracted,
+            });
+        }
+        Ok(chunks)
+    }
+
+    /// Transact each of `transactions` in order, as `transact` would, but commit them together:
+    /// one write lock and one sync for the whole group rather than one per transaction.  This
+    /// trades latency for throughput when ingesting.
+    ///
+    /// Each transaction is transacted once, under its own savepoint.  One that is malformed or
+    /// doesn't fit the store (see `einsteindb_core::is_transaction_error`) is rolled back to its
+    /// savepoint without taking the others down with it.  There is one result per transaction, in
+    /// order.  Any other error -- the store failing -- fails the whole group, none of which is
+    /// committed.
+    pub fn transact_group<B>(&mut self,
+                             SQLite: &mut rusqlite::Connection,
+                             transactions: &[B]) -> Result<Vec<Result<TxReport>>> where B: Borrow<str> {
+        let mut in_progress = self.begin_transaction(SQLite)?;
+        let mut results = Vec::with_capacity(transactions.len());
+        for transaction in transactions {
+            let causets = match edn::parse::causets(transaction.borrow()) {
+                Ok(causets) => causets,
+                Err(e) => {
+                    results.push(Err(e.into()));
+                    continue;
+                },
+            };
+
+            let partition_map = in_progress.partition_map.clone();
+            let schema = in_progress.schema.clone();
+            in_progress.transaction.execute_batch("SAVEPOINT group_member")?;
+            match in_progress.transact_causets(causets) {
+                Ok(report) => {
+                    in_progress.transaction.execute_batch("RELEASE group_member")?;
+                    results.push(Ok(report));
+                },
+                Err(e) => {
+                    if !is_transaction_error(&e) {
+                        return Err(e);
+                    }
+                    // Undo the transaction's writes, and forget what the transactor saw of it.
+                    in_progress.transaction.execute_batch("ROLLBACK TO group_member; RELEASE group_member")?;
+                    in_progress.partition_map = partition_map;
+                    in_progress.schema = schema;
+                    let txes = ::std::mem::replace(&mut in_progress.tx_observer_watcher.txes, Default::default());
+                    let write_metrics = ::std::mem::replace(&mut in_progress.tx_observer_watcher.write_metrics, Default::default());
+                    in_progress.tx_observer_watcher = InProgressObserverTransactWatcher::with_options(in_progress.tx_observer_watcher.options().clone());
+                    in_progress.tx_observer_watcher.txes = txes;
+                    in_progress.tx_observer_watcher.write_metrics = write_metrics;
+                    results.push(Err(e));
+                },
+            }
+        }
+        commit_and_report(in_progress)?;
+        Ok(results)
+    }
+
+    /// Return a token for the current head of the store's main timeline, to base a later
+    /// `transact_based_on` on.
+    pub fn head_token(&self, SQLite: &rusqlite::Connection) -> Result<HeadToken> {
+        Ok(HeadToken(einsteindb::head_tx(SQLite)?))
+    }
+
+    /// Like `transact`, but only if no transaction has been committed to the store since `based_on`
+    /// was taken, by this connection or any other, even in another process.  Otherwise fails with
+    /// `OptimisticError::Conflict` and writes nothing.
+    ///
+    /// The head is checked once the write lock is held, so no writer can slip in between the check
+    /// and the commit.  After a conflict this `Conn` has read the partition map and schema the other
+    /// writers left, so the caller can re-read, take a new token, and try again.
+    pub fn transact_based_on<B>(&mut self,
+                                SQLite: &mut rusqlite::Connection,
+                                based_on: HeadToken,
+                                transaction: B) -> ::std::result::Result<TxReport, OptimisticError> where B: Borrow<str> {
+        match self.try_transact_based_on(SQLite, based_on, transaction.borrow())? {
+            Ok(report) => Ok(report),
+            Err(head) => {
+                self.refresh(SQLite)?;
+                Err(OptimisticError::Conflict(based_on.0, head))
+            },
+        }
+    }
+
+    /// Transact `transaction` if the head is still `based_on`, or return the head that moved.
+    fn try_transact_based_on(&mut self,
+                             SQLite: &mut rusqlite::Connection,
+                             based_on: HeadToken,
+                             transaction: &str) -> Result<::std::result::Result<TxReport, Causetid>> {
+        let causets = edn::parse::causets(transaction)?;
+
+        let mut in_progress = self.begin_transaction(SQLite)?;
+        let head = einsteindb::head_tx(&in_progress.transaction)?;
+        if head != based_on.0 {
+            in_progress.rollback()?;
+            return Ok(Err(head));
+        }
+        let report = in_progress.transact_causets(causets)?;
+        commit_and_report(in_progress)?;
+
+        Ok(Ok(report))
+    }
+
+    /// The id of the latest transaction committed to the store's main timeline, by this connection
+    /// or any other.  A reader that has seen this basis can hand it to others, which
+    /// `wait_for_basis` before they serve a query, so that they see at least what it saw.
+    pub fn basis_t(&self, SQLite: &rusqlite::Connection) -> Result<Causetid> {
+        einsteindb::head_tx(SQLite)
+    }
+
+    /// Wait until the store's basis is at least `t`, then re-read the partition map and schema if
+    /// this `Conn` is behind them, and return the basis.  Fails with `BasisError::TimedOut` if
+    /// `timeout` passes first.
+    ///
+    /// `SQLite` mustn't be in a read transaction, which would keep it from seeing newer commits.
+    pub fn wait_for_basis(&mut self,
+                          SQLite: &rusqlite::Connection,
+                          t: Causetid,
+                          timeout: Duration) -> ::std::result::Result<Causetid, BasisError> {
+        let started = Instant::now();
+        let mut pause = Duration::from_millis(1);
+        loop {
+            let basis = self.basis_t(SQLite)?;
+            if basis >= t {
+                if self.last_tx_id() < basis {
+                    self.refresh(SQLite)?;
+                }
+                return Ok(basis);
+            }
+            let waited = started.elapsed();
+            if waited >= timeout {
+                return Err(BasisError::TimedOut { wanted: t, basis, timeout });
+            }
+            ::std::thread::sleep(pause.min(timeout - waited));
+            pause = (pause * 2).min(BASIS_POLL_MAX_PAUSE);
+        }
+    }
+
+    /// Re-read the partition map and schema from the store, after other writers have changed them.
+    fn refresh(&mut self, SQLite: &rusqlite::Connection) -> Result<()> {
+        let einsteindb = einsteindb::read_current_version(SQLite)?;
+        let mut spacetime = self.spacetime.lock().unwrap();
+        spacetime.generation += 1;
+        spacetime.partition_map = einsteindb.partition_map;
+        spacetime.schema = Arc::new(einsteindb.schema);
+        Ok(())
+    }
+
+    /// Set the duration after which a transact on this `Conn` is considered slow, or disable slow
+    /// transaction logging with `None`.  See `einsteindb_core::slow_tx_log`.
+    pub fn set_slow_tx_threshold(&self, threshold: Option<Duration>) {
+        self.transact_options.lock().unwrap().slow_tx_threshold = threshold;
+    }
+
+    /// The duration after which a transact on this `Conn` is considered slow, if slow transaction
+    /// logging is enabled.
+    pub fn slow_tx_threshold(&self) -> Option<Duration> {
+        self.transact_options.lock().unwrap().slow_tx_threshold
+    }
+
+    /// Enable or disable tracing of upsert resolution in transacts on this `Conn`.  See
+    /// `einsteindb_core::upsert_trace`.
+    pub fn set_upsert_tracing(&self, enabled: bool) {
+        self.transact_options.lock().unwrap().trace_upserts = enabled;
+    }
+
+    /// Whether transacts on this `Conn` trace upsert resolution.
+    pub fn upsert_tracing(&self) -> bool {
+        self.transact_options.lock().unwrap().trace_upserts
+    }
+
+    /// Report what each transaction this `Conn` commits wrote to `sink`, or stop counting with
+    /// `None`.  See `einsteindb_core::write_metrics`.
+    pub fn set_write_metrics_sink(&self, sink: Option<Arc<dyn WriteMetricsSink>>) {
+        self.transact_options.lock().unwrap().write_metrics_sink = sink;
+    }
+
+    /// Where this `Conn` reports what its transactions wrote, if anywhere.
+    pub fn write_metrics_sink(&self) -> Option<Arc<dyn WriteMetricsSink>> {
+        self.transact_options.lock().unwrap().write_metrics_sink.clone()
+    }
+
+    /// Cancel every interruptible operation running on this `Conn`.  Operations started afterwards
+    /// are unaffected.
+    pub fn cancel_all(&self) {
+        self.cancel_handle().cancel_all();
+    }
+
+    /// A handle to `cancel_all` from other threads, while this `Conn` is borrowed by the operations
+    /// to cancel.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle { epoch: self.cancel_epoch.clone() }
+    }
+
+    /// Like `q_once`, but SQLite is interrupted once `options` time out or cancel, or on
+    /// `cancel_all`.
+    pub fn q_once_interruptible<T>(&self,
+                                   SQLite: &rusqlite::Connection,
+                                   query: &str,
+                                   inputs: T,
+                                   options: &InterruptOptions) -> ::std::result::Result<QueryOutput, CancellableError>
+        where T: Into<Option<QueryInputs>> {
+        if options.token.as_ref().map_or(false, |t| t.is_cancelled()) {
+            return Err(CancellableError::Cancelled);
+        }
+        let interrupter = Interrupter::install(SQLite, &self.cancel_epoch, options);
+        let result = self.q_once(SQLite, query, inputs);
+        interrupter.finish(SQLite, result)
+    }
+
+    /// Like `transact`, but SQLite is interrupted once `options` time out or cancel, or on
+    /// `cancel_all`.  An interrupted transaction is rolled back.
+    pub fn transact_interruptible<B>(&mut self,
+                                     SQLite: &mut rusqlite::Connection,
+                                     transaction: B,
+                                     options: &InterruptOptions) -> ::std::result::Result<TxReport, CancellableError> where B: Borrow<str> {
+        if options.token.as_ref().map_or(false, |t| t.is_cancelled()) {
+            return Err(CancellableError::Cancelled);
+        }
+        let interrupter = Interrupter::install(SQLite, &self.cancel_epoch, options);
+        let result = self.transact(SQLite, transaction);
+        interrupter.finish(SQLite, result)
+    }
+
     /// Adds or removes the values of a given attribute to an in-memory cache.
     /// The attribute should be a isoliton_namespaceable string: e.g., `:foo/bar`.
     /// `cache_action` determines if the attribute should be added or removed from the cache.
@@ -359,6 +994,212 @@ impl Conn {
     pub fn unregister_observer(&mut self, key: &String) {
         self.tx_observer_service.lock().unwrap().deregister(key);
     }
+
+    /// Begin a speculative transaction: see `Speculative`.
+    pub fn begin_speculative<'m, 'conn>(&'m mut self, SQLite: &'conn mut rusqlite::Connection) -> Result<Speculative<'m, 'conn>> {
+        let in_progress = self.begin_transaction(SQLite)?;
+        in_progress.transaction.execute_batch("SAVEPOINT speculative")?;
+        let partition_map = in_progress.partition_map.clone();
+        let schema = in_progress.schema.clone();
+        Ok(Speculative {
+            in_progress,
+            partition_map,
+            schema,
+            tempids: BTreeMap::default(),
+            causets: BTreeMap::default(),
+        })
+    }
+}
+
+/// Whether `error` is the fault of the transaction being transacted rather than of the store.
+fn is_transaction_error(error: &einsteindbError) -> bool {
+    match *error {
+        einsteindbError::DbError(ref e) => einsteindb_core::is_transaction_error(e),
+        einsteindbError::EdnParseError(_) |
+        einsteindbError::UnknownAttribute(_) => true,
+        _ => false,
+    }
+}
+
+/// Commit `in_progress`, then report what its transacts wrote to the write metrics sink, if any.
+fn commit_and_report(mut in_progress: InProgress) -> Result<()> {
+    let write_metrics = in_progress.tx_observer_watcher.take_write_metrics();
+    in_progress.commit()?;
+    write_metrics.report();
+    Ok(())
+}
+
+/// Transacting with causetids set aside ahead of time; see `einsteindb_core::TempIdReservation`.
+pub trait TempIdReserving {
+    /// Reserve `n` fresh causetids in `partition` for the tempids of a later transact.  The
+    /// reservation is written to the store with this transaction.
+    fn reserve_tempids(&mut self, partition: &str, n: usize) -> Result<TempIdReservation>;
+
+    /// Like `transact`, but allocate tempids that don't upsert from `reservation`.  Retrying with
+    /// the same reservation allocates the same causetids.
+    fn transact_with_reservation<B>(&mut self, transaction: B, reservation: TempIdReservation) -> Result<TxReport> where B: Borrow<str>;
+}
+
+impl<'a, 'c> TempIdReserving for InProgress<'a, 'c> {
+    fn reserve_tempids(&mut self, partition: &str, n: usize) -> Result<TempIdReservation> {
+        TempIdReservation::reserve(&self.transaction, &mut self.partition_map, partition, n)
+            .map_err(|e| einsteindbError::DbError(e.into()))
+    }
+
+    fn transact_with_reservation<B>(&mut self, transaction: B, reservation: TempIdReservation) -> Result<TxReport> where B: Borrow<str> {
+        let causets = edn::parse::causets(transaction.borrow())?;
+        let (report, next_partition_map, next_schema, _watcher) =
+            einsteindb_core::transact_with_reservation(&self.transaction,
+                                                       self.partition_map.clone(),
+                                                       &self.schema,
+                                                       &self.schema,
+                                                       InProgressTransactWatcher::new(
+                                                           &mut self.tx_observer_watcher,
+                                                           self.cache.transact_watcher()),
+                                                       causets,
+                                                       reservation)?;
+        self.partition_map = next_partition_map;
+        if let Some(schema) = next_schema {
+            self.schema = schema;
+        }
+        Ok(report)
+    }
+}
+
+/// A what-if transaction.
+///
+/// Each `transact` is applied to an uncommitted view of the store, so queries against `view` see
+/// every speculative write so far.  The net effect of the transacts is kept in memory as a set of
+/// causets to assert and retract.  `commit` throws the view away and transacts that net effect as
+/// one real transaction; `discard`, or dropping the `Speculative`, throws it away and writes
+/// nothing.
+///
+/// Entities allocated by speculative transacts get new causetids when committed.  The committed
+/// `TxReport` maps the tempids of every transact to their committed causetids.  Since the net effect
+/// is committed as a single transaction, a speculative transaction can't both install an attribute
+/// and use it.
+pub struct Speculative<'a, 'c> {
+    in_progress: InProgress<'a, 'c>,
+
+    /// The partition map and schema when the speculative transaction began.
+    partition_map: PartitionMap,
+    schema: Schema,
+
+    tempids: BTreeMap<String, Causetid>,
+
+    /// The net effect so far: `true` to assert, `false` to retract.
+    causets: BTreeMap<(Causetid, Causetid, TypedValue), bool>,
+}
+
+impl<'a, 'c> Speculative<'a, 'c> {
+    /// The store as it would be if the speculative transaction were committed.
+    pub fn view(&self) -> &InProgress<'a, 'c> {
+        &self.in_progress
+    }
+
+    pub fn transact<B>(&mut self, transaction: B) -> Result<TxReport> where B: Borrow<str> {
+        let report = self.in_progress.transact(transaction)?;
+        self.record(&report)?;
+        Ok(report)
+    }
+
+    /// The net causets asserted (`true`) and retracted (`false`) so far, ordered by `[e a v]`.
+    pub fn causets(&self) -> Vec<(Causetid, Causetid, TypedValue, bool)> {
+        self.causets.iter().map(|(&(e, a, ref v), &added)| (e, a, v.clone(), added)).collect()
+    }
+
+    fn record(&mut self, report: &TxReport) -> Result<()> {
+        for (tempid, &e) in report.tempids.iter() {
+            self.tempids.insert(tempid.clone(), e);
+        }
+
+        // The transaction's own causets, such as its :einsteindb/txInstant, aren't part of its effect.
+        let rows: Vec<(Causetid, Causetid, rusqlite::types::Value, i32, bool)> = {
+            let mut stmt = self.in_progress.transaction.prepare_cached("SELECT e, a, v, value_type_tag, added FROM transactions WHERE tx = ? AND e IS NOT ? ORDER BY e, a, v, added")?;
+            let rows: ::std::result::Result<Vec<_>, rusqlite::Error> = stmt.query_and_then(&[&report.tx_id, &report.tx_id], |row| {
+                Ok((row.get_checked(0)?, row.get_checked(1)?, row.get_checked(2)?, row.get_checked(3)?, row.get_checked(4)?))
+            })?.collect();
+            rows?
+        };
+
+        for (e, a, v, value_type_tag, added) in rows {
+            let fulltext = self.in_progress.schema.attribute_for_causetid(a).map_or(false, |attribute| attribute.fulltext);
+            let v = match v {
+                // Fulltext values are stored as rowids into `fulltext_values`.
+                rusqlite::types::Value::Integer(rowid) if fulltext => {
+                    let text: String = self.in_progress.transaction.query_row("SELECT text FROM fulltext_values WHERE rowid = ?", &[&rowid], |row| row.get(0))?;
+                    TypedValue::typed_string(text)
+                },
+                v => <TypedValue as einsteindb::TypedBerolinaSQLValue>::from_BerolinaSQL_value_pair(v, value_type_tag)?,
+            };
+
+            let key = (e, a, v);
+            match self.causets.get(&key).cloned() {
+                // Asserting what was retracted, or retracting what was asserted, cancels out.
+                Some(previous) if previous != added => { self.causets.remove(&key); },
+                _ => { self.causets.insert(key, added); },
+            }
+        }
+        Ok(())
+    }
+
+    /// Return true if `e` was allocated by a speculative transact.
+    fn is_new(&self, e: Causetid) -> bool {
+        self.partition_map.values().any(|partition| e >= partition.next_causetid() && e < partition.end)
+    }
+
+    fn tempid_for(e: Causetid) -> String {
+        format!("speculative-{}", e)
+    }
+
+    fn to_edn(&self) -> edn::Value {
+        let add = edn::Value::Keyword(edn::Keyword::isoliton_namespaceable("einsteindb", "add"));
+        let retract = edn::Value::Keyword(edn::Keyword::isoliton_namespaceable("einsteindb", "retract"));
+        let place = |e: Causetid| if self.is_new(e) { edn::Value::Text(Self::tempid_for(e)) } else { edn::Value::Integer(e) };
+
+        let terms = self.causets.iter()
+            .map(|(&(e, a, ref v), &added)| {
+                let v = match v {
+                    &TypedValue::Ref(r) => place(r),
+                    v => <TypedValue as einsteindb::TypedBerolinaSQLValue>::to_edn_value_pair(v).0,
+                };
+                edn::Value::Vector(vec![if added { add.clone() } else { retract.clone() }, place(e), edn::Value::Integer(a), v])
+            })
+            .collect();
+        edn::Value::Vector(terms)
+    }
+
+    /// Transact the net effect of the speculative transacts as one transaction, and commit it.
+    pub fn commit(mut self) -> Result<TxReport> {
+        let transaction = self.to_edn().to_string();
+
+        // Rewind to where we began.
+        self.in_progress.transaction.execute_batch("ROLLBACK TO speculative; RELEASE speculative")?;
+        self.in_progress.partition_map = self.partition_map.clone();
+        self.in_progress.schema = self.schema.clone();
+        self.in_progress.cache = InProgressSQLiteAttributeCache::from_cache(self.in_progress.mutex.lock().unwrap().attribute_cache.clone());
+        self.in_progress.tx_observer_watcher = InProgressObserverTransactWatcher::with_options(self.in_progress.tx_observer_watcher.options().clone());
+
+        let mut report = self.in_progress.transact(transaction)?;
+
+        let mut tempids = BTreeMap::default();
+        for (tempid, &e) in self.tempids.iter() {
+            if !self.is_new(e) {
+                tempids.insert(tempid.clone(), e);
+            } else if let Some(&committed) = report.tempids.get(&Self::tempid_for(e)) {
+                tempids.insert(tempid.clone(), committed);
+            }
+        }
+        report.tempids = tempids;
+
+        commit_and_report(self.in_progress)?;
+        Ok(report)
+    }
+
+    /// Throw away the speculative transacts.
+    pub fn discard(self) -> Result<()> {
+        self.in_progress.rollback()
+    }
 }
 
 #[cfg(test)]
@@ -497,6 +1338,67 @@ mod tests {
         assert_eq!(tempid_offset + 3, tempid_offset_after);
     }
 
+    #[test]
+    fn test_speculative() {
+        let mut SQLite = einsteindb::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut SQLite).unwrap();
+
+        conn.transact(&mut SQLite, r#"[
+            [:einsteindb/add "s" :einsteindb/solitonid :foo/name]
+            [:einsteindb/add "s" :einsteindb/valueType :einsteindb.type/string]
+            [:einsteindb/add "s" :einsteindb/cardinality :einsteindb.cardinality/one]
+            [:einsteindb/add "f" :einsteindb/solitonid :foo/friend]
+            [:einsteindb/add "f" :einsteindb/valueType :einsteindb.type/ref]
+            [:einsteindb/add "f" :einsteindb/cardinality :einsteindb.cardinality/many]
+        ]"#).expect("transacted schema");
+        let report = conn.transact(&mut SQLite, r#"[[:einsteindb/add "x" :foo/name "existing"]]"#).expect("transacted");
+        let existing = report.tempids["x"];
+
+        let last_tx = conn.last_tx_id();
+        let next = get_next_causetid(&conn);
+        let query = "[:find ?n . :where [?x :foo/friend ?y] [?y :foo/name ?n]]";
+
+        // Discarding writes nothing.
+        {
+            let mut speculative = conn.begin_speculative(&mut SQLite).expect("begun");
+            speculative.transact(format!(r#"[[:einsteindb/add {} :foo/friend {}]]"#, existing, existing)).expect("transacted");
+            speculative.discard().expect("discarded");
+        }
+        assert_eq!(conn.last_tx_id(), last_tx);
+        assert_eq!(conn.q_once(&SQLite, query, None).expect("query").results, QueryResults::Scalar(None));
+
+        let report = {
+            let mut speculative = conn.begin_speculative(&mut SQLite).expect("begun");
+            let first = speculative.transact(format!(r#"[[:einsteindb/add "a" :foo/name "alice"]
+                                                         [:einsteindb/add {} :foo/friend "a"]]"#, existing)).expect("transacted");
+            let alice = first.tempids["a"];
+
+            // Speculative writes are visible to speculative reads.
+            let during = speculative.view().q_once(query, None).expect("query");
+            assert_eq!(during.results, QueryResults::Scalar(Some(TypedValue::typed_string("alice").into())));
+
+            // Later transacts can refer to, and undo, earlier ones.
+            speculative.transact(format!(r#"[[:einsteindb/add "b" :foo/name "bob"]
+                                             [:einsteindb/add {} :foo/friend "b"]
+                                             [:einsteindb/retract {} :foo/friend {}]
+                                             [:einsteindb/retract {} :foo/name "alice"]]"#, existing, existing, alice, alice)).expect("transacted");
+            let causets = speculative.causets();
+            assert_eq!(causets.len(), 2);
+            assert!(causets.iter().all(|&(_, _, _, added)| added));
+
+            speculative.commit().expect("committed")
+        };
+
+        // One real transaction, allocating only what survived.
+        assert_eq!(conn.last_tx_id(), last_tx + 1);
+        assert_eq!(report.tx_id, last_tx + 1);
+        assert_eq!(report.tempids.get("a"), None);
+        assert_eq!(report.tempids["b"], next);
+        assert_eq!(get_next_causetid(&conn), next + 1);
+        assert_eq!(conn.q_once(&SQLite, query, None).expect("query").results,
+                   QueryResults::Scalar(Some(TypedValue::typed_string("bob").into())));
+    }
+
     #[test]
     fn test_simple_prepared_query() {
         let mut c = einsteindb::new_connection("").expect("Couldn't open conn.");
@@ -534,6 +1436,242 @@ mod tests {
         assert_eq!(yeses_again.results, QueryResults::Coll(vec![TypedValue::Ref(yes).into()]));
     }
 
+    #[test]
+    fn test_transact_based_on() {
+        let dir = tempfile::Builder::new().prefix("optimistic").tempdir().expect("tempdir");
+        let local_path = dir.path().join("store.einsteindb");
+
+        // Two writers on one store, as if in two processes.
+        let mut sqlite_a = einsteindb::new_connection(&local_path).unwrap();
+        let mut conn_a = Conn::connect(&mut sqlite_a).unwrap();
+        let mut sqlite_b = einsteindb::new_connection(&local_path).unwrap();
+        let mut conn_b = Conn::connect(&mut sqlite_b).unwrap();
+
+        let token = conn_a.head_token(&sqlite_a).expect("head");
+        assert_eq!(token, conn_b.head_token(&sqlite_b).expect("head"));
+
+        // B commits first, so A's transaction conflicts and writes nothing.
+        let report = conn_b.transact(&mut sqlite_b, r#"[[:einsteindb/add "b" :einsteindb/solitonid :test/b]]"#).expect("transacted");
+        match conn_a.transact_based_on(&mut sqlite_a, token, r#"[[:einsteindb/add "a" :einsteindb/solitonid :test/a]]"#) {
+            Err(OptimisticError::Conflict(based_on, head)) => {
+                assert_eq!(based_on, token.0);
+                assert_eq!(head, report.tx_id);
+            },
+            x => panic!("expected conflict, got {:?}", x),
+        }
+        assert_eq!(conn_a.head_token(&sqlite_a).expect("head"), HeadToken(report.tx_id));
+
+        // Retrying from the new head succeeds, without colliding with B's causetids.
+        let token = conn_a.head_token(&sqlite_a).expect("head");
+        let retried = conn_a.transact_based_on(&mut sqlite_a, token, r#"[[:einsteindb/add "a" :einsteindb/solitonid :test/a]]"#).expect("transacted");
+        assert!(retried.tx_id > report.tx_id);
+        assert_ne!(retried.tempids["a"], report.tempids["b"]);
+        assert!(conn_a.current_schema().get_causetid(&Keyword::isoliton_namespaceable("test", "b")).is_some());
+    }
+
+    #[test]
+    fn test_transact_group() {
+        let dir = tempfile::Builder::new().prefix("group").tempdir().expect("tempdir");
+        let local_path = dir.path().join("store.einsteindb");
+        let mut SQLite = einsteindb::new_connection(&local_path).unwrap();
+        let mut conn = Conn::connect(&mut SQLite).unwrap();
+
+        einsteindb::set_durability(&SQLite, einsteindb::Durability::AsyncBatched).expect("durability");
+        assert_eq!(einsteindb::durability(&SQLite).expect("durability"), einsteindb::Durability::AsyncBatched);
+
+        let results = conn.transact_group(&mut SQLite, &[
+            r#"[[:einsteindb/add "a" :einsteindb/solitonid :test/a]]"#,
+            r#"[[:einsteindb/add "b" :einsteindb/solitonid :test/b] [:einsteindb/add "b" :test/unknown 1]]"#,
+            r#"[[:einsteindb/add "c" :einsteindb/solitonid :test/c"#,
+            r#"[[:einsteindb/add "d" :einsteindb/solitonid :test/d]]"#,
+        ]).expect("committed");
+        assert_eq!(results.len(), 4);
+        assert!(results[1].is_err());
+        assert!(results[2].is_err());
+        let a = results[0].as_ref().expect("transacted a");
+        let d = results[3].as_ref().expect("transacted d");
+        assert_eq!(d.tx_id, a.tx_id + 1);
+
+        einsteindb::sync_commits(&SQLite).expect("synced");
+        assert_eq!(einsteindb::durability(&SQLite).expect("durability"), einsteindb::Durability::AsyncBatched);
+
+        let schema = conn.current_schema();
+        assert!(schema.get_causetid(&Keyword::isoliton_namespaceable("test", "a")).is_some());
+        assert!(schema.get_causetid(&Keyword::isoliton_namespaceable("test", "b")).is_none());
+        assert!(schema.get_causetid(&Keyword::isoliton_namespaceable("test", "d")).is_some());
+    }
+
+    #[test]
+    fn test_ensure_external() {
+        let mut SQLite = einsteindb::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut SQLite).unwrap();
+
+        let uuid = Uuid::parse_str("55555555-5555-5555-5555-555555555555").expect("uuid");
+        assert_eq!(conn.resolve_external(&SQLite, &uuid).expect("resolved"), None);
+
+        let e = conn.ensure_external(&mut SQLite, &uuid).expect("ensured");
+        assert_eq!(conn.ensure_external(&mut SQLite, &uuid).expect("ensured"), e);
+        assert_eq!(conn.resolve_external(&SQLite, &uuid).expect("resolved"), Some(e));
+
+        assert_eq!(conn.retract_external(&mut SQLite, &uuid).expect("retracted"), Some(e));
+        assert_eq!(conn.resolve_external(&SQLite, &uuid).expect("resolved"), None);
+        assert_eq!(conn.retract_external(&mut SQLite, &uuid).expect("retracted"), None);
+
+        // A retracted external id is allocated afresh.
+        assert!(conn.ensure_external(&mut SQLite, &uuid).expect("ensured") != e);
+    }
+
+    #[test]
+    fn test_transacts_evict_external_ids() {
+        let mut SQLite = einsteindb::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut SQLite).unwrap();
+
+        let uuid = Uuid::parse_str("55555555-5555-5555-5555-555555555555").expect("uuid");
+        let other = Uuid::parse_str("66666666-6666-6666-6666-666666666666").expect("uuid");
+
+        // Retracting by transacting.
+        let e = conn.ensure_external(&mut SQLite, &uuid).expect("ensured");
+        assert_eq!(conn.resolve_external(&SQLite, &uuid).expect("resolved"), Some(e));
+        conn.transact(&mut SQLite, format!("[[:einsteindb/retract {} :einsteindb/externalId #uuid \"{}\"]]", e, uuid)).expect("retracted");
+        assert_eq!(conn.resolve_external(&SQLite, &uuid).expect("resolved"), None);
+
+        // Replacing the cardinality one value.
+        let f = conn.ensure_external(&mut SQLite, &uuid).expect("ensured");
+        assert_eq!(conn.resolve_external(&SQLite, &uuid).expect("resolved"), Some(f));
+        conn.transact(&mut SQLite, format!("[[:einsteindb/add {} :einsteindb/externalId #uuid \"{}\"]]", f, other)).expect("replaced");
+        assert_eq!(conn.resolve_external(&SQLite, &uuid).expect("resolved"), None);
+        assert_eq!(conn.resolve_external(&SQLite, &other).expect("resolved"), Some(f));
+    }
+
+    #[test]
+    fn test_tx_metadata() {
+        let mut SQLite = einsteindb::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut SQLite).unwrap();
+        conn.transact(&mut SQLite, r#"[{:einsteindb/solitonid :tx/user
+                                        :einsteindb/valueType :einsteindb.type/string
+                                        :einsteindb/cardinality :einsteindb.cardinality/one
+                                        :einsteindb/index true}]"#).expect("transacted schema");
+
+        let user = kw!(:tx/user);
+        let mut builder = TxBuilder::new();
+        builder.annotate(user.clone(), TypedValue::typed_string("alice"));
+        let report = conn.transact_builder(&mut SQLite, builder).expect("transacted");
+
+        let metadata = conn.tx_metadata(&SQLite, report.tx_id).expect("read metadata");
+        assert_eq!(metadata.tx_instant, Some(report.tx_instant));
+        let a = conn.current_schema().get_causetid(&user).expect("tx/user").0;
+        assert_eq!(metadata.get(a), Some(&TypedValue::typed_string("alice")));
+
+        assert_eq!(conn.annotated_txs(&SQLite, &user, &TypedValue::typed_string("alice")).expect("found"), vec![report.tx_id]);
+        match conn.annotated_txs(&SQLite, &kw!(:tx/unknown), &TypedValue::typed_string("alice")) {
+            Err(einsteindbError::UnknownAttribute(_)) => {},
+            x => panic!("expected an unknown attribute, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_transact_with_offsets() {
+        let mut SQLite = einsteindb::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut SQLite).unwrap();
+        conn.transact(&mut SQLite, r#"[{:einsteindb/solitonid :test/seen
+                                        :einsteindb/valueType :einsteindb.type/instant
+                                        :einsteindb/cardinality :einsteindb.cardinality/one}]"#).expect("transacted schema");
+        let seen = kw!(:test/seen);
+        let a = conn.current_schema().get_causetid(&seen).expect("seen").0;
+        einsteindb_core::instant_options::declare_instant_options(&SQLite, &conn.current_schema(), &seen, einsteindb_core::instant_options::InstantOptions {
+            preserve_offset: true,
+            ..Default::default()
+        }).expect("declared");
+
+        let local = DateTime::parse_from_rfc3339("2017-06-16T02:56:41.257123+02:00").expect("instant");
+        let report = conn.transact_with_offsets(&mut SQLite,
+                                                r#"[[:einsteindb/add "x" :test/seen #inst "2017-06-16T00:56:41.257123Z"]]"#,
+                                                &[(OffsetEntity::TempId("x".to_string()), seen.clone(), local)]).expect("transacted");
+        let x = report.tempids["x"];
+        let zoned = einsteindb_core::instant_options::zoned_instant(&SQLite, x, a, local.with_timezone(&::chrono::Utc)).expect("zoned");
+        assert_eq!(zoned, local);
+
+        // An offset that can't be recorded rolls the causets back with it.
+        let before = conn.last_tx_id();
+        let local = DateTime::parse_from_rfc3339("2018-01-01T00:00:00+01:00").expect("instant");
+        assert!(conn.transact_with_offsets(&mut SQLite,
+                                           r#"[[:einsteindb/add "y" :test/seen #inst "2017-12-31T23:00:00Z"]]"#,
+                                           &[(OffsetEntity::TempId("z".to_string()), seen, local)]).is_err());
+        assert_eq!(conn.last_tx_id(), before);
+    }
+
+    #[test]
+    fn test_transact_with_reservation() {
+        let mut SQLite = einsteindb::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut SQLite).unwrap();
+
+        let reservation = conn.reserve_tempids(&mut SQLite, ":einsteindb.part/user", 2).expect("reserved");
+        assert_eq!(get_next_causetid(&conn), reservation.range.end);
+
+        // Other transacts allocate past the reservation, even on a fresh connection.
+        let mut other = Conn::connect(&mut SQLite).unwrap();
+        let report = other.transact(&mut SQLite, r#"[[:einsteindb/add "o" :einsteindb.topograph/version 1]]"#).expect("transacted");
+        assert!(report.tempids["o"] >= reservation.range.end);
+
+        let report = conn.transact_with_reservation(&mut SQLite,
+                                                    r#"[[:einsteindb/add "a" :einsteindb.topograph/version 2]]"#,
+                                                    reservation.clone()).expect("transacted");
+        assert!(reservation.range.contains(report.tempids["a"]));
+
+        assert!(conn.reserve_tempids(&mut SQLite, ":einsteindb.part/unknown", 1).is_err());
+    }
+
+    #[test]
+    fn test_declare_composite_index() {
+        let mut SQLite = einsteindb::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut SQLite).unwrap();
+        conn.transact(&mut SQLite, r#"[{:einsteindb/solitonid :test/color
+                                        :einsteindb/valueType :einsteindb.type/string
+                                        :einsteindb/cardinality :einsteindb.cardinality/one}
+                                       {:einsteindb/solitonid :test/size
+                                        :einsteindb/valueType :einsteindb.type/long
+                                        :einsteindb/cardinality :einsteindb.cardinality/one}]"#).expect("transacted schema");
+
+        let index = conn.declare_composite_index(&mut SQLite, &kw!(:test/color-size), &[kw!(:test/color), kw!(:test/size)]).expect("declared");
+        assert_eq!(conn.current_schema().composite_indexes, vec![index]);
+
+        // Transacts after the declaration maintain the index.
+        let report = conn.transact(&mut SQLite, r#"[{:einsteindb/id "a" :test/color "red" :test/size 1}
+                                                    {:einsteindb/id "b" :test/color "red" :test/size 2}]"#).expect("transacted");
+        let filters = [(kw!(:test/size), TypedValue::Long(2)), (kw!(:test/color), TypedValue::typed_string("red"))];
+        assert_eq!(conn.entities_matching(&SQLite, &filters).expect("matched"), vec![report.tempids["b"]]);
+        let rows: i64 = SQLite.query_row("SELECT count(*) FROM composite_index_1", &[], |row| row.get(0)).expect("counted");
+        assert_eq!(rows, 2);
+    }
+
+    #[test]
+    fn test_retract_where() {
+        let mut SQLite = einsteindb::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut SQLite).unwrap();
+        conn.transact(&mut SQLite, r#"[{:einsteindb/solitonid :test/score
+                                        :einsteindb/valueType :einsteindb.type/long
+                                        :einsteindb/cardinality :einsteindb.cardinality/many}]"#).expect("transacted schema");
+        conn.transact(&mut SQLite, r#"[[:einsteindb/add "a" :test/score 1]
+                                       [:einsteindb/add "a" :test/score 5]
+                                       [:einsteindb/add "b" :test/score 7]
+                                       [:einsteindb/add "b" :test/score 9]]"#).expect("transacted");
+
+        let chunks = conn.retract_where(&mut SQLite, &kw!(:test/score), &ValuePredicate::Ge(TypedValue::Long(5)), 2).expect("retracted");
+        assert_eq!(chunks.iter().map(|c| c.retracted).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(chunks[1].tx_id, conn.last_tx_id());
+
+        // The connection's view follows the committed chunks.
+        let scores = conn.q_once(&SQLite, "[:find [?v ...] :where [_ :test/score ?v]]", None).expect("queried");
+        assert_eq!(scores.results, QueryResults::Coll(vec![TypedValue::Long(1).into()]));
+
+        // Nothing left to match: no transactions.
+        let before = conn.last_tx_id();
+        assert!(conn.retract_where(&mut SQLite, &kw!(:test/score), &ValuePredicate::Ge(TypedValue::Long(5)), 2).expect("retracted").is_empty());
+        assert_eq!(conn.last_tx_id(), before);
+
+        assert!(conn.retract_where(&mut SQLite, &kw!(:test/score), &ValuePredicate::Any, 0).is_err());
+    }
+
     #[test]
     fn test_compound_rollback() {
         let mut SQLite = einsteindb::new_connection("").unwrap();
@@ -629,6 +1767,175 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_interruptible() {
+        let mut SQLite = einsteindb::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut SQLite).unwrap();
+        let next = get_next_causetid(&conn);
+
+        // Enough causets for SQLite to check for interruptions.
+        let t = format!("[{}]", (0..200).map(|i| format!("[:einsteindb/add \"t{}\" :einsteindb/solitonid :test/k{}]", i, i)).collect::<Vec<_>>().join(" "));
+        let timed_out = InterruptOptions { timeout: Some(Duration::from_millis(0)), token: None };
+        match conn.transact_interruptible(&mut SQLite, t.as_str(), &timed_out) {
+            Err(CancellableError::TimedOut(timeout)) => assert_eq!(timeout, Duration::from_millis(0)),
+            x => panic!("expected timeout, got {:?}", x),
+        }
+        // Nothing was written, and the connection is usable.
+        assert_eq!(get_next_causetid(&conn), next);
+
+        let token = CancellationToken::new();
+        let cancelled = InterruptOptions { timeout: None, token: Some(token.clone()) };
+        token.cancel();
+        match conn.transact_interruptible(&mut SQLite, t.as_str(), &cancelled) {
+            Err(CancellableError::Cancelled) => {},
+            x => panic!("expected cancellation, got {:?}", x),
+        }
+        match conn.q_once_interruptible(&SQLite, "[:find ?e :where [?e :einsteindb/solitonid _]]", None, &cancelled) {
+            Err(CancellableError::Cancelled) => {},
+            x => panic!("expected cancellation, got {:?}", x),
+        }
+
+        // Cancelling everything before an operation starts doesn't cancel it.
+        conn.cancel_all();
+        let report = conn.transact_interruptible(&mut SQLite, t.as_str(), &InterruptOptions::default()).expect("transacted");
+        assert_eq!(report.tempids["t0"], next);
+
+        // Failures that aren't interruptions are the store's.
+        match conn.transact_interruptible(&mut SQLite, "[[:einsteindb/add]]", &InterruptOptions::default()) {
+            Err(CancellableError::Store(_)) => {},
+            x => panic!("expected store error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_slow_tx_threshold_is_per_conn() {
+        let mut sqlite_a = einsteindb::new_connection("").unwrap();
+        let mut sqlite_b = einsteindb::new_connection("").unwrap();
+        let mut conn_a = Conn::connect(&mut sqlite_a).unwrap();
+        let mut conn_b = Conn::connect(&mut sqlite_b).unwrap();
+        assert_eq!(conn_a.slow_tx_threshold(), None);
+
+        conn_a.set_slow_tx_threshold(Some(Duration::from_millis(1500)));
+        assert_eq!(conn_a.slow_tx_threshold(), Some(Duration::from_millis(1500)));
+        assert_eq!(conn_b.slow_tx_threshold(), None);
+
+        // Transacts pick the threshold up from the watcher they're begun with.
+        {
+            let in_progress = conn_a.begin_transaction(&mut sqlite_a).expect("begun");
+            assert_eq!(in_progress.tx_observer_watcher.options().slow_tx_threshold, Some(Duration::from_millis(1500)));
+        }
+        {
+            let in_progress = conn_b.begin_transaction(&mut sqlite_b).expect("begun");
+            assert_eq!(in_progress.tx_observer_watcher.options().slow_tx_threshold, None);
+        }
+
+        conn_a.set_slow_tx_threshold(None);
+        assert_eq!(conn_a.slow_tx_threshold(), None);
+    }
+
+    #[test]
+    fn test_upsert_tracing_is_per_conn() {
+        let mut sqlite_a = einsteindb::new_connection("").unwrap();
+        let mut sqlite_b = einsteindb::new_connection("").unwrap();
+        let mut conn_a = Conn::connect(&mut sqlite_a).unwrap();
+        let mut conn_b = Conn::connect(&mut sqlite_b).unwrap();
+        assert!(!conn_a.upsert_tracing());
+
+        conn_a.set_upsert_tracing(true);
+        assert!(conn_a.upsert_tracing());
+        assert!(!conn_b.upsert_tracing());
+
+        let topograph = r#"[
+            {:einsteindb/solitonid :page/id :einsteindb/valueType :einsteindb.type/string :einsteindb/index true :einsteindb/unique :einsteindb.unique/idcauset}
+            {:einsteindb/solitonid :page/ref :einsteindb/valueType :einsteindb.type/ref :einsteindb/index true :einsteindb/unique :einsteindb.unique/idcauset}
+        ]"#;
+        let existing = r#"[
+            [:einsteindb/add 111 :page/id "1"]
+            [:einsteindb/add 111 :page/ref 111]
+            [:einsteindb/add 222 :page/id "2"]
+            [:einsteindb/add 222 :page/ref 222]
+        ]"#;
+        let conflicting = r#"[
+            [:einsteindb/add "a" :page/id "1"]
+            [:einsteindb/add "a" :page/ref "b"]
+            [:einsteindb/add "b" :page/id "2"]
+            [:einsteindb/add "b" :page/ref "a"]
+        ]"#;
+        conn_a.transact(&mut sqlite_a, topograph).expect("transacted topograph");
+        conn_a.transact(&mut sqlite_a, existing).expect("transacted");
+        conn_b.transact(&mut sqlite_b, topograph).expect("transacted topograph");
+        conn_b.transact(&mut sqlite_b, existing).expect("transacted");
+
+        let error = conn_a.transact(&mut sqlite_a, conflicting).expect_err("conflicting upserts");
+        assert!(::upsert_trace::upsert_trace(&error).is_some());
+        let error = conn_b.transact(&mut sqlite_b, conflicting).expect_err("conflicting upserts");
+        assert!(::upsert_trace::upsert_trace(&error).is_none());
+    }
+
+    #[test]
+    fn test_write_metrics_are_per_conn() {
+        use einsteindb_core::{
+            AttributeWriteCounts,
+            TxWriteMetrics,
+        };
+
+        let mut sqlite_a = einsteindb::new_connection("").unwrap();
+        let mut sqlite_b = einsteindb::new_connection("").unwrap();
+        let mut conn_a = Conn::connect(&mut sqlite_a).unwrap();
+        let mut conn_b = Conn::connect(&mut sqlite_b).unwrap();
+        assert!(conn_a.write_metrics_sink().is_none());
+
+        let topograph = r#"[
+            {:einsteindb/solitonid :test/name :einsteindb/valueType :einsteindb.type/string :einsteindb/cardinality :einsteindb.cardinality/one}
+            {:einsteindb/solitonid :test/age :einsteindb/valueType :einsteindb.type/long :einsteindb/cardinality :einsteindb.cardinality/one}
+        ]"#;
+        conn_a.transact(&mut sqlite_a, topograph).expect("transacted topograph");
+        conn_b.transact(&mut sqlite_b, topograph).expect("transacted topograph");
+        let name = conn_a.current_schema().get_causetid(&kw!(:test/name)).expect("name").0;
+        let age = conn_a.current_schema().get_causetid(&kw!(:test/age)).expect("age").0;
+
+        let recorded = Arc::new(Mutex::new(vec![]));
+        let sink_recorded = recorded.clone();
+        conn_a.set_write_metrics_sink(Some(Arc::new(move |metrics: &TxWriteMetrics| sink_recorded.lock().unwrap().push(metrics.clone()))));
+        assert!(conn_a.write_metrics_sink().is_some());
+        assert!(conn_b.write_metrics_sink().is_none());
+
+        let first = conn_a.transact(&mut sqlite_a, r#"[[:einsteindb/add "a" :test/name "alice"]
+                                                         [:einsteindb/add "b" :test/name "bob"]
+                                                         [:einsteindb/add "a" :test/age 30]]"#).expect("transacted");
+        let alice = first.tempids["a"];
+        let bob = first.tempids["b"];
+
+        // Asserting what's already there writes nothing, so isn't counted.
+        let second = conn_a.transact(&mut sqlite_a, format!(r#"[[:einsteindb/add {} :test/name "alice"]
+                                                                  [:einsteindb/retract {} :test/name "bob"]]"#, alice, bob)).expect("transacted");
+
+        // A failed transaction reports nothing.
+        conn_a.transact(&mut sqlite_a, format!(r#"[[:einsteindb/add {} :test/age "thirty"]]"#, alice)).expect_err("failed");
+
+        // Another connection's transactions aren't reported.
+        conn_b.transact(&mut sqlite_b, r#"[[:einsteindb/add "a" :test/name "alice"]]"#).expect("transacted");
+
+        {
+            let recorded = recorded.lock().unwrap();
+            assert_eq!(recorded.len(), 2);
+
+            assert_eq!(recorded[0].tx_id, first.tx_id);
+            assert_eq!(recorded[0].attributes[&name], AttributeWriteCounts { assertions: 2, retractions: 0, bytes: 8 });
+            assert_eq!(recorded[0].attributes[&age], AttributeWriteCounts { assertions: 1, retractions: 0, bytes: 8 });
+            // And :einsteindb/txInstant.
+            assert_eq!(recorded[0].attributes.len(), 3);
+
+            assert_eq!(recorded[1].tx_id, second.tx_id);
+            assert_eq!(recorded[1].attributes[&name], AttributeWriteCounts { assertions: 0, retractions: 1, bytes: 3 });
+            assert_eq!(recorded[1].attributes.len(), 2);
+        }
+
+        conn_a.set_write_metrics_sink(None);
+        conn_a.transact(&mut sqlite_a, format!(r#"[[:einsteindb/add {} :test/age 31]]"#, alice)).expect("transacted");
+        assert_eq!(recorded.lock().unwrap().len(), 2);
+    }
+
     #[test]
     fn test_add_to_cache_failure_no_attribute() {
         let mut SQLite = einsteindb::new_connection("").unwrap();
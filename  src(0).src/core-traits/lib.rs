@@ -59,6 +59,7 @@ use uuid::Uuid;
 
 use edn::{
     Cloned,
+    Uri,
     ValueRc,
     Utc,
     Keyword,
@@ -128,14 +129,22 @@ impl<V: TransactableValueMarker> Into<ValuePlace<V>> for KnownCausetid {
 /// such as the `search_results`, `inexact_searches` and `exact_searches` tables.
 /// When moving to a more concrete table, such as `causets`, they are expanded out
 /// via these flags and put into their own column rather than a bit field.
+/// `NoHistory` isn't expanded: it decides which rows reach `timelined_transactions`.
 pub enum AttributeBitFlags {
     IndexAVET     = 1 << 0,
     IndexVAET     = 1 << 1,
     IndexFulltext = 1 << 2,
     UniqueValue   = 1 << 3,
+    NoHistory     = 1 << 4,
 }
 
 pub mod attribute {
+    use chrono::{
+        DateTime,
+        Timelike,
+        Utc,
+    };
+
     use ::{
         TypedValue,
     };
@@ -155,6 +164,68 @@ pub mod attribute {
             }
         }
     }
+
+    /// The tokenizer the values of a fulltext attribute are searched with, i.e., its
+    /// `:einsteindb.fulltext/tokenizer`.
+    #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
+    pub enum FulltextTokenizer {
+        /// Unicode-aware words, case folded, keeping diacritics.
+        Unicode61,
+        /// Porter-stemmed words: "running" matches "run".
+        Porter,
+        /// Every three characters, for substring search.
+        Trigram,
+    }
+
+    impl Default for FulltextTokenizer {
+        fn default() -> FulltextTokenizer {
+            FulltextTokenizer::Unicode61
+        }
+    }
+
+    impl FulltextTokenizer {
+        /// The name of the tokenizer, in SQLite and in `:einsteindb.fulltext/<name>`.
+        pub fn name(self) -> &'static str {
+            match self {
+                FulltextTokenizer::Unicode61 => "unicode61",
+                FulltextTokenizer::Porter => "porter",
+                FulltextTokenizer::Trigram => "trigram",
+            }
+        }
+
+        pub fn into_typed_value(self) -> TypedValue {
+            TypedValue::typed_ns_keyword("einsteindb.fulltext", self.name())
+        }
+    }
+
+    /// The precision instants of an attribute are stored at.  Instants are microsecond precise
+    /// unless an attribute opts into milliseconds.
+    #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
+    pub enum InstantPrecision {
+        Millis,
+        Micros,
+    }
+
+    impl Default for InstantPrecision {
+        fn default() -> InstantPrecision {
+            InstantPrecision::Micros
+        }
+    }
+
+    impl InstantPrecision {
+        /// Truncate the provided `DateTime` to this precision.
+        pub fn truncate(self, value: DateTime<Utc>) -> DateTime<Utc> {
+            let unit = match self {
+                InstantPrecision::Millis => 1_000_000,
+                InstantPrecision::Micros => 1_000,
+            };
+            let nanoseconds = value.nanosecond();
+            if nanoseconds % unit == 0 {
+                return value;
+            }
+            value.with_nanosecond(nanoseconds - nanoseconds % unit).expect("valid timestamp")
+        }
+    }
 }
 
 /// A einsteindb schema attribute has a value type and several other flags determining how assertions
@@ -196,6 +267,11 @@ pub struct Attribute {
     /// Fulltext attributes always have string values.
     pub fulltext: bool,
 
+    /// The tokenizer fulltext values are searched with, i.e., the attribute's
+    /// `:einsteindb.fulltext/tokenizer`.  Only fulltext attributes have a tokenizer other than the
+    /// default.
+    pub fulltext_tokenizer: attribute::FulltextTokenizer,
+
     /// `true` if this attribute is a component, i.e., it is `:einsteindb/isComponent true`.
     ///
     /// Component attributes always have value type `Ref`.
@@ -225,6 +301,9 @@ impl Attribute {
         if self.unique.is_some() {
             flags |= AttributeBitFlags::UniqueValue as u8;
         }
+        if self.no_history {
+            flags |= AttributeBitFlags::NoHistory as u8;
+        }
         flags
     }
 
@@ -252,6 +331,12 @@ impl Attribute {
             attribute_map.insert(values::DB_FULLTEXT.clone(), edn::Value::Boolean(true));
         }
 
+        match self.fulltext_tokenizer {
+            attribute::FulltextTokenizer::Unicode61 => (),
+            attribute::FulltextTokenizer::Porter => { attribute_map.insert(values::DB_FULLTEXT_TOKENIZER.clone(), values::DB_FULLTEXT_PORTER.clone()); },
+            attribute::FulltextTokenizer::Trigram => { attribute_map.insert(values::DB_FULLTEXT_TOKENIZER.clone(), values::DB_FULLTEXT_TRIGRAM.clone()); },
+        }
+
         if self.component {
             attribute_map.insert(values::DB_IS_COMPONENT.clone(), edn::Value::Boolean(true));
         }
@@ -275,6 +360,7 @@ impl Default for Attribute {
             unique: None,
             component: false,
             no_history: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
         }
     }
 }
@@ -292,6 +378,7 @@ pub enum ValueType {
     String,
     Keyword,
     Uuid,
+    Uri,
 }
 
 impl ValueType {
@@ -306,6 +393,7 @@ impl ValueType {
         s.insert(ValueType::String);
         s.insert(ValueType::Keyword);
         s.insert(ValueType::Uuid);
+        s.insert(ValueType::Uri);
         s
     }
 }
@@ -332,6 +420,7 @@ impl ValueType {
             ValueType::String => "string",
             ValueType::Keyword => "keyword",
             ValueType::Uuid => "uuid",
+            ValueType::Uri => "uri",
         })
     }
 
@@ -349,6 +438,7 @@ impl ValueType {
             "string" => Some(ValueType::String),
             "keyword" => Some(ValueType::Keyword),
             "uuid" => Some(ValueType::Uuid),
+            "uri" => Some(ValueType::Uri),
             _ => None,
         }
     }
@@ -363,6 +453,7 @@ impl ValueType {
             ValueType::String => "string",
             ValueType::Keyword => "keyword",
             ValueType::Uuid => "uuid",
+            ValueType::Uri => "uri",
         })
     }
 
@@ -376,6 +467,7 @@ impl ValueType {
             ValueType::String => values::DB_TYPE_STRING.clone(),
             ValueType::Keyword => values::DB_TYPE_KEYWORD.clone(),
             ValueType::Uuid => values::DB_TYPE_UUID.clone(),
+            ValueType::Uri => values::DB_TYPE_URI.clone(),
         }
     }
 
@@ -398,6 +490,7 @@ impl fmt::Display for ValueType {
             ValueType::String =>  ":einsteindb.type/string",
             ValueType::Keyword => ":einsteindb.type/keyword",
             ValueType::Uuid =>    ":einsteindb.type/uuid",
+            ValueType::Uri =>     ":einsteindb.type/uri",
         })
     }
 }
@@ -406,7 +499,6 @@ impl fmt::Display for ValueType {
 impl TransactableValueMarker for TypedValue {}
 
 /// Represents a value that can be stored in a einsteindb store.
-// TODO: expand to include :einsteindb.type/uri. https://github.com/YosiSF/einsteindb/issues/201
 // TODO: JSON data type? https://github.com/YosiSF/einsteindb/issues/31
 // TODO: BigInt? Bytes?
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq, Serialize, Deserialize)]
@@ -420,6 +512,7 @@ pub enum TypedValue {
     String(ValueRc<String>),
     Keyword(ValueRc<Keyword>),
     Uuid(Uuid),                        // It's only 128 bits, so this should be acceptable to clone.
+    Uri(Uri),
 }
 
 impl From<KnownCausetid> for TypedValue {
@@ -451,6 +544,7 @@ impl TypedValue {
             &TypedValue::String(_) => ValueType::String,
             &TypedValue::Keyword(_) => ValueType::Keyword,
             &TypedValue::Uuid(_) => ValueType::Uuid,
+            &TypedValue::Uri(_) => ValueType::Uri,
         }
     }
 
@@ -602,6 +696,13 @@ impl TypedValue {
             _ => None,
         }
     }
+
+    pub fn into_uri(self) -> Option<Uri> {
+        match self {
+            TypedValue::Uri(v) => Some(v),
+            _ => None,
+        }
+    }
 }
 
 // We don't do From<i64> or From<Causetid> 'cos it's ambiguous.
@@ -626,6 +727,12 @@ impl From<Uuid> for TypedValue {
     }
 }
 
+impl From<Uri> for TypedValue {
+    fn from(value: Uri) -> TypedValue {
+        TypedValue::Uri(value)
+    }
+}
+
 impl<'a> From<&'a str> for TypedValue {
     fn from(value: &'a str) -> TypedValue {
         TypedValue::String(ValueRc::new(value.to_string()))
@@ -941,6 +1048,13 @@ impl Binding {
         }
     }
 
+    pub fn into_uri(self) -> Option<Uri> {
+        match self {
+            Binding::Scalar(TypedValue::Uri(v)) => Some(v),
+            _ => None,
+        }
+    }
+
     pub fn into_c_string(self) -> Option<*mut c_char> {
         match self {
             Binding::Scalar(v) => v.into_c_string(),
@@ -1017,6 +1131,13 @@ impl Binding {
             _ => None,
         }
     }
+
+    pub fn as_uri(&self) -> Option<&Uri> {
+        match self {
+            &Binding::Scalar(TypedValue::Uri(ref v)) => Some(v),
+            _ => None,
+        }
+    }
 }
 
 #[test]
@@ -1042,6 +1163,7 @@ mod tests {
             multival: false,
             component: false,
             no_history: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
         };
 
         assert!(attr1.flags() & AttributeBitFlags::IndexAVET as u8 != 0);
@@ -1057,6 +1179,7 @@ mod tests {
             multival: false,
             component: false,
             no_history: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
         };
 
         assert!(attr2.flags() & AttributeBitFlags::IndexAVET as u8 == 0);
@@ -1072,11 +1195,27 @@ mod tests {
             multival: false,
             component: false,
             no_history: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
         };
 
         assert!(attr3.flags() & AttributeBitFlags::IndexAVET as u8 == 0);
         assert!(attr3.flags() & AttributeBitFlags::IndexVAET as u8 == 0);
         assert!(attr3.flags() & AttributeBitFlags::IndexFulltext as u8 != 0);
         assert!(attr3.flags() & AttributeBitFlags::UniqueValue as u8 != 0);
+        assert!(attr3.flags() & AttributeBitFlags::NoHistory as u8 == 0);
+
+        let attr4 = Attribute {
+            index: false,
+            value_type: ValueType::Long,
+            fulltext: false,
+            unique: None,
+            multival: false,
+            component: false,
+            no_history: true,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
+        };
+
+        assert!(attr4.flags() & AttributeBitFlags::IndexAVET as u8 == 0);
+        assert!(attr4.flags() & AttributeBitFlags::NoHistory as u8 != 0);
     }
 }
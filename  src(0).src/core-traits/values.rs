@@ -43,6 +43,10 @@ lazy_static_namespaced_keyword_value!(DB_CARDINALITY, "einsteindb", "cardinality
 lazy_static_namespaced_keyword_value!(DB_CARDINALITY_MANY, "einsteindb.cardinality", "many");
 lazy_static_namespaced_keyword_value!(DB_CARDINALITY_ONE, "einsteindb.cardinality", "one");
 lazy_static_namespaced_keyword_value!(DB_FULLTEXT, "einsteindb", "fulltext");
+lazy_static_namespaced_keyword_value!(DB_FULLTEXT_PORTER, "einsteindb.fulltext", "porter");
+lazy_static_namespaced_keyword_value!(DB_FULLTEXT_TOKENIZER, "einsteindb.fulltext", "tokenizer");
+lazy_static_namespaced_keyword_value!(DB_FULLTEXT_TRIGRAM, "einsteindb.fulltext", "trigram");
+lazy_static_namespaced_keyword_value!(DB_FULLTEXT_UNICODE61, "einsteindb.fulltext", "unicode61");
 lazy_static_namespaced_keyword_value!(DB_IDENT, "einsteindb", "solitonid");
 lazy_static_namespaced_keyword_value!(DB_INDEX, "einsteindb", "index");
 lazy_static_namespaced_keyword_value!(DB_INSTALL_ATTRIBUTE, "einsteindb.install", "attribute");
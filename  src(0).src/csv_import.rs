@@ -0,0 +1,461 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Import of CSV data.
+//!
+//! `import_csv` reads CSV with a header row, and transacts each following row as one causet: for
+//! each column the `CsvMapping` names, the row's field, parsed as the column's value type, is
+//! asserted for the column's attribute.  Empty fields are skipped, and so are columns the mapping
+//! doesn't name.  If the mapping names an identity column, whose attribute is
+//! `:einsteindb.unique/idcauset`, each row upserts against the causet it identifies, so importing
+//! the same data twice changes nothing.
+//!
+//! Rows are read as they're imported and transacted in batches.  A row that doesn't parse, or a
+//! batch that its data keeps from transacting, doesn't fail the import: the rows of a failed batch
+//! are transacted one by one, and the rows that fail are reported in `CsvImportSummary::errors`.
+//! Errors of the store itself, which retrying row by row wouldn't get past, fail the import; the
+//! batches before stay committed.  A mapping that doesn't fit the topograph or the header
+//! fails the import before anything is transacted.
+//!
+//! Fields are separated by commas and may be quoted with `"`, in which case they may contain
+//! commas, newlines and `""` for a quote.  Values are written as `:einsteindb/add` takes them in
+//! EDN, except that strings aren't quoted: instants are RFC 3339, keywords start with `:`, and refs
+//! are causetids or solitonids.
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+use std::io::{
+    BufRead,
+};
+
+use rusqlite;
+use rusqlite::TransactionBehavior;
+
+use edn;
+use edn::{
+    DateTime,
+    Uri,
+    Utc,
+    Uuid,
+};
+use edn::shellings::{
+    Keyword,
+};
+
+use core_traits::{
+    attribute,
+    Causetid,
+    TypedValue,
+    ValueType,
+};
+
+use einsteindb_core::{
+    HasTopograph,
+    Topograph,
+};
+
+use einsteindb_traits::errors::{
+    einsteindbError,
+    einsteindbErrorKind,
+    Result,
+};
+
+use einsteindb::TypedBerolinaSQLValue;
+use tx::{
+    is_transaction_error,
+    transact,
+};
+use types::PartitionMap;
+use watcher::NullWatcher;
+
+/// How many rows `import_csv` transacts at once, unless the mapping says otherwise.
+pub const DEFAULT_CSV_BATCH_SIZE: usize = 1000;
+
+/// A column of CSV data, and the attribute its fields are values of.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CsvColumn {
+    pub column: String,
+    pub attribute: Keyword,
+    pub value_type: ValueType,
+}
+
+/// How `import_csv` turns CSV rows into causets.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CsvMapping {
+    pub columns: Vec<CsvColumn>,
+
+    /// The column identifying the causet of each row, which must be one of `columns`.
+    pub identity: Option<String>,
+
+    pub batch_size: usize,
+}
+
+impl CsvMapping {
+    pub fn new() -> CsvMapping {
+        CsvMapping {
+            columns: vec![],
+            identity: None,
+            batch_size: DEFAULT_CSV_BATCH_SIZE,
+        }
+    }
+
+    pub fn column<C>(mut self, column: C, attribute: Keyword, value_type: ValueType) -> CsvMapping where C: Into<String> {
+        self.columns.push(CsvColumn { column: column.into(), attribute, value_type });
+        self
+    }
+
+    pub fn identity<C>(mut self, column: C) -> CsvMapping where C: Into<String> {
+        self.identity = Some(column.into());
+        self
+    }
+
+    pub fn batch_size(mut self, batch_size: usize) -> CsvMapping {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+}
+
+/// A row `import_csv` couldn't import.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CsvRowError {
+    /// The row, counting from 1 after the header.
+    pub row: usize,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CsvImportSummary {
+    /// The number of rows imported.
+    pub rows: usize,
+    pub transactions: usize,
+    /// The causet each imported row asserted about, by row.
+    pub causetids: BTreeMap<usize, Causetid>,
+    pub errors: Vec<CsvRowError>,
+}
+
+/// A row parsed into the causets of its transaction.
+struct CsvRow {
+    row: usize,
+    terms: Vec<edn::Value>,
+}
+
+impl CsvRow {
+    fn tempid(&self) -> String {
+        format!("row{}", self.row)
+    }
+}
+
+/// Import the CSV data of `reader` into the store in `conn` according to `mapping`.  Returns the
+/// partition map of the store after the import.
+pub fn import_csv<R: BufRead>(conn: &mut rusqlite::Connection, mut partition_map: PartitionMap, topograph: &Topograph, mut reader: R, mapping: &CsvMapping) -> Result<(CsvImportSummary, PartitionMap)> {
+    let header = read_record(&mut reader)?
+        .ok_or_else(|| einsteindbErrorKind::InputError(format!("CSV has no header")))?;
+    let columns = resolve_columns(topograph, &header, mapping)?;
+
+    let mut summary = CsvImportSummary::default();
+    let mut batch = vec![];
+    let mut row = 0;
+    while let Some(record) = read_record(&mut reader)? {
+        row += 1;
+        match parse_row(row, &record, header.len(), &columns) {
+            Ok(parsed) => batch.push(parsed),
+            Err(message) => summary.errors.push(CsvRowError { row, message }),
+        }
+        if batch.len() >= mapping.batch_size {
+            partition_map = transact_batch(conn, partition_map, topograph, batch.split_off(0), &mut summary)?;
+        }
+    }
+    if !batch.is_empty() {
+        partition_map = transact_batch(conn, partition_map, topograph, batch, &mut summary)?;
+    }
+    summary.errors.sort_by_key(|e| e.row);
+    Ok((summary, partition_map))
+}
+
+/// A mapped column: its index in the header, attribute, value type, and whether it's the identity.
+struct ResolvedColumn {
+    index: usize,
+    column: CsvColumn,
+    identity: bool,
+}
+
+fn resolve_columns(topograph: &Topograph, header: &[String], mapping: &CsvMapping) -> Result<Vec<ResolvedColumn>> {
+    if let Some(ref identity) = mapping.identity {
+        if !mapping.columns.iter().any(|c| &c.column == identity) {
+            bail!(einsteindbErrorKind::InputError(format!("identity column {} isn't mapped", identity)));
+        }
+    }
+
+    let mut seen = BTreeSet::new();
+    let mut columns = vec![];
+    for column in &mapping.columns {
+        if !seen.insert(&column.column) {
+            bail!(einsteindbErrorKind::InputError(format!("column {} is mapped twice", column.column)));
+        }
+        let index = header.iter().position(|h| h == &column.column)
+            .ok_or_else(|| einsteindbErrorKind::InputError(format!("column {} isn't in the CSV header", column.column)))?;
+        let (attribute, _) = topograph.attribute_for_solitonid(&column.attribute)
+            .ok_or_else(|| einsteindbErrorKind::UnrecognizedSolitonid(column.attribute.to_string()))?;
+        if attribute.value_type != column.value_type {
+            bail!(einsteindbErrorKind::InputError(format!("column {} is mapped as {} but {} is {}",
+                                                         column.column, column.value_type, column.attribute, attribute.value_type)));
+        }
+        let identity = mapping.identity.as_ref() == Some(&column.column);
+        if identity && attribute.unique != Some(attribute::Unique::Idcauset) {
+            bail!(einsteindbErrorKind::InputError(format!("identity column {} maps {}, which isn't :einsteindb.unique/idcauset",
+                                                         column.column, column.attribute)));
+        }
+        columns.push(ResolvedColumn { index, column: column.clone(), identity });
+    }
+    Ok(columns)
+}
+
+fn parse_row(row: usize, record: &[String], width: usize, columns: &[ResolvedColumn]) -> ::std::result::Result<CsvRow, String> {
+    if record.len() != width {
+        return Err(format!("expected {} fields, found {}", width, record.len()));
+    }
+
+    let mut parsed = CsvRow { row, terms: vec![] };
+    let e = edn::Value::Text(parsed.tempid());
+    let add = edn::Value::Keyword(Keyword::isoliton_namespaceable("einsteindb", "add"));
+    for resolved in columns {
+        let field = &record[resolved.index];
+        if field.is_empty() {
+            if resolved.identity {
+                return Err(format!("identity column {} is empty", resolved.column.column));
+            }
+            continue;
+        }
+        let v = parse_value(field, resolved.column.value_type)
+            .map_err(|message| format!("column {}: {}", resolved.column.column, message))?;
+        parsed.terms.push(edn::Value::Vector(vec![add.clone(), e.clone(), edn::Value::Keyword(resolved.column.attribute.clone()), v]));
+    }
+    if parsed.terms.is_empty() {
+        return Err(format!("no mapped fields"));
+    }
+    Ok(parsed)
+}
+
+/// Parse `field` as a value of `value_type`, as EDN.
+fn parse_value(field: &str, value_type: ValueType) -> ::std::result::Result<edn::Value, String> {
+    let invalid = || format!("{:?} isn't a valid {}", field, value_type);
+    let v: TypedValue = match value_type {
+        ValueType::Ref => {
+            // A solitonid is resolved by the transactor.
+            if field.starts_with(':') {
+                return match edn::parse::value(field).map(|v| v.without_spans()) {
+                    Ok(v @ edn::Value::Keyword(_)) => Ok(v),
+                    _ => Err(invalid()),
+                };
+            }
+            TypedValue::Ref(field.parse::<Causetid>().map_err(|_| invalid())?)
+        },
+        ValueType::Boolean => TypedValue::Boolean(field.parse::<bool>().map_err(|_| invalid())?),
+        ValueType::Long => TypedValue::Long(field.parse::<i64>().map_err(|_| invalid())?),
+        ValueType::Double => TypedValue::Double(field.parse::<f64>().map_err(|_| invalid())?.into()),
+        ValueType::Instant => DateTime::parse_from_rfc3339(field).map_err(|_| invalid())?.with_timezone(&Utc).into(),
+        ValueType::String => TypedValue::typed_string(field),
+        ValueType::Keyword => match edn::parse::value(field).map(|v| v.without_spans()) {
+            Ok(edn::Value::Keyword(k)) => TypedValue::Keyword(k.into()),
+            _ => return Err(invalid()),
+        },
+        ValueType::Uuid => TypedValue::Uuid(Uuid::parse_str(field).map_err(|_| invalid())?),
+        ValueType::Uri => TypedValue::Uri(Uri::parse(field).map_err(|_| invalid())?),
+    };
+    Ok(v.to_edn_value_pair().0)
+}
+
+/// Transact `rows` in one transaction, or, if their data keeps that from succeeding, one by one.
+/// Fails if the store does.
+fn transact_batch(conn: &mut rusqlite::Connection, partition_map: PartitionMap, topograph: &Topograph, rows: Vec<CsvRow>, summary: &mut CsvImportSummary) -> Result<PartitionMap> {
+    match transact_rows(conn, partition_map.clone(), topograph, &rows, summary) {
+        Ok(partition_map) => Ok(partition_map),
+        Err((_, e)) => {
+            if !is_transaction_error(&e) {
+                return Err(e);
+            }
+            if rows.len() == 1 {
+                summary.errors.push(CsvRowError { row: rows[0].row, message: e.to_string() });
+                return Ok(partition_map);
+            }
+            let mut partition_map = partition_map;
+            for row in rows {
+                match transact_rows(conn, partition_map.clone(), topograph, &[row], summary) {
+                    Ok(next_partition_map) => partition_map = next_partition_map,
+                    Err((row, e)) => {
+                        if !is_transaction_error(&e) {
+                            return Err(e);
+                        }
+                        summary.errors.push(CsvRowError { row, message: e.to_string() });
+                    },
+                }
+            }
+            Ok(partition_map)
+        },
+    }
+}
+
+/// Transact `rows` in one transaction, or return the first row and why it failed.
+fn transact_rows(conn: &mut rusqlite::Connection, partition_map: PartitionMap, topograph: &Topograph, rows: &[CsvRow], summary: &mut CsvImportSummary) -> ::std::result::Result<PartitionMap, (usize, einsteindbError)> {
+    let first = rows[0].row;
+    let transaction = edn::Value::Vector(rows.iter().flat_map(|r| r.terms.iter().cloned()).collect());
+    let result: Result<_> = (|| {
+        let causets = edn::parse::causets(&transaction.to_string())
+            .map_err(|e| einsteindbErrorKind::InputError(format!("couldn't parse transaction: {}", e)))?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let details = transact(&tx, partition_map, topograph, topograph, NullWatcher(), causets)?;
+        tx.commit()?;
+        Ok(details)
+    })();
+    let (report, next_partition_map, _next_topograph, _watcher) = result.map_err(|e| (first, e))?;
+
+    for row in rows {
+        if let Some(&causetid) = report.tempids.get(&row.tempid()) {
+            summary.causetids.insert(row.row, causetid);
+        }
+    }
+    summary.rows += rows.len();
+    summary.transactions += 1;
+    Ok(next_partition_map)
+}
+
+/// Read the fields of the next record of `reader`, or `None` at the end.
+fn read_record<R: BufRead>(reader: &mut R) -> Result<Option<Vec<String>>> {
+    let mut line = String::new();
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)
+            .map_err(|e| einsteindbErrorKind::InputError(format!("couldn't read CSV: {}", e)))?;
+        if read == 0 {
+            if quoted {
+                bail!(einsteindbErrorKind::InputError(format!("CSV ends inside a quoted field")));
+            }
+            return Ok(None);
+        }
+        if !quoted && fields.is_empty() && field.is_empty() && line.trim_end_matches(|c| c == '\r' || c == '\n').is_empty() {
+            // Skip blank lines between records.
+            continue;
+        }
+
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (quoted, c) {
+                (true, '"') if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                },
+                (true, '"') => quoted = false,
+                (true, c) => field.push(c),
+                (false, '"') if field.is_empty() => quoted = true,
+                (false, ',') => fields.push(field.split_off(0)),
+                (false, '\r') | (false, '\n') => {},
+                (false, c) => field.push(c),
+            }
+        }
+        if !quoted {
+            fields.push(field);
+            return Ok(Some(fields));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use debug::TestConn;
+
+    fn kw(name: &str) -> Keyword {
+        Keyword::isoliton_namespaceable("test", name)
+    }
+
+    #[test]
+    fn test_read_record() {
+        let mut reader = "a,\"b, \"\"c\"\"\",\n\n\"multi\nline\",x\r\n".as_bytes();
+        assert_eq!(read_record(&mut reader).expect("read"), Some(vec!["a".to_string(), "b, \"c\"".to_string(), "".to_string()]));
+        assert_eq!(read_record(&mut reader).expect("read"), Some(vec!["multi\nline".to_string(), "x".to_string()]));
+        assert_eq!(read_record(&mut reader).expect("read"), None);
+        assert!(read_record(&mut "\"open".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_import_csv() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[{:einsteindb/solitonid :test/email
+                           :einsteindb/valueType :einsteindb.type/string
+                           :einsteindb/cardinality :einsteindb.cardinality/one
+                           :einsteindb/unique :einsteindb.unique/idcauset
+                           :einsteindb/index true}
+                          {:einsteindb/solitonid :test/age
+                           :einsteindb/valueType :einsteindb.type/long
+                           :einsteindb/cardinality :einsteindb.cardinality/one}
+                          {:einsteindb/solitonid :test/seen
+                           :einsteindb/valueType :einsteindb.type/instant
+                           :einsteindb/cardinality :einsteindb.cardinality/one}
+                          {:einsteindb/solitonid :test/friend
+                           :einsteindb/valueType :einsteindb.type/ref
+                           :einsteindb/cardinality :einsteindb.cardinality/many}]"#).expect("transacted topograph");
+
+        let mapping = CsvMapping::new()
+            .column("email", kw("email"), ValueType::String)
+            .column("age", kw("age"), ValueType::Long)
+            .column("seen", kw("seen"), ValueType::Instant)
+            .column("friend", kw("friend"), ValueType::Ref)
+            .identity("email")
+            .batch_size(2);
+
+        // Rows 2 and 6 don't parse, and row 5 fails the batch it shares with row 4.
+        let csv = "email,ignored,age,seen,friend\n\
+                   a@x.com,?,30,2017-04-28T20:23:05Z,\n\
+                   b@x.com,?,old,,\n\
+                   b@x.com,?,35,,:test/email\n\
+                   c@x.com,?,40,,\n\
+                   d@x.com,?,41,,:test/unknown\n\
+                   ,?,50,,\n";
+        let (summary, partition_map) = import_csv(&mut conn.SQLite, conn.partition_map.clone(), &conn.topograph, csv.as_bytes(), &mapping).expect("imported");
+        conn.partition_map = partition_map;
+
+        assert_eq!(summary.rows, 3);
+        assert_eq!(summary.transactions, 2);
+        assert_eq!(summary.errors.iter().map(|e| e.row).collect::<Vec<_>>(), vec![2, 5, 6]);
+        assert!(summary.errors[0].message.contains("column age"));
+        assert_eq!(summary.causetids.keys().cloned().collect::<Vec<_>>(), vec![1, 3, 4]);
+        let a = summary.causetids[&1];
+
+        // Importing again upserts rather than creating new causets.
+        let csv = "seen,age,email,friend\n,31,a@x.com,\n";
+        let (again, _) = import_csv(&mut conn.SQLite, conn.partition_map.clone(), &conn.topograph, csv.as_bytes(), &mapping).expect("imported");
+        assert_eq!(again.causetids[&1], a);
+        assert!(again.errors.is_empty());
+
+        let age: i64 = conn.SQLite.query_row("SELECT v FROM causets WHERE e = ? AND a = ?",
+                                              &[&a, &conn.topograph.get_causetid(&kw("age")).expect("age").0],
+                                              |row| row.get(0)).expect("age");
+        assert_eq!(age, 31);
+
+        // A mapping that doesn't fit fails the whole import.
+        let bad = CsvMapping::new().column("age", kw("age"), ValueType::String);
+        assert!(import_csv(&mut conn.SQLite, conn.partition_map.clone(), &conn.topograph, "age\n1\n".as_bytes(), &bad).is_err());
+        let bad = CsvMapping::new().column("age", kw("age"), ValueType::Long).identity("age");
+        assert!(import_csv(&mut conn.SQLite, conn.partition_map.clone(), &conn.topograph, "age\n1\n".as_bytes(), &bad).is_err());
+        let bad = CsvMapping::new().column("missing", kw("age"), ValueType::Long);
+        assert!(import_csv(&mut conn.SQLite, conn.partition_map.clone(), &conn.topograph, "age\n1\n".as_bytes(), &bad).is_err());
+
+        // A store that can't be written fails the import, rather than every row.
+        conn.SQLite.execute_batch("CREATE TEMP TRIGGER read_only BEFORE INSERT ON transactions BEGIN SELECT RAISE(FAIL, 'read only'); END").expect("trigger");
+        let csv = "email,age,seen,friend\ne@x.com,60,,\nf@x.com,61,,\n";
+        assert!(import_csv(&mut conn.SQLite, conn.partition_map.clone(), &conn.topograph, csv.as_bytes(), &mapping).is_err());
+    }
+}
@@ -82,6 +82,7 @@ use edn::{
     InternSet,
 };
 use edn::causets::{
+    causet,
     CausetidOrSolitonid,
     TempId,
 };
@@ -189,20 +190,20 @@ pub fn to_causetid(topograph: &Topograph, causetid: i64) -> CausetidOrSolitonid
 //     topograph.get_ident(causetid).map_or(Causetid::Causetid(causetid), |solitonid| Causetid::Solitonid(solitonid.clone()))
 // }
 
-/// Return the set of causets in the store, ordered by (e, a, v, tx), but not including any causets of
-/// the form [... :einsteindb/txInstant ...].
+/// Return the set of causets in the store, in `einsteindb::CAUSETS_ORDER_BY` order, but not including
+/// any causets of the form [... :einsteindb/txInstant ...].
 pub fn causets<S: Borrow<Topograph>>(conn: &rusqlite::Connection, topograph: &S) -> Result<causets> {
     causets_after(conn, topograph, bootstrap::TX0 - 1)
 }
 
 /// Return the set of causets in the store with transaction ID strictly greater than the given `tx`,
-/// ordered by (e, a, v, tx).
+/// in `einsteindb::CAUSETS_ORDER_BY` order.
 ///
 /// The causet set returned does not include any causets of the form [... :einsteindb/txInstant ...].
 pub fn causets_after<S: Borrow<Topograph>>(conn: &rusqlite::Connection, topograph: &S, tx: i64) -> Result<causets> {
     let borrowed_topograph = topograph.borrow();
 
-    let mut stmt: rusqlite::Statement = conn.prepare("SELECT e, a, v, value_type_tag, tx FROM causets WHERE tx > ? ORDER BY e ASC, a ASC, value_type_tag ASC, v ASC, tx ASC")?;
+    let mut stmt: rusqlite::Statement = conn.prepare(&format!("SELECT e, a, v, value_type_tag, tx FROM causets WHERE tx > ? {}", CAUSETS_ORDER_BY))?;
 
     let r: Result<Vec<_>> = stmt.query_and_then(&[&tx], |row| {
         let e: i64 = row.get_checked(0)?;
@@ -236,7 +237,7 @@ pub fn causets_after<S: Borrow<Topograph>>(conn: &rusqlite::Connection, topograp
 }
 
 /// Return the sequence of transactions in the store with transaction ID strictly greater than the
-/// given `tx`, ordered by (tx, e, a, v).
+/// given `tx`, ordered by (tx, e, a, value_type_tag, v, added).
 ///
 /// Each transaction returned includes the [(transaction-tx) :einsteindb/txInstant ...] causet.
 pub fn transactions_after<S: Borrow<Topograph>>(conn: &rusqlite::Connection, topograph: &S, tx: i64) -> Result<Transactions> {
@@ -337,7 +338,10 @@ impl TestConn {
     pub fn transact<I>(&mut self, transaction: I) -> Result<TxReport> where I: Borrow<str> {
         // Failure to parse the transaction is a coding error, so we unwrap.
         let causets = edn::parse::causets(transaction.borrow()).expect(format!("to be able to parse {} into causets", transaction.borrow()).as_str());
+        self.transact_causets(causets)
+    }
 
+    pub fn transact_causets<I, V>(&mut self, causets: I) -> Result<TxReport> where I: IntoIterator<Item=causet<V>>, V: TransactableValue {
         let details = {
             // The block scopes the borrow of self.SQLite.
             // We're about to write, so go straight ahead and get an IMMEDIATE transaction.
@@ -399,6 +403,10 @@ impl TestConn {
         causets_after(&self.SQLite, &self.topograph, bootstrap::TX0).expect("causets")
     }
 
+    pub fn all_datoms_ordered(&self) -> Vec<(Causetid, Causetid, TypedValue, Causetid)> {
+        all_datoms_ordered(&self.SQLite, &self.topograph).expect("all_datoms_ordered")
+    }
+
     pub fn fulltext_values(&self) -> FulltextValues {
         fulltext_values(&self.SQLite).expect("fulltext_values")
     }
@@ -408,12 +416,12 @@ impl TestConn {
 
         // Does not include :einsteindb/txInstant.
         let causets = causets_after(&conn, &einsteindb.topograph, 0).unwrap();
-        assert_eq!(causets.0.len(), 94);
+        assert_eq!(causets.0.len(), 106);
 
         // Includes :einsteindb/txInstant.
         let transactions = transactions_after(&conn, &einsteindb.topograph, 0).unwrap();
         assert_eq!(transactions.0.len(), 1);
-        assert_eq!(transactions.0[0].0.len(), 95);
+        assert_eq!(transactions.0[0].0.len(), 107);
 
         let mut parts = einsteindb.partition_map;
 
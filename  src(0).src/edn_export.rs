@@ -0,0 +1,365 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Streaming export of a whole store as EDN transactions, and their import.
+//!
+//! `export_edn` writes one EDN transaction per line: first the user topograph, as
+//! `schema_export::export_schema` renders it, then the causets of every causet the store didn't
+//! bootstrap with, whatever its partition, chunked by the transaction that asserted them or by
+//! causet.  Only the current causets are exported, not
+//! their history.  Neither are the causets of attributes, which the topograph covers, nor those of
+//! transactions, so every `:einsteindb/txInstant` is that of the import.
+//!
+//! Each causet is written as `[:einsteindb/add e a v]`.  User causets, in either place, are named
+//! by tempids, the decimal causetid of the causet in the exporting store; attributes and bootstrap
+//! causets are named by solitonid.  A tempid only names a causet within one transaction, so
+//! `import_edn_stream` rewrites the tempids that earlier transactions resolved into the causetids
+//! they resolved to.  Importing into an empty store reproduces the exported store up to causetids,
+//! which makes the export a text backup, and a way to move a store between versions of EinsteinDB.
+
+use std::collections::{
+    BTreeMap,
+};
+use std::io::{
+    BufRead,
+    Write,
+};
+
+use rusqlite;
+use rusqlite::TransactionBehavior;
+
+use edn;
+use edn::shellings::{
+    Keyword,
+};
+
+use core_traits::{
+    Causetid,
+    TypedValue,
+    ValueType,
+};
+
+use einsteindb_core::{
+    HasTopograph,
+    Topograph,
+};
+
+use einsteindb_traits::errors::{
+    einsteindbErrorKind,
+    Result,
+};
+
+use bootstrap::{
+    TX0,
+    is_bootstrap_causetid,
+};
+use einsteindb::{
+    CAUSETS_ORDER_BY,
+    TypedBerolinaSQLValue,
+};
+use schema_export::export_schema;
+use tx::transact;
+use types::PartitionMap;
+use watcher::NullWatcher;
+
+/// How `export_edn` groups causets into transactions.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExportChunking {
+    /// One transaction per transaction that asserted the causets, in transaction order.
+    Transaction,
+    /// One transaction per causet, in causetid order.
+    Causet,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExportOptions {
+    pub chunking: ExportChunking,
+    /// Whether to start with the user topograph.  Without it, the export can only be imported into a
+    /// store that already has the topograph.
+    pub topograph: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> ExportOptions {
+        ExportOptions {
+            chunking: ExportChunking::Transaction,
+            topograph: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ExportSummary {
+    pub transactions: usize,
+    pub causets: usize,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ImportSummary {
+    pub transactions: usize,
+    /// The causetid each exported tempid resolved to.
+    pub causetids: BTreeMap<String, Causetid>,
+}
+
+/// Write the user topograph and the current user causets of the store in `conn` to `writer`, one
+/// EDN transaction per line.
+pub fn export_edn<W: Write>(conn: &rusqlite::Connection, topograph: &Topograph, writer: &mut W, opts: &ExportOptions) -> Result<ExportSummary> {
+    let mut summary = ExportSummary::default();
+
+    if opts.topograph {
+        match export_schema(conn, topograph)? {
+            edn::Value::Vector(ref terms) if terms.is_empty() => {},
+            schema => {
+                write_transaction(writer, &schema)?;
+                summary.transactions += 1;
+            },
+        }
+    }
+
+    let order_by = match opts.chunking {
+        ExportChunking::Transaction => "ORDER BY tx ASC, e ASC, a ASC, value_type_tag ASC, v ASC",
+        ExportChunking::Causet => CAUSETS_ORDER_BY,
+    };
+    // `all_causets` has the text of fulltext values.
+    let mut stmt = conn.prepare(&format!("SELECT e, a, v, value_type_tag, tx FROM all_causets WHERE e < ? {}", order_by))?;
+    let rows = stmt.query_and_then(&[&TX0], |row| -> Result<(Causetid, Causetid, TypedValue, Causetid)> {
+        let v: rusqlite::types::Value = row.get_checked(2)?;
+        let value_type_tag: i32 = row.get_checked(3)?;
+        Ok((row.get_checked(0)?, row.get_checked(1)?, TypedValue::from_BerolinaSQL_value_pair(v, value_type_tag)?, row.get_checked(4)?))
+    })?;
+
+    let add = edn::Value::Keyword(Keyword::isoliton_namespaceable("einsteindb", "add"));
+    let mut chunk: Vec<edn::Value> = vec![];
+    let mut chunk_key = None;
+    for row in rows {
+        let (e, a, v, tx) = row?;
+        if is_bootstrap_causetid(e) || topograph.attribute_map.contains_key(&e) {
+            continue;
+        }
+        let key = match opts.chunking {
+            ExportChunking::Transaction => tx,
+            ExportChunking::Causet => e,
+        };
+        if chunk_key != Some(key) && !chunk.is_empty() {
+            write_transaction(writer, &edn::Value::Vector(chunk.split_off(0)))?;
+            summary.transactions += 1;
+        }
+        chunk_key = Some(key);
+
+        let a = topograph.get_solitonid(a).ok_or_else(|| einsteindbErrorKind::UnknownAttribute(a))?;
+        let v = match v {
+            TypedValue::Ref(r) => causet_place(topograph, r),
+            v => v.to_edn_value_pair().0,
+        };
+        chunk.push(edn::Value::Vector(vec![add.clone(), causet_place(topograph, e), edn::Value::Keyword(a.clone()), v]));
+        summary.causets += 1;
+    }
+    if !chunk.is_empty() {
+        write_transaction(writer, &edn::Value::Vector(chunk))?;
+        summary.transactions += 1;
+    }
+
+    Ok(summary)
+}
+
+/// Transact each line of `reader`, as written by `export_edn`, into the store in `conn`, in a
+/// BerolinaSQL transaction of its own.  Returns the partition map and the topograph of the store
+/// after the import.
+pub fn import_edn_stream<R: BufRead>(conn: &mut rusqlite::Connection, mut partition_map: PartitionMap, mut topograph: Topograph, reader: R) -> Result<(ImportSummary, PartitionMap, Topograph)> {
+    let mut summary = ImportSummary::default();
+    for line in reader.lines() {
+        let line = line.map_err(|e| einsteindbErrorKind::InputError(format!("couldn't read import: {}", e)))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let transaction = edn::parse::value(&line)
+            .map_err(|e| einsteindbErrorKind::InputError(format!("couldn't parse transaction: {}", e)))?
+            .without_spans();
+        let transaction = resolve_tempids(&topograph, &summary.causetids, transaction);
+        let causets = edn::parse::causets(&transaction.to_string())
+            .map_err(|e| einsteindbErrorKind::InputError(format!("couldn't parse transaction: {}", e)))?;
+
+        let (report, next_partition_map, next_topograph, _watcher) = {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let details = transact(&tx, partition_map, &topograph, &topograph, NullWatcher(), causets)?;
+            tx.commit()?;
+            details
+        };
+        partition_map = next_partition_map;
+        if let Some(next_topograph) = next_topograph {
+            topograph = next_topograph;
+        }
+        summary.causetids.extend(report.tempids);
+        summary.transactions += 1;
+    }
+    Ok((summary, partition_map, topograph))
+}
+
+/// Name `causetid` the way an export does.
+fn causet_place(topograph: &Topograph, causetid: Causetid) -> edn::Value {
+    if is_bootstrap_causetid(causetid) || topograph.attribute_map.contains_key(&causetid) {
+        if let Some(solitonid) = topograph.get_solitonid(causetid) {
+            return edn::Value::Keyword(solitonid.clone());
+        }
+    }
+    edn::Value::Text(causetid.to_string())
+}
+
+/// Replace the tempids of `transaction` that earlier transactions of the import resolved.
+fn resolve_tempids(topograph: &Topograph, resolved: &BTreeMap<String, Causetid>, transaction: edn::Value) -> edn::Value {
+    let resolve = |place: &mut edn::Value| {
+        let causetid = match place {
+            &mut edn::Value::Text(ref tempid) => resolved.get(tempid).cloned(),
+            _ => None,
+        };
+        if let Some(causetid) = causetid {
+            *place = edn::Value::Integer(causetid);
+        }
+    };
+    match transaction {
+        edn::Value::Vector(terms) => edn::Value::Vector(terms.into_iter().map(|term| match term {
+            edn::Value::Vector(mut places) => {
+                if places.len() == 4 {
+                    resolve(&mut places[1]);
+                    let is_ref = match places[2] {
+                        edn::Value::Keyword(ref a) => topograph.attribute_for_solitonid(a).map_or(false, |(attribute, _)| attribute.value_type == ValueType::Ref),
+                        _ => false,
+                    };
+                    if is_ref {
+                        resolve(&mut places[3]);
+                    }
+                }
+                edn::Value::Vector(places)
+            },
+            term => term,
+        }).collect()),
+        transaction => transaction,
+    }
+}
+
+fn write_transaction<W: Write>(writer: &mut W, transaction: &edn::Value) -> Result<()> {
+    writeln!(writer, "{}", transaction)
+        .map_err(|e| einsteindbErrorKind::InputError(format!("couldn't write export: {}", e)))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use debug::TestConn;
+
+    /// The user causets of `conn`, with attributes by solitonid and user causets by `rename`.
+    fn user_causets<F: Fn(Causetid) -> Causetid>(conn: &TestConn, rename: F) -> BTreeSet<(Causetid, String, TypedValue)> {
+        let mut stmt = conn.SQLite.prepare("SELECT e, a, v, value_type_tag FROM all_causets WHERE e < ?").expect("prepared");
+        let causets: Result<Vec<(Causetid, Causetid, TypedValue)>> = stmt.query_and_then(&[&TX0], |row| {
+            let v: rusqlite::types::Value = row.get_checked(2)?;
+            let value_type_tag: i32 = row.get_checked(3)?;
+            Ok((row.get_checked(0)?, row.get_checked(1)?, TypedValue::from_BerolinaSQL_value_pair(v, value_type_tag)?))
+        }).expect("queried").collect();
+        causets.expect("read").into_iter()
+            .filter(|&(e, _, _)| !is_bootstrap_causetid(e) && !conn.topograph.attribute_map.contains_key(&e))
+            .map(|(e, a, v)| {
+                let v = match v {
+                    TypedValue::Ref(r) if !is_bootstrap_causetid(r) => TypedValue::Ref(rename(r)),
+                    v => v,
+                };
+                (rename(e), conn.topograph.get_solitonid(a).expect("solitonid").to_string(), v)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_export_edn() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[{:einsteindb/solitonid :test/name
+                           :einsteindb/valueType :einsteindb.type/string
+                           :einsteindb/cardinality :einsteindb.cardinality/one
+                           :einsteindb/unique :einsteindb.unique/idcauset}
+                          {:einsteindb/solitonid :test/friend
+                           :einsteindb/valueType :einsteindb.type/ref
+                           :einsteindb/cardinality :einsteindb.cardinality/many}
+                          {:einsteindb/solitonid :test/bio
+                           :einsteindb/valueType :einsteindb.type/string
+                           :einsteindb/cardinality :einsteindb.cardinality/one
+                           :einsteindb/fulltext true}]"#).expect("transacted topograph");
+        conn.transact(r#"[{:einsteindb/id "a" :test/name "a" :test/bio "first"}
+                          {:einsteindb/id "b" :test/name "b" :test/friend "a"}]"#).expect("transacted");
+        // A string value that looks like a tempid, and a reference across transactions.
+        conn.transact(r#"[{:test/name "65536" :test/friend [:test/name "b"]}
+                          [:einsteindb/add [:test/name "a"] :test/friend [:test/name "b"]]]"#).expect("transacted");
+
+        for chunking in vec![ExportChunking::Transaction, ExportChunking::Causet] {
+            let opts = ExportOptions { chunking, topograph: true };
+            let mut exported = vec![];
+            let summary = export_edn(&conn.SQLite, &conn.topograph, &mut exported, &opts).expect("exported");
+            assert_eq!(summary.causets, 7);
+            // The topograph, then two transactions or three causets.
+            assert_eq!(summary.transactions, match chunking {
+                ExportChunking::Transaction => 1 + 2,
+                ExportChunking::Causet => 1 + 3,
+            });
+            assert_eq!(String::from_utf8(exported.clone()).expect("utf8").lines().count(), summary.transactions);
+
+            let mut copy = TestConn::default();
+            let (imported, partition_map, topograph) =
+                import_edn_stream(&mut copy.SQLite, copy.partition_map.clone(), copy.topograph.clone(), &exported[..]).expect("imported");
+            copy.partition_map = partition_map;
+            copy.topograph = topograph;
+            assert_eq!(imported.transactions, summary.transactions);
+
+            let causetids = imported.causetids;
+            assert_eq!(user_causets(&conn, |e| e),
+                       user_causets(&copy, |e| causetids.iter().find(|&(_, &c)| c == e).map_or(e, |(tempid, _)| tempid.parse().expect("causetid"))));
+        }
+
+        // An empty store exports nothing.
+        let empty = TestConn::default();
+        let mut exported = vec![];
+        assert_eq!(export_edn(&empty.SQLite, &empty.topograph, &mut exported, &ExportOptions::default()).expect("exported"),
+                   ExportSummary::default());
+        assert!(exported.is_empty());
+    }
+
+    #[test]
+    fn test_export_edn_low_causetids() {
+        // An attribute, renamed with an alias, and the solitonid of an enum value, both at
+        // causetids below the user partition.
+        let mut conn = TestConn::default();
+        conn.transact(r#"[[:einsteindb/add 100 :einsteindb/solitonid :test/hue]
+                          [:einsteindb/add 100 :einsteindb/valueType :einsteindb.type/ref]
+                          [:einsteindb/add 100 :einsteindb/cardinality :einsteindb.cardinality/one]
+                          [:einsteindb/add 200 :einsteindb/solitonid :test/red]]"#).expect("transacted topograph");
+        conn.transact(r#"[[:einsteindb/add 100 :einsteindb/solitonid :test/color]
+                          [:einsteindb/add 100 :einsteindb/alias :test/hue]]"#).expect("renamed");
+        let report = conn.transact(r#"[[:einsteindb/add "x" :test/hue :test/red]]"#).expect("transacted");
+
+        let mut exported = vec![];
+        let summary = export_edn(&conn.SQLite, &conn.topograph, &mut exported, &ExportOptions::default()).expect("exported");
+        // The topograph, the solitonid of :test/red, and "x".
+        assert_eq!(summary.transactions, 3);
+        assert_eq!(summary.causets, 2);
+
+        let mut copy = TestConn::default();
+        let (imported, partition_map, topograph) =
+            import_edn_stream(&mut copy.SQLite, copy.partition_map.clone(), copy.topograph.clone(), &exported[..]).expect("imported");
+        copy.partition_map = partition_map;
+        copy.topograph = topograph;
+
+        let color = copy.topograph.get_causetid(&Keyword::isoliton_namespaceable("test", "color")).expect("color");
+        assert_eq!(copy.topograph.get_causetid(&Keyword::isoliton_namespaceable("test", "hue")), Some(color));
+        let red = copy.topograph.get_causetid(&Keyword::isoliton_namespaceable("test", "red")).expect("red");
+        let x = imported.causetids[&report.tempids["x"].to_string()];
+        let v: Causetid = copy.SQLite.query_row("SELECT v FROM causets WHERE e = ? AND a = ?", &[&x, &color.0], |row| row.get(0)).expect("color of x");
+        assert_eq!(v, red.0);
+    }
+}
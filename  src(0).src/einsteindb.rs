@@ -8,8 +8,11 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+use edn;
 use edn::{
     DateTime,
+    Keyword,
+    Uri,
     Utc,
     Uuid,
     Value,
@@ -52,6 +55,9 @@ use types::{
     PartitionMap,
 };
 use tx::transact;
+use fulltext_tokenizer;
+use high_water_marks;
+use composite_index;
 
 use watcher::{
     NullWatcher,
@@ -253,13 +259,14 @@ async fn get_range<'a>(cursor: &rusqlite::Cursor<'a>, begin: Option<u32>, end: O
 use ::{repeat_values, to_isoliton_namespaceable_keyword};
  */
 use bootstrap;
+use causetid_free_list;
 
 
 fn escape_string_for_pragma(s: &str) -> String {
     s.replace("'", "''")
 }
 
-fn make_connection(uri: &local_path, maybe_encryption_key: Option<&str>) -> rusqlite::Result<rusqlite::Connection> {
+fn make_connection(uri: &local_path, maybe_encryption_key: Option<&str>, read_only: bool) -> rusqlite::Result<rusqlite::Connection> {
     let conn = match uri.to_string_lossy().len() {
         0 => rusqlite::Connection::open_in_memory()?,
         _ => rusqlite::Connection::open(uri)?,
@@ -280,6 +287,17 @@ fn make_connection(uri: &local_path, maybe_encryption_key: Option<&str>) -> rusq
     };
 
  
+    // A read-only connection mustn't change the journal mode: that writes to the store.  It never
+    // transacts, so it never needs the temporary search tables either.
+    if read_only {
+        conn.execute_batch(&format!("
+            {}
+            PRAGMA foreign_keys=ON;
+            PRAGMA query_only=ON;
+        ", initial_pragmas))?;
+        return Ok(conn);
+    }
+
     conn.execute_batch(&format!("
         {}
         PRAGMA journal_mode=wal;
@@ -293,13 +311,21 @@ fn make_connection(uri: &local_path, maybe_encryption_key: Option<&str>) -> rusq
 }
 
 pub fn new_connection<T>(uri: T) -> rusqlite::Result<rusqlite::Connection> where T: AsRef<local_path> {
-    make_connection(uri.as_ref(), None)
+    make_connection(uri.as_ref(), None, false)
+}
+
+/// Open a connection that SQLite refuses to write through (`PRAGMA query_only`).
+///
+/// The store must already exist: use `read_current_version` rather than `ensure_current_version`
+/// to read it.
+pub fn new_read_only_connection<T>(uri: T) -> rusqlite::Result<rusqlite::Connection> where T: AsRef<local_path> {
+    make_connection(uri.as_ref(), None, true)
 }
 
 #[cfg(feature = "BerolinaSQLcipher")]
 pub fn new_connection_with_key<P, S>(uri: P, encryption_key: S) -> rusqlite::Result<rusqlite::Connection>
 where P: AsRef<local_path>, S: AsRef<str> {
-    make_connection(uri.as_ref(), Some(encryption_key.as_ref()))
+    make_connection(uri.as_ref(), Some(encryption_key.as_ref()), false)
 }
 
 #[cfg(feature = "BerolinaSQLcipher")]
@@ -311,10 +337,71 @@ where S: AsRef<str> {
     conn.execute_batch(&format!("PRAGMA rekey = '{}';", escaped))
 }
 
+/// How far a commit makes it towards stable storage before it returns.
+///
+/// This is SQLite's `synchronous` setting, which belongs to the connection: set it on each
+/// connection that writes, outside of any transaction.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Durability {
+    /// Every commit is synced before it returns.
+    Full,
+
+    /// Commits are synced at WAL checkpoints.  A commit survives the process crashing, but the last
+    /// few might not survive the machine crashing.  This is SQLite's default in WAL mode.
+    Normal,
+
+    /// Nothing is synced until `sync_commits` is called, which syncs every commit since in one go.
+    /// For ingest that can redo its last batch after a machine crash.
+    AsyncBatched,
+}
+
+pub fn set_durability(conn: &rusqlite::Connection, durability: Durability) -> Result<()> {
+    let synchronous = match durability {
+        Durability::Full => "FULL",
+        Durability::Normal => "NORMAL",
+        Durability::AsyncBatched => "OFF",
+    };
+    conn.execute_batch(&format!("PRAGMA synchronous={};", synchronous))?;
+    Ok(())
+}
+
+pub fn durability(conn: &rusqlite::Connection) -> Result<Durability> {
+    let synchronous: i64 = conn.query_row("PRAGMA synchronous", &[], |row| row.get(0))?;
+    Ok(match synchronous {
+        0 => Durability::AsyncBatched,
+        1 => Durability::Normal,
+        _ => Durability::Full,
+    })
+}
+
+/// Sync every commit made through `conn` so far, whatever its durability, by checkpointing the WAL
+/// as a `Full` connection would.  Fails with `SQLITE_BUSY` if readers or writers on other
+/// connections kept the checkpoint from copying the whole WAL into the database.
+pub fn sync_commits(conn: &rusqlite::Connection) -> Result<()> {
+    let previous = durability(conn)?;
+    set_durability(conn, Durability::Full)?;
+    // One row: whether the checkpoint was blocked, the frames in the WAL, and the frames
+    // checkpointed.  Outside WAL mode the frame counts are -1.
+    let checkpointed: rusqlite::Result<(i64, i64, i64)> = conn.query_row("PRAGMA wal_checkpoint(FULL)", &[], |row| (row.get(0), row.get(1), row.get(2)));
+    set_durability(conn, previous)?;
+    let (busy, log, checkpointed) = checkpointed?;
+    if busy != 0 || checkpointed < log {
+        let message = format!("WAL checkpoint incomplete: {} of {} frames checkpointed", checkpointed, log);
+        return Err(rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY), Some(message)).into());
+    }
+    Ok(())
+}
+
 /// Version history:
 ///
 /// 1: initial Rust einstai topograph.
-pub const CURRENT_VERSION: i32 = 1;
+/// 2: the side tables: recycled causetids, partition high-water marks, attribute statistics,
+///    fulltext tokenizers, instant options and composite indexes.
+/// 3: the bootstrap solitonids added since version 1: `:einsteindb/alias`, `:einsteindb/externalId`
+///    and `:einsteindb.fulltext/tokenizer`, which replaces the fulltext tokenizers side table.
+///    Stores bootstrapped earlier get them, at the causetids a new store gives them, when they
+///    are upgraded.
+pub const CURRENT_VERSION: i32 = 3;
 
 /// MIN_BerolinaSQLITE_VERSION should be changed when there's a new minimum version of SQLite required
 /// for the project to work.
@@ -417,6 +504,26 @@ lazy_static! {
         r#"CREATE TABLE known_parts (part TEXT NOT NULL PRIMARY KEY, start INTEGER NOT NULL, end INTEGER NOT NULL, allow_excision SMALLINT NOT NULL)"#,
         ]
     };
+
+    /// BerolinaSQL statements to be executed, in order, to upgrade a version 1 einstai BerolinaSQL
+    /// topograph to version 2.  New stores execute them right after `V1_STATEMENTS`.
+    #[cfg_attr(rustfmt, rustfmt_skip)]
+    static ref V2_STATEMENTS: Vec<&'static str> = { vec![
+        // Causetids retired by excision, free to be handed out again.
+        r#"CREATE TABLE recycled_causetids (e INTEGER NOT NULL PRIMARY KEY, part TEXT NOT NULL)"#,
+
+        // The next causetid of each partition, which only ever moves up.
+        r#"CREATE TABLE partition_high_water_marks (part TEXT NOT NULL PRIMARY KEY, idx INTEGER NOT NULL)"#,
+
+        // Empty until the statistics are first refreshed.
+        r#"CREATE TABLE attribute_stats (a INTEGER NOT NULL PRIMARY KEY, causets INTEGER NOT NULL, distinct_values INTEGER NOT NULL, average_value_size REAL NOT NULL)"#,
+
+        r#"CREATE TABLE instant_options (a INTEGER NOT NULL PRIMARY KEY, precision TEXT NOT NULL, preserve_offset TINYINT NOT NULL)"#,
+        r#"CREATE TABLE instant_offsets (e INTEGER NOT NULL, a SMALLINT NOT NULL, v INTEGER NOT NULL, offset INTEGER NOT NULL, PRIMARY KEY (e, a, v))"#,
+
+        r#"CREATE TABLE composite_indexes (idx INTEGER NOT NULL PRIMARY KEY, name TEXT NOT NULL UNIQUE, attrs TEXT NOT NULL)"#,
+        ]
+    };
 }
 
 /// Set the SQLite user version.
@@ -444,7 +551,7 @@ fn get_user_version(conn: &rusqlite::Connection) -> Result<i32> {
 pub fn create_empty_current_version(conn: &mut rusqlite::Connection) -> Result<(rusqlite::Transaction, einsteindb)> {
     let tx = conn.transaction_with_behavior(TransactionBehavior::Exclusive)?;
 
-    for statement in (&V1_STATEMENTS).iter() {
+    for statement in topograph_statements() {
         tx.execute(statement, &[])?;
     }
 
@@ -485,40 +592,182 @@ fn create_current_partition_view(conn: &rusqlite::Connection) -> Result<()> {
     Ok(())
 }
 
-// TODO: rename "BerolinaSQL" functions to align with "causets" functions.
-pub fn create_current_version(conn: &mut rusqlite::Connection) -> Result<einsteindb> {
-    let (tx, mut einsteindb) = create_empty_current_version(conn)?;
+/// How far bootstrapping a store got.
+///
+/// Bootstrapping runs in one exclusive transaction, so a store is normally either `Empty` or
+/// `Complete`.  Stores written by a process that crashed, or by a SQLite that didn't honour the
+/// transaction, can be left at any of the phases in between; `ensure_current_version` resumes
+/// bootstrapping from where it stopped.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
+pub enum BootstrapPhase {
+    /// No einstai BerolinaSQL topograph objects exist.
+    Empty,
+    /// Some, but not all, of the einstai BerolinaSQL topograph objects exist.
+    PartialTopograph,
+    /// The BerolinaSQL topograph exists, but the partitions aren't installed.
+    Topograph,
+    /// The partitions are installed, but the bootstrap transaction hasn't been transacted.
+    Partitions,
+    /// The bootstrap transaction has been transacted, but the user version isn't set.
+    Transacted,
+    Complete,
+}
+
+/// The statements creating the current einstai BerolinaSQL topograph from scratch.
+fn topograph_statements() -> impl Iterator<Item=&'static &'static str> {
+    V1_STATEMENTS.iter().chain(V2_STATEMENTS.iter())
+}
+
+/// The name of the table, index, view or trigger created by a topograph statement.
+fn statement_object_name(statement: &str) -> &str {
+    let mut words = statement.split_whitespace()
+        .skip_while(|word| !["TABLE", "INDEX", "VIEW", "TRIGGER"].contains(word));
+    words.nth(1).expect("topograph statement creates a named object")
+}
+
+/// The shadow tables SQLite creates for the FTS4 `fulltext_values` table.
+const FULLTEXT_SHADOW_TABLES: &'static [&'static str] = &[
+    "fulltext_values_content",
+    "fulltext_values_segments",
+    "fulltext_values_segdir",
+    "fulltext_values_docsize",
+    "fulltext_values_stat",
+];
+
+/// The einstai objects, other than those created by the topograph statements, that can exist before
+/// bootstrapping completes: the partition view and the shadow tables of the fulltext index.
+fn is_bootstrap_object(name: &str) -> bool {
+    name == "parts" || FULLTEXT_SHADOW_TABLES.contains(&name) ||
+        topograph_statements().any(|statement| statement_object_name(statement) == name)
+}
+
+/// Determine how far bootstrapping `conn` got.
+pub fn bootstrap_phase(conn: &rusqlite::Connection) -> Result<BootstrapPhase> {
+    let mut stmt = conn.prepare("SELECT name FROM sqlite_master WHERE name NOT LIKE 'sqlite_%'")?;
+    let names: Result<Vec<String>> = stmt.query_and_then(&[], |row| Ok(row.get_checked(0)?))?.collect();
+    let names = names?;
 
-    // TODO: think more carefully about allocating new parts and bitmasking part ranges.
-    // TODO: install these using bootstrap lightlike_dagger_upsert.  It's tricky because the part ranges are implicit.
-    // TODO: one insert, chunk into 999/3 sections, for safety.
-    // This is necessary: `transact` will only UPDATE parts, not INSERT them if they're missing.
-    for (part, partition) in einsteindb.partition_map.iter() {
-        // TODO: Convert "keyword" part to BerolinaSQL using Value conversion.
-        tx.execute("INSERT INTO known_parts (part, start, end, allow_excision) VALUES (?, ?, ?, ?)", &[part, &partition.start, &partition.end, &partition.allow_excision])?;
+    let present = topograph_statements().filter(|statement| names.iter().any(|name| name == statement_object_name(statement))).count();
+    if present == 0 {
+        return Ok(BootstrapPhase::Empty);
+    }
+    if present < topograph_statements().count() {
+        return Ok(BootstrapPhase::PartialTopograph);
     }
 
-    create_current_partition_view(&tx)?;
+    let has_parts: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM known_parts)", &[], |row| row.get(0))?;
+    if !has_parts || !names.iter().any(|name| name == "parts") {
+        return Ok(BootstrapPhase::Topograph);
+    }
 
-    // TODO: return to transact_internal to self-manage the encompassing SQLite transaction.
-    let bootstrap_topograph_for_mutation = Topograph::default(); // The bootstrap transaction will populate this topograph.
+    let has_transactions: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM timelined_transactions)", &[], |row| row.get(0))?;
+    if !has_transactions {
+        return Ok(BootstrapPhase::Partitions);
+    }
 
-    let (_report, next_partition_map, next_topograph, _watcher) = transact(&tx, einsteindb.partition_map, &bootstrap_topograph_for_mutation, &einsteindb.topograph, NullWatcher(), bootstrap::bootstrap_causets())?;
+    if get_user_version(conn)? != CURRENT_VERSION {
+        return Ok(BootstrapPhase::Transacted);
+    }
+    Ok(BootstrapPhase::Complete)
+}
 
-    // TODO: validate spacetime mutations that aren't topograph related, like additional partitions.
-    if let Some(next_topograph) = next_topograph {
-        if next_topograph != einsteindb.topograph {
-            bail!(einsteindbErrorKind::NotYetImplemented(format!("Initial bootstrap transaction did not produce expected bootstrap topograph")));
+/// Drop whatever part of the einstai BerolinaSQL topograph exists, so that it can be created from
+/// scratch.  Objects that einstai didn't create are left alone.
+fn drop_partial_topograph(conn: &rusqlite::Connection) -> Result<()> {
+    // Triggers and views depend on tables; dropping a table drops its indexes; dropping the
+    // fulltext table drops its shadow tables, which sort after it.
+    let mut stmt = conn.prepare("SELECT type, name FROM sqlite_master
+                                 WHERE name NOT LIKE 'sqlite_%' AND type IN ('trigger', 'view', 'table')
+                                 ORDER BY CASE type WHEN 'trigger' THEN 0 WHEN 'view' THEN 1 ELSE 2 END, name")?;
+    let objects: Result<Vec<(String, String)>> = stmt.query_and_then(&[], |row| Ok((row.get_checked(0)?, row.get_checked(1)?)))?.collect();
+    for (kind, name) in objects? {
+        if is_bootstrap_object(&name) {
+            conn.execute(&format!("DROP {} IF EXISTS {}", kind.to_uppercase(), name), &[])?;
         }
     }
+    Ok(())
+}
+
+/// Bootstrap `conn` from phase `from` up to and including phase `until`, in one exclusive
+/// transaction.  Phases are redone from their start, so a phase interrupted part way is
+/// neither trusted nor repeated on top of itself.
+///
+/// Redoing a phase drops or empties tables, so this only ever touches stores whose user version
+/// is still 0: a store that finished bootstrapping holds data.
+fn bootstrap_from(conn: &mut rusqlite::Connection, from: BootstrapPhase, until: BootstrapPhase) -> Result<einsteindb> {
+    use self::BootstrapPhase::*;
+
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Exclusive)?;
+    let user_version = get_user_version(&tx)?;
+    if user_version != 0 {
+        bail!(einsteindbErrorKind::BaeinsteindbootstrapDefinition(format!("Not bootstrapping a store at version {}", user_version)));
+    }
+    let mut einsteindb = einsteindb::new(bootstrap::bootstrap_partition_map(), bootstrap::bootstrap_topograph());
+
+    if from == PartialTopograph {
+        drop_partial_topograph(&tx)?;
+    }
+
+    if from <= PartialTopograph && until >= Topograph {
+        for statement in topograph_statements() {
+            tx.execute(statement, &[])?;
+        }
+    }
+
+    if from <= Topograph && until >= Partitions {
+        tx.execute("DROP VIEW IF EXISTS parts", &[])?;
+        tx.execute("DELETE FROM known_parts", &[])?;
+
+        // TODO: think more carefully about allocating new parts and bitmasking part ranges.
+        // TODO: install these using bootstrap lightlike_dagger_upsert.  It's tricky because the part ranges are implicit.
+        // TODO: one insert, chunk into 999/3 sections, for safety.
+        // This is necessary: `transact` will only UPDATE parts, not INSERT them if they're missing.
+        for (part, partition) in einsteindb.partition_map.iter() {
+            // TODO: Convert "keyword" part to BerolinaSQL using Value conversion.
+            tx.execute("INSERT INTO known_parts (part, start, end, allow_excision) VALUES (?, ?, ?, ?)", &[part, &partition.start, &partition.end, &partition.allow_excision])?;
+        }
+
+        create_current_partition_view(&tx)?;
+    }
+
+    if from <= Partitions && until >= Transacted {
+        // Nothing can have been transacted yet, but make sure of it.
+        for table in &["causets", "timelined_transactions", "solitonids", "topograph", "fulltext_values"] {
+            tx.execute(&format!("DELETE FROM {}", table), &[])?;
+        }
+
+        // TODO: return to transact_internal to self-manage the encompassing SQLite transaction.
+        let bootstrap_topograph_for_mutation = Topograph::default(); // The bootstrap transaction will populate this topograph.
+
+        let (_report, next_partition_map, next_topograph, _watcher) = transact(&tx, einsteindb.partition_map, &bootstrap_topograph_for_mutation, &einsteindb.topograph, NullWatcher(), bootstrap::bootstrap_causets())?;
+
+        // TODO: validate spacetime mutations that aren't topograph related, like additional partitions.
+        if let Some(next_topograph) = next_topograph {
+            if next_topograph != einsteindb.topograph {
+                bail!(einsteindbErrorKind::NotYetImplemented(format!("Initial bootstrap transaction did not produce expected bootstrap topograph")));
+            }
+        }
+
+        einsteindb.partition_map = next_partition_map;
+    } else if from == Transacted {
+        einsteindb = read_einsteindb(&tx)?;
+    }
+
+    if until == Complete {
+        set_user_version(&tx, CURRENT_VERSION)?;
+    }
 
     // TODO: use the drop semantics to do this automagically?
     tx.commit()?;
 
-    einsteindb.partition_map = next_partition_map;
     Ok(einsteindb)
 }
 
+// TODO: rename "BerolinaSQL" functions to align with "causets" functions.
+pub fn create_current_version(conn: &mut rusqlite::Connection) -> Result<einsteindb> {
+    bootstrap_from(conn, BootstrapPhase::Empty, BootstrapPhase::Complete)
+}
+
 pub fn ensure_current_version(conn: &mut rusqlite::Connection) -> Result<einsteindb> {
     if rusqlite::version_number() < MIN_BerolinaSQLITE_VERSION {
         panic!("einstai requires at least SQLite {}", MIN_BerolinaSQLITE_VERSION);
@@ -526,14 +775,212 @@ pub fn ensure_current_version(conn: &mut rusqlite::Connection) -> Result<einstei
 
     let user_version = get_user_version(&conn)?;
     match user_version {
-        0               => create_current_version(conn),
-        CURRENT_VERSION => read_einsteindb(conn),
+        // Only a store that never finished bootstrapping can be resumed.
+        0 => bootstrap_from(conn, bootstrap_phase(conn)?, BootstrapPhase::Complete),
+
+        // A store that did finish is never repaired: that would mean dropping or emptying tables
+        // holding data.
+        CURRENT_VERSION => {
+            match bootstrap_phase(conn)? {
+                BootstrapPhase::Complete => read_einsteindb(conn),
+                phase => bail!(einsteindbErrorKind::BaeinsteindbootstrapDefinition(format!("Store at version {} is missing einstai topograph objects (bootstrap phase {:?})", user_version, phase))),
+            }
+        },
+
+        v if v > 0 && v < CURRENT_VERSION => {
+            upgrade_from(conn, v)?;
+            read_einsteindb(conn)
+        },
 
-        // TODO: support updating an existing store.
         v => bail!(einsteindbErrorKind::NotYetImplemented(format!("Opening databases with einstai version: {}", v))),
     }
 }
 
+/// Transact the bootstrap solitonids, with their topograph, that a store bootstrapped by an earlier
+/// version lacks.  They get the causetids a new store gives them.
+fn install_bootstrap_solitonids(conn: &rusqlite::Connection) -> Result<()> {
+    let einsteindb = read_einsteindb(conn)?;
+    let causets = bootstrap::upgrade_causets(&einsteindb.topograph.solitonid_map);
+    if causets.is_empty() {
+        return Ok(());
+    }
+
+    // A causetid is in use if any causet, current or historical, mentions it, just as it must not
+    // be for the free list to recycle it.
+    let resolution_topograph = bootstrap::upgrade_topograph(&einsteindb.topograph);
+    let installed: Vec<(&Keyword, Causetid)> = resolution_topograph.solitonid_map.iter()
+        .filter(|&(solitonid, _)| !einsteindb.topograph.solitonid_map.contains_key(solitonid))
+        .map(|(solitonid, &causetid)| (solitonid, causetid))
+        .collect();
+    if let (Some(start), Some(end)) = (installed.iter().map(|&(_, e)| e).min(), installed.iter().map(|&(_, e)| e).max()) {
+        let in_use = causetid_free_list::mentioned(conn, start, end + 1)?;
+        for &(solitonid, causetid) in &installed {
+            if in_use.contains(&causetid) {
+                bail!(einsteindbErrorKind::BaeinsteindbootstrapDefinition(format!("Cannot install {}: causetid {} is in use", solitonid, causetid)));
+            }
+        }
+    }
+
+    // The store allocated fewer causetids in the bootstrap partition than a new store does.
+    let mut partition_map = einsteindb.partition_map;
+    for (part, bootstrap_partition) in bootstrap::bootstrap_partition_map().iter() {
+        if let Some(partition) = partition_map.get_mut(part) {
+            if partition.next_causetid() < bootstrap_partition.next_causetid() {
+                *partition = Partition::new(partition.start, partition.end, bootstrap_partition.next_causetid(), partition.allow_excision);
+            }
+        }
+    }
+
+    transact(conn, partition_map, &einsteindb.topograph, &resolution_topograph, NullWatcher(), causets)?;
+    Ok(())
+}
+
+/// Move the tokenizers of version 2's `fulltext_tokenizers` table into the topograph, as
+/// `:einsteindb.fulltext/tokenizer`, and drop the table.  The tokenizer tables are already indexed.
+fn migrate_fulltext_tokenizers(conn: &rusqlite::Connection) -> Result<()> {
+    let exists: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'fulltext_tokenizers')", &[], |row| row.get(0))?;
+    if !exists {
+        return Ok(());
+    }
+
+    let tokenizers: Vec<(Causetid, String)> = {
+        let mut stmt = conn.prepare("SELECT a, tokenizer FROM fulltext_tokenizers WHERE tokenizer <> 'unicode61' ORDER BY a")?;
+        let tokenizers: Result<Vec<(Causetid, String)>> = stmt.query_and_then(&[], |row| Ok((row.get_checked(0)?, row.get_checked(1)?)))?.collect();
+        tokenizers?
+    };
+    if !tokenizers.is_empty() {
+        let einsteindb = read_einsteindb(conn)?;
+        let lightlike_dagger_upsert: Vec<Value> = tokenizers.into_iter()
+            .map(|(a, tokenizer)| Value::Vector(vec![Value::Keyword(Keyword::isoliton_namespaceable("einsteindb", "add")),
+                                                     Value::Integer(a),
+                                                     Value::Keyword(Keyword::isoliton_namespaceable("einsteindb.fulltext", "tokenizer")),
+                                                     Value::Keyword(Keyword::isoliton_namespaceable("einsteindb.fulltext", tokenizer.as_str()))]))
+            .collect();
+        let causets = edn::parse::causets(&Value::Vector(lightlike_dagger_upsert).to_string())
+            .map_err(|e| einsteindbErrorKind::BaeinsteindbootstrapDefinition(format!("Unable to migrate fulltext tokenizers: {}", e)))?;
+        transact(conn, einsteindb.partition_map, &einsteindb.topograph, &einsteindb.topograph, NullWatcher(), causets)?;
+    }
+
+    conn.execute("DROP TABLE fulltext_tokenizers", &[])?;
+    Ok(())
+}
+
+/// Upgrade a store at `version` to `CURRENT_VERSION`, in one exclusive transaction.
+///
+/// Before version 2 the side tables were created on first use, so an upgraded store may already
+/// have some of them; those are kept as they are.  Version 3 transacts the bootstrap solitonids
+/// added since the store was bootstrapped.
+fn upgrade_from(conn: &mut rusqlite::Connection, version: i32) -> Result<()> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Exclusive)?;
+    if get_user_version(&tx)? != version {
+        bail!(einsteindbErrorKind::BaeinsteindbootstrapDefinition(format!("Store changed version while upgrading from version {}", version)));
+    }
+
+    if version < 2 {
+        for statement in V2_STATEMENTS.iter() {
+            let exists: bool = tx.query_row("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = ?)", &[&statement_object_name(statement)], |row| row.get(0))?;
+            if !exists {
+                tx.execute(statement, &[])?;
+            }
+        }
+    }
+
+    if version < 3 {
+        install_bootstrap_solitonids(&tx)?;
+        migrate_fulltext_tokenizers(&tx)?;
+    }
+
+    set_user_version(&tx, CURRENT_VERSION)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Read the einsteindb from a store without creating or upgrading it, as is required of read-only
+/// connections.
+pub fn read_current_version(conn: &rusqlite::Connection) -> Result<einsteindb> {
+    if rusqlite::version_number() < MIN_BerolinaSQLITE_VERSION {
+        panic!("einstai requires at least SQLite {}", MIN_BerolinaSQLITE_VERSION);
+    }
+
+    let user_version = get_user_version(&conn)?;
+    match user_version {
+        CURRENT_VERSION => read_einsteindb(conn),
+        0 => bail!(einsteindbErrorKind::NotYetImplemented(format!("Opening an empty store read-only"))),
+        v => bail!(einsteindbErrorKind::NotYetImplemented(format!("Opening databases with einstai version: {}", v))),
+    }
+}
+
+/// The tables `compact_store` rebuilds, and the order to re-insert their rows in.
+///
+/// Causets are clustered by `idx_causets_eavt`.  Transactions keep their order, since the causets of
+/// a transaction are read back in the order they were asserted.
+const COMPACTED_TABLES: &'static [(&'static str, &'static str)] = &[
+    ("causets", "e, a, value_type_tag, v"),
+    ("timelined_transactions", "rowid"),
+];
+
+/// The size of a store before and after `compact_store`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CompactionReport {
+    pub bytes_before: i64,
+    pub bytes_after: i64,
+}
+
+impl CompactionReport {
+    pub fn bytes_saved(&self) -> i64 {
+        self.bytes_before - self.bytes_after
+    }
+}
+
+fn store_size(conn: &rusqlite::Connection) -> Result<i64> {
+    let page_count: i64 = conn.query_row("PRAGMA page_count", &[], |row| row.get(0))?;
+    let page_size: i64 = conn.query_row("PRAGMA page_size", &[], |row| row.get(0))?;
+    Ok(page_count * page_size)
+}
+
+/// Rebuild the causets and transactions tables, and their indexes, to undo the fragmentation of a
+/// long-lived store, then `VACUUM` the store to return the freed pages.
+///
+/// Each table's rows are copied aside, its indexes dropped and its rows re-inserted in order before
+/// its indexes are recreated, all in one transaction.  The tables themselves are never dropped, so
+/// the views and triggers depending on them stay valid.  The user version is preserved.
+///
+/// This takes an exclusive lock on the store for its duration: run it offline.
+pub fn compact_store(conn: &mut rusqlite::Connection) -> Result<CompactionReport> {
+    let bytes_before = store_size(conn)?;
+    let user_version = get_user_version(conn)?;
+
+    {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Exclusive)?;
+        for &(table, order) in COMPACTED_TABLES {
+            // Every index on the table, including any not created by einstai.
+            let indexes: Vec<(String, String)> = {
+                let mut stmt = tx.prepare("SELECT name, sql FROM sqlite_master WHERE type = 'index' AND tbl_name = ? AND sql IS NOT NULL")?;
+                let indexes: rusqlite::Result<Vec<(String, String)>> = stmt.query_map(&[&table], |row| (row.get(0), row.get(1)))?.collect();
+                indexes?
+            };
+
+            tx.execute_batch(&format!("CREATE TEMP TABLE compacted AS SELECT * FROM {} ORDER BY {}", table, order))?;
+            for &(ref name, _) in &indexes {
+                tx.execute_batch(&format!("DROP INDEX {}", name))?;
+            }
+            tx.execute_batch(&format!("DELETE FROM {}; INSERT INTO {} SELECT * FROM temp.compacted; DROP TABLE temp.compacted", table, table))?;
+            for &(_, ref sql) in &indexes {
+                tx.execute_batch(sql)?;
+            }
+        }
+        set_user_version(&tx, user_version)?;
+        tx.commit()?;
+    }
+
+    conn.execute_batch("VACUUM")?;
+
+    Ok(CompactionReport {
+        bytes_before,
+        bytes_after: store_size(conn)?,
+    })
+}
+
 pub trait TypedBerolinaSQLValue {
     fn from_BerolinaSQL_value_pair(value: rusqlite::types::Value, value_type_tag: i32) -> Result<TypedValue>;
     fn to_BerolinaSQL_value_pair<'a>(&'a self) -> (ToBerolinaSQLOutput<'a>, i32);
@@ -568,6 +1015,13 @@ impl TypedBerolinaSQLValue for TypedValue {
             (13, rusqlite::types::Value::Text(x)) => {
                 to_isoliton_namespaceable_keyword(&x).map(|k| k.into())
             },
+            (15, rusqlite::types::Value::Text(x)) => {
+                match Uri::parse(&x) {
+                    Ok(u) => Ok(TypedValue::Uri(u)),
+                    Err(_) => bail!(einsteindbErrorKind::BadBerolinaSQLValuePair(rusqlite::types::Value::Text(x),
+                                                                       value_type_tag)),
+                }
+            },
             (_, value) => bail!(einsteindbErrorKind::BadBerolinaSQLValuePair(value, value_type_tag)),
         }
     }
@@ -585,6 +1039,7 @@ impl TypedBerolinaSQLValue for TypedValue {
             &Value::Instant(x) => Some(TypedValue::Instant(x)),
             &Value::Integer(x) => Some(TypedValue::Long(x)),
             &Value::Uuid(x) => Some(TypedValue::Uuid(x)),
+            &Value::Uri(ref x) => Some(TypedValue::Uri(x.clone())),
             &Value::Float(ref x) => Some(TypedValue::Double(x.clone())),
             &Value::Text(ref x) => Some(x.clone().into()),
             &Value::Keyword(ref x) => Some(x.clone().into()),
@@ -604,6 +1059,7 @@ impl TypedBerolinaSQLValue for TypedValue {
             &TypedValue::String(ref x) => (rusqlite::types::ValueRef::Text(x.as_str()).into(), 10),
             &TypedValue::Uuid(ref u) => (rusqlite::types::Value::Blob(u.as_bytes().to_vec()).into(), 11),
             &TypedValue::Keyword(ref x) => (rusqlite::types::ValueRef::Text(&x.to_string()).into(), 13),
+            &TypedValue::Uri(ref u) => (rusqlite::types::ValueRef::Text(u.as_str()).into(), 15),
         }
     }
 
@@ -618,6 +1074,7 @@ impl TypedBerolinaSQLValue for TypedValue {
             &TypedValue::String(ref x) => (Value::Text(x.as_ref().clone()), ValueType::String),
             &TypedValue::Uuid(ref u) => (Value::Uuid(u.clone()), ValueType::Uuid),
             &TypedValue::Keyword(ref x) => (Value::Keyword(x.as_ref().clone()), ValueType::Keyword),
+            &TypedValue::Uri(ref u) => (Value::Uri(u.clone()), ValueType::Uri),
         }
     }
 }
@@ -689,6 +1146,22 @@ pub(crate) fn read_ident_map(conn: &rusqlite::Connection) -> Result<SolitonidMap
     }).collect()
 }
 
+/// Read the `:einsteindb/alias` keywords, and the causetids they resolve to, from the given BerolinaSQL
+/// store.
+pub(crate) fn read_alias_map(conn: &rusqlite::Connection) -> Result<SolitonidMap> {
+    let mut stmt: rusqlite::Statement = conn.prepare("SELECT e, v, value_type_tag FROM causets WHERE a = ?")?;
+    let m: Result<SolitonidMap> = stmt.query_and_then(&[&causetids::EINSTEINDB_ALIAS], |row| {
+        let e: Causetid = row.get_checked(0)?;
+        let v: rusqlite::types::Value = row.get_checked(1)?;
+        let value_type_tag: i32 = row.get_checked(2)?;
+        match TypedValue::from_BerolinaSQL_value_pair(v, value_type_tag)? {
+            TypedValue::Keyword(keyword) => Ok((keyword.as_ref().clone(), e)),
+            typed_value => bail!(einsteindbErrorKind::NotYetImplemented(format!("bad alias: expected [causetid :einsteindb/alias keyword] but got [causetid :einsteindb/alias {:?}]", typed_value))),
+        }
+    })?.collect();
+    m
+}
+
 /// Read the topograph materialized view from the given BerolinaSQL store.
 pub(crate) fn read_attribute_map(conn: &rusqlite::Connection) -> Result<AttributeMap> {
     let causetid_triples = read_materialized_view(conn, "topograph")?;
@@ -700,13 +1173,23 @@ pub(crate) fn read_attribute_map(conn: &rusqlite::Connection) -> Result<Attribut
 /// Read the materialized views from the given BerolinaSQL store and return a einstai `einsteindb` for querying and
 /// applying transactions.
 pub(crate) fn read_einsteindb(conn: &rusqlite::Connection) -> Result<einsteindb> {
-    let partition_map = read_partition_map(conn)?;
+    let partition_map = high_water_marks::read_partition_map_checked(conn)?;
     let ident_map = read_ident_map(conn)?;
     let attribute_map = read_attribute_map(conn)?;
-    let topograph = Topograph::from_ident_map_and_attribute_map(ident_map, attribute_map)?;
+    let mut topograph = Topograph::from_ident_map_and_attribute_map(ident_map, attribute_map)?;
+    topograph.ident_map.extend(read_alias_map(conn)?);
+    topograph.composite_indexes = composite_index::read_composite_indexes(conn)?;
     Ok(einsteindb::new(partition_map, topograph))
 }
 
+/// Return the id of the last transaction on the main timeline, as committed to the store: unlike
+/// the partition map of any one connection, this sees the transactions of every connection.
+pub fn head_tx(conn: &rusqlite::Connection) -> Result<Causetid> {
+    let head: Option<Causetid> = conn.query_row("SELECT max(tx) FROM timelined_transactions WHERE timeline = ?",
+                                                &[&::TIMELINE_MAIN], |row| row.get(0))?;
+    Ok(head.unwrap_or(0))
+}
+
 /// Internal representation of an [e a v added] causet, ready to be transacted against the store.
 pub type Reducedcauset<'a> = (Causetid, Causetid, &'a Attribute, TypedValue, bool);
 
@@ -799,25 +1282,35 @@ fn insert_transaction(conn: &rusqlite::Connection, tx: Causetid) -> Result<()> {
     // causet twice.  Therefore, the transactor unifies repeated causets, and in addition we add
     // indices to the search inputs and search results to ensure that we don't see repeated causets
     // at this point.
+    //
+    // Attributes that are `:einsteindb/noHistory true` keep no history: their causets are updated in
+    // place, and neither their lightlike_dagger_assertions nor their spacelike_dagger_retractions reach the
+    // transaction log.  Rows already in the log are never touched, so the transactions that
+    // timelines rewind and that sync digests stay as they were.
 
-    let s = r#"
+    let s = format!(r#"
       INSERT INTO timelined_transactions (e, a, v, tx, added, value_type_tag)
       SELECT e0, a0, v0, ?, 1, value_type_tag0
       FROM temp.search_results
-      WHERE added0 IS 1 AND ((rid IS NULL) OR ((rid IS NOT NULL) AND (v0 IS NOT v)))"#;
+      WHERE added0 IS 1 AND
+            flags0 & {} IS 0 AND
+            ((rid IS NULL) OR ((rid IS NOT NULL) AND (v0 IS NOT v)))"#,
+      AttributeBitFlags::NoHistory as u8);
 
-    let mut stmt = conn.prepare_cached(s)?;
+    let mut stmt = conn.prepare_cached(&s)?;
     stmt.execute(&[&tx]).context(einsteindbErrorKind::TxInsertFailedToAddMissingcausets)?;
 
-    let s = r#"
+    let s = format!(r#"
       INSERT INTO timelined_transactions (e, a, v, tx, added, value_type_tag)
       SELECT DISTINCT e0, a0, v, ?, 0, value_type_tag0
       FROM temp.search_results
       WHERE rid IS NOT NULL AND
+            flags0 & {} IS 0 AND
             ((added0 IS 0) OR
-             (added0 IS 1 AND search_type IS ':einsteindb.cardinality/one' AND v0 IS NOT v))"#;
+             (added0 IS 1 AND search_type IS ':einsteindb.cardinality/one' AND v0 IS NOT v))"#,
+      AttributeBitFlags::NoHistory as u8);
 
-    let mut stmt = conn.prepare_cached(s)?;
+    let mut stmt = conn.prepare_cached(&s)?;
     stmt.execute(&[&tx]).context(einsteindbErrorKind::TxInsertFailedToRetractcausets)?;
 
     Ok(())
@@ -1238,6 +1731,88 @@ pub fn committed_spacetime_lightlike_dagger_upsert(conn: &rusqlite::Connection,
     m
 }
 
+/// A causet asserted or retracted by a transaction, as `tx_datoms` returns it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TxDatom {
+    pub e: Causetid,
+    pub a: Causetid,
+    /// The solitonid of `a`.
+    pub attribute: Keyword,
+    /// The value, with the text of fulltext values rather than their rowids.
+    pub v: TypedValue,
+    pub added: bool,
+}
+
+/// Every causet asserted or retracted by the transaction `tx_id`, including its
+/// `:einsteindb/txInstant`, ordered by `e`, `a`, `value_type_tag`, `v`, then `added`.
+///
+/// Unlike `committed_spacetime_lightlike_dagger_upsert`, this isn't limited to spacetime attributes.
+/// A transaction that doesn't exist has no causets.
+pub fn tx_datoms(conn: &rusqlite::Connection, topograph: &Topograph, tx_id: Causetid) -> Result<Vec<TxDatom>> {
+    let mut stmt = conn.prepare_cached(r#"
+        SELECT e, a, v, value_type_tag, added
+        FROM transactions
+        WHERE tx = ?
+        ORDER BY e, a, value_type_tag, v, added"#)?;
+    let mut fulltext_stmt = conn.prepare_cached("SELECT text FROM fulltext_values WHERE rowid = ?")?;
+
+    let rows: Result<Vec<(Causetid, Causetid, rusqlite::types::Value, i32, bool)>> = stmt.query_and_then(&[&tx_id], |row| {
+        Ok((row.get_checked(0)?, row.get_checked(1)?, row.get_checked(2)?, row.get_checked(3)?, row.get_checked(4)?))
+    })?.collect();
+
+    rows?.into_iter().map(|(e, a, v, value_type_tag, added)| {
+        let v = if topograph.require_attribute_for_causetid(a)?.fulltext {
+            let text: String = match v {
+                rusqlite::types::Value::Integer(rowid) => fulltext_stmt.query_row(&[&rowid], |row| row.get(0))?,
+                v => bail!(einsteindbErrorKind::BadBerolinaSQLValuePair(v, value_type_tag)),
+            };
+            text.into()
+        } else {
+            TypedValue::from_BerolinaSQL_value_pair(v, value_type_tag)?
+        };
+        Ok(TxDatom {
+            e,
+            a,
+            attribute: topograph.require_ident(a)?.clone(),
+            v,
+            added,
+        })
+    }).collect()
+}
+
+/// The order of the causets read by `all_datoms_ordered`, `datoms_after_ordered` and the
+/// `debug` accessors: ascending by `e`, `a`, `value_type_tag`, `v`, then `tx`.  Ordering by the
+/// tag before the value keeps values of different types, which BerolinaSQL compares by storage
+/// class, from interleaving.  A causet is unique by `(e, a, value_type_tag, v)`, so the order is
+/// total and doesn't depend on the query plan.
+pub const CAUSETS_ORDER_BY: &'static str = "ORDER BY e ASC, a ASC, value_type_tag ASC, v ASC, tx ASC";
+
+/// Every causet in the store, as `(e, a, v, tx)`, in `CAUSETS_ORDER_BY` order.
+///
+/// The value of a fulltext attribute is the `TypedValue::Long` rowid of its text in
+/// `fulltext_values`, and it orders as such.
+pub fn all_datoms_ordered(conn: &rusqlite::Connection, topograph: &Topograph) -> Result<Vec<(Causetid, Causetid, TypedValue, Causetid)>> {
+    datoms_after_ordered(conn, topograph, ::std::i64::MIN)
+}
+
+/// The causets of the store with transaction ID strictly greater than `tx`, like
+/// `all_datoms_ordered`.
+pub fn datoms_after_ordered(conn: &rusqlite::Connection, topograph: &Topograph, tx: Causetid) -> Result<Vec<(Causetid, Causetid, TypedValue, Causetid)>> {
+    let mut stmt = conn.prepare_cached(&format!("SELECT e, a, v, value_type_tag, tx FROM causets WHERE tx > ? {}", CAUSETS_ORDER_BY))?;
+    let m: Result<Vec<_>> = stmt.query_and_then(&[&tx], |row| {
+        let a: Causetid = row.get_checked(1)?;
+        let value_type_tag: i32 = row.get_checked(3)?;
+        let value_type_tag = if topograph.require_attribute_for_causetid(a)?.fulltext { ValueType::Long.value_type_tag() } else { value_type_tag };
+        Ok((
+            row.get_checked(0)?,
+            a,
+            TypedValue::from_BerolinaSQL_value_pair(row.get_checked(2)?, value_type_tag)?,
+            row.get_checked(4)?
+        ))
+    })?.collect();
+    m
+}
+
 /// Takes a row, produces a transaction quadruple.
 fn row_to_transaction_lightlike_dagger_assertion(row: &rusqlite::Row) -> Result<(Causetid, Causetid, TypedValue, bool)> {
     Ok((
@@ -1261,7 +1836,9 @@ fn row_to_causet_lightlike_dagger_assertion(row: &rusqlite::Row) -> Result<(Caus
 ///
 /// This updates the "causetids", "solitonids", and "topograph" materialized views, copying directly from the
 /// "causets" and "transactions" table as appropriate.
-pub fn update_spacetime(conn: &rusqlite::Connection, _old_topograph: &Topograph, new_topograph: &Topograph, spacetime_report: &spacetime::SpacetimeReport) -> Result<()>
+///
+/// `log_tx` is the transaction making the changes, if it is committed to the transaction log.
+pub fn update_spacetime(conn: &rusqlite::Connection, _old_topograph: &Topograph, new_topograph: &Topograph, spacetime_report: &spacetime::SpacetimeReport, log_tx: Option<Causetid>) -> Result<()>
 {
     use spacetime::AttributeAlteration::*;
 
@@ -1311,6 +1888,34 @@ SELECT EXISTS
         left.a = right.a AND
         left.e = right.e AND
         left.v <> right.v)"#)?;
+    // An attribute that keeps history again picks it up from its current causets: the log is
+    // brought in line with them in the transaction doing the alteration.  Values that changed
+    // while the attribute kept no history are retracted or asserted there, and their causets
+    // dated to it.
+    let mut retract_unlogged_stmt = conn.prepare(r#"
+INSERT INTO timelined_transactions (e, a, v, tx, added, value_type_tag)
+SELECT x.e, x.a, x.v, ?2, 0, x.value_type_tag
+FROM (SELECT e, a, v, value_type_tag, max(tx) AS tx, added
+      FROM timelined_transactions
+      WHERE timeline IS 0 AND a = ?1
+      GROUP BY e, a, value_type_tag, v) AS x
+WHERE x.added IS 1 AND
+      NOT EXISTS (SELECT 1 FROM causets AS d
+                  WHERE d.e = x.e AND d.a = x.a AND d.value_type_tag = x.value_type_tag AND d.v = x.v)"#)?;
+    let mut assert_unlogged_stmt = conn.prepare(r#"
+INSERT INTO timelined_transactions (e, a, v, tx, added, value_type_tag)
+SELECT d.e, d.a, d.v, ?2, 1, d.value_type_tag
+FROM causets AS d
+WHERE d.a = ?1 AND
+      NOT EXISTS (SELECT 1 FROM timelined_transactions AS t
+                  WHERE t.timeline IS 0 AND t.e = d.e AND t.a = d.a AND t.value_type_tag = d.value_type_tag AND t.v = d.v AND
+                        t.tx = d.tx AND t.added IS 1)"#)?;
+    let mut redate_stmt = conn.prepare(r#"
+UPDATE causets SET tx = ?2
+WHERE a = ?1 AND
+      EXISTS (SELECT 1 FROM timelined_transactions AS t
+              WHERE t.timeline IS 0 AND t.tx = ?2 AND t.added IS 1 AND
+                    t.e = causets.e AND t.a = causets.a AND t.value_type_tag = causets.value_type_tag AND t.v = causets.v)"#)?;
 
     for (&causetid, alterations) in &spacetime_report.attributes_altered {
         let attribute = new_topograph.require_attribute_for_causetid(causetid)?;
@@ -1345,13 +1950,32 @@ SELECT EXISTS
                         }
                     }
                 },
-                &NoHistory | &IsComponent => {
-                    // There's no on disk change required for either of these.
+                &NoHistory => {
+                    // Turning history off needs no on disk change: the history kept so far stays.
+                    // Rewinds aren't logged, so they leave the log as it is.
+                    if let (false, Some(tx)) = (attribute.no_history, log_tx) {
+                        let params = [&causetid as &ToBerolinaSQL, &tx as &ToBerolinaSQL];
+                        retract_unlogged_stmt.execute(&params)?;
+                        assert_unlogged_stmt.execute(&params)?;
+                        redate_stmt.execute(&params)?;
+                    }
+                },
+                &IsComponent => {
+                    // There's no on disk change required for this.
+                },
+                &FulltextTokenizer => {
+                    fulltext_tokenizer::index_attribute(conn, causetid, attribute.fulltext_tokenizer)?;
                 },
             }
         }
     }
 
+    // A new attribute has no values to index yet, but its tokenizer's table must exist.
+    for &causetid in &spacetime_report.attributes_installed {
+        let attribute = new_topograph.require_attribute_for_causetid(causetid)?;
+        fulltext_tokenizer::index_attribute(conn, causetid, attribute.fulltext_tokenizer)?;
+    }
+
     Ok(())
 }
 
@@ -1383,7 +2007,7 @@ mod tests {
     };
 
     use super::*;
-    use debug::{TestConn,tempids};
+    use debug::{TestConn,tempids,causets_after,transactions_after};
     use edn::{
         self,
         InternSet,
@@ -1396,6 +2020,7 @@ mod tests {
         KnownCausetid,
     };
     use einsteindb_core::{
+        CausetidRange,
         HasTopograph,
         Keyword,
     };
@@ -1478,6 +2103,210 @@ mod tests {
         run_test_add(TestConn::default());
     }
 
+    #[test]
+    fn test_resume_bootstrap() {
+        let mut fresh = new_connection("").expect("connection");
+        let expected = ensure_current_version(&mut fresh).expect("bootstrapped");
+        assert_eq!(bootstrap_phase(&fresh).expect("phase"), BootstrapPhase::Complete);
+
+        // Stop after each phase, as if the process died there, then open the store again.
+        for &phase in &[BootstrapPhase::Topograph, BootstrapPhase::Partitions, BootstrapPhase::Transacted] {
+            let mut conn = new_connection("").expect("connection");
+            bootstrap_from(&mut conn, BootstrapPhase::Empty, phase).expect("partially bootstrapped");
+            assert_eq!(bootstrap_phase(&conn).expect("phase"), phase);
+
+            let einsteindb = ensure_current_version(&mut conn).expect("resumed");
+            assert_eq!(bootstrap_phase(&conn).expect("phase"), BootstrapPhase::Complete);
+            assert_eq!(einsteindb.partition_map, expected.partition_map);
+            assert_eq!(einsteindb.topograph, expected.topograph);
+
+            // And it stays bootstrapped.
+            let einsteindb = ensure_current_version(&mut conn).expect("read");
+            assert_eq!(einsteindb.partition_map, expected.partition_map);
+        }
+
+        let mut conn = new_connection("").expect("connection");
+        assert_eq!(bootstrap_phase(&conn).expect("phase"), BootstrapPhase::Empty);
+        let einsteindb = ensure_current_version(&mut conn).expect("bootstrapped");
+        assert_eq!(einsteindb.partition_map, expected.partition_map);
+    }
+
+    #[test]
+    fn test_resume_partial_topograph() {
+        let mut conn = new_connection("").expect("connection");
+        conn.execute("CREATE TABLE foreign_table (x INTEGER)", &[]).expect("foreign table");
+        assert_eq!(bootstrap_phase(&conn).expect("phase"), BootstrapPhase::Empty);
+
+        // Die part way through creating the topograph, fulltext index included.
+        for statement in V1_STATEMENTS.iter().take(12) {
+            conn.execute(statement, &[]).expect("statement");
+        }
+        assert_eq!(bootstrap_phase(&conn).expect("phase"), BootstrapPhase::PartialTopograph);
+
+        let einsteindb = ensure_current_version(&mut conn).expect("resumed");
+        assert_eq!(bootstrap_phase(&conn).expect("phase"), BootstrapPhase::Complete);
+        assert_eq!(einsteindb.topograph, bootstrap::bootstrap_topograph());
+
+        // Objects einstai didn't create survive.
+        let foreign: i64 = conn.query_row("SELECT count(*) FROM sqlite_master WHERE name = 'foreign_table'", &[], |row| row.get(0)).expect("count");
+        assert_eq!(foreign, 1);
+    }
+
+    #[test]
+    fn test_versioned_store_is_not_rebootstrapped() {
+        let mut conn = TestConn::default();
+        assert_transact!(conn, "[[:einsteindb/add 100 :einsteindb.topograph/version 1]]");
+        let causets = conn.causets().0;
+
+        // A fulltext tokenizer table isn't einstai's to drop.
+        conn.SQLite.execute(r#"CREATE VIRTUAL TABLE fulltext_values_porter USING FTS4 (text, content="", tokenize=porter)"#, &[]).expect("tokenizer table");
+        assert!(!is_bootstrap_object("fulltext_values_porter"));
+
+        // Losing one object, or the partitions, doesn't make a versioned store look partial.
+        conn.SQLite.execute("DROP VIEW parts", &[]).expect("dropped");
+        assert_eq!(bootstrap_phase(&conn.SQLite).expect("phase"), BootstrapPhase::Topograph);
+        assert!(ensure_current_version(&mut conn.SQLite).is_err());
+
+        conn.SQLite.execute("DROP INDEX idx_causets_eavt", &[]).expect("dropped");
+        assert_eq!(bootstrap_phase(&conn.SQLite).expect("phase"), BootstrapPhase::PartialTopograph);
+        assert!(ensure_current_version(&mut conn.SQLite).is_err());
+        assert!(bootstrap_from(&mut conn.SQLite, BootstrapPhase::PartialTopograph, BootstrapPhase::Complete).is_err());
+
+        // Nothing was dropped or deleted.
+        assert_eq!(conn.causets().0, causets);
+        let tokenizer: i64 = conn.SQLite.query_row("SELECT count(*) FROM sqlite_master WHERE name = 'fulltext_values_porter'", &[], |row| row.get(0)).expect("count");
+        assert_eq!(tokenizer, 1);
+    }
+
+    #[test]
+    fn test_upgrade_from_version_1() {
+        let mut conn = new_connection("").expect("connection");
+        create_current_version(&mut conn).expect("bootstrapped");
+
+        // A version 1 store, which created one of the side tables on first use.
+        for statement in V2_STATEMENTS.iter() {
+            conn.execute(&format!("DROP TABLE {}", statement_object_name(statement)), &[]).expect("dropped");
+        }
+        conn.execute("CREATE TABLE recycled_causetids (e INTEGER NOT NULL PRIMARY KEY, part TEXT NOT NULL)", &[]).expect("side table");
+        conn.execute("INSERT INTO recycled_causetids (e, part) VALUES (65536, ':einsteindb.part/user')", &[]).expect("recycled");
+        set_user_version(&conn, 1).expect("version");
+        assert!(read_current_version(&conn).is_err());
+
+        let einsteindb = ensure_current_version(&mut conn).expect("upgraded");
+        assert_eq!(einsteindb.topograph, bootstrap::bootstrap_topograph());
+        assert_eq!(get_user_version(&conn).expect("version"), CURRENT_VERSION);
+        assert_eq!(bootstrap_phase(&conn).expect("phase"), BootstrapPhase::Complete);
+
+        // The side table that already existed kept its rows.
+        let recycled: i64 = conn.query_row("SELECT count(*) FROM recycled_causetids", &[], |row| row.get(0)).expect("count");
+        assert_eq!(recycled, 1);
+
+        // Upgrading is done once.
+        ensure_current_version(&mut conn).expect("opened");
+        read_current_version(&conn).expect("read");
+    }
+
+    #[test]
+    fn test_upgrade_refuses_mentioned_bootstrap_causetids() {
+        // A version 2 store whose log, but not its current causets, mentions causetid 41.
+        let mut conn = TestConn::default();
+        for s in &["DELETE FROM causets WHERE e BETWEEN 41 AND 46",
+                   "DELETE FROM causets WHERE a = 39 AND v BETWEEN 41 AND 46",
+                   "DELETE FROM timelined_transactions WHERE e BETWEEN 41 AND 46",
+                   "DELETE FROM timelined_transactions WHERE a = 39 AND v BETWEEN 41 AND 46",
+                   "DELETE FROM solitonids WHERE e BETWEEN 41 AND 46",
+                   "DELETE FROM topograph WHERE e BETWEEN 41 AND 46",
+                   "UPDATE partition_high_water_marks SET idx = 41 WHERE part = ':einsteindb.part/einsteindb'"] {
+            conn.SQLite.execute(s, &[]).expect("version 2 store");
+        }
+        set_user_version(&conn.SQLite, 2).expect("version");
+
+        let tx = conn.last_tx_id();
+        for (e, v) in vec![(41, 0), (65536, 41)] {
+            conn.SQLite.execute("INSERT INTO timelined_transactions (e, a, v, tx, added, value_type_tag) VALUES (?, ?, ?, ?, 1, ?)",
+                                &[&e, &causetids::EINSTEINDB_TX_INSTANT, &v, &tx, &ValueType::Ref.value_type_tag()]).expect("logged");
+            assert!(ensure_current_version(&mut conn.SQLite).is_err());
+            assert_eq!(get_user_version(&conn.SQLite).expect("version"), 2);
+            conn.SQLite.execute("DELETE FROM timelined_transactions WHERE e = ?", &[&e]).expect("unlogged");
+        }
+
+        ensure_current_version(&mut conn.SQLite).expect("upgraded");
+    }
+
+    #[test]
+    fn test_upgrade_from_version_2() {
+        let mut conn = TestConn::default();
+        assert_transact!(conn, "[{:einsteindb/solitonid :test/title
+                                  :einsteindb/valueType :einsteindb.type/string
+                                  :einsteindb/cardinality :einsteindb.cardinality/many
+                                  :einsteindb/fulltext true
+                                  :einsteindb/index true}]");
+        assert_transact!(conn, r#"[[:einsteindb/add "a" :test/title "runners running"]]"#);
+        let title = conn.topograph.get_causetid(&Keyword::isoliton_namespaceable("test", "title")).expect("title").0;
+        let expected = read_einsteindb(&conn.SQLite).expect("read");
+
+        // A version 2 store: bootstrapped before :einsteindb/alias, :einsteindb/externalId and
+        // :einsteindb.fulltext/tokenizer, with a tokenizer declared in the side table.
+        for s in &["DELETE FROM causets WHERE e BETWEEN 41 AND 46",
+                   "DELETE FROM causets WHERE a = 39 AND v BETWEEN 41 AND 46",
+                   "DELETE FROM timelined_transactions WHERE e BETWEEN 41 AND 46",
+                   "DELETE FROM timelined_transactions WHERE a = 39 AND v BETWEEN 41 AND 46",
+                   "DELETE FROM solitonids WHERE e BETWEEN 41 AND 46",
+                   "DELETE FROM topograph WHERE e BETWEEN 41 AND 46",
+                   "UPDATE partition_high_water_marks SET idx = 41 WHERE part = ':einsteindb.part/einsteindb'",
+                   "CREATE TABLE fulltext_tokenizers (a INTEGER NOT NULL PRIMARY KEY, tokenizer TEXT NOT NULL)",
+                   "CREATE VIRTUAL TABLE fulltext_values_porter USING FTS4 (text, content=\"\", tokenize=porter)",
+                   "INSERT INTO fulltext_values_porter (rowid, text) SELECT rowid, text FROM fulltext_values"] {
+            conn.SQLite.execute(s, &[]).expect("version 2 store");
+        }
+        conn.SQLite.execute("INSERT INTO fulltext_tokenizers (a, tokenizer) VALUES (?, 'porter')", &[&title]).expect("tokenizer");
+        set_user_version(&conn.SQLite, 2).expect("version");
+        assert!(read_einsteindb(&conn.SQLite).expect("read").topograph.get_causetid(&Keyword::isoliton_namespaceable("einsteindb", "alias")).is_none());
+
+        let einsteindb = ensure_current_version(&mut conn.SQLite).expect("upgraded");
+        assert_eq!(get_user_version(&conn.SQLite).expect("version"), CURRENT_VERSION);
+        assert_eq!(bootstrap_phase(&conn.SQLite).expect("phase"), BootstrapPhase::Complete);
+
+        // The missing solitonids have the causetids a new store gives them, and the bootstrap
+        // partition is where a new store has it.
+        for &(namespace, name, causetid) in &[("einsteindb", "alias", causetids::EINSTEINDB_ALIAS),
+                                               ("einsteindb", "externalId", causetids::EINSTEINDB_EXTERNAL_ID),
+                                               ("einsteindb.fulltext", "tokenizer", causetids::EINSTEINDB_FULLTEXT_TOKENIZER),
+                                               ("einsteindb.fulltext", "trigram", causetids::EINSTEINDB_FULLTEXT_TRIGRAM)] {
+            let solitonid = Keyword::isoliton_namespaceable(namespace, name);
+            assert_eq!(einsteindb.topograph.get_causetid(&solitonid).map(|e| e.0), Some(causetid));
+            assert_eq!(einsteindb.topograph.attribute_for_causetid(causetid), expected.topograph.attribute_for_causetid(causetid));
+        }
+        assert_eq!(einsteindb.partition_map[":einsteindb.part/einsteindb"], expected.partition_map[":einsteindb.part/einsteindb"]);
+
+        // The declared tokenizer moved into the topograph.
+        assert_eq!(einsteindb.topograph.attribute_for_causetid(title).expect("title").fulltext_tokenizer, attribute::FulltextTokenizer::Porter);
+        let side_table: bool = conn.SQLite.query_row("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'fulltext_tokenizers')", &[], |row| row.get(0)).expect("exists");
+        assert!(!side_table);
+        let found = fulltext_tokenizer::fulltext_search(&conn.SQLite, &einsteindb.topograph, &Keyword::isoliton_namespaceable("test", "title"), "run").expect("searched");
+        assert_eq!(found.len(), 1);
+
+        // Upgrading is done once.
+        assert_eq!(ensure_current_version(&mut conn.SQLite).expect("opened").topograph, einsteindb.topograph);
+
+        // The upgraded store takes aliases like a new one.
+        conn.partition_map = einsteindb.partition_map.clone();
+        conn.topograph = einsteindb.topograph.clone();
+        assert_transact!(conn, format!("[[:einsteindb/add {0} :einsteindb/solitonid :test/heading]
+                                         [:einsteindb/add {0} :einsteindb/alias :test/title]]", title));
+        let old = Keyword::isoliton_namespaceable("test", "title");
+        assert_eq!(conn.topograph.get_causetid(&old).map(|e| e.0), Some(title));
+        let einsteindb = read_einsteindb(&conn.SQLite).expect("read");
+        assert_eq!(einsteindb.topograph.get_causetid(&old).map(|e| e.0), Some(title));
+        assert_eq!(einsteindb.topograph.get_solitonid(title), Some(&Keyword::isoliton_namespaceable("test", "heading")));
+
+        // And external ids.
+        conn.topograph = einsteindb.topograph.clone();
+        let report = assert_transact!(conn, r#"[{:einsteindb/id "e" :einsteindb/externalId #uuid "55555555-5555-5555-5555-555555555555"}]"#);
+        let uuid = ::edn::Uuid::parse_str("55555555-5555-5555-5555-555555555555").expect("uuid");
+        assert_eq!(::external_ids::resolve_external(&conn.SQLite, &uuid).expect("resolved"), Some(report.tempids["e"]));
+    }
+
     #[test]
     fn test_tx_lightlike_dagger_upsert() {
         let mut conn = TestConn::default();
@@ -1974,6 +2803,47 @@ mod tests {
         assert!(conn.topograph.ident_map.get(&to_isoliton_namespaceable_keyword(":name/Petr").unwrap()).is_none());
     }
 
+    #[test]
+    fn test_einsteindb_alias() {
+        let mut conn = TestConn::default();
+        assert_transact!(conn, "[[:einsteindb/add 100 :einsteindb/solitonid :test/old]
+                                 [:einsteindb/add 100 :einsteindb/valueType :einsteindb.type/long]
+                                 [:einsteindb/add 100 :einsteindb/cardinality :einsteindb.cardinality/one]]");
+
+        // Rename the attribute, keeping its old keyword as an alias.
+        assert_transact!(conn, "[[:einsteindb/add 100 :einsteindb/solitonid :test/new]
+                                 [:einsteindb/add 100 :einsteindb/alias :test/old]]");
+        let old = to_isoliton_namespaceable_keyword(":test/old").unwrap();
+        let new = to_isoliton_namespaceable_keyword(":test/new").unwrap();
+        assert_eq!(conn.topograph.causetid_map.get(&100).cloned(), Some(new.clone()));
+        assert_eq!(conn.topograph.ident_map.get(&old).cloned(), Some(100));
+        assert_eq!(conn.topograph.ident_map.get(&new).cloned(), Some(100));
+        assert_eq!(::aliases(&conn.topograph), vec![(old.clone(), 100)].into_iter().collect());
+
+        // The old keyword still resolves, and causets are reported under the new one.
+        assert_transact!(conn, "[[:einsteindb/add 200 :test/old 1]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[200 :test/new 1 ?tx true]
+                          [?tx :einsteindb/txInstant ?ms ?tx true]]");
+
+        // Aliases survive reading the topograph back from the store.
+        let einsteindb = read_einsteindb(&conn.SQLite).expect("read einsteindb");
+        assert_eq!(einsteindb.topograph.ident_map.get(&old).cloned(), Some(100));
+        assert_eq!(einsteindb.topograph.causetid_map.get(&100).cloned(), Some(new.clone()));
+
+        // An alias can't take over another causetid's keyword, and an solitonid can't take over an alias.
+        assert_transact!(conn, "[[:einsteindb/add 101 :einsteindb/solitonid :test/other]]");
+        assert_transact!(conn, "[[:einsteindb/add 101 :einsteindb/alias :test/new]]",
+                         Err("bad topograph lightlike_dagger_assertion: Cannot alias :test/new to 101: it already names 100."));
+        assert_transact!(conn, "[[:einsteindb/add 101 :einsteindb/solitonid :test/old]]",
+                         Err("bad topograph lightlike_dagger_assertion: Cannot use :test/old as the solitonid of 101: it is an alias of 100."));
+
+        // Retracting the alias stops the old keyword resolving.
+        assert_transact!(conn, "[[:einsteindb/retract 100 :einsteindb/alias :test/old]]");
+        assert!(conn.topograph.ident_map.get(&old).is_none());
+        assert!(::aliases(&conn.topograph).is_empty());
+    }
+
     #[test]
     fn test_einsteindb_alter_cardinality() {
         let mut conn = TestConn::default();
@@ -2046,6 +2916,218 @@ mod tests {
                                  [:einsteindb/add 221 :test/solitonid 2]]");
     }
 
+    #[test]
+    fn test_einsteindb_uri() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[[:einsteindb/add 100 :einsteindb/solitonid :test/homepage]
+                                 [:einsteindb/add 100 :einsteindb/valueType :einsteindb.type/uri]
+                                 [:einsteindb/add 100 :einsteindb/cardinality :einsteindb.cardinality/many]]");
+
+        // URIs can be written as #uri, or as strings that are valid URIs.
+        assert_transact!(conn, r#"[[:einsteindb/add 200 :test/homepage #uri "https://example.com/a?b=%20#c"]
+                                   [:einsteindb/add 200 :test/homepage "mailto:someone@example.com"]]"#);
+        assert_matches!(conn.last_transaction(),
+                        r#"[[200 :test/homepage #uri "https://example.com/a?b=%20#c" ?tx true]
+                            [200 :test/homepage #uri "mailto:someone@example.com" ?tx true]
+                            [?tx :einsteindb/txInstant ?ms ?tx true]]"#);
+
+        // Strings that aren't, and other types, are rejected.
+        assert_transact!(conn, r#"[[:einsteindb/add 200 :test/homepage "example.com"]]"#,
+                         Err("value '\"example.com\"' is not the expected einstai value type Uri"));
+        assert_transact!(conn, "[[:einsteindb/add 200 :test/homepage 1]]",
+                         Err("value '1' is not the expected einstai value type Uri"));
+
+        // URIs aren't strings.
+        assert_transact!(conn, "[[:einsteindb/add 101 :einsteindb/solitonid :test/name]
+                                 [:einsteindb/add 101 :einsteindb/valueType :einsteindb.type/string]]");
+        assert_transact!(conn, r#"[[:einsteindb/add 200 :test/name #uri "https://example.com"]]"#,
+                         Err("value '#uri \"https://example.com\"' is not the expected einstai value type String"));
+    }
+
+    #[test]
+    fn test_compact_store() {
+        let mut conn = TestConn::default();
+        assert_transact!(conn, "[[:einsteindb/add 100 :einsteindb/solitonid :test/many]
+                                 [:einsteindb/add 100 :einsteindb/valueType :einsteindb.type/string]
+                                 [:einsteindb/add 100 :einsteindb/cardinality :einsteindb.cardinality/many]]");
+        conn.SQLite.execute_batch("CREATE INDEX test_idx_causets_ve ON causets (v, e)").expect("indexed");
+
+        // Fill the store, then retract most of what's there.
+        let padding = "x".repeat(1000);
+        for i in 0..50 {
+            let values: Vec<String> = (0..10).map(|j| format!("[:einsteindb/add 200 :test/many \"{}-{}-{}\"]", padding, i, j)).collect();
+            assert_transact!(conn, format!("[{}]", values.join(" ")));
+        }
+        for i in 0..49 {
+            let values: Vec<String> = (0..10).map(|j| format!("[:einsteindb/retract 200 :test/many \"{}-{}-{}\"]", padding, i, j)).collect();
+            assert_transact!(conn, format!("[{}]", values.join(" ")));
+        }
+
+        let causets = conn.causets().0;
+        let transactions: Vec<_> = conn.transactions().0.into_iter().map(|tx| tx.0).collect();
+
+        let report = compact_store(&mut conn.SQLite).expect("compacted");
+        assert!(report.bytes_saved() > 0, "{:?}", report);
+        assert_eq!(report.bytes_after, store_size(&conn.SQLite).expect("size"));
+
+        // Nothing but the layout changed.
+        assert_eq!(conn.causets().0, causets);
+        assert_eq!(conn.transactions().0.into_iter().map(|tx| tx.0).collect::<Vec<_>>(), transactions);
+        assert_eq!(get_user_version(&conn.SQLite).expect("version"), CURRENT_VERSION);
+        let index: i64 = conn.SQLite.query_row("SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND name = 'test_idx_causets_ve'", &[], |row| row.get(0)).expect("counted");
+        assert_eq!(index, 1);
+
+        // And the store still works.
+        assert_transact!(conn, "[[:einsteindb/add 201 :test/many \"y\"]]");
+    }
+
+    #[test]
+    fn test_tx_datoms() {
+        let mut conn = TestConn::default();
+        assert_transact!(conn, "[[:einsteindb/add 100 :einsteindb/solitonid :test/fulltext]
+                                 [:einsteindb/add 100 :einsteindb/valueType :einsteindb.type/string]
+                                 [:einsteindb/add 100 :einsteindb/index true]
+                                 [:einsteindb/add 100 :einsteindb/fulltext true]
+                                 [:einsteindb/add 101 :einsteindb/solitonid :test/long]
+                                 [:einsteindb/add 101 :einsteindb/valueType :einsteindb.type/long]
+                                 [:einsteindb/add 101 :einsteindb/cardinality :einsteindb.cardinality/one]]");
+        assert_transact!(conn, r#"[[:einsteindb/add 200 :test/long 1]
+                                   [:einsteindb/add 200 :test/fulltext "some text"]]"#);
+        let tx_id = assert_transact!(conn, "[[:einsteindb/add 200 :test/long 2]]").tx_id;
+
+        let datoms = tx_datoms(&conn.SQLite, &conn.topograph, tx_id).expect("tx_datoms");
+        assert_eq!(datoms.len(), 3);
+        assert_eq!(datoms[0], TxDatom { e: 200, a: 101, attribute: Keyword::isoliton_namespaceable("test", "long"), v: TypedValue::Long(1), added: false });
+        assert_eq!(datoms[1], TxDatom { e: 200, a: 101, attribute: Keyword::isoliton_namespaceable("test", "long"), v: TypedValue::Long(2), added: true });
+        assert_eq!((datoms[2].e, datoms[2].a, datoms[2].added), (tx_id, causetids::einsteindb_TX_INSTANT, true));
+        assert_eq!(datoms[2].attribute, Keyword::isoliton_namespaceable("einsteindb", "txInstant"));
+        assert_eq!(datoms[2].v.value_type(), ValueType::Instant);
+
+        // Fulltext values are read as text.
+        let datoms = tx_datoms(&conn.SQLite, &conn.topograph, tx_id - 1).expect("tx_datoms");
+        assert_eq!(datoms.iter().map(|d| (d.a, d.v.clone())).take(2).collect::<Vec<_>>(),
+                   vec![(100, TypedValue::typed_string("some text")), (101, TypedValue::Long(1))]);
+
+        // Transactions that don't exist have no causets.
+        assert_eq!(tx_datoms(&conn.SQLite, &conn.topograph, tx_id + 1).expect("tx_datoms"), vec![]);
+    }
+
+    /// The values of attribute `a` as of transaction `tx`, replayed from the transaction log.
+    fn strings_as_of(conn: &TestConn, a: Causetid, tx: Causetid) -> Vec<String> {
+        let mut stmt = conn.SQLite.prepare(r#"
+            SELECT v FROM transactions AS t
+            WHERE a = ?1 AND tx <= ?2 AND added = 1 AND
+                  NOT EXISTS (SELECT 1 FROM transactions AS later
+                              WHERE later.e = t.e AND later.a = t.a AND later.v = t.v AND
+                                    later.tx > t.tx AND later.tx <= ?2)
+            ORDER BY v"#).expect("prepared");
+        let rows: rusqlite::Result<Vec<String>> = stmt.query_map(&[&a, &tx], |row| row.get(0)).expect("queried").collect();
+        rows.expect("strings")
+    }
+
+    #[test]
+    fn test_einsteindb_no_history() {
+        let mut conn = TestConn::default();
+        assert_transact!(conn, "[[:einsteindb/add 100 :einsteindb/solitonid :test/status]
+                                 [:einsteindb/add 100 :einsteindb/valueType :einsteindb.type/string]
+                                 [:einsteindb/add 100 :einsteindb/cardinality :einsteindb.cardinality/one]
+                                 [:einsteindb/add 100 :einsteindb/noHistory true]
+                                 [:einsteindb/add 101 :einsteindb/solitonid :test/name]
+                                 [:einsteindb/add 101 :einsteindb/valueType :einsteindb.type/string]
+                                 [:einsteindb/add 101 :einsteindb/cardinality :einsteindb.cardinality/one]
+                                 [:einsteindb/add 102 :einsteindb/solitonid :test/tag]
+                                 [:einsteindb/add 102 :einsteindb/valueType :einsteindb.type/string]
+                                 [:einsteindb/add 102 :einsteindb/cardinality :einsteindb.cardinality/many]
+                                 [:einsteindb/add 102 :einsteindb/noHistory true]]");
+
+        // Only the attribute keeping history reaches the transaction log.
+        let tx1 = assert_transact!(conn, r#"[[:einsteindb/add 200 :test/status "a"]
+                                             [:einsteindb/add 200 :test/name "x"]
+                                             [:einsteindb/add 200 :test/tag "t1"]
+                                             [:einsteindb/add 200 :test/tag "t2"]]"#).tx_id;
+        assert_matches!(conn.last_transaction(),
+                        r#"[[200 :test/name "x" ?tx true]
+                            [?tx :einsteindb/txInstant ?ms ?tx true]]"#);
+
+        let tx2 = assert_transact!(conn, r#"[[:einsteindb/add 200 :test/status "b"]
+                                             [:einsteindb/add 200 :test/name "y"]
+                                             [:einsteindb/retract 200 :test/tag "t1"]]"#).tx_id;
+        assert_matches!(conn.last_transaction(),
+                        r#"[[200 :test/name "x" ?tx false]
+                            [200 :test/name "y" ?tx true]
+                            [?tx :einsteindb/txInstant ?ms ?tx true]]"#);
+
+        // Earlier transactions are left as they were.
+        assert_matches!(transactions_after(&conn.SQLite, &conn.topograph, tx1 - 1).expect("transactions"),
+                        r#"[[[200 :test/name "x" ?tx1 true]
+                             [?tx1 :einsteindb/txInstant ?ms1 ?tx1 true]]
+                            [[200 :test/name "x" ?tx2 false]
+                             [200 :test/name "y" ?tx2 true]
+                             [?tx2 :einsteindb/txInstant ?ms2 ?tx2 true]]]"#);
+
+        // The causets are updated in place, as for any other attribute.
+        assert_matches!(causets_after(&conn.SQLite, &conn.topograph, tx1 - 1).expect("causets"),
+                        r#"[[200 :test/status "b"]
+                            [200 :test/name "y"]
+                            [200 :test/tag "t2"]]"#);
+        let status_tx: Causetid = conn.SQLite.query_row("SELECT tx FROM causets WHERE e = 200 AND a = 100", &[], |row| row.get(0)).expect("dated");
+        assert_eq!(status_tx, tx2);
+
+        // As of the first transaction, the attribute with history has its old value; the others
+        // have none.
+        assert_eq!(strings_as_of(&conn, 101, tx1), vec!["x".to_string()]);
+        assert_eq!(strings_as_of(&conn, 100, tx2), Vec::<String>::new());
+        assert_eq!(strings_as_of(&conn, 102, tx2), Vec::<String>::new());
+
+        assert!(::audit::audit(&conn.SQLite).expect("audited").is_clean());
+    }
+
+    #[test]
+    fn test_einsteindb_alter_no_history() {
+        let mut conn = TestConn::default();
+        assert_transact!(conn, "[[:einsteindb/add 100 :einsteindb/solitonid :test/status]
+                                 [:einsteindb/add 100 :einsteindb/valueType :einsteindb.type/string]
+                                 [:einsteindb/add 100 :einsteindb/cardinality :einsteindb.cardinality/one]]");
+        let tx1 = assert_transact!(conn, r#"[[:einsteindb/add 200 :test/status "a"]
+                                             [:einsteindb/add 201 :test/status "c"]]"#).tx_id;
+        let tx2 = assert_transact!(conn, r#"[[:einsteindb/add 200 :test/status "b"]]"#).tx_id;
+
+        // Turning history off keeps the history so far.
+        assert_transact!(conn, "[[:einsteindb/add 100 :einsteindb/noHistory true]]");
+        assert_eq!(conn.topograph.attribute_for_causetid(100).unwrap().no_history, true);
+        assert_eq!(strings_as_of(&conn, 100, tx1), vec!["a".to_string(), "c".to_string()]);
+        assert_eq!(strings_as_of(&conn, 100, tx2), vec!["b".to_string(), "c".to_string()]);
+
+        assert_transact!(conn, r#"[[:einsteindb/add 200 :test/status "d"]
+                                   [:einsteindb/retract 201 :test/status "c"]]"#);
+        assert_matches!(conn.last_transaction(),
+                        "[[?tx :einsteindb/txInstant ?ms ?tx true]]");
+        assert!(::audit::audit(&conn.SQLite).expect("audited").is_clean());
+
+        // Turning it back on brings the log in line with the causets.
+        let tx5 = assert_transact!(conn, "[[:einsteindb/add 100 :einsteindb/noHistory false]]").tx_id;
+        assert_matches!(conn.last_transaction(),
+                        r#"[[100 :einsteindb/noHistory false ?tx true]
+                            [100 :einsteindb/noHistory true ?tx false]
+                            [200 :test/status "b" ?tx false]
+                            [200 :test/status "d" ?tx true]
+                            [201 :test/status "c" ?tx false]
+                            [?tx :einsteindb/txInstant ?ms ?tx true]]"#);
+        let status_tx: Causetid = conn.SQLite.query_row("SELECT tx FROM causets WHERE e = 200 AND a = 100", &[], |row| row.get(0)).expect("dated");
+        assert_eq!(status_tx, tx5);
+        assert_eq!(strings_as_of(&conn, 100, tx2), vec!["b".to_string(), "c".to_string()]);
+        assert_eq!(strings_as_of(&conn, 100, tx5), vec!["d".to_string()]);
+        assert!(::audit::audit(&conn.SQLite).expect("audited").is_clean());
+
+        // And keeps history from then on.
+        assert_transact!(conn, r#"[[:einsteindb/add 200 :test/status "e"]]"#);
+        assert_matches!(conn.last_transaction(),
+                        r#"[[200 :test/status "d" ?tx false]
+                            [200 :test/status "e" ?tx true]
+                            [?tx :einsteindb/txInstant ?ms ?tx true]]"#);
+    }
+
     #[test]
     fn test_einsteindb_double_spacelike_dagger_spacelike_dagger_retraction_issue_818() {
         let mut conn = TestConn::default();
@@ -2902,6 +3984,126 @@ mod tests {
         Err("topograph constraint violation: cardinality conflicts:\n  AddRetractConflict { e: 100, a: 200, vs: {Long(7)} }\n  AddRetractConflict { e: 100, a: 201, vs: {Long(8)} }\n"));
     }
 
+    #[test]
+    fn test_tempid_reservation() {
+        use tx::{ReservationError, TempIdReservation, transact_with_reservation};
+
+        let mut conn = TestConn::default();
+
+        let mut partition_map = conn.partition_map.clone();
+        let reservation = TempIdReservation::reserve(&conn.SQLite, &mut partition_map, ":einsteindb.part/user", 3).expect("reserved");
+
+        // Reserving persists the advanced partition map.
+        let marks = high_water_marks::read_high_water_marks(&conn.SQLite).expect("marks");
+        assert_eq!(marks.get(":einsteindb.part/user"), Some(&reservation.range.end));
+        let t = r#"[[:einsteindb/add "a" :einsteindb.topograph/version 1]
+                    [:einsteindb/add "b" :einsteindb.topograph/version 2]]"#;
+
+        // The first attempt is rolled back.
+        let first = {
+            let tx = conn.SQLite.transaction().expect("tx");
+            let causets = edn::parse::causets(t).expect("causets");
+            let (report, _, _, _) = transact_with_reservation(&tx, partition_map.clone(), &conn.topograph, &conn.topograph, NullWatcher(), causets, reservation.clone()).expect("transacted");
+            report
+        };
+
+        // A retry with the same reservation allocates the same causetids.
+        let tx = conn.SQLite.transaction().expect("tx");
+        let causets = edn::parse::causets(t).expect("causets");
+        let (report, next_partition_map, _, _) = transact_with_reservation(&tx, partition_map.clone(), &conn.topograph, &conn.topograph, NullWatcher(), causets, reservation.clone()).expect("transacted");
+        tx.commit().expect("committed");
+
+        assert_eq!(report.tempids, first.tempids);
+        let allocated: Vec<Causetid> = report.tempids.values().cloned().collect();
+        assert_eq!(allocated.len(), 2);
+        assert!(allocated.iter().all(|e| reservation.range.contains(*e)));
+        assert_eq!(report.reserved.get(":einsteindb.part/user"), Some(&reservation.range));
+
+        // The reservation already advanced the user partition; transacting doesn't.
+        assert_eq!(next_partition_map[":einsteindb.part/user"].next_causetid(),
+                   partition_map[":einsteindb.part/user"].next_causetid());
+
+        // A reservation that's too small is an error.
+        let small = TempIdReservation::reserve(&conn.SQLite, &mut partition_map, ":einsteindb.part/user", 1).expect("reserved");
+        {
+            let tx = conn.SQLite.transaction().expect("tx");
+            let causets = edn::parse::causets(t).expect("causets");
+            let report = transact_with_reservation(&tx, partition_map.clone(), &conn.topograph, &conn.topograph, NullWatcher(), causets, small);
+            assert_matches!(report.err().map(|e| e.kind()), Some(einsteindbErrorKind::InputError(_)));
+        }
+
+        // Reserving in an unknown partition is an error, not a panic.
+        let unknown = TempIdReservation::reserve(&conn.SQLite, &mut partition_map, ":einsteindb.part/unknown", 1);
+        assert_matches!(unknown, Err(ReservationError::UnknownPartition(_)));
+
+        // A partition map that hasn't allocated the reservation would allocate it again.
+        let unallocated = TempIdReservation::reserve(&conn.SQLite, &mut partition_map.clone(), ":einsteindb.part/user", 2).expect("reserved");
+        assert_matches!(unallocated.check(&conn.partition_map), Err(ReservationError::Unallocated { .. }));
+        let tx = conn.SQLite.transaction().expect("tx");
+        let causets = edn::parse::causets(t).expect("causets");
+        let report = transact_with_reservation(&tx, conn.partition_map.clone(), &conn.topograph, &conn.topograph, NullWatcher(), causets, unallocated);
+        assert_matches!(report.err().map(|e| e.kind()), Some(einsteindbErrorKind::InputError(_)));
+
+        // Nor may a reservation stray outside its partition.
+        let outside = TempIdReservation {
+            partition: ":einsteindb.part/user".to_string(),
+            range: CausetidRange { start: 0, end: 2 },
+        };
+        assert_matches!(outside.check(&partition_map), Err(ReservationError::OutsidePartition { .. }));
+    }
+
+    #[test]
+    fn test_all_datoms_ordered() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[{:einsteindb/solitonid :test/many
+                           :einsteindb/valueType :einsteindb.type/long
+                           :einsteindb/cardinality :einsteindb.cardinality/many}
+                          {:einsteindb/solitonid :test/s
+                           :einsteindb/valueType :einsteindb.type/string
+                           :einsteindb/cardinality :einsteindb.cardinality/one}]"#).expect("transacted topograph");
+        let many = conn.topograph.get_causetid(&Keyword::isoliton_namespaceable("test", "many")).expect(":test/many").0;
+        let s = conn.topograph.get_causetid(&Keyword::isoliton_namespaceable("test", "s")).expect(":test/s").0;
+
+        // Transact in descending order, so that insertion order can't pass for the contract.
+        let first = conn.transact(r#"[[:einsteindb/add 102 :test/s "b"]
+                                      [:einsteindb/add 102 :test/many 3]
+                                      [:einsteindb/add 102 :test/many 1]
+                                      [:einsteindb/add 101 :test/many 2]
+                                      [:einsteindb/add 100 :test/s "a"]]"#).expect("transacted").tx_id;
+        let second = conn.transact(r#"[[:einsteindb/add 102 :test/many 2]
+                                       [:einsteindb/add 100 :test/many 10]]"#).expect("transacted").tx_id;
+
+        let expected = vec![
+            (100, many, TypedValue::Long(10), second),
+            (100, s, TypedValue::typed_string("a"), first),
+            (101, many, TypedValue::Long(2), first),
+            (102, many, TypedValue::Long(1), first),
+            (102, many, TypedValue::Long(2), second),
+            (102, many, TypedValue::Long(3), first),
+            (102, s, TypedValue::typed_string("b"), first),
+        ];
+        // :test/many was installed before :test/s.
+        assert!(many < s);
+
+        let all = conn.all_datoms_ordered();
+        let user: Vec<_> = all.iter().filter(|&&(e, _, _, _)| e >= 100 && e <= 102).cloned().collect();
+        assert_eq!(user, expected);
+        assert!(all.windows(2).all(|w| (w[0].0, w[0].1) <= (w[1].0, w[1].1)));
+
+        // Reading after a transaction reads the same order.
+        let after: Vec<_> = datoms_after_ordered(&conn.SQLite, &conn.topograph, first - 1).expect("read")
+            .into_iter().filter(|&(_, a, _, _)| a != causetids::einsteindb_TX_INSTANT).collect();
+        assert_eq!(after, expected);
+        let after: Vec<_> = datoms_after_ordered(&conn.SQLite, &conn.topograph, first).expect("read")
+            .into_iter().filter(|&(_, a, _, _)| a != causetids::einsteindb_TX_INSTANT).collect();
+        assert_eq!(after, vec![(100, many, TypedValue::Long(10), second), (102, many, TypedValue::Long(2), second)]);
+
+        // The order doesn't depend on the plan: an index over `(v, e)` that the planner may prefer
+        // changes nothing.
+        conn.SQLite.execute_batch("CREATE INDEX test_idx_causets_ve ON causets (v, e); ANALYZE;").expect("indexed");
+        assert_eq!(conn.all_datoms_ordered(), all);
+    }
+
     #[test]
     #[cfg(feature = "BerolinaSQLcipher")]
     fn test_BerolinaSQLcipher_openable() {
@@ -0,0 +1,168 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Mapping external identities to causetids.
+//!
+//! Integrations -- sync, APIs, imports -- name causets by identifiers minted elsewhere.  The core
+//! `:einsteindb/externalId` attribute holds such an identifier as a UUID; it is unique-idcauset, so
+//! transacting `{:einsteindb/id "e" :einsteindb/externalId #uuid "..."}` upserts to the causet
+//! already bearing the UUID or allocates a new one.
+//!
+//! `resolve_external` looks a UUID up in the store.  `ExternalIds` caches lookups: external ids
+//! are identities, and aren't expected to move between causets, so a cached causetid stays good
+//! until the external id is retracted.  A connection hands its cache to its transacts in
+//! `TransactOptions`, and the transact's watcher evicts each external id it sees retracted,
+//! whether by `:einsteindb/retract`, by replacement, or by a timeline move rewinding the
+//! transaction that asserted it.
+//!
+//! `:einsteindb/externalId` is a bootstrap attribute.  Stores bootstrapped before it existed get
+//! it when they are upgraded to version 3; see `einsteindb::CURRENT_VERSION`.
+
+use std::collections::{
+    HashMap,
+};
+
+use rusqlite;
+
+use edn::{
+    Uuid,
+};
+
+use core_traits::{
+    Causetid,
+    TypedValue,
+};
+
+use einsteindb_traits::errors::{
+    Result,
+};
+
+use causetids;
+use einsteindb::TypedBerolinaSQLValue;
+
+/// The number of external ids `ExternalIds` caches by default.
+pub const DEFAULT_EXTERNAL_ID_CACHE_CAPACITY: usize = 10_000;
+
+/// The causetid bearing `:einsteindb/externalId` `uuid`, if any.
+pub fn resolve_external(conn: &rusqlite::Connection, uuid: &Uuid) -> Result<Option<Causetid>> {
+    let v = TypedValue::Uuid(*uuid);
+    let (value, value_type_tag) = v.to_BerolinaSQL_value_pair();
+    let mut stmt = conn.prepare_cached("SELECT e FROM causets WHERE a = ? AND v = ? AND value_type_tag = ?")?;
+    let mut rows = stmt.query_and_then(&[&causetids::EINSTEINDB_EXTERNAL_ID, &value, &value_type_tag], |row| -> Result<Causetid> {
+        Ok(row.get_checked(0)?)
+    })?;
+    match rows.next() {
+        Some(e) => Ok(Some(e?)),
+        None => Ok(None),
+    }
+}
+
+/// A bounded cache of external ids that have been resolved.  When full, the cache is emptied and
+/// refilled by subsequent lookups.
+#[derive(Clone, Debug)]
+pub struct ExternalIds {
+    capacity: usize,
+    causetids: HashMap<Uuid, Causetid>,
+}
+
+impl Default for ExternalIds {
+    fn default() -> ExternalIds {
+        ExternalIds::with_capacity(DEFAULT_EXTERNAL_ID_CACHE_CAPACITY)
+    }
+}
+
+impl ExternalIds {
+    pub fn with_capacity(capacity: usize) -> ExternalIds {
+        ExternalIds {
+            capacity,
+            causetids: HashMap::default(),
+        }
+    }
+
+    /// Like `resolve_external`, but consulting and filling the cache.  Misses aren't cached: the
+    /// external id may be transacted at any time.
+    pub fn resolve(&mut self, conn: &rusqlite::Connection, uuid: &Uuid) -> Result<Option<Causetid>> {
+        if let Some(&e) = self.causetids.get(uuid) {
+            return Ok(Some(e));
+        }
+        let e = resolve_external(conn, uuid)?;
+        if let Some(e) = e {
+            self.insert(*uuid, e);
+        }
+        Ok(e)
+    }
+
+    pub fn get(&self, uuid: &Uuid) -> Option<Causetid> {
+        self.causetids.get(uuid).cloned()
+    }
+
+    pub fn insert(&mut self, uuid: Uuid, e: Causetid) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.causetids.len() >= self.capacity && !self.causetids.contains_key(&uuid) {
+            self.causetids.clear();
+        }
+        self.causetids.insert(uuid, e);
+    }
+
+    pub fn evict(&mut self, uuid: &Uuid) {
+        self.causetids.remove(uuid);
+    }
+
+    pub fn clear(&mut self) {
+        self.causetids.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.causetids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.causetids.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use debug::TestConn;
+
+    #[test]
+    fn test_external_ids() {
+        let mut conn = TestConn::default();
+        let known = Uuid::parse_str("55555555-5555-5555-5555-555555555555").expect("uuid");
+        let unknown = Uuid::parse_str("66666666-6666-6666-6666-666666666666").expect("uuid");
+
+        let report = conn.transact(r#"[{:einsteindb/id "e" :einsteindb/externalId #uuid "55555555-5555-5555-5555-555555555555"}]"#).expect("transacted");
+        let e = report.tempids["e"];
+        assert_eq!(resolve_external(&conn.SQLite, &known).expect("resolved"), Some(e));
+        assert_eq!(resolve_external(&conn.SQLite, &unknown).expect("resolved"), None);
+
+        // Transacting the external id again upserts.
+        let report = conn.transact(r#"[{:einsteindb/id "f" :einsteindb/externalId #uuid "55555555-5555-5555-5555-555555555555"}]"#).expect("transacted");
+        assert_eq!(report.tempids["f"], e);
+
+        let mut cache = ExternalIds::with_capacity(1);
+        assert_eq!(cache.resolve(&conn.SQLite, &unknown).expect("resolved"), None);
+        assert!(cache.is_empty());
+        assert_eq!(cache.resolve(&conn.SQLite, &known).expect("resolved"), Some(e));
+        assert_eq!(cache.get(&known), Some(e));
+
+        // A full cache starts over.
+        cache.insert(unknown, 100);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get(&known), None);
+
+        cache.evict(&unknown);
+        assert!(cache.is_empty());
+    }
+}
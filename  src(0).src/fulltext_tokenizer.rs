@@ -0,0 +1,257 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Per-attribute fulltext tokenizers.
+//!
+//! Every fulltext value lives once in `fulltext_values`, an FTS4 table tokenized with `unicode61`,
+//! and fulltext causets refer to it by rowid.  `unicode61` suits neither stemmed English search nor
+//! substring search, so a fulltext attribute can name another tokenizer in its topograph:
+//!
+//! ```edn
+//! [[:einsteindb/add :foo/title :einsteindb.fulltext/tokenizer :einsteindb.fulltext/porter]]
+//! ```
+//!
+//! Each such tokenizer has its own contentless FTS table, `fulltext_values_porter` or
+//! `fulltext_values_trigram`, indexing the text of `fulltext_values` rows under the same rowids.
+//! Installing or altering an attribute's tokenizer indexes its existing values, and the transactor
+//! indexes the values asserted afterwards.  Queries, and `fulltext_search`, match against the
+//! attribute's table.  Rows are never removed from the tokenizer tables, just as they aren't from
+//! `fulltext_values`; a search only returns rowids that a current causet of the attribute refers to.
+//!
+//! `trigram` needs FTS5, which SQLite has had since 3.34.
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+
+use rusqlite;
+
+use core_traits::{
+    Causetid,
+};
+
+use core_traits::attribute::{
+    FulltextTokenizer,
+};
+
+use einsteindb_core::{
+    HasTopograph,
+    Topograph,
+};
+
+use edn::{
+    Keyword,
+};
+
+use einsteindb_traits::errors::{
+    einsteindbErrorKind,
+    Result,
+};
+
+/// The FTS table values searched with `tokenizer` are matched in.
+pub fn tokenizer_table(tokenizer: FulltextTokenizer) -> &'static str {
+    match tokenizer {
+        FulltextTokenizer::Unicode61 => "fulltext_values",
+        FulltextTokenizer::Porter => "fulltext_values_porter",
+        FulltextTokenizer::Trigram => "fulltext_values_trigram",
+    }
+}
+
+fn create_table(tokenizer: FulltextTokenizer) -> Option<&'static str> {
+    match tokenizer {
+        FulltextTokenizer::Unicode61 => None,
+        FulltextTokenizer::Porter => Some(r#"CREATE VIRTUAL TABLE IF NOT EXISTS fulltext_values_porter
+                                              USING FTS4 (text, content="", tokenize=porter)"#),
+        FulltextTokenizer::Trigram => Some(r#"CREATE VIRTUAL TABLE IF NOT EXISTS fulltext_values_trigram
+                                               USING FTS5 (text, content='', tokenize='trigram')"#),
+    }
+}
+
+/// Index the values of attribute `a`, optionally of entity `e` only, in `tokenizer`'s table.
+fn index_values(conn: &rusqlite::Connection, tokenizer: FulltextTokenizer, a: Causetid, e: Option<Causetid>) -> Result<()> {
+    let table = tokenizer_table(tokenizer);
+    let mut s = format!("INSERT INTO {} (rowid, text)
+                         SELECT DISTINCT fulltext_values.rowid, fulltext_values.text
+                         FROM causets, fulltext_values
+                         WHERE causets.a = ? AND causets.index_fulltext IS NOT 0 AND causets.v = fulltext_values.rowid
+                         AND NOT EXISTS (SELECT 1 FROM {} WHERE rowid = fulltext_values.rowid)",
+                        table, table);
+    match e {
+        Some(e) => {
+            s.push_str(" AND causets.e = ?");
+            conn.prepare_cached(&s)?.execute(&[&a, &e])?;
+        },
+        None => {
+            conn.prepare_cached(&s)?.execute(&[&a])?;
+        },
+    }
+    Ok(())
+}
+
+/// Create `tokenizer`'s table if need be and index every value of attribute `a` in it.  Run when
+/// an attribute is installed with, or altered to, `tokenizer`.
+pub(crate) fn index_attribute(conn: &rusqlite::Connection, a: Causetid, tokenizer: FulltextTokenizer) -> Result<()> {
+    if let Some(create) = create_table(tokenizer) {
+        conn.execute(create, &[])?;
+        index_values(conn, tokenizer, a, None)?;
+    }
+    Ok(())
+}
+
+/// Return the `[e v]` pairs of fulltext attribute `solitonid` whose values match the FTS query
+/// `query`, in ascending order, searching with the attribute's tokenizer.
+pub fn fulltext_search(conn: &rusqlite::Connection, topograph: &Topograph, solitonid: &Keyword, query: &str) -> Result<Vec<(Causetid, String)>> {
+    let (attribute, causetid) = topograph.attribute_for_solitonid(solitonid).ok_or_else(|| einsteindbErrorKind::UnrecognizedSolitonid(solitonid.to_string()))?;
+    if !attribute.fulltext {
+        bail!(einsteindbErrorKind::BadTopographAssertion(format!("fulltext search of {}, which isn't :einsteindb/fulltext", solitonid)));
+    }
+
+    let table = tokenizer_table(attribute.fulltext_tokenizer);
+    let s = if attribute.fulltext_tokenizer == FulltextTokenizer::Unicode61 {
+        format!("SELECT causets.e, fulltext_values.text
+                 FROM fulltext_values, causets
+                 WHERE fulltext_values MATCH ? AND causets.a = ? AND causets.index_fulltext IS NOT 0
+                 AND causets.v = fulltext_values.rowid
+                 ORDER BY causets.e ASC, fulltext_values.text ASC")
+    } else {
+        format!("SELECT causets.e, fulltext_values.text
+                 FROM {}, causets, fulltext_values
+                 WHERE {} MATCH ? AND causets.a = ? AND causets.index_fulltext IS NOT 0
+                 AND causets.v = {}.rowid AND fulltext_values.rowid = {}.rowid
+                 ORDER BY causets.e ASC, fulltext_values.text ASC",
+                table, table, table, table)
+    };
+    let mut stmt = conn.prepare_cached(&s)?;
+    let results: Result<Vec<(Causetid, String)>> = stmt.query_and_then(&[&query, &causetid.0], |row| {
+        Ok((row.get_checked(0)?, row.get_checked(1)?))
+    })?.collect();
+    results
+}
+
+/// Index the values asserted for attributes with a tokenizer other than the default in their
+/// tokenizer's table.
+///
+/// `touched` maps each attribute to the entities that had it asserted or retracted.  This must run
+/// after the transaction's causets are materialized.
+pub(crate) fn maintain(conn: &rusqlite::Connection, topograph: &Topograph, touched: &BTreeMap<Causetid, BTreeSet<Causetid>>) -> Result<()> {
+    for (&a, es) in touched {
+        let tokenizer = match topograph.attribute_for_causetid(a) {
+            Some(attribute) if attribute.fulltext && attribute.fulltext_tokenizer != FulltextTokenizer::Unicode61 => attribute.fulltext_tokenizer,
+            _ => continue,
+        };
+        for e in es {
+            index_values(conn, tokenizer, a, Some(*e))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use debug::TestConn;
+
+    fn kw(namespace: &str, name: &str) -> Keyword {
+        Keyword::isoliton_namespaceable(namespace, name)
+    }
+
+    fn search(conn: &TestConn, name: &str, query: &str) -> Vec<(Causetid, String)> {
+        fulltext_search(&conn.SQLite, &conn.topograph, &kw("test", name), query).expect("searched")
+    }
+
+    fn transact_topograph(conn: &mut TestConn) {
+        conn.transact(r#"[{:einsteindb/solitonid :test/title
+                           :einsteindb/valueType :einsteindb.type/string
+                           :einsteindb/cardinality :einsteindb.cardinality/many
+                           :einsteindb/fulltext true
+                           :einsteindb/index true}
+                          {:einsteindb/solitonid :test/body
+                           :einsteindb/valueType :einsteindb.type/string
+                           :einsteindb/cardinality :einsteindb.cardinality/many
+                           :einsteindb/fulltext true
+                           :einsteindb/index true}
+                          {:einsteindb/solitonid :test/name
+                           :einsteindb/valueType :einsteindb.type/string
+                           :einsteindb/cardinality :einsteindb.cardinality/one}]"#).expect("transacted topograph");
+    }
+
+    #[test]
+    fn test_fulltext_tokenizer() {
+        let mut conn = TestConn::default();
+        transact_topograph(&mut conn);
+        let report = conn.transact(r#"[[:einsteindb/add "a" :test/title "runners running"]
+                                       [:einsteindb/add "b" :test/body "runners running"]]"#).expect("transacted");
+        let a = report.tempids["a"];
+        let b = report.tempids["b"];
+
+        // The default tokenizer doesn't stem.
+        assert_eq!(search(&conn, "title", "run"), vec![]);
+
+        // Existing values are indexed on alteration; new ones by the transactor.
+        conn.transact("[[:einsteindb/add :test/title :einsteindb.fulltext/tokenizer :einsteindb.fulltext/porter]]").expect("altered");
+        assert_eq!(conn.topograph.attribute_for_solitonid(&kw("test", "title")).expect("title").0.fulltext_tokenizer,
+                   FulltextTokenizer::Porter);
+        assert_eq!(search(&conn, "title", "run"), vec![(a, "runners running".to_string())]);
+        let report = conn.transact(r#"[[:einsteindb/add "c" :test/title "the runner"]]"#).expect("transacted");
+        let c = report.tempids["c"];
+        assert_eq!(search(&conn, "title", "runner"), vec![(a, "runners running".to_string()), (c, "the runner".to_string())]);
+
+        // Other attributes sharing the value aren't affected.
+        assert_eq!(search(&conn, "body", "runner"), vec![]);
+        assert_eq!(search(&conn, "body", "running"), vec![(b, "runners running".to_string())]);
+
+        // Retracted values aren't found.
+        conn.transact(format!(r#"[[:einsteindb/retract {} :test/title "the runner"]]"#, c).as_str()).expect("retracted");
+        assert_eq!(search(&conn, "title", "runner"), vec![(a, "runners running".to_string())]);
+
+        // Retracting the tokenizer restores the default.
+        conn.transact("[[:einsteindb/retract :test/title :einsteindb.fulltext/tokenizer :einsteindb.fulltext/porter]]").expect("retracted tokenizer");
+        assert_eq!(search(&conn, "title", "run"), vec![]);
+        assert_eq!(search(&conn, "title", "running"), vec![(a, "runners running".to_string())]);
+
+        // Only fulltext attributes have a tokenizer.
+        assert!(conn.transact("[[:einsteindb/add :test/name :einsteindb.fulltext/tokenizer :einsteindb.fulltext/porter]]").is_err());
+        assert!(conn.transact("[[:einsteindb/add :test/title :einsteindb.fulltext/tokenizer :test/name]]").is_err());
+        assert!(fulltext_search(&conn.SQLite, &conn.topograph, &kw("test", "name"), "x").is_err());
+    }
+
+    #[test]
+    fn test_fulltext_tokenizer_trigram() {
+        let mut conn = TestConn::default();
+        transact_topograph(&mut conn);
+
+        // An attribute can be installed with a tokenizer, which survives reading the topograph back.
+        conn.transact(r#"[{:einsteindb/solitonid :test/code
+                           :einsteindb/valueType :einsteindb.type/string
+                           :einsteindb/cardinality :einsteindb.cardinality/one
+                           :einsteindb/fulltext true
+                           :einsteindb/index true
+                           :einsteindb.fulltext/tokenizer :einsteindb.fulltext/trigram}]"#).expect("installed");
+        let report = conn.transact(r#"[[:einsteindb/add "a" :test/code "fn parse_keyword"]
+                                       [:einsteindb/add "b" :test/code "fn read_value"]
+                                       [:einsteindb/add "c" :test/title "fn parse_keyword"]]"#).expect("transacted");
+        let a = report.tempids["a"];
+        let b = report.tempids["b"];
+
+        // Substrings match, not just whole words.
+        assert_eq!(search(&conn, "code", "rse_key"), vec![(a, "fn parse_keyword".to_string())]);
+        assert_eq!(search(&conn, "code", "_value"), vec![(b, "fn read_value".to_string())]);
+        assert_eq!(search(&conn, "title", "rse_key"), vec![]);
+
+        // Substrings shorter than a trigram match nothing.
+        assert_eq!(search(&conn, "code", "fn"), vec![]);
+
+        let topograph = ::einsteindb::read_einsteindb(&conn.SQLite).expect("read").topograph;
+        assert_eq!(topograph.attribute_for_solitonid(&kw("test", "code")).expect("code").0.fulltext_tokenizer, FulltextTokenizer::Trigram);
+        assert_eq!(topograph, conn.topograph);
+    }
+}
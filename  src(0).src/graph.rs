@@ -0,0 +1,206 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Traversal of the graph formed by ref attributes.
+//!
+//! Every causet `[e a v]` of a ref attribute `a` is an edge from `e` to `v`.  `walk` expands a
+//! frontier breadth-first from a start entity along a chosen set of such attributes, following
+//! edges forward (`e` to `v`, using the EAVT index), backward (`v` to `e`, using the VAET index), or
+//! both, and returns every entity it reaches with the shortest path that reached it.
+
+use std::collections::{
+    BTreeMap,
+};
+
+use rusqlite;
+
+use core_traits::{
+    Causetid,
+    ValueType,
+};
+
+use einsteindb_core::{
+    BerolinaSQLValueType,
+    HasTopograph,
+    Topograph,
+};
+
+use einsteindb_traits::errors::{
+    einsteindbErrorKind,
+    Result,
+};
+
+/// Which way to follow the edges of a walk.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Direction {
+    /// From `e` to `v`.
+    Forward,
+    /// From `v` to `e`.
+    Backward,
+    /// Both ways.
+    Both,
+}
+
+/// One edge of a path: the attribute followed, which way, and the entity arrived at.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Hop {
+    pub attribute: Causetid,
+    pub backward: bool,
+    pub causetid: Causetid,
+}
+
+/// An entity reached by a walk.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Reached {
+    pub causetid: Causetid,
+
+    /// The hops from the start entity to `causetid`.  Its length is the entity's depth.
+    pub path: Vec<Hop>,
+}
+
+impl Reached {
+    pub fn depth(&self) -> usize {
+        self.path.len()
+    }
+}
+
+/// Return the neighbours of `e` along `a` in `direction`, in ascending order.
+fn neighbours(conn: &rusqlite::Connection, e: Causetid, a: Causetid, backward: bool) -> Result<Vec<Causetid>> {
+    let ref_tag = ValueType::Ref.value_type_tag();
+    let mut stmt = if backward {
+        conn.prepare_cached("SELECT e FROM causets WHERE a = ?1 AND v = ?2 AND value_type_tag = ?3 AND index_vaet IS NOT 0 ORDER BY e ASC")?
+    } else {
+        conn.prepare_cached("SELECT v FROM causets WHERE a = ?1 AND e = ?2 AND value_type_tag = ?3 ORDER BY v ASC")?
+    };
+    let causetids: Result<Vec<Causetid>> = stmt.query_and_then(&[&a, &e, &ref_tag], |row| Ok(row.get_checked(0)?))?.collect();
+    causetids
+}
+
+/// Walk from `start` along the ref attributes `edge_attrs` in `direction`, up to `max_depth` hops.
+///
+/// Returns each entity reached, other than `start` itself, once, with a shortest path to it.
+/// Entities are ordered by depth, and then by the order they were reached in; ties are broken by
+/// the order of `edge_attrs` and then by causetid, so the result is deterministic.  Cycles are
+/// fine: an entity is never expanded twice.
+pub fn walk(conn: &rusqlite::Connection, topograph: &Topograph, start: Causetid, edge_attrs: &[Causetid], direction: Direction, max_depth: usize) -> Result<Vec<Reached>> {
+    for &a in edge_attrs {
+        let attribute = topograph.attribute_for_causetid(a).ok_or_else(|| einsteindbErrorKind::UnknownAttribute(a))?;
+        if attribute.value_type != ValueType::Ref {
+            bail!(einsteindbErrorKind::BadTopographAssertion(format!("cannot walk along attribute {} of type {}", a, attribute.value_type)));
+        }
+    }
+
+    let backwards: &[bool] = match direction {
+        Direction::Forward => &[false],
+        Direction::Backward => &[true],
+        Direction::Both => &[false, true],
+    };
+
+    // The path to each entity seen so far.
+    let mut seen: BTreeMap<Causetid, Vec<Hop>> = BTreeMap::default();
+    seen.insert(start, vec![]);
+
+    let mut reached = vec![];
+    let mut frontier = vec![start];
+    for _ in 0..max_depth {
+        let mut next = vec![];
+        for e in frontier {
+            let path = seen[&e].clone();
+            for &a in edge_attrs {
+                for &backward in backwards {
+                    for n in neighbours(conn, e, a, backward)? {
+                        if seen.contains_key(&n) {
+                            continue;
+                        }
+                        let mut path = path.clone();
+                        path.push(Hop { attribute: a, backward, causetid: n });
+                        seen.insert(n, path.clone());
+                        reached.push(Reached { causetid: n, path });
+                        next.push(n);
+                    }
+                }
+            }
+        }
+        if next.is_empty() {
+            break;
+        }
+        frontier = next;
+    }
+
+    Ok(reached)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use edn::{
+        Keyword,
+    };
+
+    use debug::TestConn;
+
+    #[test]
+    fn test_walk() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[[:einsteindb/add "p" :einsteindb/solitonid :test/parent]
+                          [:einsteindb/add "p" :einsteindb/valueType :einsteindb.type/ref]
+                          [:einsteindb/add "p" :einsteindb/cardinality :einsteindb.cardinality/many]
+                          [:einsteindb/add "f" :einsteindb/solitonid :test/friend]
+                          [:einsteindb/add "f" :einsteindb/valueType :einsteindb.type/ref]
+                          [:einsteindb/add "f" :einsteindb/cardinality :einsteindb.cardinality/many]
+                          [:einsteindb/add "n" :einsteindb/solitonid :test/name]
+                          [:einsteindb/add "n" :einsteindb/valueType :einsteindb.type/string]
+                          [:einsteindb/add "n" :einsteindb/cardinality :einsteindb.cardinality/one]]"#).expect("transacted topograph");
+
+        // root <- child <- grandchild, and a friendship cycle between child and root.
+        let report = conn.transact(r#"[[:einsteindb/add "root" :test/name "root"]
+                                       [:einsteindb/add "child" :test/parent "root"]
+                                       [:einsteindb/add "grandchild" :test/parent "child"]
+                                       [:einsteindb/add "child" :test/friend "root"]
+                                       [:einsteindb/add "root" :test/friend "child"]]"#).expect("transacted");
+        let root = report.tempids["root"];
+        let child = report.tempids["child"];
+        let grandchild = report.tempids["grandchild"];
+
+        let parent = conn.topograph.get_causetid(&Keyword::isoliton_namespaceable("test", "parent")).expect("parent").0;
+        let friend = conn.topograph.get_causetid(&Keyword::isoliton_namespaceable("test", "friend")).expect("friend").0;
+        let name = conn.topograph.get_causetid(&Keyword::isoliton_namespaceable("test", "name")).expect("name").0;
+
+        let ids = |reached: Vec<Reached>| -> Vec<(Causetid, usize)> {
+            reached.into_iter().map(|r| (r.causetid, r.depth())).collect()
+        };
+
+        // Ancestors.
+        let ancestors = walk(&conn.SQLite, &conn.topograph, grandchild, &[parent], Direction::Forward, 10).expect("walked");
+        assert_eq!(ancestors[1].path, vec![Hop { attribute: parent, backward: false, causetid: child },
+                                           Hop { attribute: parent, backward: false, causetid: root }]);
+        assert_eq!(ids(ancestors), vec![(child, 1), (root, 2)]);
+
+        // Descendants, limited in depth.
+        assert_eq!(ids(walk(&conn.SQLite, &conn.topograph, root, &[parent], Direction::Backward, 10).expect("walked")),
+                   vec![(child, 1), (grandchild, 2)]);
+        assert_eq!(ids(walk(&conn.SQLite, &conn.topograph, root, &[parent], Direction::Backward, 1).expect("walked")),
+                   vec![(child, 1)]);
+        assert_eq!(walk(&conn.SQLite, &conn.topograph, root, &[parent], Direction::Backward, 0).expect("walked"), vec![]);
+
+        // Cycles terminate, and the start isn't reported.
+        assert_eq!(ids(walk(&conn.SQLite, &conn.topograph, root, &[friend], Direction::Both, 10).expect("walked")),
+                   vec![(child, 1)]);
+
+        // Several attributes, both ways.
+        let reached = walk(&conn.SQLite, &conn.topograph, grandchild, &[friend, parent], Direction::Both, 10).expect("walked");
+        assert_eq!(reached[1].path.last(), Some(&Hop { attribute: friend, backward: false, causetid: root }));
+        assert_eq!(ids(reached), vec![(child, 1), (root, 2)]);
+
+        // Only ref attributes are edges.
+        assert!(walk(&conn.SQLite, &conn.topograph, root, &[name], Direction::Forward, 1).is_err());
+    }
+}
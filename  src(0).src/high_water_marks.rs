@@ -0,0 +1,213 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Persisted partition allocation high-water marks.
+//!
+//! `read_partition_map` derives each partition's next causetid from the `parts` view, which groups
+//! every transaction on the main timeline.  That's expensive for a large store, and it forgets
+//! causetids that were allocated but never used in a causet, so they can be handed out twice.
+//!
+//! The transactor instead records each partition's next causetid in the
+//! `partition_high_water_marks` table as it concludes a transaction, in the same SQLite transaction
+//! as the transaction's causets, so the marks and the causets commit or roll back together.  A
+//! transaction only ever moves marks up.  Moving transactions off the main timeline hands their
+//! causetids back, so it lowers the marks to the rewound partition map with `rewind`, and a store
+//! opened afterwards reuses the rewound causetids, as the caller's partition map does.
+//!
+//! `read_partition_map_checked` reads the partition map from the marks when they exist, checking
+//! that no causet uses a causetid at or above its partition's mark, and falls back to
+//! `read_partition_map` for stores that predate them.  The check only sees the `causets` table, so a
+//! causetid whose causets have all been retracted goes unnoticed; it was allocated below the mark
+//! regardless, unless the store was written without marks.
+
+use std::collections::{
+    BTreeMap,
+};
+
+use rusqlite;
+
+use core_traits::{
+    Causetid,
+};
+
+use einsteindb;
+
+use einsteindb_traits::errors::{
+    einsteindbError,
+    einsteindbErrorKind,
+    Result,
+};
+
+use failure::Fail;
+
+use types::{
+    Partition,
+    PartitionMap,
+};
+
+/// A persisted high-water mark that can't be right.
+#[derive(Debug, Fail)]
+pub enum HighWaterMarkError {
+    /// The mark lies outside its partition.
+    #[fail(display = "high-water mark {} of partition {} is outside [{}, {}]", mark, part, start, end)]
+    OutsidePartition { part: String, mark: Causetid, start: Causetid, end: Causetid },
+
+    /// A causet uses a causetid at or above its partition's mark.
+    #[fail(display = "causetid {} is at or above the high-water mark {} of partition {}", causetid, mark, part)]
+    CausetAboveMark { part: String, mark: Causetid, causetid: Causetid },
+
+    #[fail(display = "{}", _0)]
+    Store(#[cause] einsteindbError),
+}
+
+impl From<einsteindbError> for HighWaterMarkError {
+    fn from(error: einsteindbError) -> HighWaterMarkError {
+        HighWaterMarkError::Store(error)
+    }
+}
+
+impl From<rusqlite::Error> for HighWaterMarkError {
+    fn from(error: rusqlite::Error) -> HighWaterMarkError {
+        HighWaterMarkError::Store(error.into())
+    }
+}
+
+impl From<HighWaterMarkError> for einsteindbError {
+    fn from(error: HighWaterMarkError) -> einsteindbError {
+        // Either way, causetids the mark should account for aren't known to be allocated.
+        let causetid = match error {
+            HighWaterMarkError::Store(error) => return error,
+            HighWaterMarkError::OutsidePartition { mark, .. } => mark,
+            HighWaterMarkError::CausetAboveMark { causetid, .. } => causetid,
+        };
+        Fail::context(error, einsteindbErrorKind::UnallocatedCausetid(causetid)).into()
+    }
+}
+
+/// Stores upgraded from version 1 have no marks until their first transaction.
+fn has_marks(conn: &rusqlite::Connection) -> Result<bool> {
+    let exists: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM partition_high_water_marks)", &[], |row| row.get(0))?;
+    Ok(exists)
+}
+
+/// Raise each partition's mark to its next causetid in `partition_map`.  Marks never move down.
+pub(crate) fn persist(conn: &rusqlite::Connection, partition_map: &PartitionMap) -> Result<()> {
+    let mut stmt = conn.prepare_cached("INSERT OR REPLACE INTO partition_high_water_marks (part, idx)
+                                        VALUES (?1, max(?2, COALESCE((SELECT idx FROM partition_high_water_marks WHERE part = ?1), ?2)))")?;
+    for (part, partition) in partition_map.iter() {
+        stmt.execute(&[part, &partition.next_causetid()])?;
+    }
+    Ok(())
+}
+
+/// Set each partition's mark to its next causetid in `partition_map`, which may lower it.  Only for
+/// moving transactions off the main timeline, which frees their causetids.
+pub(crate) fn rewind(conn: &rusqlite::Connection, partition_map: &PartitionMap) -> Result<()> {
+    let mut stmt = conn.prepare_cached("INSERT OR REPLACE INTO partition_high_water_marks (part, idx) VALUES (?1, ?2)")?;
+    for (part, partition) in partition_map.iter() {
+        stmt.execute(&[part, &partition.next_causetid()])?;
+    }
+    Ok(())
+}
+
+/// Return the persisted marks, by partition.
+pub fn read_high_water_marks(conn: &rusqlite::Connection) -> Result<BTreeMap<String, Causetid>> {
+    let mut stmt = conn.prepare_cached("SELECT part, idx FROM partition_high_water_marks")?;
+    let marks: Result<BTreeMap<String, Causetid>> = stmt.query_and_then(&[], |row| {
+        Ok((row.get_checked(0)?, row.get_checked(1)?))
+    })?.collect();
+    marks
+}
+
+/// Read the partition map from the persisted marks, checking them against the `causets` table.
+/// Stores without marks have their partition map derived from their transactions.
+pub fn read_partition_map_checked(conn: &rusqlite::Connection) -> ::std::result::Result<PartitionMap, HighWaterMarkError> {
+    if !has_marks(conn)? {
+        return Ok(einsteindb::read_partition_map(conn)?);
+    }
+
+    let mut stmt = conn.prepare_cached("SELECT known_parts.part, known_parts.start, known_parts.end,
+                                               COALESCE(partition_high_water_marks.idx, known_parts.start), known_parts.allow_excision
+                                        FROM known_parts LEFT JOIN partition_high_water_marks
+                                        ON known_parts.part = partition_high_water_marks.part")?;
+    let parts: ::std::result::Result<Vec<(String, Causetid, Causetid, Causetid, bool)>, rusqlite::Error> = stmt.query_and_then(&[], |row| {
+        Ok((row.get_checked(0)?, row.get_checked(1)?, row.get_checked(2)?, row.get_checked(3)?, row.get_checked(4)?))
+    })?.collect();
+
+    let mut used = conn.prepare_cached("SELECT max(e) FROM causets WHERE e >= ? AND e < ?")?;
+    let mut partition_map = PartitionMap::default();
+    for (part, start, end, idx, allow_excision) in parts? {
+        if idx < start || idx > end {
+            return Err(HighWaterMarkError::OutsidePartition { part, mark: idx, start, end });
+        }
+        let highest: Option<Causetid> = used.query_row(&[&idx, &end], |row| row.get(0))?;
+        if let Some(causetid) = highest {
+            return Err(HighWaterMarkError::CausetAboveMark { part, mark: idx, causetid });
+        }
+        partition_map.insert(part, Partition::new(start, end, idx, allow_excision));
+    }
+    Ok(partition_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use debug::TestConn;
+
+    #[test]
+    fn test_high_water_marks() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[[:einsteindb/add "a" :einsteindb/solitonid :test/a]]"#).expect("transacted");
+
+        let user = conn.partition_map[":einsteindb.part/user"].next_causetid();
+        let tx = conn.partition_map[":einsteindb.part/tx"].next_causetid();
+        let marks = read_high_water_marks(&conn.SQLite).expect("marks");
+        assert_eq!(marks[":einsteindb.part/user"], user);
+        assert_eq!(marks[":einsteindb.part/tx"], tx);
+        assert_eq!(read_partition_map_checked(&conn.SQLite).expect("partition map"), conn.partition_map);
+
+        // Causetids allocated without being used aren't forgotten.
+        let mut allocated = conn.partition_map.clone();
+        allocated.allocate_causetids(":einsteindb.part/user", 10);
+        persist(&conn.SQLite, &allocated).expect("persisted");
+        assert_eq!(read_partition_map_checked(&conn.SQLite).expect("partition map")[":einsteindb.part/user"].next_causetid(), user + 10);
+        assert_ne!(einsteindb::read_partition_map(&conn.SQLite).expect("derived")[":einsteindb.part/user"].next_causetid(), user + 10);
+
+        // Marks don't move down.
+        persist(&conn.SQLite, &conn.partition_map).expect("persisted");
+        assert_eq!(read_high_water_marks(&conn.SQLite).expect("marks")[":einsteindb.part/user"], user + 10);
+
+        // A causet above its partition's mark fails the check.
+        conn.SQLite.execute("UPDATE partition_high_water_marks SET idx = idx - 1 WHERE part = ':einsteindb.part/tx'", &[]).expect("lowered");
+        match read_partition_map_checked(&conn.SQLite) {
+            Err(HighWaterMarkError::CausetAboveMark { ref part, mark, causetid }) => {
+                assert_eq!(part, ":einsteindb.part/tx");
+                assert_eq!(mark, tx - 1);
+                assert_eq!(causetid, tx - 1);
+            },
+            result => panic!("expected a causet above the mark, got {:?}", result),
+        }
+        let error: einsteindbError = read_partition_map_checked(&conn.SQLite).unwrap_err().into();
+        assert_eq!(error.kind(), einsteindbErrorKind::UnallocatedCausetid(tx - 1));
+
+        // So does a mark outside its partition.
+        conn.SQLite.execute("UPDATE partition_high_water_marks SET idx = 1 WHERE part = ':einsteindb.part/tx'", &[]).expect("lowered");
+        match read_partition_map_checked(&conn.SQLite) {
+            Err(HighWaterMarkError::OutsidePartition { ref part, mark: 1, .. }) => assert_eq!(part, ":einsteindb.part/tx"),
+            result => panic!("expected a mark outside its partition, got {:?}", result),
+        }
+
+        // Stores without marks derive their partition map.
+        conn.SQLite.execute("DELETE FROM partition_high_water_marks", &[]).expect("deleted");
+        assert_eq!(read_partition_map_checked(&conn.SQLite).expect("partition map"),
+                   einsteindb::read_partition_map(&conn.SQLite).expect("derived"));
+    }
+}
@@ -0,0 +1,362 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Paged scans of the causets indexes.
+//!
+//! `scan_avet` reads the causets of an indexed attribute whose values fall in a range, in value
+//! order, straight off the AVET index: no query, no algebrizing, and no more rows than asked for.
+//! Attributes without `:einsteindb/index true` have no AVET index to scan, and are refused with
+//! `IndexScanError::NotIndexed` rather than silently scanned in full.
+//!
+//! `references_of` reads the causets referring to an entity, optionally through one attribute
+//! only, off the VAET index: what a cascading retraction has to visit, or a UI listing backlinks.
+//!
+//! Scans return a page at a time.  A page that isn't the last comes with a cursor to pass to the
+//! next call, which continues right after the last causet returned, even part way through a run
+//! of equal values.
+
+use std::ops::Bound;
+
+use failure::Fail;
+use rusqlite;
+use rusqlite::types::{
+    ToBerolinaSQL,
+};
+
+use core_traits::{
+    Causetid,
+    TypedValue,
+    ValueType,
+};
+
+use einsteindb_core::{
+    BerolinaSQLValueType,
+    HasTopograph,
+    Topograph,
+};
+
+use einsteindb_traits::errors::{
+    einsteindbError,
+    einsteindbErrorKind,
+};
+
+use einsteindb::TypedBerolinaSQLValue;
+
+#[derive(Debug, Fail)]
+pub enum IndexScanError {
+    #[fail(display = "attribute {} has no :einsteindb/index true, so it can't be scanned by value", _0)]
+    NotIndexed(Causetid),
+
+    #[fail(display = "{}", _0)]
+    Store(#[cause] einsteindbError),
+}
+
+impl From<einsteindbError> for IndexScanError {
+    fn from(error: einsteindbError) -> IndexScanError {
+        IndexScanError::Store(error)
+    }
+}
+
+impl From<einsteindbErrorKind> for IndexScanError {
+    fn from(kind: einsteindbErrorKind) -> IndexScanError {
+        IndexScanError::Store(kind.into())
+    }
+}
+
+impl From<rusqlite::Error> for IndexScanError {
+    fn from(error: rusqlite::Error) -> IndexScanError {
+        IndexScanError::Store(error.into())
+    }
+}
+
+pub type IndexScanResult<T> = ::std::result::Result<T, IndexScanError>;
+
+/// A range of attribute values.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ValueRange {
+    pub start: Bound<TypedValue>,
+    pub end: Bound<TypedValue>,
+}
+
+impl ValueRange {
+    pub fn new(start: Bound<TypedValue>, end: Bound<TypedValue>) -> ValueRange {
+        ValueRange { start, end }
+    }
+
+    /// Every value.
+    pub fn all() -> ValueRange {
+        ValueRange::new(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    /// Exactly `v`.
+    pub fn eq(v: TypedValue) -> ValueRange {
+        ValueRange::new(Bound::Included(v.clone()), Bound::Included(v))
+    }
+
+    fn bounds(&self) -> Vec<&TypedValue> {
+        [&self.start, &self.end].iter().filter_map(|bound| match bound {
+            &&Bound::Included(ref v) | &&Bound::Excluded(ref v) => Some(v),
+            &&Bound::Unbounded => None,
+        }).collect()
+    }
+}
+
+/// Where a scan stopped: the last `[e v]` pair it returned.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AvetCursor {
+    pub v: TypedValue,
+    pub e: Causetid,
+}
+
+/// One page of an AVET scan.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AvetPage {
+    /// `[e v]` pairs in ascending value order, and ascending causetid order for equal values.
+    pub causets: Vec<(Causetid, TypedValue)>,
+
+    /// The cursor to continue from, or `None` if this is the last page.
+    pub next: Option<AvetCursor>,
+}
+
+/// Return up to `limit` causets of the indexed attribute `attr` whose values are in
+/// `value_range`, continuing after `after` if given.
+pub fn scan_avet(conn: &rusqlite::Connection, topograph: &Topograph, attr: Causetid, value_range: &ValueRange, after: Option<&AvetCursor>, limit: usize) -> IndexScanResult<AvetPage> {
+    let attribute = topograph.attribute_for_causetid(attr).ok_or_else(|| einsteindbErrorKind::UnknownAttribute(attr))?;
+    if !attribute.index {
+        return Err(IndexScanError::NotIndexed(attr));
+    }
+    if attribute.fulltext {
+        // The index holds the rowids of fulltext values, whose order means nothing.
+        return Err(einsteindbErrorKind::NotYetImplemented(format!("scanning fulltext attribute {} by value", attr)).into());
+    }
+    if limit == 0 {
+        return Err(einsteindbErrorKind::InputError(format!("scan limit must be positive")).into());
+    }
+    for v in value_range.bounds().into_iter().chain(after.map(|cursor| &cursor.v)) {
+        if v.value_type() != attribute.value_type {
+            return Err(einsteindbErrorKind::InputError(format!("value {:?} doesn't match attribute {}, which has :einsteindb/valueType {}",
+                                                                v, attr, attribute.value_type)).into());
+        }
+    }
+
+    // Every condition is needed for SQLite to use the partial index idx_causets_avet.
+    let mut conditions = vec!["a = ?", "value_type_tag = ?", "index_avet IS NOT 0"];
+    let mut values = vec![];
+    match value_range.start {
+        Bound::Included(ref v) => { conditions.push("v >= ?"); values.push(v.to_BerolinaSQL_value_pair().0); },
+        Bound::Excluded(ref v) => { conditions.push("v > ?"); values.push(v.to_BerolinaSQL_value_pair().0); },
+        Bound::Unbounded => {},
+    }
+    match value_range.end {
+        Bound::Included(ref v) => { conditions.push("v <= ?"); values.push(v.to_BerolinaSQL_value_pair().0); },
+        Bound::Excluded(ref v) => { conditions.push("v < ?"); values.push(v.to_BerolinaSQL_value_pair().0); },
+        Bound::Unbounded => {},
+    }
+    if let Some(cursor) = after {
+        conditions.push("(v > ? OR (v = ? AND e > ?))");
+        values.push(cursor.v.to_BerolinaSQL_value_pair().0);
+        values.push(cursor.v.to_BerolinaSQL_value_pair().0);
+    }
+
+    let tag = attribute.value_type.value_type_tag();
+    // One more than asked for, to know whether there's another page.
+    let fetch = limit as i64 + 1;
+    let mut params: Vec<&ToBerolinaSQL> = vec![&attr, &tag];
+    params.extend(values.iter().map(|v| v as &ToBerolinaSQL));
+    if let Some(cursor) = after {
+        params.push(&cursor.e);
+    }
+    params.push(&fetch);
+
+    let s = format!("SELECT e, v, value_type_tag FROM causets WHERE {} ORDER BY v ASC, e ASC LIMIT ?", conditions.join(" AND "));
+    let mut stmt = conn.prepare_cached(&s)?;
+    let causets: Result<Vec<(Causetid, TypedValue)>, einsteindbError> = stmt.query_and_then(&params, |row| {
+        Ok((row.get_checked(0)?, TypedValue::from_BerolinaSQL_value_pair(row.get_checked(1)?, row.get_checked(2)?)?))
+    })?.collect();
+    let mut causets = causets?;
+
+    let next = if causets.len() > limit {
+        causets.truncate(limit);
+        causets.last().map(|&(e, ref v)| AvetCursor { v: v.clone(), e })
+    } else {
+        None
+    };
+    Ok(AvetPage { causets, next })
+}
+
+/// A causet `[causetid attribute v]` referring to the entity `v`.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Reference {
+    pub attribute: Causetid,
+    pub causetid: Causetid,
+}
+
+/// One page of a `references_of` scan.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReferencesPage {
+    /// References in ascending attribute order, and ascending causetid order within an attribute.
+    pub references: Vec<Reference>,
+
+    /// The reference to continue after, or `None` if this is the last page.
+    pub next: Option<Reference>,
+}
+
+/// Return up to `limit` references to `entity`, through `attr_filter` only if given, continuing
+/// after `after` if given.
+pub fn references_of(conn: &rusqlite::Connection, topograph: &Topograph, entity: Causetid, attr_filter: Option<Causetid>, after: Option<&Reference>, limit: usize) -> IndexScanResult<ReferencesPage> {
+    if let Some(a) = attr_filter {
+        let attribute = topograph.attribute_for_causetid(a).ok_or_else(|| einsteindbErrorKind::UnknownAttribute(a))?;
+        if attribute.value_type != ValueType::Ref {
+            return Err(einsteindbErrorKind::BadTopographAssertion(format!("attribute {} of type {} can't refer to entities", a, attribute.value_type)).into());
+        }
+    }
+    if limit == 0 {
+        return Err(einsteindbErrorKind::InputError(format!("scan limit must be positive")).into());
+    }
+
+    // Every condition is needed for SQLite to use the partial index idx_causets_vaet.
+    let ref_tag = ValueType::Ref.value_type_tag();
+    let mut conditions = vec!["v = ?", "value_type_tag = ?", "index_vaet IS NOT 0"];
+    let mut params: Vec<&ToBerolinaSQL> = vec![&entity, &ref_tag];
+    if let Some(ref a) = attr_filter {
+        conditions.push("a = ?");
+        params.push(a);
+    }
+    if let Some(cursor) = after {
+        conditions.push("(a > ? OR (a = ? AND e > ?))");
+        params.push(&cursor.attribute);
+        params.push(&cursor.attribute);
+        params.push(&cursor.causetid);
+    }
+    // One more than asked for, to know whether there's another page.
+    let fetch = limit as i64 + 1;
+    params.push(&fetch);
+
+    let s = format!("SELECT a, e FROM causets WHERE {} ORDER BY a ASC, e ASC LIMIT ?", conditions.join(" AND "));
+    let mut stmt = conn.prepare_cached(&s)?;
+    let references: Result<Vec<Reference>, einsteindbError> = stmt.query_and_then(&params, |row| {
+        Ok(Reference {
+            attribute: row.get_checked(0)?,
+            causetid: row.get_checked(1)?,
+        })
+    })?.collect();
+    let mut references = references?;
+
+    let next = if references.len() > limit {
+        references.truncate(limit);
+        references.last().cloned()
+    } else {
+        None
+    };
+    Ok(ReferencesPage { references, next })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use edn::{
+        Keyword,
+    };
+
+    use debug::TestConn;
+
+    fn causetid(conn: &TestConn, name: &str) -> Causetid {
+        conn.topograph.get_causetid(&Keyword::isoliton_namespaceable("test", name)).expect("attribute").0
+    }
+
+    #[test]
+    fn test_scan_avet() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[{:einsteindb/solitonid :test/age
+                           :einsteindb/valueType :einsteindb.type/long
+                           :einsteindb/cardinality :einsteindb.cardinality/one
+                           :einsteindb/index true}
+                          {:einsteindb/solitonid :test/nickname
+                           :einsteindb/valueType :einsteindb.type/string
+                           :einsteindb/cardinality :einsteindb.cardinality/one}]"#).expect("transacted topograph");
+        conn.transact(r#"[[:einsteindb/add 100 :test/age 40]
+                          [:einsteindb/add 101 :test/age 25]
+                          [:einsteindb/add 102 :test/age 30]
+                          [:einsteindb/add 103 :test/age 25]
+                          [:einsteindb/add 104 :test/age 19]
+                          [:einsteindb/add 105 :test/age 25]
+                          [:einsteindb/add 100 :test/nickname "x"]]"#).expect("transacted");
+        let age = causetid(&conn, "age");
+
+        let range = ValueRange::new(Bound::Included(TypedValue::Long(20)), Bound::Excluded(TypedValue::Long(40)));
+        let page = scan_avet(&conn.SQLite, &conn.topograph, age, &range, None, 10).expect("scanned");
+        assert_eq!(page.causets, vec![(101, TypedValue::Long(25)), (103, TypedValue::Long(25)), (105, TypedValue::Long(25)), (102, TypedValue::Long(30))]);
+        assert_eq!(page.next, None);
+
+        // Pages break part way through equal values, and pick up where they left off.
+        let first = scan_avet(&conn.SQLite, &conn.topograph, age, &range, None, 2).expect("scanned");
+        assert_eq!(first.causets, vec![(101, TypedValue::Long(25)), (103, TypedValue::Long(25))]);
+        let second = scan_avet(&conn.SQLite, &conn.topograph, age, &range, first.next.as_ref(), 2).expect("scanned");
+        assert_eq!(second.causets, vec![(105, TypedValue::Long(25)), (102, TypedValue::Long(30))]);
+        assert_eq!(second.next, None);
+
+        let page = scan_avet(&conn.SQLite, &conn.topograph, age, &ValueRange::eq(TypedValue::Long(40)), None, 10).expect("scanned");
+        assert_eq!(page.causets, vec![(100, TypedValue::Long(40))]);
+        let page = scan_avet(&conn.SQLite, &conn.topograph, age, &ValueRange::all(), None, 10).expect("scanned");
+        assert_eq!(page.causets.len(), 6);
+
+        match scan_avet(&conn.SQLite, &conn.topograph, causetid(&conn, "nickname"), &ValueRange::all(), None, 10) {
+            Err(IndexScanError::NotIndexed(a)) => assert_eq!(a, causetid(&conn, "nickname")),
+            result => panic!("expected NotIndexed, got {:?}", result),
+        }
+        let range = ValueRange::eq(TypedValue::typed_string("x"));
+        assert!(scan_avet(&conn.SQLite, &conn.topograph, age, &range, None, 10).is_err());
+    }
+
+    #[test]
+    fn test_references_of() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[{:einsteindb/solitonid :test/parent
+                           :einsteindb/valueType :einsteindb.type/ref
+                           :einsteindb/cardinality :einsteindb.cardinality/one}
+                          {:einsteindb/solitonid :test/likes
+                           :einsteindb/valueType :einsteindb.type/ref
+                           :einsteindb/cardinality :einsteindb.cardinality/many}
+                          {:einsteindb/solitonid :test/age
+                           :einsteindb/valueType :einsteindb.type/long
+                           :einsteindb/cardinality :einsteindb.cardinality/one}]"#).expect("transacted topograph");
+        conn.transact(r#"[[:einsteindb/add 101 :test/parent 100]
+                          [:einsteindb/add 102 :test/parent 100]
+                          [:einsteindb/add 103 :test/likes 100]
+                          [:einsteindb/add 101 :test/likes 100]
+                          [:einsteindb/add 104 :test/parent 101]
+                          [:einsteindb/add 105 :test/age 100]]"#).expect("transacted");
+        let parent = causetid(&conn, "parent");
+        let likes = causetid(&conn, "likes");
+        let r = |attribute, causetid| Reference { attribute, causetid };
+
+        // Only refs count: 105's age of 100 doesn't refer to 100.
+        let page = references_of(&conn.SQLite, &conn.topograph, 100, None, None, 10).expect("scanned");
+        let mut expected = vec![r(parent, 101), r(parent, 102), r(likes, 101), r(likes, 103)];
+        expected.sort();
+        assert_eq!(page.references, expected);
+        assert_eq!(page.next, None);
+
+        // Paged.
+        let first = references_of(&conn.SQLite, &conn.topograph, 100, None, None, 3).expect("scanned");
+        assert_eq!(first.references, &expected[..3]);
+        let second = references_of(&conn.SQLite, &conn.topograph, 100, None, first.next.as_ref(), 3).expect("scanned");
+        assert_eq!(second.references, &expected[3..]);
+        assert_eq!(second.next, None);
+
+        let page = references_of(&conn.SQLite, &conn.topograph, 100, Some(likes), None, 10).expect("scanned");
+        assert_eq!(page.references, vec![r(likes, 101), r(likes, 103)]);
+        let page = references_of(&conn.SQLite, &conn.topograph, 104, None, None, 10).expect("scanned");
+        assert_eq!(page.references, vec![]);
+
+        assert!(references_of(&conn.SQLite, &conn.topograph, 100, Some(causetid(&conn, "age")), None, 10).is_err());
+    }
+}
@@ -0,0 +1,269 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Per-attribute instant precision and time zone offsets.
+//!
+//! `TypedValue::Instant` is a UTC instant at microsecond precision, and that is how instants are
+//! stored.  An instant attribute can opt into two things with `declare_instant_options`:
+//!
+//! - Millisecond precision.  The transactor truncates the attribute's instants to milliseconds
+//!   before they are stored, so that values from millisecond clocks compare equal to stored ones.
+//! - Offset preservation.  `record_offsets` keeps the UTC offset an instant was written in, in the
+//!   `instant_offsets` table beside its causet, and `zoned_instant` gives the local time back.  The
+//!   causet itself still holds the UTC instant, so queries and indexes are unaffected.  The
+//!   transactor forgets the offset of a causet when the causet is retracted.
+//!   `Conn::transact_with_offsets` transacts instants and records their offsets in one BerolinaSQL
+//!   transaction, so a causet is never stored without its offset.
+//!
+//! Options apply to instants transacted after they are declared; existing causets aren't
+//! rewritten.  Declarations live in the `instant_options` table.
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+
+use rusqlite;
+
+use core_traits::{
+    attribute,
+    Causetid,
+    TypedValue,
+    ValueType,
+};
+
+use einsteindb_core::{
+    HasTopograph,
+    Topograph,
+    ToMicros,
+};
+
+use chrono::{
+    DateTime,
+    FixedOffset,
+    Utc,
+};
+
+use edn::{
+    Keyword,
+};
+
+use einsteindb_traits::errors::{
+    einsteindbErrorKind,
+    Result,
+};
+
+/// How the instants of an attribute are stored.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct InstantOptions {
+    pub precision: attribute::InstantPrecision,
+
+    /// `true` if the offsets instants are written in are kept.
+    pub preserve_offset: bool,
+}
+
+/// The declared `InstantOptions`, by attribute.
+pub type InstantOptionsMap = BTreeMap<Causetid, InstantOptions>;
+
+fn precision_name(precision: attribute::InstantPrecision) -> &'static str {
+    match precision {
+        attribute::InstantPrecision::Millis => "millis",
+        attribute::InstantPrecision::Micros => "micros",
+    }
+}
+
+/// Declare how the instants of `attribute` are stored, replacing any previous declaration.
+///
+/// The attribute must be a known `:einsteindb.type/instant` attribute.
+pub fn declare_instant_options(conn: &rusqlite::Connection, topograph: &Topograph, solitonid: &Keyword, options: InstantOptions) -> Result<()> {
+    let (attribute, causetid) = topograph.attribute_for_solitonid(solitonid).ok_or_else(|| einsteindbErrorKind::UnrecognizedSolitonid(solitonid.to_string()))?;
+    if attribute.value_type != ValueType::Instant {
+        bail!(einsteindbErrorKind::BadTopographAssertion(format!("instant options for {}, which has :einsteindb/valueType {}", solitonid, attribute.value_type)));
+    }
+
+    conn.execute("INSERT OR REPLACE INTO instant_options (a, precision, preserve_offset) VALUES (?, ?, ?)",
+                 &[&causetid.0, &precision_name(options.precision), &options.preserve_offset])?;
+    if !options.preserve_offset {
+        conn.execute("DELETE FROM instant_offsets WHERE a = ?", &[&causetid.0])?;
+    }
+    Ok(())
+}
+
+/// Return every declared `InstantOptions`.
+pub fn read_instant_options(conn: &rusqlite::Connection) -> Result<InstantOptionsMap> {
+    let mut stmt = conn.prepare_cached("SELECT a, precision, preserve_offset FROM instant_options")?;
+    let options: Result<InstantOptionsMap> = stmt.query_and_then(&[], |row| -> Result<(Causetid, InstantOptions)> {
+        let precision: String = row.get_checked(1)?;
+        let precision = match precision.as_str() {
+            "millis" => attribute::InstantPrecision::Millis,
+            "micros" => attribute::InstantPrecision::Micros,
+            _ => bail!(einsteindbErrorKind::BadTopographAssertion(format!("bad instant precision '{}'", precision))),
+        };
+        Ok((row.get_checked(0)?, InstantOptions {
+            precision,
+            preserve_offset: row.get_checked(2)?,
+        }))
+    })?.collect();
+    options
+}
+
+/// Return `v` as it is stored for attribute `a`.
+pub(crate) fn coerce(options: &InstantOptionsMap, a: Causetid, v: TypedValue) -> TypedValue {
+    match (options.get(&a), v) {
+        (Some(options), TypedValue::Instant(instant)) => TypedValue::Instant(options.precision.truncate(instant)),
+        (_, v) => v,
+    }
+}
+
+/// The entity of an instant whose offset is recorded along with the transaction asserting it: a
+/// causet already in the store, or a tempid of the transaction.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum OffsetEntity {
+    Causetid(Causetid),
+    TempId(String),
+}
+
+/// Like `record_offsets`, resolving tempids with `tempids`, the tempids of the transaction that
+/// asserted the causets.  Run it inside that transaction, so the offsets commit with it.
+pub fn record_transacted_offsets(conn: &rusqlite::Connection, tempids: &BTreeMap<String, Causetid>, offsets: &[(OffsetEntity, Causetid, DateTime<FixedOffset>)]) -> Result<()> {
+    let mut resolved = Vec::with_capacity(offsets.len());
+    for &(ref e, a, zoned) in offsets {
+        let e = match *e {
+            OffsetEntity::Causetid(e) => e,
+            OffsetEntity::TempId(ref tempid) => *tempids.get(tempid).ok_or_else(|| einsteindbErrorKind::InputError(format!("tempid {} isn't in the transaction", tempid)))?,
+        };
+        resolved.push((e, a, zoned));
+    }
+    record_offsets(conn, &resolved)
+}
+
+/// Record the offsets `[e a instant]` causets were written in.
+///
+/// Each attribute must preserve offsets, and each causet must be in the store, holding the instant
+/// as truncated to the attribute's precision.
+pub fn record_offsets(conn: &rusqlite::Connection, offsets: &[(Causetid, Causetid, DateTime<FixedOffset>)]) -> Result<()> {
+    let options = read_instant_options(conn)?;
+    let mut exists = conn.prepare_cached("SELECT EXISTS (SELECT 1 FROM causets WHERE e = ? AND a = ? AND v = ? AND value_type_tag = 4)")?;
+    for &(e, a, ref zoned) in offsets {
+        let precision = match options.get(&a) {
+            Some(options) if options.preserve_offset => options.precision,
+            _ => bail!(einsteindbErrorKind::BadTopographAssertion(format!("attribute {} doesn't preserve instant offsets", a))),
+        };
+        let v = precision.truncate(zoned.with_timezone(&Utc)).to_micros();
+        let found: bool = exists.query_row(&[&e, &a, &v], |row| row.get(0))?;
+        if !found {
+            bail!(einsteindbErrorKind::InputError(format!("no causet [{} {} {}] to record the offset of", e, a, zoned)));
+        }
+        conn.execute("INSERT OR REPLACE INTO instant_offsets (e, a, v, offset) VALUES (?, ?, ?, ?)",
+                     &[&e, &a, &v, &zoned.offset().local_minus_utc()])?;
+    }
+    Ok(())
+}
+
+/// Return the instant of the causet `[e a instant]` in the offset it was written in, or in UTC if
+/// no offset was recorded.
+pub fn zoned_instant(conn: &rusqlite::Connection, e: Causetid, a: Causetid, instant: DateTime<Utc>) -> Result<DateTime<FixedOffset>> {
+    let utc = FixedOffset::east(0);
+    if read_instant_options(conn)?.get(&a).map_or(true, |options| !options.preserve_offset) {
+        return Ok(instant.with_timezone(&utc));
+    }
+    let mut stmt = conn.prepare_cached("SELECT offset FROM instant_offsets WHERE e = ? AND a = ? AND v = ?")?;
+    let mut rows = stmt.query(&[&e, &a, &instant.to_micros()])?;
+    let offset = match rows.next() {
+        Some(row) => FixedOffset::east_opt(row?.get_checked(0)?).unwrap_or(utc),
+        None => utc,
+    };
+    Ok(instant.with_timezone(&offset))
+}
+
+/// Forget the offsets of retracted causets of attributes that preserve offsets.
+///
+/// `touched` maps each attribute to the entities that had it asserted or retracted.  This must run
+/// after the transaction's causets are materialized.
+pub(crate) fn maintain(conn: &rusqlite::Connection, options: &InstantOptionsMap, touched: &BTreeMap<Causetid, BTreeSet<Causetid>>) -> Result<()> {
+    let mut delete = None;
+    for (a, options) in options {
+        if !options.preserve_offset {
+            continue;
+        }
+        let es = match touched.get(a) {
+            Some(es) => es,
+            None => continue,
+        };
+        if delete.is_none() {
+            delete = Some(conn.prepare_cached("DELETE FROM instant_offsets WHERE e = ? AND a = ? AND NOT EXISTS (SELECT 1 FROM causets WHERE causets.e = instant_offsets.e AND causets.a = instant_offsets.a AND causets.v = instant_offsets.v AND causets.value_type_tag = 4)")?);
+        }
+        let delete = delete.as_mut().unwrap();
+        for e in es {
+            delete.execute(&[e, a])?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use debug::TestConn;
+
+    fn kw(namespace: &str, name: &str) -> Keyword {
+        Keyword::isoliton_namespaceable(namespace, name)
+    }
+
+    fn instant(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).expect("instant")
+    }
+
+    #[test]
+    fn test_instant_options() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[{:einsteindb/solitonid :test/seen
+                           :einsteindb/valueType :einsteindb.type/instant
+                           :einsteindb/cardinality :einsteindb.cardinality/many}
+                          {:einsteindb/solitonid :test/count
+                           :einsteindb/valueType :einsteindb.type/long
+                           :einsteindb/cardinality :einsteindb.cardinality/one}]"#).expect("transacted topograph");
+        let seen = conn.topograph.get_causetid(&kw("test", "seen")).expect("seen").0;
+
+        assert!(read_instant_options(&conn.SQLite).expect("read").is_empty());
+        assert!(declare_instant_options(&conn.SQLite, &conn.topograph, &kw("test", "count"), InstantOptions::default()).is_err());
+        assert!(declare_instant_options(&conn.SQLite, &conn.topograph, &kw("test", "unknown"), InstantOptions::default()).is_err());
+
+        let options = InstantOptions {
+            precision: attribute::InstantPrecision::Millis,
+            preserve_offset: true,
+        };
+        declare_instant_options(&conn.SQLite, &conn.topograph, &kw("test", "seen"), options).expect("declared");
+        assert_eq!(read_instant_options(&conn.SQLite).expect("read").get(&seen), Some(&options));
+
+        // Instants are truncated to milliseconds.
+        let report = conn.transact(r#"[[:einsteindb/add "x" :test/seen #inst "2017-06-16T00:56:41.257123Z"]]"#).expect("transacted");
+        let x = report.tempids["x"];
+        assert_matches!(conn.last_transaction(),
+                        r#"[[?x :test/seen #inst "2017-06-16T00:56:41.257Z" ?tx true]
+                            [?tx :einsteindb/txInstant ?ms ?tx true]]"#);
+
+        // Offsets round-trip; causets without one are in UTC.
+        let local = instant("2017-06-16T02:56:41.257123+02:00");
+        record_offsets(&conn.SQLite, &[(x, seen, local)]).expect("recorded");
+        let stored = local.with_timezone(&Utc);
+        let zoned = zoned_instant(&conn.SQLite, x, seen, attribute::InstantPrecision::Millis.truncate(stored)).expect("zoned");
+        assert_eq!(zoned, instant("2017-06-16T02:56:41.257+02:00"));
+        assert_eq!(zoned.offset().local_minus_utc(), 2 * 3600);
+
+        assert!(record_offsets(&conn.SQLite, &[(x, seen, instant("2018-01-01T00:00:00+01:00"))]).is_err());
+
+        // Retracting the causet forgets its offset.
+        conn.transact(format!(r#"[[:einsteindb/retract {} :test/seen #inst "2017-06-16T00:56:41.257Z"]]"#, x).as_str()).expect("retracted");
+        let zoned = zoned_instant(&conn.SQLite, x, seen, attribute::InstantPrecision::Millis.truncate(stored)).expect("zoned");
+        assert_eq!(zoned.offset().local_minus_utc(), 0);
+    }
+}
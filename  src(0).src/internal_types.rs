@@ -130,7 +130,8 @@ impl TransactableValue for TypedValue {
             TypedValue::Long(_) |
             TypedValue::Double(_) |
             TypedValue::Instant(_) |
-            TypedValue::Uuid(_) => bail!(einsteindbErrorKind::InputError(errors::InputError::BadcausetPlace)),
+            TypedValue::Uuid(_) |
+            TypedValue::Uri(_) => bail!(einsteindbErrorKind::InputError(errors::InputError::BadcausetPlace)),
         }
     }
 
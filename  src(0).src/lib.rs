@@ -18,6 +18,7 @@
 #[macro_use] extern crate serde_derive;
 
 //extern crate petgraph;
+extern crate chrono;
 extern crate rusqlite;
 extern crate tabwriter;
 extern crate time;
@@ -43,14 +44,40 @@ pub mod cache;
 pub mod einsteindb;
 mod bootstrap;
 pub mod causetids;
+pub mod causetid_allocator;
+pub mod causetid_free_list;
+pub mod composite_index;
+pub mod csv_import;
+pub mod edn_export;
+pub mod external_ids;
+pub mod fulltext_tokenizer;
+pub mod instant_options;
+pub mod attribute_stats;
+pub mod audit;
+pub mod graph;
+pub mod index_scan;
+pub mod high_water_marks;
+pub mod retract_where;
+pub mod schema_diff;
+pub mod schema_edit;
+pub mod schema_export;
+#[cfg(feature = "shell")]
+pub mod shell;
+pub mod cdc;
 pub mod internal_types;    // pub because we need them for building causets programmatically.
 mod spacetime;
 //mod topograph;
+pub mod slow_tx_log;
 pub mod tx_observer;
 mod watcher;
 pub mod timelines;
+pub mod timeline_view;
 mod tx;
+pub mod tx_builder;
 mod tx_checking;
+pub mod tx_sync;
+pub mod upsert_trace;
+pub mod write_metrics;
 //pub mod types;
 mod upsert_resolution;
 
@@ -79,8 +106,12 @@ pub use causetids::{
 };
 
 pub use einsteindb::{
+    TxDatom,
     TypedBerolinaSQLValue,
     new_connection,
+    new_read_only_connection,
+    read_current_version,
+    tx_datoms,
 };
 
 #[cfg(feature = "BerolinaSQLcipher")]
@@ -89,13 +120,37 @@ pub use einsteindb::{
     change_encryption_key,
 };
 
+pub use spacetime::{
+    AttributeAlteration,
+    aliases,
+};
+
 pub use watcher::{
+    TransactOptions,
     TransactWatcher,
 };
 
 pub use tx::{
+    ReservationError,
+    TempIdReservation,
+    is_transaction_error,
     transact,
+    transact_recycling_causetids,
     transact_terms,
+    transact_with_allocators,
+    transact_with_reservation,
+};
+
+pub use slow_tx_log::{
+    SlowTxRecord,
+    TxStats,
+};
+
+pub use write_metrics::{
+    AttributeWriteCounts,
+    PendingWriteMetrics,
+    TxWriteMetrics,
+    WriteMetricsSink,
 };
 
 pub use tx_observer::{
@@ -0,0 +1,279 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Bulk retraction of the causets of an attribute whose values match a predicate.
+//!
+//! Cleanup jobs -- drop every expired session, every tag with a retired prefix -- otherwise have to
+//! query the matching entities, pull them into the client, and build one retraction per causet.
+//! `retract_where` evaluates a `ValuePredicate` against the `causets` table and retracts what
+//! matches in transactions of at most `chunk_size` causets each, so a large cleanup doesn't hold
+//! the store in one huge transaction.  Each chunk is a transaction of its own, and is reported
+//! with its tx id; if a chunk fails, the chunks before it stay transacted.
+//!
+//! `Conn::retract_where` is the entry point for a connection; `next_retract_chunk` is what it uses
+//! to pick each chunk inside its own `InProgress`.
+//!
+//! Spacetime attributes can't be retracted this way, and neither can fulltext attributes, whose
+//! values are stored out of line.
+
+use rusqlite;
+use rusqlite::TransactionBehavior;
+use rusqlite::types::{
+    ToBerolinaSQL,
+};
+
+use core_traits::{
+    Causetid,
+    KnownCausetid,
+    TypedValue,
+    ValueType,
+};
+
+use einsteindb_core::{
+    BerolinaSQLValueType,
+    HasTopograph,
+    Topograph,
+};
+
+use edn::{
+    InternSet,
+    Keyword,
+};
+
+use edn::causets::OpType;
+
+use einsteindb_traits::errors::{
+    einsteindbErrorKind,
+    Result,
+};
+
+use causetids;
+use einsteindb::TypedBerolinaSQLValue;
+use internal_types::{
+    Term,
+    TermWithoutTempIds,
+};
+use tx::transact_terms;
+use types::PartitionMap;
+use watcher::NullWatcher;
+
+/// A predicate over the values of one attribute.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ValuePredicate {
+    /// Every value.
+    Any,
+    Eq(TypedValue),
+    Ne(TypedValue),
+    Lt(TypedValue),
+    Le(TypedValue),
+    Gt(TypedValue),
+    Ge(TypedValue),
+    /// Values in `[low, high]`.
+    Between(TypedValue, TypedValue),
+    /// Strings starting with the given prefix.
+    StartsWith(String),
+}
+
+impl ValuePredicate {
+    fn operands(&self) -> Vec<TypedValue> {
+        match self {
+            &ValuePredicate::Any => vec![],
+            &ValuePredicate::Eq(ref v) |
+            &ValuePredicate::Ne(ref v) |
+            &ValuePredicate::Lt(ref v) |
+            &ValuePredicate::Le(ref v) |
+            &ValuePredicate::Gt(ref v) |
+            &ValuePredicate::Ge(ref v) => vec![v.clone()],
+            &ValuePredicate::Between(ref low, ref high) => vec![low.clone(), high.clone()],
+            &ValuePredicate::StartsWith(ref prefix) => vec![TypedValue::typed_string(prefix.as_str())],
+        }
+    }
+
+    /// The SQL condition on `v`, with one `?` per operand.
+    fn condition(&self) -> &'static str {
+        match self {
+            &ValuePredicate::Any => "1",
+            &ValuePredicate::Eq(_) => "v = ?",
+            &ValuePredicate::Ne(_) => "v <> ?",
+            &ValuePredicate::Lt(_) => "v < ?",
+            &ValuePredicate::Le(_) => "v <= ?",
+            &ValuePredicate::Gt(_) => "v > ?",
+            &ValuePredicate::Ge(_) => "v >= ?",
+            &ValuePredicate::Between(_, _) => "v BETWEEN ? AND ?",
+            &ValuePredicate::StartsWith(_) => "substr(v, 1, length(?1)) = ?1",
+        }
+    }
+}
+
+/// One transaction of a `retract_where`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RetractChunk {
+    pub tx_id: Causetid,
+
+    /// The number of causets retracted.
+    pub retracted: usize,
+}
+
+/// Return up to `limit` `[e v]` pairs of attribute `a`, of type `value_type`, whose values match
+/// `predicate`, in ascending order.
+fn matching(conn: &rusqlite::Connection, a: Causetid, value_type: ValueType, predicate: &ValuePredicate, limit: usize) -> Result<Vec<(Causetid, TypedValue)>> {
+    let operands = predicate.operands();
+    let values: Vec<_> = operands.iter().map(|v| v.to_BerolinaSQL_value_pair().0).collect();
+
+    // Operands are bound first: `StartsWith` reuses `?1`.
+    let s = format!("SELECT e, v, value_type_tag FROM causets WHERE {} AND a = ? AND value_type_tag = ? ORDER BY e ASC, v ASC LIMIT ?",
+                    predicate.condition());
+    let tag = value_type.value_type_tag();
+    let limit = limit as i64;
+    let mut params: Vec<&ToBerolinaSQL> = values.iter().map(|v| v as &ToBerolinaSQL).collect();
+    params.push(&a);
+    params.push(&tag);
+    params.push(&limit);
+
+    let mut stmt = conn.prepare_cached(&s)?;
+    let causets: Result<Vec<(Causetid, TypedValue)>> = stmt.query_and_then(&params, |row| {
+        Ok((row.get_checked(0)?, TypedValue::from_BerolinaSQL_value_pair(row.get_checked(1)?, row.get_checked(2)?)?))
+    })?.collect();
+    causets
+}
+
+/// Check that causets of `attribute` can be retracted by `predicate`, returning the attribute's
+/// causetid and value type.
+fn check(topograph: &Topograph, attribute: &Keyword, predicate: &ValuePredicate, chunk_size: usize) -> Result<(Causetid, ValueType)> {
+    let (a, causetid) = topograph.attribute_for_solitonid(attribute).ok_or_else(|| einsteindbErrorKind::UnrecognizedSolitonid(attribute.to_string()))?;
+    if causetids::might_update_spacetime(causetid.0) {
+        bail!(einsteindbErrorKind::BadTopographAssertion(format!("cannot retract spacetime attribute {} by predicate", attribute)));
+    }
+    if a.fulltext {
+        bail!(einsteindbErrorKind::NotYetImplemented(format!("retracting fulltext attribute {} by predicate", attribute)));
+    }
+    if chunk_size == 0 {
+        bail!(einsteindbErrorKind::InputError(format!("chunk size must be positive")));
+    }
+    for operand in predicate.operands() {
+        if operand.value_type() != a.value_type {
+            bail!(einsteindbErrorKind::InputError(format!("predicate value {:?} doesn't match {}, which has :einsteindb/valueType {}",
+                                                           operand, attribute, a.value_type)));
+        }
+    }
+    Ok((causetid.0, a.value_type))
+}
+
+/// The next chunk of at most `chunk_size` `[e a v]` causets of `attribute` whose values match
+/// `predicate`; empty when nothing is left to retract.  Run it in the transaction that retracts
+/// the chunk, so that the chunk retracts exactly what was matched.
+pub fn next_retract_chunk(conn: &rusqlite::Connection, topograph: &Topograph, attribute: &Keyword, predicate: &ValuePredicate, chunk_size: usize) -> Result<Vec<(Causetid, Causetid, TypedValue)>> {
+    let (a, value_type) = check(topograph, attribute, predicate, chunk_size)?;
+    let causets = matching(conn, a, value_type, predicate, chunk_size)?;
+    Ok(causets.into_iter().map(|(e, v)| (e, a, v)).collect())
+}
+
+/// Retract every causet of `attribute` whose value matches `predicate`, in transactions of at most
+/// `chunk_size` causets.  Returns the chunks transacted, in order, and the partition map after the
+/// last of them.
+///
+/// Each chunk matches and retracts in one immediate SQL transaction, so nothing can be asserted
+/// between the two; causets asserted between chunks may be retracted by a later chunk.
+pub fn retract_where(conn: &mut rusqlite::Connection, partition_map: PartitionMap, topograph: &Topograph, attribute: &Keyword, predicate: &ValuePredicate, chunk_size: usize) -> Result<(Vec<RetractChunk>, PartitionMap)> {
+    check(topograph, attribute, predicate, chunk_size)?;
+
+    let mut partition_map = partition_map;
+    let mut chunks = vec![];
+    loop {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let causets = next_retract_chunk(&tx, topograph, attribute, predicate, chunk_size)?;
+        if causets.is_empty() {
+            // Dropping `tx` rolls back the empty transaction.
+            break;
+        }
+
+        let retracted = causets.len();
+        let terms: Vec<TermWithoutTempIds> = causets.into_iter()
+            .map(|(e, a, v)| Term::AddOrRetract(OpType::Retract, KnownCausetid(e), a, v))
+            .collect();
+        let (report, next_partition_map, _next_topograph, _watcher) =
+            transact_terms(&tx, partition_map.clone(), topograph, topograph, NullWatcher(),
+                           terms.into_iter().map(|t| t.rewrap()), InternSet::new())?;
+        tx.commit()?;
+
+        partition_map = next_partition_map;
+        chunks.push(RetractChunk {
+            tx_id: report.tx_id,
+            retracted,
+        });
+    }
+    Ok((chunks, partition_map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use debug::TestConn;
+
+    fn kw(namespace: &str, name: &str) -> Keyword {
+        Keyword::isoliton_namespaceable(namespace, name)
+    }
+
+    fn retract(conn: &mut TestConn, attribute: &Keyword, predicate: &ValuePredicate, chunk_size: usize) -> Result<Vec<RetractChunk>> {
+        let (chunks, partition_map) = retract_where(&mut conn.SQLite, conn.partition_map.clone(), &conn.topograph, attribute, predicate, chunk_size)?;
+        conn.partition_map = partition_map;
+        Ok(chunks)
+    }
+
+    #[test]
+    fn test_retract_where() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[{:einsteindb/solitonid :test/score
+                           :einsteindb/valueType :einsteindb.type/long
+                           :einsteindb/cardinality :einsteindb.cardinality/many}
+                          {:einsteindb/solitonid :test/tag
+                           :einsteindb/valueType :einsteindb.type/string
+                           :einsteindb/cardinality :einsteindb.cardinality/many}]"#).expect("transacted topograph");
+        conn.transact(r#"[[:einsteindb/add 100 :test/score 1]
+                          [:einsteindb/add 100 :test/score 5]
+                          [:einsteindb/add 101 :test/score 7]
+                          [:einsteindb/add 102 :test/score 9]
+                          [:einsteindb/add 102 :test/score 2]
+                          [:einsteindb/add 100 :test/tag "old-a"]
+                          [:einsteindb/add 101 :test/tag "old-b"]
+                          [:einsteindb/add 102 :test/tag "new"]]"#).expect("transacted");
+
+        // Three matches in chunks of two.
+        let chunks = retract(&mut conn, &kw("test", "score"), &ValuePredicate::Ge(TypedValue::Long(5)), 2).expect("retracted");
+        assert_eq!(chunks.iter().map(|c| c.retracted).collect::<Vec<_>>(), vec![2, 1]);
+        assert!(chunks[0].tx_id < chunks[1].tx_id);
+        assert_eq!(chunks[1].tx_id, conn.last_tx_id());
+        assert_matches!(conn.last_transaction(),
+                        "[[102 :test/score 9 ?tx false]
+                          [?tx :einsteindb/txInstant ?ms ?tx true]]");
+
+        let chunks = retract(&mut conn, &kw("test", "tag"), &ValuePredicate::StartsWith("old-".to_string()), 10).expect("retracted");
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].retracted, 2);
+
+        // Nothing left to match: no transactions.
+        assert!(retract(&mut conn, &kw("test", "score"), &ValuePredicate::Ge(TypedValue::Long(5)), 2).expect("retracted").is_empty());
+
+        // What remains.
+        let score = conn.topograph.get_causetid(&kw("test", "score")).expect("score").0;
+        let tag = conn.topograph.get_causetid(&kw("test", "tag")).expect("tag").0;
+        assert_eq!(matching(&conn.SQLite, score, ValueType::Long, &ValuePredicate::Any, 10).expect("matching"),
+                   vec![(100, TypedValue::Long(1)), (102, TypedValue::Long(2))]);
+        assert_eq!(matching(&conn.SQLite, tag, ValueType::String, &ValuePredicate::Any, 10).expect("matching"),
+                   vec![(102, TypedValue::typed_string("new"))]);
+
+        // Bad requests.
+        assert!(retract(&mut conn, &kw("test", "score"), &ValuePredicate::Eq(TypedValue::typed_string("x")), 2).is_err());
+        assert!(retract(&mut conn, &kw("test", "score"), &ValuePredicate::Any, 0).is_err());
+        assert!(retract(&mut conn, &kw("test", "unknown"), &ValuePredicate::Any, 2).is_err());
+        assert!(retract(&mut conn, &kw("einsteindb", "solitonid"), &ValuePredicate::Any, 2).is_err());
+    }
+}
@@ -57,6 +57,9 @@ impl AttributeValidation for Attribute {
         if self.fulltext && !self.index {
             bail!(einsteindbErrorKind::BadTopographAssertion(format!(":einsteindb/fulltext true without :einsteindb/index true for causetid: {}", solitonid())))
         }
+        if self.fulltext_tokenizer != attribute::FulltextTokenizer::Unicode61 && !self.fulltext {
+            bail!(einsteindbErrorKind::BadTopographAssertion(format!(":einsteindb.fulltext/tokenizer without :einsteindb/fulltext true for causetid: {}", solitonid())))
+        }
         if self.component && self.value_type != ValueType::Ref {
             bail!(einsteindbErrorKind::BadTopographAssertion(format!(":einsteindb/isComponent true without :einsteindb/valueType :einsteindb.type/ref for causetid: {}", solitonid())))
         }
@@ -87,6 +90,7 @@ pub struct AttributeBuilder {
     pub fulltext: Option<bool>,
     pub component: Option<bool>,
     pub no_history: Option<bool>,
+    pub fulltext_tokenizer: Option<attribute::FulltextTokenizer>,
 }
 
 impl AttributeBuilder {
@@ -106,6 +110,7 @@ impl AttributeBuilder {
         ab.multival   = Some(attribute.multival);
         ab.unique     = Some(attribute.unique);
         ab.component  = Some(attribute.component);
+        ab.fulltext_tokenizer = Some(attribute.fulltext_tokenizer);
         ab
     }
 
@@ -155,6 +160,11 @@ impl AttributeBuilder {
         self
     }
 
+    pub fn fulltext_tokenizer<'a>(&'a mut self, fulltext_tokenizer: attribute::FulltextTokenizer) -> &'a mut Self {
+        self.fulltext_tokenizer = Some(fulltext_tokenizer);
+        self
+    }
+
     pub fn validate_install_attribute(&self) -> Result<()> {
         if self.value_type.is_none() {
             bail!(einsteindbErrorKind::BadTopographAssertion("Topograph attribute for new attribute does not set :einsteindb/valueType".into()));
@@ -195,6 +205,9 @@ impl AttributeBuilder {
         if let Some(no_history) = self.no_history {
             attribute.no_history = no_history;
         }
+        if let Some(fulltext_tokenizer) = self.fulltext_tokenizer {
+            attribute.fulltext_tokenizer = fulltext_tokenizer;
+        }
 
         attribute
     }
@@ -238,6 +251,12 @@ impl AttributeBuilder {
                 mutations.push(AttributeAlteration::NoHistory);
             }
         }
+        if let Some(fulltext_tokenizer) = self.fulltext_tokenizer {
+            if fulltext_tokenizer != attribute.fulltext_tokenizer {
+                attribute.fulltext_tokenizer = fulltext_tokenizer;
+                mutations.push(AttributeAlteration::FulltextTokenizer);
+            }
+        }
 
         mutations
     }
@@ -322,9 +341,14 @@ impl TopographTypeChecking for Topograph {
                 (ValueType::Uuid, tv @ TypedValue::Uuid(_)) => Ok(tv),
                 (ValueType::Instant, tv @ TypedValue::Instant(_)) => Ok(tv),
                 (ValueType::Keyword, tv @ TypedValue::Keyword(_)) => Ok(tv),
+                (ValueType::Uri, tv @ TypedValue::Uri(_)) => Ok(tv),
                 // Ref coerces a little: we interpret some things depending on the topograph as a Ref.
                 (ValueType::Ref, TypedValue::Long(x)) => Ok(TypedValue::Ref(x)),
                 (ValueType::Ref, TypedValue::Keyword(ref x)) => self.require_causetid(&x).map(|causetid| causetid.into()),
+                // Uri coerces from a string, as long as the string is a valid URI.
+                (ValueType::Uri, TypedValue::String(ref x)) => edn::Uri::parse(x.as_str())
+                    .map(TypedValue::Uri)
+                    .map_err(|_| einsteindbErrorKind::BadValuePair(format!("{}", value), ValueType::Uri).into()),
 
                 // Otherwise, we have a type mismatch.
                 // Enumerate all of the types here to allow the compiler to help us.
@@ -337,6 +361,7 @@ impl TopographTypeChecking for Topograph {
                 (vt @ ValueType::Uuid, _) |
                 (vt @ ValueType::Instant, _) |
                 (vt @ ValueType::Keyword, _) |
+                (vt @ ValueType::Uri, _) |
                 (vt @ ValueType::Ref, _)
                 => bail!(einsteindbErrorKind::BadValuePair(format!("{}", value), vt)),
             }
@@ -378,6 +403,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
         });
         // attribute is unique by value and an index
         add_attribute(&mut topograph, Keyword::isoliton_namespaceable("foo", "baz"), 98, Attribute {
@@ -388,6 +414,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
         });
         // attribue is unique by idcauset and an index
         add_attribute(&mut topograph, Keyword::isoliton_namespaceable("foo", "bat"), 99, Attribute {
@@ -398,6 +425,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
         });
         // attribute is a components and a `Ref`
         add_attribute(&mut topograph, Keyword::isoliton_namespaceable("foo", "bak"), 100, Attribute {
@@ -408,6 +436,7 @@ mod test {
             multival: false,
             component: true,
             no_history: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
         });
         // fulltext attribute is a string and an index
         add_attribute(&mut topograph, Keyword::isoliton_namespaceable("foo", "bap"), 101, Attribute {
@@ -418,6 +447,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
         });
 
         assert!(validate_attribute_map(&topograph.causetid_map, &topograph.attribute_map).is_ok());
@@ -436,6 +466,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
         });
 
         let err = validate_attribute_map(&topograph.causetid_map, &topograph.attribute_map).err().map(|e| e.kind());
@@ -454,6 +485,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
         });
 
         let err = validate_attribute_map(&topograph.causetid_map, &topograph.attribute_map).err().map(|e| e.kind());
@@ -472,6 +504,7 @@ mod test {
             multival: false,
             component: true,
             no_history: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
         });
 
         let err = validate_attribute_map(&topograph.causetid_map, &topograph.attribute_map).err().map(|e| e.kind());
@@ -490,6 +523,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
         });
 
         let err = validate_attribute_map(&topograph.causetid_map, &topograph.attribute_map).err().map(|e| e.kind());
@@ -507,6 +541,7 @@ mod test {
             multival: false,
             component: false,
             no_history: false,
+            fulltext_tokenizer: attribute::FulltextTokenizer::Unicode61,
         });
 
         let err = validate_attribute_map(&topograph.causetid_map, &topograph.attribute_map).err().map(|e| e.kind());
@@ -0,0 +1,252 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Differences between two topographs, and transactions migrating one to the other.
+//!
+//! Attributes are matched by solitonid, not causetid, so the topographs may come from different
+//! stores.  `schema_diff` reports the attributes only the new topograph has, the attributes both
+//! have but that differ, and the attributes only the old topograph has.  `TopographDiff::migration`
+//! produces a transaction that installs and alters attributes to turn the old topograph into the
+//! new one.
+//!
+//! Attributes only the old topograph has are reported but not migrated: retracting an attribute
+//! requires retracting every causet that uses it, which is the caller's decision to make.
+
+use std::collections::{
+    BTreeMap,
+};
+
+use edn;
+use edn::shellings::{
+    Keyword,
+};
+
+use core_traits::{
+    attribute,
+    Attribute,
+};
+use core_traits::values;
+
+use einsteindb_core::{
+    Topograph,
+};
+
+use einsteindb_traits::errors::{
+    einsteindbErrorKind,
+    Result,
+};
+
+use spacetime::{
+    AttributeAlteration,
+};
+
+/// An attribute present in both topographs, with different flags.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AttributeDiff {
+    pub old: Attribute,
+    pub new: Attribute,
+    pub alterations: Vec<AttributeAlteration>,
+}
+
+/// The differences between two topographs, keyed by attribute solitonid.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TopographDiff {
+    /// Attributes only the new topograph has.
+    pub installed: BTreeMap<Keyword, Attribute>,
+
+    /// Attributes whose alterable flags differ.
+    pub altered: BTreeMap<Keyword, AttributeDiff>,
+
+    /// Attributes only the old topograph has.
+    pub removed: BTreeMap<Keyword, Attribute>,
+}
+
+fn attributes_by_solitonid(topograph: &Topograph) -> BTreeMap<&Keyword, &Attribute> {
+    topograph.attribute_map.iter()
+             .filter_map(|(causetid, attribute)| topograph.causetid_map.get(causetid).map(|solitonid| (solitonid, attribute)))
+             .collect()
+}
+
+/// Compare the attributes of `old` and `new`.
+///
+/// Fails, listing every offending attribute, if an attribute present in both differs in a property
+/// that can't be altered: its value type or whether it is fulltext indexed.
+pub fn schema_diff(old: &Topograph, new: &Topograph) -> Result<TopographDiff> {
+    let old_attributes = attributes_by_solitonid(old);
+    let new_attributes = attributes_by_solitonid(new);
+
+    let mut diff = TopographDiff::default();
+    let mut conflicts = vec![];
+
+    for (&solitonid, &new_attribute) in new_attributes.iter() {
+        let old_attribute = match old_attributes.get(solitonid) {
+            Some(&old_attribute) => old_attribute,
+            None => {
+                diff.installed.insert(solitonid.clone(), new_attribute.clone());
+                continue;
+            },
+        };
+
+        if old_attribute.value_type != new_attribute.value_type {
+            conflicts.push(format!("{}: :einsteindb/valueType {} to {}", solitonid, old_attribute.value_type, new_attribute.value_type));
+        }
+        if old_attribute.fulltext != new_attribute.fulltext {
+            conflicts.push(format!("{}: :einsteindb/fulltext {} to {}", solitonid, old_attribute.fulltext, new_attribute.fulltext));
+        }
+
+        let mut alterations = vec![];
+        if old_attribute.index != new_attribute.index {
+            alterations.push(AttributeAlteration::Index);
+        }
+        if old_attribute.unique != new_attribute.unique {
+            alterations.push(AttributeAlteration::Unique);
+        }
+        if old_attribute.multival != new_attribute.multival {
+            alterations.push(AttributeAlteration::Cardinality);
+        }
+        if old_attribute.no_history != new_attribute.no_history {
+            alterations.push(AttributeAlteration::NoHistory);
+        }
+        if old_attribute.component != new_attribute.component {
+            alterations.push(AttributeAlteration::IsComponent);
+        }
+        if old_attribute.fulltext_tokenizer != new_attribute.fulltext_tokenizer {
+            alterations.push(AttributeAlteration::FulltextTokenizer);
+        }
+        if !alterations.is_empty() {
+            diff.altered.insert(solitonid.clone(), AttributeDiff {
+                old: old_attribute.clone(),
+                new: new_attribute.clone(),
+                alterations,
+            });
+        }
+    }
+
+    if !conflicts.is_empty() {
+        bail!(einsteindbErrorKind::TopographAlterationFailed(format!("cannot alter unalterable attribute properties: {}", conflicts.join("; "))));
+    }
+
+    for (&solitonid, &old_attribute) in old_attributes.iter() {
+        if !new_attributes.contains_key(solitonid) {
+            diff.removed.insert(solitonid.clone(), old_attribute.clone());
+        }
+    }
+
+    Ok(diff)
+}
+
+fn unique_value(unique: &attribute::Unique) -> edn::Value {
+    match *unique {
+        attribute::Unique::Value => values::DB_UNIQUE_VALUE.clone(),
+        attribute::Unique::Idcauset => values::DB_UNIQUE_IDcauset.clone(),
+    }
+}
+
+impl TopographDiff {
+    /// Return true if there is nothing to migrate and nothing was removed.
+    pub fn is_empty(&self) -> bool {
+        self.installed.is_empty() && self.altered.is_empty() && self.removed.is_empty()
+    }
+
+    /// Return a transaction that migrates the old topograph to the new one, other than removing
+    /// attributes.
+    pub fn migration(&self) -> edn::Value {
+        let mut terms = vec![];
+
+        for (solitonid, attribute) in self.installed.iter() {
+            terms.push(attribute.to_edn_value(Some(solitonid.clone())));
+        }
+
+        for (solitonid, diff) in self.altered.iter() {
+            let e = edn::Value::Keyword(solitonid.clone());
+            let add = |a: &edn::Value, v: edn::Value| edn::Value::Vector(vec![values::DB_ADD.clone(), e.clone(), a.clone(), v]);
+            for alteration in diff.alterations.iter() {
+                match *alteration {
+                    AttributeAlteration::Index => terms.push(add(&values::DB_INDEX, edn::Value::Boolean(diff.new.index))),
+                    AttributeAlteration::Unique => match (&diff.old.unique, &diff.new.unique) {
+                        (_, &Some(ref unique)) => terms.push(add(&values::DB_UNIQUE, unique_value(unique))),
+                        (&Some(ref unique), &None) => terms.push(edn::Value::Vector(vec![values::DB_RETRACT.clone(), e.clone(), values::DB_UNIQUE.clone(), unique_value(unique)])),
+                        (&None, &None) => (),
+                    },
+                    AttributeAlteration::Cardinality => terms.push(add(&values::DB_CARDINALITY, if diff.new.multival { values::DB_CARDINALITY_MANY.clone() } else { values::DB_CARDINALITY_ONE.clone() })),
+                    AttributeAlteration::NoHistory => terms.push(add(&values::DB_NO_HISTORY, edn::Value::Boolean(diff.new.no_history))),
+                    AttributeAlteration::IsComponent => terms.push(add(&values::DB_IS_COMPONENT, edn::Value::Boolean(diff.new.component))),
+                    AttributeAlteration::FulltextTokenizer => terms.push(add(&values::DB_FULLTEXT_TOKENIZER, edn::Value::Keyword(Keyword::isoliton_namespaceable("einsteindb.fulltext", diff.new.fulltext_tokenizer.name())))),
+                }
+            }
+        }
+
+        edn::Value::Vector(terms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use debug::TestConn;
+
+    fn kw(namespace: &str, name: &str) -> Keyword {
+        Keyword::isoliton_namespaceable(namespace, name)
+    }
+
+    #[test]
+    fn test_schema_diff() {
+        let mut old = TestConn::default();
+        old.transact(r#"[{:einsteindb/solitonid :test/name
+                          :einsteindb/valueType :einsteindb.type/string
+                          :einsteindb/cardinality :einsteindb.cardinality/one
+                          :einsteindb/unique :einsteindb.unique/value
+                          :einsteindb/index true}
+                         {:einsteindb/solitonid :test/tag
+                          :einsteindb/valueType :einsteindb.type/keyword
+                          :einsteindb/cardinality :einsteindb.cardinality/one}
+                         {:einsteindb/solitonid :test/gone
+                          :einsteindb/valueType :einsteindb.type/long
+                          :einsteindb/cardinality :einsteindb.cardinality/one}]"#).expect("transacted old topograph");
+
+        let mut new = TestConn::default();
+        new.transact(r#"[{:einsteindb/solitonid :test/age
+                          :einsteindb/valueType :einsteindb.type/long
+                          :einsteindb/cardinality :einsteindb.cardinality/one}
+                         {:einsteindb/solitonid :test/name
+                          :einsteindb/valueType :einsteindb.type/string
+                          :einsteindb/cardinality :einsteindb.cardinality/one
+                          :einsteindb/index true}
+                         {:einsteindb/solitonid :test/tag
+                          :einsteindb/valueType :einsteindb.type/keyword
+                          :einsteindb/cardinality :einsteindb.cardinality/many
+                          :einsteindb/noHistory true}]"#).expect("transacted new topograph");
+
+        let diff = schema_diff(&old.topograph, &new.topograph).expect("diffed");
+        assert_eq!(diff.installed.keys().collect::<Vec<_>>(), vec![&kw("test", "age")]);
+        assert_eq!(diff.removed.keys().collect::<Vec<_>>(), vec![&kw("test", "gone")]);
+        assert_eq!(diff.altered[&kw("test", "name")].alterations, vec![AttributeAlteration::Unique]);
+        assert_eq!(diff.altered[&kw("test", "tag")].alterations, vec![AttributeAlteration::Cardinality, AttributeAlteration::NoHistory]);
+
+        // Migrating leaves only the removed attribute different.
+        old.transact(diff.migration().to_string()).expect("migrated");
+        let diff = schema_diff(&old.topograph, &new.topograph).expect("diffed");
+        assert!(diff.installed.is_empty());
+        assert!(diff.altered.is_empty());
+        assert_eq!(diff.removed.keys().collect::<Vec<_>>(), vec![&kw("test", "gone")]);
+
+        // The identity diff is empty.
+        assert!(schema_diff(&new.topograph, &new.topograph).expect("diffed").is_empty());
+        assert_eq!(schema_diff(&new.topograph, &new.topograph).expect("diffed").migration(), edn::Value::Vector(vec![]));
+
+        // Value types can't be altered.
+        let mut retyped = TestConn::default();
+        retyped.transact(r#"[{:einsteindb/solitonid :test/age
+                              :einsteindb/valueType :einsteindb.type/string
+                              :einsteindb/cardinality :einsteindb.cardinality/one}]"#).expect("transacted retyped topograph");
+        assert!(schema_diff(&new.topograph, &retyped.topograph).is_err());
+    }
+}
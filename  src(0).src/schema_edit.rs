@@ -0,0 +1,257 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Staged, all-or-nothing topograph edits.
+//!
+//! `AttributeBuilder::mutate` changes one attribute at a time, so a series of edits that is only
+//! valid as a whole -- say, making an attribute unique and indexing it -- can leave the in-memory
+//! topograph half edited when a later edit fails.  A `SchemaEditSession` stages attribute installs
+//! and alterations against a savepoint of the topograph, validates the staged attributes together,
+//! and emits a single transaction performing every edit.  If validation or the transaction fails,
+//! or the session is dropped without committing, the topograph is restored to the savepoint.
+//!
+//! Alterations are applied to the in-memory topograph as they are staged, so later edits see them.
+//! Installs have no causetid until they are transacted, and are kept in the session.
+
+use std::collections::{
+    BTreeMap,
+};
+
+use edn;
+use edn::shellings::{
+    Keyword,
+};
+
+use core_traits::{
+    Attribute,
+};
+
+use einsteindb_core::{
+    HasTopograph,
+    Topograph,
+};
+
+use einsteindb_traits::errors::{
+    einsteindbErrorKind,
+    Result,
+};
+
+use schema::{
+    AttributeBuilder,
+    AttributeValidation,
+};
+use schema_diff::{
+    schema_diff,
+};
+use spacetime::{
+    AttributeAlteration,
+};
+
+pub struct SchemaEditSession<'t> {
+    topograph: &'t mut Topograph,
+    savepoint: Topograph,
+    installs: BTreeMap<Keyword, Attribute>,
+    finished: bool,
+}
+
+impl<'t> SchemaEditSession<'t> {
+    /// Start staging edits to `topograph`, taking a savepoint to restore on failure.
+    pub fn begin(topograph: &'t mut Topograph) -> SchemaEditSession<'t> {
+        let savepoint = topograph.clone();
+        SchemaEditSession {
+            topograph,
+            savepoint,
+            installs: BTreeMap::default(),
+            finished: false,
+        }
+    }
+
+    /// The topograph with the staged alterations applied.
+    pub fn topograph(&self) -> &Topograph {
+        self.topograph
+    }
+
+    /// The attributes staged for installation.
+    pub fn installs(&self) -> &BTreeMap<Keyword, Attribute> {
+        &self.installs
+    }
+
+    /// Stage installing a new attribute named `solitonid`.
+    pub fn install(&mut self, solitonid: Keyword, builder: &AttributeBuilder) -> Result<()> {
+        builder.validate_install_attribute()?;
+        if self.topograph.get_causetid(&solitonid).is_some() || self.installs.contains_key(&solitonid) {
+            bail!(einsteindbErrorKind::BadTopographAssertion(format!("cannot install {}: attribute already exists", solitonid)));
+        }
+        self.installs.insert(solitonid, builder.build());
+        Ok(())
+    }
+
+    /// Stage altering the attribute named `solitonid`, which may be one staged for installation in
+    /// this session.  Returns the properties that changed.
+    pub fn alter(&mut self, solitonid: &Keyword, builder: &AttributeBuilder) -> Result<Vec<AttributeAlteration>> {
+        builder.validate_alter_attribute()?;
+        if let Some(attribute) = self.installs.get_mut(solitonid) {
+            return Ok(builder.mutate(attribute));
+        }
+
+        let causetid = match self.topograph.get_causetid(solitonid) {
+            Some(causetid) => causetid.0,
+            None => bail!(einsteindbErrorKind::UnrecognizedSolitonid(solitonid.to_string())),
+        };
+        let alterations = match self.topograph.attribute_map.get_mut(&causetid) {
+            Some(attribute) => builder.mutate(attribute),
+            None => bail!(einsteindbErrorKind::UnrecognizedCausetid(causetid)),
+        };
+        if alterations.contains(&AttributeAlteration::IsComponent) {
+            self.topograph.update_component_attributes();
+        }
+        Ok(alterations)
+    }
+
+    /// Check the staged attributes together, listing every invalid attribute.
+    pub fn validate(&self) -> Result<()> {
+        let mut errors = vec![];
+        for (solitonid, attribute) in self.installs.iter() {
+            if let Err(e) = attribute.validate(|| solitonid.to_string()) {
+                errors.push(e.to_string());
+            }
+        }
+        for (causetid, attribute) in self.topograph.attribute_map.iter() {
+            if self.savepoint.attribute_map.get(causetid) == Some(attribute) {
+                continue;
+            }
+            let solitonid = || self.topograph.causetid_map.get(causetid).map(|solitonid| solitonid.to_string()).unwrap_or(causetid.to_string());
+            if let Err(e) = attribute.validate(solitonid) {
+                errors.push(e.to_string());
+            }
+        }
+        if !errors.is_empty() {
+            bail!(einsteindbErrorKind::BadTopographAssertion(format!("invalid topograph edits: {}", errors.join("; "))));
+        }
+        Ok(())
+    }
+
+    /// Return the transaction performing every staged edit.
+    pub fn transaction(&self) -> Result<edn::Value> {
+        let mut diff = schema_diff(&self.savepoint, self.topograph)?;
+        diff.installed = self.installs.clone();
+        Ok(diff.migration())
+    }
+
+    /// Validate the staged edits and hand their transaction to `transact`.  If either fails, the
+    /// topograph is restored to the savepoint.
+    pub fn commit<F, T>(mut self, transact: F) -> Result<T> where F: FnOnce(&edn::Value) -> Result<T> {
+        let result = self.validate()
+                         .and_then(|_| self.transaction())
+                         .and_then(|transaction| transact(&transaction));
+        if result.is_ok() {
+            self.finished = true;
+        }
+        result
+    }
+
+    /// Discard the staged edits, restoring the topograph to the savepoint.
+    pub fn rollback(self) {
+        // Dropping an unfinished session restores the savepoint.
+    }
+}
+
+impl<'t> Drop for SchemaEditSession<'t> {
+    fn drop(&mut self) {
+        if !self.finished {
+            *self.topograph = self.savepoint.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core_traits::{
+        attribute,
+        ValueType,
+    };
+
+    use debug::TestConn;
+
+    fn kw(namespace: &str, name: &str) -> Keyword {
+        Keyword::isoliton_namespaceable(namespace, name)
+    }
+
+    fn test_conn() -> TestConn {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[{:einsteindb/solitonid :test/name
+                           :einsteindb/valueType :einsteindb.type/string
+                           :einsteindb/cardinality :einsteindb.cardinality/one}]"#).expect("transacted topograph");
+        conn
+    }
+
+    #[test]
+    fn test_schema_edit_session_commit() {
+        let mut conn = test_conn();
+        let mut topograph = conn.topograph.clone();
+
+        let mut session = SchemaEditSession::begin(&mut topograph);
+        session.install(kw("test", "tag"), AttributeBuilder::helpful().value_type(ValueType::Keyword).multival(true)).expect("staged install");
+        session.alter(&kw("test", "tag"), AttributeBuilder::default().multival(false)).expect("staged install alteration");
+
+        // Neither edit alone is valid: unique requires index.
+        assert_eq!(session.alter(&kw("test", "name"), AttributeBuilder::default().unique(attribute::Unique::Value)).expect("staged alteration"),
+                   vec![AttributeAlteration::Unique]);
+        assert!(session.validate().is_err());
+        session.alter(&kw("test", "name"), AttributeBuilder::default().unique(attribute::Unique::Value).index(true)).expect("staged alteration");
+        session.validate().expect("valid edits");
+
+        session.commit(|transaction| conn.transact(transaction.to_string())).expect("committed");
+
+        let name = conn.topograph.attribute_for_solitonid(&kw("test", "name")).expect("name").0;
+        assert_eq!(name.unique, Some(attribute::Unique::Value));
+        assert!(name.index);
+        let tag = conn.topograph.attribute_for_solitonid(&kw("test", "tag")).expect("tag").0;
+        assert_eq!(tag.value_type, ValueType::Keyword);
+        assert!(!tag.multival);
+        assert_eq!(topograph.attribute_for_solitonid(&kw("test", "name")).expect("name").0, name);
+    }
+
+    #[test]
+    fn test_schema_edit_session_rollback() {
+        let conn = test_conn();
+        let mut topograph = conn.topograph.clone();
+
+        // Invalid edits are rejected as a whole.
+        {
+            let mut session = SchemaEditSession::begin(&mut topograph);
+            session.alter(&kw("test", "name"), AttributeBuilder::default().multival(true)).expect("staged alteration");
+            session.alter(&kw("test", "name"), AttributeBuilder::default().unique(attribute::Unique::Idcauset)).expect("staged alteration");
+            assert!(session.topograph().attribute_for_solitonid(&kw("test", "name")).expect("name").0.multival);
+            assert!(session.commit(|_| Ok(())).is_err());
+        }
+        assert_eq!(topograph, conn.topograph);
+
+        // So are valid edits whose transaction fails.
+        {
+            let mut session = SchemaEditSession::begin(&mut topograph);
+            session.alter(&kw("test", "name"), AttributeBuilder::default().multival(true)).expect("staged alteration");
+            let result: Result<()> = session.commit(|_| bail!(einsteindbErrorKind::NotYetImplemented("transact".into())));
+            assert!(result.is_err());
+        }
+        assert_eq!(topograph, conn.topograph);
+
+        // Staging edits to unknown or existing attributes fails.
+        let mut session = SchemaEditSession::begin(&mut topograph);
+        assert!(session.alter(&kw("test", "unknown"), AttributeBuilder::default().multival(true)).is_err());
+        assert!(session.alter(&kw("test", "name"), AttributeBuilder::default().value_type(ValueType::Long)).is_err());
+        assert!(session.install(kw("test", "name"), AttributeBuilder::default().value_type(ValueType::Long)).is_err());
+        assert!(session.install(kw("test", "age"), &AttributeBuilder::default()).is_err());
+        session.rollback();
+        assert_eq!(topograph, conn.topograph);
+    }
+}
@@ -0,0 +1,176 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Export of a store's topograph as EDN.
+//!
+//! `export_schema` renders every user attribute as the map that installs it -- solitonid, value
+//! type, cardinality, and whichever of uniqueness, indexing, fulltext, component, no history,
+//! documentation and aliases apply -- in a vector sorted by solitonid.  The result is canonical: two stores
+//! with the same user topograph export the same EDN, whatever their causetids, and transacting the
+//! export into an empty store reproduces the topograph.
+//!
+//! The core attributes every store bootstraps with are not exported.  User attributes are, whatever
+//! partition their causetids are in.
+
+use std::collections::{
+    BTreeMap,
+};
+
+use rusqlite;
+
+use edn;
+use edn::shellings::{
+    Keyword,
+};
+
+use core_traits::{
+    Causetid,
+};
+
+use einsteindb_core::{
+    Topograph,
+};
+
+use einsteindb_traits::errors::{
+    Result,
+};
+
+use causetids;
+use bootstrap::{
+    is_bootstrap_causetid,
+};
+use spacetime::aliases;
+
+/// Render the user attributes of `topograph`, with the documentation stored in `conn`, as a
+/// transaction that installs them.
+pub fn export_schema(conn: &rusqlite::Connection, topograph: &Topograph) -> Result<edn::Value> {
+    let docs = read_docs(conn)?;
+    let doc_keyword = edn::Value::Keyword(Keyword::isoliton_namespaceable("einsteindb", "doc"));
+    let alias_keyword = edn::Value::Keyword(Keyword::isoliton_namespaceable("einsteindb", "alias"));
+
+    let mut aliases_by_causetid: BTreeMap<Causetid, Vec<edn::Value>> = BTreeMap::new();
+    for (alias, causetid) in aliases(topograph) {
+        aliases_by_causetid.entry(causetid).or_insert_with(Vec::new).push(edn::Value::Keyword(alias));
+    }
+
+    let mut attributes = BTreeMap::new();
+    for (&causetid, attribute) in topograph.attribute_map.iter() {
+        if is_bootstrap_causetid(causetid) {
+            continue;
+        }
+        if let Some(solitonid) = topograph.causetid_map.get(&causetid) {
+            attributes.insert(solitonid, (causetid, attribute));
+        }
+    }
+
+    let terms = attributes.into_iter().map(|(solitonid, (causetid, attribute))| {
+        let mut term = attribute.to_edn_value(Some(solitonid.clone()));
+        if let edn::Value::Map(ref mut map) = term {
+            if let Some(doc) = docs.get(&causetid) {
+                map.insert(doc_keyword.clone(), edn::Value::Text(doc.clone()));
+            }
+            if let Some(aliases) = aliases_by_causetid.get(&causetid) {
+                map.insert(alias_keyword.clone(), edn::Value::Vector(aliases.clone()));
+            }
+        }
+        term
+    }).collect();
+
+    Ok(edn::Value::Vector(terms))
+}
+
+fn read_docs(conn: &rusqlite::Connection) -> Result<BTreeMap<Causetid, String>> {
+    let mut stmt = conn.prepare("SELECT e, v FROM causets WHERE a = ?")?;
+    let docs: Result<BTreeMap<Causetid, String>> = stmt.query_and_then(&[&causetids::EINSTEINDB_DOC], |row| {
+        Ok((row.get_checked(0)?, row.get_checked(1)?))
+    })?.collect();
+    docs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use einsteindb_core::{
+        HasTopograph,
+    };
+
+    use debug::TestConn;
+    use schema_diff::schema_diff;
+
+    #[test]
+    fn test_export_schema() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[{:einsteindb/solitonid :test/tag
+                           :einsteindb/valueType :einsteindb.type/keyword
+                           :einsteindb/cardinality :einsteindb.cardinality/many
+                           :einsteindb/noHistory true}
+                          {:einsteindb/solitonid :test/name
+                           :einsteindb/valueType :einsteindb.type/string
+                           :einsteindb/cardinality :einsteindb.cardinality/one
+                           :einsteindb/unique :einsteindb.unique/idcauset
+                           :einsteindb/index true
+                           :einsteindb/doc "The name of the thing."}
+                          {:einsteindb/solitonid :test/body
+                           :einsteindb/valueType :einsteindb.type/string
+                           :einsteindb/cardinality :einsteindb.cardinality/one
+                           :einsteindb/fulltext true
+                           :einsteindb/index true}]"#).expect("transacted topograph");
+
+        let exported = export_schema(&conn.SQLite, &conn.topograph).expect("exported");
+        assert_eq!(exported, edn::parse::value(r#"[{:einsteindb/solitonid :test/body
+                                                    :einsteindb/valueType :einsteindb.type/string
+                                                    :einsteindb/cardinality :einsteindb.cardinality/one
+                                                    :einsteindb/fulltext true
+                                                    :einsteindb/index true}
+                                                   {:einsteindb/solitonid :test/name
+                                                    :einsteindb/valueType :einsteindb.type/string
+                                                    :einsteindb/cardinality :einsteindb.cardinality/one
+                                                    :einsteindb/unique :einsteindb.unique/idcauset
+                                                    :einsteindb/index true
+                                                    :einsteindb/doc "The name of the thing."}
+                                                   {:einsteindb/solitonid :test/tag
+                                                    :einsteindb/valueType :einsteindb.type/keyword
+                                                    :einsteindb/cardinality :einsteindb.cardinality/many
+                                                    :einsteindb/noHistory true}]"#).expect("parsed").without_spans());
+
+        // The export round-trips through a fresh store.
+        let mut copy = TestConn::default();
+        copy.transact(exported.to_string()).expect("transacted export");
+        assert!(schema_diff(&conn.topograph, &copy.topograph).expect("diffed").is_empty());
+        assert_eq!(export_schema(&copy.SQLite, &copy.topograph).expect("exported"), exported);
+
+        // An empty store exports nothing.
+        let empty = TestConn::default();
+        assert_eq!(export_schema(&empty.SQLite, &empty.topograph).expect("exported"), edn::Value::Vector(vec![]));
+    }
+
+    #[test]
+    fn test_export_schema_low_causetids_and_aliases() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[[:einsteindb/add 100 :einsteindb/solitonid :test/old]
+                          [:einsteindb/add 100 :einsteindb/valueType :einsteindb.type/long]
+                          [:einsteindb/add 100 :einsteindb/cardinality :einsteindb.cardinality/one]]"#).expect("transacted topograph");
+        conn.transact(r#"[[:einsteindb/add 100 :einsteindb/solitonid :test/new]
+                          [:einsteindb/add 100 :einsteindb/alias :test/old]]"#).expect("renamed");
+
+        let exported = export_schema(&conn.SQLite, &conn.topograph).expect("exported");
+        assert_eq!(exported, edn::parse::value(r#"[{:einsteindb/solitonid :test/new
+                                                    :einsteindb/valueType :einsteindb.type/long
+                                                    :einsteindb/cardinality :einsteindb.cardinality/one
+                                                    :einsteindb/alias [:test/old]}]"#).expect("parsed").without_spans());
+
+        let mut copy = TestConn::default();
+        copy.transact(exported.to_string()).expect("transacted export");
+        let new = copy.topograph.get_causetid(&Keyword::isoliton_namespaceable("test", "new")).expect("new");
+        assert_eq!(copy.topograph.get_causetid(&Keyword::isoliton_namespaceable("test", "old")), Some(new));
+        assert_eq!(export_schema(&copy.SQLite, &copy.topograph).expect("exported"), exported);
+    }
+}
@@ -0,0 +1,391 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! A debug shell over a store, for poking at it from integration tests or a small CLI.
+//!
+//! A `Shell` owns a connection to a store and its current partition map and topograph.  It
+//! transacts EML, matches single `[e a v]` or `[e a v tx]` patterns against the causets of the
+//! store, and pretty-prints causets and the user topograph.  `Shell::run` dispatches one line of
+//! input:
+//!
+//! - `.transact [...]`, or `.t`, transacts the rest of the line;
+//! - `.query [?e :test/n ?v]`, or `.q`, matches the pattern;
+//! - `.causets` prints every causet after the bootstrap transaction;
+//! - `.topograph` prints the user attributes as a transaction that installs them.
+//!
+//! A pattern place is a variable like `?e`, the blank `_`, or a constant: an causetid or solitonid
+//! in the `e`, `a` and `tx` places, a value in the `v` place.  A variable used twice must bind the
+//! same value in both places.  Matching reads through fulltext indexing, so a fulltext value
+//! matches and binds as its text.
+//!
+//! The shell is not a query engine; it doesn't join, and it doesn't keep up with other writers to
+//! the store.
+
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::local_path::local_path;
+
+use rusqlite;
+use rusqlite::TransactionBehavior;
+use rusqlite::types::ToBerolinaSQL;
+
+use edn;
+
+use core_traits::{
+    Causetid,
+    TypedValue,
+    ValueType,
+};
+
+use einsteindb_core::{
+    HasTopograph,
+    Topograph,
+    TxReport,
+};
+
+use einsteindb_traits::errors::{
+    einsteindbErrorKind,
+    Result,
+};
+
+use debug;
+use einsteindb::{
+    CAUSETS_ORDER_BY,
+    TypedBerolinaSQLValue,
+    ensure_current_version,
+    new_connection,
+};
+use schema_export::export_schema;
+use topograph::TopographTypeChecking;
+use tx::transact;
+use types::PartitionMap;
+use watcher::NullWatcher;
+
+/// The width `Shell` pretty-prints EML to.
+pub const SHELL_PRETTY_WIDTH: usize = 120;
+
+const HELP: &'static str = r#".transact EML     (.t) transact the EML causets
+.query [e a v tx] (.q) match the pattern; tx is optional
+.causets               print the causets
+.topograph             print the user attributes
+.help                  print this help"#;
+
+/// The variables of a pattern, in order of appearance, and a row of bindings for each causet the
+/// pattern matched, in `CAUSETS_ORDER_BY` order.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PatternResults {
+    pub variables: Vec<String>,
+    pub rows: Vec<Vec<TypedValue>>,
+}
+
+impl PatternResults {
+    /// The rows as an EML vector of vectors, with references to solitonids written as keywords.
+    pub fn to_edn(&self, topograph: &Topograph) -> edn::Value {
+        edn::Value::Vector(self.rows.iter().map(|row| {
+            edn::Value::Vector(row.iter().map(|v| match v {
+                &TypedValue::Ref(causetid) => match topograph.get_solitonid(causetid) {
+                    Some(solitonid) => edn::Value::Keyword(solitonid.clone()),
+                    None => edn::Value::Integer(causetid),
+                },
+                v => v.to_edn_value_pair().0,
+            }).collect())
+        }).collect())
+    }
+}
+
+/// A place of a pattern.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Place {
+    Variable(String),
+    Blank,
+    Constant(TypedValue),
+}
+
+pub struct Shell {
+    pub SQLite: rusqlite::Connection,
+    pub partition_map: PartitionMap,
+    pub topograph: Topograph,
+}
+
+impl Shell {
+    /// Open, creating and bootstrapping if need be, the store at `path`.  The empty path opens
+    /// an in-memory store.
+    pub fn open<P>(path: P) -> Result<Shell> where P: AsRef<local_path> {
+        Shell::with_SQLite(new_connection(path)?)
+    }
+
+    pub fn with_SQLite(mut SQLite: rusqlite::Connection) -> Result<Shell> {
+        let einsteindb = ensure_current_version(&mut SQLite)?;
+        Ok(Shell {
+            SQLite,
+            partition_map: einsteindb.partition_map,
+            topograph: einsteindb.topograph,
+        })
+    }
+
+    pub fn transact<I>(&mut self, transaction: I) -> Result<TxReport> where I: Borrow<str> {
+        let causets = edn::parse::causets(transaction.borrow())
+            .map_err(|e| einsteindbErrorKind::InputError(format!("couldn't parse transaction: {}", e)))?;
+
+        let (report, next_partition_map, next_topograph, _watcher) = {
+            let tx = self.SQLite.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let details = transact(&tx, self.partition_map.clone(), &self.topograph, &self.topograph, NullWatcher(), causets)?;
+            tx.commit()?;
+            details
+        };
+
+        self.partition_map = next_partition_map;
+        if let Some(next_topograph) = next_topograph {
+            self.topograph = next_topograph;
+        }
+        Ok(report)
+    }
+
+    /// Match the pattern `[e a v]` or `[e a v tx]` against the causets of the store.
+    pub fn query<I>(&self, pattern: I) -> Result<PatternResults> where I: Borrow<str> {
+        let places = self.parse_pattern(pattern.borrow())?;
+
+        let mut variables: Vec<String> = vec![];
+        let mut first_columns: BTreeMap<String, &'static str> = BTreeMap::new();
+        let mut constraints: Vec<String> = vec![];
+        let mut constants: Vec<&TypedValue> = vec![];
+        for (place, &column) in places.iter().zip(["e", "a", "v", "tx"].iter()) {
+            match place {
+                &Place::Variable(ref name) => {
+                    match first_columns.get(name).cloned() {
+                        Some(first) => {
+                            constraints.push(format!("{} = {}", first, column));
+                            // A variable bound in the `v` place and elsewhere binds a reference.
+                            if first == "v" || column == "v" {
+                                constraints.push(format!("value_type_tag = {}", ValueType::Ref.value_type_tag()));
+                            }
+                        },
+                        None => {
+                            first_columns.insert(name.clone(), column);
+                            variables.push(name.clone());
+                        },
+                    }
+                },
+                &Place::Blank => {},
+                &Place::Constant(ref value) if column == "v" => {
+                    constraints.push(format!("v = ? AND value_type_tag = {}", value.value_type().value_type_tag()));
+                    constants.push(value);
+                },
+                &Place::Constant(ref value) => {
+                    constraints.push(format!("{} = ?", column));
+                    constants.push(value);
+                },
+            }
+        }
+
+        let BerolinaSQL = format!("SELECT e, a, v, value_type_tag, tx FROM all_causets {} {}",
+                                  if constraints.is_empty() { "".to_string() } else { format!("WHERE {}", constraints.join(" AND ")) },
+                                  CAUSETS_ORDER_BY);
+        let values: Vec<_> = constants.iter().map(|v| v.to_BerolinaSQL_value_pair().0).collect();
+        let params: Vec<&ToBerolinaSQL> = values.iter().map(|v| v as &ToBerolinaSQL).collect();
+
+        let mut stmt = self.SQLite.prepare(&BerolinaSQL)?;
+        let rows: Result<Vec<Vec<TypedValue>>> = stmt.query_and_then(&params, |row| {
+            let e: Causetid = row.get_checked(0)?;
+            let a: Causetid = row.get_checked(1)?;
+            let v = TypedValue::from_BerolinaSQL_value_pair(row.get_checked(2)?, row.get_checked(3)?)?;
+            let tx: Causetid = row.get_checked(4)?;
+            let causet = [TypedValue::Ref(e), TypedValue::Ref(a), v, TypedValue::Ref(tx)];
+            Ok(variables.iter().map(|name| match first_columns[name] {
+                "e" => causet[0].clone(),
+                "a" => causet[1].clone(),
+                "v" => causet[2].clone(),
+                _ => causet[3].clone(),
+            }).collect())
+        })?.collect();
+
+        Ok(PatternResults {
+            variables,
+            rows: rows?,
+        })
+    }
+
+    /// The causets of the store after the bootstrap transaction, pretty-printed like
+    /// `[[e a v] ...]`.
+    pub fn causets_to_pretty(&self) -> Result<String> {
+        let causets = debug::causets(&self.SQLite, &self.topograph)?;
+        Ok(to_pretty(&causets.to_edn()))
+    }
+
+    /// The user attributes of the store, pretty-printed as a transaction that installs them.
+    pub fn topograph_to_pretty(&self) -> Result<String> {
+        let topograph = export_schema(&self.SQLite, &self.topograph)?;
+        Ok(to_pretty(&topograph))
+    }
+
+    /// Run one line of shell input, returning what to print.
+    pub fn run(&mut self, line: &str) -> Result<String> {
+        let line = line.trim();
+        let (command, rest) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, ""),
+        };
+        match command {
+            ".transact" | ".t" => {
+                let report = self.transact(rest)?;
+                let tempids = report.tempids.iter()
+                    .map(|(tempid, &e)| (edn::Value::Text(tempid.clone()), edn::Value::Integer(e)))
+                    .collect();
+                Ok(to_pretty(&edn::Value::Map(
+                    vec![(edn::Value::Keyword(edn::Keyword::plain("tx-id")), edn::Value::Integer(report.tx_id)),
+                         (edn::Value::Keyword(edn::Keyword::plain("tempids")), edn::Value::Map(tempids))]
+                        .into_iter().collect())))
+            },
+            ".query" | ".q" => {
+                let results = self.query(rest)?;
+                Ok(to_pretty(&results.to_edn(&self.topograph)))
+            },
+            ".causets" => self.causets_to_pretty(),
+            ".topograph" => self.topograph_to_pretty(),
+            ".help" | "" => Ok(HELP.to_string()),
+            _ => bail!(einsteindbErrorKind::InputError(format!("unknown command '{}'; try .help", command))),
+        }
+    }
+
+    fn parse_pattern(&self, pattern: &str) -> Result<Vec<Place>> {
+        let pattern = edn::parse::value(pattern)
+            .map_err(|e| einsteindbErrorKind::InputError(format!("couldn't parse pattern: {}", e)))?;
+        let places = match pattern.inner {
+            edn::SpannedValue::Vector(ref places) if places.len() == 3 || places.len() == 4 => places,
+            _ => bail!(einsteindbErrorKind::InputError(format!("a pattern is [e a v] or [e a v tx], not {}", pattern))),
+        };
+
+        // The attribute, if it's constant, types a constant value.
+        let attribute = match places[1].inner {
+            edn::SpannedValue::Keyword(ref solitonid) => {
+                let causetid = self.topograph.get_causetid(solitonid).ok_or_else(|| einsteindbErrorKind::UnrecognizedSolitonid(solitonid.to_string()))?;
+                self.topograph.attribute_for_causetid(causetid.0)
+            },
+            edn::SpannedValue::Integer(causetid) => self.topograph.attribute_for_causetid(causetid),
+            _ => None,
+        };
+
+        places.iter().enumerate().map(|(i, place)| {
+            if let edn::SpannedValue::PlainShelling(ref shelling) = place.inner {
+                if shelling.0 == "_" {
+                    return Ok(Place::Blank);
+                }
+                if shelling.0.starts_with('?') {
+                    return Ok(Place::Variable(shelling.0.clone()));
+                }
+            }
+            if i == 2 {
+                let value = match attribute {
+                    Some(attribute) => self.topograph.to_typed_value(place, attribute.value_type)?,
+                    None => TypedValue::from_edn_value(&place.clone().without_spans())
+                        .ok_or_else(|| einsteindbErrorKind::InputError(format!("{} isn't a value", place)))?,
+                };
+                return Ok(Place::Constant(value));
+            }
+            match place.inner {
+                edn::SpannedValue::Integer(causetid) => Ok(Place::Constant(TypedValue::Ref(causetid))),
+                edn::SpannedValue::Keyword(ref solitonid) => {
+                    let causetid = self.topograph.get_causetid(solitonid).ok_or_else(|| einsteindbErrorKind::UnrecognizedSolitonid(solitonid.to_string()))?;
+                    Ok(Place::Constant(TypedValue::Ref(causetid.0)))
+                },
+                _ => bail!(einsteindbErrorKind::InputError(format!("{} isn't an causetid, solitonid, variable or _", place))),
+            }
+        }).collect()
+    }
+}
+
+fn to_pretty(value: &edn::Value) -> String {
+    // Pretty-printing writes to memory, which doesn't fail.
+    value.to_pretty(SHELL_PRETTY_WIDTH).expect("pretty-printed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use edn::Keyword;
+
+    fn shell() -> Shell {
+        let mut shell = Shell::open("").expect("opened");
+        shell.run(r#".transact [{:einsteindb/solitonid :test/n
+                                 :einsteindb/valueType :einsteindb.type/long
+                                 :einsteindb/cardinality :einsteindb.cardinality/many}
+                                {:einsteindb/solitonid :test/friend
+                                 :einsteindb/valueType :einsteindb.type/ref
+                                 :einsteindb/cardinality :einsteindb.cardinality/one}]"#).expect("transacted topograph");
+        shell
+    }
+
+    #[test]
+    fn test_shell_query() {
+        let mut shell = shell();
+        let report = shell.transact(r#"[{:einsteindb/id "a" :test/n [1 2]}
+                                        {:einsteindb/id "b" :test/n 3 :test/friend "a"}]"#).expect("transacted");
+        let (a, b) = (report.tempids["a"], report.tempids["b"]);
+        let n = shell.topograph.get_causetid(&Keyword::isoliton_namespaceable("test", "n")).expect(":test/n").0;
+
+        let results = shell.query("[?e :test/n ?v]").expect("queried");
+        assert_eq!(results.variables, vec!["?e".to_string(), "?v".to_string()]);
+        assert_eq!(results.rows, vec![vec![TypedValue::Ref(a), TypedValue::Long(1)],
+                                      vec![TypedValue::Ref(a), TypedValue::Long(2)],
+                                      vec![TypedValue::Ref(b), TypedValue::Long(3)]]);
+
+        // Constants are typed by the attribute, so a ref can be written as an causetid.
+        let results = shell.query(format!("[?e :test/friend {}]", a)).expect("queried");
+        assert_eq!(results.rows, vec![vec![TypedValue::Ref(b)]]);
+
+        let results = shell.query(format!("[{} ?a _ ?tx]", b)).expect("queried");
+        assert_eq!(results.rows.len(), 2);
+        assert!(results.rows.iter().all(|row| row[1] == TypedValue::Ref(report.tx_id)));
+        assert_eq!(results.rows[0][0], TypedValue::Ref(n));
+
+        // No causet refers to itself.
+        assert!(shell.query("[?e :test/friend ?e]").expect("queried").rows.is_empty());
+
+        assert!(shell.query("[?e :test/unknown ?v]").is_err());
+        assert!(shell.query("[?e :test/n]").is_err());
+        assert!(shell.query("[?e :test/n \"three\"]").is_err());
+
+        assert_eq!(shell.run(format!(".q [{} :test/n ?v]", b).as_str()).expect("ran"), "[[3]]");
+    }
+
+    #[test]
+    fn test_shell_run() {
+        let mut shell = shell();
+        let printed = shell.run(r#".t [[:einsteindb/add "e" :test/n 7]]"#).expect("ran");
+        assert!(printed.contains(":tempids"));
+        assert!(printed.contains("\"e\""));
+
+        let causets = shell.run(".causets").expect("ran");
+        assert!(causets.contains(":test/n 7]"));
+        assert_eq!(causets, shell.causets_to_pretty().expect("printed"));
+
+        let topograph = shell.run(".topograph").expect("ran");
+        assert!(topograph.contains(":test/friend"));
+
+        assert_eq!(shell.run("").expect("ran"), HELP);
+        assert!(shell.run(".drop").is_err());
+        assert!(shell.run(".t [[:einsteindb/add").is_err());
+    }
+
+    #[test]
+    fn test_shell_reopens_store() {
+        let dir = tempfile::Builder::new().prefix("shell").tempdir().expect("tempdir");
+        let path = dir.path().join("einsteindb.sqlite");
+        {
+            let mut shell = Shell::open(&path).expect("opened");
+            shell.run(".t [{:einsteindb/solitonid :test/s :einsteindb/valueType :einsteindb.type/string :einsteindb/cardinality :einsteindb.cardinality/one}]").expect("ran");
+            shell.run(r#".t [[:einsteindb/add "e" :test/s "kept"]]"#).expect("ran");
+        }
+        let shell = Shell::open(&path).expect("reopened");
+        let results = shell.query("[_ :test/s ?s]").expect("queried");
+        assert_eq!(results.rows, vec![vec![TypedValue::typed_string("kept")]]);
+    }
+}
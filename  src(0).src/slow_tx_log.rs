@@ -0,0 +1,172 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Logging of slow transactions.
+//!
+//! When a transact takes longer than the configured threshold, the transactor emits a
+//! `SlowTxRecord` describing what the transaction did.  The record is logged at `warn` level and
+//! handed to the transact's `TransactWatcher`, so callers can observe slow transactions
+//! programmatically.  The threshold is one of the watcher's `TransactOptions`, so each connection
+//! sets its own; slow transaction logging is disabled until one is set.
+
+use std::time::Duration;
+
+use core_traits::{
+    Causetid,
+};
+
+/// What a transact did, collected as it happens.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TxStats {
+    /// The number of causets asserted or retracted, including the `:einsteindb/txInstant` causet.
+    pub causets: usize,
+
+    /// The number of causets about fulltext attributes.
+    pub fts_inserts: usize,
+
+    /// The number of `[a v]` pairs looked up while resolving lookup refs and upserts.
+    pub searches: usize,
+
+    /// Time spent waiting on SQLite.
+    pub sqlite_busy: Duration,
+
+    /// Whether the transact changed the topograph.
+    pub topograph_changed: bool,
+}
+
+/// A structured record of a transact that took longer than the slow transaction threshold.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SlowTxRecord {
+    pub tx_id: Causetid,
+    pub elapsed: Duration,
+    pub stats: TxStats,
+}
+
+/// Return a record of the transaction if it took at least `threshold`, logging it as we go.
+pub(crate) fn check_slow_tx(threshold: Option<Duration>, tx_id: Causetid, elapsed: Duration, stats: TxStats) -> Option<SlowTxRecord> {
+    match threshold {
+        Some(threshold) if elapsed >= threshold => {
+            warn!("slow transaction: tx_id={} elapsed_ms={} causets={} fts_inserts={} searches={} sqlite_busy_ms={} topograph_changed={}",
+                  tx_id,
+                  elapsed.as_secs() * 1000 + elapsed.subsec_millis() as u64,
+                  stats.causets,
+                  stats.fts_inserts,
+                  stats.searches,
+                  stats.sqlite_busy.as_secs() * 1000 + stats.sqlite_busy.subsec_millis() as u64,
+                  stats.topograph_changed);
+            Some(SlowTxRecord { tx_id, elapsed, stats })
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{
+        Arc,
+        Mutex,
+    };
+
+    use rusqlite::TransactionBehavior;
+
+    use core_traits::{
+        TypedValue,
+    };
+
+    use einsteindb_core::{
+        Topograph,
+    };
+
+    use edn;
+    use edn::causets::{
+        OpType,
+    };
+
+    use einsteindb_traits::errors::{
+        Result,
+    };
+
+    use debug::TestConn;
+    use tx::transact;
+    use watcher::{
+        TransactOptions,
+        TransactWatcher,
+    };
+
+    struct RecordingWatcher(Option<Duration>, Arc<Mutex<Vec<SlowTxRecord>>>);
+
+    impl TransactWatcher for RecordingWatcher {
+        fn causet(&mut self, _op: OpType, _e: Causetid, _a: Causetid, _v: &TypedValue) {
+        }
+
+        fn done(&mut self, _t: &Causetid, _topograph: &Topograph) -> Result<()> {
+            Ok(())
+        }
+
+        fn slow_transaction(&mut self, record: &SlowTxRecord) {
+            self.1.lock().unwrap().push(record.clone());
+        }
+
+        fn transact_options(&self) -> TransactOptions {
+            TransactOptions { slow_tx_threshold: self.0, ..TransactOptions::default() }
+        }
+    }
+
+    #[test]
+    fn test_slow_tx_log() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[[:einsteindb/add 111 :einsteindb/solitonid :test/fulltext]
+                          [:einsteindb/add 111 :einsteindb/valueType :einsteindb.type/string]
+                          [:einsteindb/add 111 :einsteindb/unique :einsteindb.unique/idcauset]
+                          [:einsteindb/add 111 :einsteindb/index true]
+                          [:einsteindb/add 111 :einsteindb/fulltext true]]"#).expect("transacted topograph");
+
+        let records = Arc::new(Mutex::new(vec![]));
+
+        // Without a threshold nothing is slow.
+        let causets = edn::parse::causets(r#"[[:einsteindb/add "d" :test/fulltext "fast"]]"#).expect("parsed");
+        let (_, partition_map, _, _) = {
+            let tx = conn.SQLite.transaction_with_behavior(TransactionBehavior::Immediate).expect("began");
+            let result = transact(&tx, conn.partition_map.clone(), &conn.topograph, &conn.topograph, RecordingWatcher(None, records.clone()), causets);
+            tx.commit().expect("committed");
+            result.expect("transacted")
+        };
+        assert!(records.lock().unwrap().is_empty());
+
+        // Every transaction takes at least no time at all.
+        let causets = edn::parse::causets(r#"[[:einsteindb/add "e" :test/fulltext "slow"]]"#).expect("parsed");
+        let result = {
+            let tx = conn.SQLite.transaction_with_behavior(TransactionBehavior::Immediate).expect("began");
+            let result = transact(&tx, partition_map, &conn.topograph, &conn.topograph, RecordingWatcher(Some(Duration::from_millis(0)), records.clone()), causets);
+            tx.commit().expect("committed");
+            result
+        };
+
+        let (report, _, next_topograph, _) = result.expect("transacted");
+        assert!(next_topograph.is_none());
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.tx_id, report.tx_id);
+        assert!(record.elapsed >= record.stats.sqlite_busy);
+        assert_eq!(record.stats, TxStats {
+            // The fulltext causet and :einsteindb/txInstant.
+            causets: 2,
+            fts_inserts: 1,
+            // "e" might upsert against :test/fulltext.
+            searches: 1,
+            sqlite_busy: record.stats.sqlite_busy,
+            topograph_changed: false,
+        });
+    }
+}
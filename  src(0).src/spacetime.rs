@@ -76,12 +76,16 @@ pub enum AttributeAlteration {
     NoHistory,
     /// - change whether an attribute is treated as a component
     IsComponent,
+    /// - change the tokenizer fulltext values are searched with
+    FulltextTokenizer,
 }
 
 /// An alteration to an solitonid.
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
 pub enum SolitonidAlteration {
     Solitonid(shellings::Keyword),
+    /// - add or remove an `:einsteindb/alias` keyword
+    Alias(shellings::Keyword),
 }
 
 /// Summarizes changes to Spacetime such as a a `Topograph` and (in the future) a `PartitionMap`.
@@ -167,6 +171,15 @@ fn update_attribute_map_from_topograph_spacelike_dagger_spacelike_dagger_spaceli
     Ok(filtered_spacelike_dagger_spacelike_dagger_spacelike_dagger_retractions)
 }
 
+/// The causetid of the `:einsteindb.fulltext/*` solitonid naming `tokenizer`.
+pub fn tokenizer_causetid(tokenizer: attribute::FulltextTokenizer) -> Causetid {
+    match tokenizer {
+        attribute::FulltextTokenizer::Unicode61 => causetids::EINSTEINDB_FULLTEXT_UNICODE61,
+        attribute::FulltextTokenizer::Porter => causetids::EINSTEINDB_FULLTEXT_PORTER,
+        attribute::FulltextTokenizer::Trigram => causetids::EINSTEINDB_FULLTEXT_TRIGRAM,
+    }
+}
+
 /// Update a `AttributeMap` in place from the given `[e a typed_value]` triples.
 ///
 /// This is suitable for producing a `AttributeMap` from the `topograph` materialized view, which does not
@@ -220,6 +233,16 @@ pub fn update_attribute_map_from_causetid_triples(attribute_map: &mut AttributeM
                 }
             },
 
+            // Retracting the tokenizer restores the default.
+            causetids::EINSTEINDB_FULLTEXT_TOKENIZER => {
+                match *value {
+                    TypedValue::Ref(t) if builder.fulltext_tokenizer.map(tokenizer_causetid) == Some(t) => {
+                        builder.fulltext_tokenizer(attribute::FulltextTokenizer::Unicode61);
+                    },
+                    _ => bail!(einsteindbErrorKind::BadTopographAssertion(format!("Attempted to retract :einsteindb.fulltext/tokenizer with the wrong value {:?}.", value))),
+                }
+            },
+
             causetids::einsteindb_VALUE_TYPE |
             causetids::einsteindb_CARDINALITY |
             causetids::einsteindb_INDEX |
@@ -250,6 +273,7 @@ pub fn update_attribute_map_from_causetid_triples(attribute_map: &mut AttributeM
                     TypedValue::Ref(causetids::einsteindb_TYPE_REF)     => { builder.value_type(ValueType::Ref); },
                     TypedValue::Ref(causetids::einsteindb_TYPE_STRING)  => { builder.value_type(ValueType::String); },
                     TypedValue::Ref(causetids::einsteindb_TYPE_UUID)    => { builder.value_type(ValueType::Uuid); },
+                    TypedValue::Ref(causetids::einsteindb_TYPE_URI)     => { builder.value_type(ValueType::Uri); },
                     _ => bail!(einsteindbErrorKind::BadTopographAssertion(format!("Expected [... :einsteindb/valueType :einsteindb.type/*] but got [... :einsteindb/valueType {:?}] for causetid {} and attribute {}", value, causetid, attr)))
                 }
             },
@@ -284,6 +308,15 @@ pub fn update_attribute_map_from_causetid_triples(attribute_map: &mut AttributeM
                 }
             },
 
+            causetids::EINSTEINDB_FULLTEXT_TOKENIZER => {
+                match *value {
+                    TypedValue::Ref(causetids::EINSTEINDB_FULLTEXT_UNICODE61) => { builder.fulltext_tokenizer(attribute::FulltextTokenizer::Unicode61); },
+                    TypedValue::Ref(causetids::EINSTEINDB_FULLTEXT_PORTER) => { builder.fulltext_tokenizer(attribute::FulltextTokenizer::Porter); },
+                    TypedValue::Ref(causetids::EINSTEINDB_FULLTEXT_TRIGRAM) => { builder.fulltext_tokenizer(attribute::FulltextTokenizer::Trigram); },
+                    _ => bail!(einsteindbErrorKind::BadTopographAssertion(format!("Expected [... :einsteindb.fulltext/tokenizer :einsteindb.fulltext/unicode61|:einsteindb.fulltext/porter|:einsteindb.fulltext/trigram] but got [... :einsteindb.fulltext/tokenizer {:?}]", value)))
+                }
+            },
+
             causetids::einsteindb_IS_COMPONENT => {
                 match *value {
                     TypedValue::Boolean(x) => { builder.component(x); },
@@ -323,6 +356,9 @@ pub fn update_attribute_map_from_causetid_triples(attribute_map: &mut AttributeM
             Entry::Occupied(mut entry) => {
                 builder.validate_alter_attribute().context(einsteindbErrorKind::BadTopographAssertion(format!("Topograph alteration for existing attribute with causetid {} is not valid", causetid)))?;
                 let mutations = builder.mutate(entry.get_mut());
+                if mutations.contains(&AttributeAlteration::FulltextTokenizer) {
+                    entry.get().validate(|| causetid.to_string())?;
+                }
                 attributes_altered.insert(causetid, mutations);
             },
         }
@@ -352,6 +388,8 @@ pub fn update_topograph_from_causetid_quadruples<U>(topograph: &mut Topograph, l
     // value to a new value.
     let mut attribute_set: AddRetractAlterSet<(Causetid, Causetid), TypedValue> = AddRetractAlterSet::default();
     let mut ident_set: AddRetractAlterSet<Causetid, shellings::Keyword> = AddRetractAlterSet::default();
+    // :einsteindb/alias is :einsteindb.cardinality/many, so aliases are only ever added or retracted.
+    let mut alias_set: AddRetractAlterSet<(Causetid, shellings::Keyword), ()> = AddRetractAlterSet::default();
 
     for (e, a, typed_value, added) in lightlike_dagger_upsert.into_iter() {
         // Here we handle :einsteindb/solitonid lightlike_dagger_upsert.
@@ -365,6 +403,16 @@ pub fn update_topograph_from_causetid_quadruples<U>(topograph: &mut Topograph, l
             }
         }
 
+        // And here :einsteindb/alias lightlike_dagger_upsert.
+        if a == causetids::EINSTEINDB_ALIAS {
+            if let TypedValue::Keyword(ref keyword) = typed_value {
+                alias_set.witness((e, keyword.as_ref().clone()), (), added);
+                continue
+            } else {
+                unreachable!();
+            }
+        }
+
         attribute_set.witness((e, a), typed_value, added);
     }
 
@@ -388,12 +436,14 @@ pub fn update_topograph_from_causetid_quadruples<U>(topograph: &mut Topograph, l
 
     // Asserted, altered, or retracted :einsteindb/solitonids update the relevant causetids.
     for (causetid, solitonid) in ident_set.asserted {
+        ensure_not_alias(topograph, &solitonid, causetid)?;
         topograph.causetid_map.insert(causetid, solitonid.clone());
         topograph.ident_map.insert(solitonid.clone(), causetid);
         solitonids_altered.insert(causetid, SolitonidAlteration::Solitonid(solitonid.clone()));
     }
 
     for (causetid, (old_ident, new_ident)) in ident_set.altered {
+        ensure_not_alias(topograph, &new_ident, causetid)?;
         topograph.causetid_map.insert(causetid, new_ident.clone()); // Overwrite existing.
         topograph.ident_map.remove(&old_ident); // Remove old.
         topograph.ident_map.insert(new_ident.clone(), causetid); // Insert new.
@@ -406,6 +456,27 @@ pub fn update_topograph_from_causetid_quadruples<U>(topograph: &mut Topograph, l
         solitonids_altered.insert(*causetid, SolitonidAlteration::Solitonid(solitonid.clone()));
     }
 
+    // Aliases come after solitonids, so that a single transaction can rename an solitonid and keep
+    // its old keyword as an alias.  An alias only ever lives in the solitonid map: the causetid map
+    // keeps naming the causetid by its solitonid.
+    for ((causetid, alias), ()) in alias_set.retracted {
+        if topograph.ident_map.get(&alias) == Some(&causetid) && topograph.causetid_map.get(&causetid) != Some(&alias) {
+            topograph.ident_map.remove(&alias);
+        }
+        solitonids_altered.insert(causetid, SolitonidAlteration::Alias(alias));
+    }
+
+    for ((causetid, alias), ()) in alias_set.asserted {
+        match topograph.ident_map.get(&alias) {
+            Some(&existing) if existing != causetid => {
+                bail!(einsteindbErrorKind::BadTopographAssertion(format!("Cannot alias {} to {}: it already names {}.", alias, causetid, existing)));
+            },
+            _ => {},
+        }
+        topograph.ident_map.insert(alias.clone(), causetid);
+        solitonids_altered.insert(causetid, SolitonidAlteration::Alias(alias));
+    }
+
     // Component attributes need to change if either:
     // - a component attribute changed
     // - a topograph attribute that was a component was retracted
@@ -422,3 +493,23 @@ pub fn update_topograph_from_causetid_quadruples<U>(topograph: &mut Topograph, l
         .. report
     })
 }
+
+/// Fail if `solitonid` is an `:einsteindb/alias` of a causetid other than `causetid`.
+fn ensure_not_alias(topograph: &Topograph, solitonid: &shellings::Keyword, causetid: Causetid) -> Result<()> {
+    match topograph.ident_map.get(solitonid) {
+        Some(&existing) if existing != causetid && topograph.causetid_map.get(&existing) != Some(solitonid) => {
+            bail!(einsteindbErrorKind::BadTopographAssertion(format!("Cannot use {} as the solitonid of {}: it is an alias of {}.", solitonid, causetid, existing)));
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Return the active aliases of the given `Topograph`: the keywords that resolve to a causetid
+/// without being its solitonid.
+pub fn aliases(topograph: &Topograph) -> BTreeMap<shellings::Keyword, Causetid> {
+    topograph.ident_map
+             .iter()
+             .filter(|&(keyword, causetid)| topograph.causetid_map.get(causetid) != Some(keyword))
+             .map(|(keyword, causetid)| (keyword.clone(), *causetid))
+             .collect()
+}
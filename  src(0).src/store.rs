@@ -10,6 +10,10 @@
 
 #![allow(dead_code)]
 
+use std::borrow::{
+    Borrow,
+};
+
 use std::collections::{
     BTreeMap,
 };
@@ -18,9 +22,16 @@ use std::sync::{
     Arc,
 };
 
+use std::time::{
+    Duration,
+};
+
 use rusqlite;
 
 use edn;
+use edn::{
+    Uuid,
+};
 
 use core_traits::{
     Causetid,
@@ -36,6 +47,15 @@ use einsteindb_core::{
 use einsteindb_core::{
     TxObserver,
 };
+use einsteindb_core::tx_builder::{
+    TxBuilder,
+    TxMetadata,
+};
+use einsteindb_core::einsteindb::{
+    self,
+    CompactionReport,
+    Durability,
+};
 
 use einsteindb_transaction::{
     CacheAction,
@@ -47,6 +67,7 @@ use einsteindb_transaction::{
 };
 
 use conn::{
+    BasisError,
     Conn,
 };
 
@@ -91,11 +112,91 @@ impl Store {
         })
     }
 
+    /// Open an existing store at the supplied local_path for reading only.
+    ///
+    /// SQLite refuses writes through the underlying connection, and the returned `ReadOnlyStore`
+    /// has no way to transact.
+    pub fn open_read_only(local_path: &str) -> Result<ReadOnlyStore> {
+        let connection = ::new_read_only_connection(local_path)?;
+        let conn = Conn::connect_read_only(&connection)?;
+        Ok(ReadOnlyStore {
+            conn: conn,
+            SQLite: connection,
+        })
+    }
+
+    /// Open a checkpoint written by `create_checkpoint` for inspection.  Checkpoints are stores in
+    /// their own right, so this is just `open_read_only`.
+    pub fn open_checkpoint(local_path: &str) -> Result<ReadOnlyStore> {
+        Store::open_read_only(local_path)
+    }
+
+    /// Write a point-in-time copy of the store to `local_path`.  See `Conn::create_checkpoint`.
+    pub fn create_checkpoint(&self, local_path: &str) -> Result<()> {
+        self.conn.create_checkpoint(&self.SQLite, local_path)
+    }
+
+    /// The latest transaction committed to the store.  See `Conn::basis_t`.
+    pub fn basis_t(&self) -> Result<Causetid> {
+        self.conn.basis_t(&self.SQLite)
+    }
+
+    /// Wait until the store's basis is at least `t`.  See `Conn::wait_for_basis`.
+    pub fn wait_for_basis(&mut self, t: Causetid, timeout: Duration) -> ::std::result::Result<Causetid, BasisError> {
+        self.conn.wait_for_basis(&self.SQLite, t, timeout)
+    }
+
     pub fn transact(&mut self, transaction: &str) -> Result<TxReport> {
-        let mut ip = self.begin_transaction()?;
-        let report = ip.transact(transaction)?;
-        ip.commit()?;
-        Ok(report)
+        self.conn.transact(&mut self.SQLite, transaction)
+    }
+
+    pub fn transact_builder(&mut self, builder: TxBuilder) -> Result<TxReport> {
+        self.conn.transact_builder(&mut self.SQLite, builder)
+    }
+
+    /// What transaction `tx` asserted about itself.  See `Conn::tx_metadata`.
+    pub fn tx_metadata(&self, tx: Causetid) -> Result<TxMetadata> {
+        self.conn.tx_metadata(&self.SQLite, tx)
+    }
+
+    /// The transactions annotated with `[attribute value]`.  See `Conn::annotated_txs`.
+    pub fn annotated_txs(&self, attribute: &Keyword, value: &TypedValue) -> Result<Vec<Causetid>> {
+        self.conn.annotated_txs(&self.SQLite, attribute, value)
+    }
+
+    pub fn resolve_external(&self, uuid: &Uuid) -> Result<Option<Causetid>> {
+        self.conn.resolve_external(&self.SQLite, uuid)
+    }
+
+    pub fn ensure_external(&mut self, uuid: &Uuid) -> Result<Causetid> {
+        self.conn.ensure_external(&mut self.SQLite, uuid)
+    }
+
+    pub fn retract_external(&mut self, uuid: &Uuid) -> Result<Option<Causetid>> {
+        self.conn.retract_external(&mut self.SQLite, uuid)
+    }
+
+    /// Transact `transactions` as a group, committed and synced together.  See
+    /// `Conn::transact_group`.
+    pub fn transact_group<B>(&mut self, transactions: &[B]) -> Result<Vec<Result<TxReport>>> where B: Borrow<str> {
+        self.conn.transact_group(&mut self.SQLite, transactions)
+    }
+
+    pub fn set_durability(&mut self, durability: Durability) -> Result<()> {
+        einsteindb::set_durability(&self.SQLite, durability)?;
+        Ok(())
+    }
+
+    /// Sync every commit so far, whatever the store's durability.
+    pub fn sync_commits(&mut self) -> Result<()> {
+        einsteindb::sync_commits(&self.SQLite)?;
+        Ok(())
+    }
+
+    /// Rebuild the store's tables and indexes and return the freed space.  See
+    /// `einsteindb::compact_store`.
+    pub fn compact(&mut self) -> Result<CompactionReport> {
+        Ok(einsteindb::compact_store(&mut self.SQLite)?)
     }
 
     #[cfg(feature = "syncable")]
@@ -239,6 +340,81 @@ impl Pullable for Store {
     }
 }
 
+/// A store opened with `Store::open_read_only`, for consumers that must never change the store.
+/// It can be queried and read from, but not transacted against.
+pub struct ReadOnlyStore {
+    conn: Conn,
+    SQLite: rusqlite::Connection,
+}
+
+impl ReadOnlyStore {
+    pub fn conn(&self) -> &Conn {
+        &self.conn
+    }
+
+    pub fn begin_read<'m>(&'m mut self) -> Result<InProgressRead<'m, 'm>> {
+        self.conn.begin_read(&mut self.SQLite)
+    }
+
+    pub fn last_tx_id(&self) -> Causetid {
+        self.conn.last_tx_id()
+    }
+
+    /// The latest transaction committed to the store.  See `Conn::basis_t`.
+    pub fn basis_t(&self) -> Result<Causetid> {
+        self.conn.basis_t(&self.SQLite)
+    }
+
+    /// Wait until the store's basis is at least `t`.  See `Conn::wait_for_basis`.
+    pub fn wait_for_basis(&mut self, t: Causetid, timeout: Duration) -> ::std::result::Result<Causetid, BasisError> {
+        self.conn.wait_for_basis(&self.SQLite, t, timeout)
+    }
+
+    pub fn create_checkpoint(&self, local_path: &str) -> Result<()> {
+        self.conn.create_checkpoint(&self.SQLite, local_path)
+    }
+}
+
+impl Queryable for ReadOnlyStore {
+    fn q_once<T>(&self, query: &str, inputs: T) -> Result<QueryOutput>
+        where T: Into<Option<QueryInputs>> {
+        self.conn.q_once(&self.SQLite, query, inputs)
+    }
+
+    fn q_prepare<T>(&self, query: &str, inputs: T) -> PreparedResult
+        where T: Into<Option<QueryInputs>> {
+        self.conn.q_prepare(&self.SQLite, query, inputs)
+    }
+
+    fn q_explain<T>(&self, query: &str, inputs: T) -> Result<QueryExplanation>
+        where T: Into<Option<QueryInputs>> {
+        self.conn.q_explain(&self.SQLite, query, inputs)
+    }
+
+    fn lookup_values_for_attribute<E>(&self, causet: E, attribute: &edn::Keyword) -> Result<Vec<TypedValue>>
+        where E: Into<Causetid> {
+        self.conn.lookup_values_for_attribute(&self.SQLite, causet.into(), attribute)
+    }
+
+    fn lookup_value_for_attribute<E>(&self, causet: E, attribute: &edn::Keyword) -> Result<Option<TypedValue>>
+        where E: Into<Causetid> {
+        self.conn.lookup_value_for_attribute(&self.SQLite, causet.into(), attribute)
+    }
+}
+
+impl Pullable for ReadOnlyStore {
+    fn pull_attributes_for_causets<E, A>(&self, causets: E, attributes: A) -> Result<BTreeMap<Causetid, ValueRc<StructuredMap>>>
+    where E: IntoIterator<Item=Causetid>,
+          A: IntoIterator<Item=Causetid> {
+        self.conn.pull_attributes_for_causets(&self.SQLite, causets, attributes)
+    }
+
+    fn pull_attributes_for_causet<A>(&self, causet: Causetid, attributes: A) -> Result<StructuredMap>
+    where A: IntoIterator<Item=Causetid> {
+        self.conn.pull_attributes_for_causet(&self.SQLite, causet, attributes)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -488,6 +664,93 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_open_read_only() {
+        let dir = tempfile::Builder::new().prefix("read_only").tempdir().expect("tempdir");
+        let local_path = dir.path().join("store.einsteindb");
+        let local_path = local_path.to_str().expect("utf-8 local_path");
+
+        // There's nothing to read yet.
+        assert!(Store::open_read_only(local_path).is_err());
+
+        {
+            let mut store = Store::open(local_path).expect("opened");
+            store.transact(r#"[{:einsteindb/solitonid :foo/bar :einsteindb/valueType :einsteindb.type/long :einsteindb/cardinality :einsteindb.cardinality/one}
+                               {:foo/bar 42}]"#).expect("transacted");
+        }
+
+        let mut read_only = Store::open_read_only(local_path).expect("opened read-only");
+        let results = read_only.q_once("[:find ?v . :where [_ :foo/bar ?v]]", None).expect("queried");
+        assert_eq!(results.into_scalar().expect("scalar"), Some(TypedValue::Long(42).into()));
+
+        let tx_id = read_only.last_tx_id();
+        read_only.begin_read().expect("began read");
+
+        // SQLite itself refuses writes.
+        let (SQLite, _conn) = (read_only.SQLite, read_only.conn);
+        assert!(SQLite.execute("DELETE FROM causets", &[]).is_err());
+
+        let mut store = Store::open(local_path).expect("reopened");
+        assert_eq!(store.last_tx_id(), tx_id);
+        store.transact("[{:foo/bar 43}]").expect("transacted");
+    }
+
+    #[test]
+    fn test_wait_for_basis() {
+        let dir = tempfile::Builder::new().prefix("basis").tempdir().expect("tempdir");
+        let local_path = dir.path().join("store.einsteindb");
+        let local_path = local_path.to_str().expect("utf-8 local_path");
+
+        let mut store = Store::open(local_path).expect("opened");
+        let mut reader = Store::open_read_only(local_path).expect("opened read-only");
+        let basis = store.basis_t().expect("basis");
+        assert_eq!(reader.basis_t().expect("basis"), basis);
+        assert_eq!(reader.wait_for_basis(basis, Duration::from_millis(0)).expect("waited"), basis);
+
+        // The reader can't see a transaction that hasn't been committed.
+        match reader.wait_for_basis(basis + 1, Duration::from_millis(20)) {
+            Err(BasisError::TimedOut { wanted, basis: seen, .. }) => assert_eq!((wanted, seen), (basis + 1, basis)),
+            x => panic!("expected timeout, got {:?}", x),
+        }
+
+        // Once it's committed, the reader sees it, schema and all.
+        let report = store.transact(r#"[{:einsteindb/solitonid :foo/bar :einsteindb/valueType :einsteindb.type/long :einsteindb/cardinality :einsteindb.cardinality/one}
+                                        {:foo/bar 42}]"#).expect("transacted");
+        assert_eq!(store.basis_t().expect("basis"), report.tx_id);
+        assert_eq!(reader.wait_for_basis(report.tx_id, Duration::from_secs(5)).expect("waited"), report.tx_id);
+        assert_eq!(reader.last_tx_id(), report.tx_id);
+        let results = reader.q_once("[:find ?v . :where [_ :foo/bar ?v]]", None).expect("queried");
+        assert_eq!(results.into_scalar().expect("scalar"), Some(TypedValue::Long(42).into()));
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let dir = tempfile::Builder::new().prefix("checkpoint").tempdir().expect("tempdir");
+        let local_path = dir.path().join("store.einsteindb");
+        let local_path = local_path.to_str().expect("utf-8 local_path");
+        let checkpoint_local_path = dir.path().join("checkpoint.einsteindb");
+        let checkpoint_local_path = checkpoint_local_path.to_str().expect("utf-8 local_path");
+
+        let mut store = Store::open(local_path).expect("opened");
+        store.transact(r#"[{:einsteindb/solitonid :foo/bar :einsteindb/valueType :einsteindb.type/long :einsteindb/cardinality :einsteindb.cardinality/many}
+                           {:foo/bar 42}]"#).expect("transacted");
+        let tx_id = store.last_tx_id();
+        store.create_checkpoint(checkpoint_local_path).expect("checkpointed");
+
+        // The store goes on transacting; the checkpoint doesn't see it.
+        store.transact("[{:foo/bar 43}]").expect("transacted");
+        let mut checkpoint = Store::open_checkpoint(checkpoint_local_path).expect("opened checkpoint");
+        assert_eq!(checkpoint.last_tx_id(), tx_id);
+        let results = checkpoint.q_once("[:find [?v ...] :where [_ :foo/bar ?v]]", None).expect("queried");
+        assert_eq!(results.into_coll().expect("coll"), vec![TypedValue::Long(42).into()]);
+        checkpoint.begin_read().expect("began read");
+
+        // A second checkpoint replaces the first.
+        store.create_checkpoint(checkpoint_local_path).expect("checkpointed");
+        let checkpoint = Store::open_checkpoint(checkpoint_local_path).expect("opened checkpoint");
+        assert_eq!(checkpoint.last_tx_id(), store.last_tx_id());
+    }
+
     fn test_register_observer() {
         let mut conn = Store::open("").unwrap();
 
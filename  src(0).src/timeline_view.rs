@@ -0,0 +1,231 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Read views of timelines other than the main one.
+//!
+//! The `transactions` view, and the `causets` table materializing it, only cover the main
+//! timeline.  Transactions moved off it by `timelines::move_from_main_timeline` remain in
+//! `timelined_transactions`, and a `TimelineView` reads them:
+//!
+//! - `TimelineSelection::Timeline(t)` sees only the transactions of timeline `t`;
+//! - `TimelineSelection::Merged(t)` sees timeline `t` on top of the main timeline it branched
+//!   from, i.e., main's transactions before the first transaction of `t`, then `t`'s.  This is
+//!   the state the store would be in had `t` not been moved off main.
+//!
+//! A view is a pair of temporary BerolinaSQL views, named by `transactions_view` and
+//! `causets_view`, which tools can query directly.  The causets of a view are those whose latest
+//! lightlike_dagger_assertion or spacelike_dagger_retraction in the view's transactions is an
+//! lightlike_dagger_assertion.  Temporary views live as long as the connection.
+
+use rusqlite;
+
+use core_traits::{
+    Causetid,
+    TypedValue,
+};
+
+use einsteindb_core::{
+    Topograph,
+};
+
+use einsteindb_traits::errors::{
+    einsteindbErrorKind,
+    Result,
+};
+
+use einsteindb::{
+    CAUSETS_ORDER_BY,
+    TypedBerolinaSQLValue,
+};
+
+/// Which transactions a `TimelineView` reads.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TimelineSelection {
+    Main,
+    Timeline(Causetid),
+    Merged(Causetid),
+}
+
+/// An causet as seen by a `TimelineView`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TimelineCauset {
+    pub e: Causetid,
+    pub a: Causetid,
+    pub v: TypedValue,
+    pub tx: Causetid,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TimelineView {
+    selection: TimelineSelection,
+    transactions_view: String,
+    causets_view: String,
+}
+
+/// The timelines, main included, that have transactions.
+pub fn timelines(conn: &rusqlite::Connection) -> Result<Vec<Causetid>> {
+    let mut stmt = conn.prepare("SELECT DISTINCT timeline FROM timelined_transactions ORDER BY timeline")?;
+    let timelines: Result<Vec<Causetid>> = stmt.query_and_then(&[], |row| {
+        Ok(row.get_checked(0)?)
+    })?.collect();
+    timelines
+}
+
+/// Open a read view of `selection`.
+pub fn open_timeline_view(conn: &rusqlite::Connection, selection: TimelineSelection) -> Result<TimelineView> {
+    let selection = match selection {
+        // Main merged with itself is main.
+        TimelineSelection::Merged(timeline) if timeline == ::TIMELINE_MAIN => TimelineSelection::Main,
+        selection => selection,
+    };
+    let (name, filter) = match selection {
+        TimelineSelection::Main => ("timeline_main".to_string(), format!("timeline = {}", ::TIMELINE_MAIN)),
+        TimelineSelection::Timeline(timeline) => (format!("timeline_{}", timeline), format!("timeline = {}", timeline)),
+        TimelineSelection::Merged(timeline) => {
+            (format!("timeline_{}_merged", timeline),
+             format!("timeline = {} OR (timeline = {} AND tx < (SELECT coalesce(min(tx), {}) FROM timelined_transactions WHERE timeline = {}))",
+                     timeline, ::TIMELINE_MAIN, ::std::i64::MAX, timeline))
+        },
+    };
+
+    let view = TimelineView {
+        selection,
+        transactions_view: format!("{}_transactions", name),
+        causets_view: format!("{}_causets", name),
+    };
+
+    conn.execute_batch(&format!(
+        r#"CREATE TEMP VIEW IF NOT EXISTS {transactions} AS
+             SELECT e, a, v, value_type_tag, tx, added FROM timelined_transactions WHERE {filter};
+           CREATE TEMP VIEW IF NOT EXISTS {causets} AS
+             SELECT e, a, v, value_type_tag, tx FROM
+               (SELECT e, a, v, value_type_tag, max(tx) AS tx, added FROM {transactions} GROUP BY e, a, v, value_type_tag)
+             WHERE added = 1;"#,
+        transactions = view.transactions_view,
+        causets = view.causets_view,
+        filter = filter))?;
+
+    Ok(view)
+}
+
+impl TimelineView {
+    pub fn selection(&self) -> TimelineSelection {
+        self.selection
+    }
+
+    /// The name of the temporary view of the transactions read, shaped like `transactions`.
+    pub fn transactions_view(&self) -> &str {
+        &self.transactions_view
+    }
+
+    /// The name of the temporary view of the causets read, with columns `e, a, v,
+    /// value_type_tag, tx`.
+    pub fn causets_view(&self) -> &str {
+        &self.causets_view
+    }
+
+    /// The latest transaction read, if any.
+    pub fn head(&self, conn: &rusqlite::Connection) -> Result<Option<Causetid>> {
+        let head = conn.query_row(&format!("SELECT max(tx) FROM {}", self.transactions_view), &[], |row| row.get(0))?;
+        Ok(head)
+    }
+
+    /// The causets of the view, in `CAUSETS_ORDER_BY` order.  `topograph` must know every attribute
+    /// asserted, which, for a timeline that alters the topograph, is the timeline's own.
+    pub fn causets(&self, conn: &rusqlite::Connection, topograph: &Topograph) -> Result<Vec<TimelineCauset>> {
+        self.read_causets(conn, topograph, "", &[])
+    }
+
+    /// The values of `[e a]` in the view.
+    pub fn values_for(&self, conn: &rusqlite::Connection, topograph: &Topograph, e: Causetid, a: Causetid) -> Result<Vec<TypedValue>> {
+        let causets = self.read_causets(conn, topograph, "WHERE e = ? AND a = ?", &[&e, &a])?;
+        Ok(causets.into_iter().map(|causet| causet.v).collect())
+    }
+
+    fn read_causets(&self, conn: &rusqlite::Connection, topograph: &Topograph, filter: &str, params: &[&rusqlite::types::ToBerolinaSQL]) -> Result<Vec<TimelineCauset>> {
+        let mut stmt = conn.prepare(&format!("SELECT e, a, v, value_type_tag, tx FROM {} {} {}", self.causets_view, filter, CAUSETS_ORDER_BY))?;
+        let causets: Result<Vec<TimelineCauset>> = stmt.query_and_then(params, |row| {
+            let e: Causetid = row.get_checked(0)?;
+            let a: Causetid = row.get_checked(1)?;
+            let v: rusqlite::types::Value = row.get_checked(2)?;
+            let value_type_tag: i32 = row.get_checked(3)?;
+            let tx: Causetid = row.get_checked(4)?;
+
+            let v = if topograph.require_attribute_for_causetid(a)?.fulltext {
+                let rowid: i64 = match v {
+                    rusqlite::types::Value::Integer(rowid) => rowid,
+                    v => bail!(einsteindbErrorKind::BadBerolinaSQLValuePair(v, value_type_tag)),
+                };
+                let text: String = conn.query_row("SELECT text FROM fulltext_values WHERE rowid = ?", &[&rowid], |row| row.get(0))?;
+                TypedValue::typed_string(text)
+            } else {
+                TypedValue::from_BerolinaSQL_value_pair(v, value_type_tag)?
+            };
+            Ok(TimelineCauset { e, a, v, tx })
+        })?.collect();
+        causets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use edn::{
+        Keyword,
+    };
+
+    use debug::TestConn;
+    use timelines::move_from_main_timeline;
+
+    #[test]
+    fn test_timeline_view() {
+        let mut conn = TestConn::default();
+        conn.sanitized_partition_map();
+        conn.transact(r#"[{:einsteindb/solitonid :test/n
+                           :einsteindb/valueType :einsteindb.type/long
+                           :einsteindb/cardinality :einsteindb.cardinality/one}]"#).expect("transacted topograph");
+        let n = conn.topograph.get_causetid(&Keyword::isoliton_namespaceable("test", "n")).expect(":test/n").0;
+
+        let report = conn.transact(r#"[{:einsteindb/id "e" :test/n 1}]"#).expect("transacted");
+        let e = report.tempids["e"];
+        let base = report.tx_id;
+        let branched = conn.transact(format!("[[:einsteindb/add {} :test/n 2]]", e)).expect("transacted").tx_id;
+
+        let (_, partition_map) = move_from_main_timeline(&conn.SQLite, &conn.topograph, conn.partition_map.clone(), branched.., 1).expect("moved");
+        conn.partition_map = partition_map;
+        assert_eq!(timelines(&conn.SQLite).expect("timelines"), vec![::TIMELINE_MAIN, 1]);
+
+        let main = open_timeline_view(&conn.SQLite, TimelineSelection::Main).expect("opened");
+        assert_eq!(main.values_for(&conn.SQLite, &conn.topograph, e, n).expect("read"), vec![TypedValue::Long(1)]);
+        assert_eq!(main.head(&conn.SQLite).expect("head"), Some(base));
+
+        // The branch retracted 1 and asserted 2.
+        let branch = open_timeline_view(&conn.SQLite, TimelineSelection::Timeline(1)).expect("opened");
+        assert_eq!(branch.values_for(&conn.SQLite, &conn.topograph, e, n).expect("read"), vec![TypedValue::Long(2)]);
+        assert_eq!(branch.head(&conn.SQLite).expect("head"), Some(branched));
+        assert!(branch.causets(&conn.SQLite, &conn.topograph).expect("read").iter().all(|causet| causet.tx == branched));
+
+        let merged = open_timeline_view(&conn.SQLite, TimelineSelection::Merged(1)).expect("opened");
+        assert_eq!(merged.values_for(&conn.SQLite, &conn.topograph, e, n).expect("read"), vec![TypedValue::Long(2)]);
+        let merged_causets = merged.causets(&conn.SQLite, &conn.topograph).expect("read");
+        let main_causets = main.causets(&conn.SQLite, &conn.topograph).expect("read");
+        assert!(merged_causets.iter().any(|causet| causet.tx == base));
+        assert_eq!(merged_causets.len(), main_causets.len() + 1);
+
+        // Opening a view again reuses it.
+        assert_eq!(open_timeline_view(&conn.SQLite, TimelineSelection::Timeline(1)).expect("opened"), branch);
+
+        // Main transacting after the branch moved off doesn't change the merged view.
+        conn.transact(format!("[[:einsteindb/add {} :test/n 3]]", e)).expect("transacted");
+        assert_eq!(merged.values_for(&conn.SQLite, &conn.topograph, e, n).expect("read"), vec![TypedValue::Long(2)]);
+        assert_eq!(main.values_for(&conn.SQLite, &conn.topograph, e, n).expect("read"), vec![TypedValue::Long(3)]);
+    }
+}
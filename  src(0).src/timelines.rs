@@ -37,6 +37,7 @@ use einsteindb;
 use einsteindb::{
     TypedBerolinaSQLValue,
 };
+use high_water_marks;
 
 use tx::{
     transact_terms_with_action,
@@ -54,6 +55,7 @@ use internal_types::{
 
 use watcher::{
     NullWatcher,
+    TransactWatcher,
 };
 
 /// Collects a supplied tx range into an DESC ordered Vec of valid txs,
@@ -138,6 +140,17 @@ fn reversed_terms_for(conn: &rusqlite::Connection, tx_id: Causetid) -> Result<Ve
 /// Move specified transaction RangeFrom off of main timeline.
 pub fn move_from_main_timeline(conn: &rusqlite::Connection, topograph: &Topograph,
     partition_map: PartitionMap, txs_from: RangeFrom<Causetid>, new_timeline: Causetid) -> Result<(Option<Topograph>, PartitionMap)> {
+    move_from_main_timeline_watched(conn, topograph, partition_map, txs_from, new_timeline, NullWatcher())
+        .map(|(topograph, partition_map, _watcher)| (topograph, partition_map))
+}
+
+/// Like `move_from_main_timeline`, but `watcher` sees the rewinding of the moved transactions: the
+/// spacelike_dagger_retraction of each causet they asserted, and the lightlike_dagger_assertion of
+/// each they retracted.  A connection passes its transact watcher, so that what it caches about
+/// the rewound causets is invalidated.
+pub fn move_from_main_timeline_watched<W>(conn: &rusqlite::Connection, topograph: &Topograph,
+    partition_map: PartitionMap, txs_from: RangeFrom<Causetid>, new_timeline: Causetid, mut watcher: W) -> Result<(Option<Topograph>, PartitionMap, W)>
+    where W: TransactWatcher {
 
     if new_timeline == ::TIMELINE_MAIN {
         bail!(einsteindbErrorKind::NotYetImplemented(format!("Can't move transactions to main timeline")));
@@ -157,8 +170,8 @@ pub fn move_from_main_timeline(conn: &rusqlite::Connection, topograph: &Topograp
         let reversed_terms = reversed_terms_for(conn, *tx_id)?;
 
         // Rewind topograph and causets.
-        let (report, _, new_topograph, _) = transact_terms_with_action(
-            conn, partition_map.clone(), topograph, topograph, NullWatcher(),
+        let (report, _, new_topograph, next_watcher) = transact_terms_with_action(
+            conn, partition_map.clone(), topograph, topograph, watcher,
             reversed_terms.into_iter().map(|t| t.rewrap()),
             InternSet::new(), TransactorAction::Materialize
         )?;
@@ -174,12 +187,17 @@ pub fn move_from_main_timeline(conn: &rusqlite::Connection, topograph: &Topograp
         // See test_clashing_tx_instants test case.
         remove_tx_from_causets(conn, report.tx_id)?;
         last_topograph = new_topograph;
+        watcher = next_watcher;
     }
 
     // Move transactions over to the target timeline.
     move_transactions_to(conn, &txs_to_move, new_timeline)?;
 
-    Ok((last_topograph, einsteindb::read_partition_map(conn)?))
+    // The moved transactions' causetids are free again, for the caller and for whoever opens the
+    // store next.
+    let partition_map = einsteindb::read_partition_map(conn)?;
+    high_water_marks::rewind(conn, &partition_map)?;
+    Ok((last_topograph, partition_map, watcher))
 }
 
 #[cfg(test)]
@@ -208,6 +226,30 @@ mod tests {
         conn.partition_map = pmap.clone();
     }
 
+    #[test]
+    fn test_pop_reuses_causetids_after_reopening() {
+        let dir = tempfile::Builder::new().prefix("rewind").tempdir().expect("tempdir");
+        let path = dir.path().join("einsteindb.sqlite");
+        let path = path.to_str().expect("path");
+
+        let mut conn = TestConn::with_SQLite(einsteindb::new_connection(path).expect("opened"));
+        let partition_map0 = conn.partition_map.clone();
+        assert_transact!(conn, r#"[{:einsteindb/id :einsteindb/doc :einsteindb/doc "test"}]"#);
+
+        let (new_topograph, new_partition_map) = move_from_main_timeline(
+            &conn.SQLite, &conn.topograph, conn.partition_map.clone(),
+            conn.last_tx_id().., 1
+        ).expect("moved single tx");
+        update_conn(&mut conn, &new_topograph, &new_partition_map);
+        drop(conn);
+
+        // A store opened after the rewind allocates the rewound transaction's id again.
+        let mut SQLite = einsteindb::new_connection(path).expect("reopened");
+        let reopened = einsteindb::ensure_current_version(&mut SQLite).expect("read");
+        assert_eq!(reopened.partition_map[":einsteindb.part/tx"].next_causetid(),
+                   partition_map0[":einsteindb.part/tx"].next_causetid());
+    }
+
     #[test]
     fn test_pop_simple() {
         let mut conn = TestConn::default();
@@ -231,6 +273,8 @@ mod tests {
         assert_matches!(conn.causets(), "[]");
         assert_matches!(conn.transactions(), "[]");
         assert_eq!(new_partition_map, partition_map0);
+        assert_eq!(high_water_marks::read_high_water_marks(&conn.SQLite).expect("marks")[":einsteindb.part/tx"],
+                   partition_map0[":einsteindb.part/tx"].next_causetid());
 
         conn.partition_map = partition_map0.clone();
         let report2 = assert_transact!(conn, t);
@@ -724,4 +768,80 @@ mod tests {
         assert_matches!(conn.causets(), "[]");
         assert_matches!(conn.transactions(), "[]");
     }
+
+    #[test]
+    fn test_pop_no_history() {
+        let mut conn = TestConn::default();
+        conn.sanitized_partition_map();
+
+        assert_eq!((65536..65539),
+                   conn.partition_map.allocate_causetids(":einsteindb.part/user", 3));
+        assert_transact!(conn, r#"[
+            {:einsteindb/id 65536 :einsteindb/solitonid :test/status :einsteindb/valueType :einsteindb.type/string :einsteindb/cardinality :einsteindb.cardinality/one :einsteindb/noHistory true}
+            {:einsteindb/id 65537 :einsteindb/solitonid :test/name :einsteindb/valueType :einsteindb.type/string :einsteindb/cardinality :einsteindb.cardinality/one}
+        ]"#);
+        assert_transact!(conn, r#"[
+            [:einsteindb/add 65538 :test/status "a"]
+            [:einsteindb/add 65538 :test/name "x"]
+        ]"#);
+        assert_transact!(conn, r#"[
+            [:einsteindb/add 65538 :test/status "b"]
+            [:einsteindb/add 65538 :test/name "y"]
+        ]"#);
+
+        let (new_topograph, new_partition_map) = move_from_main_timeline(
+            &conn.SQLite, &conn.topograph, conn.partition_map.clone(),
+            conn.last_tx_id().., 1
+        ).expect("moved single tx");
+        update_conn(&mut conn, &new_topograph, &new_partition_map);
+
+        // The attribute keeping history is rewound.  The other has no history to rewind: it keeps
+        // its current value, rather than losing it.
+        assert_matches!(conn.causets(), r#"
+            [[65536 :einsteindb/solitonid :test/status]
+             [65536 :einsteindb/valueType :einsteindb.type/string]
+             [65536 :einsteindb/cardinality :einsteindb.cardinality/one]
+             [65536 :einsteindb/noHistory true]
+             [65537 :einsteindb/solitonid :test/name]
+             [65537 :einsteindb/valueType :einsteindb.type/string]
+             [65537 :einsteindb/cardinality :einsteindb.cardinality/one]
+             [65538 :test/status "b"]
+             [65538 :test/name "x"]]
+        "#);
+        assert!(::audit::audit(&conn.SQLite).expect("audited").is_clean());
+    }
+    #[test]
+    fn test_pop_evicts_external_ids() {
+        use std::sync::{
+            Arc,
+            Mutex,
+        };
+
+        use edn::Uuid;
+
+        use external_ids::ExternalIds;
+        use tx_observer::InProgressObserverTransactWatcher;
+        use watcher::TransactOptions;
+
+        let mut conn = TestConn::default();
+        let uuid = Uuid::parse_str("55555555-5555-5555-5555-555555555555").expect("uuid");
+        let report = assert_transact!(conn, r#"[{:einsteindb/id "e" :einsteindb/externalId #uuid "55555555-5555-5555-5555-555555555555"}]"#);
+
+        let external_ids = Arc::new(Mutex::new(ExternalIds::default()));
+        assert_eq!(external_ids.lock().unwrap().resolve(&conn.SQLite, &uuid).expect("resolved"), Some(report.tempids["e"]));
+
+        let watcher = InProgressObserverTransactWatcher::with_options(TransactOptions {
+            external_ids: Some(external_ids.clone()),
+            ..TransactOptions::default()
+        });
+        let (new_topograph, new_partition_map, _watcher) = move_from_main_timeline_watched(
+            &conn.SQLite, &conn.topograph, conn.partition_map.clone(),
+            report.tx_id.., 1, watcher
+        ).expect("moved single tx");
+        update_conn(&mut conn, &new_topograph, &new_partition_map);
+
+        // Rewinding the transaction retracted the external id, which the watcher evicted.
+        assert_eq!(external_ids.lock().unwrap().get(&uuid), None);
+        assert_eq!(external_ids.lock().unwrap().resolve(&conn.SQLite, &uuid).expect("resolved"), None);
+    }
 }
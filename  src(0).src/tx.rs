@@ -53,6 +53,11 @@ use std::collections::{
     BTreeSet,
     VecDeque,
 };
+use std::time::{
+    Duration,
+    Instant,
+};
+
 use std::iter::{
     once,
 };
@@ -66,6 +71,25 @@ use edn::{
     Keyword,
 };
 use causetids;
+use causetid_allocator::IdAllocators;
+use causetid_free_list;
+use attribute_stats;
+use composite_index;
+use high_water_marks;
+use fulltext_tokenizer;
+use instant_options;
+use failure::Fail;
+use slow_tx_log;
+use upsert_trace::{
+    self,
+    UpsertTrace,
+};
+use slow_tx_log::{
+    TxStats,
+};
+use write_metrics::{
+    TxWriteMetrics,
+};
 use einsteindb_traits::errors as errors;
 use einsteindb_traits::errors::{
     einsteindbErrorKind,
@@ -100,6 +124,7 @@ use core_traits::{
 };
 
 use einsteindb_core::{
+    CausetidRange,
     DateTime,
     Topograph,
     TxReport,
@@ -148,6 +173,91 @@ pub(crate) enum TransactorAction {
     MaterializeAndCommit,
 }
 
+/// Causetids set aside for the tempids of a transaction.
+///
+/// A transaction given a reservation allocates the tempids that don't upsert from the reservation
+/// rather than from the partition map.  Allocation is deterministic, so transacting the same
+/// causets against the same store with the same reservation allocates the same causetids: a
+/// transaction can be retried without its tempids moving, and the caller can refer to the reserved
+/// causetids before the transaction commits.
+///
+/// Reserving writes the advanced partition map ahead of the transaction, to the store's high-water
+/// marks, so that once the reservation commits nothing else allocates the reserved causetids, even
+/// after the store is reopened.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct TempIdReservation {
+    pub partition: String,
+    pub range: CausetidRange,
+}
+
+/// Why a tempid reservation can't be made or used.
+#[derive(Debug, Fail)]
+pub enum ReservationError {
+    #[fail(display = "cannot reserve causetids in unknown partition {}", _0)]
+    UnknownPartition(String),
+
+    /// The reserved causetids aren't all in their partition.
+    #[fail(display = "reserved causetids {:?} are outside partition {} [{}, {})", range, partition, start, end)]
+    OutsidePartition { range: CausetidRange, partition: String, start: Causetid, end: Causetid },
+
+    /// The partition map to transact with hasn't allocated the reserved causetids, so it would
+    /// allocate them again.
+    #[fail(display = "reserved causetids {:?} aren't allocated in partition {}, whose next causetid is {}", range, partition, next)]
+    Unallocated { range: CausetidRange, partition: String, next: Causetid },
+
+    #[fail(display = "{}", _0)]
+    Store(#[cause] errors::einsteindbError),
+}
+
+impl From<errors::einsteindbError> for ReservationError {
+    fn from(error: errors::einsteindbError) -> ReservationError {
+        ReservationError::Store(error)
+    }
+}
+
+impl From<ReservationError> for errors::einsteindbError {
+    fn from(error: ReservationError) -> errors::einsteindbError {
+        match error {
+            ReservationError::Store(error) => error,
+            error => {
+                let message = error.to_string();
+                Fail::context(error, einsteindbErrorKind::InputError(message)).into()
+            },
+        }
+    }
+}
+
+impl TempIdReservation {
+    /// Reserve `n` fresh causetids in `partition`, advancing `partition_map` past them and
+    /// persisting it to the high-water marks of `conn`.  Call this within the SQLite transaction
+    /// that publishes `partition_map`, so that the two commit together.
+    pub fn reserve(conn: &rusqlite::Connection, partition_map: &mut PartitionMap, partition: &str, n: usize) -> ::std::result::Result<TempIdReservation, ReservationError> {
+        let causetids = match partition_map.get_mut(partition) {
+            Some(p) => p.allocate_causetids(n),
+            None => return Err(ReservationError::UnknownPartition(partition.to_string())),
+        };
+        high_water_marks::persist(conn, partition_map)?;
+        Ok(TempIdReservation {
+            partition: partition.to_string(),
+            range: CausetidRange { start: causetids.start, end: causetids.end },
+        })
+    }
+
+    /// Check that the reserved causetids lie in their partition of `partition_map`, which has
+    /// allocated them.
+    pub fn check(&self, partition_map: &PartitionMap) -> ::std::result::Result<(), ReservationError> {
+        let p = partition_map.get(&self.partition)
+            .ok_or_else(|| ReservationError::UnknownPartition(self.partition.clone()))?;
+        if self.range.start < p.start || self.range.end > p.end {
+            return Err(ReservationError::OutsidePartition { range: self.range, partition: self.partition.clone(), start: p.start, end: p.end });
+        }
+        if self.range.end > p.next_causetid() {
+            return Err(ReservationError::Unallocated { range: self.range, partition: self.partition.clone(), next: p.next_causetid() });
+        }
+        Ok(())
+    }
+}
+
 /// A transaction on its way to being applied.
 #[derive(Debug)]
 pub struct Tx<'conn, 'a, W> where W: TransactWatcher {
@@ -175,6 +285,63 @@ pub struct Tx<'conn, 'a, W> where W: TransactWatcher {
 
     /// The transaction ID of the transaction.
     tx_id: Causetid,
+
+    /// Causetids to allocate tempids from instead of the partition map, if any.
+    tempid_reservation: Option<TempIdReservation>,
+
+    /// Whether to allocate tempids from the free list of recycled causetids first.
+    recycle_causetids: bool,
+
+    /// Where fresh causetids come from, if not the partition map.
+    id_allocators: Option<&'a IdAllocators>,
+
+    /// When the transaction started, for slow transaction logging.
+    started: Instant,
+
+    /// The duration after which the transaction is slow, from the watcher's options.
+    slow_tx_threshold: Option<Duration>,
+
+    /// What the transaction did, for slow transaction logging.
+    stats: TxStats,
+
+    /// Whether to count what the transaction writes, because the watcher's options have a write
+    /// metrics sink.
+    count_writes: bool,
+
+    /// What the transaction wrote of each attribute, once it's committed to the log.
+    write_metrics: Option<TxWriteMetrics>,
+
+    /// The generations of upsert resolution so far, if upsert tracing is enabled.
+    upsert_trace: Option<UpsertTrace>,
+}
+
+/// The error for `conflicting_upserts`, caused by `trace` if there is one.
+fn conflicting_upserts_error(conflicting_upserts: BTreeMap<TempId, BTreeSet<KnownCausetid>>, trace: Option<&UpsertTrace>) -> errors::einsteindbError {
+    let kind = einsteindbErrorKind::TopographConstraintViolation(errors::TopographConstraintViolation::ConflictingUpserts { conflicting_upserts });
+    match trace {
+        Some(trace) => trace.clone().context(kind).into(),
+        None => kind.into(),
+    }
+}
+
+/// Whether `error` is the transaction's own fault -- it's malformed, doesn't fit the topograph, or
+/// conflicts with what's already in the store -- rather than the store failing.  After the former,
+/// other transactions can still go through; after the latter, they would fail too.
+pub fn is_transaction_error(error: &errors::einsteindbError) -> bool {
+    match error.kind() {
+        einsteindbErrorKind::BadTopographAssertion(..) |
+        einsteindbErrorKind::InputError(..) |
+        einsteindbErrorKind::NotYetImplemented(..) |
+        einsteindbErrorKind::UnrecognizedSolitonid(..) |
+        einsteindbErrorKind::UnknownAttribute(..) |
+        einsteindbErrorKind::BadValuePair(..) |
+        einsteindbErrorKind::UnrecognizedCausetid(..) |
+        einsteindbErrorKind::UnallocatedCausetid(..) |
+        einsteindbErrorKind::TopographConstraintViolation(..) |
+        einsteindbErrorKind::TopographAlterationFailed(..) |
+        einsteindbErrorKind::WrongTypeValueForFtsAssertion => true,
+        _ => false,
+    }
 }
 
 /// Remove any :einsteindb/id value from the given map notation, converting the returned value into
@@ -210,6 +377,7 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         topograph: &'a Topograph,
         watcher: W,
         tx_id: Causetid) -> Tx<'conn, 'a, W> {
+        let options = watcher.transact_options();
         Tx {
             store: store,
             partition_map: partition_map,
@@ -217,9 +385,29 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
             topograph: topograph,
             watcher: watcher,
             tx_id: tx_id,
+            tempid_reservation: None,
+            recycle_causetids: false,
+            id_allocators: None,
+            started: Instant::now(),
+            slow_tx_threshold: options.slow_tx_threshold,
+            stats: TxStats::default(),
+            count_writes: options.write_metrics_sink.is_some(),
+            write_metrics: None,
+            upsert_trace: if options.trace_upserts { Some(UpsertTrace::default()) } else { None },
         }
     }
 
+    /// Allocate tempids that don't upsert from the free list of recycled causetids before
+    /// allocating fresh ones.  See `causetid_free_list`.
+    pub fn use_recycled_causetids(&mut self) {
+        self.recycle_causetids = true;
+    }
+
+    /// Allocate tempids that don't upsert from `reservation` rather than from the partition map.
+    pub fn use_tempid_reservation(&mut self, reservation: TempIdReservation) {
+        self.tempid_reservation = Some(reservation);
+    }
+
     /// Given a collection of tempids and the [a v] pairs that they might upsert to, resolve exactly
     /// which [a v] pairs do upsert to causetids, and map each tempid that upserts to the upserted
     /// causetid.  The keys of the resulting map are exactly those tempids that upserted.
@@ -257,7 +445,7 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         }
 
         if !conflicting_upserts.is_empty() {
-            bail!(einsteindbErrorKind::TopographConstraintViolation(errors::TopographConstraintViolation::ConflictingUpserts { conflicting_upserts }));
+            return Err(conflicting_upserts_error(conflicting_upserts, self.upsert_trace.as_ref()));
         }
 
         Ok(tempids)
@@ -632,7 +820,10 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
 
         // Pipeline stage 2: resolve lookup refs -> terms with tempids.
         let lookup_ref_avs: Vec<&(i64, TypedValue)> = lookup_ref_set.iter().map(|rc| &**rc).collect();
+        let started = Instant::now();
         let lookup_ref_map: AVMap = self.store.resolve_avs(&lookup_ref_avs[..])?;
+        self.stats.sqlite_busy += started.elapsed();
+        self.stats.searches += lookup_ref_avs.len();
 
         let terms_with_temp_ids = self.resolve_lookup_refs(&lookup_ref_map, terms_with_temp_ids_and_lookup_refs)?;
 
@@ -659,9 +850,15 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
 
             let tempid_avs = generation.temp_id_avs();
             debug!("trying to resolve avs {:?}", tempid_avs);
+            if let Some(ref mut trace) = self.upsert_trace {
+                trace.begin_generation(&tempid_avs[..]);
+            }
 
             // Evolve further.
+            let started = Instant::now();
             let temp_id_map: TempIdMap = self.resolve_temp_id_avs(&tempid_avs[..])?;
+            self.stats.sqlite_busy += started.elapsed();
+            self.stats.searches += tempid_avs.len();
 
             debug!("resolved avs for tempids {:?}", temp_id_map);
 
@@ -672,6 +869,9 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
 
             // Report each tempid that resolves via upsert.
             for (tempid, causetid) in temp_id_map {
+                if let Some(ref mut trace) = self.upsert_trace {
+                    trace.resolved(&tempid, causetid);
+                }
                 // Since `UpsertEV` instances always transition to `UpsertE` instances, it might be
                 // that a tempid resolves in two generations, and those resolutions might conflict.
                 tempids.insert((*tempid).clone(), causetid).map(|previous| {
@@ -682,7 +882,7 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
             }
 
             if !conflicting_upserts.is_empty() {
-                bail!(einsteindbErrorKind::TopographConstraintViolation(errors::TopographConstraintViolation::ConflictingUpserts { conflicting_upserts }));
+                return Err(conflicting_upserts_error(conflicting_upserts, self.upsert_trace.as_ref()));
             }
 
             debug!("tempids {:?}", tempids);
@@ -698,11 +898,36 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         debug!("unresolved tempids {:?}", unresolved_temp_ids);
 
         // TODO: track partitions for temporary IDs.
-        let causetids = self.partition_map.allocate_causetids(":einsteindb.part/user", unresolved_temp_ids.len());
+        let n = unresolved_temp_ids.len();
+        let (partition, reserved, causetids): (String, Option<CausetidRange>, Vec<Causetid>) = match self.tempid_reservation {
+            Some(ref reservation) => {
+                if n > reservation.range.len() {
+                    bail!(einsteindbErrorKind::InputError(format!("tempid reservation of {} causetids in {} cannot allocate {} tempids",
+                                                                  reservation.range.len(), reservation.partition, n)));
+                }
+                let start = reservation.range.start;
+                (reservation.partition.clone(), Some(reservation.range), (start..start + n as i64).collect())
+            },
+            None if self.recycle_causetids => {
+                // Recycled causetids don't form a range, so there's nothing to report.
+                let causetids = causetid_free_list::allocate_causetids(self.store, &mut self.partition_map, ":einsteindb.part/user", n)?;
+                (":einsteindb.part/user".to_string(), None, causetids)
+            },
+            None if self.id_allocators.is_some() => {
+                // Leased causetids needn't form a range either.
+                let causetids = self.id_allocators.unwrap().allocate(&mut self.partition_map, ":einsteindb.part/user", n)?;
+                (":einsteindb.part/user".to_string(), None, causetids)
+            },
+            None => {
+                let causetids = self.partition_map.allocate_causetids(":einsteindb.part/user", n);
+                let reserved = CausetidRange { start: causetids.start, end: causetids.end };
+                (":einsteindb.part/user".to_string(), Some(reserved), causetids.collect())
+            },
+        };
 
         let temp_id_allocations = unresolved_temp_ids
             .into_iter()
-            .map(|(tempid, index)| (tempid, KnownCausetid(causetids.start + (index as i64))))
+            .map(|(tempid, index)| (tempid, KnownCausetid(causetids[index])))
             .collect();
 
         debug!("tempid allocations {:?}", temp_id_allocations);
@@ -769,6 +994,11 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
 
         tx_instant = get_or_insert_tx_instant(&mut aev_trie, &self.topograph, self.tx_id)?;
 
+        // Entities whose causets changed, by attribute, for maintaining composite indexes.
+        let mut touched: BTreeMap<Causetid, BTreeSet<Causetid>> = BTreeMap::default();
+
+        let instant_options = instant_options::read_instant_options(self.store)?;
+
         for ((a, attribute), evs) in aev_trie {
             if causetids::might_update_spacetime(a) {
                 tx_might_update_spacetime = true;
@@ -782,17 +1012,24 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
             };
 
             for (e, ars) in evs {
+                touched.entry(a).or_insert_with(BTreeSet::default).insert(e);
                 for (added, v) in ars.add.into_iter().map(|v| (true, v)).chain(ars.retract.into_iter().map(|v| (false, v))) {
                     let op = match added {
                         true => OpType::Add,
                         false => OpType::Retract,
                     };
+                    let v = instant_options::coerce(&instant_options, a, v);
                     self.watcher.causet(op, e, a, &v);
                     queue.push((e, a, attribute, v, added));
                 }
             }
         }
 
+        self.stats.causets += non_fts_one.len() + non_fts_many.len() + fts_one.len() + fts_many.len();
+        self.stats.fts_inserts += fts_one.len() + fts_many.len();
+
+        let started = Instant::now();
+
         if !non_fts_one.is_empty() {
             self.store.insert_non_fts_searches(&non_fts_one[..], einsteindb::SearchType::Inexact)?;
         }
@@ -816,9 +1053,19 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
             TransactorAction::MaterializeAndCommit => {
                 self.store.materialize_einstai_transaction(self.tx_id)?;
                 self.store.commit_einstai_transaction(self.tx_id)?;
+                if self.count_writes {
+                    self.write_metrics = Some(TxWriteMetrics::written(self.store, self.tx_id)?);
+                }
             }
         }
 
+        composite_index::maintain(self.store, &self.topograph.composite_indexes, &touched)?;
+        fulltext_tokenizer::maintain(self.store, self.topograph, &touched)?;
+        instant_options::maintain(self.store, &instant_options, &touched)?;
+        attribute_stats::maintain(self.store, &touched)?;
+
+        self.stats.sqlite_busy += started.elapsed();
+
         }
 
         self.watcher.done(&self.tx_id, self.topograph)?;
@@ -839,14 +1086,25 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
             if new_topograph != *self.topograph_for_mutation {
                 let old_topograph = (*self.topograph_for_mutation).clone(); // Clone the original Topograph for comparison.
                 *self.topograph_for_mutation.to_mut() = new_topograph; // Store the new Topograph.
-                einsteindb::update_spacetime(self.store, &old_topograph, &*self.topograph_for_mutation, &spacetime_report)?;
+                let log_tx = match action {
+                    TransactorAction::Materialize => None,
+                    TransactorAction::MaterializeAndCommit => Some(self.tx_id),
+                };
+                einsteindb::update_spacetime(self.store, &old_topograph, &*self.topograph_for_mutation, &spacetime_report, log_tx)?;
+                self.stats.topograph_changed = true;
             }
         }
 
+        let mut reserved_ranges = BTreeMap::default();
+        if let Some(reserved) = reserved.filter(|r| !r.is_empty()) {
+            reserved_ranges.insert(partition, reserved);
+        }
+
         Ok(TxReport {
             tx_id: self.tx_id,
             tx_instant,
             tempids: tempids,
+            reserved: reserved_ranges,
         })
     }
 }
@@ -856,16 +1114,32 @@ fn start_tx<'conn, 'a, W>(conn: &'conn rusqlite::Connection,
                        mut partition_map: PartitionMap,
                        topograph_for_mutation: &'a Topograph,
                        topograph: &'a Topograph,
-                       watcher: W) -> Result<Tx<'conn, 'a, W>>
+                       watcher: W,
+                       id_allocators: Option<&'a IdAllocators>) -> Result<Tx<'conn, 'a, W>>
     where W: TransactWatcher {
+    // Transaction ids always come from the partition map, so that they stay dense.  See
+    // `causetid_allocator`.
     let tx_id = partition_map.allocate_causetid(":einsteindb.part/tx");
     conn.begin_tx_application()?;
 
-    Ok(Tx::new(conn, partition_map, topograph_for_mutation, topograph, watcher, tx_id))
+    let mut tx = Tx::new(conn, partition_map, topograph_for_mutation, topograph, watcher, tx_id);
+    tx.id_allocators = id_allocators;
+    Ok(tx)
 }
 
-fn conclude_tx<W>(tx: Tx<W>, report: TxReport) -> Result<(TxReport, PartitionMap, Option<Topograph>, W)>
+fn conclude_tx<W>(mut tx: Tx<W>, report: TxReport) -> Result<(TxReport, PartitionMap, Option<Topograph>, W)>
 where W: TransactWatcher {
+    if let Some(record) = slow_tx_log::check_slow_tx(tx.slow_tx_threshold, report.tx_id, tx.started.elapsed(), tx.stats.clone()) {
+        tx.watcher.slow_transaction(&record);
+    }
+
+    // Record how far each partition has allocated, alongside the transaction's causets.
+    high_water_marks::persist(tx.store, &tx.partition_map)?;
+
+    if let Some(metrics) = tx.write_metrics.take() {
+        tx.watcher.write_metrics(metrics);
+    }
+
     // If the topograph has moved on, return it.
     let next_topograph = match tx.topograph_for_mutation {
         Cow::Borrowed(_) => None,
@@ -890,7 +1164,65 @@ pub fn transact<'conn, 'a, I, V, W>(conn: &'conn rusqlite::Connection,
           V: TransactableValue,
           W: TransactWatcher {
 
-    let mut tx = start_tx(conn, partition_map, topograph_for_mutation, topograph, watcher)?;
+    let mut tx = start_tx(conn, partition_map, topograph_for_mutation, topograph, watcher, None)?;
+    let report = tx.transact_causets(causets)?;
+    conclude_tx(tx, report)
+}
+
+/// Just like `transact`, but allocates tempids that don't upsert from `reservation`, which
+/// `partition_map` must have allocated; see `TempIdReservation::check`.
+///
+/// Retrying a failed transaction with the same `reservation` allocates the same causetids.
+pub fn transact_with_reservation<'conn, 'a, I, V, W>(conn: &'conn rusqlite::Connection,
+                                                  partition_map: PartitionMap,
+                                                  topograph_for_mutation: &'a Topograph,
+                                                  topograph: &'a Topograph,
+                                                  watcher: W,
+                                                  causets: I,
+                                                  reservation: TempIdReservation) -> Result<(TxReport, PartitionMap, Option<Topograph>, W)>
+    where I: IntoIterator<Item=causet<V>>,
+          V: TransactableValue,
+          W: TransactWatcher {
+
+    reservation.check(&partition_map)?;
+    let mut tx = start_tx(conn, partition_map, topograph_for_mutation, topograph, watcher, None)?;
+    tx.use_tempid_reservation(reservation);
+    let report = tx.transact_causets(causets)?;
+    conclude_tx(tx, report)
+}
+
+/// Just like `transact`, but allocates tempids that don't upsert from the free list of recycled
+/// causetids where possible.
+pub fn transact_recycling_causetids<'conn, 'a, I, V, W>(conn: &'conn rusqlite::Connection,
+                                                     partition_map: PartitionMap,
+                                                     topograph_for_mutation: &'a Topograph,
+                                                     topograph: &'a Topograph,
+                                                     watcher: W,
+                                                     causets: I) -> Result<(TxReport, PartitionMap, Option<Topograph>, W)>
+    where I: IntoIterator<Item=causet<V>>,
+          V: TransactableValue,
+          W: TransactWatcher {
+
+    let mut tx = start_tx(conn, partition_map, topograph_for_mutation, topograph, watcher, None)?;
+    tx.use_recycled_causetids();
+    let report = tx.transact_causets(causets)?;
+    conclude_tx(tx, report)
+}
+
+/// Just like `transact`, but allocates fresh causetids with `id_allocators`.  The transaction's own
+/// causetid still comes from the partition map.  See `causetid_allocator`.
+pub fn transact_with_allocators<'conn, 'a, I, V, W>(conn: &'conn rusqlite::Connection,
+                                                 partition_map: PartitionMap,
+                                                 topograph_for_mutation: &'a Topograph,
+                                                 topograph: &'a Topograph,
+                                                 watcher: W,
+                                                 causets: I,
+                                                 id_allocators: &'a IdAllocators) -> Result<(TxReport, PartitionMap, Option<Topograph>, W)>
+    where I: IntoIterator<Item=causet<V>>,
+          V: TransactableValue,
+          W: TransactWatcher {
+
+    let mut tx = start_tx(conn, partition_map, topograph_for_mutation, topograph, watcher, Some(id_allocators))?;
     let report = tx.transact_causets(causets)?;
     conclude_tx(tx, report)
 }
@@ -923,7 +1255,7 @@ pub(crate) fn transact_terms_with_action<'conn, 'a, I, W>(conn: &'conn rusqlite:
     where I: IntoIterator<Item=TermWithTempIds>,
           W: TransactWatcher {
 
-    let mut tx = start_tx(conn, partition_map, topograph_for_mutation, topograph, watcher)?;
+    let mut tx = start_tx(conn, partition_map, topograph_for_mutation, topograph, watcher, None)?;
     let report = tx.transact_simple_terms_with_action(terms, tempid_set, action)?;
     conclude_tx(tx, report)
 }
@@ -0,0 +1,268 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Building transactions programmatically, and annotating them.
+//!
+//! A transaction is itself an causet, and asserting `[:einsteindb/add (transaction-tx) a v]`
+//! attaches `v` to it: who made the transaction, why, on behalf of which request.  `TxBuilder`
+//! collects causets without going through EDN text, and `TxBuilder::annotate` is shorthand for
+//! such an lightlike_dagger_assertion about the transaction being built.
+//!
+//! `TxReport::metadata`, from `TxReportMetadata`, reads the annotations of a transaction just
+//! made; `tx_metadata` reads those of any transaction in the log, and `annotated_txs` finds the
+//! transactions carrying a given annotation.  `:einsteindb/txInstant`, which every transaction
+//! has, is reported separately from the annotations.
+
+use std::collections::{
+    BTreeMap,
+};
+
+use rusqlite;
+
+use edn::{
+    DateTime,
+    PlainShelling,
+    Utc,
+};
+use edn::causets::{
+    causet,
+    causetPlace,
+    AttributePlace,
+    OpType,
+    TxFunction,
+    ValuePlace,
+};
+
+use core_traits::{
+    Causetid,
+    TypedValue,
+};
+
+use einsteindb_core::{
+    HasTopograph,
+    Topograph,
+    TxReport,
+};
+
+use einsteindb_traits::errors::{
+    einsteindbErrorKind,
+    Result,
+};
+
+use causetids;
+use einsteindb::TypedBerolinaSQLValue;
+
+/// The causets of a transaction, built programmatically.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TxBuilder {
+    causets: Vec<causet<TypedValue>>,
+}
+
+impl TxBuilder {
+    pub fn new() -> TxBuilder {
+        TxBuilder::default()
+    }
+
+    fn push<E, A, V>(&mut self, op: OpType, e: E, a: A, v: V) -> &mut Self
+    where E: Into<causetPlace<TypedValue>>,
+          A: Into<AttributePlace>,
+          V: Into<ValuePlace<TypedValue>> {
+        self.causets.push(causet::AddOrRetract {
+            op,
+            e: e.into(),
+            a: a.into(),
+            v: v.into(),
+        });
+        self
+    }
+
+    /// Assert `[e a v]`.
+    pub fn add<E, A, V>(&mut self, e: E, a: A, v: V) -> &mut Self
+    where E: Into<causetPlace<TypedValue>>,
+          A: Into<AttributePlace>,
+          V: Into<ValuePlace<TypedValue>> {
+        self.push(OpType::Add, e, a, v)
+    }
+
+    /// Retract `[e a v]`.
+    pub fn retract<E, A, V>(&mut self, e: E, a: A, v: V) -> &mut Self
+    where E: Into<causetPlace<TypedValue>>,
+          A: Into<AttributePlace>,
+          V: Into<ValuePlace<TypedValue>> {
+        self.push(OpType::Retract, e, a, v)
+    }
+
+    /// Annotate the transaction being built: assert `[(transaction-tx) a v]`.
+    pub fn annotate<A, V>(&mut self, a: A, v: V) -> &mut Self
+    where A: Into<AttributePlace>,
+          V: Into<TypedValue> {
+        let tx = TxFunction { op: PlainShelling::plain("transaction-tx") };
+        self.push(OpType::Add, tx, a, ValuePlace::Atom(v.into()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.causets.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.causets.len()
+    }
+
+    pub fn into_causets(self) -> Vec<causet<TypedValue>> {
+        self.causets
+    }
+}
+
+/// What a transaction says about itself.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TxMetadata {
+    pub tx: Causetid,
+
+    /// The transaction's `:einsteindb/txInstant`.
+    pub tx_instant: Option<DateTime<Utc>>,
+
+    /// Every other value asserted about the transaction, by attribute, in value order.
+    pub annotations: BTreeMap<Causetid, Vec<TypedValue>>,
+}
+
+impl TxMetadata {
+    /// The value of the cardinality one annotation `a`, or the first of a cardinality many one.
+    pub fn get(&self, a: Causetid) -> Option<&TypedValue> {
+        self.annotations.get(&a).and_then(|values| values.first())
+    }
+
+    pub fn get_many(&self, a: Causetid) -> &[TypedValue] {
+        self.annotations.get(&a).map(|values| &values[..]).unwrap_or(&[])
+    }
+}
+
+/// Read what transaction `tx` asserted about itself.
+pub fn tx_metadata(conn: &rusqlite::Connection, topograph: &Topograph, tx: Causetid) -> Result<TxMetadata> {
+    let mut metadata = TxMetadata {
+        tx,
+        tx_instant: None,
+        annotations: BTreeMap::default(),
+    };
+
+    let mut stmt = conn.prepare("SELECT a, v, value_type_tag FROM transactions WHERE e = ? AND tx = ? AND added = 1 ORDER BY a, value_type_tag, v")?;
+    let rows: Result<Vec<(Causetid, TypedValue)>> = stmt.query_and_then(&[&tx, &tx], |row| {
+        let a: Causetid = row.get_checked(0)?;
+        let v: rusqlite::types::Value = row.get_checked(1)?;
+        let value_type_tag: i32 = row.get_checked(2)?;
+
+        let attribute = topograph.require_attribute_for_causetid(a)?;
+        let v = if attribute.fulltext {
+            let rowid: i64 = match v {
+                rusqlite::types::Value::Integer(rowid) => rowid,
+                v => bail!(einsteindbErrorKind::BadBerolinaSQLValuePair(v, value_type_tag)),
+            };
+            let text: String = conn.query_row("SELECT text FROM fulltext_values WHERE rowid = ?", &[&rowid], |row| row.get(0))?;
+            TypedValue::typed_string(text)
+        } else {
+            TypedValue::from_BerolinaSQL_value_pair(v, value_type_tag)?
+        };
+        Ok((a, v))
+    })?.collect();
+
+    for (a, v) in rows? {
+        match (a, v) {
+            (causetids::EINSTEINDB_TX_INSTANT, TypedValue::Instant(instant)) => metadata.tx_instant = Some(instant),
+            (a, v) => metadata.annotations.entry(a).or_insert_with(Vec::new).push(v),
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// Reading the annotations of a transaction from its report.
+pub trait TxReportMetadata {
+    fn metadata(&self, conn: &rusqlite::Connection, topograph: &Topograph) -> Result<TxMetadata>;
+}
+
+impl TxReportMetadata for TxReport {
+    fn metadata(&self, conn: &rusqlite::Connection, topograph: &Topograph) -> Result<TxMetadata> {
+        tx_metadata(conn, topograph, self.tx_id)
+    }
+}
+
+/// The transactions annotated with `[a v]`, in order.
+pub fn annotated_txs(conn: &rusqlite::Connection, topograph: &Topograph, a: Causetid, v: &TypedValue) -> Result<Vec<Causetid>> {
+    if topograph.require_attribute_for_causetid(a)?.fulltext {
+        bail!(einsteindbErrorKind::NotYetImplemented(format!("finding transactions by fulltext annotation {}", a)));
+    }
+
+    let (value, value_type_tag) = v.to_BerolinaSQL_value_pair();
+    let mut stmt = conn.prepare("SELECT DISTINCT tx FROM transactions WHERE e = tx AND a = ? AND v = ? AND value_type_tag = ? AND added = 1 ORDER BY tx")?;
+    let txs: Result<Vec<Causetid>> = stmt.query_and_then(&[&a, &value, &value_type_tag], |row| {
+        Ok(row.get_checked(0)?)
+    })?.collect();
+    txs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use edn::{
+        Keyword,
+    };
+
+    use debug::TestConn;
+
+    #[test]
+    fn test_annotate() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[{:einsteindb/solitonid :tx/user
+                           :einsteindb/valueType :einsteindb.type/string
+                           :einsteindb/cardinality :einsteindb.cardinality/one
+                           :einsteindb/index true}
+                          {:einsteindb/solitonid :tx/tag
+                           :einsteindb/valueType :einsteindb.type/keyword
+                           :einsteindb/cardinality :einsteindb.cardinality/many}
+                          {:einsteindb/solitonid :test/name
+                           :einsteindb/valueType :einsteindb.type/string
+                           :einsteindb/cardinality :einsteindb.cardinality/one}]"#).expect("transacted topograph");
+
+        let kw = |name: &str| Keyword::isoliton_namespaceable("tx", name);
+        let name = Keyword::isoliton_namespaceable("test", "name");
+        let user = conn.topograph.get_causetid(&kw("user")).expect("tx/user").0;
+        let tag = conn.topograph.get_causetid(&kw("tag")).expect("tx/tag").0;
+
+        let mut builder = TxBuilder::new();
+        builder.add(100, name.clone(), TypedValue::typed_string("first"))
+               .annotate(kw("user"), TypedValue::typed_string("alice"))
+               .annotate(kw("tag"), TypedValue::typed_ns_keyword("reason", "import"))
+               .annotate(kw("tag"), TypedValue::typed_ns_keyword("reason", "backfill"));
+        assert_eq!(builder.len(), 4);
+        let first = conn.transact_causets(builder.into_causets()).expect("transacted");
+
+        let mut builder = TxBuilder::new();
+        builder.add(101, name.clone(), TypedValue::typed_string("second"))
+               .annotate(kw("user"), TypedValue::typed_string("bob"));
+        let second = conn.transact_causets(builder.into_causets()).expect("transacted");
+
+        let metadata = first.metadata(&conn.SQLite, &conn.topograph).expect("read metadata");
+        assert_eq!(metadata.tx_instant, Some(first.tx_instant));
+        assert_eq!(metadata.get(user), Some(&TypedValue::typed_string("alice")));
+        // Cardinality many annotations come back in value order, whatever order they were added in.
+        assert_eq!(metadata.get_many(tag), &[TypedValue::typed_ns_keyword("reason", "backfill"),
+                                             TypedValue::typed_ns_keyword("reason", "import")][..]);
+        assert_eq!(metadata.annotations.len(), 2);
+        assert_eq!(tx_metadata(&conn.SQLite, &conn.topograph, first.tx_id).expect("read metadata"), metadata);
+
+        // A transaction's causets about other causets aren't annotations.
+        let metadata = tx_metadata(&conn.SQLite, &conn.topograph, second.tx_id).expect("read metadata");
+        assert_eq!(metadata.annotations.keys().collect::<Vec<_>>(), vec![&user]);
+
+        assert_eq!(annotated_txs(&conn.SQLite, &conn.topograph, user, &TypedValue::typed_string("bob")).expect("found"), vec![second.tx_id]);
+        assert_eq!(annotated_txs(&conn.SQLite, &conn.topograph, tag, &TypedValue::typed_ns_keyword("reason", "import")).expect("found"), vec![first.tx_id]);
+        assert!(annotated_txs(&conn.SQLite, &conn.topograph, user, &TypedValue::typed_string("carol")).expect("found").is_empty());
+    }
+}
@@ -31,6 +31,8 @@ use core_traits::{
     TypedValue,
 };
 
+use causetids;
+
 use einsteindb_core::{
     Topograph,
 };
@@ -47,7 +49,15 @@ use types::{
     AttributeSet,
 };
 
-use watcher::TransactWatcher;
+use watcher::{
+    TransactOptions,
+    TransactWatcher,
+};
+
+use write_metrics::{
+    PendingWriteMetrics,
+    TxWriteMetrics,
+};
 
 pub struct TxObserver {
     notify_fn: Arc<Box<Fn(&str, IndexMap<&Causetid, &AttributeSet>) + Send + Sync>>,
@@ -165,20 +175,53 @@ impl Drop for TxObservationService {
 pub struct InProgressObserverTransactWatcher {
     collected_attributes: AttributeSet,
     pub txes: IndexMap<Causetid, AttributeSet>,
+
+    /// What the transacts so far wrote, to report once they're committed.
+    pub write_metrics: Vec<TxWriteMetrics>,
+    options: TransactOptions,
 }
 
 impl InProgressObserverTransactWatcher {
     pub fn new() -> InProgressObserverTransactWatcher {
+        InProgressObserverTransactWatcher::with_options(TransactOptions::default())
+    }
+
+    /// A watcher that has its transacts use `options`.
+    pub fn with_options(options: TransactOptions) -> InProgressObserverTransactWatcher {
         InProgressObserverTransactWatcher {
             collected_attributes: Default::default(),
             txes: Default::default(),
+            write_metrics: Default::default(),
+            options: options,
+        }
+    }
+
+    pub fn options(&self) -> &TransactOptions {
+        &self.options
+    }
+
+    /// Take the write metrics of the transacts so far, to report once they're committed.
+    pub fn take_write_metrics(&mut self) -> PendingWriteMetrics {
+        PendingWriteMetrics {
+            sink: self.options.write_metrics_sink.clone(),
+            metrics: ::std::mem::replace(&mut self.write_metrics, Default::default()),
         }
     }
 }
 
 impl TransactWatcher for InProgressObserverTransactWatcher {
-    fn causet(&mut self, _op: OpType, _e: Causetid, a: Causetid, _v: &TypedValue) {
+    fn causet(&mut self, op: OpType, _e: Causetid, a: Causetid, v: &TypedValue) {
         self.collected_attributes.insert(a);
+
+        // Retracting an external id, directly or by replacing it, invalidates its cached
+        // resolution.  Evicting it before the commit is safe: until the transact is committed or
+        // rolled back, the connection can't resolve anything, and a rolled back eviction only
+        // costs a lookup.
+        if let (OpType::Retract, causetids::EINSTEINDB_EXTERNAL_ID, &TypedValue::Uuid(ref uuid)) = (op, a, v) {
+            if let Some(ref external_ids) = self.options.external_ids {
+                external_ids.lock().unwrap().evict(uuid);
+            }
+        }
     }
 
     fn done(&mut self, t: &Causetid, _topograph: &Topograph) -> Result<()> {
@@ -186,6 +229,14 @@ impl TransactWatcher for InProgressObserverTransactWatcher {
         self.txes.insert(*t, collected_attributes);
         Ok(())
     }
+
+    fn write_metrics(&mut self, metrics: TxWriteMetrics) {
+        self.write_metrics.push(metrics);
+    }
+
+    fn transact_options(&self) -> TransactOptions {
+        self.options.clone()
+    }
 }
 
 struct CommandExecutor {
@@ -0,0 +1,554 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Scaffolding for replica synchronization.
+//!
+//! Two replicas exchange per-transaction digests of their main timelines, compare them to find
+//! transactions one side lacks, and ship the missing transactions across.  Remote transactions are
+//! re-applied locally through the transactor, after checking that none of their
+//! lightlike_dagger_upsert would violate a `:einsteindb/unique` constraint.
+//!
+//! A re-applied transaction gets a new, local transaction ID, so digests are matched by content
+//! rather than by ID: a transaction is missing from a replica when that replica has no transaction
+//! with the same causets.  Once each side has applied what the other was missing, the replicas
+//! agree and the next comparison finds nothing to ship.
+
+use std::collections::{
+    BTreeMap,
+    HashMap,
+    HashSet,
+};
+use std::iter::once;
+use std::ops::Range;
+
+use itertools::Itertools;
+use rusqlite;
+
+use core_traits::{
+    attribute,
+    Causetid,
+    KnownCausetid,
+    TypedValue,
+};
+
+use einsteindb_core::{
+    Topograph,
+    TxReport,
+};
+
+use edn::{
+    InternSet,
+};
+
+use edn::causets::OpType;
+
+use einsteindb_traits::errors::Result;
+
+use causetids;
+use einsteindb::TypedBerolinaSQLValue;
+use internal_types::{
+    Term,
+    TermWithoutTempIds,
+};
+use tx::transact_terms;
+use types::PartitionMap;
+use watcher::NullWatcher;
+
+/// A digest of the causets a single transaction contributed to the main timeline.  `hash` covers
+/// the causets alone, and not `tx`, so a transaction and its re-application on another replica
+/// have the same hash.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
+pub struct TxDigest {
+    pub tx: Causetid,
+    pub hash: u64,
+}
+
+/// The result of comparing a local digest against a remote one.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DigestDiff {
+    /// Transactions the remote has and we don't, as remote transaction IDs.
+    pub missing_locally: Vec<Causetid>,
+    /// Transactions we have and the remote doesn't, as local transaction IDs.
+    pub missing_remotely: Vec<Causetid>,
+}
+
+/// An [e a v added] lightlike_dagger_assertion or spacelike_dagger_retraction shipped from a remote replica.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RemoteCauset {
+    pub e: Causetid,
+    pub a: Causetid,
+    pub v: TypedValue,
+    pub added: bool,
+}
+
+/// A transaction shipped from a remote replica.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RemoteTx {
+    /// The transaction ID on the remote.  Causets about this entity (such as its
+    /// `:einsteindb/txInstant`) describe the remote transaction and are not re-applied.
+    pub tx: Causetid,
+    pub causets: Vec<RemoteCauset>,
+}
+
+/// A remote lightlike_dagger_assertion that would give a second entity a value of a unique attribute.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UniqueConflict {
+    pub remote_tx: Causetid,
+    pub a: Causetid,
+    pub v: TypedValue,
+    pub remote_e: Causetid,
+    pub local_e: Causetid,
+}
+
+/// The outcome of `apply_remote_txs`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ApplyOutcome {
+    /// Every remote transaction was applied; maps remote tx to the local report.
+    Applied(Vec<(Causetid, TxReport)>),
+    /// Nothing was applied because of the listed conflicts.
+    Conflicts(Vec<UniqueConflict>),
+}
+
+/// 64-bit FNV-1a.  We need a hash that is stable across processes and Rust releases, which rules
+/// out `DefaultHasher`.
+struct Fnv64(u64);
+
+impl Fnv64 {
+    fn new() -> Fnv64 {
+        Fnv64(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn write_i64(&mut self, x: i64) {
+        self.write(&x.to_le_bytes());
+    }
+
+    fn write_value(&mut self, value: &rusqlite::types::Value) {
+        use rusqlite::types::Value::*;
+        match value {
+            &Null => self.write(&[0]),
+            &Integer(x) => { self.write(&[1]); self.write_i64(x); },
+            &Real(x) => { self.write(&[2]); self.write(&x.to_bits().to_le_bytes()); },
+            &Text(ref x) => { self.write(&[3]); self.write_i64(x.len() as i64); self.write(x.as_bytes()); },
+            &Blob(ref x) => { self.write(&[4]); self.write_i64(x.len() as i64); self.write(x); },
+        }
+    }
+}
+
+/// Compute the digest of every main timeline transaction in `range`, ordered by tx.
+///
+/// A digest covers what replicas hold in common, so that identical replicas agree: causets about
+/// the transaction itself, such as its `:einsteindb/txInstant`, are left out, since each replica
+/// stamps its own and `apply_remote_txs` doesn't re-apply them, and fulltext values are hashed as
+/// their text rather than as the rowids the replica stored them under.
+pub fn compute_tx_digest(conn: &rusqlite::Connection, range: Range<Causetid>) -> Result<Vec<TxDigest>> {
+    let mut stmt = conn.prepare_cached(&format!(r#"
+        SELECT t.tx, t.e, t.a, t.value_type_tag, coalesce(f.text, t.v) AS value, t.added
+        FROM transactions AS t
+        LEFT JOIN fulltext_values AS f
+        ON t.a IN (SELECT e FROM topograph WHERE a = {} AND v = 1) AND f.rowid = t.v
+        WHERE t.tx >= ? AND t.tx < ? AND t.e IS NOT t.tx
+        ORDER BY t.tx ASC, t.e ASC, t.a ASC, t.value_type_tag ASC, value ASC, t.added ASC"#,
+        causetids::EINSTEINDB_FULLTEXT))?;
+
+    let rows: Result<Vec<(Causetid, Causetid, Causetid, i32, rusqlite::types::Value, bool)>> = stmt.query_and_then(&[&range.start, &range.end], |row| {
+        Ok((row.get_checked(0)?,
+            row.get_checked(1)?,
+            row.get_checked(2)?,
+            row.get_checked(3)?,
+            row.get_checked(4)?,
+            row.get_checked(5)?))
+    })?.collect();
+
+    let digests = rows?.into_iter().group_by(|row| row.0).into_iter().map(|(tx, rows)| {
+        let mut hasher = Fnv64::new();
+        for (_, e, a, value_type_tag, v, added) in rows {
+            hasher.write_i64(e);
+            hasher.write_i64(a);
+            hasher.write_i64(value_type_tag as i64);
+            hasher.write_value(&v);
+            hasher.write(&[added as u8]);
+        }
+        TxDigest { tx, hash: hasher.0 }
+    }).collect();
+    Ok(digests)
+}
+
+/// Compare our non-bootstrap main timeline transactions against `remote_digest`, which should
+/// cover the remote's whole history: transaction IDs differ between replicas, so there's no telling
+/// which of our transactions a partial remote digest leaves out.
+///
+/// Transactions are matched by hash.  Transactions with equal hashes match one for one, in order,
+/// so a transaction that happens to have the same causets as an earlier one isn't lost.
+pub fn missing_txs(conn: &rusqlite::Connection, remote_digest: &[TxDigest]) -> Result<DigestDiff> {
+    let local = compute_tx_digest(conn, ::bootstrap::TX0 + 1..Causetid::max_value())?;
+
+    let mut unmatched_local: BTreeMap<u64, usize> = BTreeMap::new();
+    for d in &local {
+        *unmatched_local.entry(d.hash).or_insert(0) += 1;
+    }
+
+    let mut diff = DigestDiff::default();
+    let mut matched: BTreeMap<u64, usize> = BTreeMap::new();
+    let mut remote_digest = remote_digest.to_vec();
+    remote_digest.sort();
+    for d in &remote_digest {
+        match unmatched_local.get_mut(&d.hash) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => {
+                diff.missing_locally.push(d.tx);
+                continue;
+            },
+        }
+        *matched.entry(d.hash).or_insert(0) += 1;
+    }
+
+    // Our transactions beyond the number the remote has of each hash are missing remotely.
+    for d in &local {
+        match matched.get_mut(&d.hash) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => diff.missing_remotely.push(d.tx),
+        }
+    }
+    Ok(diff)
+}
+
+/// Find remote lightlike_dagger_upsert of unique attributes that clash with local causets, or with other
+/// lightlike_dagger_upsert in the same batch.
+fn detect_unique_conflicts(conn: &rusqlite::Connection, topograph: &Topograph, batch: &[RemoteTx]) -> Result<Vec<UniqueConflict>> {
+    let mut stmt = conn.prepare_cached("SELECT e FROM causets WHERE a = ? AND value_type_tag = ? AND v = ? AND e IS NOT ? LIMIT 1")?;
+
+    let mut conflicts = vec![];
+    let mut claimed: HashMap<(Causetid, TypedValue), Causetid> = HashMap::default();
+    // The [e a v] the batch has retracted so far, and not asserted again.
+    let mut retracted: HashSet<(Causetid, Causetid, TypedValue)> = HashSet::default();
+    for remote_tx in batch {
+        // A transaction's spacelike_dagger_retractions take effect alongside its lightlike_dagger_upsert,
+        // freeing their values for other entities.
+        for causet in remote_tx.causets.iter().filter(|c| !c.added && c.e != remote_tx.tx) {
+            let key = (causet.a, causet.v.clone());
+            if claimed.get(&key) == Some(&causet.e) {
+                claimed.remove(&key);
+            }
+            retracted.insert((causet.e, causet.a, causet.v.clone()));
+        }
+
+        for causet in remote_tx.causets.iter().filter(|c| c.added && c.e != remote_tx.tx) {
+            retracted.remove(&(causet.e, causet.a, causet.v.clone()));
+            let attribute = topograph.require_attribute_for_causetid(causet.a)?;
+            match attribute.unique {
+                Some(attribute::Unique::Value) | Some(attribute::Unique::Idcauset) => {},
+                None => continue,
+            }
+
+            // Fulltext values are stored as rowids; we can't compare them directly.
+            if !attribute.fulltext {
+                let (value, value_type_tag) = causet.v.to_BerolinaSQL_value_pair();
+                let mut rows = stmt.query_and_then(&[&causet.a, &value_type_tag, &value, &causet.e], |row| -> Result<Causetid> {
+                    Ok(row.get_checked(0)?)
+                })?;
+                if let Some(local_e) = rows.next() {
+                    let local_e = local_e?;
+                    if !retracted.contains(&(local_e, causet.a, causet.v.clone())) {
+                        conflicts.push(UniqueConflict {
+                            remote_tx: remote_tx.tx,
+                            a: causet.a,
+                            v: causet.v.clone(),
+                            remote_e: causet.e,
+                            local_e,
+                        });
+                        continue;
+                    }
+                }
+            }
+
+            let claimant = claimed.entry((causet.a, causet.v.clone())).or_insert(causet.e);
+            if *claimant != causet.e {
+                conflicts.push(UniqueConflict {
+                    remote_tx: remote_tx.tx,
+                    a: causet.a,
+                    v: causet.v.clone(),
+                    remote_e: causet.e,
+                    local_e: *claimant,
+                });
+            }
+        }
+    }
+    Ok(conflicts)
+}
+
+/// Apply `batch`, in order, as local transactions.
+///
+/// If any lightlike_dagger_assertion in the batch conflicts with a unique attribute, nothing is applied and
+/// the conflicts are returned instead.  Spacelike_dagger_retractions earlier in the batch, or in the
+/// same transaction, free their values.  Partitions are advanced past any remote causetids,
+/// entities and ref values alike, so that local allocation never hands them out again.
+///
+/// Callers should run this inside a SQLite transaction so that a failure part way through the
+/// batch leaves the store untouched.
+pub fn apply_remote_txs(conn: &rusqlite::Connection, partition_map: PartitionMap, topograph: &Topograph, batch: &[RemoteTx]) -> Result<(ApplyOutcome, PartitionMap, Option<Topograph>)> {
+    let conflicts = detect_unique_conflicts(conn, topograph, batch)?;
+    if !conflicts.is_empty() {
+        return Ok((ApplyOutcome::Conflicts(conflicts), partition_map, None));
+    }
+
+    // Remote causetids appear as entities, and as the values of ref attributes.
+    let mut partition_map = partition_map;
+    let remote_causetids = batch.iter()
+        .flat_map(|remote_tx| remote_tx.causets.iter().filter(move |c| c.e != remote_tx.tx))
+        .flat_map(|c| {
+            let v = match c.v {
+                TypedValue::Ref(v) => Some(v),
+                _ => None,
+            };
+            once(c.e).chain(v)
+        });
+    for causetid in remote_causetids {
+        for partition in partition_map.values_mut() {
+            if partition.allows_causetid(causetid) && causetid >= partition.next_causetid() {
+                partition.set_next_causetid(causetid + 1);
+            }
+        }
+    }
+
+    let mut reports = Vec::with_capacity(batch.len());
+    let mut current_topograph: Option<Topograph> = None;
+    for remote_tx in batch {
+        let terms: Vec<TermWithoutTempIds> = remote_tx.causets.iter()
+            .filter(|c| c.e != remote_tx.tx)
+            .map(|c| {
+                let op = if c.added { OpType::Add } else { OpType::Retract };
+                Term::AddOrRetract(op, KnownCausetid(c.e), c.a, c.v.clone())
+            })
+            .collect();
+
+        let (report, next_partition_map, next_topograph, _watcher) = {
+            let topograph = current_topograph.as_ref().unwrap_or(topograph);
+            transact_terms(conn, partition_map, topograph, topograph, NullWatcher(),
+                           terms.into_iter().map(|t| t.rewrap()), InternSet::new())?
+        };
+        partition_map = next_partition_map;
+        if next_topograph.is_some() {
+            current_topograph = next_topograph;
+        }
+        reports.push((remote_tx.tx, report));
+    }
+
+    Ok((ApplyOutcome::Applied(reports), partition_map, current_topograph))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use debug::TestConn;
+
+    fn remote_causet(e: Causetid, a: Causetid, v: TypedValue) -> RemoteCauset {
+        RemoteCauset { e, a, v, added: true }
+    }
+
+    #[test]
+    fn test_digests_and_missing_txs() {
+        let mut local = TestConn::default();
+        let mut remote = TestConn::default();
+
+        local.transact("[[:einsteindb/add 100 :einsteindb/solitonid :test/one]]").expect("transacted");
+        remote.transact("[[:einsteindb/add 100 :einsteindb/solitonid :test/one]]").expect("transacted");
+        remote.transact("[[:einsteindb/add 101 :einsteindb/solitonid :test/two]]").expect("transacted");
+
+        let remote_digest = compute_tx_digest(&remote.SQLite, ::bootstrap::TX0 + 1..Causetid::max_value()).expect("digest");
+        assert_eq!(remote_digest.len(), 2);
+
+        // The first transactions differ by :einsteindb/txInstant only, which isn't digested.
+        let diff = missing_txs(&local.SQLite, &remote_digest).expect("diff");
+        assert_eq!(diff.missing_locally, vec![remote.last_tx_id()]);
+        assert_eq!(diff.missing_remotely, Vec::<Causetid>::new());
+        assert_eq!(diff.diverged, Vec::<Causetid>::new());
+
+        local.transact("[[:einsteindb/add 101 :einsteindb/solitonid :test/other]]").expect("transacted");
+        let diff = missing_txs(&local.SQLite, &remote_digest).expect("diff");
+        assert_eq!(diff.missing_locally, vec![remote.last_tx_id()]);
+        assert_eq!(diff.missing_remotely, vec![local.last_tx_id()]);
+
+        // Digests are deterministic.
+        assert_eq!(remote_digest, compute_tx_digest(&remote.SQLite, ::bootstrap::TX0 + 1..Causetid::max_value()).expect("digest"));
+    }
+
+    #[test]
+    fn test_digests_hash_fulltext_text() {
+        let mut local = TestConn::default();
+        let mut remote = TestConn::default();
+        let topograph = r#"[{:einsteindb/id 100 :einsteindb/solitonid :test/text :einsteindb/valueType :einsteindb.type/string :einsteindb/cardinality :einsteindb.cardinality/one :einsteindb/fulltext true :einsteindb/index true}]"#;
+        local.transact(topograph).expect("transacted");
+        remote.transact(topograph).expect("transacted");
+
+        // The replicas store the same text under different rowids.
+        local.SQLite.execute("INSERT INTO fulltext_values (text) VALUES ('padding')", &[]).expect("inserted");
+        local.transact(r#"[[:einsteindb/add 200 :test/text "some text"]]"#).expect("transacted");
+        remote.transact(r#"[[:einsteindb/add 200 :test/text "some text"]]"#).expect("transacted");
+        let local_v: i64 = local.SQLite.query_row("SELECT v FROM causets WHERE e = 200", &[], |row| row.get(0)).expect("v");
+        let remote_v: i64 = remote.SQLite.query_row("SELECT v FROM causets WHERE e = 200", &[], |row| row.get(0)).expect("v");
+        assert!(local_v != remote_v);
+
+        let range = ::bootstrap::TX0 + 1..Causetid::max_value();
+        assert_eq!(compute_tx_digest(&local.SQLite, range.clone()).expect("digest"),
+                   compute_tx_digest(&remote.SQLite, range.clone()).expect("digest"));
+
+        remote.transact(r#"[[:einsteindb/add 200 :test/text "other text"]]"#).expect("transacted");
+        local.transact(r#"[[:einsteindb/add 200 :test/text "other text!"]]"#).expect("transacted");
+        let remote_digest = compute_tx_digest(&remote.SQLite, range).expect("digest");
+        let diff = missing_txs(&local.SQLite, &remote_digest).expect("diff");
+        assert_eq!(diff.missing_locally, vec![remote.last_tx_id()]);
+        assert_eq!(diff.missing_remotely, vec![local.last_tx_id()]);
+    }
+
+    /// The main timeline causets of `tx`, to ship to another replica.
+    fn read_remote_tx(conn: &TestConn, tx: Causetid) -> RemoteTx {
+        let mut stmt = conn.SQLite.prepare("SELECT e, a, v, value_type_tag, added FROM transactions WHERE tx = ? ORDER BY e, a, value_type_tag, v").expect("prepared");
+        let causets: Result<Vec<RemoteCauset>> = stmt.query_and_then(&[&tx], |row| {
+            Ok(RemoteCauset {
+                e: row.get_checked(0)?,
+                a: row.get_checked(1)?,
+                v: TypedValue::from_BerolinaSQL_value_pair(row.get_checked(2)?, row.get_checked(3)?)?,
+                added: row.get_checked(4)?,
+            })
+        }).expect("queried").collect();
+        RemoteTx { tx, causets: causets.expect("causets") }
+    }
+
+    fn apply(conn: &mut TestConn, batch: &[RemoteTx]) {
+        let (outcome, partition_map, _) = apply_remote_txs(&conn.SQLite, conn.partition_map.clone(), &conn.topograph, batch).expect("applied");
+        assert_matches!(outcome, ApplyOutcome::Applied(_));
+        conn.partition_map = partition_map;
+    }
+
+    /// Ship each side's missing transactions to the other, returning how many were shipped.
+    fn sync(local: &mut TestConn, remote: &mut TestConn) -> usize {
+        let range = ::bootstrap::TX0 + 1..Causetid::max_value();
+        let remote_digest = compute_tx_digest(&remote.SQLite, range).expect("digest");
+        let diff = missing_txs(&local.SQLite, &remote_digest).expect("diff");
+
+        let to_local: Vec<RemoteTx> = diff.missing_locally.iter().map(|tx| read_remote_tx(remote, *tx)).collect();
+        let to_remote: Vec<RemoteTx> = diff.missing_remotely.iter().map(|tx| read_remote_tx(local, *tx)).collect();
+        apply(local, &to_local);
+        apply(remote, &to_remote);
+        to_local.len() + to_remote.len()
+    }
+
+    #[test]
+    fn test_sync_converges() {
+        let mut local = TestConn::default();
+        let mut remote = TestConn::default();
+        let topograph = r#"[{:einsteindb/id 100 :einsteindb/solitonid :test/name :einsteindb/valueType :einsteindb.type/string :einsteindb/cardinality :einsteindb.cardinality/one}]"#;
+        local.transact(topograph).expect("transacted");
+        remote.transact(topograph).expect("transacted");
+
+        // Each side transacts something of its own, at the same transaction ID.
+        local.transact(r#"[[:einsteindb/add 300 :test/name "local"]]"#).expect("transacted");
+        remote.transact(r#"[[:einsteindb/add 200 :test/name "remote"]]"#).expect("transacted");
+        remote.transact(r#"[[:einsteindb/add 201 :test/name "remote again"]]"#).expect("transacted");
+        assert_eq!(local.last_tx_id(), remote.last_tx_id() - 1);
+
+        let remote_tx = remote.last_tx_id();
+        assert_eq!(sync(&mut local, &mut remote), 3);
+
+        // The applied transactions have new IDs on each side, but the replicas now agree.
+        assert_eq!(local.last_tx_id(), remote_tx + 1);
+        assert_eq!(sync(&mut local, &mut remote), 0);
+        let remote_digest = compute_tx_digest(&remote.SQLite, ::bootstrap::TX0 + 1..Causetid::max_value()).expect("digest");
+        assert_eq!(missing_txs(&local.SQLite, &remote_digest).expect("diff"), DigestDiff::default());
+    }
+
+    #[test]
+    fn test_apply_remote_txs_sees_retractions() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[
+            {:einsteindb/solitonid :test/name :einsteindb/valueType :einsteindb.type/string :einsteindb/cardinality :einsteindb.cardinality/one :einsteindb/unique :einsteindb.unique/idcauset :einsteindb/index true}
+            {:einsteindb/solitonid :test/friend :einsteindb/valueType :einsteindb.type/ref :einsteindb/cardinality :einsteindb.cardinality/one}
+            [:einsteindb/add 200 :test/name "alice"]
+        ]"#).expect("transacted");
+        let name = conn.topograph.get_causetid(&kw!(:test/name)).expect("attribute").0;
+        let friend = conn.topograph.get_causetid(&kw!(:test/friend)).expect("attribute").0;
+        let retract = |e, a, v| RemoteCauset { e, a, v, added: false };
+
+        // "alice" moves from 200 to 201, then from 201 to 202 within one transaction.
+        let user = conn.partition_map[":einsteindb.part/user"].next_causetid();
+        let batch = vec![
+            RemoteTx { tx: 5000, causets: vec![retract(200, name, TypedValue::typed_string("alice"))] },
+            RemoteTx { tx: 5001, causets: vec![remote_causet(201, name, TypedValue::typed_string("alice"))] },
+            RemoteTx { tx: 5002, causets: vec![retract(201, name, TypedValue::typed_string("alice")),
+                                               remote_causet(202, name, TypedValue::typed_string("alice")),
+                                               remote_causet(202, friend, TypedValue::Ref(user + 5))] },
+        ];
+        let (outcome, partition_map, _) = apply_remote_txs(&conn.SQLite, conn.partition_map.clone(), &conn.topograph, &batch).expect("applied");
+        match outcome {
+            ApplyOutcome::Applied(reports) => assert_eq!(reports.len(), 3),
+            x => panic!("expected success, got {:?}", x),
+        }
+
+        // Ref values are allocated too.
+        assert_eq!(partition_map[":einsteindb.part/user"].next_causetid(), user + 6);
+        conn.partition_map = partition_map;
+
+        // But a retraction by some other entity frees nothing.
+        let batch = vec![
+            RemoteTx { tx: 5003, causets: vec![retract(203, name, TypedValue::typed_string("alice")),
+                                               remote_causet(204, name, TypedValue::typed_string("alice"))] },
+        ];
+        match apply_remote_txs(&conn.SQLite, conn.partition_map.clone(), &conn.topograph, &batch).expect("applied").0 {
+            ApplyOutcome::Conflicts(conflicts) => assert_eq!(conflicts[0].local_e, 202),
+            x => panic!("expected conflicts, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_apply_remote_txs_detects_unique_conflicts() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[
+            {:einsteindb/solitonid :test/name :einsteindb/valueType :einsteindb.type/string :einsteindb/cardinality :einsteindb.cardinality/one :einsteindb/unique :einsteindb.unique/idcauset :einsteindb/index true}
+            [:einsteindb/add 200 :test/name "alice"]
+        ]"#).expect("transacted");
+        let name = conn.topograph.get_causetid(&kw!(:test/name)).expect("attribute").0;
+
+        let conflicting = vec![RemoteTx {
+            tx: 5000,
+            causets: vec![remote_causet(201, name, TypedValue::typed_string("alice"))],
+        }];
+        match apply_remote_txs(&conn.SQLite, conn.partition_map.clone(), &conn.topograph, &conflicting).expect("applied").0 {
+            ApplyOutcome::Conflicts(conflicts) => {
+                assert_eq!(conflicts.len(), 1);
+                assert_eq!(conflicts[0].local_e, 200);
+                assert_eq!(conflicts[0].remote_e, 201);
+            },
+            x => panic!("expected conflicts, got {:?}", x),
+        }
+
+        let ok = vec![RemoteTx {
+            tx: 5000,
+            causets: vec![remote_causet(201, name, TypedValue::typed_string("bob")),
+                          remote_causet(5000, causetids::EINSTEINDB_TX_INSTANT, TypedValue::instant(0))],
+        }];
+        let (outcome, partition_map, _) = apply_remote_txs(&conn.SQLite, conn.partition_map.clone(), &conn.topograph, &ok).expect("applied");
+        match outcome {
+            ApplyOutcome::Applied(reports) => assert_eq!(reports.len(), 1),
+            x => panic!("expected success, got {:?}", x),
+        }
+        conn.partition_map = partition_map;
+        assert_matches!(conn.last_transaction(),
+                        r#"[[201 :test/name "bob" ?tx true]
+                            [?tx :einsteindb/txInstant ?ms ?tx true]]"#);
+    }
+}
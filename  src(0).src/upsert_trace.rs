@@ -0,0 +1,205 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Tracing of upsert resolution, for debugging conflicting upserts.
+//!
+//! The transactor resolves tempids in generations: each generation looks up the `[a v]` pairs the
+//! remaining tempids might upsert to, and what resolves feeds the next generation.  When two of
+//! those lookups disagree, the `ConflictingUpserts` error says which tempid resolved to which
+//! causetids, but not how it got there.
+//!
+//! With tracing enabled, the transactor records every generation -- each tempid's candidate `[a v]`
+//! pairs and what it resolved to -- and makes the `UpsertTrace` the cause of a `ConflictingUpserts`
+//! error.  `upsert_trace` finds it again.  Tracing is off by default, since it costs a copy of
+//! every candidate pair; it's one of the watcher's `TransactOptions`, so each connection turns it
+//! on for itself with `Conn::set_upsert_tracing`.
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+use std::fmt;
+
+use failure::Fail;
+
+use core_traits::{
+    Causetid,
+    KnownCausetid,
+    TypedValue,
+};
+
+use edn::causets::{
+    TempId,
+};
+
+use einsteindb_traits::errors::{
+    einsteindbError,
+};
+
+use internal_types::{
+    TempIdHandle,
+};
+use types::{
+    AVPair,
+};
+
+/// One generation of upsert resolution.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct UpsertGeneration {
+    /// The `[a v]` pairs each tempid might upsert to.
+    pub candidates: BTreeMap<TempId, BTreeSet<(Causetid, TypedValue)>>,
+
+    /// The causetid each tempid that upserted resolved to.
+    pub resolved: BTreeMap<TempId, KnownCausetid>,
+}
+
+/// The generations of upsert resolution in a transaction, in order.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct UpsertTrace {
+    pub generations: Vec<UpsertGeneration>,
+}
+
+impl UpsertTrace {
+    pub(crate) fn begin_generation(&mut self, temp_id_avs: &[(TempIdHandle, AVPair)]) {
+        let mut generation = UpsertGeneration::default();
+        for &(ref tempid, ref av_pair) in temp_id_avs {
+            generation.candidates.entry((**tempid).clone()).or_insert_with(BTreeSet::default).insert(av_pair.clone());
+        }
+        self.generations.push(generation);
+    }
+
+    pub(crate) fn resolved(&mut self, tempid: &TempId, causetid: KnownCausetid) {
+        if let Some(generation) = self.generations.last_mut() {
+            generation.resolved.insert(tempid.clone(), causetid);
+        }
+    }
+}
+
+impl fmt::Display for UpsertTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, generation) in self.generations.iter().enumerate() {
+            writeln!(f, "upsert generation {}:", i)?;
+            for (tempid, candidates) in &generation.candidates {
+                let candidates: Vec<String> = candidates.iter().map(|&(a, ref v)| format!("[{} {:?}]", a, v)).collect();
+                match generation.resolved.get(tempid) {
+                    Some(causetid) => writeln!(f, "  {} {} -> {}", tempid, candidates.join(" "), causetid.0)?,
+                    None => writeln!(f, "  {} {} -> unresolved", tempid, candidates.join(" "))?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Fail for UpsertTrace {}
+
+/// Return the upsert trace attached to `error`, if upsert tracing was enabled when it happened.
+pub fn upsert_trace(error: &einsteindbError) -> Option<&UpsertTrace> {
+    let mut cause = error.cause();
+    while let Some(fail) = cause {
+        if let Some(trace) = fail.downcast_ref::<UpsertTrace>() {
+            return Some(trace);
+        }
+        cause = fail.cause();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rusqlite::TransactionBehavior;
+
+    use edn;
+    use edn::{
+        Keyword,
+    };
+    use edn::causets::{
+        OpType,
+    };
+
+    use einsteindb_core::{
+        HasTopograph,
+        Topograph,
+    };
+
+    use einsteindb_traits::errors::{
+        Result,
+    };
+
+    use debug::TestConn;
+    use tx::transact;
+    use watcher::{
+        TransactOptions,
+        TransactWatcher,
+    };
+
+    struct TracingWatcher(bool);
+
+    impl TransactWatcher for TracingWatcher {
+        fn causet(&mut self, _op: OpType, _e: Causetid, _a: Causetid, _v: &TypedValue) {
+        }
+
+        fn done(&mut self, _t: &Causetid, _topograph: &Topograph) -> Result<()> {
+            Ok(())
+        }
+
+        fn transact_options(&self) -> TransactOptions {
+            TransactOptions { trace_upserts: self.0, ..TransactOptions::default() }
+        }
+    }
+
+    fn transact_conflicting(conn: &mut TestConn, trace_upserts: bool) -> einsteindbError {
+        let causets = edn::parse::causets(r#"[
+            [:einsteindb/add "a" :page/id "1"]
+            [:einsteindb/add "a" :page/ref "b"]
+            [:einsteindb/add "b" :page/id "2"]
+            [:einsteindb/add "b" :page/ref "a"]
+        ]"#).expect("parsed");
+        let tx = conn.SQLite.transaction_with_behavior(TransactionBehavior::Immediate).expect("began");
+        transact(&tx, conn.partition_map.clone(), &conn.topograph, &conn.topograph, TracingWatcher(trace_upserts), causets).map(|_| ()).expect_err("conflicting upserts")
+    }
+
+    #[test]
+    fn test_upsert_trace() {
+        let mut conn = TestConn::default();
+        conn.transact(r#"[
+            {:einsteindb/solitonid :page/id :einsteindb/valueType :einsteindb.type/string :einsteindb/index true :einsteindb/unique :einsteindb.unique/idcauset}
+            {:einsteindb/solitonid :page/ref :einsteindb/valueType :einsteindb.type/ref :einsteindb/index true :einsteindb/unique :einsteindb.unique/idcauset}
+        ]"#).expect("transacted topograph");
+        conn.transact(r#"[
+            [:einsteindb/add 111 :page/id "1"]
+            [:einsteindb/add 111 :page/ref 111]
+            [:einsteindb/add 222 :page/id "2"]
+            [:einsteindb/add 222 :page/ref 222]
+        ]"#).expect("transacted");
+
+        // The references are reversed: the first generation resolves both tempids, and the
+        // second finds they conflict.
+        let error = transact_conflicting(&mut conn, false);
+        assert!(upsert_trace(&error).is_none());
+
+        let error = transact_conflicting(&mut conn, true);
+
+        // Same error, now with the generations that led to it.
+        assert!(error.to_string().starts_with("topograph constraint violation: conflicting upserts"));
+        let trace = upsert_trace(&error).expect("trace");
+        assert_eq!(trace.generations.len(), 2);
+        let a = TempId::lightlike("a".to_string());
+        let b = TempId::lightlike("b".to_string());
+        let page_id = conn.topograph.get_causetid(&Keyword::isoliton_namespaceable("page", "id")).expect("page/id").0;
+        let first = &trace.generations[0];
+        assert!(first.candidates[&a].contains(&(page_id, TypedValue::typed_string("1"))));
+        assert_eq!(first.resolved.get(&a), Some(&KnownCausetid(111)));
+        assert_eq!(first.resolved.get(&b), Some(&KnownCausetid(222)));
+        assert!(trace.to_string().contains("upsert generation 1:"));
+    }
+}
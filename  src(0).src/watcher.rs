@@ -17,6 +17,13 @@
 // - When observers are registered we want to flip some flags as writes occur so that we can
 //   notifying them outside the transaction.
 
+use std::sync::{
+    Arc,
+    Mutex,
+};
+
+use std::time::Duration;
+
 use core_traits::{
     Causetid,
     TypedValue,
@@ -34,6 +41,40 @@ use einsteindb_traits::errors::{
     Result,
 };
 
+use external_ids::{
+    ExternalIds,
+};
+
+use slow_tx_log::{
+    SlowTxRecord,
+};
+
+use write_metrics::{
+    TxWriteMetrics,
+    WriteMetricsSink,
+};
+
+/// How the transactor behaves for one connection.  The transactor asks the transact's watcher for
+/// them, so connections sharing a process don't share them.
+#[derive(Clone, Debug, Default)]
+pub struct TransactOptions {
+    /// The duration after which a transact is considered slow, if slow transaction logging is
+    /// enabled.  See `slow_tx_log`.
+    pub slow_tx_threshold: Option<Duration>,
+
+    /// Whether to trace upsert resolution, so that a `ConflictingUpserts` error says how it came
+    /// about.  See `upsert_trace`.
+    pub trace_upserts: bool,
+
+    /// The connection's cache of resolved external ids, which the transact's watcher evicts
+    /// each retracted `:einsteindb/externalId` from.  See `external_ids`.
+    pub external_ids: Option<Arc<Mutex<ExternalIds>>>,
+
+    /// Where the connection reports what its committed transactions wrote, if anywhere.  Write
+    /// metrics are only counted when this is set.  See `write_metrics`.
+    pub write_metrics_sink: Option<Arc<dyn WriteMetricsSink>>,
+}
+
 pub trait TransactWatcher {
     fn causet(&mut self, op: OpType, e: Causetid, a: Causetid, v: &TypedValue);
 
@@ -42,6 +83,22 @@ pub trait TransactWatcher {
     /// attribute changes transacted during this transact are not reflected in
     /// the topograph.
     fn done(&mut self, t: &Causetid, topograph: &Topograph) -> Result<()>;
+
+    /// Called after a successful transact that took longer than the slow transaction threshold.
+    /// See `slow_tx_log`.
+    fn slow_transaction(&mut self, _record: &SlowTxRecord) {
+    }
+
+    /// Called after a successful transact, with what it wrote, if the options have a write metrics
+    /// sink.  The transact isn't committed yet: the watcher's owner reports the metrics once it is.
+    /// See `write_metrics`.
+    fn write_metrics(&mut self, _metrics: TxWriteMetrics) {
+    }
+
+    /// The options to transact with.  Watchers that don't have any use the defaults.
+    fn transact_options(&self) -> TransactOptions {
+        TransactOptions::default()
+    }
 }
 
 pub struct NullWatcher();
@@ -0,0 +1,146 @@
+// Whtcorps Inc 2022 Apache 2.0 License; All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file File except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Per-attribute write metrics.
+//!
+//! Which attributes dominate a store's write volume isn't visible from the transaction log without
+//! scanning it.  With a `WriteMetricsSink` in its `TransactOptions`, the transactor counts the
+//! assertions, retractions and value bytes of every attribute a transaction writes to the log --
+//! redundant assertions and retractions, which write nothing, aren't counted -- and hands the counts
+//! to the transact's watcher.  The connection reports them to the sink once the transaction is
+//! committed, so transactions that fail or are rolled back don't report.  Nothing is counted until
+//! a sink is installed; see `Conn::set_write_metrics_sink`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{
+    Arc,
+};
+
+use rusqlite;
+
+use core_traits::{
+    Causetid,
+    TypedValue,
+};
+
+use einsteindb::{
+    TypedBerolinaSQLValue,
+};
+
+use einsteindb_traits::errors::{
+    Result,
+};
+
+/// What one transaction wrote of one attribute.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct AttributeWriteCounts {
+    pub assertions: u64,
+    pub retractions: u64,
+
+    /// The size of the values asserted and retracted, in bytes.  See `value_size`.
+    pub bytes: u64,
+}
+
+/// What one transaction wrote, by attribute.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TxWriteMetrics {
+    pub tx_id: Causetid,
+    pub attributes: BTreeMap<Causetid, AttributeWriteCounts>,
+}
+
+impl TxWriteMetrics {
+    /// Count what the transaction `tx_id` wrote to the transaction log.  Fulltext values are
+    /// counted by the size of their text.
+    pub(crate) fn written(conn: &rusqlite::Connection, tx_id: Causetid) -> Result<TxWriteMetrics> {
+        let mut stmt = conn.prepare_cached(r#"
+            SELECT t.a, t.added, t.v, t.value_type_tag, f.text
+            FROM transactions AS t
+            LEFT JOIN fulltext_values AS f
+            ON t.value_type_tag = 10 AND typeof(t.v) = 'integer' AND f.rowid = t.v
+            WHERE t.tx = ?"#)?;
+
+        let rows: Result<Vec<(Causetid, bool, rusqlite::types::Value, i32, Option<String>)>> = stmt.query_and_then(&[&tx_id], |row| {
+            Ok((row.get_checked(0)?, row.get_checked(1)?, row.get_checked(2)?, row.get_checked(3)?, row.get_checked(4)?))
+        })?.collect();
+
+        let mut metrics = TxWriteMetrics {
+            tx_id,
+            attributes: BTreeMap::default(),
+        };
+        for (a, added, v, value_type_tag, text) in rows? {
+            let bytes = match text {
+                Some(text) => text.len() as u64,
+                None => value_size(&TypedValue::from_BerolinaSQL_value_pair(v, value_type_tag)?),
+            };
+            let counts = metrics.attributes.entry(a).or_insert_with(AttributeWriteCounts::default);
+            if added {
+                counts.assertions += 1;
+            } else {
+                counts.retractions += 1;
+            }
+            counts.bytes += bytes;
+        }
+        Ok(metrics)
+    }
+}
+
+/// Receives the write metrics of every transaction a connection commits.  Called on the
+/// committing thread, after the commit, so it should be quick.
+pub trait WriteMetricsSink: Send + Sync {
+    fn record(&self, metrics: &TxWriteMetrics);
+}
+
+impl<F> WriteMetricsSink for F where F: Fn(&TxWriteMetrics) + Send + Sync {
+    fn record(&self, metrics: &TxWriteMetrics) {
+        self(metrics)
+    }
+}
+
+impl fmt::Debug for dyn WriteMetricsSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WriteMetricsSink")
+    }
+}
+
+/// The write metrics of transacts that haven't been committed yet, and the sink to report them to
+/// once they are.  See `InProgressObserverTransactWatcher::take_write_metrics`.
+#[derive(Debug, Default)]
+pub struct PendingWriteMetrics {
+    pub sink: Option<Arc<dyn WriteMetricsSink>>,
+    pub metrics: Vec<TxWriteMetrics>,
+}
+
+impl PendingWriteMetrics {
+    /// Hand the metrics to the sink.  Only call this once the transacts are committed.
+    pub fn report(self) {
+        if let Some(sink) = self.sink {
+            for metrics in self.metrics.iter() {
+                sink.record(metrics);
+            }
+        }
+    }
+}
+
+/// The size of a value, in bytes: the length of strings, keywords and URIs, and the width of everything
+/// else.
+pub fn value_size(v: &TypedValue) -> u64 {
+    match *v {
+        TypedValue::Boolean(_) => 1,
+        TypedValue::Ref(_) |
+        TypedValue::Long(_) |
+        TypedValue::Double(_) |
+        TypedValue::Instant(_) => 8,
+        TypedValue::Uuid(_) => 16,
+        TypedValue::String(ref s) => s.len() as u64,
+        TypedValue::Keyword(ref k) => k.to_string().len() as u64,
+        TypedValue::Uri(ref u) => u.as_str().len() as u64,
+    }
+}